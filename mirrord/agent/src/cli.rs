@@ -1,7 +1,16 @@
 #![deny(missing_docs)]
 
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
-use mirrord_protocol::{MeshVendor, AGENT_NETWORK_INTERFACE_ENV, AGENT_OPERATOR_CERT_ENV};
+use mirrord_protocol::{
+    MeshVendor, AGENT_MAX_SESSION_DURATION_ENV, AGENT_MIRROR_BUFFER_DIR_ENV,
+    AGENT_MIRROR_BUFFER_DISK_ENV, AGENT_MIRROR_BUFFER_MEMORY_ENV, AGENT_MIRROR_BUFFER_ON_FULL_ENV,
+    AGENT_NETWORK_INTERFACE_ENV, AGENT_OPERATOR_CERT_ENV, AGENT_OTLP_METRICS_ENDPOINT_ENV,
+    AGENT_OTLP_METRICS_HEADERS_ENV, AGENT_OTLP_METRICS_INTERVAL_ENV,
+    AGENT_OUTGOING_CONNECT_RETRIES_ENV, AGENT_OUTGOING_CONNECT_TIMEOUT_ENV, AGENT_QUIC_CERT_ENV,
+    AGENT_QUIC_KEY_ENV, AGENT_SNIFFER_ENV, AGENT_TRANSPORT_ENV, AGENT_UNPRIVILEGED_ENV,
+};
 
 const DEFAULT_RUNTIME: &str = "containerd";
 
@@ -26,6 +35,15 @@ pub struct Args {
     #[arg(short = 'i', long, env = AGENT_NETWORK_INTERFACE_ENV)]
     pub network_interface: Option<String>,
 
+    /// How the sniffer captures the traffic of the subscribed ports.
+    #[arg(long, env = AGENT_SNIFFER_ENV, value_enum, default_value_t)]
+    pub sniffer: SnifferBackend,
+
+    /// Run without the Linux capabilities of the agent's features, providing only the ones that
+    /// work without them (see [`crate::unprivileged`]).
+    #[arg(long, env = AGENT_UNPRIVILEGED_ENV, default_value_t = false)]
+    pub unprivileged: bool,
+
     /// Return an error after accepting the first client connection, in order to test agent error
     /// cleanup.
     ///
@@ -41,6 +59,109 @@ pub struct Args {
     /// If not given, the agent will not use TLS.
     #[arg(long, env = AGENT_OPERATOR_CERT_ENV)]
     pub operator_tls_cert_pem: Option<String>,
+
+    /// Transport on which the agent accepts the clients.
+    #[arg(long, env = AGENT_TRANSPORT_ENV, value_enum, default_value_t)]
+    pub transport: Transport,
+
+    /// PEM-encoded X509 certificate that this agent presents to the QUIC clients.
+    ///
+    /// Required with [`Transport::Quic`].
+    #[arg(long, env = AGENT_QUIC_CERT_ENV)]
+    pub quic_cert_pem: Option<String>,
+
+    /// PEM-encoded private key of [`Args::quic_cert_pem`].
+    ///
+    /// Required with [`Transport::Quic`].
+    #[arg(long, env = AGENT_QUIC_KEY_ENV)]
+    pub quic_key_pem: Option<String>,
+
+    /// OTLP/HTTP endpoint to which the agent pushes metrics about the sessions it serves.
+    ///
+    /// If not given, metrics are not exported.
+    #[arg(long, env = AGENT_OTLP_METRICS_ENDPOINT_ENV)]
+    pub otlp_metrics_endpoint: Option<String>,
+
+    /// Headers sent with the OTLP metrics, `key1=value1,key2=value2`.
+    #[arg(long, env = AGENT_OTLP_METRICS_HEADERS_ENV)]
+    pub otlp_metrics_headers: Option<String>,
+
+    /// Seconds between the exports of the OTLP metrics.
+    #[arg(long, env = AGENT_OTLP_METRICS_INTERVAL_ENV)]
+    pub otlp_metrics_interval: Option<u64>,
+
+    /// Seconds after which the agent ends the sessions of its clients and stops accepting new
+    /// ones.
+    ///
+    /// If not given, sessions are not limited.
+    #[arg(long, env = AGENT_MAX_SESSION_DURATION_ENV)]
+    pub max_session_duration: Option<u64>,
+
+    /// Milliseconds to wait for each attempt to make an outgoing TCP connection.
+    ///
+    /// If not given, defaults to 3 seconds.
+    #[arg(long, env = AGENT_OUTGOING_CONNECT_TIMEOUT_ENV)]
+    pub outgoing_connect_timeout: Option<u64>,
+
+    /// How many times to retry an outgoing TCP connection that failed with a transient error.
+    #[arg(long, env = AGENT_OUTGOING_CONNECT_RETRIES_ENV, default_value_t = 0)]
+    pub outgoing_connect_retries: u32,
+
+    /// Bytes of mirrored traffic kept in memory for each client that reads it slower than it
+    /// comes.
+    ///
+    /// If not given, defaults to 16 MiB.
+    #[arg(long, env = AGENT_MIRROR_BUFFER_MEMORY_ENV)]
+    pub mirror_buffer_memory: Option<u64>,
+
+    /// Bytes of mirrored traffic spilled to [`Args::mirror_buffer_dir`] when the memory of a
+    /// client is full, for all the clients together.
+    #[arg(long, env = AGENT_MIRROR_BUFFER_DISK_ENV, default_value_t = 0)]
+    pub mirror_buffer_disk: u64,
+
+    /// Directory to which the mirrored traffic is spilled.
+    ///
+    /// If not given, the temporary directory is used.
+    #[arg(long, env = AGENT_MIRROR_BUFFER_DIR_ENV)]
+    pub mirror_buffer_dir: Option<PathBuf>,
+
+    /// Which mirrored traffic is dropped when the buffer of a client is full.
+    #[arg(long, env = AGENT_MIRROR_BUFFER_ON_FULL_ENV, value_enum, default_value_t)]
+    pub mirror_buffer_on_full: MirrorBufferFull,
+}
+
+/// What the agent drops when the mirrored traffic of a client doesn't fit in its buffer
+/// (`agent.mirror_buffer.on_full`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MirrorBufferFull {
+    /// The oldest data in the buffer, to make room for the new one.
+    #[default]
+    #[value(name = "drop_oldest")]
+    DropOldest,
+    /// The data that doesn't fit.
+    #[value(name = "drop_newest")]
+    DropNewest,
+}
+
+/// Capture backends of the sniffer (`agent.sniffer`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SnifferBackend {
+    /// Raw socket with a classic BPF filter.
+    #[default]
+    Raw,
+    /// eBPF program attached with TC, falls back to [`SnifferBackend::Raw`] when it can't be
+    /// loaded.
+    Ebpf,
+}
+
+/// Transports on which the agent accepts the clients (`agent.transport`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// TCP connections, secured with TLS when [`Args::operator_tls_cert_pem`] is given.
+    #[default]
+    Tcp,
+    /// QUIC connections, each carrying the client's messages on its first bidirectional stream.
+    Quic,
 }
 
 #[derive(Clone, Debug, Default, Subcommand)]
@@ -81,6 +202,16 @@ impl Mode {
         matches!(self, Mode::Targetless)
     }
 
+    /// Name of the mode, as reported in the metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Targeted { .. } => "targeted",
+            Mode::Ephemeral { .. } => "ephemeral",
+            Mode::Targetless => "targetless",
+            Mode::BlackboxTest => "blackbox_test",
+        }
+    }
+
     // TODO(alex): Remove this when `mesh` option is removed from `cli::Mode`, and put into
     // `cli::Args`.
     /// Digs into `Mode` subcomand to get the `MeshVendor`.
@@ -8,8 +8,9 @@ use std::{
 use actix_codec::Framed;
 use futures::{SinkExt, TryStreamExt};
 use mirrord_protocol::{ClientMessage, DaemonCodec, DaemonMessage};
+use quinn::{RecvStream, SendStream};
 use thiserror::Error;
-use tokio::net::TcpStream;
+use tokio::{io::Join, net::TcpStream};
 use tokio_rustls::{
     client::TlsStream,
     rustls::{
@@ -25,7 +26,7 @@ use x509_parser::{
     nom, pem,
 };
 
-use crate::util::ClientId;
+use crate::{transport::ClientStream, util::ClientId};
 
 /// Wrapper over [`TlsConnector`] that can make successful TLS connections only to the server using
 /// a predefined certificate.
@@ -142,17 +143,20 @@ pub struct ClientConnection {
 }
 
 impl ClientConnection {
-    /// Wraps the given [`TcpStream`] into this struct.
+    /// Wraps the given [`ClientStream`] into this struct.
     /// If an [`AgentTlsConnector`] is given, it is used to first make a TLS connection using the
     /// given [`TcpStream`].
-    #[tracing::instrument(level = "trace", skip(tls), fields(use_tls = tls.is_some()), err)]
+    ///
+    /// QUIC connections are already secured, so the [`AgentTlsConnector`] is not used with them.
+    /// The client talks to us on the first bidirectional stream it opens.
+    #[tracing::instrument(level = "trace", skip(stream, tls), fields(use_tls = tls.is_some()), err)]
     pub async fn new(
-        stream: TcpStream,
+        stream: ClientStream,
         client_id: u32,
         tls: Option<AgentTlsConnector>,
     ) -> io::Result<Self> {
-        let framed = match tls {
-            Some(connector) => {
+        let framed = match (stream, tls) {
+            (ClientStream::Tcp(stream), Some(connector)) => {
                 let tls_stream = connector
                     .inner
                     .connect(connector.server_name.clone(), stream)
@@ -160,7 +164,18 @@ impl ClientConnection {
 
                 ConnectionFramed::Tls(Framed::new(tls_stream, DaemonCodec::default()))
             }
-            None => ConnectionFramed::Tcp(Framed::new(stream, DaemonCodec::default())),
+            (ClientStream::Tcp(stream), None) => {
+                ConnectionFramed::Tcp(Framed::new(stream, DaemonCodec::default()))
+            }
+            (ClientStream::Quic(incoming), _) => {
+                let connection = incoming.accept()?.await?;
+                let (send, recv) = connection.accept_bi().await?;
+
+                ConnectionFramed::Quic(Framed::new(
+                    tokio::io::join(recv, send),
+                    DaemonCodec::default(),
+                ))
+            }
         };
 
         Ok(Self { framed, client_id })
@@ -172,6 +187,7 @@ impl ClientConnection {
         match &mut self.framed {
             ConnectionFramed::Tcp(framed) => framed.send(message).await?,
             ConnectionFramed::Tls(framed) => framed.send(message).await?,
+            ConnectionFramed::Quic(framed) => framed.send(message).await?,
         }
 
         Ok(())
@@ -183,6 +199,7 @@ impl ClientConnection {
         match &mut self.framed {
             ConnectionFramed::Tcp(framed) => framed.try_next().await,
             ConnectionFramed::Tls(framed) => framed.try_next().await,
+            ConnectionFramed::Quic(framed) => framed.try_next().await,
         }
     }
 }
@@ -195,6 +212,10 @@ impl fmt::Debug for ClientConnection {
                 "uses_tls",
                 &matches!(self.framed, ConnectionFramed::Tls(..)),
             )
+            .field(
+                "uses_quic",
+                &matches!(self.framed, ConnectionFramed::Quic(..)),
+            )
             .finish()
     }
 }
@@ -204,6 +225,7 @@ impl fmt::Debug for ClientConnection {
 enum ConnectionFramed {
     Tcp(Framed<TcpStream, DaemonCodec>),
     Tls(Framed<TlsStream<TcpStream>, DaemonCodec>),
+    Quic(Framed<Join<RecvStream, SendStream>, DaemonCodec>),
 }
 
 #[cfg(test)]
@@ -211,14 +233,16 @@ mod test {
     use std::sync::Arc;
 
     use futures::StreamExt;
-    use mirrord_protocol::ClientCodec;
+    use mirrord_protocol::{ClientCodec, AGENT_QUIC_ALPN};
+    use quinn::crypto::rustls::QuicClientConfig;
     use tokio::net::{TcpListener, TcpStream};
     use tokio_rustls::{
-        rustls::{pki_types::PrivateKeyDer, ServerConfig},
+        rustls::{self, pki_types::PrivateKeyDer, ServerConfig},
         TlsAcceptor,
     };
 
     use super::*;
+    use crate::transport;
 
     /// Verifies that [`AgentTlsConnector`] correctly accepts a
     /// connection from a server using the provided certificate.
@@ -245,7 +269,7 @@ mod test {
         tokio::join!(
             async move {
                 let stream = TcpStream::connect(addr).await.unwrap();
-                let mut connection = ClientConnection::new(stream, 0, Some(connector))
+                let mut connection = ClientConnection::new(stream.into(), 0, Some(connector))
                     .await
                     .unwrap();
                 connection
@@ -296,7 +320,7 @@ mod test {
                 .unwrap();
 
                 let stream = TcpStream::connect(addr).await.unwrap();
-                ClientConnection::new(stream, 0, Some(connector))
+                ClientConnection::new(stream.into(), 0, Some(connector))
                     .await
                     .unwrap_err();
             },
@@ -306,4 +330,61 @@ mod test {
             },
         );
     }
+
+    /// Verifies that [`ClientConnection`] talks with a QUIC client on the first bidirectional
+    /// stream it opens.
+    #[tokio::test]
+    async fn quic_client_connection() {
+        let _ = rustls::crypto::CryptoProvider::install_default(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        );
+
+        let cert = rcgen::generate_simple_self_signed(vec!["mirrord-agent".to_string()]).unwrap();
+        let server_config =
+            transport::server_config(&cert.cert.pem(), &cert.key_pair.serialize_pem()).unwrap();
+        let server =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let mut root_store = RootCertStore::empty();
+        root_store.add(cert.cert.der().clone()).unwrap();
+        let mut crypto = ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![AGENT_QUIC_ALPN.to_vec()];
+        let client_config =
+            quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto).unwrap()));
+        let client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        tokio::join!(
+            async move {
+                let incoming = server.accept().await.unwrap();
+                let mut connection = ClientConnection::new(ClientStream::Quic(incoming), 0, None)
+                    .await
+                    .unwrap();
+                match connection.receive().await.unwrap() {
+                    Some(ClientMessage::Ping) => {}
+                    other => panic!("unexpected message: {other:?}"),
+                }
+                connection.send(DaemonMessage::Pong).await.unwrap();
+                // Dropping the connection closes it right away, wait for the client to get the
+                // message first.
+                let _ = connection.receive().await;
+            },
+            async move {
+                let connection = client
+                    .connect_with(client_config, addr, "mirrord-agent")
+                    .unwrap()
+                    .await
+                    .unwrap();
+                let (send, recv) = connection.open_bi().await.unwrap();
+                let mut framed = Framed::new(tokio::io::join(recv, send), ClientCodec::default());
+                framed.send(ClientMessage::Ping).await.unwrap();
+                match framed.next().await.unwrap() {
+                    Ok(DaemonMessage::Pong) => {}
+                    other => panic!("unexpected message: {other:?}"),
+                }
+            },
+        );
+    }
 }
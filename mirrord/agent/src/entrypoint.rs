@@ -1,7 +1,6 @@
 use std::{
     collections::HashMap,
     mem,
-    net::{Ipv4Addr, SocketAddrV4},
     path::PathBuf,
     sync::{
         atomic::{AtomicU32, Ordering},
@@ -12,15 +11,18 @@ use std::{
 use client_connection::AgentTlsConnector;
 use dns::{DnsCommand, DnsWorker};
 use futures::TryFutureExt;
-use mirrord_protocol::{ClientMessage, DaemonMessage, GetEnvVarsRequest, LogMessage};
+use mirrord_protocol::{
+    capabilities::{Capability, ProtocolCapabilities},
+    scratch::{DaemonScratch, LayerScratch},
+    ClientMessage, DaemonMessage, GetEnvVarsRequest, LogMessage,
+};
 use tokio::{
-    net::{TcpListener, TcpStream},
     process::Command,
     select,
     signal::unix::SignalKind,
     sync::mpsc::{self, Sender},
     task::JoinSet,
-    time::{timeout, Duration},
+    time::{self, timeout, Duration, Instant},
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace, warn};
@@ -33,25 +35,38 @@ use crate::{
     dns::DnsApi,
     error::{AgentError, Result},
     file::FileManager,
-    outgoing::{TcpOutgoingApi, UdpOutgoingApi},
+    fs_usage::FsUsageTask,
+    grep::GrepTask,
+    host_os::HostOs,
+    listeners::ListenersWatch,
+    metrics::{ClientGuard, MessageKind, OtlpMetricsExporter},
+    outgoing::{ConnectPolicy, TcpOutgoingApi, UdpOutgoingApi},
     runtime::get_container,
-    sniffer::{SnifferCommand, TcpConnectionSniffer, TcpSnifferApi},
+    scratch::ScratchSync,
+    sniffer::{MirrorBufferPolicy, SnifferCommand, TcpConnectionSniffer, TcpSnifferApi},
     steal::{
         ip_tables::{
-            new_iptables, IPTablesWrapper, SafeIpTables, IPTABLE_MESH, IPTABLE_MESH_ENV,
-            IPTABLE_PREROUTING, IPTABLE_PREROUTING_ENV, IPTABLE_STANDARD, IPTABLE_STANDARD_ENV,
+            new_iptables, nftables_requested, IPTablesWrapper, SafeIpTables, IPTABLE_MESH,
+            IPTABLE_MESH_ENV, IPTABLE_NODE_PORT, IPTABLE_NODE_PORT_ENV, IPTABLE_PREROUTING,
+            IPTABLE_PREROUTING_ENV, IPTABLE_STANDARD, IPTABLE_STANDARD_ENV,
         },
         StealerCommand, TcpConnectionStealer, TcpStealerApi,
     },
+    transport::{AgentListener, ClientStream},
+    udp_incoming::UdpIncomingApi,
     util::{run_thread_in_namespace, ClientId},
     watched_task::{TaskStatus, WatchedTask},
     *,
 };
 
-/// Size of [`mpsc`] channels connecting [`TcpStealerApi`] and [`TcpSnifferApi`] with their
-/// background tasks.
+/// Size of [`mpsc`] channels connecting [`TcpStealerApi`] with its background task.
+///
+/// [`TcpSnifferApi`] uses a [`MirrorBufferPolicy`] instead.
 const CHANNEL_SIZE: usize = 1024;
 
+/// Sent to the clients in [`DaemonMessage::Close`] when [`Args::max_session_duration`] elapses.
+const SESSION_EXPIRED_MESSAGE: &str = "session reached its maximum duration (session.max_duration)";
+
 /// Keeps track of next client id.
 /// Stores common data used when serving client connections.
 /// Can be cheaply cloned and passed to per-client background tasks.
@@ -67,6 +82,18 @@ struct State {
     ephemeral: bool,
     /// When present, it is used to secure incoming TCP connections.
     tls_connector: Option<AgentTlsConnector>,
+    /// Features that won't work on the node's OS, sent to every client as warnings (see
+    /// [`HostOs::preflight`]).
+    host_warnings: Arc<Vec<String>>,
+    /// When the sessions of all clients end, see [`Args::max_session_duration`].
+    session_deadline: Option<Instant>,
+    /// How the outgoing TCP connections of the clients are made.
+    connect_policy: ConnectPolicy,
+    /// How the mirrored traffic is buffered for slow clients.
+    mirror_buffer: MirrorBufferPolicy,
+    /// Features this agent can't provide, left out of the capabilities it answers with, see
+    /// [`Args::unprivileged`].
+    unavailable_features: Arc<Vec<Capability>>,
 }
 
 impl State {
@@ -111,6 +138,12 @@ impl State {
             cli::Mode::Targetless | cli::Mode::BlackboxTest => (false, None, "self".to_string()),
         };
 
+        let unavailable_features = if args.unprivileged {
+            unprivileged::unavailable_features(&pid)
+        } else {
+            Vec::new()
+        };
+
         let environ_path = PathBuf::from("/proc").join(pid).join("environ");
 
         match env::get_proc_environ(environ_path).await {
@@ -120,15 +153,32 @@ impl State {
             }
         };
 
+        let host_warnings = HostOs::get().preflight(nftables_requested());
+        host_warnings.iter().for_each(|warning| warn!("{warning}"));
+
+        let session_deadline = args
+            .max_session_duration
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+
         Ok(State {
             next_client_id: Default::default(),
             container,
             env: Arc::new(env),
             ephemeral,
             tls_connector,
+            host_warnings: Arc::new(host_warnings),
+            session_deadline,
+            connect_policy: ConnectPolicy::new(args),
+            mirror_buffer: MirrorBufferPolicy::new(args),
+            unavailable_features: Arc::new(unavailable_features),
         })
     }
 
+    /// Whether this agent can provide the given feature, see [`Args::unprivileged`].
+    fn provides(&self, feature: Capability) -> bool {
+        !self.unavailable_features.contains(&feature)
+    }
+
     /// Return the process ID of the target container if there is one.
     pub fn container_pid(&self) -> Option<u64> {
         self.container.as_ref().map(ContainerHandle::pid)
@@ -136,11 +186,12 @@ impl State {
 
     pub async fn serve_client_connection(
         self,
-        stream: TcpStream,
+        stream: ClientStream,
         tasks: BackgroundTasks,
         cancellation_token: CancellationToken,
     ) -> u32 {
         let client_id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let _metrics_guard = ClientGuard::new();
 
         let result = ClientConnection::new(stream, client_id, self.tls_connector.clone())
             .map_err(AgentError::from)
@@ -200,6 +251,18 @@ struct ClientConnectionHandler {
     tcp_outgoing_api: TcpOutgoingApi,
     udp_outgoing_api: UdpOutgoingApi,
     dns_api: DnsApi,
+    /// Started when the client asks for the target's listeners, with
+    /// [`ClientMessage::WatchListeners`].
+    listeners_watch: Option<ListenersWatch>,
+    /// Started when the client first asks for the incoming UDP traffic, with
+    /// [`ClientMessage::Udp`] or [`ClientMessage::UdpSteal`].
+    udp_incoming_api: Option<UdpIncomingApi>,
+    /// The search started with [`ClientMessage::Grep`], until it finishes.
+    grep: Option<GrepTask>,
+    /// The observation started with [`ClientMessage::FsUsage`], until it's done.
+    fs_usage: Option<FsUsageTask>,
+    /// Started with [`LayerScratch::Start`], until it stops with [`DaemonScratch::Stopped`].
+    scratch: Option<ScratchSync>,
     state: State,
 }
 
@@ -215,12 +278,21 @@ impl ClientConnectionHandler {
 
         let file_manager = FileManager::new(pid.or_else(|| state.ephemeral.then_some(1)));
 
-        let tcp_sniffer_api = Self::create_sniffer_api(id, bg_tasks.sniffer, &mut connection).await;
+        for warning in state.host_warnings.iter() {
+            // Ignore message send error.
+            let _ = connection
+                .send(DaemonMessage::LogMessage(LogMessage::warn(warning.clone())))
+                .await;
+        }
+
+        let tcp_sniffer_api =
+            Self::create_sniffer_api(id, bg_tasks.sniffer, &state.mirror_buffer, &mut connection)
+                .await;
         let tcp_stealer_api =
             Self::create_stealer_api(id, bg_tasks.stealer, &mut connection).await?;
         let dns_api = Self::create_dns_api(bg_tasks.dns);
 
-        let tcp_outgoing_api = TcpOutgoingApi::new(pid);
+        let tcp_outgoing_api = TcpOutgoingApi::new(pid, state.connect_policy);
         let udp_outgoing_api = UdpOutgoingApi::new(pid);
 
         let client_handler = Self {
@@ -232,6 +304,11 @@ impl ClientConnectionHandler {
             tcp_outgoing_api,
             udp_outgoing_api,
             dns_api,
+            listeners_watch: None,
+            udp_incoming_api: None,
+            grep: None,
+            fs_usage: None,
+            scratch: None,
             state,
         };
 
@@ -241,10 +318,11 @@ impl ClientConnectionHandler {
     async fn create_sniffer_api(
         id: ClientId,
         task: BackgroundTask<SnifferCommand>,
+        mirror_buffer: &MirrorBufferPolicy,
         connection: &mut ClientConnection,
     ) -> Option<TcpSnifferApi> {
         if let BackgroundTask::Running(sniffer_status, sniffer_sender) = task {
-            match TcpSnifferApi::new(id, sniffer_sender, sniffer_status, CHANNEL_SIZE).await {
+            match TcpSnifferApi::new(id, sniffer_sender, sniffer_status, mirror_buffer).await {
                 Ok(api) => Some(api),
                 Err(e) => {
                     let message = format!(
@@ -363,6 +441,81 @@ impl ClientConnectionHandler {
                     Ok(message) => self.respond(DaemonMessage::GetAddrInfoResponse(message)).await?,
                     Err(e) => break e,
                 },
+                ports = async {
+                    if let Some(ref mut listeners_watch) = self.listeners_watch {
+                        listeners_watch.changed().await
+                    } else {
+                        unreachable!()
+                    }
+                }, if self.listeners_watch.is_some() => match ports {
+                    Ok(ports) => self.respond(DaemonMessage::Listeners(ports)).await?,
+                    Err(error) => {
+                        warn!(%error, "Failed to read the listening sockets of the target, no longer watching them");
+                        self.listeners_watch = None;
+                    }
+                },
+                message = async {
+                    if let Some(ref mut udp_incoming_api) = self.udp_incoming_api {
+                        udp_incoming_api.daemon_message().await
+                    } else {
+                        unreachable!()
+                    }
+                }, if self.udp_incoming_api.is_some() => match message {
+                    Ok(message) => self.respond(message).await?,
+                    Err(e) => break e,
+                },
+                message = async {
+                    if let Some(ref mut grep) = self.grep {
+                        grep.recv().await
+                    } else {
+                        unreachable!()
+                    }
+                }, if self.grep.is_some() => match message {
+                    Some(message) => self.respond(DaemonMessage::Grep(message)).await?,
+                    None => self.grep = None,
+                },
+                report = async {
+                    if let Some(ref mut fs_usage) = self.fs_usage {
+                        fs_usage.recv().await
+                    } else {
+                        unreachable!()
+                    }
+                }, if self.fs_usage.is_some() => {
+                    self.fs_usage = None;
+                    self.respond(DaemonMessage::FsUsage(report)).await?;
+                },
+                message = async {
+                    if let Some(ref mut scratch) = self.scratch {
+                        scratch.recv().await
+                    } else {
+                        unreachable!()
+                    }
+                }, if self.scratch.is_some() => {
+                    if matches!(message, DaemonScratch::Stopped(..)) {
+                        self.scratch = None;
+                    }
+                    self.respond(DaemonMessage::Scratch(message)).await?;
+                },
+                _ = std::future::ready(()), if self.file_manager.has_read_stream_chunk() => {
+                    if let Some(chunk) = self.file_manager.next_read_stream_chunk() {
+                        self.respond(DaemonMessage::ReadStream(chunk)).await?;
+                    }
+                },
+                _ = async {
+                    if let Some(deadline) = self.state.session_deadline {
+                        time::sleep_until(deadline).await
+                    } else {
+                        unreachable!()
+                    }
+                }, if self.state.session_deadline.is_some() => {
+                    info!(
+                        client_id = self.id,
+                        "Session reached its maximum duration, disconnecting the client"
+                    );
+                    self.respond(DaemonMessage::Close(SESSION_EXPIRED_MESSAGE.to_string()))
+                        .await?;
+                    return Ok(());
+                },
                 _ = cancellation_token.cancelled() => return Ok(()),
             }
         };
@@ -385,6 +538,10 @@ impl ClientConnectionHandler {
     /// Returns `false` if the client disconnected.
     #[tracing::instrument(level = "trace", skip(self))]
     async fn handle_client_message(&mut self, message: ClientMessage) -> Result<bool> {
+        if let Some(kind) = metrics_kind(&message) {
+            metrics::client_message(kind);
+        }
+
         match message {
             ClientMessage::FileRequest(req) => {
                 if let Some(response) = self.file_manager.handle_message(req)? {
@@ -462,10 +619,96 @@ impl ClientConnectionHandler {
                 .await?;
             }
             ClientMessage::ReadyForLogs => {}
+            ClientMessage::Capabilities(client_capabilities) => {
+                let mut capabilities =
+                    ProtocolCapabilities::all().intersection(&client_capabilities);
+                for feature in self.state.unavailable_features.iter() {
+                    capabilities.remove(*feature);
+                }
+
+                self.respond(DaemonMessage::Capabilities(capabilities))
+                    .await?;
+            }
+            ClientMessage::WatchListeners => {
+                if self.listeners_watch.is_none() {
+                    self.listeners_watch = Some(ListenersWatch::new(self.state.container_pid()));
+                }
+            }
+            ClientMessage::Udp(message) => self.udp_incoming_api().mirror_message(message).await?,
+            ClientMessage::UdpSteal(message) => {
+                self.udp_incoming_api().steal_message(message).await?
+            }
+            ClientMessage::Grep(request) => {
+                // Replaces (and stops) the previous search, if any.
+                let pid = self
+                    .state
+                    .container_pid()
+                    .or_else(|| self.state.ephemeral.then_some(1));
+                self.grep = Some(GrepTask::new(pid, request));
+            }
+            ClientMessage::FsUsage(request) => {
+                // Replaces (and stops) the previous observation, if any.
+                let pid = self
+                    .state
+                    .container_pid()
+                    .or_else(|| self.state.ephemeral.then_some(1));
+                self.fs_usage = Some(FsUsageTask::new(pid, request));
+            }
+            ClientMessage::Scratch(LayerScratch::Start(path)) => {
+                // Replaces (and stops) the previous synchronization, if any.
+                let pid = self
+                    .state
+                    .container_pid()
+                    .or_else(|| self.state.ephemeral.then_some(1));
+                match ScratchSync::new(pid, path) {
+                    Ok(scratch) => self.scratch = Some(scratch),
+                    Err(error) => {
+                        self.scratch = None;
+                        self.respond(DaemonMessage::Scratch(DaemonScratch::Stopped(error.into())))
+                            .await?;
+                    }
+                }
+            }
+            ClientMessage::Scratch(message) => {
+                if let Some(scratch) = self.scratch.as_mut() {
+                    scratch.handle(message);
+                }
+            }
         }
 
         Ok(true)
     }
+
+    /// Returns the [`UdpIncomingApi`] of this client, starting it if needed.
+    fn udp_incoming_api(&mut self) -> &mut UdpIncomingApi {
+        let pid = self.state.container_pid();
+        self.udp_incoming_api
+            .get_or_insert_with(|| UdpIncomingApi::new(pid))
+    }
+}
+
+/// Kind of the message in the agent's metrics, [`None`] for the ones that are not about what the
+/// client does in the cluster.
+fn metrics_kind(message: &ClientMessage) -> Option<MessageKind> {
+    match message {
+        ClientMessage::FileRequest(..)
+        | ClientMessage::Grep(..)
+        | ClientMessage::Scratch(..)
+        | ClientMessage::FsUsage(..) => Some(MessageKind::File),
+        ClientMessage::GetAddrInfoRequest(..) => Some(MessageKind::Dns),
+        ClientMessage::GetEnvVarsRequest(..) => Some(MessageKind::Env),
+        ClientMessage::TcpOutgoing(..) => Some(MessageKind::OutgoingTcp),
+        ClientMessage::UdpOutgoing(..) => Some(MessageKind::OutgoingUdp),
+        ClientMessage::Tcp(..) | ClientMessage::Udp(..) => Some(MessageKind::Mirror),
+        ClientMessage::TcpSteal(..) | ClientMessage::UdpSteal(..) => Some(MessageKind::Steal),
+        ClientMessage::Ping
+        | ClientMessage::Close
+        | ClientMessage::PauseTargetRequest(..)
+        | ClientMessage::SwitchProtocolVersion(..)
+        | ClientMessage::ReadyForLogs
+        | ClientMessage::Capabilities(..)
+        | ClientMessage::WatchListeners => None,
+    }
 }
 
 /// Initializes the agent's [`State`], channels, threads, and runs [`ClientConnectionHandler`]s.
@@ -473,11 +716,7 @@ impl ClientConnectionHandler {
 async fn start_agent(args: Args) -> Result<()> {
     trace!("start_agent -> Starting agent with args: {args:?}");
 
-    let listener = TcpListener::bind(SocketAddrV4::new(
-        Ipv4Addr::UNSPECIFIED,
-        args.communicate_port,
-    ))
-    .await?;
+    let listener = AgentListener::bind(&args).await?;
 
     let state = State::new(&args).await?;
 
@@ -490,7 +729,11 @@ async fn start_agent(args: Args) -> Result<()> {
     let (stealer_command_tx, stealer_command_rx) = mpsc::channel::<StealerCommand>(1000);
     let (dns_command_tx, dns_command_rx) = mpsc::channel::<DnsCommand>(1000);
 
-    let (sniffer_task, sniffer_status) = if args.mode.is_targetless() {
+    // An unprivileged agent may not be able to capture or redirect the traffic.
+    let sniffer_enabled = !args.mode.is_targetless() && state.provides(Capability::IncomingMirror);
+    let stealer_enabled = !args.mode.is_targetless() && state.provides(Capability::IncomingSteal);
+
+    let (sniffer_task, sniffer_status) = if !sniffer_enabled {
         (None, None)
     } else {
         let cancellation_token = cancellation_token.clone();
@@ -499,15 +742,19 @@ async fn start_agent(args: Args) -> Result<()> {
 
         let watched_task = WatchedTask::new(
             TcpConnectionSniffer::TASK_NAME,
-            TcpConnectionSniffer::new(sniffer_command_rx, args.network_interface, mesh).and_then(
-                |sniffer| async move {
-                    let res = sniffer.start(cancellation_token).await;
-                    if let Err(err) = res.as_ref() {
-                        error!("Sniffer failed: {err}");
-                    }
-                    Ok(())
-                },
-            ),
+            TcpConnectionSniffer::new(
+                sniffer_command_rx,
+                args.network_interface,
+                mesh,
+                args.sniffer,
+            )
+            .and_then(|sniffer| async move {
+                let res = sniffer.start(cancellation_token).await;
+                if let Err(err) = res.as_ref() {
+                    error!("Sniffer failed: {err}");
+                }
+                Ok(())
+            }),
         );
         let status = watched_task.status();
         let task = run_thread_in_namespace(
@@ -520,7 +767,7 @@ async fn start_agent(args: Args) -> Result<()> {
         (Some(task), Some(status))
     };
 
-    let (stealer_task, stealer_status) = if args.mode.is_targetless() {
+    let (stealer_task, stealer_status) = if !stealer_enabled {
         (None, None)
     } else {
         let cancellation_token = cancellation_token.clone();
@@ -562,6 +809,24 @@ async fn start_agent(args: Args) -> Result<()> {
         (task, status)
     };
 
+    let metrics_task = match args.otlp_metrics_endpoint.as_deref() {
+        Some(endpoint) => {
+            match OtlpMetricsExporter::new(
+                endpoint,
+                args.otlp_metrics_headers.as_deref(),
+                args.otlp_metrics_interval,
+                args.mode.name(),
+            ) {
+                Ok(exporter) => Some(tokio::spawn(exporter.run(cancellation_token.clone()))),
+                Err(error) => {
+                    warn!(%error, "start_agent -> OTLP metrics are disabled");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
     let bg_tasks = BackgroundTasks {
         sniffer: sniffer_status
             .map(|status| BackgroundTask::Running(status, sniffer_command_tx))
@@ -610,9 +875,13 @@ async fn start_agent(args: Args) -> Result<()> {
         Err(AgentError::TestError)?
     }
 
+    // Once the sessions reach their maximum duration, we only wait for the clients to finish.
+    let session_deadline = state.session_deadline;
+    let mut session_expired = false;
+
     loop {
         select! {
-            Ok((stream, addr)) = listener.accept() => {
+            Ok((stream, addr)) = listener.accept(), if !session_expired => {
                 trace!(peer = %addr, "start_agent -> Connection accepted");
                 clients.spawn(state
                     .clone()
@@ -641,6 +910,20 @@ async fn start_agent(args: Args) -> Result<()> {
                     }
                 }
             }
+
+            _ = async {
+                if let Some(deadline) = session_deadline {
+                    time::sleep_until(deadline).await
+                } else {
+                    unreachable!()
+                }
+            }, if session_deadline.is_some() && !session_expired => {
+                info!(
+                    "start_agent -> Session reached its maximum duration, no longer accepting \
+                     clients"
+                );
+                session_expired = true;
+            }
         }
     }
 
@@ -678,6 +961,16 @@ async fn start_agent(args: Args) -> Result<()> {
         }
     }
 
+    if let Some(metrics_task) = metrics_task {
+        if let Err(error) = metrics_task.await {
+            error!(
+                ?error,
+                "start_agent -> {} task failed",
+                OtlpMetricsExporter::TASK_NAME
+            );
+        }
+    }
+
     trace!("start_agent -> Agent shutdown");
 
     Ok(())
@@ -727,6 +1020,7 @@ async fn start_iptable_guard(args: Args) -> Result<()> {
     std::env::set_var(IPTABLE_PREROUTING_ENV, IPTABLE_PREROUTING.as_str());
     std::env::set_var(IPTABLE_MESH_ENV, IPTABLE_MESH.as_str());
     std::env::set_var(IPTABLE_STANDARD_ENV, IPTABLE_STANDARD.as_str());
+    std::env::set_var(IPTABLE_NODE_PORT_ENV, IPTABLE_NODE_PORT.as_str());
 
     let mut sigterm = tokio::signal::unix::signal(SignalKind::terminate())?;
 
@@ -787,7 +1081,9 @@ pub async fn main() -> Result<()> {
 
     let args = cli::parse_args();
 
+    // Without `NET_ADMIN` there are no iptables to clean up.
     let agent_result = if args.mode.is_targetless()
+        || args.unprivileged
         || (std::env::var(IPTABLE_PREROUTING_ENV).is_ok()
             && std::env::var(IPTABLE_MESH_ENV).is_ok())
     {
@@ -13,7 +13,7 @@ use thiserror::Error;
 
 use crate::{
     client_connection::TlsSetupError, namespace::NamespaceError, sniffer::SnifferCommand,
-    steal::StealerCommand,
+    steal::StealerCommand, transport::QuicSetupError,
 };
 
 #[derive(Debug, Error)]
@@ -84,6 +84,9 @@ pub(crate) enum AgentError {
     #[error("IPTables failed with `{0}`")]
     IPTablesError(String),
 
+    #[error("ipvsadm failed with `{0}`")]
+    IpvsadmError(String),
+
     #[error("Join task failed")]
     JoinTask,
 
@@ -132,9 +135,15 @@ pub(crate) enum AgentError {
     #[error("TLS setup failed: {0}")]
     TlsSetupError(#[from] TlsSetupError),
 
+    #[error("QUIC setup failed: {0}")]
+    QuicSetupError(#[from] QuicSetupError),
+
     /// Child agent process spawned in `main` failed.
     #[error("Agent child process failed: {0}")]
     AgentFailed(ExitStatus),
+
+    #[error("Exporting OTLP metrics failed: {0}")]
+    OtlpMetrics(String),
 }
 
 pub(crate) type Result<T, E = AgentError> = std::result::Result<T, E>;
@@ -4,28 +4,48 @@ use std::{
     fs::{read_link, DirEntry, File, OpenOptions, ReadDir},
     io::{self, prelude::*, BufReader, SeekFrom},
     iter::{Enumerate, Map, Peekable},
-    os::unix::{fs::MetadataExt, prelude::FileExt},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{
+            fs::{FileTypeExt, MetadataExt, OpenOptionsExt},
+            prelude::FileExt,
+        },
+    },
     path::{Path, PathBuf},
     vec::IntoIter,
 };
 
 use faccess::{AccessMode, PathExt};
-use libc::DT_DIR;
+use libc::{c_int, DT_DIR};
 use mirrord_protocol::{
     file::{
-        AccessFileRequest, AccessFileResponse, CloseDirRequest, CloseFileRequest, DirEntryInternal,
-        FdOpenDirRequest, GetDEnts64Request, GetDEnts64Response, OpenDirResponse, OpenFileRequest,
-        OpenFileResponse, OpenOptionsInternal, OpenRelativeFileRequest, ReadDirRequest,
-        ReadDirResponse, ReadFileRequest, ReadFileResponse, ReadLimitedFileRequest,
-        ReadLinkFileRequest, ReadLinkFileResponse, SeekFileRequest, SeekFileResponse,
-        WriteFileRequest, WriteFileResponse, WriteLimitedFileRequest, XstatFsRequest,
-        XstatFsResponse, XstatRequest, XstatResponse,
+        AccessFileRequest, AccessFileResponse, AllowFifosRequest, CanonicalizeRequest,
+        CanonicalizeResponse, CloseDirRequest, CloseFileRequest, DirEntryInternal,
+        FdOpenDirRequest, FileLock, FileLockType, FlockOperation, FlockRequest, GetDEnts64Request,
+        GetDEnts64Response, GetLockRequest, GetLockResponse, OpenDirResponse, OpenFileRequest,
+        OpenFileResponse, OpenImageFileRequest, OpenOptionsInternal, OpenRelativeFileRequest,
+        ReadDirBatchRequest, ReadDirBatchResponse, ReadDirRequest, ReadDirResponse,
+        ReadFileRequest, ReadFileResponse, ReadLimitedFileRequest, ReadLinkFileRequest,
+        ReadLinkFileResponse, ReadStreamAckRequest, ReadStreamChunk, ReadStreamRequest,
+        ReadStreamStopRequest, SeekFileRequest, SeekFileResponse, SetLockRequest, WatchAddRequest,
+        WatchAddResponse, WatchEvent, WatchEventsResponse, WatchRemoveRequest, WriteFileRequest,
+        WriteFileResponse, WriteLimitedFileRequest, XstatFsRequest, XstatFsResponse, XstatRequest,
+        XstatResponse,
     },
     FileRequest, FileResponse, RemoteResult, ResponseError,
 };
+use nix::{
+    errno::Errno,
+    sched::{unshare, CloneFlags},
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+};
 use tracing::{error, trace};
 
-use crate::{error::Result, util::IndexAllocator};
+use crate::{
+    error::Result,
+    namespace::{set_namespace, NamespaceType},
+    util::IndexAllocator,
+};
 
 #[derive(Debug)]
 pub enum RemoteFile {
@@ -54,11 +74,172 @@ type GetDEnts64Stream = Peekable<
 
 #[derive(Debug, Default)]
 pub(crate) struct FileManager {
+    pid: Option<u64>,
     root_path: PathBuf,
+    /// Detached clone of the target's root mount, without the submounts, see
+    /// [`clone_root_mount`].
+    ///
+    /// Created on the first [`FileRequest::OpenImage`].
+    image_root: Option<OwnedFd>,
+    /// Detached clone of the target's whole mount tree, see [`clone_root_mount`].
+    ///
+    /// Created on the first operation that fails with [`io::ErrorKind::PermissionDenied`] when
+    /// going through the [`FileManager::root_path`], see [`FileManager::with_rootfs_fallback`].
+    rootfs: Option<OwnedFd>,
     open_files: HashMap<u64, RemoteFile>,
     dir_streams: HashMap<u64, Enumerate<ReadDir>>,
     getdents_streams: HashMap<u64, GetDEnts64Stream>,
     index_allocator: IndexAllocator<u64, 100>,
+    /// Created on the first [`FileRequest::WatchAdd`].
+    watches: Option<FileWatches>,
+    /// Whether the client can open named pipes, see [`FileRequest::AllowFifos`] and
+    /// [`open_file`].
+    allow_fifos: bool,
+    /// Files streamed to the client, by `stream_id`, see [`FileRequest::ReadStream`].
+    read_streams: HashMap<u64, ReadStream>,
+}
+
+/// A file streamed to the client, see [`FileRequest::ReadStream`].
+///
+/// Reads with [`FileExt::read_at`] from the position where the stream started, and moves the
+/// position of the file only when the client stops the stream.
+#[derive(Debug)]
+struct ReadStream {
+    fd: u64,
+    /// Position of the file when the stream started.
+    start: u64,
+    /// How many bytes we sent.
+    sent: u64,
+    /// How many more bytes we can send before the client acknowledges them.
+    credit: u64,
+    /// Failed to start the stream, the client gets this error in the first chunk.
+    error: Option<ResponseError>,
+    /// We sent the end of the file or an error, the stream waits for the client to stop it.
+    finished: bool,
+}
+
+impl ReadStream {
+    fn has_chunk(&self) -> bool {
+        !self.finished && (self.credit > 0 || self.error.is_some())
+    }
+}
+
+/// Remote file watches of a client, see [`FileRequest::WatchAdd`].
+///
+/// Every watch gets its own id, but the watches of the same file share one inotify watch, which
+/// reports all events. The events are filtered with the mask of each watch when the client takes
+/// them with [`FileRequest::WatchEvents`].
+#[derive(Debug)]
+struct FileWatches {
+    /// Non-blocking, the events wait in its queue until the client asks for them.
+    inotify: Inotify,
+    /// Our watches, `watch_id -> (inotify watch, mask)`.
+    watches: HashMap<u64, (WatchDescriptor, AddWatchFlags)>,
+    next_watch_id: u64,
+}
+
+impl FileWatches {
+    /// Events that are always reported, whatever the mask of the watch.
+    const UNMASKABLE: AddWatchFlags = AddWatchFlags::IN_IGNORED
+        .union(AddWatchFlags::IN_UNMOUNT)
+        .union(AddWatchFlags::IN_Q_OVERFLOW);
+
+    /// Flags of the mask that change how the watch is added, we pass them to inotify.
+    const ADD_FLAGS: AddWatchFlags = AddWatchFlags::IN_DONT_FOLLOW
+        .union(AddWatchFlags::IN_ONLYDIR)
+        .union(AddWatchFlags::IN_EXCL_UNLINK);
+
+    fn new() -> io::Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+
+        Ok(Self {
+            inotify,
+            watches: Default::default(),
+            next_watch_id: 0,
+        })
+    }
+
+    /// `host_path` is the path of the watched file in the agent's filesystem.
+    fn add(&mut self, host_path: &Path, mask: AddWatchFlags) -> io::Result<u64> {
+        let descriptor = self.inotify.add_watch(
+            host_path,
+            AddWatchFlags::IN_ALL_EVENTS | (mask & Self::ADD_FLAGS),
+        )?;
+
+        let watch_id = self.next_watch_id;
+        self.next_watch_id += 1;
+        self.watches.insert(watch_id, (descriptor, mask));
+
+        Ok(watch_id)
+    }
+
+    fn remove(&mut self, watch_id: u64) {
+        let Some((descriptor, _)) = self.watches.remove(&watch_id) else {
+            return;
+        };
+
+        if !self.watches.values().any(|(other, _)| *other == descriptor) {
+            // Fails when the file was removed, and the kernel dropped the watch already.
+            let _ = self.inotify.rm_watch(descriptor);
+        }
+    }
+
+    /// Takes the events queued since the last call, and copies each one to the watches that
+    /// want it.
+    fn take_events(&mut self) -> io::Result<Vec<WatchEvent>> {
+        let mut events = Vec::new();
+        let mut finished = Vec::new();
+
+        loop {
+            let batch = match self.inotify.read_events() {
+                Ok(batch) => batch,
+                Err(Errno::EAGAIN) => break,
+                Err(fail) => return Err(fail.into()),
+            };
+
+            for event in batch {
+                let overflow = event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW);
+
+                for (watch_id, (descriptor, mask)) in &self.watches {
+                    if (*descriptor != event.wd && !overflow) || finished.contains(watch_id) {
+                        continue;
+                    }
+
+                    if !event.mask.intersects(*mask | Self::UNMASKABLE) {
+                        continue;
+                    }
+
+                    events.push(WatchEvent {
+                        watch_id: *watch_id,
+                        mask: event.mask.bits(),
+                        cookie: event.cookie,
+                        name: event
+                            .name
+                            .as_ref()
+                            .map(|name| name.to_string_lossy().into_owned()),
+                    });
+
+                    if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                        finished.push(*watch_id);
+                    } else if mask.contains(AddWatchFlags::IN_ONESHOT) {
+                        events.push(WatchEvent {
+                            watch_id: *watch_id,
+                            mask: AddWatchFlags::IN_IGNORED.bits(),
+                            cookie: 0,
+                            name: None,
+                        });
+                        finished.push(*watch_id);
+                    }
+                }
+            }
+        }
+
+        for watch_id in finished {
+            self.remove(watch_id);
+        }
+
+        Ok(events)
+    }
 }
 
 pub fn get_root_path_from_optional_pid(pid: Option<u64>) -> PathBuf {
@@ -68,6 +249,71 @@ pub fn get_root_path_from_optional_pid(pid: Option<u64>) -> PathBuf {
     }
 }
 
+/// Maximum number of entries in a [`ReadDirBatchResponse`].
+const READ_DIR_BATCH_MAX_ENTRIES: u64 = 1024;
+
+/// Maximum size of a [`ReadStreamChunk`], see [`FileManager::next_read_stream_chunk`].
+const READ_STREAM_CHUNK_SIZE: u64 = 256 * 1024;
+
+/// `open_tree` flag that makes it return a detached copy of the mount, instead of the mount itself.
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+
+/// `open_tree` flag that makes it copy the whole mount tree, instead of just the one mount.
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+
+/// Clones the root mount of the `pid`'s mount namespace into a detached mount, that the agent can
+/// access through `/proc/self/fd/<fd>`.
+///
+/// - With `recursive`, the clone contains all the mounts on top of the root (volumes, config maps,
+///   secrets, etc.), so it looks like the filesystem seen by the target;
+/// - Without `recursive`, the clone contains only the files baked into the container image.
+///
+/// Runs in a separate thread, as we have to enter the target's mount namespace in order to clone
+/// its mounts.
+#[tracing::instrument(level = "trace")]
+fn clone_root_mount(pid: Option<u64>, recursive: bool) -> io::Result<OwnedFd> {
+    std::thread::spawn(move || {
+        // Threads share the filesystem attributes (root, cwd), and we can't change the mount
+        // namespace of this thread without unsharing them first.
+        unshare(CloneFlags::CLONE_FS)?;
+
+        if let Some(pid) = pid {
+            set_namespace(pid, NamespaceType::Mnt).map_err(io::Error::other)?;
+        }
+
+        let mut flags = OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint;
+        if recursive {
+            flags |= AT_RECURSIVE;
+        }
+
+        let fd =
+            unsafe { libc::syscall(libc::SYS_open_tree, libc::AT_FDCWD, c"/".as_ptr(), flags) };
+
+        if fd < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(unsafe { OwnedFd::from_raw_fd(fd as _) })
+        }
+    })
+    .join()
+    .map_err(|_| io::Error::other("thread cloning the root mount panicked"))?
+}
+
+/// Returns the path of the mount cloned with [`clone_root_mount`] into `slot`, cloning it first if
+/// it's not there yet.
+fn cloned_root_path(
+    slot: &mut Option<OwnedFd>,
+    pid: Option<u64>,
+    recursive: bool,
+) -> io::Result<PathBuf> {
+    let fd = match slot.as_ref() {
+        Some(fd) => fd.as_raw_fd(),
+        None => slot.insert(clone_root_mount(pid, recursive)?).as_raw_fd(),
+    };
+
+    Ok(PathBuf::from(format!("/proc/self/fd/{fd}")))
+}
+
 /// Resolve a path that might contain symlinks from a specific container to a path accessible from
 /// the root host
 #[tracing::instrument(level = "trace")]
@@ -122,6 +368,133 @@ pub fn resolve_path<P: AsRef<Path> + std::fmt::Debug, R: AsRef<Path> + std::fmt:
     Ok(final_path)
 }
 
+/// How many symlinks [`canonicalize_in_root`] follows before failing with `ELOOP`, like
+/// `MAXSYMLINKS` in Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves all symlinks of the absolute `path` in the target's filesystem under `root_path`, like
+/// `realpath` in the target.
+///
+/// Unlike [`resolve_path`], symlinks are resolved recursively and in every component, and neither
+/// absolute symlinks nor `..` ever leave the `root_path`. Returns the path in the target, not the
+/// one under `root_path`.
+///
+/// Fails with `ELOOP` after [`MAX_SYMLINKS`] symlinks, with `ENOENT` when a component doesn't
+/// exist, and with `ENOTDIR` when a component in the middle is not a directory.
+#[tracing::instrument(level = "trace", ret)]
+pub(crate) fn canonicalize_in_root(path: &Path, root_path: &Path) -> io::Result<PathBuf> {
+    use std::path::Component;
+
+    if !path.is_absolute() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("path to canonicalize is not absolute: {path:?}"),
+        ));
+    }
+
+    // Components left to resolve, in reverse, so that the ones of a symlink's target can be pushed.
+    let mut pending = path
+        .components()
+        .rev()
+        .map(|component| component.as_os_str().to_os_string())
+        .collect::<Vec<_>>();
+    let mut resolved = PathBuf::from("/");
+    let mut symlinks = 0;
+
+    while let Some(component) = pending.pop() {
+        match Path::new(&component).components().next() {
+            Some(Component::Normal(..)) => {}
+            Some(Component::ParentDir) => {
+                resolved.pop();
+                continue;
+            }
+            _ => continue,
+        }
+
+        let candidate = resolved.join(&component);
+        let host_path = path_in_root(root_path, &candidate);
+        let metadata = host_path.symlink_metadata()?;
+
+        if metadata.is_symlink() {
+            symlinks += 1;
+            if symlinks > MAX_SYMLINKS {
+                return Err(io::Error::from_raw_os_error(libc::ELOOP));
+            }
+
+            let target = read_link(&host_path)?;
+            if target.is_absolute() {
+                resolved = PathBuf::from("/");
+            }
+            pending.extend(
+                target
+                    .components()
+                    .rev()
+                    .map(|component| component.as_os_str().to_os_string()),
+            );
+        } else if !metadata.is_dir() && !pending.is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+        } else {
+            resolved = candidate;
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Where the absolute `path` in the target is under the `root_path`.
+fn path_in_root(root_path: &Path, path: &Path) -> PathBuf {
+    root_path.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Opens the file at `path`, which was already resolved in the target's filesystem.
+///
+/// Named pipes (FIFOs) would block here until the other side opens them, and then on every read
+/// until there's data, stalling all requests of the client. They can be opened only when
+/// `allow_fifos` is set, and then they're opened non-blocking, for both reading and writing: we
+/// keep the pipe open, so that it doesn't reach EOF when the writer on the other side closes it,
+/// and the data waits in the pipe until the client reads it. Reads and writes that would block
+/// fail with `EAGAIN`, and the layer retries them.
+fn open_file(
+    path: &Path,
+    open_options: OpenOptionsInternal,
+    allow_fifos: bool,
+) -> io::Result<File> {
+    let is_fifo = path
+        .metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_fifo());
+    if !is_fifo {
+        return OpenOptions::from(open_options).open(path);
+    }
+
+    if !allow_fifos {
+        return Err(io::Error::from_raw_os_error(libc::ENXIO));
+    }
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+}
+
+/// Converts the [`FileLock`] from the layer into a `struct flock` for open file description locks,
+/// which require `l_pid` to be 0.
+fn to_libc_flock(lock: &FileLock) -> libc::flock {
+    // SAFETY: `struct flock` is plain data, all zeroes is a valid value.
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+
+    flock.l_type = match lock.lock_type {
+        FileLockType::Read => libc::F_RDLCK,
+        FileLockType::Write => libc::F_WRLCK,
+        FileLockType::Unlock => libc::F_UNLCK,
+    } as _;
+    flock.l_whence = lock.whence;
+    flock.l_start = lock.start;
+    flock.l_len = lock.len;
+
+    flock
+}
+
 impl FileManager {
     /// Executes the request and returns the response.
     #[tracing::instrument(level = "trace", skip(self))]
@@ -136,6 +509,10 @@ impl FileManager {
                 let open_result = self.open(path.into(), open_options);
                 Some(FileResponse::Open(open_result))
             }
+            FileRequest::OpenImage(OpenImageFileRequest { path }) => {
+                let open_result = self.open_image(path);
+                Some(FileResponse::Open(open_result))
+            }
             FileRequest::OpenRelative(OpenRelativeFileRequest {
                 relative_fd,
                 path,
@@ -212,6 +589,12 @@ impl FileManager {
                 let read_dir_result = self.read_dir(remote_fd);
                 Some(FileResponse::ReadDir(read_dir_result))
             }
+            FileRequest::ReadDirBatch(ReadDirBatchRequest { remote_fd, amount }) => Some(
+                FileResponse::ReadDirBatch(self.read_dir_batch(remote_fd, amount)),
+            ),
+            FileRequest::Canonicalize(CanonicalizeRequest { path }) => {
+                Some(FileResponse::Canonicalize(self.canonicalize(path)))
+            }
             FileRequest::CloseDir(CloseDirRequest { remote_fd }) => {
                 self.close_dir(remote_fd);
                 None
@@ -222,6 +605,46 @@ impl FileManager {
             }) => Some(FileResponse::GetDEnts64(
                 self.getdents64(remote_fd, buffer_size),
             )),
+            FileRequest::WatchAdd(WatchAddRequest { path, mask }) => {
+                Some(FileResponse::WatchAdd(self.watch_add(path, mask)))
+            }
+            FileRequest::WatchRemove(WatchRemoveRequest { watch_id }) => {
+                if let Some(watches) = self.watches.as_mut() {
+                    watches.remove(watch_id);
+                }
+                None
+            }
+            FileRequest::WatchEvents(..) => Some(FileResponse::WatchEvents(self.watch_events())),
+            FileRequest::AllowFifos(AllowFifosRequest) => {
+                self.allow_fifos = true;
+                None
+            }
+            FileRequest::Flock(FlockRequest { fd, operation }) => {
+                Some(FileResponse::Flock(self.flock(fd, operation)))
+            }
+            FileRequest::SetLock(SetLockRequest { fd, lock }) => {
+                Some(FileResponse::SetLock(self.set_lock(fd, lock)))
+            }
+            FileRequest::GetLock(GetLockRequest { fd, lock }) => {
+                Some(FileResponse::GetLock(self.get_lock(fd, lock)))
+            }
+            FileRequest::ReadStream(request) => {
+                self.read_stream(request);
+                None
+            }
+            FileRequest::ReadStreamAck(ReadStreamAckRequest { stream_id, amount }) => {
+                if let Some(stream) = self.read_streams.get_mut(&stream_id) {
+                    stream.credit += amount;
+                }
+                None
+            }
+            FileRequest::ReadStreamStop(ReadStreamStopRequest {
+                stream_id,
+                consumed,
+            }) => {
+                self.read_stream_stop(stream_id, consumed);
+                None
+            }
         })
     }
 
@@ -230,21 +653,64 @@ impl FileManager {
         let root_path = get_root_path_from_optional_pid(pid);
         trace!("Agent root path >> {root_path:?}");
         Self {
+            pid,
             open_files: HashMap::new(),
             root_path,
             ..Default::default()
         }
     }
 
+    /// Runs `op` with the [`FileManager::root_path`], and retries it with the path of the
+    /// [`FileManager::rootfs`] if the first attempt fails with
+    /// [`io::ErrorKind::PermissionDenied`].
+    ///
+    /// Going through `/proc/<pid>/root` requires ptrace access to the target process, which the
+    /// agent may not get (e.g. with user namespaced containers or hardened kernels), even though
+    /// the target itself can read the file. The cloned rootfs is the same merged filesystem
+    /// (image layers and volumes), but it's owned by the agent.
+    fn with_rootfs_fallback<T>(&mut self, op: impl Fn(&Path) -> io::Result<T>) -> io::Result<T> {
+        match op(&self.root_path) {
+            Err(fail) if fail.kind() == io::ErrorKind::PermissionDenied && self.pid.is_some() => {
+                trace!(?fail, "retrying the operation through the cloned rootfs");
+
+                let rootfs = cloned_root_path(&mut self.rootfs, self.pid, true)?;
+                op(&rootfs)
+            }
+            result => result,
+        }
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     fn open(
         &mut self,
         path: PathBuf,
         open_options: OpenOptionsInternal,
     ) -> RemoteResult<OpenFileResponse> {
-        let path = resolve_path(path, &self.root_path)?;
-        let file = OpenOptions::from(open_options).open(&path)?;
+        let allow_fifos = self.allow_fifos;
+        let (path, file) = self.with_rootfs_fallback(|root_path| {
+            let path = resolve_path(&path, root_path)?;
+            let file = open_file(&path, open_options, allow_fifos)?;
+            Ok((path, file))
+        })?;
 
+        self.insert_open_file(path, file)
+    }
+
+    /// Opens the file from the container image, see [`clone_root_mount`].
+    ///
+    /// The file is always opened read-only.
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn open_image(&mut self, path: PathBuf) -> RemoteResult<OpenFileResponse> {
+        let image_root = cloned_root_path(&mut self.image_root, self.pid, false)?;
+
+        let path = resolve_path(path, image_root)?;
+        let file = File::open(&path)?;
+
+        self.insert_open_file(path, file)
+    }
+
+    /// Stores a file opened from `path` in [`FileManager::open_files`].
+    fn insert_open_file(&mut self, path: PathBuf, file: File) -> RemoteResult<OpenFileResponse> {
         let fd = self
             .index_allocator
             .next_index()
@@ -278,7 +744,7 @@ impl FileManager {
         if let RemoteFile::Directory(relative_dir) = relative_dir {
             let path = relative_dir.join(&path);
 
-            let file = OpenOptions::from(open_options).open(&path)?;
+            let file = open_file(&path, open_options, self.allow_fifos)?;
 
             let fd = self.index_allocator.next_index().ok_or_else(|| {
                 ResponseError::AllocationFailure("FileManager::open_relative".to_string())
@@ -326,6 +792,95 @@ impl FileManager {
             })
     }
 
+    /// Starts streaming the file, the chunks are taken with
+    /// [`FileManager::next_read_stream_chunk`].
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_stream(&mut self, request: ReadStreamRequest) {
+        let ReadStreamRequest {
+            fd,
+            stream_id,
+            window,
+        } = request;
+
+        let start = match self.open_files.get_mut(&fd) {
+            Some(RemoteFile::File(file)) => file.stream_position().map_err(ResponseError::from),
+            Some(_) => Err(ResponseError::NotFile(fd)),
+            None => Err(ResponseError::NotFound(fd)),
+        };
+
+        self.read_streams.insert(
+            stream_id,
+            ReadStream {
+                fd,
+                start: *start.as_ref().unwrap_or(&0),
+                sent: 0,
+                credit: window,
+                error: start.err(),
+                finished: false,
+            },
+        );
+    }
+
+    /// Stops the stream, and moves the position of the file right after the bytes the client
+    /// `consumed`.
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_stream_stop(&mut self, stream_id: u64, consumed: u64) {
+        let Some(stream) = self.read_streams.remove(&stream_id) else {
+            return;
+        };
+
+        if let Some(RemoteFile::File(file)) = self.open_files.get_mut(&stream.fd) {
+            if let Err(error) = file.seek(SeekFrom::Start(stream.start + consumed)) {
+                error!(
+                    ?error,
+                    stream_id, "Failed to restore the position of a streamed file"
+                );
+            }
+        }
+    }
+
+    /// Whether [`FileManager::next_read_stream_chunk`] has something to send.
+    pub(crate) fn has_read_stream_chunk(&self) -> bool {
+        self.read_streams.values().any(ReadStream::has_chunk)
+    }
+
+    /// Reads the next chunk of one of the [`ReadStream`]s that can send more.
+    pub(crate) fn next_read_stream_chunk(&mut self) -> Option<ReadStreamChunk> {
+        let (&stream_id, stream) = self
+            .read_streams
+            .iter_mut()
+            .find(|(_, stream)| stream.has_chunk())?;
+        let offset = stream.start + stream.sent;
+
+        let bytes = match (stream.error.take(), self.open_files.get(&stream.fd)) {
+            (Some(error), _) => Err(error),
+            (None, Some(RemoteFile::File(file))) => {
+                let mut buffer = vec![0; stream.credit.min(READ_STREAM_CHUNK_SIZE) as usize];
+                file.read_at(&mut buffer, offset)
+                    .map(|read_amount| {
+                        buffer.truncate(read_amount);
+                        buffer
+                    })
+                    .map_err(ResponseError::from)
+            }
+            (None, _) => Err(ResponseError::NotFound(stream.fd)),
+        };
+
+        match &bytes {
+            Ok(bytes) if !bytes.is_empty() => {
+                stream.sent += bytes.len() as u64;
+                stream.credit -= bytes.len() as u64;
+            }
+            _ => stream.finished = true,
+        }
+
+        Some(ReadStreamChunk {
+            stream_id,
+            offset,
+            bytes,
+        })
+    }
+
     /// Remote implementation of `fgets`.
     ///
     /// Uses `BufReader::read_line` to read a line (including `"\n"`) from a file with `fd`. The
@@ -405,17 +960,31 @@ impl FileManager {
     }
 
     /// Handles our `readlink_detour` with [`std::fs::read_link`].
+    ///
+    /// The symlinks in the parent directories are resolved in the target, see
+    /// [`canonicalize_in_root`].
     #[tracing::instrument(level = "trace", skip_all)]
     pub(crate) fn read_link(&mut self, path: PathBuf) -> RemoteResult<ReadLinkFileResponse> {
-        let path = path
-            .strip_prefix("/")
+        path.strip_prefix("/")
             .inspect_err(|fail| error!("file_worker -> {:#?}", fail))?;
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL).into());
+        };
 
-        let full_path = self.root_path.join(path);
+        self.with_rootfs_fallback(|root_path| {
+            let parent = canonicalize_in_root(parent, root_path)?;
+            read_link(path_in_root(root_path, &parent).join(name))
+        })
+        .map(|path| ReadLinkFileResponse { path })
+        .map_err(ResponseError::from)
+    }
 
-        read_link(full_path)
-            .map(|path| ReadLinkFileResponse { path })
-            .map_err(ResponseError::from)
+    /// Resolves all symlinks of the absolute `path` in the target, see [`canonicalize_in_root`].
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) fn canonicalize(&mut self, path: PathBuf) -> RemoteResult<CanonicalizeResponse> {
+        let path = self.with_rootfs_fallback(|root_path| canonicalize_in_root(&path, root_path))?;
+
+        Ok(CanonicalizeResponse { path })
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -496,6 +1065,112 @@ impl FileManager {
             })
     }
 
+    /// Returns the open [`RemoteFile::File`] with this `fd`, the target of the lock requests.
+    fn lockable_file(&self, fd: u64) -> RemoteResult<&File> {
+        match self.open_files.get(&fd) {
+            Some(RemoteFile::File(file)) => Ok(file),
+            Some(_) => Err(ResponseError::NotFile(fd)),
+            None => Err(ResponseError::NotFound(fd)),
+        }
+    }
+
+    /// `flock` on the remote file, never waits for the lock.
+    ///
+    /// Fails with `EWOULDBLOCK` when the lock is held by someone else, the layer retries the
+    /// request when the application asked to wait.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) fn flock(&mut self, fd: u64, operation: FlockOperation) -> RemoteResult<()> {
+        let file = self.lockable_file(fd)?;
+
+        let operation = match operation {
+            FlockOperation::Shared => libc::LOCK_SH,
+            FlockOperation::Exclusive => libc::LOCK_EX,
+            FlockOperation::Unlock => libc::LOCK_UN,
+        };
+
+        if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    /// `fcntl(F_SETLK)` on the remote file, never waits for the lock.
+    ///
+    /// We take open file description locks (`F_OFD_SETLK`), so that the locks of different
+    /// clients don't belong to the agent process as a whole.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) fn set_lock(&mut self, fd: u64, lock: FileLock) -> RemoteResult<()> {
+        let file = self.lockable_file(fd)?;
+        let mut flock = to_libc_flock(&lock);
+
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLK, &mut flock) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    /// `fcntl(F_GETLK)` on the remote file, returns the lock that would prevent us from taking
+    /// `lock`, if any.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) fn get_lock(&mut self, fd: u64, lock: FileLock) -> RemoteResult<GetLockResponse> {
+        let file = self.lockable_file(fd)?;
+        let mut flock = to_libc_flock(&lock);
+
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_GETLK, &mut flock) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let lock_type = match c_int::from(flock.l_type) {
+            libc::F_RDLCK => FileLockType::Read,
+            libc::F_WRLCK => FileLockType::Write,
+            _ => return Ok(GetLockResponse { conflicting: None }),
+        };
+
+        Ok(GetLockResponse {
+            conflicting: Some(FileLock {
+                lock_type,
+                whence: flock.l_whence,
+                start: flock.l_start,
+                len: flock.l_len,
+                pid: flock.l_pid,
+            }),
+        })
+    }
+
+    /// Starts watching `path` for the `inotify` events in `mask`.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) fn watch_add(&mut self, path: PathBuf, mask: u32) -> RemoteResult<WatchAddResponse> {
+        let mask = AddWatchFlags::from_bits_truncate(mask);
+        let host_path = self.with_rootfs_fallback(|root_path| {
+            let host_path = resolve_path(&path, root_path)?;
+            // Fails with `PermissionDenied` when the file can't be reached through this root.
+            host_path.metadata()?;
+            Ok(host_path)
+        })?;
+
+        let watches = match &mut self.watches {
+            Some(watches) => watches,
+            None => self.watches.insert(FileWatches::new()?),
+        };
+
+        watches
+            .add(&host_path, mask)
+            .map(|watch_id| WatchAddResponse { watch_id })
+            .map_err(ResponseError::from)
+    }
+
+    /// Takes the events that happened on the watches since the last call.
+    pub(crate) fn watch_events(&mut self) -> RemoteResult<WatchEventsResponse> {
+        let events = match self.watches.as_mut() {
+            Some(watches) => watches.take_events()?,
+            None => Vec::new(),
+        };
+
+        Ok(WatchEventsResponse { events })
+    }
+
     pub(crate) fn close(&mut self, fd: u64) {
         trace!("FileManager::close -> fd {:#?}", fd,);
 
@@ -503,6 +1178,7 @@ impl FileManager {
             error!("FileManager::close -> fd {:#?} not found", fd);
         } else {
             self.index_allocator.free_index(fd);
+            self.read_streams.retain(|_, stream| stream.fd != fd);
         }
     }
 
@@ -521,7 +1197,6 @@ impl FileManager {
         pathname: PathBuf,
         mode: u8,
     ) -> RemoteResult<AccessFileResponse> {
-        let pathname = resolve_path(pathname, &self.root_path)?;
         trace!(
             "FileManager::access -> pathname {:#?} | mode {:#?}",
             pathname,
@@ -533,8 +1208,7 @@ impl FileManager {
         let mode =
             AccessMode::from_bits((mode << 4).reverse_bits() | 1).unwrap_or(AccessMode::EXISTS);
 
-        pathname
-            .access(mode)
+        self.with_rootfs_fallback(|root_path| resolve_path(&pathname, root_path)?.access(mode))
             .map(|_| AccessFileResponse)
             .map_err(ResponseError::from)
     }
@@ -586,11 +1260,13 @@ impl FileManager {
         let path = path.strip_prefix("/").map_err(|_| {
             std::io::Error::new(std::io::ErrorKind::InvalidInput, "couldn't strip prefix")
         })?;
-        let res = if follow_symlink {
-            resolve_path(path, &self.root_path)?.metadata()
-        } else {
-            self.root_path.join(path).symlink_metadata()
-        };
+        let res = self.with_rootfs_fallback(|root_path| {
+            if follow_symlink {
+                resolve_path(path, root_path)?.metadata()
+            } else {
+                root_path.join(path).symlink_metadata()
+            }
+        });
 
         res.map(|metadata| XstatResponse {
             metadata: metadata.into(),
@@ -727,6 +1403,29 @@ impl FileManager {
         Ok(result)
     }
 
+    /// Reads up to `amount` next entries of the directory, each with its metadata.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) fn read_dir_batch(
+        &mut self,
+        fd: u64,
+        amount: u64,
+    ) -> RemoteResult<ReadDirBatchResponse> {
+        let amount = amount.min(READ_DIR_BATCH_MAX_ENTRIES) as usize;
+        let dir_stream = self.get_dir_stream(fd)?;
+
+        let entries = dir_stream
+            .take(amount)
+            .map(|(offset, entry)| {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                let entry = DirEntryInternal::try_from((offset, Ok(entry)))?;
+                Ok((entry, metadata.into()))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(ReadDirBatchResponse { entries })
+    }
+
     /// The getdents64 syscall writes dir entries to a buffer, as long as they fit.
     /// If a call did not process all the entries in a dir, the result of the next call continues
     /// where the last one stopped.
@@ -784,3 +1483,63 @@ impl FileManager {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    /// Creates an empty root for [`canonicalize_in_root`] in the temp dir.
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "mirrord-canonicalize-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("app/data")).unwrap();
+        std::fs::write(root.join("app/data/file"), b"").unwrap();
+
+        root
+    }
+
+    #[test]
+    fn canonicalize_stays_in_root() {
+        let root = test_root("stays");
+        symlink("/app/data", root.join("app/absolute")).unwrap();
+        symlink("../../../../app", root.join("app/data/escape")).unwrap();
+
+        assert_eq!(
+            canonicalize_in_root(Path::new("/app/absolute/file"), &root).unwrap(),
+            PathBuf::from("/app/data/file")
+        );
+        assert_eq!(
+            canonicalize_in_root(Path::new("/app/data/escape/data/./file"), &root).unwrap(),
+            PathBuf::from("/app/data/file")
+        );
+        assert_eq!(
+            canonicalize_in_root(Path::new("/../app/data/../data"), &root).unwrap(),
+            PathBuf::from("/app/data")
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn canonicalize_fails_on_loops_and_missing() {
+        let root = test_root("loops");
+        symlink("second", root.join("app/first")).unwrap();
+        symlink("/app/first", root.join("app/second")).unwrap();
+
+        let errno = |path: &str| {
+            canonicalize_in_root(Path::new(path), &root)
+                .unwrap_err()
+                .raw_os_error()
+        };
+        assert_eq!(errno("/app/first"), Some(libc::ELOOP));
+        assert_eq!(errno("/app/missing/file"), Some(libc::ENOENT));
+        assert_eq!(errno("/app/data/file/more"), Some(libc::ENOTDIR));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
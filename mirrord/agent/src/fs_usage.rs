@@ -0,0 +1,409 @@
+//! Observes which files the target accesses for `mirrord analyze fs-usage`, requested with
+//! [`ClientMessage::FsUsage`](mirrord_protocol::ClientMessage::FsUsage).
+//!
+//! We put fanotify marks on the mounts of the target's mount namespace and collect the files that
+//! are opened, read or written until the requested time is up. The observation runs in its own
+//! thread that enters the target's mount namespace, so that the paths we get from the events are
+//! the ones seen by the target.
+
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs, io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use mirrord_protocol::{
+    fs_usage::{FileUsage, FsUsageReport, FsUsageRequest},
+    RemoteResult,
+};
+use nix::sched::{unshare, CloneFlags};
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use crate::namespace::{set_namespace, NamespaceType};
+
+/// Events that mean that the file was read. There is no event for a file opened only for
+/// writing, so these files are reported as read too.
+const READ_MASK: u64 = libc::FAN_ACCESS | libc::FAN_OPEN;
+
+/// Events that mean that the file was written.
+const WRITE_MASK: u64 = libc::FAN_MODIFY | libc::FAN_CLOSE_WRITE;
+
+/// How often we check whether the observation was cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Size of the buffer we read the events into.
+const EVENTS_BUFFER_LEN: usize = 64 * 1024;
+
+/// Filesystems that don't hold files of the target's application.
+const IGNORED_FS_TYPES: &[&str] = &[
+    "proc",
+    "sysfs",
+    "cgroup",
+    "cgroup2",
+    "devpts",
+    "mqueue",
+    "bpf",
+    "tracefs",
+    "debugfs",
+    "securityfs",
+    "pstore",
+];
+
+/// An observation started with [`FsUsageTask::new`], stopped when dropped.
+pub(crate) struct FsUsageTask {
+    report: oneshot::Receiver<RemoteResult<FsUsageReport>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl FsUsageTask {
+    /// Starts observing the files of the target with the given `pid` (the agent's own mount
+    /// namespace when [`None`]).
+    pub(crate) fn new(pid: Option<u64>, request: FsUsageRequest) -> Self {
+        let (tx, report) = oneshot::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let observation = Observation {
+            cancelled: cancelled.clone(),
+            own_pid: std::process::id() as i32,
+            files: Default::default(),
+            overflowed: false,
+        };
+        let duration = Duration::from_secs(request.duration_secs);
+
+        let spawned = thread::Builder::new()
+            .name("fs-usage".to_string())
+            .spawn(move || {
+                let _ = tx.send(observation.run(pid, duration));
+            });
+        if let Err(error) = spawned {
+            warn!(%error, "Failed to spawn the fs usage thread");
+        }
+
+        Self { report, cancelled }
+    }
+
+    /// Returns the report when the observation is done.
+    ///
+    /// Cancel safe.
+    pub(crate) async fn recv(&mut self) -> RemoteResult<FsUsageReport> {
+        (&mut self.report).await.unwrap_or_else(|_| {
+            Err(io::Error::other("the fs usage thread stopped unexpectedly").into())
+        })
+    }
+}
+
+impl Drop for FsUsageTask {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether a file was read and/or written.
+#[derive(Default, Debug, Clone, Copy)]
+struct Access {
+    read: bool,
+    written: bool,
+}
+
+/// State of the observation, lives in its thread.
+struct Observation {
+    cancelled: Arc<AtomicBool>,
+    /// The agent's own accesses are not reported.
+    own_pid: i32,
+    files: BTreeMap<PathBuf, Access>,
+    overflowed: bool,
+}
+
+impl Observation {
+    fn run(mut self, pid: Option<u64>, duration: Duration) -> RemoteResult<FsUsageReport> {
+        // Threads share the filesystem attributes (root, cwd), and we can't change the mount
+        // namespace of this thread without unsharing them first.
+        unshare(CloneFlags::CLONE_FS).map_err(io::Error::from)?;
+        if let Some(pid) = pid {
+            set_namespace(pid, NamespaceType::Mnt).map_err(io::Error::other)?;
+        }
+
+        let fanotify = fanotify_init()?;
+
+        let mountinfo = fs::read_to_string("/proc/thread-self/mountinfo")?;
+        let mut marked = 0;
+        for mount_point in mount_points(&mountinfo) {
+            match fanotify_mark_mount(&fanotify, &mount_point) {
+                Ok(()) => marked += 1,
+                Err(error) => {
+                    warn!(%error, mount_point = %mount_point.display(), "Failed to observe a mount")
+                }
+            }
+        }
+        if marked == 0 {
+            return Err(io::Error::other("failed to observe any mount of the target").into());
+        }
+
+        self.observe(&fanotify, duration)?;
+
+        Ok(FsUsageReport {
+            files: self
+                .files
+                .into_iter()
+                .map(|(path, access)| FileUsage {
+                    path,
+                    read: access.read,
+                    written: access.written,
+                })
+                .collect(),
+            overflowed: self.overflowed,
+        })
+    }
+
+    /// Reads the events until `duration` passes or the observation is cancelled.
+    fn observe(&mut self, fanotify: &OwnedFd, duration: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + duration;
+        let mut buffer = vec![0_u8; EVENTS_BUFFER_LEN];
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.cancelled.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            let mut poll_fd = libc::pollfd {
+                fd: fanotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = remaining.min(POLL_INTERVAL).as_millis() as libc::c_int;
+            if unsafe { libc::poll(&mut poll_fd, 1, timeout) } == -1 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+
+            let read = unsafe {
+                libc::read(
+                    fanotify.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+            if read < 0 {
+                let error = io::Error::last_os_error();
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                ) {
+                    continue;
+                }
+                return Err(error);
+            }
+
+            self.handle_events(buffer.get(..read as usize).unwrap_or_default());
+        }
+    }
+
+    /// Records the events read from the fanotify file descriptor, and closes their file
+    /// descriptors.
+    fn handle_events(&mut self, mut events: &[u8]) {
+        const METADATA_LEN: usize = std::mem::size_of::<libc::fanotify_event_metadata>();
+
+        while events.len() >= METADATA_LEN {
+            let metadata = unsafe {
+                std::ptr::read_unaligned(events.as_ptr().cast::<libc::fanotify_event_metadata>())
+            };
+            if metadata.vers != libc::FANOTIFY_METADATA_VERSION
+                || (metadata.event_len as usize) < METADATA_LEN
+            {
+                warn!(
+                    version = metadata.vers,
+                    "Unexpected fanotify event, skipping the rest"
+                );
+                return;
+            }
+            events = events
+                .get(metadata.event_len as usize..)
+                .unwrap_or_default();
+
+            if metadata.mask & libc::FAN_Q_OVERFLOW != 0 {
+                self.overflowed = true;
+            }
+            if metadata.fd < 0 {
+                continue;
+            }
+
+            let fd = unsafe { OwnedFd::from_raw_fd(metadata.fd) };
+            if metadata.pid == self.own_pid {
+                continue;
+            }
+
+            // The fd path is resolved in our root, which is the target's.
+            let Ok(path) = fs::read_link(format!("/proc/thread-self/fd/{}", fd.as_raw_fd())) else {
+                continue;
+            };
+            self.record(path, metadata.mask);
+        }
+    }
+
+    fn record(&mut self, path: PathBuf, mask: u64) {
+        // Temporary files that are already gone.
+        if path.as_os_str().as_bytes().ends_with(b" (deleted)") {
+            return;
+        }
+
+        let access = self.files.entry(path).or_default();
+        access.read |= mask & READ_MASK != 0;
+        access.written |= mask & WRITE_MASK != 0;
+    }
+}
+
+fn fanotify_init() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::fanotify_init(
+            libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_NONBLOCK,
+            (libc::O_RDONLY | libc::O_LARGEFILE | libc::O_CLOEXEC) as libc::c_uint,
+        )
+    };
+
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+/// Observes the files of the mount at `mount_point`.
+fn fanotify_mark_mount(fanotify: &OwnedFd, mount_point: &Path) -> io::Result<()> {
+    let path = CString::new(mount_point.as_os_str().as_bytes())?;
+    let marked = unsafe {
+        libc::fanotify_mark(
+            fanotify.as_raw_fd(),
+            libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+            READ_MASK | WRITE_MASK,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+        )
+    };
+
+    if marked == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Mount points from `/proc/<pid>/mountinfo` that may hold files of the application.
+fn mount_points(mountinfo: &str) -> Vec<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mount_point = line.split(' ').nth(4)?;
+            // Optional fields come before the separator.
+            let fs_type = line.split(" - ").nth(1)?.split(' ').next()?;
+
+            if IGNORED_FS_TYPES.contains(&fs_type) {
+                return None;
+            }
+
+            let mount_point = unescape_mount_point(mount_point);
+            if mount_point.starts_with("/proc") || mount_point.starts_with("/sys") {
+                return None;
+            }
+
+            Some(mount_point)
+        })
+        .collect()
+}
+
+/// Mount points in `mountinfo` have spaces, tabs, newlines and backslashes escaped as octal, e.g.
+/// `\040`.
+fn unescape_mount_point(escaped: &str) -> PathBuf {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let octal = tail
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+
+        match octal {
+            Some(unescaped) if byte == b'\\' => {
+                bytes.push(unescaped);
+                rest = tail.get(3..).unwrap_or_default();
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(&bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mount_points_skip_pseudo_filesystems() {
+        let mountinfo = "\
+1085 1009 0:137 / / rw,relatime master:318 - overlay overlay rw,lowerdir=/a
+1086 1085 0:140 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw
+1087 1085 0:141 / /dev rw,nosuid - tmpfs tmpfs rw,size=65536k,mode=755
+1093 1085 0:139 / /sys ro,nosuid,nodev,noexec,relatime - sysfs sysfs ro
+1101 1085 254:1 /var/lib/kubelet/pods/x/volumes/data /app/my\\040data rw - ext4 /dev/vda1 rw
+1010 1086 0:141 /null /proc/kcore rw,nosuid - tmpfs tmpfs rw,size=65536k,mode=755
+";
+
+        assert_eq!(
+            mount_points(mountinfo),
+            vec![
+                PathBuf::from("/"),
+                PathBuf::from("/dev"),
+                PathBuf::from("/app/my data")
+            ]
+        );
+    }
+
+    #[test]
+    fn accesses_are_merged() {
+        let mut observation = Observation {
+            cancelled: Default::default(),
+            own_pid: 1,
+            files: Default::default(),
+            overflowed: false,
+        };
+
+        observation.record("/app/config.yaml".into(), libc::FAN_OPEN);
+        observation.record("/app/config.yaml".into(), libc::FAN_ACCESS);
+        observation.record("/tmp/cache".into(), libc::FAN_OPEN);
+        observation.record("/tmp/cache".into(), libc::FAN_CLOSE_WRITE);
+        observation.record("/tmp/gone (deleted)".into(), libc::FAN_OPEN);
+
+        let files = observation
+            .files
+            .iter()
+            .map(|(path, access)| (path.to_str().unwrap(), access.read, access.written))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                ("/app/config.yaml", true, false),
+                ("/tmp/cache", true, true)
+            ]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Searches the files of the target for `mirrord grep`, requested with
+//! [`ClientMessage::Grep`](mirrord_protocol::ClientMessage::Grep).
+//!
+//! The search runs in its own thread with the lowest scheduling priority, so it only gets the CPU
+//! time that the target and the rest of the agent don't need, and the matches are streamed to the
+//! client as they are found.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use mirrord_protocol::{
+    grep::{DaemonGrep, GrepMatch, GrepRequest, GrepSummary},
+    RemoteResult,
+};
+use regex::bytes::{Regex, RegexBuilder};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::file::{get_root_path_from_optional_pid, resolve_path};
+
+/// Nice value of the search thread, the lowest priority.
+const SEARCH_NICENESS: libc::c_int = 19;
+
+/// Files that have a NUL byte in this many first bytes are considered binary and skipped.
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+/// Longer lines are cut to this many bytes in the [`GrepMatch`].
+const MAX_LINE_LEN: usize = 4 * 1024;
+
+/// Results that were found but not yet sent to the client. When full, the search waits.
+const RESULTS_CAPACITY: usize = 128;
+
+/// A search started with [`GrepTask::new`], stopped when dropped.
+pub(crate) struct GrepTask {
+    results: mpsc::Receiver<DaemonGrep>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl GrepTask {
+    /// Starts searching the files of the target with the given `pid` (the agent's own files when
+    /// [`None`]).
+    pub(crate) fn new(pid: Option<u64>, request: GrepRequest) -> Self {
+        let (tx, results) = mpsc::channel(RESULTS_CAPACITY);
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let search = Search {
+            root: get_root_path_from_optional_pid(pid),
+            tx,
+            cancelled: cancelled.clone(),
+            summary: GrepSummary {
+                files_searched: 0,
+                files_skipped: 0,
+                matches: 0,
+                truncated: false,
+            },
+        };
+
+        let spawned = thread::Builder::new()
+            .name("grep".to_string())
+            .spawn(move || search.run(request));
+        if let Err(error) = spawned {
+            warn!(%error, "Failed to spawn the grep thread");
+        }
+
+        Self { results, cancelled }
+    }
+
+    /// Returns the next result of the search, [`None`] after [`DaemonGrep::Finished`].
+    ///
+    /// Cancel safe.
+    pub(crate) async fn recv(&mut self) -> Option<DaemonGrep> {
+        self.results.recv().await
+    }
+}
+
+impl Drop for GrepTask {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// State of the search, lives in the search thread.
+struct Search {
+    /// Root of the target's filesystem, as seen from the agent.
+    root: PathBuf,
+    tx: mpsc::Sender<DaemonGrep>,
+    cancelled: Arc<AtomicBool>,
+    summary: GrepSummary,
+}
+
+/// Whether the search should go on.
+enum Flow {
+    Continue,
+    Stop,
+}
+
+impl Search {
+    fn run(mut self, request: GrepRequest) {
+        // `PRIO_PROCESS` with a thread id changes only this thread.
+        let priority = unsafe {
+            libc::setpriority(
+                libc::PRIO_PROCESS,
+                libc::gettid() as libc::id_t,
+                SEARCH_NICENESS,
+            )
+        };
+        if priority == -1 {
+            warn!(
+                error = %io::Error::last_os_error(),
+                "Failed to lower the priority of the grep thread"
+            );
+        }
+
+        let result = self.search(&request).map(|()| self.summary.clone());
+        let _ = self.tx.blocking_send(DaemonGrep::Finished(result));
+    }
+
+    fn search(&mut self, request: &GrepRequest) -> RemoteResult<()> {
+        let regex = RegexBuilder::new(&request.pattern)
+            .case_insensitive(request.ignore_case)
+            .build()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+        for path in &request.paths {
+            // Follows the symlinks in the given path, but not the ones found while walking the
+            // directories.
+            let host_path = resolve_path(path, &self.root)?;
+
+            if let Flow::Stop = self.walk(host_path, path.clone(), &regex, request.max_matches) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Searches the file or directory at `host_path`. `path` is the same path, as seen by the
+    /// target.
+    fn walk(&mut self, host_path: PathBuf, path: PathBuf, regex: &Regex, max: u64) -> Flow {
+        let mut pending = vec![(host_path, path)];
+
+        while let Some((host_path, path)) = pending.pop() {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Flow::Stop;
+            }
+
+            let Ok(metadata) = host_path.symlink_metadata() else {
+                self.summary.files_skipped += 1;
+                continue;
+            };
+
+            if metadata.is_dir() {
+                let Ok(entries) = host_path.read_dir() else {
+                    self.summary.files_skipped += 1;
+                    continue;
+                };
+
+                let mut entries = entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.file_name())
+                    .collect::<Vec<_>>();
+                // Reversed, so the entries are popped in order.
+                entries.sort_unstable_by(|a, b| b.cmp(a));
+                pending.extend(
+                    entries
+                        .into_iter()
+                        .map(|name| (host_path.join(&name), path.join(name))),
+                );
+            } else if metadata.is_file() {
+                let Ok(file) = File::open(&host_path) else {
+                    self.summary.files_skipped += 1;
+                    continue;
+                };
+
+                if let Flow::Stop = self.search_file(BufReader::new(file), &path, regex, max) {
+                    return Flow::Stop;
+                }
+            }
+        }
+
+        Flow::Continue
+    }
+
+    /// Sends the lines of `reader` that match the `regex`.
+    fn search_file<R: BufRead>(
+        &mut self,
+        mut reader: R,
+        path: &Path,
+        regex: &Regex,
+        max: u64,
+    ) -> Flow {
+        match reader.fill_buf() {
+            Ok(start) if start.iter().take(BINARY_CHECK_LEN).any(|byte| *byte == 0) => {
+                self.summary.files_skipped += 1;
+                return Flow::Continue;
+            }
+            Ok(..) => {}
+            Err(..) => {
+                self.summary.files_skipped += 1;
+                return Flow::Continue;
+            }
+        }
+
+        self.summary.files_searched += 1;
+
+        let mut line = Vec::new();
+        let mut line_number = 0;
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(..) => return Flow::Continue,
+                Ok(..) => line_number += 1,
+            }
+
+            let content = line
+                .strip_suffix(b"\n")
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+                .unwrap_or(&line);
+            if !regex.is_match(content) {
+                continue;
+            }
+
+            if self.summary.matches >= max {
+                self.summary.truncated = true;
+                return Flow::Stop;
+            }
+            self.summary.matches += 1;
+
+            let grep_match = GrepMatch {
+                path: path.to_path_buf(),
+                line_number,
+                line: String::from_utf8_lossy(content.get(..MAX_LINE_LEN).unwrap_or(content))
+                    .into_owned(),
+            };
+            if self
+                .tx
+                .blocking_send(DaemonGrep::Match(grep_match))
+                .is_err()
+            {
+                // The client is gone.
+                return Flow::Stop;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn search() -> (Search, mpsc::Receiver<DaemonGrep>) {
+        let (tx, rx) = mpsc::channel(RESULTS_CAPACITY);
+        let search = Search {
+            root: PathBuf::from("/"),
+            tx,
+            cancelled: Default::default(),
+            summary: GrepSummary {
+                files_searched: 0,
+                files_skipped: 0,
+                matches: 0,
+                truncated: false,
+            },
+        };
+
+        (search, rx)
+    }
+
+    #[test]
+    fn matching_lines_are_sent() {
+        let (mut search, mut rx) = search();
+        let regex = Regex::new("value=\\d+").unwrap();
+        let contents = b"first\nvalue=1\r\nvalue=x\nlast value=22";
+
+        search.search_file(&contents[..], Path::new("/etc/app.conf"), &regex, 10);
+
+        let lines = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|result| match result {
+                DaemonGrep::Match(grep_match) => (grep_match.line_number, grep_match.line),
+                other => panic!("unexpected result {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![(2, "value=1".to_string()), (4, "last value=22".to_string())]
+        );
+        assert_eq!(search.summary.files_searched, 1);
+        assert!(!search.summary.truncated);
+    }
+
+    #[test]
+    fn stops_after_max_matches() {
+        let (mut search, _rx) = search();
+        let regex = Regex::new("a").unwrap();
+
+        let flow = search.search_file(&b"a\na\na\n"[..], Path::new("/a"), &regex, 2);
+
+        assert!(matches!(flow, Flow::Stop));
+        assert_eq!(search.summary.matches, 2);
+        assert!(search.summary.truncated);
+    }
+
+    #[test]
+    fn binary_files_are_skipped() {
+        let (mut search, mut rx) = search();
+        let regex = Regex::new("a").unwrap();
+
+        search.search_file(&b"a\0a\n"[..], Path::new("/a"), &regex, 10);
+
+        assert!(rx.try_recv().is_err());
+        assert_eq!(search.summary.files_skipped, 1);
+        assert_eq!(search.summary.files_searched, 0);
+    }
+}
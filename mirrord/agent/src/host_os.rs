@@ -0,0 +1,132 @@
+//! Detection of the OS running on the agent's node.
+//!
+//! Immutable OSes like Talos and Bottlerocket lack some of the things the agent assumes are on the
+//! host, so we adjust to them (e.g. [`HostOs::requires_nftables`]) and warn the clients about
+//! features that won't work there (see [`HostOs::preflight`]).
+//!
+//! The host is only visible to targeted agents, which run in the host's PID namespace. For other
+//! agents the OS is always [`HostOs::Unknown`].
+
+use std::{fs, path::Path, sync::LazyLock};
+
+use tracing::debug;
+
+/// `os-release` of the host, accessed through the root of the host's init process.
+const HOST_OS_RELEASE_PATH: &str = "/proc/1/root/etc/os-release";
+
+/// The OS of the node, detected once on first use.
+static HOST_OS: LazyLock<HostOs> = LazyLock::new(|| {
+    let host_os = HostOs::detect(Path::new(HOST_OS_RELEASE_PATH));
+    debug!(?host_os, "Detected host OS");
+    host_os
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HostOs {
+    Talos,
+    Bottlerocket,
+    /// Any other OS, with the `ID` from its `os-release`.
+    Other(String),
+    /// We were not able to read the host's `os-release`.
+    Unknown,
+}
+
+impl HostOs {
+    /// Returns the OS of the node the agent is running on.
+    pub(crate) fn get() -> &'static Self {
+        &HOST_OS
+    }
+
+    fn detect(os_release: &Path) -> Self {
+        fs::read_to_string(os_release)
+            .map(|os_release| Self::from_os_release(&os_release))
+            .unwrap_or(Self::Unknown)
+    }
+
+    /// Parses the `ID` from the contents of an `os-release` file.
+    fn from_os_release(os_release: &str) -> Self {
+        let id = os_release
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("ID="))
+            .map(|id| id.trim_matches('"').to_lowercase())
+            .next();
+
+        match id.as_deref() {
+            Some("talos") => Self::Talos,
+            Some("bottlerocket") => Self::Bottlerocket,
+            Some(id) => Self::Other(id.to_string()),
+            None => Self::Unknown,
+        }
+    }
+
+    /// These OSes don't ship the legacy iptables kernel modules, so the agent has to use
+    /// `iptables-nft` even when nftables were not requested.
+    pub(crate) fn requires_nftables(&self) -> bool {
+        matches!(self, Self::Talos | Self::Bottlerocket)
+    }
+
+    /// Containerd sockets to try first on this OS, the paths are relative to the agent's root
+    /// (where the host's `/run` is mounted under `/host/run`).
+    pub(crate) fn containerd_sock_paths(&self) -> &'static [&'static str] {
+        match self {
+            // The Kubernetes containerd is exposed under the legacy dockershim path, the default
+            // containerd socket belongs to the host containers.
+            Self::Bottlerocket => &["/host/run/dockershim.sock"],
+            // CRI containerd, the system one under `/system/run` doesn't run the pods.
+            Self::Talos => &["/host/run/containerd/containerd.sock"],
+            Self::Other(..) | Self::Unknown => &[],
+        }
+    }
+
+    /// Lists the features that don't work as configured on this OS, so the clients can be warned
+    /// before using them.
+    ///
+    /// `nftables` is whether the user requested `agent.nftables`.
+    pub(crate) fn preflight(&self, nftables: bool) -> Vec<String> {
+        let name = match self {
+            Self::Talos => "Talos",
+            Self::Bottlerocket => "Bottlerocket",
+            Self::Other(..) | Self::Unknown => return vec![],
+        };
+
+        if nftables {
+            return vec![];
+        }
+
+        vec![format!(
+            "The agent's node runs {name}, which doesn't support iptables-legacy. The agent uses \
+             nftables instead, set `agent.nftables` to `true` to silence this warning."
+        )]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_os_release() {
+        assert_eq!(
+            HostOs::from_os_release("NAME=\"Talos\"\nID=talos\nVERSION_ID=v1.7.0\n"),
+            HostOs::Talos
+        );
+        assert_eq!(
+            HostOs::from_os_release("NAME=Bottlerocket\nID=bottlerocket\n"),
+            HostOs::Bottlerocket
+        );
+        assert_eq!(
+            HostOs::from_os_release("ID=\"ubuntu\"\nID_LIKE=debian\n"),
+            HostOs::Other("ubuntu".to_string())
+        );
+        assert_eq!(HostOs::from_os_release("NAME=Unknown\n"), HostOs::Unknown);
+    }
+
+    #[test]
+    fn preflight_warnings() {
+        assert!(HostOs::Other("ubuntu".to_string())
+            .preflight(false)
+            .is_empty());
+        assert!(HostOs::Talos.preflight(true).is_empty());
+        assert_eq!(HostOs::Bottlerocket.preflight(false).len(), 1);
+    }
+}
@@ -0,0 +1,118 @@
+//! Detection of the TCP ports the target listens on, for `feature.network.incoming.ports: "auto"`.
+//!
+//! The client asks for it with [`ClientMessage::WatchListeners`](mirrord_protocol::ClientMessage),
+//! and from then on [`ListenersWatch`] reads the socket tables of the target's network namespace
+//! every [`ListenersWatch::INTERVAL`], so the client learns about listeners that appear later in
+//! the session as well.
+
+use std::{collections::BTreeSet, io, path::PathBuf, time::Duration};
+
+use mirrord_protocol::Port;
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+/// State of listening sockets in `/proc/net/tcp`, see `include/net/tcp_states.h`.
+const TCP_LISTEN: &str = "0A";
+
+/// Periodically reads the listening TCP sockets of the target, see [`ListenersWatch::changed`].
+pub(crate) struct ListenersWatch {
+    /// `/proc/<pid>/net` of the target, or `/proc/self/net` when there's no target container.
+    net_path: PathBuf,
+    interval: Interval,
+    /// Ports found by the last scan, [`None`] before the first one.
+    ports: Option<Vec<Port>>,
+}
+
+impl ListenersWatch {
+    /// How often the socket tables are read.
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    pub(crate) fn new(pid: Option<u64>) -> Self {
+        let net_path = PathBuf::from("/proc")
+            .join(pid.map(|pid| pid.to_string()).as_deref().unwrap_or("self"))
+            .join("net");
+
+        let mut interval = time::interval(Self::INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            net_path,
+            interval,
+            ports: None,
+        }
+    }
+
+    /// Returns the sorted listening ports, as soon as they differ from the ones returned
+    /// previously. The first call returns right away.
+    ///
+    /// Cancel safe.
+    pub(crate) async fn changed(&mut self) -> io::Result<Vec<Port>> {
+        loop {
+            self.interval.tick().await;
+
+            let ports = self.scan().await?;
+            if self.ports.as_ref() != Some(&ports) {
+                self.ports = Some(ports.clone());
+                return Ok(ports);
+            }
+        }
+    }
+
+    /// Reads the listening ports from the IPv4 and IPv6 socket tables.
+    async fn scan(&self) -> io::Result<Vec<Port>> {
+        let mut ports = BTreeSet::new();
+
+        for table in ["tcp", "tcp6"] {
+            match tokio::fs::read_to_string(self.net_path.join(table)).await {
+                Ok(contents) => ports.extend(listening_ports(&contents)),
+                // There's no `tcp6` when IPv6 is disabled in the kernel.
+                Err(error) if error.kind() == io::ErrorKind::NotFound && table == "tcp6" => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(ports.into_iter().collect())
+    }
+}
+
+/// Parses the ports of the listening sockets from the contents of `/proc/net/tcp` or
+/// `/proc/net/tcp6`.
+fn listening_ports(table: &str) -> impl Iterator<Item = Port> + '_ {
+    // Skip the header.
+    table.lines().skip(1).filter_map(|line| {
+        // sl local_address rem_address st ...
+        let mut fields = line.split_whitespace();
+        let local_address = fields.nth(1)?;
+        let state = fields.nth(1)?;
+        if state != TCP_LISTEN {
+            return None;
+        }
+
+        let (_, port) = local_address.rsplit_once(':')?;
+        Port::from_str_radix(port, 16).ok()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_tcp_table() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1234 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1235 1 0000000000000000 100 0 0 10 0
+   2: 0A00000F:1F90 0A000010:D2F4 01 00000000:00000000 00:00000000 00000000     0        0 1236 1 0000000000000000 20 4 30 10 -1
+";
+
+        assert_eq!(listening_ports(table).collect::<Vec<_>>(), vec![8080, 3306]);
+    }
+
+    #[test]
+    fn parse_tcp6_table() {
+        let table = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000000000000:0050 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 4321 1 0000000000000000 100 0 0 10 0
+";
+
+        assert_eq!(listening_ports(table).collect::<Vec<_>>(), vec![80]);
+    }
+}
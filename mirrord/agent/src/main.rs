@@ -22,18 +22,36 @@ mod error;
 #[cfg(target_os = "linux")]
 mod file;
 #[cfg(target_os = "linux")]
+mod fs_usage;
+#[cfg(target_os = "linux")]
+mod grep;
+#[cfg(target_os = "linux")]
+mod host_os;
+#[cfg(target_os = "linux")]
 mod http;
 #[cfg(target_os = "linux")]
+mod listeners;
+#[cfg(target_os = "linux")]
+mod metrics;
+#[cfg(target_os = "linux")]
 mod namespace;
 #[cfg(target_os = "linux")]
 mod outgoing;
 #[cfg(target_os = "linux")]
 mod runtime;
 #[cfg(target_os = "linux")]
+mod scratch;
+#[cfg(target_os = "linux")]
 mod sniffer;
 #[cfg(target_os = "linux")]
 mod steal;
 #[cfg(target_os = "linux")]
+mod transport;
+#[cfg(target_os = "linux")]
+mod udp_incoming;
+#[cfg(target_os = "linux")]
+mod unprivileged;
+#[cfg(target_os = "linux")]
 mod util;
 #[cfg(target_os = "linux")]
 mod watched_task;
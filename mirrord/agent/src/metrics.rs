@@ -0,0 +1,395 @@
+//! Metrics about the sessions served by the agent, pushed to an OTLP endpoint (`agent.otlp_metrics`
+//! in the config).
+//!
+//! Meant for clusters without the operator, or without a scrape infrastructure, where there's no
+//! other way to see how mirrord is used on a shared environment. The metrics are kept in a few
+//! atomics, and [`OtlpMetricsExporter`] sends their current values every
+//! [`OtlpMetricsExporter::interval`], encoded as OTLP JSON over HTTP. Only plain HTTP is supported,
+//! so this is usually an OpenTelemetry Collector running in the cluster.
+
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        LazyLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use http::{
+    header::{CONTENT_TYPE, HOST},
+    uri::PathAndQuery,
+    HeaderName, HeaderValue, Request, Uri,
+};
+use http_body_util::Full;
+use hyper::client::conn::http1;
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::error::{AgentError, Result};
+
+/// Path of the metrics in the OTLP/HTTP API, appended to endpoints that don't have one.
+const OTLP_METRICS_PATH: &str = "/v1/metrics";
+
+/// Default for [`OtlpMetricsExporter::interval`].
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a single export can take.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time the agent started, the start of the cumulative sums.
+static START_TIME: LazyLock<SystemTime> = LazyLock::new(SystemTime::now);
+
+/// Clients connected right now.
+static CLIENTS: AtomicI64 = AtomicI64::new(0);
+
+/// Clients that connected since the agent started.
+static CLIENT_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Messages received from the clients, by [`MessageKind`].
+static CLIENT_MESSAGES: [AtomicU64; MessageKind::ALL.len()] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; MessageKind::ALL.len()]
+};
+
+/// Kinds of client messages we count, the `kind` attribute of `mirrord.agent.client.messages`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MessageKind {
+    File,
+    Dns,
+    Env,
+    OutgoingTcp,
+    OutgoingUdp,
+    Mirror,
+    Steal,
+}
+
+impl MessageKind {
+    const ALL: [Self; 7] = [
+        Self::File,
+        Self::Dns,
+        Self::Env,
+        Self::OutgoingTcp,
+        Self::OutgoingUdp,
+        Self::Mirror,
+        Self::Steal,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Dns => "dns",
+            Self::Env => "env",
+            Self::OutgoingTcp => "outgoing_tcp",
+            Self::OutgoingUdp => "outgoing_udp",
+            Self::Mirror => "mirror",
+            Self::Steal => "steal",
+        }
+    }
+}
+
+/// A client message of the given kind was received.
+pub(crate) fn client_message(kind: MessageKind) {
+    CLIENT_MESSAGES[kind as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a connected client for as long as it's alive.
+pub(crate) struct ClientGuard(());
+
+impl ClientGuard {
+    pub(crate) fn new() -> Self {
+        CLIENTS.fetch_add(1, Ordering::Relaxed);
+        CLIENT_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        CLIENTS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Nanoseconds since the epoch, as a string, like OTLP JSON encodes 64 bit integers.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// The current values of the metrics, as an OTLP `ExportMetricsServiceRequest`.
+fn export_request(mode: &str) -> Value {
+    let start = unix_nanos(*START_TIME);
+    let now = unix_nanos(SystemTime::now());
+
+    let messages = MessageKind::ALL
+        .iter()
+        .map(|kind| {
+            json!({
+                "attributes": [{ "key": "kind", "value": { "stringValue": kind.as_str() } }],
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+                "asInt": CLIENT_MESSAGES[*kind as usize].load(Ordering::Relaxed).to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": "mirrord-agent" } },
+                    { "key": "service.version", "value": { "stringValue": env!("CARGO_PKG_VERSION") } },
+                    { "key": "mirrord.agent.mode", "value": { "stringValue": mode } },
+                ],
+            },
+            "scopeMetrics": [{
+                "scope": { "name": "mirrord-agent", "version": env!("CARGO_PKG_VERSION") },
+                "metrics": [
+                    {
+                        "name": "mirrord.agent.clients",
+                        "description": "Clients connected to the agent.",
+                        "unit": "{client}",
+                        "gauge": {
+                            "dataPoints": [{
+                                "timeUnixNano": now,
+                                "asInt": CLIENTS.load(Ordering::Relaxed).to_string(),
+                            }],
+                        },
+                    },
+                    {
+                        "name": "mirrord.agent.client.connections",
+                        "description": "Clients that connected to the agent.",
+                        "unit": "{connection}",
+                        "sum": {
+                            // Cumulative.
+                            "aggregationTemporality": 2,
+                            "isMonotonic": true,
+                            "dataPoints": [{
+                                "startTimeUnixNano": start,
+                                "timeUnixNano": now,
+                                "asInt": CLIENT_CONNECTIONS.load(Ordering::Relaxed).to_string(),
+                            }],
+                        },
+                    },
+                    {
+                        "name": "mirrord.agent.client.messages",
+                        "description": "Messages received from the clients, by kind.",
+                        "unit": "{message}",
+                        "sum": {
+                            "aggregationTemporality": 2,
+                            "isMonotonic": true,
+                            "dataPoints": messages,
+                        },
+                    },
+                ],
+            }],
+        }],
+    })
+}
+
+/// Parses headers in the format of `OTEL_EXPORTER_OTLP_HEADERS`, `key1=value1,key2=value2`.
+fn parse_headers(headers: &str) -> Result<Vec<(HeaderName, HeaderValue)>> {
+    headers
+        .split(',')
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .map(|header| {
+            let (name, value) = header.split_once('=').ok_or_else(|| {
+                AgentError::OtlpMetrics(format!("header `{header}` is not `key=value`"))
+            })?;
+
+            let name = HeaderName::try_from(name.trim())
+                .map_err(|error| AgentError::OtlpMetrics(error.to_string()))?;
+            let value = HeaderValue::try_from(value.trim())
+                .map_err(|error| AgentError::OtlpMetrics(error.to_string()))?;
+
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Adds [`OTLP_METRICS_PATH`] to an endpoint without a path.
+fn metrics_uri(endpoint: &str) -> Result<Uri> {
+    let uri = endpoint
+        .parse::<Uri>()
+        .map_err(|error| AgentError::OtlpMetrics(format!("invalid endpoint: {error}")))?;
+
+    if uri.scheme_str() != Some("http") {
+        return Err(AgentError::OtlpMetrics(format!(
+            "endpoint {endpoint} is not an http:// URL"
+        )));
+    }
+
+    if uri.path() != "/" {
+        return Ok(uri);
+    }
+
+    let mut parts = uri.into_parts();
+    parts.path_and_query = Some(PathAndQuery::from_static(OTLP_METRICS_PATH));
+    Uri::from_parts(parts)
+        .map_err(|error| AgentError::OtlpMetrics(format!("invalid endpoint: {error}")))
+}
+
+/// Pushes the metrics to an OTLP/HTTP endpoint.
+pub(crate) struct OtlpMetricsExporter {
+    uri: Uri,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    interval: Duration,
+    /// `mirrord.agent.mode` resource attribute.
+    mode: &'static str,
+}
+
+impl OtlpMetricsExporter {
+    pub(crate) const TASK_NAME: &'static str = "OtlpMetricsExporter";
+
+    /// `headers` are in the format of `OTEL_EXPORTER_OTLP_HEADERS`, `interval` is in seconds.
+    pub(crate) fn new(
+        endpoint: &str,
+        headers: Option<&str>,
+        interval: Option<u64>,
+        mode: &'static str,
+    ) -> Result<Self> {
+        LazyLock::force(&START_TIME);
+
+        Ok(Self {
+            uri: metrics_uri(endpoint)?,
+            headers: headers.map(parse_headers).transpose()?.unwrap_or_default(),
+            interval: interval
+                .filter(|interval| *interval > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_INTERVAL),
+            mode,
+        })
+    }
+
+    /// Exports the metrics every [`Self::interval`], and one last time when cancelled.
+    pub(crate) async fn run(self, cancellation_token: CancellationToken) {
+        let mut interval = tokio::time::interval(self.interval);
+
+        loop {
+            let cancelled = tokio::select! {
+                _ = interval.tick() => false,
+                _ = cancellation_token.cancelled() => true,
+            };
+
+            match tokio::time::timeout(EXPORT_TIMEOUT, self.export()).await {
+                Ok(Ok(())) => debug!(uri = %self.uri, "Exported OTLP metrics"),
+                Ok(Err(error)) => warn!(%error, uri = %self.uri, "Failed to export OTLP metrics"),
+                Err(..) => warn!(uri = %self.uri, "Exporting OTLP metrics timed out"),
+            }
+
+            if cancelled {
+                break;
+            }
+        }
+    }
+
+    async fn export(&self) -> Result<()> {
+        let host = self
+            .uri
+            .host()
+            .ok_or_else(|| AgentError::OtlpMetrics("endpoint has no host".to_string()))?;
+        let port = self.uri.port_u16().unwrap_or(80);
+        let authority = self
+            .uri
+            .authority()
+            .map(|authority| authority.as_str())
+            .unwrap_or(host);
+
+        let stream = TcpStream::connect((host, port)).await?;
+        let (mut request_sender, connection) = http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(|error| AgentError::OtlpMetrics(error.to_string()))?;
+        tokio::spawn(connection);
+
+        let mut request = Request::post(self.uri.clone())
+            .header(HOST, authority)
+            .header(CONTENT_TYPE, "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let request = request
+            .body(Full::new(Bytes::from(
+                export_request(self.mode).to_string(),
+            )))
+            .map_err(|error| AgentError::OtlpMetrics(error.to_string()))?;
+
+        let response = request_sender
+            .send_request(request)
+            .await
+            .map_err(|error| AgentError::OtlpMetrics(error.to_string()))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(AgentError::OtlpMetrics(format!(
+                "endpoint responded with {}",
+                response.status()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_path() {
+        assert_eq!(
+            metrics_uri("http://collector:4318").unwrap(),
+            "http://collector:4318/v1/metrics"
+        );
+        assert_eq!(
+            metrics_uri("http://collector:4318/custom/metrics").unwrap(),
+            "http://collector:4318/custom/metrics"
+        );
+        assert!(metrics_uri("https://collector:4318").is_err());
+    }
+
+    #[test]
+    fn headers() {
+        let headers = parse_headers("api-key=secret, x-team = infra,").unwrap();
+
+        assert_eq!(
+            headers,
+            vec![
+                (
+                    HeaderName::from_static("api-key"),
+                    HeaderValue::from_static("secret")
+                ),
+                (
+                    HeaderName::from_static("x-team"),
+                    HeaderValue::from_static("infra")
+                ),
+            ]
+        );
+        assert!(parse_headers("api-key").is_err());
+    }
+
+    #[test]
+    fn counts_clients_and_messages() {
+        let client = ClientGuard::new();
+        client_message(MessageKind::Steal);
+
+        let request = export_request("targeted");
+        let metrics = &request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics[0]["name"], "mirrord.agent.clients");
+
+        let steal = metrics[2]["sum"]["dataPoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|point| point["attributes"][0]["value"]["stringValue"] == "steal")
+            .unwrap();
+        assert_ne!(steal["asInt"], "0");
+
+        drop(client);
+    }
+}
@@ -1,4 +1,4 @@
-use std::fs::File;
+use std::fs::{self, File};
 
 use nix::sched::{setns, CloneFlags};
 use thiserror::Error;
@@ -15,6 +15,7 @@ pub(crate) enum NamespaceError {
 #[derive(Debug)]
 pub(crate) enum NamespaceType {
     Net,
+    Mnt,
 }
 
 impl NamespaceType {
@@ -22,6 +23,15 @@ impl NamespaceType {
     fn path_from_pid(&self, pid: u64) -> String {
         match self {
             NamespaceType::Net => format!("/proc/{}/ns/net", pid),
+            NamespaceType::Mnt => format!("/proc/{}/ns/mnt", pid),
+        }
+    }
+
+    /// Path of the namespace of the calling thread.
+    fn own_path(&self) -> &'static str {
+        match self {
+            NamespaceType::Net => "/proc/thread-self/ns/net",
+            NamespaceType::Mnt => "/proc/thread-self/ns/mnt",
         }
     }
 }
@@ -30,6 +40,7 @@ impl From<NamespaceType> for CloneFlags {
     fn from(ns_type: NamespaceType) -> Self {
         match ns_type {
             NamespaceType::Net => CloneFlags::CLONE_NEWNET,
+            NamespaceType::Mnt => CloneFlags::CLONE_NEWNS,
         }
     }
 }
@@ -39,7 +50,16 @@ impl From<NamespaceType> for CloneFlags {
 /// happen on the same thread always.
 #[tracing::instrument(level = "trace")]
 pub(crate) fn set_namespace(pid: u64, namespace_type: NamespaceType) -> Result<(), NamespaceError> {
-    let fd = File::open(namespace_type.path_from_pid(pid))?;
+    let path = namespace_type.path_from_pid(pid);
+
+    // Entering the namespace we're already in (e.g. the network namespace of the target, from an
+    // ephemeral container) needs `SYS_ADMIN` too, which an unprivileged agent doesn't have.
+    let own = fs::read_link(namespace_type.own_path()).ok();
+    if own.is_some() && fs::read_link(&path).ok() == own {
+        return Ok(());
+    }
+
+    let fd = File::open(path)?;
 
     // use as_raw_fd to get reference so it will drop after setns
     setns(fd, namespace_type.into())?;
@@ -3,7 +3,7 @@ use std::{collections::HashMap, fmt, thread, time::Duration};
 use bytes::Bytes;
 use mirrord_protocol::{
     outgoing::{tcp::*, *},
-    ConnectionId, RemoteError, ResponseError,
+    ConnectionId, ErrorKindInternal, RemoteError, RemoteResult, ResponseError,
 };
 use socket_stream::SocketStream;
 use streammap_ext::StreamMap;
@@ -17,6 +17,7 @@ use tokio_stream::StreamExt;
 use tokio_util::io::ReaderStream;
 
 use crate::{
+    cli::Args,
     error::Result,
     util::run_thread_in_namespace,
     watched_task::{TaskStatus, WatchedTask},
@@ -27,6 +28,65 @@ mod udp;
 
 pub(crate) use udp::UdpOutgoingApi;
 
+/// How the [`TcpOutgoingTask`] connects to the remote addresses, see
+/// [`Args::outgoing_connect_timeout`] and [`Args::outgoing_connect_retries`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ConnectPolicy {
+    /// Timeout of each connect attempt.
+    timeout: Duration,
+    /// Attempts made after the first one failed with a transient error.
+    retries: u32,
+}
+
+impl ConnectPolicy {
+    /// Default timeout of each connect attempt.
+    ///
+    /// # TODO(alex)
+    /// This timeout works around the issue where golang tries to connect
+    /// to an invalid socket address and hangs until the socket times out.
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+    /// Wait before the first retry, doubled for every next one.
+    const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+    /// Longest wait between two attempts.
+    const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+    pub(crate) fn new(args: &Args) -> Self {
+        Self {
+            timeout: args
+                .outgoing_connect_timeout
+                .map(Duration::from_millis)
+                .unwrap_or(Self::DEFAULT_TIMEOUT),
+            retries: args.outgoing_connect_retries,
+        }
+    }
+
+    /// Wait before the given retry (starting from 1).
+    fn backoff(retry: u32) -> Duration {
+        Self::RETRY_BACKOFF
+            .saturating_mul(2_u32.saturating_pow(retry.saturating_sub(1)))
+            .min(Self::MAX_RETRY_BACKOFF)
+    }
+
+    /// Whether a connect attempt that failed with `error` might succeed when retried.
+    fn is_transient(error: &ResponseError) -> bool {
+        match error {
+            ResponseError::Remote(RemoteError::ConnectTimedOut(..)) => true,
+            ResponseError::RemoteIO(error) => matches!(
+                error.kind,
+                ErrorKindInternal::ConnectionRefused
+                    | ErrorKindInternal::ConnectionReset
+                    | ErrorKindInternal::ConnectionAborted
+                    | ErrorKindInternal::HostUnreachable
+                    | ErrorKindInternal::NetworkUnreachable
+                    | ErrorKindInternal::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+
 /// An interface for a background task handling [`LayerTcpOutgoing`] messages.
 /// Each agent client has their own independent instance (neither this wrapper nor the background
 /// task are shared).
@@ -53,14 +113,15 @@ impl TcpOutgoingApi {
     /// # Params
     ///
     /// * `pid` - process id of the agent's target container
+    /// * `connect_policy` - how to connect to the remote addresses
     #[tracing::instrument(level = "trace")]
-    pub(crate) fn new(pid: Option<u64>) -> Self {
+    pub(crate) fn new(pid: Option<u64>, connect_policy: ConnectPolicy) -> Self {
         let (layer_tx, layer_rx) = mpsc::channel(1000);
         let (daemon_tx, daemon_rx) = mpsc::channel(1000);
 
         let watched_task = WatchedTask::new(
             Self::TASK_NAME,
-            TcpOutgoingTask::new(pid, layer_rx, daemon_tx).run(),
+            TcpOutgoingTask::new(pid, connect_policy, layer_rx, daemon_tx).run(),
         );
         let task_status = watched_task.status();
         let task = run_thread_in_namespace(
@@ -106,6 +167,7 @@ struct TcpOutgoingTask {
     readers: StreamMap<ConnectionId, ReaderStream<ReadHalf<SocketStream>>>,
     /// Optional pid of agent's target. Used in [`SocketStream::connect`].
     pid: Option<u64>,
+    connect_policy: ConnectPolicy,
     layer_rx: Receiver<LayerTcpOutgoing>,
     daemon_tx: Sender<DaemonTcpOutgoing>,
 }
@@ -117,6 +179,7 @@ impl fmt::Debug for TcpOutgoingTask {
             .field("writers", &self.writers.len())
             .field("readers", &self.readers.len())
             .field("pid", &self.pid)
+            .field("connect_policy", &self.connect_policy)
             .finish()
     }
 }
@@ -125,15 +188,9 @@ impl TcpOutgoingTask {
     /// Buffer size for reading from the outgoing connections.
     const READ_BUFFER_SIZE: usize = 64 * 1024;
 
-    /// Timeout for connect attempts.
-    ///
-    /// # TODO(alex)
-    /// This timeout works around the issue where golang tries to connect
-    /// to an invalid socket address and hangs until the socket times out.
-    const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
-
     fn new(
         pid: Option<u64>,
+        connect_policy: ConnectPolicy,
         layer_rx: Receiver<LayerTcpOutgoing>,
         daemon_tx: Sender<DaemonTcpOutgoing>,
     ) -> Self {
@@ -142,11 +199,54 @@ impl TcpOutgoingTask {
             writers: Default::default(),
             readers: Default::default(),
             pid,
+            connect_policy,
             layer_rx,
             daemon_tx,
         }
     }
 
+    /// Connects to the `remote_address`, retrying according to the [`ConnectPolicy`].
+    ///
+    /// Returns the error of the last attempt when all of them fail.
+    async fn connect(&self, remote_address: &SocketAddress) -> RemoteResult<SocketStream> {
+        let ConnectPolicy { timeout, retries } = self.connect_policy;
+        let mut retry = 0;
+
+        loop {
+            let result = time::timeout(
+                timeout,
+                SocketStream::connect(remote_address.clone(), self.pid),
+            )
+            .await
+            .unwrap_or_else(|_elapsed| {
+                tracing::warn!(
+                    %remote_address,
+                    connect_timeout_ms = timeout.as_millis(),
+                    "Connect attempt timed out."
+                );
+
+                Err(ResponseError::Remote(RemoteError::ConnectTimedOut(
+                    remote_address.clone(),
+                )))
+            });
+
+            match result {
+                Err(error) if retry < retries && ConnectPolicy::is_transient(&error) => {
+                    retry += 1;
+                    tracing::debug!(
+                        %remote_address,
+                        %error,
+                        retry,
+                        "Connect attempt failed, retrying."
+                    );
+
+                    time::sleep(ConnectPolicy::backoff(retry)).await;
+                }
+                result => break result,
+            }
+        }
+    }
+
     /// Runs this task as long as the channels connecting it with [`TcpOutgoingApi`] are open.
     async fn run(mut self) -> Result<()> {
         loop {
@@ -257,23 +357,8 @@ impl TcpOutgoingTask {
             // We make connection to the requested address, split the stream into halves with
             // `io::split`, and put them into respective maps.
             LayerTcpOutgoing::Connect(LayerConnect { remote_address }) => {
-                let daemon_connect = time::timeout(
-                    Self::CONNECT_TIMEOUT,
-                    SocketStream::connect(remote_address.clone(), self.pid),
-                )
-                .await
-                .unwrap_or_else(|_elapsed| {
-                    tracing::warn!(
-                        %remote_address,
-                        connect_timeout_ms = Self::CONNECT_TIMEOUT.as_millis(),
-                        "Connect attempt timed out."
-                    );
-
-                    Err(ResponseError::Remote(RemoteError::ConnectTimedOut(
-                        remote_address.clone(),
-                    )))
-                })
-                .and_then(|remote_stream| {
+                let connected = self.connect(&remote_address).await;
+                let daemon_connect = connected.and_then(|remote_stream| {
                     let agent_address = remote_stream.local_addr()?;
                     let connection_id = self.next_connection_id;
                     self.next_connection_id += 1;
@@ -18,6 +18,7 @@ use tower::service_fn;
 use crate::{
     env::parse_raw_env,
     error::{AgentError, Result},
+    host_os::HostOs,
     runtime::crio::CriOContainer,
 };
 
@@ -180,7 +181,9 @@ impl ContainerdContainer {
     /// containerd socket to use and we need to find the one
     /// that manages our target container
     async fn get_channel(&self) -> Result<Channel> {
-        for sock_path in CONTAINERD_SOCK_PATHS {
+        let host_sock_paths = HostOs::get().containerd_sock_paths();
+
+        for sock_path in host_sock_paths.iter().chain(&CONTAINERD_SOCK_PATHS) {
             if let Ok(channel) =
                 connect_and_find_container(self.container_id.clone(), sock_path).await
             {
@@ -0,0 +1,335 @@
+//! Keeps a directory of the target in sync with a local directory of the client, started with
+//! [`ClientMessage::Scratch`](mirrord_protocol::ClientMessage::Scratch).
+//!
+//! Changes made in the target are picked up with inotify and sent to the client, changes made by
+//! the client are written to the directory. We remember the last state of every entry that both
+//! sides agree on, so that writing a change from the client doesn't send it back.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, RawFd},
+        unix::fs::PermissionsExt,
+    },
+    path::{Component, Path, PathBuf},
+};
+
+use mirrord_protocol::scratch::{
+    DaemonScratch, LayerScratch, ScratchEntry, ScratchEntryKind, MAX_SCRATCH_FILE_SIZE,
+};
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor},
+};
+use tokio::io::unix::AsyncFd;
+use tracing::{debug, warn};
+
+use crate::file::{get_root_path_from_optional_pid, resolve_path};
+
+/// Events of the watched directories that we care about.
+const WATCH_MASK: AddWatchFlags = AddWatchFlags::IN_CREATE
+    .union(AddWatchFlags::IN_CLOSE_WRITE)
+    .union(AddWatchFlags::IN_ATTRIB)
+    .union(AddWatchFlags::IN_MOVED_TO)
+    .union(AddWatchFlags::IN_MOVED_FROM)
+    .union(AddWatchFlags::IN_DELETE)
+    .union(AddWatchFlags::IN_DONT_FOLLOW)
+    .union(AddWatchFlags::IN_ONLYDIR);
+
+/// Lets us wait for the inotify events with [`AsyncFd`].
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Last synchronized state of an entry, compared with the current one to find out if it changed.
+#[derive(Debug, PartialEq, Eq)]
+enum EntryState {
+    Dir,
+    /// Hash of the mode and the contents.
+    File(u64),
+}
+
+impl EntryState {
+    fn of(entry: &ScratchEntry) -> Self {
+        match &entry.kind {
+            ScratchEntryKind::Dir => Self::Dir,
+            ScratchEntryKind::File(contents) => {
+                let mut hasher = DefaultHasher::new();
+                entry.mode.hash(&mut hasher);
+                contents.hash(&mut hasher);
+                Self::File(hasher.finish())
+            }
+        }
+    }
+}
+
+/// Synchronization of one directory, stopped when dropped.
+pub(crate) struct ScratchSync {
+    /// The synchronized directory, in the agent's filesystem.
+    root: PathBuf,
+    inotify: AsyncFd<InotifyFd>,
+    /// Watched directories, relative to the [`Self::root`].
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    /// Entries that the client has, relative to the [`Self::root`].
+    synced: HashMap<PathBuf, EntryState>,
+    /// Not yet taken with [`Self::recv`].
+    pending: VecDeque<DaemonScratch>,
+}
+
+impl ScratchSync {
+    /// Starts synchronizing the directory with the given absolute `path` in the target with the
+    /// given `pid` (in the agent's filesystem when [`None`]), creating it if needed.
+    ///
+    /// Everything that is already there is reported with [`Self::recv`].
+    pub(crate) fn new(pid: Option<u64>, path: PathBuf) -> io::Result<Self> {
+        let root = resolve_path(path, get_root_path_from_optional_pid(pid))?;
+        fs::create_dir_all(&root)?;
+
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+
+        let mut sync = Self {
+            root,
+            inotify: AsyncFd::new(InotifyFd(inotify))?,
+            dirs: Default::default(),
+            synced: Default::default(),
+            pending: Default::default(),
+        };
+        sync.watch_dir(PathBuf::new())?;
+
+        Ok(sync)
+    }
+
+    /// Returns the next change made in the target, or [`DaemonScratch::Stopped`] when we can no
+    /// longer watch the directory.
+    ///
+    /// Cancel safe.
+    pub(crate) async fn recv(&mut self) -> DaemonScratch {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return message;
+            }
+
+            let mut guard = match self.inotify.readable().await {
+                Ok(guard) => guard,
+                Err(error) => return DaemonScratch::Stopped(error.into()),
+            };
+
+            let events = match guard.get_inner().0.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => {
+                    guard.clear_ready();
+                    continue;
+                }
+                Err(errno) => return DaemonScratch::Stopped(io::Error::from(errno).into()),
+            };
+
+            for event in events {
+                self.handle_event(event);
+            }
+        }
+    }
+
+    /// Applies a change made by the client.
+    pub(crate) fn handle(&mut self, message: LayerScratch) {
+        let result = match message {
+            LayerScratch::Start(..) => Ok(()),
+            LayerScratch::Changed(entry) => self.write_entry(entry),
+            LayerScratch::Removed(path) => self.remove_entry(path),
+        };
+
+        if let Err(error) = result {
+            warn!(%error, "Failed to apply a change of the shared scratch directory");
+        }
+    }
+
+    fn write_entry(&mut self, entry: ScratchEntry) -> io::Result<()> {
+        let full_path = self.full_path(&entry.path)?;
+        let permissions = fs::Permissions::from_mode(entry.mode);
+        let state = EntryState::of(&entry);
+
+        // Remembered before writing, so that the events caused by the write are not sent back.
+        match entry.kind {
+            ScratchEntryKind::Dir => {
+                if full_path
+                    .symlink_metadata()
+                    .is_ok_and(|meta| !meta.is_dir())
+                {
+                    fs::remove_file(&full_path)?;
+                }
+                self.synced.insert(entry.path.clone(), state);
+                fs::create_dir_all(&full_path)?;
+                fs::set_permissions(&full_path, permissions)?;
+                self.watch_dir(entry.path)?;
+            }
+            ScratchEntryKind::File(contents) => {
+                if full_path.symlink_metadata().is_ok_and(|meta| meta.is_dir()) {
+                    self.forget(&entry.path);
+                    fs::remove_dir_all(&full_path)?;
+                }
+                self.synced.insert(entry.path, state);
+                fs::write(&full_path, contents)?;
+                fs::set_permissions(&full_path, permissions)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, path: PathBuf) -> io::Result<()> {
+        let full_path = self.full_path(&path)?;
+        self.forget(&path);
+
+        let result = match full_path.symlink_metadata() {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(&full_path),
+            Ok(..) => fs::remove_file(&full_path),
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Path of the entry in the agent's filesystem. Fails when the client sends a path that is
+    /// not inside the directory.
+    fn full_path(&self, relative: &Path) -> io::Result<PathBuf> {
+        let valid = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(..)));
+        if !valid || relative.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid path in the shared scratch directory: {relative:?}"),
+            ));
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    /// Forgets the entry and everything under it.
+    fn forget(&mut self, relative: &Path) -> bool {
+        let before = self.synced.len();
+        self.synced.retain(|path, _| !path.starts_with(relative));
+        self.synced.len() != before
+    }
+
+    /// Watches the directory, and reports everything in it.
+    fn watch_dir(&mut self, relative: PathBuf) -> io::Result<()> {
+        let full_path = self.root.join(&relative);
+        let descriptor = self.inotify.get_ref().0.add_watch(&full_path, WATCH_MASK)?;
+        self.dirs.insert(descriptor, relative.clone());
+
+        for dir_entry in fs::read_dir(&full_path)? {
+            self.report(relative.join(dir_entry?.file_name()));
+        }
+
+        Ok(())
+    }
+
+    /// Reports the entry to the client, if it changed since the last time.
+    fn report(&mut self, relative: PathBuf) {
+        let entry = match read_entry(&self.root, &relative) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return,
+            Err(error) => {
+                debug!(%error, ?relative, "Failed to read an entry of the shared scratch directory");
+                return;
+            }
+        };
+
+        let state = EntryState::of(&entry);
+        let is_dir = state == EntryState::Dir;
+        if self.synced.get(&relative) != Some(&state) {
+            self.synced.insert(relative.clone(), state);
+            self.pending.push_back(DaemonScratch::Changed(entry));
+        }
+
+        if is_dir {
+            if let Err(error) = self.watch_dir(relative) {
+                warn!(%error, "Failed to watch a directory of the shared scratch directory");
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: InotifyEvent) {
+        if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+            self.rescan();
+            return;
+        }
+
+        if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+            self.dirs.remove(&event.wd);
+            return;
+        }
+
+        let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+            return;
+        };
+        let relative = dir.join(name);
+
+        if event
+            .mask
+            .intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM)
+        {
+            if self.forget(&relative) {
+                self.pending.push_back(DaemonScratch::Removed(relative));
+            }
+        } else if event.mask.contains(AddWatchFlags::IN_CREATE)
+            && !event.mask.contains(AddWatchFlags::IN_ISDIR)
+        {
+            // Reported when it's closed after writing.
+        } else {
+            self.report(relative);
+        }
+    }
+
+    /// Some events were lost, finds the changes by comparing the directory with
+    /// [`Self::synced`].
+    fn rescan(&mut self) {
+        let gone = self
+            .synced
+            .keys()
+            .filter(|path| self.root.join(path).symlink_metadata().is_err())
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in gone {
+            if self.forget(&path) {
+                self.pending.push_back(DaemonScratch::Removed(path));
+            }
+        }
+
+        if let Err(error) = self.watch_dir(PathBuf::new()) {
+            warn!(%error, "Failed to rescan the shared scratch directory");
+        }
+    }
+}
+
+/// Reads the entry from the directory, [`None`] when it's not synchronized (too big, or neither a
+/// directory nor a regular file).
+fn read_entry(root: &Path, relative: &Path) -> io::Result<Option<ScratchEntry>> {
+    let full_path = root.join(relative);
+    let meta = full_path.symlink_metadata()?;
+    let mode = meta.permissions().mode() & 0o7777;
+
+    let kind = if meta.is_dir() {
+        ScratchEntryKind::Dir
+    } else if meta.is_file() && meta.len() <= MAX_SCRATCH_FILE_SIZE {
+        ScratchEntryKind::File(fs::read(&full_path)?)
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(ScratchEntry {
+        path: relative.to_path_buf(),
+        mode,
+        kind,
+    }))
+}
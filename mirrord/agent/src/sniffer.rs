@@ -20,18 +20,28 @@ use rawsocket::RawCapture;
 use tokio::{
     net::UdpSocket,
     select,
-    sync::mpsc::{self, Receiver, Sender},
+    sync::mpsc::{Receiver, Sender},
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, trace, warn};
 
+use self::{
+    buffer::{BufferReceiver, BufferSender},
+    ebpf::EbpfCapture,
+};
 use crate::{
+    cli::SnifferBackend,
     error::AgentError,
     http::HttpVersion,
     util::{ClientId, IndexAllocator, Subscriptions},
     watched_task::TaskStatus,
 };
 
+mod buffer;
+mod ebpf;
+
+pub(crate) use buffer::MirrorBufferPolicy;
+
 #[derive(Debug, Eq, Copy, Clone)]
 pub(crate) struct TcpSessionIdentifier {
     /// The remote address that is sending a packet to the impersonated pod.
@@ -140,7 +150,8 @@ async fn resolve_interface() -> Result<Option<String>, AgentError> {
 async fn prepare_sniffer(
     network_interface: Option<String>,
     mesh: Option<MeshVendor>,
-) -> Result<RawCapture, AgentError> {
+    backend: SnifferBackend,
+) -> Result<PacketCapture, AgentError> {
     // Priority is whatever the user set as an option to mirrord, then we check if we're in an istio
     // mesh, otherwise we try to get the appropriate interface.
     let interface = match network_interface.or_else(|| {
@@ -156,6 +167,16 @@ async fn prepare_sniffer(
     };
 
     trace!("Using {interface:#?} interface.");
+    if backend == SnifferBackend::Ebpf {
+        match EbpfCapture::new(&interface) {
+            Ok(capture) => return Ok(PacketCapture::Ebpf(capture)),
+            Err(error) => warn!(
+                %error,
+                "Failed to set up the eBPF sniffer, falling back to the raw socket sniffer"
+            ),
+        }
+    }
+
     let capture = RawCapture::from_interface_name(&interface)?;
     // We start with a BPF that drops everything so we won't receive *EVERYTHING*
     // as we don't know what the layer will ask us to listen for, so this is essentially setting
@@ -166,7 +187,39 @@ async fn prepare_sniffer(
     capture
         .ignore_outgoing()
         .map_err(AgentError::PacketIgnoreOutgoing)?;
-    Ok(capture)
+    Ok(PacketCapture::Raw(capture))
+}
+
+/// Source of the packets of [`TcpConnectionSniffer`], chosen with [`SnifferBackend`].
+///
+/// Both yield whole Ethernet frames of the incoming TCP traffic of the subscribed ports.
+enum PacketCapture {
+    Raw(RawCapture),
+    Ebpf(EbpfCapture),
+}
+
+impl PacketCapture {
+    async fn next(&mut self) -> Result<Vec<u8>, AgentError> {
+        match self {
+            Self::Raw(capture) => Ok(capture.next().await?),
+            Self::Ebpf(capture) => Ok(capture.next().await?),
+        }
+    }
+
+    /// Captures only the packets from or to these ports, nothing when empty.
+    fn set_ports(&mut self, ports: &[Port]) -> Result<(), AgentError> {
+        match self {
+            Self::Raw(capture) if ports.is_empty() => {
+                capture.set_filter(rawsocket::filter::build_drop_always())?
+            }
+            Self::Raw(capture) => {
+                capture.set_filter(rawsocket::filter::build_tcp_port_filter(ports))?
+            }
+            Self::Ebpf(capture) => capture.set_ports(ports)?,
+        };
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -210,7 +263,7 @@ fn get_tcp_packet(eth_packet: Vec<u8>) -> Option<(TcpSessionIdentifier, TcpPacke
 
 #[derive(Debug)]
 enum SnifferCommands {
-    NewAgent(Sender<DaemonTcp>),
+    NewAgent(BufferSender),
     Subscribe(Port),
     UnsubscribePort(Port),
     UnsubscribeConnection(ConnectionId),
@@ -240,8 +293,8 @@ pub(crate) struct TcpSnifferApi {
     client_id: ClientId,
     /// Channel used to send commands to the [`TcpConnectionSniffer`].
     sender: Sender<SnifferCommand>,
-    /// Channel used to receive messages from the [`TcpConnectionSniffer`].
-    receiver: Receiver<DaemonTcp>,
+    /// Buffer of the messages from the [`TcpConnectionSniffer`], see [`MirrorBufferPolicy`].
+    receiver: BufferReceiver,
     /// View on the sniffer task's status.
     task_status: TaskStatus,
 }
@@ -251,15 +304,15 @@ impl TcpSnifferApi {
     /// * `client_id` - id of the client using this struct
     /// * `sniffer_sender` - channel used to send commands to the [`TcpConnectionSniffer`]
     /// * `task_status` - handle to the [`TcpConnectionSniffer`] exit status
-    /// * `channel_size` - capacity of the channel connecting [`TcpConnectionSniffer`] back to this
+    /// * `mirror_buffer` - limits of the buffer between the [`TcpConnectionSniffer`] and this
     ///   struct
     pub async fn new(
         client_id: ClientId,
         sniffer_sender: Sender<SnifferCommand>,
         task_status: TaskStatus,
-        channel_size: usize,
+        mirror_buffer: &MirrorBufferPolicy,
     ) -> Result<TcpSnifferApi, AgentError> {
-        let (sender, receiver) = mpsc::channel(channel_size);
+        let (sender, receiver) = buffer::channel(client_id, mirror_buffer);
 
         sniffer_sender
             .send(SnifferCommand {
@@ -319,8 +372,8 @@ impl Drop for TcpSnifferApi {
 pub(crate) struct TcpConnectionSniffer {
     port_subscriptions: Subscriptions<Port, ClientId>,
     receiver: Receiver<SnifferCommand>,
-    client_senders: HashMap<ClientId, Sender<DaemonTcp>>,
-    raw_capture: RawCapture,
+    client_senders: HashMap<ClientId, BufferSender>,
+    capture: PacketCapture,
     sessions: TCPSessionMap,
     //todo: impl drop for index allocator and connection id..
     connection_id_to_tcp_identifier: HashMap<ConnectionId, TcpSessionIdentifier>,
@@ -340,7 +393,7 @@ impl TcpConnectionSniffer {
                         self.handle_command(command).await?;
                     } else { break; }
                 },
-                packet = self.raw_capture.next() => {
+                packet = self.capture.next() => {
                     self.handle_packet(packet?).await?;
                 }
                 _ = cancel_token.cancelled() => {
@@ -353,7 +406,8 @@ impl TcpConnectionSniffer {
     }
 
     /// Creates and prepares a new [`TcpConnectionSniffer`] that uses BPF filters to capture network
-    /// packets.
+    /// packets, or an eBPF program with [`SnifferBackend::Ebpf`] (falls back to the BPF filters if
+    /// the program can't be loaded).
     ///
     /// The capture uses a network interface specified by the user, if there is none, then it tries
     /// to find a proper one by starting a connection. If this fails, we use "eth0" as a last
@@ -363,12 +417,13 @@ impl TcpConnectionSniffer {
         receiver: Receiver<SnifferCommand>,
         network_interface: Option<String>,
         mesh: Option<MeshVendor>,
+        backend: SnifferBackend,
     ) -> Result<Self, AgentError> {
-        let raw_capture = prepare_sniffer(network_interface, mesh).await?;
+        let capture = prepare_sniffer(network_interface, mesh, backend).await?;
 
         Ok(Self {
             receiver,
-            raw_capture,
+            capture,
             port_subscriptions: Default::default(),
             client_senders: HashMap::new(),
             sessions: TCPSessionMap::new(),
@@ -380,7 +435,7 @@ impl TcpConnectionSniffer {
 
     /// New layer is connecting to this agent sniffer.
     #[tracing::instrument(level = "trace", ret, skip(self, sender))]
-    fn handle_new_client(&mut self, client_id: ClientId, sender: Sender<DaemonTcp>) {
+    fn handle_new_client(&mut self, client_id: ClientId, sender: BufferSender) {
         self.client_senders.insert(client_id, sender);
     }
 
@@ -414,13 +469,9 @@ impl TcpConnectionSniffer {
 
         if ports.is_empty() {
             trace!("Empty ports, setting dummy bpf");
-            self.raw_capture
-                .set_filter(rawsocket::filter::build_drop_always())?
-        } else {
-            self.raw_capture
-                .set_filter(rawsocket::filter::build_tcp_port_filter(&ports))?
-        };
-        Ok(())
+        }
+
+        self.capture.set_ports(&ports)
     }
 
     fn qualified_port(&self, port: u16) -> bool {
@@ -488,21 +539,22 @@ impl TcpConnectionSniffer {
     }
 
     /// Sends a [`DaemonTcp`] message back to the client with `client_id`.
+    ///
+    /// Doesn't wait for the client, the message is buffered (or dropped) as the
+    /// [`MirrorBufferPolicy`] says.
     #[tracing::instrument(level = "trace", ret, skip(self, message))]
     async fn send_message_to_client(
         &mut self,
         client_id: &ClientId,
         message: DaemonTcp,
     ) -> Result<(), AgentError> {
-        if let Some(sender) = self.client_senders.get(client_id) {
-            sender.send(message).await.map_err(|err| {
-                warn!(
-                    "Failed to send message to client {} with {:#?}!",
-                    client_id, err
-                );
-                let _ = self.handle_client_closed(*client_id);
-                err
-            })?;
+        let closed = self
+            .client_senders
+            .get(client_id)
+            .is_some_and(|sender| sender.send(message).is_err());
+        if closed {
+            warn!("Failed to send message to client {client_id}, it's gone!");
+            self.handle_client_closed(*client_id)?;
         }
         Ok(())
     }
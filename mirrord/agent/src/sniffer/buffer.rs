@@ -0,0 +1,571 @@
+//! Buffers the mirrored traffic of each client between the [`TcpConnectionSniffer`] and the
+//! client (`agent.mirror_buffer`), see [`channel`].
+//!
+//! The sniffer never waits for a client, so a slow client doesn't hold up the sniffer (and with
+//! it the other clients). Instead, the messages of the client are kept in memory up to
+//! [`MirrorBufferPolicy::memory`] bytes, then spilled to files in [`MirrorBufferPolicy::dir`] up
+//! to [`MirrorBufferPolicy::disk`] bytes (shared by all clients). When both are full, data is
+//! dropped as [`MirrorBufferFull`] says.
+//!
+//! Only [`DaemonTcp::Data`] is ever dropped, so the clients still see every connection open and
+//! close. The connections that lose some of their data are mirrored incomplete.
+//!
+//! [`TcpConnectionSniffer`]: super::TcpConnectionSniffer
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{BufReader, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use mirrord_protocol::tcp::DaemonTcp;
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::{
+    cli::{Args, MirrorBufferFull},
+    util::ClientId,
+};
+
+/// Size of the files to which the messages are spilled. A file is removed once all its messages
+/// were read, so the messages read from it still take space until then (it must stay below the
+/// headroom of the agent's `emptyDir`).
+const SEGMENT_SIZE: u64 = 512 * 1024;
+
+/// Limits of the buffers, from [`Args`].
+#[derive(Clone, Debug)]
+pub(crate) struct MirrorBufferPolicy {
+    /// Bytes of data kept in memory for each client.
+    memory: u64,
+    /// Bytes that can be spilled to [`Self::dir`] by all clients together.
+    disk: u64,
+    dir: PathBuf,
+    on_full: MirrorBufferFull,
+    /// Bytes currently spilled by all clients.
+    spilled: Arc<AtomicU64>,
+}
+
+impl MirrorBufferPolicy {
+    /// Default of [`Args::mirror_buffer_memory`].
+    const DEFAULT_MEMORY: u64 = 16 * 1024 * 1024;
+
+    pub(crate) fn new(args: &Args) -> Self {
+        Self {
+            memory: args.mirror_buffer_memory.unwrap_or(Self::DEFAULT_MEMORY),
+            disk: args.mirror_buffer_disk,
+            dir: args
+                .mirror_buffer_dir
+                .clone()
+                .unwrap_or_else(std::env::temp_dir),
+            on_full: args.mirror_buffer_on_full,
+            spilled: Default::default(),
+        }
+    }
+}
+
+/// Creates the buffer of a client: the sniffer sends the messages with the [`BufferSender`], and
+/// the client receives them with the [`BufferReceiver`].
+pub(crate) fn channel(
+    client_id: ClientId,
+    policy: &MirrorBufferPolicy,
+) -> (BufferSender, BufferReceiver) {
+    let shared = Arc::new(Shared {
+        buffer: Mutex::new(Buffer {
+            policy: policy.clone(),
+            memory: Default::default(),
+            memory_bytes: 0,
+            spill: Spill::new(policy.dir.join(format!("mirror-buffer-{client_id}"))),
+            dropped: 0,
+            sender_closed: false,
+            receiver_closed: false,
+        }),
+        notify: Notify::new(),
+    });
+
+    (BufferSender(shared.clone()), BufferReceiver(shared))
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    /// Wakes the [`BufferReceiver`] up.
+    notify: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// The [`TcpConnectionSniffer`](super::TcpConnectionSniffer) side of the buffer.
+pub(crate) struct BufferSender(Arc<Shared>);
+
+/// The [`BufferReceiver`] was dropped.
+#[derive(Debug)]
+pub(crate) struct BufferClosed;
+
+impl BufferSender {
+    /// Adds the message to the buffer, without waiting.
+    pub(crate) fn send(&self, message: DaemonTcp) -> Result<(), BufferClosed> {
+        let mut buffer = self.0.lock();
+        if buffer.receiver_closed {
+            return Err(BufferClosed);
+        }
+
+        buffer.push(message);
+        drop(buffer);
+
+        self.0.notify.notify_one();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BufferSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferSender").finish_non_exhaustive()
+    }
+}
+
+impl Drop for BufferSender {
+    fn drop(&mut self) {
+        self.0.lock().sender_closed = true;
+        self.0.notify.notify_one();
+    }
+}
+
+/// The client side of the buffer.
+pub(crate) struct BufferReceiver(Arc<Shared>);
+
+impl BufferReceiver {
+    /// Returns the next message, or [`None`] when the [`BufferSender`] is gone and there are no
+    /// more messages.
+    pub(crate) async fn recv(&mut self) -> Option<DaemonTcp> {
+        loop {
+            {
+                let mut buffer = self.0.lock();
+                if let Some(message) = buffer.pop() {
+                    return Some(message);
+                }
+                if buffer.sender_closed {
+                    return None;
+                }
+            }
+
+            self.0.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for BufferReceiver {
+    fn drop(&mut self) {
+        let mut buffer = self.0.lock();
+        buffer.receiver_closed = true;
+        buffer.memory.clear();
+        buffer.spill.clear(&buffer.policy.spilled);
+    }
+}
+
+/// Messages of a client, in order: the ones in [`Buffer::memory`] are older than the ones in
+/// [`Buffer::spill`].
+struct Buffer {
+    policy: MirrorBufferPolicy,
+    memory: VecDeque<DaemonTcp>,
+    /// Bytes of [`DaemonTcp::Data`] in [`Self::memory`], the other messages are small.
+    memory_bytes: u64,
+    spill: Spill,
+    /// Data messages dropped since the last warning.
+    dropped: u64,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+impl Buffer {
+    fn push(&mut self, message: DaemonTcp) {
+        let size = data_size(&message);
+
+        loop {
+            // The other messages always fit, so the client sees every connection.
+            let fits_memory = size == 0 || self.memory_bytes + size <= self.policy.memory;
+            if self.spill.is_empty() && fits_memory {
+                self.memory_bytes += size;
+                self.memory.push_back(message);
+                return;
+            }
+
+            if self.policy.disk > 0 || !self.spill.is_empty() {
+                match self
+                    .spill
+                    .push(&message, size == 0, self.policy.disk, &self.policy.spilled)
+                {
+                    Ok(true) => return,
+                    Ok(false) => {}
+                    Err(error) => {
+                        warn!(%error, "Failed to spill the mirrored traffic to disk, dropping it");
+                        self.drop_data();
+                        return;
+                    }
+                }
+            }
+
+            if self.policy.on_full == MirrorBufferFull::DropNewest || !self.drop_oldest() {
+                self.drop_data();
+                return;
+            }
+        }
+    }
+
+    /// Drops the oldest [`DaemonTcp::Data`] in the buffer, returns whether there was one.
+    fn drop_oldest(&mut self) -> bool {
+        let oldest = self
+            .memory
+            .iter()
+            .position(|message| matches!(message, DaemonTcp::Data(..)));
+        if let Some(message) = oldest.and_then(|index| self.memory.remove(index)) {
+            self.memory_bytes -= data_size(&message);
+            self.drop_data();
+            self.refill();
+            return true;
+        }
+
+        // The spilled messages are newer than the ones in memory, so the ones we skip can be moved
+        // to the end of the memory.
+        loop {
+            match self.spill.pop(&self.policy.spilled) {
+                Ok(Some(DaemonTcp::Data(..))) => {
+                    self.drop_data();
+                    return true;
+                }
+                Ok(Some(message)) => self.memory.push_back(message),
+                Ok(None) => return false,
+                Err(error) => {
+                    warn!(%error, "Failed to read the mirrored traffic spilled to disk");
+                    self.spill.clear(&self.policy.spilled);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Moves the oldest spilled messages to the memory while there is room in it, which makes
+    /// room on the disk.
+    fn refill(&mut self) {
+        while self.memory_bytes < self.policy.memory {
+            match self.spill.pop(&self.policy.spilled) {
+                Ok(Some(message)) => {
+                    self.memory_bytes += data_size(&message);
+                    self.memory.push_back(message);
+                }
+                Ok(None) => return,
+                Err(error) => {
+                    warn!(%error, "Failed to read the mirrored traffic spilled to disk");
+                    self.spill.clear(&self.policy.spilled);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn drop_data(&mut self) {
+        if self.dropped == 0 {
+            warn!(
+                "A client reads the mirrored traffic too slowly, dropping some of it (see \
+                 `agent.mirror_buffer`)"
+            );
+        }
+        self.dropped += 1;
+    }
+
+    fn pop(&mut self) -> Option<DaemonTcp> {
+        if let Some(message) = self.memory.pop_front() {
+            self.memory_bytes -= data_size(&message);
+            return Some(message);
+        }
+
+        match self.spill.pop(&self.policy.spilled) {
+            Ok(message) => {
+                if message.is_none() && self.dropped > 0 {
+                    warn!(
+                        dropped = self.dropped,
+                        "The client caught up with the mirrored traffic"
+                    );
+                    self.dropped = 0;
+                }
+                message
+            }
+            Err(error) => {
+                warn!(%error, "Failed to read the mirrored traffic spilled to disk");
+                self.spill.clear(&self.policy.spilled);
+                None
+            }
+        }
+    }
+}
+
+/// Bytes of the message that count towards the limits.
+fn data_size(message: &DaemonTcp) -> u64 {
+    match message {
+        DaemonTcp::Data(data) => data.bytes.len() as u64,
+        _ => 0,
+    }
+}
+
+/// A spilled file, see [`SEGMENT_SIZE`].
+struct Segment {
+    path: PathBuf,
+    /// Open while messages are added to it.
+    writer: Option<File>,
+    /// Open once messages are read from it.
+    reader: Option<BufReader<File>>,
+    /// Bytes written to the file.
+    bytes: u64,
+    /// Encoded sizes of the messages not read yet, they're given back to the
+    /// [`MirrorBufferPolicy::spilled`] counter as the messages are read.
+    unread: VecDeque<u64>,
+}
+
+/// Messages spilled to disk, in files of [`SEGMENT_SIZE`] in a directory of the client.
+struct Spill {
+    dir: PathBuf,
+    segments: VecDeque<Segment>,
+    next_segment: u64,
+}
+
+impl Spill {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            segments: Default::default(),
+            next_segment: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Writes the message, unless it doesn't fit in `disk` bytes (with the other clients' ones
+    /// in `spilled`). `force` writes it anyway.
+    fn push(
+        &mut self,
+        message: &DaemonTcp,
+        force: bool,
+        disk: u64,
+        spilled: &AtomicU64,
+    ) -> std::io::Result<bool> {
+        let encoded = bincode::encode_to_vec(message, bincode::config::standard())
+            .map_err(std::io::Error::other)?;
+        let size = encoded.len() as u64;
+
+        let needs_segment = self.segments.back().map_or(true, |segment| {
+            segment.writer.is_none() || segment.bytes >= SEGMENT_SIZE
+        });
+        if needs_segment {
+            if let Some(segment) = self.segments.back_mut() {
+                segment.writer = None;
+            }
+
+            fs::create_dir_all(&self.dir)?;
+            let path = self.dir.join(self.next_segment.to_string());
+            self.segments.push_back(Segment {
+                writer: Some(File::create(&path)?),
+                path,
+                reader: None,
+                bytes: 0,
+                unread: Default::default(),
+            });
+            self.next_segment += 1;
+        }
+
+        if force {
+            spilled.fetch_add(size, Ordering::Relaxed);
+        } else if spilled
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |spilled| {
+                (spilled + size <= disk).then_some(spilled + size)
+            })
+            .is_err()
+        {
+            return Ok(false);
+        }
+
+        let Some(segment) = self.segments.back_mut() else {
+            return Ok(false);
+        };
+        if let Some(Err(error)) = segment
+            .writer
+            .as_mut()
+            .map(|writer| writer.write_all(&encoded))
+        {
+            spilled.fetch_sub(size, Ordering::Relaxed);
+            // The file may have a part of the message, nothing more can be added to it.
+            segment.writer = None;
+            return Err(error);
+        }
+        segment.bytes += size;
+        segment.unread.push_back(size);
+
+        Ok(true)
+    }
+
+    /// Reads the oldest message, removes its file when it was the last one in it.
+    fn pop(&mut self, spilled: &AtomicU64) -> std::io::Result<Option<DaemonTcp>> {
+        // A segment may be left without messages when writing to it failed.
+        while self
+            .segments
+            .front()
+            .is_some_and(|segment| segment.unread.is_empty())
+        {
+            self.remove_front(spilled);
+        }
+
+        let Some(segment) = self.segments.front_mut() else {
+            return Ok(None);
+        };
+
+        let reader = match segment.reader.as_mut() {
+            Some(reader) => reader,
+            None => segment
+                .reader
+                .insert(BufReader::new(File::open(&segment.path)?)),
+        };
+        let message = bincode::decode_from_std_read(reader, bincode::config::standard())
+            .map_err(std::io::Error::other)?;
+        if let Some(size) = segment.unread.pop_front() {
+            spilled.fetch_sub(size, Ordering::Relaxed);
+        }
+
+        // Even when it's the last segment, so that once the client catches up the messages go
+        // back to memory.
+        if segment.unread.is_empty() {
+            self.remove_front(spilled);
+        }
+
+        Ok(Some(message))
+    }
+
+    fn remove_front(&mut self, spilled: &AtomicU64) {
+        if let Some(segment) = self.segments.pop_front() {
+            let _ = fs::remove_file(&segment.path);
+            spilled.fetch_sub(segment.unread.iter().sum(), Ordering::Relaxed);
+        }
+    }
+
+    fn clear(&mut self, spilled: &AtomicU64) {
+        while !self.segments.is_empty() {
+            self.remove_front(spilled);
+        }
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mirrord_protocol::tcp::{NewTcpConnection, TcpClose, TcpData};
+
+    use super::*;
+
+    fn policy(memory: u64, disk: u64, on_full: MirrorBufferFull) -> MirrorBufferPolicy {
+        MirrorBufferPolicy {
+            memory,
+            disk,
+            dir: std::env::temp_dir().join(format!("mirror-buffer-test-{}", rand::random::<u64>())),
+            on_full,
+            spilled: Default::default(),
+        }
+    }
+
+    fn data(connection_id: u64, byte: u8) -> DaemonTcp {
+        DaemonTcp::Data(TcpData {
+            connection_id,
+            bytes: vec![byte; 10],
+        })
+    }
+
+    fn new_connection(connection_id: u64) -> DaemonTcp {
+        DaemonTcp::NewConnection(NewTcpConnection {
+            connection_id,
+            remote_address: "1.1.1.1".parse().unwrap(),
+            destination_port: 80,
+            source_port: 4321,
+            local_address: "2.2.2.2".parse().unwrap(),
+        })
+    }
+
+    fn close(connection_id: u64) -> DaemonTcp {
+        DaemonTcp::Close(TcpClose { connection_id })
+    }
+
+    async fn receive_all(sender: BufferSender, mut receiver: BufferReceiver) -> Vec<DaemonTcp> {
+        drop(sender);
+        let mut messages = vec![];
+        while let Some(message) = receiver.recv().await {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn spilled_messages_keep_their_order() {
+        let policy = policy(20, 1024, MirrorBufferFull::DropOldest);
+        let (sender, receiver) = channel(0, &policy);
+
+        let messages = vec![
+            new_connection(0),
+            data(0, 1),
+            data(0, 2),
+            data(0, 3),
+            data(0, 4),
+            close(0),
+        ];
+        for message in messages.clone() {
+            sender.send(message).unwrap();
+        }
+        assert!(policy.spilled.load(Ordering::Relaxed) > 0);
+
+        assert_eq!(receive_all(sender, receiver).await, messages);
+        assert_eq!(policy.spilled.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn only_data_is_dropped() {
+        let policy = policy(20, 0, MirrorBufferFull::DropOldest);
+        let (sender, receiver) = channel(0, &policy);
+
+        for message in [
+            new_connection(0),
+            data(0, 1),
+            data(0, 2),
+            data(0, 3),
+            close(0),
+        ] {
+            sender.send(message).unwrap();
+        }
+
+        assert_eq!(
+            receive_all(sender, receiver).await,
+            vec![new_connection(0), data(0, 2), data(0, 3), close(0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn newest_data_is_dropped() {
+        let policy = policy(20, 0, MirrorBufferFull::DropNewest);
+        let (sender, receiver) = channel(0, &policy);
+
+        for message in [data(0, 1), data(0, 2), data(0, 3)] {
+            sender.send(message).unwrap();
+        }
+
+        assert_eq!(
+            receive_all(sender, receiver).await,
+            vec![data(0, 1), data(0, 2)]
+        );
+    }
+}
@@ -0,0 +1,1000 @@
+//! eBPF capture backend of the sniffer (`agent.sniffer: "ebpf"`), see [`EbpfCapture`].
+//!
+//! A `sched_cls` program is attached with TC to the ingress of the interface, in a `clsact` qdisc.
+//! It parses the Ethernet, IPv4 and TCP headers and sends the frames from or to one of the
+//! subscribed ports (kept in a BPF hash map) to userspace, through a perf event array with one
+//! ring buffer per CPU. Unlike the raw socket, it sees the packets that some CNIs deliver straight
+//! to the pod's interface, and only the subscribed traffic ever leaves the kernel.
+//!
+//! The program is small, so it's assembled here and loaded with raw `bpf`, `perf_event_open` and
+//! netlink calls.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    ffi::CString,
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use futures::future;
+use mirrord_protocol::Port;
+use tokio::io::unix::AsyncFd;
+use tracing::{trace, warn};
+
+/// `bpf(2)` commands.
+const BPF_MAP_CREATE: u32 = 0;
+const BPF_MAP_UPDATE_ELEM: u32 = 2;
+const BPF_MAP_DELETE_ELEM: u32 = 3;
+const BPF_PROG_LOAD: u32 = 5;
+
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_MAP_TYPE_PERF_EVENT_ARRAY: u32 = 4;
+const BPF_PROG_TYPE_SCHED_CLS: u32 = 3;
+
+/// Max number of ports in the subscribed ports map.
+const MAX_PORTS: u32 = 1024;
+
+/// Pages of each per-CPU ring buffer, must be a power of 2.
+const RING_PAGES: usize = 64;
+
+/// Size of the verifier log we ask for when the program is rejected.
+const VERIFIER_LOG_SIZE: usize = 64 * 1024;
+
+/// eBPF instructions, registers and helpers, see the
+/// [kernel docs](https://docs.kernel.org/bpf/standardization/instruction-set.html).
+mod insn {
+    pub const R0: u8 = 0;
+    pub const R1: u8 = 1;
+    pub const R2: u8 = 2;
+    pub const R3: u8 = 3;
+    pub const R4: u8 = 4;
+    pub const R5: u8 = 5;
+    pub const R6: u8 = 6;
+    pub const R10: u8 = 10;
+
+    const LD: u8 = 0x00;
+    const LDX: u8 = 0x01;
+    const STX: u8 = 0x03;
+    const ALU: u8 = 0x04;
+    const JMP: u8 = 0x05;
+    const ALU64: u8 = 0x07;
+
+    const K: u8 = 0x00;
+    const X: u8 = 0x08;
+
+    pub const W: u8 = 0x00;
+    pub const H: u8 = 0x08;
+    pub const B: u8 = 0x10;
+    const DW: u8 = 0x18;
+    const IMM: u8 = 0x00;
+    const MEM: u8 = 0x60;
+
+    pub const ADD: u8 = 0x00;
+    pub const OR: u8 = 0x40;
+    pub const AND: u8 = 0x50;
+    pub const LSH: u8 = 0x60;
+    const MOV: u8 = 0xb0;
+
+    pub const JEQ: u8 = 0x10;
+    pub const JNE: u8 = 0x50;
+    const CALL: u8 = 0x80;
+    const EXIT: u8 = 0x90;
+
+    const PSEUDO_MAP_FD: u8 = 1;
+
+    pub const FN_MAP_LOOKUP_ELEM: i32 = 1;
+    pub const FN_PERF_EVENT_OUTPUT: i32 = 25;
+    pub const FN_SKB_LOAD_BYTES: i32 = 26;
+
+    /// `struct bpf_insn`.
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct Insn {
+        code: u8,
+        /// Destination register in the low 4 bits, source in the high 4 bits.
+        regs: u8,
+        pub off: i16,
+        imm: i32,
+    }
+
+    const fn new(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+        Insn {
+            code,
+            regs: (src << 4) | dst,
+            off,
+            imm,
+        }
+    }
+
+    pub const fn mov64_reg(dst: u8, src: u8) -> Insn {
+        new(ALU64 | MOV | X, dst, src, 0, 0)
+    }
+
+    pub const fn mov64_imm(dst: u8, imm: i32) -> Insn {
+        new(ALU64 | MOV | K, dst, 0, 0, imm)
+    }
+
+    /// 32-bit move, zero-extends `imm` into `dst`.
+    pub const fn mov32_imm(dst: u8, imm: i32) -> Insn {
+        new(ALU | MOV | K, dst, 0, 0, imm)
+    }
+
+    pub const fn alu64_imm(op: u8, dst: u8, imm: i32) -> Insn {
+        new(ALU64 | op | K, dst, 0, 0, imm)
+    }
+
+    pub const fn alu64_reg(op: u8, dst: u8, src: u8) -> Insn {
+        new(ALU64 | op | X, dst, src, 0, 0)
+    }
+
+    /// `dst = *(size *)(src + off)`
+    pub const fn load(size: u8, dst: u8, src: u8, off: i16) -> Insn {
+        new(LDX | MEM | size, dst, src, off, 0)
+    }
+
+    /// `*(size *)(dst + off) = src`
+    pub const fn store(size: u8, dst: u8, src: u8, off: i16) -> Insn {
+        new(STX | MEM | size, dst, src, off, 0)
+    }
+
+    /// Jumps `off` instructions forward if `dst op imm`.
+    pub const fn jump_imm(op: u8, dst: u8, imm: i32, off: i16) -> Insn {
+        new(JMP | op | K, dst, 0, off, imm)
+    }
+
+    pub const fn call(helper: i32) -> Insn {
+        new(JMP | CALL, 0, 0, 0, helper)
+    }
+
+    pub const fn exit() -> Insn {
+        new(JMP | EXIT, 0, 0, 0, 0)
+    }
+
+    /// Loads the map with the given fd into `dst`, takes 2 instructions.
+    pub const fn load_map_fd(dst: u8, fd: i32) -> [Insn; 2] {
+        [
+            new(LD | DW | IMM, dst, PSEUDO_MAP_FD, 0, fd),
+            new(0, 0, 0, 0, 0),
+        ]
+    }
+}
+
+use insn::*;
+
+/// Builds the program, resolving the jumps to its 2 labels.
+#[derive(Default)]
+struct Assembler {
+    insns: Vec<Insn>,
+    /// Jumps to [`Label::Emit`] and [`Label::Out`], patched in [`Assembler::finish`].
+    jumps: Vec<(usize, Label)>,
+    labels: Vec<(Label, usize)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Label {
+    /// Sends the packet to userspace.
+    Emit,
+    /// Lets the packet through.
+    Out,
+}
+
+impl Assembler {
+    fn push(&mut self, insns: impl IntoIterator<Item = Insn>) {
+        self.insns.extend(insns);
+    }
+
+    fn jump_imm(&mut self, op: u8, dst: u8, imm: i32, label: Label) {
+        self.jumps.push((self.insns.len(), label));
+        self.push([jump_imm(op, dst, imm, 0)]);
+    }
+
+    fn label(&mut self, label: Label) {
+        self.labels.push((label, self.insns.len()));
+    }
+
+    /// `bpf_skb_load_bytes(skb, offset, stack + stack_offset, len)`, where `offset` is in R2
+    /// and skb in R6. Jumps to [`Label::Out`] on failure.
+    fn load_bytes(&mut self, stack_offset: i32, len: i32) {
+        self.push([
+            mov64_reg(R1, R6),
+            mov64_reg(R3, R10),
+            alu64_imm(ADD, R3, stack_offset),
+            mov64_imm(R4, len),
+            call(FN_SKB_LOAD_BYTES),
+        ]);
+        self.jump_imm(JNE, R0, 0, Label::Out);
+    }
+
+    fn finish(mut self) -> Vec<Insn> {
+        for (at, label) in self.jumps {
+            let target = self
+                .labels
+                .iter()
+                .find_map(|(name, target)| (*name == label).then_some(*target))
+                .expect("jump to undefined label");
+            if let Some(insn) = self.insns.get_mut(at) {
+                insn.off = (target - at - 1) as i16;
+            }
+        }
+
+        self.insns
+    }
+}
+
+/// The TC program, see the [module docs](self).
+///
+/// The ports map is keyed by ports in network byte order. For each matching packet, the events
+/// map gets a sample of the packet length (`u32`) followed by the whole frame.
+fn program(ports_map: RawFd, events_map: RawFd) -> Vec<Insn> {
+    /// `ETH_P_IP`, as read from the packet into a register.
+    const ETH_P_IP: i32 = u16::from_ne_bytes(0x0800_u16.to_be_bytes()) as i32;
+    const ETH_HEADER_LEN: i32 = 14;
+    const IPPROTO_TCP: i32 = 6;
+    /// `BPF_F_CURRENT_CPU`, the index of the ring buffer in the events map.
+    const CURRENT_CPU: i32 = -1;
+
+    // Stack slots.
+    const ETHERTYPE: i16 = -8;
+    const IP_HEADER: i16 = -24;
+    const IP_PROTOCOL: i16 = IP_HEADER + 9;
+    const SOURCE_PORT: i16 = -32;
+    const DESTINATION_PORT: i16 = SOURCE_PORT + 2;
+    const SAMPLE_LEN: i16 = -40;
+
+    let mut asm = Assembler::default();
+    asm.push([mov64_reg(R6, R1)]);
+
+    // Ethernet: IPv4 only.
+    asm.push([mov64_imm(R2, 12)]);
+    asm.load_bytes(ETHERTYPE.into(), 2);
+    asm.push([load(H, R1, R10, ETHERTYPE)]);
+    asm.jump_imm(JNE, R1, ETH_P_IP, Label::Out);
+
+    // IPv4: TCP only, the header length is needed to find the TCP header.
+    asm.push([mov64_imm(R2, ETH_HEADER_LEN)]);
+    asm.load_bytes(IP_HEADER.into(), 10);
+    asm.push([load(B, R1, R10, IP_PROTOCOL)]);
+    asm.jump_imm(JNE, R1, IPPROTO_TCP, Label::Out);
+
+    // TCP: source and destination ports.
+    asm.push([
+        load(B, R2, R10, IP_HEADER),
+        alu64_imm(AND, R2, 0x0f),
+        alu64_imm(LSH, R2, 2),
+        alu64_imm(ADD, R2, ETH_HEADER_LEN),
+    ]);
+    asm.load_bytes(SOURCE_PORT.into(), 4);
+
+    for (port, label, op) in [
+        (DESTINATION_PORT, Label::Emit, JNE),
+        (SOURCE_PORT, Label::Out, JEQ),
+    ] {
+        asm.push(load_map_fd(R1, ports_map));
+        asm.push([
+            mov64_reg(R2, R10),
+            alu64_imm(ADD, R2, port.into()),
+            call(FN_MAP_LOOKUP_ELEM),
+        ]);
+        asm.jump_imm(op, R0, 0, label);
+    }
+
+    // bpf_perf_event_output(skb, events, (skb->len << 32) | CURRENT_CPU, &len, 4), the upper
+    // 32 bits of the flags are the number of packet bytes appended to the sample.
+    asm.label(Label::Emit);
+    asm.push([
+        load(W, R1, R6, 0),
+        store(W, R10, R1, SAMPLE_LEN),
+        mov64_reg(R3, R1),
+        alu64_imm(LSH, R3, 32),
+        mov32_imm(R1, CURRENT_CPU),
+        alu64_reg(OR, R3, R1),
+        mov64_reg(R1, R6),
+    ]);
+    asm.push(load_map_fd(R2, events_map));
+    asm.push([
+        mov64_reg(R4, R10),
+        alu64_imm(ADD, R4, SAMPLE_LEN.into()),
+        mov64_imm(R5, 4),
+        call(FN_PERF_EVENT_OUTPUT),
+    ]);
+
+    // TC_ACT_OK, the packet continues as usual.
+    asm.label(Label::Out);
+    asm.push([mov64_imm(R0, 0), exit()]);
+
+    asm.finish()
+}
+
+/// `union bpf_attr` for [`BPF_MAP_CREATE`].
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+/// `union bpf_attr` for [`BPF_MAP_UPDATE_ELEM`] and [`BPF_MAP_DELETE_ELEM`].
+#[repr(C)]
+#[derive(Default)]
+struct MapElemAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+/// `union bpf_attr` for [`BPF_PROG_LOAD`].
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+}
+
+/// Calls `bpf(2)`, the returned fd (if any) is owned by the caller.
+unsafe fn bpf<T>(cmd: u32, attr: &mut T) -> io::Result<RawFd> {
+    let result = libc::syscall(
+        libc::SYS_bpf,
+        cmd,
+        attr as *mut T,
+        mem::size_of::<T>() as u32,
+    );
+
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as RawFd)
+    }
+}
+
+fn map_create(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+) -> io::Result<OwnedFd> {
+    let mut attr = MapCreateAttr {
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        ..Default::default()
+    };
+
+    unsafe { bpf(BPF_MAP_CREATE, &mut attr).map(|fd| OwnedFd::from_raw_fd(fd)) }
+}
+
+fn map_update<K, V>(map: &OwnedFd, key: &K, value: &V) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: map.as_raw_fd() as u32,
+        key: key as *const K as u64,
+        value: value as *const V as u64,
+        ..Default::default()
+    };
+
+    unsafe { bpf(BPF_MAP_UPDATE_ELEM, &mut attr).map(drop) }
+}
+
+fn map_delete<K>(map: &OwnedFd, key: &K) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: map.as_raw_fd() as u32,
+        key: key as *const K as u64,
+        ..Default::default()
+    };
+
+    unsafe { bpf(BPF_MAP_DELETE_ELEM, &mut attr).map(drop) }
+}
+
+/// Loads the TC program. When the verifier rejects it, the error contains the end of its log.
+fn load_program(insns: &[Insn]) -> io::Result<OwnedFd> {
+    // `bpf_perf_event_output` is only available to GPL compatible programs.
+    let license = CString::new("Dual MIT/GPL").expect("license has no nul bytes");
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_SCHED_CLS,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        ..Default::default()
+    };
+
+    match unsafe { bpf(BPF_PROG_LOAD, &mut attr) } {
+        Ok(fd) => return Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        Err(error) if error.raw_os_error() != Some(libc::EACCES) => return Err(error),
+        Err(..) => {}
+    }
+
+    // Rejected by the verifier, load again to get its log.
+    let mut log = vec![0_u8; VERIFIER_LOG_SIZE];
+    attr.log_level = 1;
+    attr.log_size = log.len() as u32;
+    attr.log_buf = log.as_mut_ptr() as u64;
+
+    let error = match unsafe { bpf(BPF_PROG_LOAD, &mut attr) } {
+        Ok(fd) => return Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        Err(error) => error,
+    };
+
+    let log = String::from_utf8_lossy(&log);
+    let log = log.trim_end_matches('\0').trim_end();
+    let tail = log.lines().rev().take(5).collect::<Vec<_>>();
+    Err(io::Error::new(
+        error.kind(),
+        format!(
+            "verifier rejected the program ({error}): {}",
+            tail.into_iter().rev().collect::<Vec<_>>().join(" | ")
+        ),
+    ))
+}
+
+/// `struct perf_event_attr`, up to `PERF_ATTR_SIZE_VER0`.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_BPF_OUTPUT: u64 = 10;
+const PERF_SAMPLE_RAW: u64 = 1 << 10;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_SAMPLE: u32 = 9;
+
+/// Offsets of `data_head` and `data_tail` in `struct perf_event_mmap_page`.
+const PERF_DATA_HEAD_OFFSET: usize = 1024;
+const PERF_DATA_TAIL_OFFSET: usize = 1032;
+
+/// Ring buffer of one CPU, filled by `bpf_perf_event_output`.
+struct PerfBuffer {
+    fd: AsyncFd<OwnedFd>,
+    /// The metadata page, followed by the data pages.
+    mmap: *mut u8,
+    mmap_len: usize,
+    page_size: usize,
+}
+
+// The mapping is only accessed through `&mut EbpfCapture`.
+unsafe impl Send for PerfBuffer {}
+
+impl PerfBuffer {
+    fn open(cpu: i32, page_size: usize) -> io::Result<Self> {
+        let mut attr = PerfEventAttr {
+            type_: PERF_TYPE_SOFTWARE,
+            size: mem::size_of::<PerfEventAttr>() as u32,
+            config: PERF_COUNT_SW_BPF_OUTPUT,
+            sample_period: 1,
+            sample_type: PERF_SAMPLE_RAW,
+            wakeup_events: 1,
+            ..Default::default()
+        };
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &mut attr as *mut PerfEventAttr,
+                -1,
+                cpu,
+                -1,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        let mmap_len = page_size * (RING_PAGES + 1);
+        let mmap = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mmap_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if mmap == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            mmap: mmap.cast(),
+            mmap_len,
+            page_size,
+        })
+    }
+
+    fn data_size(&self) -> usize {
+        self.page_size * RING_PAGES
+    }
+
+    /// Copies `len` bytes from the ring, starting at `position` (not wrapped yet).
+    fn copy(&self, position: u64, len: usize) -> Vec<u8> {
+        let size = self.data_size();
+        let start = (position % size as u64) as usize;
+        let first = len.min(size - start);
+        let mut bytes = vec![0; len];
+
+        unsafe {
+            let data = self.mmap.add(self.page_size);
+            ptr::copy_nonoverlapping(data.add(start), bytes.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(data, bytes.as_mut_ptr().add(first), len - first);
+        }
+
+        bytes
+    }
+
+    /// Moves all packets from the ring to `packets`.
+    fn read_packets(&self, packets: &mut VecDeque<Vec<u8>>) {
+        let (head, tail) = unsafe {
+            (
+                &*self.mmap.add(PERF_DATA_HEAD_OFFSET).cast::<AtomicU64>(),
+                &*self.mmap.add(PERF_DATA_TAIL_OFFSET).cast::<AtomicU64>(),
+            )
+        };
+
+        let head = head.load(Ordering::Acquire);
+        let mut position = tail.load(Ordering::Relaxed);
+
+        while position < head {
+            // struct perf_event_header { u32 type; u16 misc; u16 size; }
+            let header = self.copy(position, 8);
+            let record_type = u32_at(&header, 0).unwrap_or_default();
+            let record_size = header
+                .get(6..8)
+                .and_then(|size| size.try_into().ok())
+                .map(u16::from_ne_bytes)
+                .unwrap_or_default();
+            if record_size < 8 {
+                warn!("eBPF sniffer ring buffer is corrupted, dropping its contents");
+                position = head;
+                break;
+            }
+
+            let record = self.copy(position + 8, usize::from(record_size) - 8);
+            match record_type {
+                // u32 size, then our sample: u32 packet length and the packet.
+                PERF_RECORD_SAMPLE => {
+                    let packet = u32_at(&record, 4).and_then(|len| {
+                        let len = usize::try_from(len).ok()?;
+                        record.get(8..8 + len)
+                    });
+                    match packet {
+                        Some(packet) => packets.push_back(packet.to_vec()),
+                        None => trace!("malformed eBPF sniffer sample"),
+                    }
+                }
+                // u64 id, then u64 number of lost samples.
+                PERF_RECORD_LOST => {
+                    let lost = record
+                        .get(8..16)
+                        .and_then(|lost| lost.try_into().ok())
+                        .map(u64::from_ne_bytes)
+                        .unwrap_or_default();
+                    warn!(lost, "eBPF sniffer ring buffer is full, packets were lost");
+                }
+                _ => {}
+            }
+
+            position += u64::from(record_size);
+        }
+
+        tail.store(position, Ordering::Release);
+    }
+}
+
+impl Drop for PerfBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.mmap.cast(), self.mmap_len);
+        }
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes
+        .get(offset..offset + 4)?
+        .try_into()
+        .ok()
+        .map(u32::from_ne_bytes)
+}
+
+/// `struct nlmsghdr` and `struct tcmsg`.
+const NLMSG_HEADER_LEN: usize = 16;
+const TCMSG_LEN: usize = 20;
+
+const NLMSG_ERROR: u16 = 2;
+const RTM_NEWQDISC: u16 = 36;
+const RTM_NEWTFILTER: u16 = 44;
+const RTM_DELTFILTER: u16 = 45;
+
+const NLM_F_REQUEST: u16 = 0x01;
+const NLM_F_ACK: u16 = 0x04;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+const TCA_BPF_FD: u16 = 6;
+const TCA_BPF_NAME: u16 = 7;
+const TCA_BPF_FLAGS: u16 = 8;
+const TCA_BPF_FLAG_ACT_DIRECT: u32 = 1;
+
+/// Handle of the `clsact` qdisc and parent of its ingress filters.
+const TC_H_CLSACT: u32 = 0xFFFF_FFF1;
+const TC_H_CLSACT_INGRESS: u32 = 0xFFFF_FFF2;
+const ETH_P_ALL: u16 = 0x0003;
+
+/// Our filter's handle, the filter is identified by its priority.
+const FILTER_HANDLE: u32 = 1;
+
+/// Appends a netlink attribute, padded to 4 bytes.
+fn push_attr(buf: &mut Vec<u8>, attr_type: u16, payload: &[u8]) {
+    let len = 4 + payload.len();
+    buf.extend((len as u16).to_ne_bytes());
+    buf.extend(attr_type.to_ne_bytes());
+    buf.extend(payload);
+    buf.resize(buf.len() + (4 - len % 4) % 4, 0);
+}
+
+/// TC netlink request for the interface.
+fn tc_message(
+    message_type: u16,
+    flags: u16,
+    ifindex: i32,
+    (handle, parent, info): (u32, u32, u32),
+    attrs: &[u8],
+) -> Vec<u8> {
+    let len = NLMSG_HEADER_LEN + TCMSG_LEN + attrs.len();
+
+    let mut message = Vec::with_capacity(len);
+    message.extend((len as u32).to_ne_bytes());
+    message.extend(message_type.to_ne_bytes());
+    message.extend((flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+    // Sequence number and port id, we only have one request in flight.
+    message.extend([0; 8]);
+
+    // AF_UNSPEC and padding.
+    message.extend([0; 4]);
+    message.extend(ifindex.to_ne_bytes());
+    message.extend(handle.to_ne_bytes());
+    message.extend(parent.to_ne_bytes());
+    message.extend(info.to_ne_bytes());
+
+    message.extend(attrs);
+    message
+}
+
+/// `NETLINK_ROUTE` socket for the TC requests.
+struct Netlink(OwnedFd);
+
+impl Netlink {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Sends the request and waits for the kernel's acknowledgement.
+    fn request(&self, message: &[u8]) -> io::Result<()> {
+        let sent = unsafe {
+            libc::send(
+                self.0.as_raw_fd(),
+                message.as_ptr().cast(),
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut response = [0_u8; 4096];
+        loop {
+            let received = unsafe {
+                libc::recv(
+                    self.0.as_raw_fd(),
+                    response.as_mut_ptr().cast(),
+                    response.len(),
+                    0,
+                )
+            };
+            let received = usize::try_from(received).map_err(|_| io::Error::last_os_error())?;
+            let response = response.get(..received).unwrap_or_default();
+
+            let message_type = response
+                .get(4..6)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u16::from_ne_bytes);
+            if message_type != Some(NLMSG_ERROR) {
+                continue;
+            }
+
+            // struct nlmsgerr { int error; struct nlmsghdr msg; }, 0 is the acknowledgement.
+            let error = u32_at(response, NLMSG_HEADER_LEN)
+                .map(|error| error as i32)
+                .ok_or_else(|| io::Error::other("truncated netlink response"))?;
+            return match error {
+                0 => Ok(()),
+                error => Err(io::Error::from_raw_os_error(-error)),
+            };
+        }
+    }
+}
+
+/// The TC filter with our program on the ingress of an interface, removed on drop.
+///
+/// The `clsact` qdisc stays, other filters may be using it.
+struct TcFilter {
+    netlink: Netlink,
+    ifindex: i32,
+    priority: u16,
+}
+
+impl TcFilter {
+    fn attach(interface: &str, program: &OwnedFd) -> io::Result<Self> {
+        let name = CString::new(interface).map_err(io::Error::other)?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ifindex = ifindex as i32;
+
+        let netlink = Netlink::new()?;
+
+        let mut kind = Vec::new();
+        push_attr(&mut kind, TCA_KIND, b"clsact\0");
+        match netlink.request(&tc_message(
+            RTM_NEWQDISC,
+            NLM_F_CREATE | NLM_F_EXCL,
+            ifindex,
+            (0xFFFF_0000, TC_H_CLSACT, 0),
+            &kind,
+        )) {
+            Err(error) if error.raw_os_error() != Some(libc::EEXIST) => return Err(error),
+            _ => {}
+        }
+
+        let mut options = Vec::new();
+        push_attr(&mut options, TCA_BPF_FD, &program.as_raw_fd().to_ne_bytes());
+        push_attr(&mut options, TCA_BPF_NAME, b"mirrord_sniffer\0");
+        push_attr(
+            &mut options,
+            TCA_BPF_FLAGS,
+            &TCA_BPF_FLAG_ACT_DIRECT.to_ne_bytes(),
+        );
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, TCA_KIND, b"bpf\0");
+        push_attr(&mut attrs, TCA_OPTIONS, &options);
+
+        // Every agent in the pod has its own filter, the priority tells them apart.
+        let mut attempts = 0;
+        loop {
+            let priority = rand::random::<u16>() | 0x8000;
+            let result = netlink.request(&tc_message(
+                RTM_NEWTFILTER,
+                NLM_F_CREATE | NLM_F_EXCL,
+                ifindex,
+                Self::ids(priority),
+                &attrs,
+            ));
+
+            match result {
+                Ok(()) => {
+                    return Ok(Self {
+                        netlink,
+                        ifindex,
+                        priority,
+                    })
+                }
+                Err(error) if error.raw_os_error() == Some(libc::EEXIST) && attempts < 8 => {
+                    attempts += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Handle, parent and info (priority and protocol) of the filter.
+    fn ids(priority: u16) -> (u32, u32, u32) {
+        let info = (u32::from(priority) << 16) | u32::from(ETH_P_ALL.to_be());
+        (FILTER_HANDLE, TC_H_CLSACT_INGRESS, info)
+    }
+}
+
+impl Drop for TcFilter {
+    fn drop(&mut self) {
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, TCA_KIND, b"bpf\0");
+
+        let message = tc_message(
+            RTM_DELTFILTER,
+            0,
+            self.ifindex,
+            Self::ids(self.priority),
+            &attrs,
+        );
+        if let Err(error) = self.netlink.request(&message) {
+            warn!(%error, "Failed to remove the eBPF sniffer TC filter");
+        }
+    }
+}
+
+/// Captures the incoming TCP packets of the subscribed ports with an eBPF program attached with
+/// TC, see the [module docs](self).
+///
+/// Like [`RawCapture`](rawsocket::RawCapture), yields whole Ethernet frames.
+pub(crate) struct EbpfCapture {
+    // Dropped first, so the program stops running before its maps are gone.
+    _filter: TcFilter,
+    _program: OwnedFd,
+    ports_map: OwnedFd,
+    _events_map: OwnedFd,
+    buffers: Vec<PerfBuffer>,
+    /// Ports currently in `ports_map`.
+    ports: HashSet<Port>,
+    /// Packets read from the buffers, not yet returned from [`EbpfCapture::next`].
+    packets: VecDeque<Vec<u8>>,
+}
+
+impl EbpfCapture {
+    /// Loads the program and attaches it to the ingress of `interface`. Fails when the kernel
+    /// doesn't support it or we don't have the permissions (`CAP_BPF`/`CAP_SYS_ADMIN` and
+    /// `CAP_NET_ADMIN`).
+    #[tracing::instrument(level = "trace")]
+    pub(crate) fn new(interface: &str) -> io::Result<Self> {
+        // Before 5.11, BPF memory is limited by `RLIMIT_MEMLOCK`.
+        let unlimited = libc::rlimit {
+            rlim_cur: libc::RLIM_INFINITY,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &unlimited) } != 0 {
+            trace!(error = %io::Error::last_os_error(), "failed to raise RLIMIT_MEMLOCK");
+        }
+
+        let page_size = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) })
+            .map_err(|_| io::Error::last_os_error())?;
+        let cpus = u32::try_from(unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) })
+            .map_err(|_| io::Error::last_os_error())?;
+
+        let ports_map = map_create(BPF_MAP_TYPE_HASH, 2, 1, MAX_PORTS)?;
+        let events_map = map_create(BPF_MAP_TYPE_PERF_EVENT_ARRAY, 4, 4, cpus)?;
+
+        let mut buffers = Vec::new();
+        for cpu in 0..cpus {
+            // Possible CPUs may be offline.
+            let buffer = match PerfBuffer::open(cpu as i32, page_size) {
+                Ok(buffer) => buffer,
+                Err(error) => {
+                    trace!(cpu, %error, "no eBPF sniffer ring buffer for the CPU");
+                    continue;
+                }
+            };
+            map_update(&events_map, &cpu, &buffer.fd.get_ref().as_raw_fd())?;
+            buffers.push(buffer);
+        }
+        if buffers.is_empty() {
+            return Err(io::Error::other(
+                "failed to open any perf event ring buffer",
+            ));
+        }
+
+        let program = load_program(&program(ports_map.as_raw_fd(), events_map.as_raw_fd()))?;
+        let filter = TcFilter::attach(interface, &program)?;
+
+        Ok(Self {
+            _filter: filter,
+            _program: program,
+            ports_map,
+            _events_map: events_map,
+            buffers,
+            ports: Default::default(),
+            packets: Default::default(),
+        })
+    }
+
+    /// Captures only the packets from or to these ports.
+    pub(crate) fn set_ports(&mut self, ports: &[Port]) -> io::Result<()> {
+        let ports = ports.iter().copied().collect::<HashSet<_>>();
+
+        for removed in self.ports.difference(&ports) {
+            map_delete(&self.ports_map, &removed.to_be())?;
+        }
+        for added in ports.difference(&self.ports) {
+            map_update(&self.ports_map, &added.to_be(), &1_u8)?;
+        }
+
+        self.ports = ports;
+        Ok(())
+    }
+
+    /// Returns the next captured frame.
+    pub(crate) async fn next(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(packet) = self.packets.pop_front() {
+                return Ok(packet);
+            }
+
+            let (ready, ..) = future::select_all(
+                self.buffers
+                    .iter()
+                    .map(|buffer| Box::pin(buffer.fd.readable())),
+            )
+            .await;
+            ready?.clear_ready();
+
+            for buffer in &self.buffers {
+                buffer.read_packets(&mut self.packets);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn program_jumps() {
+        let insns = program(3, 4);
+
+        assert_eq!(insns.len(), 56);
+
+        // Every jump is forward and lands inside the program.
+        for (at, insn) in insns.iter().enumerate() {
+            let target = at + 1 + usize::try_from(insn.off).expect("backward jump");
+            assert!(target < insns.len(), "jump at {at} out of the program");
+        }
+    }
+
+    #[test]
+    fn netlink_attributes() {
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, TCA_KIND, b"bpf\0");
+        push_attr(&mut attrs, TCA_BPF_NAME, b"abcde");
+
+        let mut expected = Vec::new();
+        expected.extend(8_u16.to_ne_bytes());
+        expected.extend(TCA_KIND.to_ne_bytes());
+        expected.extend(b"bpf\0");
+        expected.extend(9_u16.to_ne_bytes());
+        expected.extend(TCA_BPF_NAME.to_ne_bytes());
+        expected.extend(b"abcde\0\0\0");
+
+        assert_eq!(attrs, expected);
+    }
+}
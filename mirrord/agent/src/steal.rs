@@ -1,5 +1,5 @@
 use mirrord_protocol::{
-    tcp::{DaemonTcp, HttpResponseFallback, StealType, TcpData},
+    tcp::{DaemonTcp, HttpRequest, HttpResponseFallback, InternalHttpBody, StealType, TcpData},
     ConnectionId, Port,
 };
 use tokio::sync::mpsc::Sender;
@@ -58,6 +58,11 @@ enum Command {
     /// Should be forwarded back to the connection it was stolen from.
     HttpResponse(HttpResponseFallback),
 
+    /// Local app failed to handle a stolen HTTP request.
+    ///
+    /// Should be passed to the original destination of the connection it was stolen from.
+    HttpRequestPassThrough(HttpRequest<InternalHttpBody>),
+
     SwitchProtocolVersion(semver::Version),
 }
 
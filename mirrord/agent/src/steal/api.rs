@@ -1,4 +1,6 @@
-use mirrord_protocol::tcp::{DaemonTcp, HttpResponseFallback, LayerTcpSteal, TcpData};
+use mirrord_protocol::tcp::{
+    DaemonTcp, HttpRequest, HttpResponseFallback, InternalHttpBody, LayerTcpSteal, TcpData,
+};
 use tokio::sync::mpsc::{self, OwnedPermit, Receiver, Sender};
 
 use super::*;
@@ -141,6 +143,18 @@ impl TcpStealerApi {
         self.send_command(Command::HttpResponse(response)).await
     }
 
+    /// Handles the conversion of [`LayerTcpSteal::HttpRequestPassThrough`], that is passed from
+    /// the agent, to an internal stealer command [`Command::HttpRequestPassThrough`].
+    ///
+    /// The actual handling of this message is done in [`TcpConnectionStealer`].
+    pub(crate) async fn http_request_pass_through(
+        &mut self,
+        request: HttpRequest<InternalHttpBody>,
+    ) -> Result<(), AgentError> {
+        self.send_command(Command::HttpRequestPassThrough(request))
+            .await
+    }
+
     pub(crate) async fn switch_protocol_version(
         &mut self,
         version: semver::Version,
@@ -165,6 +179,9 @@ impl TcpStealerApi {
                 self.http_response(HttpResponseFallback::Framed(response))
                     .await
             }
+            LayerTcpSteal::HttpRequestPassThrough(request) => {
+                self.http_request_pass_through(request).await
+            }
         }
     }
 }
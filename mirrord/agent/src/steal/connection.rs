@@ -1,12 +1,13 @@
 use std::{
     collections::{HashMap, HashSet},
+    convert::Infallible,
     net::{IpAddr, Ipv4Addr, SocketAddr},
 };
 
+use bytes::Bytes;
 use fancy_regex::Regex;
-use http_body_util::BodyExt;
+use http_body_util::{combinators::BoxBody, BodyExt};
 use hyper::{
-    body::Incoming,
     http::{header::UPGRADE, request::Parts},
     Request,
 };
@@ -29,9 +30,11 @@ use crate::{
     error::{AgentError, Result},
     steal::{
         connections::{
-            ConnectionMessageIn, ConnectionMessageOut, StolenConnection, StolenConnections,
+            ConnectionMessageIn, ConnectionMessageOut, DynamicBody, StolenConnection,
+            StolenConnections,
         },
         http::HttpFilter,
+        ip_tables::LoopbackSteal,
         orig_dst,
         subscriptions::{IpTablesRedirector, PortSubscriptions},
         Command, StealerCommand,
@@ -45,7 +48,7 @@ struct MatchedHttpRequest {
     connection_id: ConnectionId,
     port: Port,
     request_id: RequestId,
-    request: Request<Incoming>,
+    request: Request<DynamicBody>,
 }
 
 impl MatchedHttpRequest {
@@ -137,7 +140,7 @@ impl Client {
     ///
     /// # Why async?
     ///
-    /// This method spawns a [`tokio::task`] to read the [`Incoming`] body od the request without
+    /// This method spawns a [`tokio::task`] to read the body of the request without
     /// blocking the main [`TcpConnectionStealer`] loop.
     fn send_request_async(&self, request: MatchedHttpRequest) -> bool {
         if request.request.headers().contains_key(UPGRADE)
@@ -201,7 +204,8 @@ impl TcpConnectionStealer {
                 .ok()
                 .and_then(|var| var.parse::<bool>().ok())
                 .unwrap_or_default();
-            let redirector = IpTablesRedirector::new(flush_connections).await?;
+            let redirector =
+                IpTablesRedirector::new(flush_connections, LoopbackSteal::from_env()).await?;
 
             PortSubscriptions::new(redirector, 4)
         };
@@ -421,6 +425,27 @@ impl TcpConnectionStealer {
                         .await;
                 }
             }
+
+            ConnectionMessageOut::ShadowResponse {
+                client_id,
+                connection_id,
+                response,
+            } => {
+                let Some(client) = self.clients.get(&client_id) else {
+                    tracing::trace!(client_id, connection_id, "Client has already exited");
+                    return Ok(());
+                };
+
+                if !client.subscribed_connections.contains(&connection_id) {
+                    tracing::trace!(client_id, connection_id, "Client has already unsubscribed");
+                    return Ok(());
+                }
+
+                let _ = client
+                    .tx
+                    .send(DaemonTcp::HttpShadowResponse(response))
+                    .await;
+            }
         }
 
         Ok(())
@@ -439,6 +464,9 @@ impl TcpConnectionStealer {
             StealType::FilteredHttpEx(port, filter) => HttpFilter::try_from(&filter)
                 .map(|filter| (port, Some(filter)))
                 .map_err(|err| BadHttpFilterExRegex(filter, err.to_string())),
+            StealType::FilteredHttpShadow(port, filter) => HttpFilter::try_from(&filter)
+                .map(|filter| (port, Some(HttpFilter::Shadow(Box::new(filter)))))
+                .map_err(|err| BadHttpFilterExRegex(filter, err.to_string())),
         };
 
         let res = match spec {
@@ -469,6 +497,31 @@ impl TcpConnectionStealer {
         Ok(())
     }
 
+    /// Converts the given [`HttpRequest`] back to a [`hyper::Request`] and sends it to
+    /// [`StolenConnections`], to be passed to the original destination.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn send_http_request_pass_through(
+        &mut self,
+        client_id: ClientId,
+        request: HttpRequest<InternalHttpBody>,
+    ) {
+        let connection_id = request.connection_id;
+        let request_id = request.request_id;
+        let request = Request::<BoxBody<Bytes, Infallible>>::from(request.internal_request)
+            .map(|body| BoxBody::new(body.map_err(|never| match never {})));
+
+        self.connections
+            .send(
+                connection_id,
+                ConnectionMessageIn::PassThrough {
+                    client_id,
+                    request_id,
+                    request,
+                },
+            )
+            .await;
+    }
+
     /// Converts the given [`HttpResponseFallback`] to a [`hyper::Response`] and sends it to
     /// [`StolenConnections`].
     #[tracing::instrument(level = "trace", skip(self))]
@@ -572,6 +625,11 @@ impl TcpConnectionStealer {
                 self.send_http_response(client_id, response).await;
             }
 
+            Command::HttpRequestPassThrough(request) => {
+                self.send_http_request_pass_through(client_id, request)
+                    .await;
+            }
+
             Command::SwitchProtocolVersion(new_version) => {
                 let client = self.clients.get_mut(&client_id).expect("client not found");
                 client.protocol_version = new_version;
@@ -3,8 +3,11 @@
 
 use std::{collections::HashMap, fmt, io, net::SocketAddr, time::Duration};
 
-use hyper::{body::Incoming, Request, Response};
-use mirrord_protocol::{tcp::NewTcpConnection, ConnectionId, Port, RequestId};
+use hyper::{Request, Response};
+use mirrord_protocol::{
+    tcp::{HttpResponse, InternalHttpBody, NewTcpConnection},
+    ConnectionId, Port, RequestId,
+};
 use thiserror::Error;
 use tokio::{
     net::TcpStream,
@@ -12,7 +15,8 @@ use tokio::{
     task::JoinSet,
 };
 
-use self::{filtered::DynamicBody, unfiltered::UnfilteredStealTask};
+pub use self::filtered::DynamicBody;
+use self::unfiltered::UnfilteredStealTask;
 use super::{http::DefaultReversibleStream, subscriptions::PortSubscription};
 use crate::{http::HttpVersion, steal::connections::filtered::FilteredStealTask, util::ClientId};
 
@@ -36,6 +40,16 @@ pub enum ConnectionMessageIn {
         request_id: RequestId,
         response: Response<DynamicBody>,
     },
+    /// Client could not handle a stolen request, it should be passed to the original destination.
+    ///
+    /// This variant translates to
+    /// [`LayerTcpSteal::HttpRequestPassThrough`](mirrord_protocol::tcp::LayerTcpSteal::HttpRequestPassThrough)
+    /// coming from the layer.
+    PassThrough {
+        client_id: ClientId,
+        request_id: RequestId,
+        request: Request<DynamicBody>,
+    },
     /// Client failed to provide an HTTP response to a stolen request.
     ///
     /// This variant does not translate to any
@@ -74,6 +88,16 @@ impl fmt::Debug for ConnectionMessageIn {
                 debug_struct.field("request_id", request_id);
                 debug_struct.field("status_code", &response.status());
             }
+            Self::PassThrough {
+                client_id,
+                request_id,
+                request,
+            } => {
+                debug_struct.field("type", &"PassThrough");
+                debug_struct.field("client_id", client_id);
+                debug_struct.field("request_id", request_id);
+                debug_struct.field("request_path", &request.uri().path());
+            }
             Self::ResponseFailed {
                 client_id,
                 request_id,
@@ -98,6 +122,7 @@ impl ConnectionMessageIn {
         match self {
             Self::Raw { client_id, .. } => *client_id,
             Self::Response { client_id, .. } => *client_id,
+            Self::PassThrough { client_id, .. } => *client_id,
             Self::ResponseFailed { client_id, .. } => *client_id,
             Self::Unsubscribed { client_id } => *client_id,
         }
@@ -130,10 +155,21 @@ pub enum ConnectionMessageOut {
     Request {
         client_id: ClientId,
         connection_id: ConnectionId,
-        request: Request<Incoming>,
+        request: Request<DynamicBody>,
         id: RequestId,
         port: Port,
     },
+    /// The original destination responded to a request shadowed for the client (the client got
+    /// the request in [`ConnectionMessageOut::Request`]).
+    ///
+    /// This variant translates to
+    /// [`DaemonTcp::HttpShadowResponse`](mirrord_protocol::tcp::DaemonTcp::HttpShadowResponse)
+    /// being sent to the layer.
+    ShadowResponse {
+        client_id: ClientId,
+        connection_id: ConnectionId,
+        response: HttpResponse<InternalHttpBody>,
+    },
     /// Subscribed the client to a new TCP connection.
     ///
     /// This variant translates to
@@ -205,6 +241,17 @@ impl fmt::Debug for ConnectionMessageOut {
                 debug_struct.field("method", request.method());
                 debug_struct.field("uri", request.uri());
             }
+            Self::ShadowResponse {
+                client_id,
+                connection_id,
+                response,
+            } => {
+                debug_struct.field("type", &"ShadowResponse");
+                debug_struct.field("connection_id", connection_id);
+                debug_struct.field("client_id", client_id);
+                debug_struct.field("request_id", &response.request_id);
+                debug_struct.field("status_code", &response.internal_response.status());
+            }
             Self::SubscribedTcp {
                 client_id,
                 connection,
@@ -5,17 +5,21 @@ use std::{
 use bytes::Bytes;
 use dashmap::DashMap;
 use http::Version;
-use http_body_util::{combinators::BoxBody, BodyExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::{
     body::Incoming,
     client::conn::{http1, http2},
+    header::UPGRADE,
     http::{Request, StatusCode},
     service::Service,
     upgrade::{OnUpgrade, Upgraded},
     Response,
 };
 use hyper_util::rt::{TokioExecutor, TokioIo};
-use mirrord_protocol::{ConnectionId, RequestId};
+use mirrord_protocol::{
+    tcp::{HttpResponse, InternalHttpBody},
+    ConnectionId, RequestId,
+};
 use tokio::{
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
@@ -49,7 +53,7 @@ enum RequestHandling {
     /// The [`Request`] should be handled by the HTTP server running at the given address.
     LetThrough {
         to: SocketAddr,
-        unchanged: Request<Incoming>,
+        unchanged: Request<DynamicBody>,
     },
     /// The [`FilteringService`] should respond immediately with the given [`Response`]
     /// on behalf of the given stealer client.
@@ -57,6 +61,10 @@ enum RequestHandling {
         response: Response<DynamicBody>,
         for_client: ClientId,
     },
+    /// The [`FilteringService`] should respond immediately with the given [`Response`] of the
+    /// original destination, to which the [`FilteredStealTask`] already passed the shadowed
+    /// [`Request`].
+    RespondFromOriginal(Response<DynamicBody>),
 }
 
 /// HTTP server side of an upgraded connection retrieved from [`FilteringService`].
@@ -117,7 +125,7 @@ impl FilteringService {
     /// Also, it does not retry the request upon failure.
     async fn send_request(
         to: SocketAddr,
-        mut request: Request<Incoming>,
+        mut request: Request<DynamicBody>,
     ) -> Result<Response<Incoming>, Box<dyn std::error::Error>> {
         let tcp_stream = TcpStream::connect(to).await.inspect_err(|error| {
             tracing::error!(?error, address = %to, "Failed connecting to request destination");
@@ -190,7 +198,7 @@ impl FilteringService {
     )]
     async fn let_through(
         &self,
-        request: Request<Incoming>,
+        request: Request<DynamicBody>,
         on_upgrade: OnUpgrade,
         to: SocketAddr,
     ) -> Response<DynamicBody> {
@@ -299,6 +307,7 @@ impl FilteringService {
                     .await;
                 response
             }
+            Ok(RequestHandling::RespondFromOriginal(response)) => response,
             Err(..) => Self::bad_gateway(
                 version,
                 "failed to receive a response from the connected mirrord session",
@@ -323,6 +332,116 @@ impl Service<Request<Incoming>> for FilteringService {
     }
 }
 
+/// Handles a request shadowed for the client with the given id.
+///
+/// The bodies of the request and of the response of the original destination are buffered, so
+/// that the client gets copies of both: the request in [`ConnectionMessageOut::Request`] and the
+/// response in [`ConnectionMessageOut::ShadowResponse`]. The HTTP client gets the response of the
+/// original destination, through the [`FilteringService`].
+#[tracing::instrument(
+    level = "trace",
+    skip(request, tx),
+    fields(request_path = request.request.uri().path())
+)]
+async fn shadow_request(
+    request: ExtractedRequest,
+    original_destination: SocketAddr,
+    client_id: ClientId,
+    connection_id: ConnectionId,
+    request_id: RequestId,
+    tx: Sender<ConnectionMessageOut>,
+) {
+    let ExtractedRequest {
+        request,
+        response_tx,
+    } = request;
+    let full_body = |body: Bytes| BoxBody::new(Full::new(body).map_err(|_| unreachable!()));
+
+    let (parts, body) = request.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(error) => {
+            tracing::trace!(?error, "Failed to read the body of a shadowed request");
+            return;
+        }
+    };
+
+    let mut request_copy = Request::new(full_body(body.clone()));
+    *request_copy.method_mut() = parts.method.clone();
+    *request_copy.uri_mut() = parts.uri.clone();
+    *request_copy.version_mut() = parts.version;
+    *request_copy.headers_mut() = parts.headers.clone();
+
+    let _ = tx
+        .send(ConnectionMessageOut::Request {
+            client_id,
+            connection_id,
+            request: request_copy,
+            id: request_id,
+            port: original_destination.port(),
+        })
+        .await;
+
+    let version = parts.version;
+    let response = FilteringService::send_request(
+        original_destination,
+        Request::from_parts(parts, full_body(body)),
+    )
+    .await
+    .ok();
+    let Some(response) = response else {
+        let _ = response_tx.send(RequestHandling::RespondFromOriginal(
+            FilteringService::bad_gateway(
+                version,
+                "failed to pass the request to its original destination",
+            ),
+        ));
+        return;
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(error) => {
+            tracing::trace!(?error, "Failed to read the body of a shadowed response");
+            let _ = response_tx.send(RequestHandling::RespondFromOriginal(
+                FilteringService::bad_gateway(
+                    version,
+                    "failed to read the response of the original destination",
+                ),
+            ));
+            return;
+        }
+    };
+
+    let mut response_copy = Response::new(Full::new(body.clone()));
+    *response_copy.status_mut() = parts.status;
+    *response_copy.version_mut() = parts.version;
+    *response_copy.headers_mut() = parts.headers.clone();
+
+    let _ = response_tx.send(RequestHandling::RespondFromOriginal(Response::from_parts(
+        parts,
+        full_body(body),
+    )));
+
+    let response = HttpResponse::<InternalHttpBody>::from_hyper_response(
+        response_copy,
+        original_destination.port(),
+        connection_id,
+        request_id,
+    )
+    .await
+    .unwrap_or_else(|never| match never {});
+
+    let _ = tx
+        .send(ConnectionMessageOut::ShadowResponse {
+            client_id,
+            connection_id,
+            response,
+        })
+        .await;
+}
+
 /// Manages a filtered stolen connection.
 ///
 /// Uses a custom [`Service`] implementation to handle HTTP protocol details, change the raw IO
@@ -523,6 +642,50 @@ where
         }
     }
 
+    /// Instructs the [`FilteringService`] to pass the request with the given id to the original
+    /// destination, because the client could not handle it. The client sends back the whole
+    /// [`Request`], since its body was consumed when the request was stolen.
+    ///
+    /// If there is no blocked request for the given ([`ClientId`], [`RequestId`]) combination or
+    /// the HTTP connection is dead, does nothing.
+    #[tracing::instrument(
+        level = "trace",
+        name = "handle_filtered_request_pass_through",
+        skip(self, request),
+        fields(
+            connection_id = self.connection_id,
+            original_destination = %self.original_destination,
+        )
+    )]
+    fn handle_pass_through(
+        &mut self,
+        client_id: ClientId,
+        request_id: RequestId,
+        request: Request<DynamicBody>,
+    ) {
+        let Some(tx) = self.blocked_requests.remove(&(client_id, request_id)) else {
+            tracing::trace!(
+                client_id,
+                request_id,
+                "Received a pass through for an unexpected (client_id, request_id) combination",
+            );
+
+            return;
+        };
+
+        let handling = RequestHandling::LetThrough {
+            to: self.original_destination,
+            unchanged: request,
+        };
+        if tx.send(handling).is_err() {
+            tracing::trace!(
+                client_id,
+                request_id,
+                "Failed to pass the request through - HTTP connection is probably closed",
+            );
+        }
+    }
+
     /// Notifies the [`FilteringService`] that the client failed to provide a [`Response`] for the
     /// request with the given id. The [`FilteringService`] is notified by dropping a
     /// [`oneshot::Sender`] from [`Self::blocked_requests`].
@@ -560,14 +723,29 @@ where
         mut request: ExtractedRequest,
         tx: &Sender<ConnectionMessageOut>,
     ) -> Result<(), ConnectionTaskError> {
-        let Some(client_id) = self.match_request(&mut request.request) else {
+        let matched = self.match_request(&mut request.request).map(|client_id| {
+            let shadow = self
+                .filters
+                .get(&client_id)
+                .is_some_and(|filter| filter.is_shadow());
+            (client_id, shadow)
+        });
+
+        let client_id = match matched {
+            // Upgraded connections can't be shadowed, the original destination keeps them.
+            Some((_, true)) if request.request.headers().contains_key(UPGRADE) => None,
+            matched => matched.map(|(client_id, _)| client_id),
+        };
+
+        let Some(client_id) = client_id else {
             let _ = request.response_tx.send(RequestHandling::LetThrough {
                 to: self.original_destination,
-                unchanged: request.request,
+                unchanged: request.request.map(BoxBody::new),
             });
 
             return Ok(());
         };
+        let shadow = matched.is_some_and(|(_, shadow)| shadow);
 
         if self.subscribed.insert(client_id, true).is_none() {
             // First time this client will receive a request from this connection.
@@ -581,10 +759,24 @@ where
         let id = self.next_request_id;
         self.next_request_id += 1;
 
+        if shadow {
+            // The client's response is not used, so the request is not blocked on it.
+            tokio::spawn(shadow_request(
+                request,
+                self.original_destination,
+                client_id,
+                self.connection_id,
+                id,
+                tx.clone(),
+            ));
+
+            return Ok(());
+        }
+
         tx.send(ConnectionMessageOut::Request {
             client_id,
             connection_id: self.connection_id,
-            request: request.request,
+            request: request.request.map(BoxBody::new),
             id,
             port: self.original_destination.port(),
         })
@@ -623,6 +815,10 @@ where
                         queued_raw_data.remove(&client_id);
                         self.handle_response(client_id, request_id, response);
                     },
+                    ConnectionMessageIn::PassThrough { request, request_id, client_id } => {
+                        queued_raw_data.remove(&client_id);
+                        self.handle_pass_through(client_id, request_id, request);
+                    },
                     ConnectionMessageIn::ResponseFailed { request_id, client_id } => {
                         queued_raw_data.remove(&client_id);
                         self.handle_response_failure(client_id, request_id);
@@ -1560,6 +1756,62 @@ mod test {
         assert!(rx.recv().await.is_none());
     }
 
+    /// The stolen connection receives a request that matches some client's filter.
+    /// The client can't handle it and sends it back, so the original destination responds.
+    #[tokio::test]
+    async fn request_passed_through_by_client() {
+        let mut setup = TestSetup::new().await;
+
+        let request = setup.prepare_request(Some(0), false);
+        tokio::join!(
+            async {
+                let response = setup.request_sender.send_request(request).await.unwrap();
+                // The original destination responds to all non-upgrade requests like this.
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            },
+            async {
+                match setup.task_out_rx.recv().await.unwrap() {
+                    ConnectionMessageOut::SubscribedHttp {
+                        client_id: 0,
+                        connection_id: TestSetup::CONNECTION_ID,
+                    } => {}
+                    other => unreachable!("unexpected message: {other:?}"),
+                };
+
+                let (request_id, request) = match setup.task_out_rx.recv().await.unwrap() {
+                    ConnectionMessageOut::Request {
+                        client_id: 0,
+                        connection_id: TestSetup::CONNECTION_ID,
+                        id,
+                        request,
+                        ..
+                    } => (id, request),
+                    other => unreachable!("unexpected message: {other:?}"),
+                };
+
+                setup
+                    .task_in_tx
+                    .send(ConnectionMessageIn::PassThrough {
+                        client_id: 0,
+                        request_id,
+                        request,
+                    })
+                    .await
+                    .unwrap();
+            }
+        );
+
+        setup
+            .task_in_tx
+            .send(ConnectionMessageIn::Unsubscribed { client_id: 0 })
+            .await
+            .unwrap();
+
+        let mut rx = setup.shutdown().await;
+        // The task should not produce the `Closed` message - the client has unsubscribed.
+        assert!(rx.recv().await.is_none());
+    }
+
     /// The stolen connection receives a request that matches some client's filter.
     /// The client unsubscribes before providing a response and the request sender gets a
     /// [`StatusCode::BAD_GATEWAY`] response.
@@ -1608,4 +1860,70 @@ mod test {
         // The task should not produce the `Closed` message - the client has unsubscribed.
         assert!(rx.recv().await.is_none());
     }
+
+    /// Stolen connection receives a request that matches a shadowing client's filter.
+    /// The original destination responds to the request and the client gets copies of both.
+    /// Then, connection is closed and the client is notified.
+    #[tokio::test]
+    async fn shadowed_request() {
+        let mut setup = TestSetup::new().await;
+
+        let request = setup.prepare_request(Some(0), false);
+        let (_, filter) = setup.filters.remove(&0).unwrap();
+        setup
+            .filters
+            .insert(0, HttpFilter::Shadow(Box::new(filter)));
+
+        // The original destination responds, no response from the client is needed.
+        let response = setup.request_sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        match setup.task_out_rx.recv().await.unwrap() {
+            ConnectionMessageOut::SubscribedHttp {
+                client_id: 0,
+                connection_id: TestSetup::CONNECTION_ID,
+            } => {}
+            other => unreachable!("unexpected message: {other:?}"),
+        };
+
+        let request_id = match setup.task_out_rx.recv().await.unwrap() {
+            ConnectionMessageOut::Request {
+                client_id: 0,
+                connection_id: TestSetup::CONNECTION_ID,
+                id,
+                request,
+                ..
+            } => {
+                assert_eq!(request.headers().get("x-client").unwrap(), "0");
+                id
+            }
+            other => unreachable!("unexpected message: {other:?}"),
+        };
+
+        match setup.task_out_rx.recv().await.unwrap() {
+            ConnectionMessageOut::ShadowResponse {
+                client_id: 0,
+                connection_id: TestSetup::CONNECTION_ID,
+                response,
+            } => {
+                assert_eq!(response.request_id, request_id);
+                assert_eq!(response.internal_response.status(), StatusCode::BAD_REQUEST);
+            }
+            other => unreachable!("unexpected message: {other:?}"),
+        };
+
+        let mut rx = tokio::time::timeout(std::time::Duration::from_secs(5), setup.shutdown())
+            .await
+            .unwrap();
+
+        match rx.recv().await.unwrap() {
+            ConnectionMessageOut::Closed {
+                client_id: 0,
+                connection_id: TestSetup::CONNECTION_ID,
+            } => {}
+            other => unreachable!("unexpected message: {other:?}"),
+        };
+
+        assert!(rx.recv().await.is_none());
+    }
 }
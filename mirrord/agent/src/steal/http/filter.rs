@@ -1,5 +1,6 @@
 use fancy_regex::Regex;
-use hyper::Request;
+use hyper::{header::CONTENT_TYPE, Request};
+use mirrord_protocol::tcp::GrpcFilter;
 
 /// Currently supported filtering criterias.
 #[derive(Debug)]
@@ -9,6 +10,13 @@ pub enum HttpFilter {
     Header(Regex),
     /// Path based filter.
     Path(Regex),
+    /// gRPC service and method based filter, matched against the `/<service>/<method>` path of
+    /// gRPC requests.
+    Grpc(GrpcFilter),
+    /// Requests matching the inner filter are shadowed instead of stolen: the client gets a copy
+    /// and the original destination still responds to them
+    /// ([`StealType::FilteredHttpShadow`](mirrord_protocol::tcp::StealType::FilteredHttpShadow)).
+    Shadow(Box<HttpFilter>),
 }
 
 impl TryFrom<&mirrord_protocol::tcp::HttpFilter> for HttpFilter {
@@ -22,11 +30,17 @@ impl TryFrom<&mirrord_protocol::tcp::HttpFilter> for HttpFilter {
             mirrord_protocol::tcp::HttpFilter::Path(path) => {
                 Ok(Self::Path(Regex::new(&format!("(?i){path}"))?))
             }
+            mirrord_protocol::tcp::HttpFilter::Grpc(grpc) => Ok(Self::Grpc(grpc.clone())),
         }
     }
 }
 
 impl HttpFilter {
+    /// Whether the requests matching this filter are shadowed instead of stolen.
+    pub fn is_shadow(&self) -> bool {
+        matches!(self, Self::Shadow(..))
+    }
+
     /// Checks whether the given [`Request`] matches this filter.
     pub fn matches<T>(&self, request: &mut Request<T>) -> bool {
         match self {
@@ -67,6 +81,32 @@ impl HttpFilter {
                     })
                     .unwrap_or(false)
             }
+
+            Self::Grpc(GrpcFilter { service, method }) => {
+                let is_grpc = request
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|content_type| content_type.to_str().ok())
+                    .is_some_and(|content_type| content_type.starts_with("application/grpc"));
+
+                // gRPC requests go to `/<service>/<method>`.
+                let Some((request_service, request_method)) = request
+                    .uri()
+                    .path()
+                    .strip_prefix('/')
+                    .and_then(|path| path.split_once('/'))
+                else {
+                    return false;
+                };
+
+                is_grpc
+                    && request_service == service
+                    && method
+                        .as_deref()
+                        .map_or(true, |method| method == request_method)
+            }
+
+            Self::Shadow(filter) => filter.matches(request),
         }
     }
 }
@@ -90,3 +130,64 @@ impl NormalizedHeaders {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn grpc_request(path: &str, content_type: &str) -> Request<()> {
+        Request::builder()
+            .uri(path)
+            .header(CONTENT_TYPE, content_type)
+            .body(())
+            .unwrap()
+    }
+
+    fn grpc_filter(service: &str, method: Option<&str>) -> HttpFilter {
+        HttpFilter::Grpc(GrpcFilter {
+            service: service.to_string(),
+            method: method.map(ToString::to_string),
+        })
+    }
+
+    #[test]
+    fn grpc_filter_matches_service_and_method() {
+        let filter = grpc_filter("my.package.Service", Some("MyMethod"));
+
+        assert!(filter.matches(&mut grpc_request(
+            "/my.package.Service/MyMethod",
+            "application/grpc"
+        )));
+        assert!(filter.matches(&mut grpc_request(
+            "/my.package.Service/MyMethod",
+            "application/grpc+proto"
+        )));
+        assert!(!filter.matches(&mut grpc_request(
+            "/my.package.Service/OtherMethod",
+            "application/grpc"
+        )));
+        assert!(!filter.matches(&mut grpc_request(
+            "/my.package.Other/MyMethod",
+            "application/grpc"
+        )));
+        assert!(!filter.matches(&mut grpc_request(
+            "/my.package.Service/MyMethod",
+            "application/json"
+        )));
+    }
+
+    #[test]
+    fn grpc_filter_without_method_matches_whole_service() {
+        let filter = grpc_filter("my.package.Service", None);
+
+        assert!(filter.matches(&mut grpc_request(
+            "/my.package.Service/MyMethod",
+            "application/grpc"
+        )));
+        assert!(filter.matches(&mut grpc_request(
+            "/my.package.Service/OtherMethod",
+            "application/grpc"
+        )));
+        assert!(!filter.matches(&mut grpc_request("/my.package.Service", "application/grpc")));
+    }
+}
@@ -10,9 +10,11 @@ use tracing::warn;
 
 use crate::{
     error::{AgentError, Result},
+    host_os::HostOs,
     steal::ip_tables::{
         flush_connections::FlushConnections,
         mesh::{MeshRedirect, MeshVendorExt},
+        node_port::NodePortRedirect,
         prerouting::PreroutingRedirect,
         redirect::Redirect,
         standard::StandardRedirect,
@@ -52,6 +54,7 @@ mod iptables {
 pub(crate) mod chain;
 pub(crate) mod flush_connections;
 pub(crate) mod mesh;
+pub(crate) mod node_port;
 pub(crate) mod output;
 pub(crate) mod prerouting;
 pub(crate) mod redirect;
@@ -87,6 +90,16 @@ pub(crate) static IPTABLE_STANDARD: LazyLock<String> = LazyLock::new(|| {
     })
 });
 
+pub(crate) static IPTABLE_NODE_PORT_ENV: &str = "MIRRORD_IPTABLE_NODE_PORT_NAME";
+pub(crate) static IPTABLE_NODE_PORT: LazyLock<String> = LazyLock::new(|| {
+    std::env::var(IPTABLE_NODE_PORT_ENV).unwrap_or_else(|_| {
+        format!(
+            "MIRRORD_NODEPORT_{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 5)
+        )
+    })
+});
+
 pub static IPTABLE_INPUT_ENV: &str = "MIRRORD_IPTABLE_INPUT_NAME";
 pub static IPTABLE_INPUT: LazyLock<String> = LazyLock::new(|| {
     std::env::var(IPTABLE_INPUT_ENV).unwrap_or_else(|_| {
@@ -99,6 +112,39 @@ pub static IPTABLE_INPUT: LazyLock<String> = LazyLock::new(|| {
 
 const IPTABLES_TABLE_NAME: &str = "nat";
 
+/// Which connections to the stolen ports are redirected, set with `MIRRORD_AGENT_STEAL_LOOPBACK`
+/// (`agent.steal_loopback` in the config).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum LoopbackSteal {
+    /// Connections from outside of the pod, and the loopback ones made inside of it.
+    #[default]
+    Include,
+    /// Only connections from outside of the pod.
+    Exclude,
+    /// Only the loopback connections made inside of the pod, e.g. from the target to a sidecar.
+    Only,
+}
+
+impl LoopbackSteal {
+    pub(crate) fn from_env() -> Self {
+        match std::env::var("MIRRORD_AGENT_STEAL_LOOPBACK").as_deref() {
+            Ok("exclude") => Self::Exclude,
+            Ok("only") => Self::Only,
+            _ => Self::Include,
+        }
+    }
+
+    /// Whether connections from outside of the pod are redirected (in `PREROUTING`).
+    pub(crate) fn external(self) -> bool {
+        self != Self::Only
+    }
+
+    /// Whether loopback connections are redirected (in `OUTPUT`).
+    pub(crate) fn loopback(self) -> bool {
+        self != Self::Exclude
+    }
+}
+
 #[cfg_attr(test, mockall::automock)]
 pub(crate) trait IPTables {
     fn with_table(&self, table_name: &'static str) -> Self
@@ -120,11 +166,15 @@ pub struct IPTablesWrapper {
     tables: Arc<iptables::IPTables>,
 }
 
-/// wrapper around iptables::new that uses nft or legacy based on env
+/// Whether nftables were requested with `MIRRORD_AGENT_NFTABLES` (`agent.nftables` in the config).
+pub fn nftables_requested() -> bool {
+    std::env::var("MIRRORD_AGENT_NFTABLES").is_ok_and(|val| val.to_lowercase() == "true")
+}
+
+/// wrapper around iptables::new that uses nft or legacy based on env, or on the host OS when it
+/// doesn't support iptables-legacy
 pub fn new_iptables() -> iptables::IPTables {
-    if let Ok(val) = std::env::var("MIRRORD_AGENT_NFTABLES")
-        && val.to_lowercase() == "true"
-    {
+    if nftables_requested() || HostOs::get().requires_nftables() {
         iptables::new_with_cmd("/usr/sbin/iptables-nft")
     } else {
         iptables::new_with_cmd("/usr/sbin/iptables-legacy")
@@ -220,6 +270,7 @@ pub(crate) enum Redirects<IPT: IPTables + Send + Sync> {
     Mesh(MeshRedirect<IPT>),
     FlushConnections(FlushConnections<IPT, Redirects<IPT>>),
     PrerouteFallback(PreroutingRedirect<IPT>),
+    NodePort(NodePortRedirect<IPT, Redirects<IPT>>),
 }
 
 /// Wrapper struct for IPTables so it flushes on drop.
@@ -236,13 +287,19 @@ impl<IPT> SafeIpTables<IPT>
 where
     IPT: IPTables + Send + Sync,
 {
-    pub(super) async fn create(ipt: IPT, flush_connections: bool) -> Result<Self> {
+    pub(super) async fn create(
+        ipt: IPT,
+        flush_connections: bool,
+        loopback: LoopbackSteal,
+    ) -> Result<Self> {
         let ipt = Arc::new(ipt);
 
         let mut redirect = if let Some(vendor) = MeshVendor::detect(ipt.as_ref())? {
-            Redirects::Mesh(MeshRedirect::create(ipt.clone(), vendor)?)
+            Redirects::Mesh(MeshRedirect::create(ipt.clone(), vendor, loopback)?)
         } else {
-            match StandardRedirect::create(ipt.clone()) {
+            match StandardRedirect::create(ipt.clone(), loopback) {
+                // The fallback can't redirect loopback connections.
+                Err(err) if loopback == LoopbackSteal::Only => return Err(err),
                 Err(err) => {
                     warn!("Unable to create StandardRedirect chain: {err}");
 
@@ -252,6 +309,17 @@ where
             }
         };
 
+        if loopback.external()
+            && let Some(mode) = NodePortRedirect::<IPT, Redirects<IPT>>::detect(ipt.as_ref())?
+        {
+            redirect = Redirects::NodePort(NodePortRedirect::create(
+                ipt.clone(),
+                Box::new(redirect),
+                mode,
+                flush_connections,
+            )?)
+        }
+
         if flush_connections {
             redirect =
                 Redirects::FlushConnections(FlushConnections::create(ipt, Box::new(redirect))?)
@@ -278,6 +346,15 @@ where
             }
         };
 
+        if let Some(mode) = NodePortRedirect::<IPT, Redirects<IPT>>::detect(ipt.as_ref())? {
+            redirect = Redirects::NodePort(NodePortRedirect::load(
+                ipt.clone(),
+                Box::new(redirect),
+                mode,
+                flush_connections,
+            )?)
+        }
+
         if flush_connections {
             redirect = Redirects::FlushConnections(FlushConnections::load(ipt, Box::new(redirect))?)
         }
@@ -333,6 +410,10 @@ mod tests {
             .with(eq("OUTPUT"))
             .returning(|_| Ok(vec![]));
 
+        mock.expect_list_rules()
+            .with(eq("PREROUTING"))
+            .returning(|_| Ok(vec![]));
+
         mock.expect_create_chain()
             .with(str::starts_with("MIRRORD_INPUT_"))
             .times(1)
@@ -416,7 +497,7 @@ mod tests {
             .times(1)
             .returning(|_| Ok(()));
 
-        let ipt = SafeIpTables::create(mock, false)
+        let ipt = SafeIpTables::create(mock, false, LoopbackSteal::Include)
             .await
             .expect("Create Failed");
 
@@ -427,6 +508,71 @@ mod tests {
         assert!(ipt.cleanup().await.is_ok());
     }
 
+    #[tokio::test]
+    async fn loopback_only() {
+        let mut mock = MockIPTables::new();
+
+        mock.expect_list_rules()
+            .with(eq("OUTPUT"))
+            .returning(|_| Ok(vec![]));
+
+        mock.expect_create_chain()
+            .with(str::starts_with("MIRRORD_INPUT_"))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        mock.expect_create_chain()
+            .with(str::starts_with("MIRRORD_STANDARD_"))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        mock.expect_insert_rule()
+            .with(
+                str::starts_with("MIRRORD_STANDARD_"),
+                str::starts_with("-m owner --gid-owner"),
+                eq(1),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        mock.expect_insert_rule()
+            .with(
+                str::starts_with("MIRRORD_STANDARD_"),
+                eq("-o lo -m tcp -p tcp --dport 69 -j REDIRECT --to-ports 420"),
+                eq(2),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        mock.expect_add_rule()
+            .with(eq("PREROUTING"), str::starts_with("-j MIRRORD_INPUT_"))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        mock.expect_add_rule()
+            .with(eq("OUTPUT"), str::starts_with("-j MIRRORD_STANDARD_"))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        mock.expect_remove_rule()
+            .with(
+                str::starts_with("MIRRORD_STANDARD_"),
+                eq("-o lo -m tcp -p tcp --dport 69 -j REDIRECT --to-ports 420"),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        mock.expect_remove_chain().times(2).returning(|_| Ok(()));
+
+        let ipt = SafeIpTables::create(mock, false, LoopbackSteal::Only)
+            .await
+            .expect("Create Failed");
+
+        assert!(ipt.add_redirect(69, 420).await.is_ok());
+
+        assert!(ipt.remove_redirect(69, 420).await.is_ok());
+    }
+
     #[tokio::test]
     async fn linkerd() {
         let mut mock = MockIPTables::new();
@@ -435,6 +581,10 @@ mod tests {
             .with(eq("OUTPUT"))
             .returning(|_| Ok(vec!["-j PROXY_INIT_OUTPUT".to_owned()]));
 
+        mock.expect_list_rules()
+            .with(eq("PREROUTING"))
+            .returning(|_| Ok(vec![]));
+
         mock.expect_list_rules()
             .with(eq("PROXY_INIT_REDIRECT"))
             .returning(|_| {
@@ -549,7 +699,7 @@ mod tests {
             .times(1)
             .returning(|_| Ok(()));
 
-        let ipt = SafeIpTables::create(mock, false)
+        let ipt = SafeIpTables::create(mock, false, LoopbackSteal::Include)
             .await
             .expect("Create Failed");
 
@@ -17,7 +17,7 @@ use crate::{
     steal::ip_tables::{chain::IPTableChain, redirect::Redirect, IPTables, IPTABLE_INPUT},
 };
 
-const MARK: &str = "0x1";
+pub(crate) const MARK: &str = "0x1";
 
 #[derive(Debug)]
 pub(crate) struct FlushConnections<IPT: IPTables, T> {
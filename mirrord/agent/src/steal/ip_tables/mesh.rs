@@ -8,7 +8,7 @@ use crate::{
     error::Result,
     steal::ip_tables::{
         output::OutputRedirect, prerouting::PreroutingRedirect, redirect::Redirect, IPTables,
-        IPTABLE_MESH,
+        LoopbackSteal, IPTABLE_MESH,
     },
 };
 
@@ -21,13 +21,14 @@ static TCP_SKIP_PORTS_LOOKUP_REGEX: LazyLock<Regex> =
 pub(crate) struct MeshRedirect<IPT: IPTables> {
     prerouteing: PreroutingRedirect<IPT>,
     output: OutputRedirect<IPT>,
+    loopback: LoopbackSteal,
 }
 
 impl<IPT> MeshRedirect<IPT>
 where
     IPT: IPTables,
 {
-    pub fn create(ipt: Arc<IPT>, vendor: MeshVendor) -> Result<Self> {
+    pub fn create(ipt: Arc<IPT>, vendor: MeshVendor, loopback: LoopbackSteal) -> Result<Self> {
         let prerouteing = PreroutingRedirect::create(ipt.clone())?;
 
         for port in Self::get_skip_ports(&ipt, &vendor)? {
@@ -39,6 +40,7 @@ where
         Ok(MeshRedirect {
             prerouteing,
             output,
+            loopback,
         })
     }
 
@@ -49,6 +51,7 @@ where
         Ok(MeshRedirect {
             prerouteing,
             output,
+            loopback: LoopbackSteal::default(),
         })
     }
 
@@ -93,23 +96,31 @@ where
     }
 
     async fn add_redirect(&self, redirected_port: Port, target_port: Port) -> Result<()> {
-        self.prerouteing
-            .add_redirect(redirected_port, target_port)
-            .await?;
-        self.output
-            .add_redirect(redirected_port, target_port)
-            .await?;
+        if self.loopback.external() {
+            self.prerouteing
+                .add_redirect(redirected_port, target_port)
+                .await?;
+        }
+        if self.loopback.loopback() {
+            self.output
+                .add_redirect(redirected_port, target_port)
+                .await?;
+        }
 
         Ok(())
     }
 
     async fn remove_redirect(&self, redirected_port: Port, target_port: Port) -> Result<()> {
-        self.prerouteing
-            .remove_redirect(redirected_port, target_port)
-            .await?;
-        self.output
-            .remove_redirect(redirected_port, target_port)
-            .await?;
+        if self.loopback.external() {
+            self.prerouteing
+                .remove_redirect(redirected_port, target_port)
+                .await?;
+        }
+        if self.loopback.loopback() {
+            self.output
+                .remove_redirect(redirected_port, target_port)
+                .await?;
+        }
 
         Ok(())
     }
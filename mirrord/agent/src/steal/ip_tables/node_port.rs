@@ -0,0 +1,764 @@
+//! Stealing traffic that enters the node through a `NodePort` or `LoadBalancer` service.
+//!
+//! When the target shares the node's network namespace (`hostNetwork` pods), kube-proxy's
+//! `KUBE-SERVICES` chain runs in the same `PREROUTING` as our rules. The traffic coming from
+//! outside of the cluster is sent to the node port (or to the load balancer's port), and kube-proxy
+//! DNATs it to the target's port before it reaches our chain, so a redirect of the target's port
+//! never matches it.
+//!
+//! [`NodePortRedirect`] jumps to its own chain ahead of kube-proxy, looks for the external ports
+//! that end up in one of our local addresses on the redirected port, and redirects those ports as
+//! well. How we find them depends on the [`KubeProxyMode`]:
+//!
+//! - in iptables mode we follow kube-proxy's chains down to their `DNAT` rules;
+//! - in IPVS mode the services are IPVS virtual servers, handled after `PREROUTING`, so we read
+//!   them with `ipvsadm`. Here the cluster IPs are local addresses as well, so the service traffic
+//!   sent from the node itself is redirected too.
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use async_trait::async_trait;
+use fancy_regex::Regex;
+use mirrord_protocol::Port;
+use tokio::process::Command;
+use tracing::warn;
+
+use crate::{
+    error::{AgentError, Result},
+    steal::ip_tables::{
+        chain::IPTableChain, flush_connections::MARK, redirect::Redirect, IPTables,
+        IPTABLE_NODE_PORT,
+    },
+};
+
+const KUBE_SERVICES_CHAIN: &str = "KUBE-SERVICES";
+const KUBE_NODEPORTS_CHAIN: &str = "KUBE-NODEPORTS";
+
+/// ipset of the cluster IPs, only used by kube-proxy in IPVS mode.
+const KUBE_CLUSTER_IP_SET: &str = "KUBE-CLUSTER-IP";
+
+/// How many chains we follow from a service to its endpoints, kube-proxy uses up to 4
+/// (`KUBE-FW` -> `KUBE-EXT` -> `KUBE-SVC` -> `KUBE-SEP`).
+const MAX_CHAIN_DEPTH: usize = 4;
+
+static DPORT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"--dport (\d+)").unwrap());
+
+static DESTINATION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"-d ([\d.]+)(?:/32)? ").unwrap());
+
+static JUMP_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"-j (KUBE-(?!MARK-)[\w-]+)").unwrap());
+
+static DNAT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"-j DNAT .*--to-destination ([\d.]+):(\d+)").unwrap());
+
+fn capture<'a>(regex: &Regex, rule: &'a str, group: usize) -> Option<&'a str> {
+    regex
+        .captures(rule)
+        .ok()
+        .flatten()
+        .and_then(|captures| captures.get(group))
+        .map(|m| m.as_str())
+}
+
+/// A port through which a service is reachable from outside of the cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExternalPort {
+    /// Load balancer ingress IP, [`None`] for node ports which are open on every node address.
+    destination: Option<String>,
+    port: Port,
+}
+
+impl ExternalPort {
+    fn redirect_rule(&self, target_port: Port) -> String {
+        let destination = self
+            .destination
+            .as_ref()
+            .map(|destination| format!("-d {destination} "))
+            .unwrap_or_default();
+
+        format!(
+            "{destination}-m tcp -p tcp --dport {} -j REDIRECT --to-ports {target_port}",
+            self.port
+        )
+    }
+}
+
+/// How kube-proxy implements the services in this network namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KubeProxyMode {
+    Iptables,
+    Ipvs,
+}
+
+/// Whether following `chain` can DNAT the traffic to one of `local_addresses` on `port`.
+fn reaches_local_endpoint<IPT: IPTables>(
+    ipt: &IPT,
+    chain: &str,
+    port: Port,
+    local_addresses: &[IpAddr],
+    depth: usize,
+) -> Result<bool> {
+    for rule in ipt.list_rules(chain)? {
+        if let Some(destination) = capture(&DNAT_REGEX, &rule, 1) {
+            let local = destination
+                .parse::<IpAddr>()
+                .is_ok_and(|destination| local_addresses.contains(&destination));
+            let same_port = capture(&DNAT_REGEX, &rule, 2) == Some(port.to_string().as_str());
+
+            if local && same_port {
+                return Ok(true);
+            }
+        } else if let Some(next) = capture(&JUMP_REGEX, &rule, 1)
+            && depth > 0
+            && reaches_local_endpoint(ipt, next, port, local_addresses, depth - 1)?
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Looks for the node ports and load balancer ports that kube-proxy DNATs to one of
+/// `local_addresses` on `port`.
+fn iptables_external_ports<IPT: IPTables>(
+    ipt: &IPT,
+    port: Port,
+    local_addresses: &[IpAddr],
+) -> Result<Vec<ExternalPort>> {
+    let mut external_ports = vec![];
+
+    // Node ports are in their own chain, load balancer ingress IPs are matched directly in
+    // `KUBE-SERVICES` and jump to the firewall (`KUBE-FW`) or external (`KUBE-EXT`) chains.
+    let candidates = ipt
+        .list_rules(KUBE_NODEPORTS_CHAIN)?
+        .into_iter()
+        .map(|rule| (rule, false))
+        .chain(
+            ipt.list_rules(KUBE_SERVICES_CHAIN)?
+                .into_iter()
+                .filter(|rule| rule.contains("-j KUBE-FW-") || rule.contains("-j KUBE-EXT-"))
+                .map(|rule| (rule, true)),
+        );
+
+    for (rule, load_balancer) in candidates {
+        let Some(external_port) = capture(&DPORT_REGEX, &rule, 1).and_then(|p| p.parse().ok())
+        else {
+            continue;
+        };
+        let Some(chain) = capture(&JUMP_REGEX, &rule, 1) else {
+            continue;
+        };
+        let destination = load_balancer
+            .then(|| capture(&DESTINATION_REGEX, &rule, 1).map(ToString::to_string))
+            .flatten();
+
+        if load_balancer && destination.is_none() {
+            continue;
+        }
+
+        if reaches_local_endpoint(ipt, chain, port, local_addresses, MAX_CHAIN_DEPTH)? {
+            external_ports.push(ExternalPort {
+                destination,
+                port: external_port,
+            });
+        }
+    }
+
+    Ok(external_ports)
+}
+
+/// Looks for the IPVS virtual servers that have a real server on one of `local_addresses` with
+/// `port`, in the output of `ipvsadm --save --numeric`:
+///
+/// ```text
+/// -A -t 10.0.0.5:30080 -s rr
+/// -a -t 10.0.0.5:30080 -r 10.0.0.5:8080 -m -w 1
+/// ```
+fn parse_ipvs_external_ports(
+    ipvsadm: &str,
+    port: Port,
+    local_addresses: &[IpAddr],
+) -> Vec<ExternalPort> {
+    let mut external_ports = vec![];
+
+    for line in ipvsadm.lines() {
+        let mut args = line.split_whitespace();
+
+        let (Some("-a"), Some("-t"), Some(virtual_server), Some("-r"), Some(real_server)) = (
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+        ) else {
+            continue;
+        };
+
+        let (Ok(virtual_server), Ok(real_server)) = (
+            virtual_server.parse::<SocketAddr>(),
+            real_server.parse::<SocketAddr>(),
+        ) else {
+            continue;
+        };
+
+        let external_port = ExternalPort {
+            destination: Some(virtual_server.ip().to_string()),
+            port: virtual_server.port(),
+        };
+
+        if real_server.port() == port
+            && local_addresses.contains(&real_server.ip())
+            && !external_ports.contains(&external_port)
+        {
+            external_ports.push(external_port);
+        }
+    }
+
+    external_ports
+}
+
+/// Reads the IPVS virtual servers with `ipvsadm`, see [`parse_ipvs_external_ports`].
+async fn ipvs_external_ports(port: Port, local_addresses: &[IpAddr]) -> Result<Vec<ExternalPort>> {
+    let ipvsadm = Command::new("ipvsadm")
+        .args(["--save", "--numeric"])
+        .output()
+        .await
+        .map_err(|error| AgentError::IpvsadmError(error.to_string()))?;
+
+    if !ipvsadm.status.success() {
+        return Err(AgentError::IpvsadmError(
+            String::from_utf8_lossy(&ipvsadm.stderr).into_owned(),
+        ));
+    }
+
+    Ok(parse_ipvs_external_ports(
+        &String::from_utf8_lossy(&ipvsadm.stdout),
+        port,
+        local_addresses,
+    ))
+}
+
+/// Addresses of this network namespace, the endpoints of a `hostNetwork` target.
+fn local_addresses() -> Vec<IpAddr> {
+    pnet::datalink::interfaces()
+        .into_iter()
+        .flat_map(|interface| interface.ips)
+        .map(|network| network.ip())
+        .collect()
+}
+
+pub(crate) struct NodePortRedirect<IPT: IPTables, T> {
+    managed: IPTableChain<IPT>,
+    inner: Box<T>,
+    mode: KubeProxyMode,
+    /// Mark the existing connections to the external ports, so that
+    /// [`FlushConnections`](super::flush_connections::FlushConnections) rejects them.
+    flush_connections: bool,
+    local_addresses: Vec<IpAddr>,
+    /// Rules we added to `managed` for each redirected port.
+    rules: Mutex<HashMap<Port, Vec<String>>>,
+}
+
+impl<IPT, T> NodePortRedirect<IPT, T>
+where
+    IPT: IPTables,
+    T: Redirect,
+{
+    const ENTRYPOINT: &'static str = "PREROUTING";
+
+    /// Returns the [`KubeProxyMode`] when kube-proxy manages the services in this network
+    /// namespace.
+    pub fn detect(ipt: &IPT) -> Result<Option<KubeProxyMode>> {
+        let kube_proxy = ipt
+            .list_rules(Self::ENTRYPOINT)?
+            .iter()
+            .any(|rule| rule.contains(&format!("-j {KUBE_SERVICES_CHAIN}")));
+
+        if !kube_proxy {
+            return Ok(None);
+        }
+
+        let ipvs = ipt
+            .list_rules(KUBE_SERVICES_CHAIN)?
+            .iter()
+            .any(|rule| rule.contains(&format!("--match-set {KUBE_CLUSTER_IP_SET} ")));
+
+        Ok(Some(if ipvs {
+            KubeProxyMode::Ipvs
+        } else {
+            KubeProxyMode::Iptables
+        }))
+    }
+
+    #[tracing::instrument(level = "trace", skip(ipt, inner))]
+    pub fn create(
+        ipt: Arc<IPT>,
+        inner: Box<T>,
+        mode: KubeProxyMode,
+        flush_connections: bool,
+    ) -> Result<Self> {
+        let managed = IPTableChain::create(ipt, IPTABLE_NODE_PORT.to_string())?;
+
+        Ok(Self::new(
+            managed,
+            inner,
+            mode,
+            flush_connections,
+            local_addresses(),
+        ))
+    }
+
+    #[tracing::instrument(level = "trace", skip(ipt, inner))]
+    pub fn load(
+        ipt: Arc<IPT>,
+        inner: Box<T>,
+        mode: KubeProxyMode,
+        flush_connections: bool,
+    ) -> Result<Self> {
+        let managed = IPTableChain::load(ipt, IPTABLE_NODE_PORT.to_string())?;
+
+        Ok(Self::new(
+            managed,
+            inner,
+            mode,
+            flush_connections,
+            local_addresses(),
+        ))
+    }
+
+    fn new(
+        managed: IPTableChain<IPT>,
+        inner: Box<T>,
+        mode: KubeProxyMode,
+        flush_connections: bool,
+        local_addresses: Vec<IpAddr>,
+    ) -> Self {
+        NodePortRedirect {
+            managed,
+            inner,
+            mode,
+            flush_connections,
+            local_addresses,
+            rules: Default::default(),
+        }
+    }
+
+    /// Marks the existing connections to `port`, see [`FlushConnections`] for why.
+    ///
+    /// [`FlushConnections`]: super::flush_connections::FlushConnections
+    async fn flush(port: Port) -> Result<()> {
+        // `--orig-port-dst`, as `--dport` matches the port after kube-proxy's DNAT.
+        let conntrack = Command::new("conntrack")
+            .args([
+                "-U",
+                "-p",
+                "tcp",
+                "--orig-port-dst",
+                &port.to_string(),
+                "-m",
+                MARK,
+            ])
+            .output()
+            .await?;
+
+        if !conntrack.status.success() && conntrack.status.code() != Some(256) {
+            warn!("`conntrack` output is {conntrack:#?}");
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<IPT, T> Redirect for NodePortRedirect<IPT, T>
+where
+    IPT: IPTables + Send + Sync,
+    T: Redirect + Send + Sync,
+{
+    #[tracing::instrument(level = "trace", skip(self), ret)]
+    async fn mount_entrypoint(&self) -> Result<()> {
+        self.inner.mount_entrypoint().await?;
+
+        // Before kube-proxy's jump to `KUBE-SERVICES`.
+        self.managed.inner().insert_rule(
+            Self::ENTRYPOINT,
+            &format!("-j {}", self.managed.chain_name()),
+            1,
+        )?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), ret)]
+    async fn unmount_entrypoint(&self) -> Result<()> {
+        self.inner.unmount_entrypoint().await?;
+
+        self.managed.inner().remove_rule(
+            Self::ENTRYPOINT,
+            &format!("-j {}", self.managed.chain_name()),
+        )?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), ret)]
+    async fn add_redirect(&self, redirected_port: Port, target_port: Port) -> Result<()> {
+        self.inner
+            .add_redirect(redirected_port, target_port)
+            .await?;
+
+        // Not being able to steal from outside of the cluster shouldn't fail the whole steal.
+        let external_ports = match self.mode {
+            KubeProxyMode::Iptables => iptables_external_ports(
+                self.managed.inner(),
+                redirected_port,
+                &self.local_addresses,
+            ),
+            KubeProxyMode::Ipvs => {
+                ipvs_external_ports(redirected_port, &self.local_addresses).await
+            }
+        };
+        let external_ports = match external_ports {
+            Ok(external_ports) => external_ports,
+            Err(error) => {
+                warn!(%error, redirected_port, "Failed to look up the external ports");
+                return Ok(());
+            }
+        };
+
+        let mut rules = vec![];
+        for external_port in external_ports {
+            let rule = external_port.redirect_rule(target_port);
+            self.managed.add_rule(&rule)?;
+            rules.push(rule);
+
+            if self.flush_connections {
+                Self::flush(external_port.port).await?;
+            }
+        }
+
+        self.rules
+            .lock()
+            .expect("node port rules lock poisoned")
+            .insert(redirected_port, rules);
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), ret)]
+    async fn remove_redirect(&self, redirected_port: Port, target_port: Port) -> Result<()> {
+        self.inner
+            .remove_redirect(redirected_port, target_port)
+            .await?;
+
+        let rules = self
+            .rules
+            .lock()
+            .expect("node port rules lock poisoned")
+            .remove(&redirected_port)
+            .unwrap_or_default();
+
+        for rule in rules {
+            self.managed.remove_rule(&rule)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::predicate::*;
+
+    use super::*;
+    use crate::steal::ip_tables::{
+        prerouting::PreroutingRedirect, MockIPTables, IPTABLE_PREROUTING,
+    };
+
+    const NODE_IP: &str = "10.0.0.5";
+
+    /// Rules of kube-proxy in iptables mode, for a `NodePort` service on 30080 and a
+    /// `LoadBalancer` service on 203.0.113.7:443, both with an endpoint on this node.
+    fn iptables_mode(mock: &mut MockIPTables) {
+        mock.expect_list_rules()
+            .with(eq(KUBE_NODEPORTS_CHAIN))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-NODEPORTS".to_owned(),
+                    "-A KUBE-NODEPORTS -p tcp -m comment --comment \"default/app:http\" -m tcp --dport 30080 -j KUBE-EXT-APP".to_owned(),
+                ])
+            });
+
+        mock.expect_list_rules()
+            .with(eq(KUBE_SERVICES_CHAIN))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-SERVICES".to_owned(),
+                    "-A KUBE-SERVICES -d 10.96.0.10/32 -p tcp -m comment --comment \"default/app:http cluster IP\" -m tcp --dport 80 -j KUBE-SVC-APP".to_owned(),
+                    "-A KUBE-SERVICES -d 203.0.113.7/32 -p tcp -m comment --comment \"default/lb:https loadbalancer IP\" -m tcp --dport 443 -j KUBE-EXT-LB".to_owned(),
+                    "-A KUBE-SERVICES -m comment --comment \"kubernetes service nodeports\" -m addrtype --dst-type LOCAL -j KUBE-NODEPORTS".to_owned(),
+                ])
+            });
+
+        mock.expect_list_rules()
+            .with(eq("KUBE-EXT-APP"))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-EXT-APP".to_owned(),
+                    "-A KUBE-EXT-APP -m comment --comment \"masquerade traffic for default/app:http external destinations\" -j KUBE-MARK-MASQ".to_owned(),
+                    "-A KUBE-EXT-APP -j KUBE-SVC-APP".to_owned(),
+                ])
+            });
+
+        mock.expect_list_rules()
+            .with(eq("KUBE-SVC-APP"))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-SVC-APP".to_owned(),
+                    "-A KUBE-SVC-APP -m comment --comment \"default/app:http -> 10.0.0.5:8080\" -m statistic --mode random --probability 0.50000000000 -j KUBE-SEP-APP1".to_owned(),
+                    "-A KUBE-SVC-APP -m comment --comment \"default/app:http -> 10.0.0.6:8080\" -j KUBE-SEP-APP2".to_owned(),
+                ])
+            });
+
+        mock.expect_list_rules()
+            .with(eq("KUBE-SEP-APP1"))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-SEP-APP1".to_owned(),
+                    "-A KUBE-SEP-APP1 -s 10.0.0.5/32 -m comment --comment \"default/app:http\" -j KUBE-MARK-MASQ".to_owned(),
+                    "-A KUBE-SEP-APP1 -p tcp -m comment --comment \"default/app:http\" -m tcp -j DNAT --to-destination 10.0.0.5:8080".to_owned(),
+                ])
+            });
+
+        mock.expect_list_rules()
+            .with(eq("KUBE-SEP-APP2"))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-SEP-APP2".to_owned(),
+                    "-A KUBE-SEP-APP2 -p tcp -m comment --comment \"default/app:http\" -m tcp -j DNAT --to-destination 10.0.0.6:8080".to_owned(),
+                ])
+            });
+
+        mock.expect_list_rules()
+            .with(eq("KUBE-EXT-LB"))
+            .returning(|_| Ok(vec!["-A KUBE-EXT-LB -j KUBE-SVC-LB".to_owned()]));
+
+        mock.expect_list_rules()
+            .with(eq("KUBE-SVC-LB"))
+            .returning(|_| Ok(vec!["-A KUBE-SVC-LB -j KUBE-SEP-LB".to_owned()]));
+
+        mock.expect_list_rules()
+            .with(eq("KUBE-SEP-LB"))
+            .returning(|_| {
+                Ok(vec![
+                    "-A KUBE-SEP-LB -p tcp -m tcp -j DNAT --to-destination 10.0.0.5:8443"
+                        .to_owned(),
+                ])
+            });
+    }
+
+    #[test]
+    fn iptables_mode_external_ports() {
+        let mut mock = MockIPTables::new();
+        iptables_mode(&mut mock);
+        let local = [NODE_IP.parse().unwrap()];
+
+        assert_eq!(
+            iptables_external_ports(&mock, 8080, &local).unwrap(),
+            vec![ExternalPort {
+                destination: None,
+                port: 30080
+            }]
+        );
+        assert_eq!(
+            iptables_external_ports(&mock, 8443, &local).unwrap(),
+            vec![ExternalPort {
+                destination: Some("203.0.113.7".to_owned()),
+                port: 443
+            }]
+        );
+        assert!(iptables_external_ports(&mock, 9999, &local)
+            .unwrap()
+            .is_empty());
+        assert!(
+            iptables_external_ports(&mock, 8080, &["10.0.0.7".parse().unwrap()])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    /// Rules of kube-proxy in IPVS mode, where the services are matched with ipsets.
+    fn ipvs_mode(mock: &mut MockIPTables) {
+        mock.expect_list_rules()
+            .with(eq(KUBE_NODEPORTS_CHAIN))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-NODEPORTS".to_owned(),
+                    "-A KUBE-NODEPORTS -p tcp -m comment --comment \"Kubernetes nodeport TCP port for masquerade purpose\" -m set --match-set KUBE-NODE-PORT-TCP dst -j KUBE-MARK-MASQ".to_owned(),
+                ])
+            });
+
+        mock.expect_list_rules()
+            .with(eq(KUBE_SERVICES_CHAIN))
+            .returning(|_| {
+                Ok(vec![
+                    "-N KUBE-SERVICES".to_owned(),
+                    "-A KUBE-SERVICES -m comment --comment \"Kubernetes service cluster ip + port for masquerade purpose\" -m set --match-set KUBE-CLUSTER-IP src,dst -j KUBE-MARK-MASQ".to_owned(),
+                    "-A KUBE-SERVICES -m addrtype --dst-type LOCAL -j KUBE-NODE-PORT".to_owned(),
+                ])
+            });
+    }
+
+    fn kube_proxy_entrypoint(mock: &mut MockIPTables) {
+        mock.expect_list_rules().with(eq("PREROUTING")).returning(|_| {
+            Ok(vec![
+                "-P PREROUTING ACCEPT".to_owned(),
+                "-A PREROUTING -m comment --comment \"kubernetes service portals\" -j KUBE-SERVICES".to_owned(),
+            ])
+        });
+    }
+
+    #[test]
+    fn detect_mode() {
+        let mut mock = MockIPTables::new();
+        kube_proxy_entrypoint(&mut mock);
+        iptables_mode(&mut mock);
+        assert_eq!(
+            NodePortRedirect::<_, PreroutingRedirect<_>>::detect(&mock).unwrap(),
+            Some(KubeProxyMode::Iptables)
+        );
+
+        let mut mock = MockIPTables::new();
+        kube_proxy_entrypoint(&mut mock);
+        ipvs_mode(&mut mock);
+        assert_eq!(
+            NodePortRedirect::<_, PreroutingRedirect<_>>::detect(&mock).unwrap(),
+            Some(KubeProxyMode::Ipvs)
+        );
+
+        let mut mock = MockIPTables::new();
+        mock.expect_list_rules()
+            .with(eq("PREROUTING"))
+            .returning(|_| Ok(vec!["-P PREROUTING ACCEPT".to_owned()]));
+        assert_eq!(
+            NodePortRedirect::<_, PreroutingRedirect<_>>::detect(&mock).unwrap(),
+            None
+        );
+    }
+
+    /// In IPVS mode kube-proxy doesn't DNAT in iptables, so there is nothing to follow there.
+    #[test]
+    fn ipvs_mode_iptables_external_ports() {
+        let mut mock = MockIPTables::new();
+        ipvs_mode(&mut mock);
+        let local = [NODE_IP.parse().unwrap()];
+
+        assert!(iptables_external_ports(&mock, 8080, &local)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn ipvs_mode_external_ports() {
+        let ipvsadm = "\
+-A -t 10.96.0.10:80 -s rr
+-a -t 10.96.0.10:80 -r 10.0.0.5:8080 -m -w 1
+-a -t 10.96.0.10:80 -r 10.0.0.6:8080 -m -w 1
+-A -t 10.0.0.5:30080 -s rr
+-a -t 10.0.0.5:30080 -r 10.0.0.5:8080 -m -w 1
+-a -t 10.0.0.5:30080 -r 10.0.0.6:8080 -m -w 1
+-A -t 10.96.0.1:443 -s rr
+-a -t 10.96.0.1:443 -r 172.18.0.2:6443 -m -w 1
+-A -u 10.96.0.53:53 -s rr
+-a -u 10.96.0.53:53 -r 10.0.0.5:8080 -m -w 1
+";
+        let local = [NODE_IP.parse().unwrap()];
+
+        assert_eq!(
+            parse_ipvs_external_ports(ipvsadm, 8080, &local),
+            vec![
+                ExternalPort {
+                    destination: Some("10.96.0.10".to_owned()),
+                    port: 80
+                },
+                ExternalPort {
+                    destination: Some("10.0.0.5".to_owned()),
+                    port: 30080
+                },
+            ]
+        );
+        assert!(parse_ipvs_external_ports(ipvsadm, 6443, &local).is_empty());
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_redirect() {
+        let mut mock = MockIPTables::new();
+        iptables_mode(&mut mock);
+
+        mock.expect_create_chain()
+            .with(eq(IPTABLE_PREROUTING.as_str()))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        mock.expect_create_chain()
+            .with(eq(IPTABLE_NODE_PORT.as_str()))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        mock.expect_insert_rule()
+            .with(
+                eq(IPTABLE_PREROUTING.as_str()),
+                eq("-m tcp -p tcp --dport 8080 -j REDIRECT --to-ports 420"),
+                eq(1),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        mock.expect_insert_rule()
+            .with(
+                eq(IPTABLE_NODE_PORT.as_str()),
+                eq("-m tcp -p tcp --dport 30080 -j REDIRECT --to-ports 420"),
+                eq(1),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        mock.expect_remove_rule()
+            .with(
+                eq(IPTABLE_PREROUTING.as_str()),
+                eq("-m tcp -p tcp --dport 8080 -j REDIRECT --to-ports 420"),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        mock.expect_remove_rule()
+            .with(
+                eq(IPTABLE_NODE_PORT.as_str()),
+                eq("-m tcp -p tcp --dport 30080 -j REDIRECT --to-ports 420"),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        mock.expect_remove_chain().times(2).returning(|_| Ok(()));
+
+        let ipt = Arc::new(mock);
+        let inner = PreroutingRedirect::create(ipt.clone()).expect("Unable to create");
+        let managed =
+            IPTableChain::create(ipt, IPTABLE_NODE_PORT.to_string()).expect("Unable to create");
+        let node_port = NodePortRedirect::new(
+            managed,
+            Box::new(inner),
+            KubeProxyMode::Iptables,
+            false,
+            vec![NODE_IP.parse().unwrap()],
+        );
+
+        assert!(node_port.add_redirect(8080, 420).await.is_ok());
+        assert!(node_port.remove_redirect(8080, 420).await.is_ok());
+    }
+}
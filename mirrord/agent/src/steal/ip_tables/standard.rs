@@ -6,7 +6,7 @@ use mirrord_protocol::Port;
 use crate::{
     error::Result,
     steal::ip_tables::{
-        output::OutputRedirect, prerouting::PreroutingRedirect, IPTables, Redirect,
+        output::OutputRedirect, prerouting::PreroutingRedirect, IPTables, LoopbackSteal, Redirect,
         IPTABLE_STANDARD,
     },
 };
@@ -14,19 +14,21 @@ use crate::{
 pub(crate) struct StandardRedirect<IPT: IPTables> {
     prerouteing: PreroutingRedirect<IPT>,
     output: OutputRedirect<IPT>,
+    loopback: LoopbackSteal,
 }
 
 impl<IPT> StandardRedirect<IPT>
 where
     IPT: IPTables,
 {
-    pub fn create(ipt: Arc<IPT>) -> Result<Self> {
+    pub fn create(ipt: Arc<IPT>, loopback: LoopbackSteal) -> Result<Self> {
         let prerouteing = PreroutingRedirect::create(ipt.clone())?;
         let output = OutputRedirect::create(ipt, IPTABLE_STANDARD.to_string())?;
 
         Ok(StandardRedirect {
             prerouteing,
             output,
+            loopback,
         })
     }
 
@@ -37,6 +39,7 @@ where
         Ok(StandardRedirect {
             prerouteing,
             output,
+            loopback: LoopbackSteal::default(),
         })
     }
 }
@@ -63,23 +66,31 @@ where
     }
 
     async fn add_redirect(&self, redirected_port: Port, target_port: Port) -> Result<()> {
-        self.prerouteing
-            .add_redirect(redirected_port, target_port)
-            .await?;
-        self.output
-            .add_redirect(redirected_port, target_port)
-            .await?;
+        if self.loopback.external() {
+            self.prerouteing
+                .add_redirect(redirected_port, target_port)
+                .await?;
+        }
+        if self.loopback.loopback() {
+            self.output
+                .add_redirect(redirected_port, target_port)
+                .await?;
+        }
 
         Ok(())
     }
 
     async fn remove_redirect(&self, redirected_port: Port, target_port: Port) -> Result<()> {
-        self.prerouteing
-            .remove_redirect(redirected_port, target_port)
-            .await?;
-        self.output
-            .remove_redirect(redirected_port, target_port)
-            .await?;
+        if self.loopback.external() {
+            self.prerouteing
+                .remove_redirect(redirected_port, target_port)
+                .await?;
+        }
+        if self.loopback.loopback() {
+            self.output
+                .remove_redirect(redirected_port, target_port)
+                .await?;
+        }
 
         Ok(())
     }
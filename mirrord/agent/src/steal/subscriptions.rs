@@ -10,7 +10,7 @@ use tokio::net::{TcpListener, TcpStream};
 
 use super::{
     http::HttpFilter,
-    ip_tables::{new_iptables, IPTablesWrapper, SafeIpTables},
+    ip_tables::{new_iptables, IPTablesWrapper, LoopbackSteal, SafeIpTables},
 };
 use crate::{error::AgentError, util::ClientId};
 
@@ -54,6 +54,8 @@ pub(crate) struct IpTablesRedirector {
     iptables: Option<SafeIpTables<IPTablesWrapper>>,
     /// Whether exisiting connections should be flushed when adding new redirects.
     flush_connections: bool,
+    /// Which connections to the redirected ports are redirected.
+    loopback: LoopbackSteal,
     /// Port of [`IpTablesRedirector::listener`].
     redirect_to: Port,
     /// Listener to which redirect all connections.
@@ -73,13 +75,19 @@ impl IpTablesRedirector {
     ///
     /// * `flush_connections` - whether exisitng connections should be flushed when adding new
     ///   redirects
-    pub(crate) async fn new(flush_connections: bool) -> Result<Self, AgentError> {
+    /// * `loopback` - whether connections from outside of the pod, loopback connections or both are
+    ///   redirected
+    pub(crate) async fn new(
+        flush_connections: bool,
+        loopback: LoopbackSteal,
+    ) -> Result<Self, AgentError> {
         let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
         let redirect_to = listener.local_addr()?.port();
 
         Ok(Self {
             iptables: None,
             flush_connections,
+            loopback,
             redirect_to,
             listener,
         })
@@ -95,7 +103,9 @@ impl PortRedirector for IpTablesRedirector {
             Some(iptables) => iptables,
             None => {
                 let iptables = new_iptables();
-                let safe = SafeIpTables::create(iptables.into(), self.flush_connections).await?;
+                let safe =
+                    SafeIpTables::create(iptables.into(), self.flush_connections, self.loopback)
+                        .await?;
                 self.iptables.insert(safe)
             }
         };
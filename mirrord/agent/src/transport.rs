@@ -0,0 +1,120 @@
+//! Transports on which the agent accepts its clients, see [`Args::transport`].
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+};
+
+use mirrord_protocol::AGENT_QUIC_ALPN;
+use quinn::{
+    crypto::rustls::{NoInitialCipherSuite, QuicServerConfig},
+    Endpoint, Incoming, ServerConfig,
+};
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls;
+
+use crate::{
+    cli::{Args, Transport},
+    error::Result,
+};
+
+/// Errors that can occur when preparing the QUIC endpoint of the agent.
+#[derive(Debug, Error)]
+pub(crate) enum QuicSetupError {
+    /// The agent was started with [`Transport::Quic`], but without the certificate or its key.
+    #[error("QUIC transport requires both the certificate and its private key")]
+    MissingCertificate,
+    /// We failed to parse the PEM.
+    #[error("failed to parse PEM: {0}")]
+    Pem(#[from] io::Error),
+    /// The key PEM did not contain any private key.
+    #[error("no private key found in the key PEM")]
+    NoPrivateKey,
+    /// The certificate or the key was rejected.
+    #[error("rustls failed: {0}")]
+    Rustls(#[from] rustls::Error),
+    /// The crypto provider can't be used for QUIC.
+    #[error("{0}")]
+    NoInitialCipherSuite(#[from] NoInitialCipherSuite),
+}
+
+/// Accepts the connections of the clients, bound to [`Args::communicate_port`].
+pub(crate) enum AgentListener {
+    Tcp(TcpListener),
+    Quic(Endpoint),
+}
+
+/// Connection of a client accepted with [`AgentListener::accept`], not yet wrapped in a
+/// [`ClientConnection`](crate::client_connection::ClientConnection).
+pub(crate) enum ClientStream {
+    Tcp(TcpStream),
+    /// The handshake is made in
+    /// [`ClientConnection::new`](crate::client_connection::ClientConnection::new), so that a slow
+    /// client doesn't hold up the others.
+    Quic(Incoming),
+}
+
+impl From<TcpStream> for ClientStream {
+    fn from(stream: TcpStream) -> Self {
+        Self::Tcp(stream)
+    }
+}
+
+impl AgentListener {
+    /// Binds the listener of the [`Args::transport`].
+    pub(crate) async fn bind(args: &Args) -> Result<Self> {
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, args.communicate_port);
+
+        match args.transport {
+            Transport::Tcp => Ok(Self::Tcp(TcpListener::bind(addr).await?)),
+            Transport::Quic => {
+                let (Some(cert_pem), Some(key_pem)) = (&args.quic_cert_pem, &args.quic_key_pem)
+                else {
+                    return Err(QuicSetupError::MissingCertificate.into());
+                };
+
+                let config = server_config(cert_pem, key_pem)?;
+                Ok(Self::Quic(Endpoint::server(config, addr.into())?))
+            }
+        }
+    }
+
+    /// Accepts the next client.
+    ///
+    /// Cancel safe.
+    pub(crate) async fn accept(&self) -> io::Result<(ClientStream, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((ClientStream::Tcp(stream), addr))
+            }
+            Self::Quic(endpoint) => {
+                let incoming = endpoint.accept().await.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotConnected, "QUIC endpoint was closed")
+                })?;
+                let addr = incoming.remote_address();
+
+                Ok((ClientStream::Quic(incoming), addr))
+            }
+        }
+    }
+}
+
+/// Builds the [`ServerConfig`] of the QUIC endpoint from the PEM-encoded certificate chain and
+/// private key.
+pub(crate) fn server_config(cert_pem: &str, key_pem: &str) -> Result<ServerConfig, QuicSetupError> {
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut key_pem.as_bytes())?
+        .ok_or(QuicSetupError::NoPrivateKey)?;
+
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    crypto.alpn_protocols = vec![AGENT_QUIC_ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(crypto)?;
+
+    Ok(ServerConfig::with_crypto(Arc::new(crypto)))
+}
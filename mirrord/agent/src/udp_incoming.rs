@@ -0,0 +1,527 @@
+//! Mirroring and stealing of the UDP datagrams incoming to the target, requested with
+//! [`ClientMessage::Udp`](mirrord_protocol::ClientMessage::Udp) and
+//! [`ClientMessage::UdpSteal`](mirrord_protocol::ClientMessage::UdpSteal).
+//!
+//! Every client gets its own [`UdpIncomingApi`] task, running in the target's network namespace:
+//!
+//! - mirrored datagrams are copied from a [`PacketSocket`], which sees the IPv4 traffic of all the
+//!   interfaces;
+//! - stolen datagrams are redirected with an iptables `REDIRECT` rule ([`UdpRedirect`]) to a socket
+//!   of the task. The application's replies are sent from the same socket, and conntrack makes them
+//!   come from the stolen port.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io, mem,
+    net::{Ipv4Addr, SocketAddr},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    sync::{Arc, LazyLock, Mutex},
+    thread,
+};
+
+use futures::StreamExt;
+use mirrord_protocol::{
+    udp::{DaemonUdp, IncomingDatagram, LayerUdp, LayerUdpSteal, OutgoingDatagram},
+    DaemonMessage, Port, RemoteResult, ResponseError,
+};
+use pnet::packet::{
+    ip::IpNextHeaderProtocols,
+    ipv4::{Ipv4Flags, Ipv4Packet},
+    udp::UdpPacket,
+    Packet,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use streammap_ext::StreamMap;
+use tokio::{
+    io::unix::AsyncFd,
+    net::UdpSocket,
+    select,
+    sync::mpsc::{self, Receiver, Sender},
+};
+use tokio_util::{codec::BytesCodec, udp::UdpFramed};
+use tracing::{trace, warn};
+
+use crate::{
+    error::Result,
+    steal::ip_tables::{chain::IPTableChain, new_iptables, IPTables, IPTablesWrapper},
+    util::run_thread_in_namespace,
+    watched_task::{TaskStatus, WatchedTask},
+};
+
+/// Ports stolen by the clients of this agent. Datagrams of a port can be stolen by one client
+/// only, as there's no way to tell which client should get them.
+static STOLEN_PORTS: LazyLock<Mutex<HashSet<Port>>> = LazyLock::new(Default::default);
+
+/// Big enough for any IPv4 packet.
+const MAX_PACKET_SIZE: usize = u16::MAX as usize;
+
+/// Messages handled by the [`UdpIncomingApi`] task.
+#[derive(Debug)]
+enum UdpIncomingCommand {
+    Mirror(LayerUdp),
+    Steal(LayerUdpSteal),
+}
+
+/// Handles the [`LayerUdp`] and [`LayerUdpSteal`] messages of a single client, passing them to
+/// the `interceptor_task` thread.
+///
+/// Started when the client sends the first of them.
+pub(crate) struct UdpIncomingApi {
+    /// Holds the `interceptor_task`.
+    _task: thread::JoinHandle<()>,
+
+    /// Status of the `interceptor_task`.
+    task_status: TaskStatus,
+
+    /// Sends the client's messages to the `interceptor_task`.
+    layer_tx: Sender<UdpIncomingCommand>,
+
+    /// Reads the [`DaemonMessage::Udp`] and [`DaemonMessage::UdpSteal`] messages from the
+    /// `interceptor_task`.
+    daemon_rx: Receiver<DaemonMessage>,
+}
+
+impl UdpIncomingApi {
+    const TASK_NAME: &'static str = "UdpIncoming";
+
+    pub(crate) fn new(pid: Option<u64>) -> Self {
+        let (layer_tx, layer_rx) = mpsc::channel(1000);
+        let (daemon_tx, daemon_rx) = mpsc::channel(1000);
+
+        let watched_task =
+            WatchedTask::new(Self::TASK_NAME, Self::interceptor_task(layer_rx, daemon_tx));
+
+        let task_status = watched_task.status();
+        let task = run_thread_in_namespace(
+            watched_task.start(),
+            Self::TASK_NAME.to_string(),
+            pid,
+            "net",
+        );
+
+        Self {
+            _task: task,
+            task_status,
+            layer_tx,
+            daemon_rx,
+        }
+    }
+
+    /// The [`UdpIncomingApi`] task.
+    ///
+    /// Exits when the [`UdpIncomingApi`] is dropped, removing its iptables rules.
+    async fn interceptor_task(
+        mut layer_rx: Receiver<UdpIncomingCommand>,
+        daemon_tx: Sender<DaemonMessage>,
+    ) -> Result<()> {
+        let mut mirrored: HashSet<Port> = Default::default();
+        let mut capture: Option<PacketSocket> = None;
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+
+        let mut redirect: Option<UdpRedirect> = None;
+        let mut stolen: HashMap<Port, Arc<UdpSocket>> = Default::default();
+        let mut readers: StreamMap<Port, UdpFramed<BytesCodec, Arc<UdpSocket>>> =
+            Default::default();
+
+        let result = loop {
+            select! {
+                command = layer_rx.recv() => {
+                    let Some(command) = command else {
+                        break Ok(());
+                    };
+                    trace!(?command, "udp incoming: interceptor_task -> command");
+
+                    let response = match command {
+                        UdpIncomingCommand::Mirror(LayerUdp::PortSubscribe(port)) => {
+                            let result = if capture.is_some() {
+                                Ok(())
+                            } else {
+                                PacketSocket::new()
+                                    .map(|socket| {
+                                        capture.replace(socket);
+                                    })
+                                    .map_err(ResponseError::from)
+                            };
+
+                            if result.is_ok() {
+                                mirrored.insert(port);
+                            }
+
+                            Some(DaemonMessage::Udp(DaemonUdp::SubscribeResult { port, result }))
+                        }
+
+                        UdpIncomingCommand::Mirror(LayerUdp::PortUnsubscribe(port)) => {
+                            mirrored.remove(&port);
+                            if mirrored.is_empty() {
+                                capture = None;
+                            }
+
+                            None
+                        }
+
+                        UdpIncomingCommand::Steal(LayerUdpSteal::PortSubscribe(port)) => {
+                            let result = steal_port(port, &mut redirect).await.map(|socket| {
+                                let socket = Arc::new(socket);
+                                readers.insert(port, UdpFramed::new(socket.clone(), BytesCodec::new()));
+                                stolen.insert(port, socket);
+                            });
+
+                            Some(DaemonMessage::UdpSteal(DaemonUdp::SubscribeResult { port, result }))
+                        }
+
+                        UdpIncomingCommand::Steal(LayerUdpSteal::PortUnsubscribe(port)) => {
+                            readers.remove(&port);
+                            if let Some(socket) = stolen.remove(&port) {
+                                release_port(port, &socket, redirect.as_ref());
+                            }
+
+                            None
+                        }
+
+                        UdpIncomingCommand::Steal(LayerUdpSteal::Reply(OutgoingDatagram {
+                            port,
+                            destination,
+                            bytes,
+                        })) => {
+                            if let Some(socket) = stolen.get(&port)
+                                && let Err(error) = socket.send_to(&bytes, destination).await
+                            {
+                                warn!(%error, port, %destination, "Failed to send a reply to a stolen UDP datagram");
+                            }
+
+                            None
+                        }
+                    };
+
+                    if let Some(response) = response
+                        && daemon_tx.send(response).await.is_err()
+                    {
+                        break Ok(());
+                    }
+                }
+
+                // [remote] -> [agent] -> [layer]
+                // A copy of a datagram that arrived to the pod.
+                received = async {
+                    if let Some(ref capture) = capture {
+                        capture.next(&mut buffer).await
+                    } else {
+                        unreachable!()
+                    }
+                }, if capture.is_some() => {
+                    let length = match received {
+                        Ok(length) => length,
+                        Err(error) => break Err(error.into()),
+                    };
+
+                    let datagram = buffer
+                        .get(..length)
+                        .and_then(|packet| parse_datagram(packet, &mirrored));
+                    if let Some(datagram) = datagram
+                        && daemon_tx
+                            .send(DaemonMessage::Udp(DaemonUdp::Datagram(datagram)))
+                            .await
+                            .is_err()
+                    {
+                        break Ok(());
+                    }
+                }
+
+                // [remote] -> [agent] -> [layer]
+                // A datagram redirected to one of our sockets.
+                Some((port, received)) = readers.next() => match received {
+                    Some(Ok((bytes, source))) => {
+                        let datagram = IncomingDatagram {
+                            port,
+                            source,
+                            bytes: bytes.to_vec(),
+                        };
+
+                        if daemon_tx
+                            .send(DaemonMessage::UdpSteal(DaemonUdp::Datagram(datagram)))
+                            .await
+                            .is_err()
+                        {
+                            break Ok(());
+                        }
+                    }
+                    // Errors reported by ICMP, e.g. a peer that no longer listens for the replies.
+                    Some(Err(error)) => {
+                        trace!(%error, port, "udp incoming: interceptor_task -> read failed");
+                    }
+                    None => {}
+                },
+            }
+        };
+
+        for (port, socket) in stolen {
+            release_port(port, &socket, redirect.as_ref());
+        }
+
+        result
+    }
+
+    /// Sends a [`LayerUdp`] message to the `interceptor_task`.
+    pub(crate) async fn mirror_message(&mut self, message: LayerUdp) -> Result<()> {
+        self.send_command(UdpIncomingCommand::Mirror(message)).await
+    }
+
+    /// Sends a [`LayerUdpSteal`] message to the `interceptor_task`.
+    pub(crate) async fn steal_message(&mut self, message: LayerUdpSteal) -> Result<()> {
+        self.send_command(UdpIncomingCommand::Steal(message)).await
+    }
+
+    async fn send_command(&mut self, command: UdpIncomingCommand) -> Result<()> {
+        if self.layer_tx.send(command).await.is_ok() {
+            Ok(())
+        } else {
+            Err(self.task_status.unwrap_err().await)
+        }
+    }
+
+    /// Receives a [`DaemonMessage::Udp`] or [`DaemonMessage::UdpSteal`] from the
+    /// `interceptor_task`.
+    pub(crate) async fn daemon_message(&mut self) -> Result<DaemonMessage> {
+        match self.daemon_rx.recv().await {
+            Some(msg) => Ok(msg),
+            None => Err(self.task_status.unwrap_err().await),
+        }
+    }
+}
+
+/// Binds the socket that receives the datagrams of the stolen `port`, and redirects them to it.
+async fn steal_port(port: Port, redirect: &mut Option<UdpRedirect>) -> RemoteResult<UdpSocket> {
+    let newly_stolen = STOLEN_PORTS
+        .lock()
+        .expect("stolen UDP ports lock poisoned")
+        .insert(port);
+    if !newly_stolen {
+        return Err(ResponseError::PortAlreadyStolen(port));
+    }
+
+    let result = async {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+
+        if redirect.is_none() {
+            redirect.replace(UdpRedirect::create()?);
+        }
+        if let Some(redirect) = redirect {
+            redirect.add(port, socket.local_addr()?.port())?;
+        }
+
+        Ok::<_, io::Error>(socket)
+    }
+    .await;
+
+    if result.is_err() {
+        STOLEN_PORTS
+            .lock()
+            .expect("stolen UDP ports lock poisoned")
+            .remove(&port);
+    }
+
+    result.map_err(ResponseError::from)
+}
+
+/// Stops redirecting the datagrams of the stolen `port`.
+fn release_port(port: Port, socket: &UdpSocket, redirect: Option<&UdpRedirect>) {
+    if let (Some(redirect), Ok(local_address)) = (redirect, socket.local_addr())
+        && let Err(error) = redirect.remove(port, local_address.port())
+    {
+        warn!(%error, port, "Failed to remove the UDP redirect rule");
+    }
+
+    STOLEN_PORTS
+        .lock()
+        .expect("stolen UDP ports lock poisoned")
+        .remove(&port);
+}
+
+/// Returns the datagram carried by the given IPv4 `packet`, if it's sent to one of the `ports`.
+///
+/// Fragmented datagrams are skipped.
+fn parse_datagram(packet: &[u8], ports: &HashSet<Port>) -> Option<IncomingDatagram> {
+    let ip_packet = Ipv4Packet::new(packet)?;
+    let fragmented = ip_packet.get_fragment_offset() != 0
+        || ip_packet.get_flags() & Ipv4Flags::MoreFragments != 0;
+    if ip_packet.get_next_level_protocol() != IpNextHeaderProtocols::Udp || fragmented {
+        return None;
+    }
+
+    let udp_packet = UdpPacket::new(ip_packet.payload())?;
+    let port = udp_packet.get_destination();
+    if !ports.contains(&port) {
+        return None;
+    }
+
+    Some(IncomingDatagram {
+        port,
+        source: SocketAddr::new(ip_packet.get_source().into(), udp_packet.get_source()),
+        bytes: udp_packet.payload().to_vec(),
+    })
+}
+
+/// An `AF_PACKET` socket that receives the IPv4 packets of all the interfaces, without the link
+/// layer headers.
+struct PacketSocket(AsyncFd<OwnedFd>);
+
+impl PacketSocket {
+    fn new() -> io::Result<Self> {
+        let protocol = (libc::ETH_P_IP as u16).to_be();
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                protocol.into(),
+            )
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(AsyncFd::new(unsafe { OwnedFd::from_raw_fd(fd) })?))
+    }
+
+    /// Reads the next packet that arrived to the pod into the `buffer`, returning its length.
+    ///
+    /// Packets sent from the pod are skipped, which also makes us see the loopback ones once.
+    async fn next(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.0.readable().await?;
+
+            let received = guard.try_io(|fd| {
+                let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+                let mut address_length = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+
+                let received = unsafe {
+                    libc::recvfrom(
+                        fd.as_raw_fd(),
+                        buffer.as_mut_ptr().cast(),
+                        buffer.len(),
+                        0,
+                        (&mut address as *mut libc::sockaddr_ll).cast(),
+                        &mut address_length,
+                    )
+                };
+
+                usize::try_from(received)
+                    .map(|length| (length, address.sll_pkttype))
+                    .map_err(|_| io::Error::last_os_error())
+            });
+
+            match received {
+                Ok(Ok((_, packet_type))) if packet_type == libc::PACKET_OUTGOING as u8 => {}
+                Ok(received) => return received.map(|(length, _)| length),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+/// The iptables chain with the UDP `REDIRECT` rules of a single client, jumped to from
+/// `PREROUTING`. Removed on drop.
+struct UdpRedirect {
+    chain: IPTableChain<IPTablesWrapper>,
+}
+
+impl UdpRedirect {
+    fn create() -> io::Result<Self> {
+        let ipt = Arc::new(IPTablesWrapper::from(new_iptables()));
+        let chain_name = format!(
+            "MIRRORD_UDP_{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 5)
+        );
+
+        let chain = IPTableChain::create(ipt, chain_name)
+            .map_err(|error| io::Error::other(error.to_string()))?;
+        chain
+            .inner()
+            .insert_rule("PREROUTING", &Self::entrypoint(&chain), 1)
+            .map_err(|error| io::Error::other(error.to_string()))?;
+
+        Ok(Self { chain })
+    }
+
+    fn entrypoint(chain: &IPTableChain<IPTablesWrapper>) -> String {
+        format!("-j {}", chain.chain_name())
+    }
+
+    fn rule(port: Port, local_port: Port) -> String {
+        format!("-p udp --dport {port} -j REDIRECT --to-ports {local_port}")
+    }
+
+    fn add(&self, port: Port, local_port: Port) -> io::Result<()> {
+        self.chain
+            .add_rule(&Self::rule(port, local_port))
+            .map(|_| ())
+            .map_err(|error| io::Error::other(error.to_string()))
+    }
+
+    fn remove(&self, port: Port, local_port: Port) -> io::Result<()> {
+        self.chain
+            .remove_rule(&Self::rule(port, local_port))
+            .map_err(|error| io::Error::other(error.to_string()))
+    }
+}
+
+impl Drop for UdpRedirect {
+    fn drop(&mut self) {
+        let _ = self
+            .chain
+            .inner()
+            .remove_rule("PREROUTING", &Self::entrypoint(&self.chain));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::{
+        ipv4::{checksum, MutableIpv4Packet},
+        udp::MutableUdpPacket,
+    };
+
+    use super::*;
+
+    fn packet(destination: Port, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; 20 + 8 + payload.len()];
+
+        let (_, udp_bytes) = bytes.split_at_mut(20);
+        let mut udp = MutableUdpPacket::new(udp_bytes).unwrap();
+        udp.set_source(5000);
+        udp.set_destination(destination);
+        udp.set_length((8 + payload.len()) as u16);
+        udp.set_payload(payload);
+
+        let mut ip = MutableIpv4Packet::new(&mut bytes).unwrap();
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length((20 + 8 + payload.len()) as u16);
+        ip.set_ttl(64);
+        ip.set_flags(flags);
+        ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ip.set_source(Ipv4Addr::new(10, 0, 0, 1));
+        ip.set_destination(Ipv4Addr::new(10, 0, 0, 2));
+        let checksum = checksum(&ip.to_immutable());
+        ip.set_checksum(checksum);
+
+        bytes
+    }
+
+    #[test]
+    fn parses_subscribed_datagrams() {
+        let ports = HashSet::from([53]);
+
+        let datagram = parse_datagram(&packet(53, 0, b"hello"), &ports).unwrap();
+        assert_eq!(
+            datagram,
+            IncomingDatagram {
+                port: 53,
+                source: "10.0.0.1:5000".parse().unwrap(),
+                bytes: b"hello".to_vec(),
+            }
+        );
+
+        assert!(parse_datagram(&packet(54, 0, b"hello"), &ports).is_none());
+        assert!(parse_datagram(&packet(53, Ipv4Flags::MoreFragments, b"hello"), &ports).is_none());
+    }
+}
@@ -0,0 +1,60 @@
+//! `agent.unprivileged`: the agent runs without added Linux capabilities, and provides only the
+//! features it can without them (see [`Capability::agent_requirement`]).
+//!
+//! The features it can't provide are left out of its answer to
+//! [`ClientMessage::Capabilities`](mirrord_protocol::ClientMessage::Capabilities), so the client
+//! tells the user about them at the start of the session. The agent doesn't enter the target's
+//! namespaces either, it must run in an ephemeral container in the target's pod.
+
+use std::path::Path;
+
+use mirrord_protocol::capabilities::Capability;
+use socket2::{Domain, Socket, Type};
+use tracing::warn;
+
+/// Checks which of the features that need Linux capabilities the agent can't provide.
+///
+/// `pid` is the target's process, as in `/proc/{pid}`.
+pub(crate) fn unavailable_features(pid: &str) -> Vec<Capability> {
+    let proc = Path::new("/proc").join(pid);
+
+    let available = |capability: Capability| match capability {
+        // The sniffer's raw socket.
+        Capability::IncomingMirror => Socket::new(Domain::PACKET, Type::RAW, None).is_ok(),
+        // The stealer's iptables rules need `NET_ADMIN`, which an unprivileged agent never has.
+        Capability::IncomingSteal => false,
+        Capability::RemoteEnv => std::fs::read(proc.join("environ")).is_ok(),
+        Capability::RemoteFs => std::fs::read_dir(proc.join("root")).is_ok(),
+        _ => true,
+    };
+
+    let unavailable = Capability::ALL
+        .iter()
+        .copied()
+        .filter(|capability| capability.agent_requirement().is_some())
+        .filter(|capability| !available(*capability))
+        .collect::<Vec<_>>();
+
+    for capability in &unavailable {
+        warn!(
+            "{capability} is not available, the agent runs without {}",
+            capability.agent_requirement().unwrap_or_default()
+        );
+    }
+
+    unavailable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn own_process_is_readable() {
+        let unavailable = unavailable_features("self");
+
+        assert!(unavailable.contains(&Capability::IncomingSteal));
+        assert!(!unavailable.contains(&Capability::RemoteEnv));
+        assert!(!unavailable.contains(&Capability::RemoteFs));
+    }
+}
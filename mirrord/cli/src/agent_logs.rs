@@ -0,0 +1,98 @@
+//! `mirrord agent logs` prints the logs of the agent of a local session, so the user doesn't have
+//! to look for its randomly named pod (or ephemeral container) with `kubectl`.
+//!
+//! The agent is found in the [`SessionState`] of the session, so only agents spawned by the CLI
+//! (without the operator) can be found.
+
+use std::io::Write;
+
+use futures::{AsyncBufReadExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::{api::LogParams, Api};
+use mirrord_kube::api::kubernetes::create_kube_api;
+
+use crate::{
+    diagnose::load_config,
+    status::{self, SessionState},
+    AgentCommand, AgentLogsArgs, CliError, Result,
+};
+
+/// Handle `mirrord agent`.
+pub(crate) async fn agent_command(command: AgentCommand) -> Result<()> {
+    match command {
+        AgentCommand::Logs(args) => agent_logs_command(*args).await,
+    }
+}
+
+/// Handle `mirrord agent logs`.
+async fn agent_logs_command(args: AgentLogsArgs) -> Result<()> {
+    let session = find_session(args.session)?;
+    let Some(agent_pod) = session.agent_pod else {
+        return Err(CliError::AgentLogsFailed(format!(
+            "session {} did not spawn its agent ({})",
+            session.pid, session.connection
+        )));
+    };
+
+    let config = load_config(args.config_file.as_deref())?;
+    let client = create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    .map_err(CliError::CreateKubeApiFailed)?;
+
+    let pod_api: Api<Pod> = match agent_pod.namespace.as_deref() {
+        Some(namespace) => Api::namespaced(client, namespace),
+        None => Api::default_namespaced(client),
+    };
+
+    let failed =
+        |error: String| CliError::AgentLogsFailed(format!("agent pod {}: {error}", agent_pod.name));
+
+    let logs = pod_api
+        .log_stream(
+            &agent_pod.name,
+            &LogParams {
+                follow: args.follow,
+                previous: args.previous,
+                tail_lines: args.tail,
+                container: agent_pod.container.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|error| failed(error.to_string()))?;
+
+    let mut lines = logs.lines();
+    while let Some(line) = lines
+        .try_next()
+        .await
+        .map_err(|error| failed(error.to_string()))?
+    {
+        // The reader is gone, e.g. `| head`.
+        if writeln!(std::io::stdout().lock(), "{line}").is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// The running session with the given internal proxy pid, or the last session that started.
+fn find_session(pid: Option<u32>) -> Result<SessionState> {
+    let dir = status::sessions_dir();
+    let failed = |error: std::io::Error| CliError::SessionStatusFailed(dir.clone(), error);
+
+    match pid {
+        Some(pid) => status::running_sessions(&dir)
+            .map_err(failed)?
+            .into_iter()
+            .find(|session| session.pid == pid)
+            .ok_or_else(|| CliError::AgentLogsFailed(format!("no running session {pid}"))),
+        None => status::last_session(&dir)
+            .map_err(failed)?
+            .ok_or_else(|| CliError::AgentLogsFailed("no session started yet".to_string())),
+    }
+}
@@ -0,0 +1,355 @@
+//! `mirrord analyze` observes the target to help with writing the mirrord config.
+//!
+//! `mirrord analyze fs-usage` asks the agent to report the files that the target accesses for a
+//! while (the agent watches them with fanotify), and turns them into a suggested `feature.fs`
+//! config that reads these files remotely, and everything else locally.
+//!
+//! `mirrord analyze outgoing` reads the destinations recorded by the sessions with
+//! `feature.network.outgoing.record`, and suggests an `outgoing.filter` that connects only the
+//! ones that look like they're in the cluster remotely.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    net::IpAddr,
+    path::Path,
+    time::Duration,
+};
+
+use mirrord_analytics::NullReporter;
+use mirrord_config::feature::network::outgoing::OutgoingRecord;
+use mirrord_progress::{Progress, ProgressTracker};
+use mirrord_protocol::{
+    capabilities::Capability,
+    fs_usage::{FileUsage, FsUsageReport, FsUsageRequest},
+    ClientMessage, DaemonMessage,
+};
+use serde_json::json;
+use tokio::time;
+use tracing::{debug, warn};
+
+use crate::{
+    connection::{create_and_connect, negotiate_capabilities, AgentConnection},
+    diagnose::load_config,
+    AnalyzeCommand, AnalyzeOutgoingArgs, CliError, FsUsageArgs, Result,
+};
+
+/// How often we ping the agent while waiting for the report, so it doesn't consider us gone.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Host names under these domains are in the cluster.
+const CLUSTER_DOMAINS: &[&str] = &[".svc", ".cluster.local", ".internal"];
+
+/// Handle `mirrord analyze`.
+pub(crate) async fn analyze_command(command: AnalyzeCommand) -> Result<()> {
+    match command {
+        AnalyzeCommand::FsUsage(args) => fs_usage_command(*args).await,
+        AnalyzeCommand::Outgoing(args) => outgoing_command(*args),
+    }
+}
+
+/// Handle `mirrord analyze fs-usage`.
+async fn fs_usage_command(args: FsUsageArgs) -> Result<()> {
+    if let Some(target) = args.target.as_deref() {
+        std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target);
+    }
+
+    let mut progress = ProgressTracker::from_env("mirrord analyze fs-usage");
+
+    let config = load_config(args.config_file.as_deref())?;
+
+    let mut analytics = NullReporter::default();
+    let (_, mut connection) = create_and_connect(&config, &mut progress, &mut analytics).await?;
+
+    let (version, capabilities) = negotiate_capabilities(&mut connection).await?;
+    if !capabilities.supports(Capability::FsUsage) {
+        return Err(CliError::FsUsageFailed(format!(
+            "the agent's protocol version {version} doesn't support it, please update the agent"
+        )));
+    }
+
+    let mut observing = progress.subtask(&format!(
+        "observing the files of the target for {}, use the application meanwhile",
+        humantime::format_duration(args.duration)
+    ));
+    let report = fs_usage(&mut connection, args.duration).await?;
+    observing.success(Some("observed the files of the target"));
+    progress.success(None);
+
+    let read_write = report
+        .files
+        .iter()
+        .filter(|file| file.written)
+        .collect::<Vec<_>>();
+    let read_only = report
+        .files
+        .iter()
+        .filter(|file| !file.written)
+        .collect::<Vec<_>>();
+
+    eprintln!(
+        "{} files were read and {} written.",
+        read_only.len(),
+        read_write.len()
+    );
+    if report.overflowed {
+        eprintln!(
+            "The target accessed files faster than the agent could keep up, some of them may be \
+             missing."
+        );
+    }
+
+    let suggestion = json!({
+        "feature": {
+            "fs": {
+                "mode": "localwithoverrides",
+                "read_only": suggest_patterns(&read_only, args.collapse),
+                "read_write": suggest_patterns(&read_write, args.collapse),
+            }
+        }
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&suggestion)
+            .map_err(|error| CliError::FsUsageFailed(error.to_string()))?
+    );
+
+    Ok(())
+}
+
+/// Starts the observation and waits for its report.
+async fn fs_usage(connection: &mut AgentConnection, duration: Duration) -> Result<FsUsageReport> {
+    let closed = || CliError::FsUsageFailed("agent unexpectedly closed connection".into());
+
+    connection
+        .sender
+        .send(ClientMessage::FsUsage(FsUsageRequest {
+            duration_secs: duration.as_secs().max(1),
+        }))
+        .await
+        .map_err(|_| closed())?;
+
+    let mut ping = time::interval(PING_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                connection
+                    .sender
+                    .send(ClientMessage::Ping)
+                    .await
+                    .map_err(|_| closed())?;
+            }
+
+            message = connection.receiver.recv() => match message {
+                Some(DaemonMessage::FsUsage(result)) => {
+                    return result.map_err(|error| CliError::FsUsageFailed(error.to_string()));
+                }
+                Some(DaemonMessage::Pong) => {}
+                Some(DaemonMessage::LogMessage(log)) => warn!("Agent: {}", log.message),
+                Some(DaemonMessage::Close(message)) => {
+                    return Err(CliError::FsUsageFailed(format!(
+                        "agent closed connection with message: {message}"
+                    )))
+                }
+                Some(message) => debug!(?message, "Ignoring an unexpected message from the agent"),
+                None => return Err(closed()),
+            },
+        }
+    }
+}
+
+/// Patterns for `feature.fs` that match the given `files`.
+///
+/// The files of a directory that has more than `collapse` of them are matched with a single
+/// pattern for the files of the directory, to keep the config short.
+fn suggest_patterns(files: &[&FileUsage], collapse: usize) -> Vec<String> {
+    let mut directories: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+    for file in files {
+        let directory = file.path.parent().unwrap_or(Path::new("/"));
+        directories.entry(directory).or_default().push(&file.path);
+    }
+
+    directories
+        .into_iter()
+        .flat_map(|(directory, files)| {
+            if files.len() > collapse {
+                let directory = directory.to_string_lossy();
+                let directory = directory.trim_end_matches('/');
+                vec![format!("^{}/[^/]+$", regex::escape(directory))]
+            } else {
+                files
+                    .into_iter()
+                    .map(|path| format!("^{}$", regex::escape(&path.to_string_lossy())))
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+/// Handle `mirrord analyze outgoing`.
+fn outgoing_command(args: AnalyzeOutgoingArgs) -> Result<()> {
+    let contents = fs::read_to_string(&args.record)
+        .map_err(|error| CliError::OutgoingRecordFailed(args.record.clone(), error))?;
+
+    let records = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str::<OutgoingRecord>(line) {
+            Ok(record) => Some(record),
+            Err(error) => {
+                warn!(%error, line, "Skipping an invalid line of the record");
+                None
+            }
+        })
+        .collect::<BTreeSet<_>>();
+
+    for record in &records {
+        eprintln!(
+            "{} (connected {}, suggested {})",
+            filter_entry(record),
+            if record.remote { "remotely" } else { "locally" },
+            if in_cluster(record) {
+                "remotely"
+            } else {
+                "locally"
+            }
+        );
+    }
+
+    let remote = suggest_remote_filter(records.iter());
+    let suggestion = if remote.is_empty() {
+        eprintln!("None of the destinations are in the cluster, outgoing traffic can be disabled.");
+        json!({ "feature": { "network": { "outgoing": false } } })
+    } else {
+        json!({ "feature": { "network": { "outgoing": { "filter": { "remote": remote } } } } })
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&suggestion)
+            .map_err(|error| CliError::OutgoingRecordFailed(args.record, error.into()))?
+    );
+
+    Ok(())
+}
+
+/// Whether the destination looks like it's in the cluster, so it has to be connected remotely.
+///
+/// Loopback destinations are kept as they were, as they may be sidecars of the target.
+fn in_cluster(record: &OutgoingRecord) -> bool {
+    if let Some(hostname) = record.hostname.as_deref() {
+        let hostname = hostname.trim_end_matches('.');
+        if !hostname.contains('.')
+            || CLUSTER_DOMAINS
+                .iter()
+                .any(|domain| hostname.ends_with(domain))
+        {
+            return true;
+        }
+    }
+
+    match record.address.ip() {
+        ip if ip.is_loopback() => record.remote,
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        // Unique local addresses, `fc00::/7`.
+        IpAddr::V6(ip) => ip
+            .segments()
+            .first()
+            .is_some_and(|segment| segment & 0xfe00 == 0xfc00),
+    }
+}
+
+/// `feature.network.outgoing.filter` entry for the destination, with the host name when we know
+/// it.
+fn filter_entry(record: &OutgoingRecord) -> String {
+    let port = record.address.port();
+    match (record.hostname.as_deref(), record.address.ip()) {
+        (Some(hostname), _) => format!("{}://{hostname}:{port}", record.protocol),
+        (None, IpAddr::V4(ip)) => format!("{}://{ip}:{port}", record.protocol),
+        (None, IpAddr::V6(ip)) => format!("{}://[{ip}]:{port}", record.protocol),
+    }
+}
+
+/// Entries of a `remote` filter for the destinations in the cluster.
+fn suggest_remote_filter<'a, I: Iterator<Item = &'a OutgoingRecord>>(records: I) -> Vec<String> {
+    records
+        .filter(|record| in_cluster(record))
+        .map(filter_entry)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use mirrord_config::feature::network::outgoing::RecordedProtocol;
+
+    use super::*;
+
+    fn file(path: &str) -> FileUsage {
+        FileUsage {
+            path: path.into(),
+            read: true,
+            written: false,
+        }
+    }
+
+    #[test]
+    fn crowded_directories_are_collapsed() {
+        let files = [
+            file("/app/config.yaml"),
+            file("/etc/ssl/certs/a.pem"),
+            file("/etc/ssl/certs/b.pem"),
+            file("/etc/ssl/certs/c.pem"),
+            file("/root.txt"),
+        ];
+        let files = files.iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            suggest_patterns(&files, 2),
+            vec![
+                "^/root\\.txt$",
+                "^/app/config\\.yaml$",
+                "^/etc/ssl/certs/[^/]+$",
+            ]
+        );
+    }
+
+    fn record(address: &str, hostname: Option<&str>, remote: bool) -> OutgoingRecord {
+        OutgoingRecord {
+            protocol: RecordedProtocol::Tcp,
+            address: address.parse().unwrap(),
+            hostname: hostname.map(ToString::to_string),
+            remote,
+        }
+    }
+
+    #[test]
+    fn only_cluster_destinations_are_remote() {
+        let records = [
+            record(
+                "10.96.3.4:5432",
+                Some("postgres.db.svc.cluster.local"),
+                true,
+            ),
+            record(
+                "10.96.3.5:5432",
+                Some("postgres.db.svc.cluster.local"),
+                true,
+            ),
+            record("10.96.7.1:6379", Some("redis"), true),
+            record("10.0.0.12:8080", None, true),
+            record("142.250.1.1:443", Some("www.googleapis.com"), true),
+            record("127.0.0.1:9000", None, false),
+        ];
+
+        assert_eq!(
+            suggest_remote_filter(records.iter()),
+            vec![
+                "tcp://10.0.0.12:8080",
+                "tcp://postgres.db.svc.cluster.local:5432",
+                "tcp://redis:6379",
+            ]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 #![deny(missing_docs)]
 
-use std::{fmt::Display, path::PathBuf};
+use std::{fmt::Display, net::IpAddr, path::PathBuf};
 
 use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum, ValueHint};
 use clap_complete::Shell;
@@ -56,6 +56,55 @@ pub(super) enum Commands {
 
     /// Diagnostic commands
     Diagnose(Box<DiagnoseArgs>),
+
+    /// Commands for inspecting how mirrord handles the session, based on the config.
+    Session(Box<SessionArgs>),
+
+    /// List the mirrord sessions running on this machine.
+    Status,
+
+    /// Change the log filter of the internal proxy and the layers of a running session, e.g.
+    /// `mirrord loglevel mirrord=trace`, without restarting it.
+    #[command(name = "loglevel")]
+    LogLevel(Box<LogLevelArgs>),
+
+    /// Commands for preparing the cluster for mirrord users.
+    Setup(Box<SetupArgs>),
+
+    /// Print the traffic that arrives at ports of the target, mirrored by the agent.
+    Dump(Box<DumpArgs>),
+
+    /// Search the files of the target for lines that match a regex, e.g.
+    /// `mirrord grep -t deploy/foo 'pattern' /var/log/app`.
+    Grep(Box<GrepArgs>),
+
+    /// Commands for observing the target, to help with writing the mirrord config.
+    Analyze(Box<AnalyzeArgs>),
+
+    /// Gather the config, versions, logs and the agent pods into a `.tar.gz` to attach to an
+    /// issue.
+    #[command(name = "debug-bundle")]
+    DebugBundle(Box<DebugBundleArgs>),
+
+    /// Print the environment that `mirrord exec` would give the application, without running
+    /// anything, e.g. `mirrord env -t deploy/foo --output json`.
+    Env(Box<EnvArgs>),
+
+    /// Commands for resolving the names of the cluster outside of a mirrord session.
+    Dns(Box<DnsArgs>),
+
+    /// Commands for the agents spawned by the local sessions.
+    Agent(Box<AgentArgs>),
+
+    /// Commands for writing mirrord config files.
+    Config(Box<ConfigArgs>),
+
+    /// Commands for the authors of `MirrordPolicy` resources.
+    Policy(Box<PolicyArgs>),
+
+    /// Stream the logs of the target container (`feature.target_logs`) - started by `exec`.
+    #[command(hide = true, name = "target-logs")]
+    TargetLogs(TargetLogsArgs),
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
@@ -82,6 +131,24 @@ impl Display for FsMode {
     }
 }
 
+/// How to get the Job of a CronJob target, see `--wait-for-run`.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum WaitForRun {
+    /// Wait for the CronJob to start its next Job on schedule - default
+    Next,
+    /// Start a Job from the CronJob's template right away
+    Trigger,
+}
+
+impl Display for WaitForRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WaitForRun::Next => "next",
+            WaitForRun::Trigger => "trigger",
+        })
+    }
+}
+
 #[derive(Args, Debug)]
 #[command(group(ArgGroup::new("exec")))]
 pub(super) struct ExecArgs {
@@ -91,10 +158,20 @@ pub(super) struct ExecArgs {
     #[arg(short = 't', long)]
     pub target: Option<String>,
 
+    /// When no target is configured, pick it in the terminal: its namespace, type, name and
+    /// container, from the ones `mirrord ls` lists.
+    #[arg(long)]
+    pub select_target: bool,
+
     /// Namespace of the pod to mirror. Defaults to "default".
     #[arg(short = 'n', long)]
     pub target_namespace: Option<String>,
 
+    /// When the target is a CronJob, target the pod of its next Job and exit when the Job
+    /// finishes.
+    #[arg(long, num_args = 0..=1, default_missing_value = "next")]
+    pub wait_for_run: Option<WaitForRun>,
+
     /// Namespace to place agent in.
     #[arg(short = 'a', long)]
     pub agent_namespace: Option<String>,
@@ -107,6 +184,11 @@ pub(super) struct ExecArgs {
     #[arg(short = 'i', long)]
     pub agent_image: Option<String>,
 
+    /// Use the agent image of this mirrord version, replacing the tag that `agent.image` is
+    /// pinned to.
+    #[arg(long)]
+    pub update_agent: bool,
+
     /// Default file system behavior: read, write, local
     #[arg(long)]
     pub fs_mode: Option<FsMode>,
@@ -153,6 +235,11 @@ pub(super) struct ExecArgs {
     #[arg(long = "steal")]
     pub tcp_steal: bool,
 
+    /// Attach to the operator session with this id (hex), shared with you with
+    /// `mirrord operator session share`.
+    #[arg(long)]
+    pub join_session: Option<String>,
+
     /// Disable tcp/udp outgoing traffic
     #[arg(long)]
     pub no_outgoing: bool,
@@ -173,6 +260,18 @@ pub(super) struct ExecArgs {
     /// Disable version check on startup.
     pub disable_version_check: bool,
 
+    /// Don't print the progress, the warnings or the config summary, only the errors.
+    ///
+    /// mirrord writes only to stderr anyway, the output of the application on stdout is left
+    /// alone.
+    #[arg(short = 'q', long)]
+    pub quiet: bool,
+
+    /// Don't connect to the cluster, only print what mirrord would do remotely (files,
+    /// connections, DNS lookups, port subscriptions) and do it locally instead.
+    #[arg(long)]
+    pub simulate: bool,
+
     /// Load config from config file
     #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
     pub config_file: Option<PathBuf>,
@@ -234,7 +333,7 @@ pub(super) enum OperatorCommand {
 /// Allows the user to forcefully kill operator sessions, use with care!
 ///
 /// Implements [`core::fmt::Display`] to show the user a nice message.
-#[derive(Debug, Subcommand, Clone, Copy)]
+#[derive(Debug, Subcommand, Clone)]
 pub(crate) enum SessionCommand {
     /// Kills the session specified by `id`.
     Kill {
@@ -249,6 +348,19 @@ pub(crate) enum SessionCommand {
     /// the session storage.
     #[clap(hide(true))]
     RetainActive,
+
+    /// Invites other users into the session specified by `id`, they can attach their local
+    /// process to it with `mirrord exec --join-session <ID>` and get a copy of the traffic it
+    /// steals.
+    Share {
+        /// Id of the session.
+        #[arg(short, long, value_parser=hex_id)]
+        id: u64,
+
+        /// User to share the session with, e.g. `user@corp`. Can be given multiple times.
+        #[arg(long, required = true)]
+        with: Vec<String>,
+    },
 }
 
 impl core::fmt::Display for SessionCommand {
@@ -257,6 +369,11 @@ impl core::fmt::Display for SessionCommand {
             SessionCommand::Kill { id } => write!(f, "mirrord operator kill --id {id}"),
             SessionCommand::KillAll => write!(f, "mirrord operator kill-all"),
             SessionCommand::RetainActive => write!(f, "mirrord operator retain-active"),
+            SessionCommand::Share { id, with } => write!(
+                f,
+                "mirrord operator session share --id {id:x} --with {}",
+                with.join(" --with ")
+            ),
         }
     }
 }
@@ -267,27 +384,49 @@ fn hex_id(raw: &str) -> Result<u64, String> {
         .map_err(|fail| format!("Failed parsing hex session id value with {fail}!"))
 }
 
-#[derive(ValueEnum, Clone, Debug)]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Format {
+    /// The targets with their type, containers and status, as a JSON list.
     Json,
+    /// A table of the targets with their status.
+    Table,
+    /// A table of the targets with their status and containers.
+    Wide,
+}
+
+/// Types of targets `mirrord ls --type` filters by.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum ListTargetType {
+    Pod,
+    Deployment,
+    Rollout,
+    Job,
+    #[value(name = "cronjob")]
+    CronJob,
+    #[value(name = "statefulset")]
+    StatefulSet,
 }
 
 #[derive(Args, Debug)]
 pub(super) struct ListTargetArgs {
     /// Specify the format of the output.
-    #[arg(
-        short = 'o',
-        long = "output",
-        value_name = "FORMAT",
-        value_enum,
-        default_value_t = Format::Json
-    )]
-    pub output: Format,
+    ///
+    /// Without it, only the paths of the targets are printed, as a JSON list.
+    #[arg(short = 'o', long = "output", value_name = "FORMAT", value_enum)]
+    pub output: Option<Format>,
 
     /// Specify the namespace to list targets in.
     #[arg(short = 'n', long = "namespace")]
     pub namespace: Option<String>,
 
+    /// Only list targets of these types, e.g. `--type deployment --type rollout`.
+    #[arg(short = 't', long = "type", value_name = "TYPE", value_enum)]
+    pub target_types: Vec<ListTargetType>,
+
+    /// Only list targets with these labels, a Kubernetes label selector, e.g. `app=api,tier!=db`.
+    #[arg(short = 'l', long = "selector")]
+    pub selector: Option<String>,
+
     /// Specify config file to use
     #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
     pub config_file: Option<PathBuf>,
@@ -306,6 +445,14 @@ pub(super) struct ExtensionExecArgs {
     pub executable: Option<String>,
 }
 
+/// Args for the [`mod@super::target_logs`] mirrord-cli command.
+#[derive(Args, Debug)]
+pub(super) struct TargetLogsArgs {
+    /// Stop when the process with this pid (the user application) exits.
+    #[arg(long)]
+    pub pid: i32,
+}
+
 /// Args for the [`mod@super::verify_config`] mirrord-cli command.
 #[derive(Args, Debug)]
 #[command(group(ArgGroup::new("verify-config")))]
@@ -314,6 +461,21 @@ pub(super) struct VerifyConfigArgs {
     #[arg(long)]
     pub(super) ide: bool,
 
+    /// Check that the current kube credentials have every permission mirrord needs to run with
+    /// this config without the operator, and list the missing ones.
+    #[arg(long)]
+    pub(super) rbac: bool,
+
+    /// Check that the target, its namespace and the agent image exist, and whether the operator
+    /// is installed, with the current kube credentials.
+    #[arg(long)]
+    pub(super) cluster: bool,
+
+    /// Also report unknown (e.g. misspelled) and deprecated fields of the config file, with their
+    /// paths.
+    #[arg(long)]
+    pub(super) strict: bool,
+
     /// Config file path.
     pub(super) path: PathBuf,
 }
@@ -338,4 +500,434 @@ pub(super) enum DiagnoseCommand {
         #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
         config_file: Option<PathBuf>,
     },
+
+    /// Measure RTT and throughput between the local machine and the agent, over the same
+    /// connection that mirrord sessions use.
+    Bandwidth {
+        /// Target to measure with, e.g. `deployment/name`. Defaults to a targetless agent.
+        #[arg(short = 't', long)]
+        target: Option<String>,
+
+        /// Specify config file to use
+        #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+        config_file: Option<PathBuf>,
+    },
+
+    /// Report the HTTP(S) proxy used to reach the cluster and check that the cluster is reachable
+    /// through it.
+    Proxy {
+        /// Specify config file to use
+        #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+        config_file: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug)]
+pub(super) struct LogLevelArgs {
+    /// The new filter, with the `RUST_LOG` syntax, e.g. `mirrord=trace` or
+    /// `warn,mirrord_intproxy=debug`.
+    pub directives: String,
+
+    /// Pid of the application running with mirrord (Linux only). When not given, the session is
+    /// looked up in our own environment, e.g. in a shell started with `mirrord exec -- bash`.
+    #[arg(short, long)]
+    pub pid: Option<u32>,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct SessionArgs {
+    #[command(subcommand)]
+    pub command: SessionInspectCommand,
+}
+
+/// Commands for inspecting how mirrord handles the session, based on the config.
+#[derive(Subcommand, Debug)]
+pub(super) enum SessionInspectCommand {
+    /// Explain why mirrord does what it does.
+    Why {
+        #[command(subcommand)]
+        command: WhyCommand,
+    },
+
+    /// List the files, directories and outgoing connections the running session opened in the
+    /// target, with their usage, to find out what a hung application is waiting on.
+    Fds {
+        /// Pid of the application running with mirrord (Linux only). When not given, the
+        /// session is looked up in our own environment, e.g. in a shell started with
+        /// `mirrord exec -- bash`.
+        #[arg(short, long)]
+        pid: Option<u32>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(super) enum WhyCommand {
+    /// Explain whether file operations on the path are done locally or remotely, and which part
+    /// of the `feature.fs` config decides it.
+    Fs {
+        /// Absolute path of the file.
+        path: PathBuf,
+
+        /// Specify config file to use
+        #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+        config_file: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug)]
+pub(super) struct SetupArgs {
+    #[command(subcommand)]
+    pub command: SetupCommand,
+}
+
+/// Commands for preparing the cluster for mirrord users.
+#[derive(Subcommand, Debug)]
+pub(super) enum SetupCommand {
+    /// Print the namespace, Role and RoleBinding (or operator user role binding) that users need
+    /// in the cluster to run mirrord with the given config.
+    #[command(override_usage = "mirrord setup print [OPTIONS] | kubectl apply -f -")]
+    Print {
+        /// Specify config file to use
+        #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+        config_file: Option<PathBuf>,
+
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = SetupFormat::Yaml)]
+        format: SetupFormat,
+
+        /// Namespace used when the config doesn't specify the target or agent namespace.
+        #[arg(short, long, default_value = "default")]
+        namespace: String,
+
+        /// Group to bind the roles to, can be repeated.
+        #[arg(long, default_value = "mirrord-users")]
+        group: Vec<String>,
+
+        /// User to bind the roles to, can be repeated.
+        #[arg(long)]
+        user: Vec<String>,
+    },
+}
+
+/// Output format of `mirrord setup print`.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub(super) enum SetupFormat {
+    /// Kubernetes manifests, ready for `kubectl apply`.
+    Yaml,
+    /// Values for a Helm chart that templates the same resources.
+    Helm,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct DumpArgs {
+    /// Target to dump the traffic of, e.g. `deployment/name`.
+    #[arg(short = 't', long)]
+    pub target: Option<String>,
+
+    /// Specify config file to use
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// Ports to dump the traffic of, e.g. `--ports 80,8080`.
+    #[arg(short = 'p', long, value_delimiter = ',', required = true)]
+    pub ports: Vec<u16>,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = DumpFormat::Raw)]
+    pub format: DumpFormat,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct GrepArgs {
+    /// Target to search the files of, e.g. `deployment/name`.
+    #[arg(short = 't', long)]
+    pub target: Option<String>,
+
+    /// Specify config file to use
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// Regex to search for, with the syntax of the `regex` crate.
+    pub pattern: String,
+
+    /// Files and directories of the target to search, directories are searched recursively.
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Match case-insensitively.
+    #[arg(short = 'i', long)]
+    pub ignore_case: bool,
+
+    /// Stop the search after this many matches.
+    #[arg(short = 'm', long, default_value_t = 1000)]
+    pub max_count: u64,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct DebugBundleArgs {
+    /// Specify config file to use
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// Where to write the bundle, defaults to `mirrord-debug-bundle-<timestamp>.tar.gz` in the
+    /// current directory.
+    #[arg(short = 'o', long, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+
+    /// How many of the last lines of the agents' logs to include.
+    #[arg(long, default_value_t = 1000)]
+    pub agent_log_lines: i64,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct AnalyzeArgs {
+    #[command(subcommand)]
+    pub command: AnalyzeCommand,
+}
+
+/// Commands for observing the target, to help with writing the mirrord config.
+#[derive(Subcommand, Debug)]
+pub(super) enum AnalyzeCommand {
+    /// Observe which files the target reads and writes for a while, e.g. while it serves some
+    /// requests, and print a `feature.fs` config that reads them remotely.
+    #[command(name = "fs-usage")]
+    FsUsage(Box<FsUsageArgs>),
+
+    /// Suggest a `feature.network.outgoing.filter` that connects only the destinations in the
+    /// cluster remotely, from the destinations recorded with `feature.network.outgoing.record`.
+    Outgoing(Box<AnalyzeOutgoingArgs>),
+}
+
+#[derive(Args, Debug)]
+pub(super) struct AnalyzeOutgoingArgs {
+    /// File written by the sessions with `feature.network.outgoing.record`.
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub record: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct FsUsageArgs {
+    /// Target to observe, e.g. `deployment/name`.
+    #[arg(short = 't', long)]
+    pub target: Option<String>,
+
+    /// Specify config file to use
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// How long to observe the target for, e.g. `30s` or `5m`.
+    #[arg(short = 'd', long, default_value = "1m", value_parser = humantime::parse_duration)]
+    pub duration: std::time::Duration,
+
+    /// Files of the same directory are suggested as a single pattern for the whole directory
+    /// when there are more than this many of them.
+    #[arg(long, default_value_t = 5)]
+    pub collapse: usize,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct AgentArgs {
+    #[command(subcommand)]
+    pub command: AgentCommand,
+}
+
+/// Commands for the agents spawned by the local sessions.
+#[derive(Subcommand, Debug)]
+pub(super) enum AgentCommand {
+    /// Print the logs of the agent of the last session that started (or of the running session
+    /// given with `--session`), e.g. `mirrord agent logs --follow`.
+    Logs(Box<AgentLogsArgs>),
+}
+
+#[derive(Args, Debug)]
+pub(super) struct AgentLogsArgs {
+    /// Pid of the internal proxy of a running session, as shown by `mirrord status`.
+    #[arg(short = 's', long)]
+    pub session: Option<u32>,
+
+    /// Specify config file to use, for the kubeconfig and the context.
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// Keep printing the logs as the agent writes them.
+    #[arg(long)]
+    pub follow: bool,
+
+    /// Print the logs of the previous instance of the agent container, if it restarted.
+    #[arg(long)]
+    pub previous: bool,
+
+    /// Print only this many of the last lines.
+    #[arg(long)]
+    pub tail: Option<i64>,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+/// Commands for writing mirrord config files.
+#[derive(Subcommand, Debug)]
+pub(super) enum ConfigCommand {
+    /// Write a commented starter config, asking for the values that aren't given as options,
+    /// e.g. `mirrord config init -t deploy/foo --steal .mirrord/mirrord.json`.
+    Init(Box<ConfigInitArgs>),
+}
+
+#[derive(Args, Debug)]
+pub(super) struct PolicyArgs {
+    #[command(subcommand)]
+    pub command: PolicyCommand,
+}
+
+/// Commands for the authors of `MirrordPolicy` resources.
+#[derive(Subcommand, Debug)]
+pub(super) enum PolicyCommand {
+    /// Show which features of a session the policies in a file would block, without applying
+    /// them, e.g. `mirrord policy test policy.yaml -f .mirrord/mirrord.json`.
+    Test(Box<PolicyTestArgs>),
+}
+
+#[derive(Args, Debug)]
+pub(super) struct PolicyTestArgs {
+    /// YAML or JSON file with the `MirrordPolicy` resources, other resources in it are skipped.
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub policy: PathBuf,
+
+    /// Config of the session to test the policies with.
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// Target of the session, instead of the one in the config, e.g. `deployment/name`.
+    #[arg(short = 't', long)]
+    pub target: Option<String>,
+
+    /// Namespace of the target, instead of the one in the config.
+    #[arg(short = 'n', long)]
+    pub target_namespace: Option<String>,
+
+    /// Label of the target, `key=value`, for the policies with a `selector`. Can be given
+    /// multiple times.
+    #[arg(short = 'l', long = "label", value_parser = parse_label)]
+    pub labels: Vec<(String, String)>,
+}
+
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    label
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected `key=value`, got `{label}`"))
+}
+
+#[derive(Args, Debug)]
+pub(super) struct ConfigInitArgs {
+    /// Where to write the config, `.json`, `.toml` or `.yaml`.
+    #[arg(default_value = ".mirrord/mirrord.json", value_hint = ValueHint::FilePath)]
+    pub path: PathBuf,
+
+    /// Target of the config, e.g. `deployment/name`, or `targetless`.
+    #[arg(short = 't', long)]
+    pub target: Option<String>,
+
+    /// Namespace of the target.
+    #[arg(short = 'n', long)]
+    pub namespace: Option<String>,
+
+    /// File system mode of the config.
+    #[arg(long, value_enum)]
+    pub fs_mode: Option<FsMode>,
+
+    /// Steal the incoming traffic instead of mirroring it.
+    #[arg(long)]
+    pub steal: bool,
+
+    /// Don't ask anything, use the defaults for the values that aren't given as options.
+    #[arg(long)]
+    pub no_input: bool,
+
+    /// Overwrite the file if it exists.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct DnsArgs {
+    #[command(subcommand)]
+    pub command: DnsCommand,
+}
+
+/// Commands for resolving the names of the cluster outside of a mirrord session.
+#[derive(Subcommand, Debug)]
+pub(super) enum DnsCommand {
+    /// Run a local DNS server that resolves names through the agent, so that tools that mirrord
+    /// doesn't hook (browsers, `curl`) can resolve the names of the cluster, e.g.
+    /// `dig @127.0.0.1 -p 5353 my-svc.my-ns.svc.cluster.local`.
+    Serve(Box<DnsServeArgs>),
+}
+
+#[derive(Args, Debug)]
+pub(super) struct DnsServeArgs {
+    /// Target whose DNS resolves the names, e.g. `deployment/name`.
+    #[arg(short = 't', long)]
+    pub target: Option<String>,
+
+    /// Specify config file to use
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// UDP port of the DNS server.
+    #[arg(short = 'p', long, default_value_t = 5353)]
+    pub port: u16,
+
+    /// Address of the DNS server.
+    #[arg(long, default_value = "127.0.0.1")]
+    pub address: IpAddr,
+
+    /// Make the system resolver use this server for the names under `--domain` while it runs,
+    /// with `/etc/resolver` on macOS, and systemd-resolved or `/etc/resolv.conf` on Linux.
+    /// Requires root.
+    #[arg(long)]
+    pub configure_system: bool,
+
+    /// Domains resolved through the cluster with `--configure-system`. `/etc/resolv.conf` can't
+    /// be limited to some domains, there all names are resolved through the cluster.
+    #[arg(long = "domain", default_value = "cluster.local")]
+    pub domains: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub(super) struct EnvArgs {
+    /// Target to get the environment of, e.g. `deployment/name`.
+    #[arg(short = 't', long)]
+    pub target: Option<String>,
+
+    /// Specify config file to use
+    #[arg(short = 'f', long, value_hint = ValueHint::FilePath)]
+    pub config_file: Option<PathBuf>,
+
+    /// Output format.
+    #[arg(short = 'o', long, value_enum, default_value_t = EnvOutput::Dotenv)]
+    pub output: EnvOutput,
+}
+
+/// Output format of `mirrord env`.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub(super) enum EnvOutput {
+    /// One JSON object with all of the variables.
+    Json,
+    /// `KEY="value"` lines, for `.env` files.
+    Dotenv,
+}
+
+/// Output format of `mirrord dump`.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub(super) enum DumpFormat {
+    /// The data of each connection, as it arrives.
+    Raw,
+    /// One JSON object per HTTP request, printed as they arrive.
+    Jsonl,
+    /// An HTTP Archive with all of the HTTP requests, printed when the dump is stopped.
+    Har,
 }
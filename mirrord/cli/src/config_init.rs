@@ -0,0 +1,430 @@
+//! `mirrord config init` writes a starter config with a comment on every value, so new users
+//! don't have to copy old configs around.
+//!
+//! The values that aren't given as options are asked for when stdin is a terminal (unless
+//! `--no-input`). The written file is checked with [`load_and_verify`], like `mirrord
+//! verify-config` does.
+//!
+//! Comments in `.json` files are Tera comments (`{# ... #}`), which mirrord removes when it loads
+//! the config.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use clap::ValueEnum;
+use mirrord_config::{config::ConfigContext, target::Target};
+use mirrord_progress::{Progress, ProgressTracker};
+use serde_json::Value;
+
+use crate::{
+    verify_config::load_and_verify, CliError, ConfigCommand, ConfigInitArgs, FsMode, Result,
+};
+
+/// Handle `mirrord config`.
+pub(crate) fn config_command(command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Init(args) => config_init_command(*args),
+    }
+}
+
+/// Format of the written config, from the extension of its path.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// The choices that go into the config.
+#[derive(Debug)]
+struct Answers {
+    /// [`None`] leaves the target out, so that the IDEs ask for it.
+    target: Option<String>,
+    namespace: Option<String>,
+    fs_mode: FsMode,
+    steal: bool,
+}
+
+/// A key of the config, with the comment written above it.
+struct Entry {
+    key: &'static str,
+    comment: &'static str,
+    node: Node,
+}
+
+enum Node {
+    Value(Value),
+    Table(Vec<Entry>),
+}
+
+impl Entry {
+    fn value<V: Into<Value>>(key: &'static str, comment: &'static str, value: V) -> Self {
+        Self {
+            key,
+            comment,
+            node: Node::Value(value.into()),
+        }
+    }
+
+    fn table(key: &'static str, comment: &'static str, entries: Vec<Entry>) -> Self {
+        Self {
+            key,
+            comment,
+            node: Node::Table(entries),
+        }
+    }
+}
+
+/// Handle `mirrord config init`.
+fn config_init_command(args: ConfigInitArgs) -> Result<()> {
+    let path = args.path.clone();
+    let failed = |error: String| CliError::ConfigInitFailed(path.clone(), error);
+
+    let format =
+        Format::from_path(&path).ok_or_else(|| failed("unsupported file extension".to_string()))?;
+    if path.exists() && !args.force {
+        return Err(failed("the file already exists".to_string()));
+    }
+
+    let answers = if std::io::stdin().is_terminal() && !args.no_input {
+        ask(&args).map_err(|error| failed(error.to_string()))?
+    } else {
+        Answers {
+            target: args.target,
+            namespace: args.namespace,
+            fs_mode: args.fs_mode.unwrap_or(FsMode::Read),
+            steal: args.steal,
+        }
+    };
+
+    let mut progress = ProgressTracker::from_env("mirrord config init");
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(|error| failed(error.to_string()))?;
+    }
+    fs::write(&path, render(&template(&answers), format))
+        .map_err(|error| failed(error.to_string()))?;
+
+    let mut context = ConfigContext::default();
+    if let Err(error) = load_and_verify(&path, &mut context) {
+        // Don't leave a config that mirrord won't run with.
+        let _ = fs::remove_file(&path);
+        return Err(CliError::ConfigError(error));
+    }
+    for warning in context.get_warnings() {
+        progress.warning(warning);
+    }
+
+    progress.success(Some(&format!(
+        "wrote {}, run your application with `mirrord exec -f {} -- <command>`",
+        path.display(),
+        path.display()
+    )));
+
+    Ok(())
+}
+
+/// Asks for the values that weren't given as options.
+fn ask(args: &ConfigInitArgs) -> io::Result<Answers> {
+    let target = match args.target.clone() {
+        Some(target) => Some(target),
+        None => ask_value(
+            "Target, e.g. `deployment/name`, `pod/name` or `targetless` (empty to pick it in the \
+             IDE)",
+            "",
+            |value| {
+                if value.is_empty() {
+                    return Ok(None);
+                }
+                Target::from_str(value)
+                    .map(|_| Some(value.to_string()))
+                    .map_err(|error| error.to_string())
+            },
+        )?,
+    };
+
+    let namespace = match args.namespace.clone() {
+        Some(namespace) => Some(namespace),
+        None if target.as_deref() == Some("targetless") => None,
+        None => ask_value(
+            "Namespace of the target (empty for the one of the kube context)",
+            "",
+            |value| Ok((!value.is_empty()).then(|| value.to_string())),
+        )?,
+    };
+
+    let fs_mode = match args.fs_mode {
+        Some(fs_mode) => fs_mode,
+        None => ask_value(
+            "File system mode: `read`, `write`, `localwithoverrides` or `local`",
+            "read",
+            |value| FsMode::from_str(value, true),
+        )?,
+    };
+
+    let steal = args.steal
+        || ask_value(
+            "Incoming traffic: `mirror` a copy of it, or `steal` it from the target",
+            "mirror",
+            |value| match value {
+                "mirror" => Ok(false),
+                "steal" => Ok(true),
+                other => Err(format!("expected `mirror` or `steal`, got `{other}`")),
+            },
+        )?;
+
+    Ok(Answers {
+        target,
+        namespace,
+        fs_mode,
+        steal,
+    })
+}
+
+/// Asks until `parse` accepts the answer, an empty answer (or the end of stdin) is `default`.
+pub(crate) fn ask_value<T, F>(question: &str, default: &str, parse: F) -> io::Result<T>
+where
+    F: Fn(&str) -> Result<T, String>,
+{
+    let mut stdin = io::stdin().lock();
+
+    loop {
+        if default.is_empty() {
+            eprint!("{question}: ");
+        } else {
+            eprint!("{question} [{default}]: ");
+        }
+        io::stderr().flush()?;
+
+        let mut line = String::new();
+        let read = stdin.read_line(&mut line)?;
+        let answer = match line.trim() {
+            "" => default,
+            answer => answer,
+        };
+
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(error) if read > 0 => eprintln!("{error}"),
+            // Nothing more to read, asking again won't help.
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidInput, error)),
+        }
+    }
+}
+
+/// The config for the answers.
+fn template(answers: &Answers) -> Vec<Entry> {
+    let mut target = vec![];
+    if let Some(path) = answers.target.as_deref() {
+        target.push(Entry::value(
+            "path",
+            "`deployment/name`, `pod/name`, `rollout/name`, ..., or `targetless` to run without \
+             one.",
+            path,
+        ));
+    }
+    if let Some(namespace) = answers.namespace.as_deref() {
+        target.push(Entry::value(
+            "namespace",
+            "Namespace of the target, the one of the kube context when left out.",
+            namespace,
+        ));
+    }
+
+    let mut config = vec![];
+    if !target.is_empty() {
+        config.push(Entry::table(
+            "target",
+            "The resource in the cluster whose traffic, files and environment the local \
+             application gets.",
+            target,
+        ));
+    }
+
+    config.push(Entry::table(
+        "feature",
+        "What the local application gets from the target.",
+        vec![
+            Entry::value("env", "Environment variables of the target.", true),
+            Entry::table(
+                "fs",
+                "Files of the target.",
+                vec![Entry::value(
+                    "mode",
+                    "`read` reads them remotely and writes locally, `write` does both remotely, \
+                     `localwithoverrides` only reads some system files remotely, `local` doesn't \
+                     touch them.",
+                    answers.fs_mode.to_string(),
+                )],
+            ),
+            Entry::table(
+                "network",
+                "Network traffic of the target.",
+                vec![
+                    Entry::table(
+                        "incoming",
+                        "Traffic that arrives at the ports the local application listens on.",
+                        vec![Entry::value(
+                            "mode",
+                            "`mirror` sends the local application a copy of it, `steal` sends it \
+                             to the local application instead of the target.",
+                            if answers.steal { "steal" } else { "mirror" },
+                        )],
+                    ),
+                    Entry::value(
+                        "outgoing",
+                        "Connections of the local application are made from the target.",
+                        true,
+                    ),
+                    Entry::value("dns", "Names are resolved by the target.", true),
+                ],
+            ),
+        ],
+    ));
+
+    config
+}
+
+fn render(config: &[Entry], format: Format) -> String {
+    let mut output = String::new();
+    match format {
+        Format::Json => {
+            output.push_str("{\n");
+            render_json(&mut output, config, 1);
+            output.push_str("}\n");
+        }
+        Format::Yaml => render_yaml(&mut output, config, 0),
+        Format::Toml => render_toml(&mut output, config, ""),
+    }
+    output
+}
+
+fn render_json(output: &mut String, entries: &[Entry], depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for (index, entry) in entries.iter().enumerate() {
+        let _ = writeln!(output, "{indent}{{# {} #}}", entry.comment);
+        let _ = write!(output, "{indent}\"{}\": ", entry.key);
+        match &entry.node {
+            Node::Value(value) => output.push_str(&value.to_string()),
+            Node::Table(entries) => {
+                output.push_str("{\n");
+                render_json(output, entries, depth + 1);
+                let _ = write!(output, "{indent}}}");
+            }
+        }
+        output.push_str(if index + 1 < entries.len() {
+            ",\n"
+        } else {
+            "\n"
+        });
+    }
+}
+
+fn render_yaml(output: &mut String, entries: &[Entry], depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for entry in entries {
+        let _ = writeln!(output, "{indent}# {}", entry.comment);
+        match &entry.node {
+            Node::Value(value) => {
+                let _ = writeln!(output, "{indent}{}: {value}", entry.key);
+            }
+            Node::Table(entries) => {
+                let _ = writeln!(output, "{indent}{}:", entry.key);
+                render_yaml(output, entries, depth + 1);
+            }
+        }
+    }
+}
+
+/// Values first, then a `[table]` for each table, as TOML can't go back to the values of a table
+/// after a nested one.
+fn render_toml(output: &mut String, entries: &[Entry], prefix: &str) {
+    for entry in entries {
+        if let Node::Value(value) = &entry.node {
+            let _ = writeln!(output, "# {}\n{} = {value}", entry.comment, entry.key);
+        }
+    }
+
+    for entry in entries {
+        if let Node::Table(entries) = &entry.node {
+            let name = if prefix.is_empty() {
+                entry.key.to_string()
+            } else {
+                format!("{prefix}.{}", entry.key)
+            };
+
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            let _ = writeln!(output, "# {}\n[{name}]", entry.comment);
+            render_toml(output, entries, &name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use mirrord_config::{
+        feature::{fs::FsModeConfig, network::incoming::IncomingMode},
+        target::deployment::DeploymentTarget,
+    };
+
+    use super::*;
+
+    #[test]
+    fn every_format_loads() {
+        let answers = Answers {
+            target: Some("deployment/app".to_string()),
+            namespace: Some("staging".to_string()),
+            fs_mode: FsMode::LocalWithOverrides,
+            steal: true,
+        };
+        let dir =
+            std::env::temp_dir().join(format!("mirrord-config-init-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for name in ["mirrord.json", "mirrord.toml", "mirrord.yaml"] {
+            let path: PathBuf = dir.join(name);
+            let format = Format::from_path(&path).unwrap();
+            fs::write(&path, render(&template(&answers), format)).unwrap();
+
+            let config = load_and_verify(&path, &mut ConfigContext::default())
+                .unwrap_or_else(|error| panic!("{name} should load: {error}"));
+            assert_eq!(
+                config.target.path,
+                Some(Target::Deployment(DeploymentTarget {
+                    deployment: "app".to_string(),
+                    container: None,
+                }))
+            );
+            assert_eq!(config.target.namespace.as_deref(), Some("staging"));
+            assert_eq!(config.feature.fs.mode, FsModeConfig::LocalWithOverrides);
+            assert_eq!(config.feature.network.incoming.mode, IncomingMode::Steal);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
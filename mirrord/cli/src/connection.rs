@@ -2,11 +2,17 @@ use std::{collections::HashSet, time::Duration};
 
 use kube::{api::GroupVersionKind, discovery, Resource};
 use mirrord_analytics::Reporter;
-use mirrord_config::LayerConfig;
+use mirrord_config::{
+    feature::network::incoming::IncomingMode,
+    target::{cron_job::WaitForRun, Target},
+    LayerConfig,
+};
 use mirrord_intproxy::agent_conn::AgentConnectInfo;
 use mirrord_kube::{
     api::{
+        autoscaling::AutoscalingPause,
         kubernetes::{create_kube_api, KubernetesAPI},
+        runtime::cron_job::CronJobRun,
         wrap_raw_connection,
     },
     error::KubeApiError,
@@ -18,7 +24,12 @@ use mirrord_operator::{
 use mirrord_progress::{
     messages::MULTIPOD_WARNING, IdeAction, IdeMessage, NotificationLevel, Progress,
 };
-use mirrord_protocol::{ClientMessage, DaemonMessage};
+use mirrord_protocol::{
+    capabilities::{Capability, ProtocolCapabilities, CAPABILITIES_VERSION},
+    ClientMessage, DaemonMessage,
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
 use crate::{CliError, Result};
@@ -29,7 +40,7 @@ pub(crate) struct AgentConnection {
 }
 
 #[tracing::instrument(level = "trace", skip(config), ret, err)]
-async fn check_if_operator_resource_exists(config: &LayerConfig) -> Result<bool> {
+pub(crate) async fn check_if_operator_resource_exists(config: &LayerConfig) -> Result<bool> {
     let client = create_kube_api(
         config.accept_invalid_certificates,
         config.kubeconfig.clone(),
@@ -67,7 +78,7 @@ async fn check_if_operator_resource_exists(config: &LayerConfig) -> Result<bool>
 /// then we create the mirrord-agent and run mirrord by itself, without the operator.
 ///
 /// Here is where we start interactions with the kubernetes API.
-#[tracing::instrument(level = "trace", skip_all)]
+#[tracing::instrument(level = "info", name = "agent_connection", skip_all)]
 pub(crate) async fn create_and_connect<P, R: Reporter>(
     config: &LayerConfig,
     progress: &mut P,
@@ -81,7 +92,10 @@ where
 
         match OperatorApi::create_session(config, &subtask, analytics).await {
             Ok(session) => {
-                subtask.success(Some("connected to the operator"));
+                subtask.success(Some(&format!(
+                    "connected to the operator, session {:x}",
+                    session.info.session_id()
+                )));
 
                 return Ok((
                     AgentConnectInfo::Operator(session.info),
@@ -117,6 +131,12 @@ where
         return Err(CliError::FeatureRequiresOperatorError("copy_target".into()));
     }
 
+    if config.session.join.is_some() {
+        return Err(CliError::FeatureRequiresOperatorError(
+            "session.join".into(),
+        ));
+    }
+
     if matches!(
         config.target,
         mirrord_config::target::TargetConfig {
@@ -178,3 +198,301 @@ where
 }
 
 pub const AGENT_CONNECT_INFO_ENV_KEY: &str = "MIRRORD_AGENT_CONNECT_INFO";
+
+/// Receives the next message from the agent, skipping its logs.
+async fn recv_skipping_logs(connection: &mut AgentConnection) -> Result<DaemonMessage> {
+    loop {
+        match connection.receiver.recv().await {
+            Some(DaemonMessage::LogMessage(log)) => tracing::warn!("Agent: {}", log.message),
+            Some(DaemonMessage::Close(message)) => {
+                return Err(CliError::AgentHandshakeFailed(format!(
+                    "agent closed connection with message: {message}"
+                )))
+            }
+            Some(message) => return Ok(message),
+            None => {
+                return Err(CliError::AgentHandshakeFailed(
+                    "agent unexpectedly closed connection".into(),
+                ))
+            }
+        }
+    }
+}
+
+/// Negotiates the protocol with the agent, the same way the internal proxy will.
+///
+/// Returns the agreed protocol version and the capabilities supported by both sides.
+#[tracing::instrument(level = "trace", skip_all)]
+pub(crate) async fn negotiate_capabilities(
+    connection: &mut AgentConnection,
+) -> Result<(Version, ProtocolCapabilities)> {
+    let closed = || CliError::AgentHandshakeFailed("agent unexpectedly closed connection".into());
+
+    connection
+        .sender
+        .send(ClientMessage::SwitchProtocolVersion(
+            mirrord_protocol::VERSION.clone(),
+        ))
+        .await
+        .map_err(|_| closed())?;
+    let version = match recv_skipping_logs(connection).await? {
+        DaemonMessage::SwitchProtocolVersionResponse(version) => version,
+        other => {
+            return Err(CliError::AgentHandshakeFailed(format!(
+                "agent responded with an unexpected message: {other:?}"
+            )))
+        }
+    };
+
+    let capabilities = if CAPABILITIES_VERSION.matches(&version) {
+        connection
+            .sender
+            .send(ClientMessage::Capabilities(ProtocolCapabilities::all()))
+            .await
+            .map_err(|_| closed())?;
+        match recv_skipping_logs(connection).await? {
+            DaemonMessage::Capabilities(capabilities) => {
+                ProtocolCapabilities::from_agent_answer(capabilities, &version)
+            }
+            other => {
+                return Err(CliError::AgentHandshakeFailed(format!(
+                    "agent responded with an unexpected message: {other:?}"
+                )))
+            }
+        }
+    } else {
+        ProtocolCapabilities::from_version(&version)
+    };
+
+    Ok((version, capabilities))
+}
+
+/// Negotiates the protocol with the agent, and warns the user about the features that the agent
+/// is too old for, with a hint on how to get a matching agent, and about the features the config
+/// uses that the agent can't provide without its Linux capabilities (`agent.unprivileged`).
+///
+/// Otherwise the session would run with these features silently degraded.
+#[tracing::instrument(level = "trace", skip_all)]
+pub(crate) async fn check_agent_capabilities<P>(
+    config: &LayerConfig,
+    connect_info: &AgentConnectInfo,
+    connection: &mut AgentConnection,
+    progress: &mut P,
+) -> Result<()>
+where
+    P: Progress + Send + Sync,
+{
+    let (version, capabilities) = negotiate_capabilities(connection).await?;
+
+    let unavailable = Capability::ALL
+        .iter()
+        .copied()
+        .filter(|capability| !capabilities.supports(*capability))
+        .filter_map(|capability| {
+            let requirement = capability.agent_requirement()?;
+            let description = used_agent_feature(config, capability)?;
+            Some(format!("{description} ({requirement})"))
+        })
+        .collect::<Vec<_>>();
+    if !unavailable.is_empty() {
+        progress.warning(&format!(
+            "The agent runs without the Linux capabilities these features need, so they are not \
+             available in this session: {}.",
+            unavailable.join(", ")
+        ));
+    }
+
+    let missing = Capability::ALL
+        .iter()
+        .filter(|capability| capability.agent_requirement().is_none())
+        .filter(|capability| !capabilities.supports(**capability))
+        .map(|capability| capability.name())
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    progress.warning(&format!(
+        "The agent speaks mirrord-protocol {version}, older than the {} of this mirrord, so \
+         these features are not available in this session: {}.",
+        *mirrord_protocol::VERSION,
+        missing.join(", ")
+    ));
+
+    let hint = match connect_info {
+        AgentConnectInfo::Operator(..) => "The agent is started by the mirrord operator, update \
+             the operator to the version of this mirrord to get them."
+            .to_string(),
+        AgentConnectInfo::DirectKubernetes(..) if !config.agent.image.is_current_version() => {
+            format!(
+                "`agent.image` is pinned to `{}`, use `{}` or run with `--update-agent` to get \
+                 them.",
+                config.agent.image(),
+                config.agent.image.with_current_version().0,
+            )
+        }
+        AgentConnectInfo::DirectKubernetes(..) => format!(
+            "Make sure that the agent image `{}` is up to date (`agent.image_pull_policy`).",
+            config.agent.image()
+        ),
+    };
+    progress.warning(&hint);
+
+    Ok(())
+}
+
+/// Describes an agent feature (see [`Capability::agent_requirement`]), `None` when `config`
+/// doesn't use it.
+fn used_agent_feature(config: &LayerConfig, capability: Capability) -> Option<&'static str> {
+    let incoming = &config.feature.network.incoming.mode;
+
+    match capability {
+        Capability::IncomingMirror if matches!(incoming, IncomingMode::Mirror) => {
+            Some("mirroring incoming traffic")
+        }
+        Capability::IncomingSteal if matches!(incoming, IncomingMode::Steal) => {
+            Some("stealing incoming traffic")
+        }
+        Capability::RemoteEnv => Some("the remote environment"),
+        Capability::RemoteFs if !config.feature.fs.mode.is_local() => {
+            Some("the remote file system")
+        }
+        _ => None,
+    }
+}
+
+/// Passes the [`CronJobSession`] to the internal proxy, so it can end the session when the Job
+/// finishes.
+pub const CRON_JOB_RUN_ENV_KEY: &str = "MIRRORD_CRON_JOB_RUN";
+
+/// A session that targets the pod of a [`CronJobRun`], see [`wait_for_cron_job_run`].
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CronJobSession {
+    pub run: CronJobRun,
+    /// The application, when `mirrord exec` started it, stopped when the Job finishes.
+    pub app_pid: Option<i32>,
+}
+
+/// With [`TargetConfig::wait_for_run`](mirrord_config::target::TargetConfig::wait_for_run), waits
+/// for the CronJob target to start a Job.
+///
+/// Returns the [`CronJobRun`] with a copy of the [`LayerConfig`] that targets its pod, so the rest
+/// of the session doesn't have to know about the CronJob.
+#[tracing::instrument(level = "trace", skip_all)]
+pub(crate) async fn wait_for_cron_job_run<P>(
+    config: &LayerConfig,
+    progress: &P,
+) -> Result<Option<(CronJobRun, LayerConfig)>>
+where
+    P: Progress + Send + Sync,
+{
+    let (Some(Target::CronJob(target)), Some(wait_for_run)) =
+        (config.target.path.as_ref(), config.target.wait_for_run)
+    else {
+        return Ok(None);
+    };
+
+    let mut subtask = progress.subtask(&match wait_for_run {
+        WaitForRun::Next => format!("waiting for the next run of cronjob/{}", target.cron_job),
+        WaitForRun::Trigger => format!("starting a run of cronjob/{}", target.cron_job),
+    });
+
+    let client = create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    .map_err(CliError::CreateKubeApiFailed)?;
+
+    let run = CronJobRun::start(
+        &client,
+        target,
+        config.target.namespace.as_deref(),
+        wait_for_run,
+    )
+    .await
+    .map_err(CliError::CronJobRunFailed)?;
+
+    subtask.success(Some(&format!(
+        "job/{} is running in pod/{}",
+        run.job, run.pod
+    )));
+
+    let mut run_config = config.clone();
+    run_config.target.path = Some(run.target());
+    run_config.target.wait_for_run = None;
+
+    Ok(Some((run, run_config)))
+}
+
+/// Env var with the [`AutoscalingPause`] of the session, the internal proxy resumes the
+/// autoscalers when the session ends.
+pub const PAUSED_AUTOSCALING_ENV_KEY: &str = "MIRRORD_PAUSED_AUTOSCALING";
+
+/// With [`FeatureConfig::pause_autoscaling`](mirrord_config::feature::FeatureConfig::pause_autoscaling),
+/// pauses the autoscalers of the target.
+///
+/// Failing to pause them doesn't stop the session, the user only gets a warning.
+#[tracing::instrument(level = "trace", skip_all)]
+pub(crate) async fn pause_autoscaling<P>(
+    config: &LayerConfig,
+    progress: &P,
+) -> Option<AutoscalingPause>
+where
+    P: Progress + Send + Sync,
+{
+    let target = config
+        .target
+        .path
+        .as_ref()
+        .filter(|_| config.feature.pause_autoscaling)?;
+
+    let mut subtask = progress.subtask(&format!("pausing the autoscalers of {target}"));
+
+    let pause = match create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    {
+        Ok(client) => {
+            AutoscalingPause::pause(&client, target, config.target.namespace.as_deref()).await
+        }
+        Err(error) => Err(error),
+    };
+
+    let pause = match pause {
+        Ok(pause) => pause,
+        Err(error) => {
+            subtask.failure(Some(&format!(
+                "failed to pause the autoscalers, they may scale the target during the session: \
+                 {error}"
+            )));
+            return None;
+        }
+    };
+
+    for skipped in &pause.skipped {
+        subtask.warning(&format!("{skipped}, not pausing it"));
+    }
+
+    let autoscalers = pause
+        .scaled_objects
+        .iter()
+        .map(|name| format!("scaledobject/{name}"))
+        .chain(pause.hpas.iter().map(|name| format!("hpa/{name}")))
+        .collect::<Vec<_>>();
+    if autoscalers.is_empty() {
+        subtask.success(Some("no autoscalers to pause"));
+        return None;
+    }
+
+    subtask.success(Some(&format!(
+        "paused {} until the session ends",
+        autoscalers.join(", ")
+    )));
+
+    Some(pause)
+}
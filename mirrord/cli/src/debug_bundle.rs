@@ -0,0 +1,334 @@
+//! `mirrord debug-bundle` gathers what we need to debug an issue into a single `.tar.gz`, so the
+//! user doesn't have to collect it by hand:
+//!
+//! - the resolved config, and the config file as it was given;
+//! - the versions of mirrord, the operator and the cluster;
+//! - the environment (OS, `MIRRORD_*` variables, the local sessions from `mirrord status`);
+//! - the internal proxy logs (`internal_proxy.log_destination` and the rotated files);
+//! - the agent pods in the agent namespace, with their events and logs.
+//!
+//! Whatever can't be collected is listed in `errors.txt`, the rest of the bundle is still
+//! written.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use k8s_openapi::api::core::v1::{Event, Pod};
+use kube::{
+    api::{ListParams, LogParams},
+    Api, Client, ResourceExt,
+};
+use mirrord_config::LayerConfig;
+use mirrord_kube::api::kubernetes::create_kube_api;
+use mirrord_operator::crd::{MirrordOperatorCrd, OPERATOR_STATUS_NAME};
+use mirrord_progress::{Progress, ProgressTracker};
+
+use crate::{diagnose::load_config, status, CliError, DebugBundleArgs, Result};
+
+/// Label of the agent pods that the CLI creates.
+const AGENT_POD_LABELS: &str = "app=mirrord";
+
+/// Environment variables with these in their names are left out of the bundle.
+const SECRET_VARIABLE_HINTS: &[&str] = &["TOKEN", "SECRET", "PASSWORD", "KEY", "CREDENTIAL"];
+
+/// The archive being written, with what couldn't be collected.
+struct Bundle {
+    archive: tar::Builder<GzEncoder<File>>,
+    errors: Vec<String>,
+}
+
+impl Bundle {
+    fn add(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        header.set_cksum();
+
+        self.archive.append_data(&mut header, name, contents)
+    }
+
+    /// Adds the local file at `path` as `name`, or records why it couldn't.
+    fn add_file(&mut self, name: &str, path: &Path) -> io::Result<()> {
+        match File::open(path) {
+            Ok(mut file) => self.archive.append_file(name, &mut file),
+            Err(error) => {
+                self.error(format!("{}: {error}", path.display()));
+                Ok(())
+            }
+        }
+    }
+
+    fn error(&mut self, error: String) {
+        self.errors.push(error);
+    }
+}
+
+/// Handle `mirrord debug-bundle`.
+pub(crate) async fn debug_bundle_command(args: DebugBundleArgs) -> Result<()> {
+    let mut progress = ProgressTracker::from_env("mirrord debug-bundle");
+
+    let output = args.output.clone().unwrap_or_else(|| {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        PathBuf::from(format!("mirrord-debug-bundle-{timestamp}.tar.gz"))
+    });
+
+    let file = File::create(&output)
+        .map_err(|error| CliError::DebugBundleFailed(output.clone(), error))?;
+    let mut bundle = Bundle {
+        archive: tar::Builder::new(GzEncoder::new(file, Compression::default())),
+        errors: vec![],
+    };
+
+    collect(&mut bundle, &args, &mut progress)
+        .await
+        .map_err(|error| CliError::DebugBundleFailed(output.clone(), error))?;
+
+    let errors = std::mem::take(&mut bundle.errors);
+    if !errors.is_empty() {
+        bundle
+            .add("errors.txt", errors.join("\n").as_bytes())
+            .map_err(|error| CliError::DebugBundleFailed(output.clone(), error))?;
+    }
+    bundle
+        .archive
+        .into_inner()
+        .and_then(GzEncoder::finish)
+        .and_then(|mut file| file.flush())
+        .map_err(|error| CliError::DebugBundleFailed(output.clone(), error))?;
+
+    if !errors.is_empty() {
+        progress.warning(&format!(
+            "{} item(s) could not be collected, see `errors.txt` in the bundle",
+            errors.len()
+        ));
+    }
+    progress.success(Some(&format!(
+        "wrote {}, please attach it to your issue or send it to us, after checking that it \
+         contains nothing you can't share",
+        output.display()
+    )));
+
+    Ok(())
+}
+
+/// Adds everything to the bundle, only failing when the archive can't be written.
+async fn collect(
+    bundle: &mut Bundle,
+    args: &DebugBundleArgs,
+    progress: &mut ProgressTracker,
+) -> io::Result<()> {
+    bundle.add("environment.txt", environment().as_bytes())?;
+
+    match status::running_sessions(&status::sessions_dir()) {
+        Ok(sessions) => bundle.add(
+            "sessions.json",
+            &serde_json::to_vec_pretty(&sessions).map_err(io::Error::from)?,
+        )?,
+        Err(error) => bundle.error(format!("local sessions: {error}")),
+    }
+
+    if let Some(path) = args.config_file.as_deref() {
+        let name = format!(
+            "config/{}",
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "config".to_string())
+        );
+        bundle.add_file(&name, path)?;
+    }
+
+    let config = match load_config(args.config_file.as_deref()) {
+        Ok(config) => config,
+        Err(error) => {
+            // Without the config we don't know the cluster nor the log files.
+            bundle.error(format!("config: {error}"));
+            bundle.add("versions.txt", versions(None, None).as_bytes())?;
+            return Ok(());
+        }
+    };
+    bundle.add("config/resolved.txt", format!("{config:#?}").as_bytes())?;
+
+    if let Some(log_file) = config.internal_proxy.log_destination.as_deref() {
+        let log_file = Path::new(log_file);
+        bundle.add_file("intproxy/intproxy.log", log_file)?;
+
+        for index in 1..=config.internal_proxy.log_rotation.max_files {
+            let mut rotated = log_file.as_os_str().to_owned();
+            rotated.push(format!(".{index}"));
+            let rotated = PathBuf::from(rotated);
+            if rotated.exists() {
+                bundle.add_file(&format!("intproxy/intproxy.log.{index}"), &rotated)?;
+            }
+        }
+    }
+
+    let mut cluster_progress = progress.subtask("collecting from the cluster");
+    let client = match create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => {
+            bundle.error(format!("cluster: {error}"));
+            bundle.add("versions.txt", versions(None, None).as_bytes())?;
+            cluster_progress.failure(Some("failed to reach the cluster"));
+            return Ok(());
+        }
+    };
+
+    let cluster_version = match client.apiserver_version().await {
+        Ok(version) => Some(version.git_version),
+        Err(error) => {
+            bundle.error(format!("cluster version: {error}"));
+            None
+        }
+    };
+    let operator_version = match Api::<MirrordOperatorCrd>::all(client.clone())
+        .get(OPERATOR_STATUS_NAME)
+        .await
+    {
+        Ok(operator) => Some(operator.spec.operator_version),
+        Err(error) => {
+            bundle.error(format!("operator: {error}"));
+            None
+        }
+    };
+    bundle.add(
+        "versions.txt",
+        versions(cluster_version.as_deref(), operator_version.as_deref()).as_bytes(),
+    )?;
+
+    collect_agents(bundle, client, &config, args.agent_log_lines).await?;
+    cluster_progress.success(Some("collected from the cluster"));
+
+    Ok(())
+}
+
+/// Adds the agent pods, their events and logs.
+async fn collect_agents(
+    bundle: &mut Bundle,
+    client: Client,
+    config: &LayerConfig,
+    log_lines: i64,
+) -> io::Result<()> {
+    let pod_api: Api<Pod> = match config.agent.namespace.as_deref() {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::default_namespaced(client.clone()),
+    };
+    let event_api: Api<Event> = match config.agent.namespace.as_deref() {
+        Some(namespace) => Api::namespaced(client, namespace),
+        None => Api::default_namespaced(client),
+    };
+
+    let pods = match pod_api
+        .list(&ListParams::default().labels(AGENT_POD_LABELS))
+        .await
+    {
+        Ok(pods) => pods,
+        Err(error) => {
+            bundle.error(format!("agent pods: {error}"));
+            return Ok(());
+        }
+    };
+
+    for pod in pods {
+        let name = pod.name_any();
+
+        match serde_yaml::to_string(&pod) {
+            Ok(yaml) => bundle.add(&format!("agents/{name}/pod.yaml"), yaml.as_bytes())?,
+            Err(error) => bundle.error(format!("agent pod {name}: {error}")),
+        }
+
+        let events = event_api
+            .list(&ListParams::default().fields(&format!("involvedObject.name={name}")))
+            .await;
+        match events {
+            Ok(events) => {
+                let events = events
+                    .into_iter()
+                    .map(|event| {
+                        format!(
+                            "{} {} {}: {}",
+                            event
+                                .last_timestamp
+                                .map(|time| time.0.to_rfc3339())
+                                .unwrap_or_default(),
+                            event.type_.unwrap_or_default(),
+                            event.reason.unwrap_or_default(),
+                            event.message.unwrap_or_default()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                bundle.add(&format!("agents/{name}/events.txt"), events.as_bytes())?;
+            }
+            Err(error) => bundle.error(format!("agent pod {name} events: {error}")),
+        }
+
+        let logs = pod_api
+            .logs(
+                &name,
+                &LogParams {
+                    tail_lines: Some(log_lines),
+                    ..Default::default()
+                },
+            )
+            .await;
+        match logs {
+            Ok(logs) => bundle.add(&format!("agents/{name}/logs.txt"), logs.as_bytes())?,
+            Err(error) => bundle.error(format!("agent pod {name} logs: {error}")),
+        }
+    }
+
+    Ok(())
+}
+
+fn versions(cluster: Option<&str>, operator: Option<&str>) -> String {
+    format!(
+        "mirrord: {}\nmirrord-protocol: {}\noperator: {}\nkubernetes: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        *mirrord_protocol::VERSION,
+        operator.unwrap_or("unknown"),
+        cluster.unwrap_or("unknown"),
+    )
+}
+
+/// The OS and the mirrord environment variables, without the ones that look like secrets.
+fn environment() -> String {
+    let mut lines = vec![
+        format!("os: {}", std::env::consts::OS),
+        format!("arch: {}", std::env::consts::ARCH),
+    ];
+
+    let mut variables = std::env::vars()
+        .filter(|(name, _)| name.starts_with("MIRRORD_"))
+        .map(|(name, value)| {
+            if SECRET_VARIABLE_HINTS.iter().any(|hint| name.contains(hint)) {
+                format!("{name}=<redacted>")
+            } else {
+                format!("{name}={value}")
+            }
+        })
+        .collect::<Vec<_>>();
+    variables.sort();
+    lines.extend(variables);
+
+    lines.join("\n")
+}
@@ -1,17 +1,29 @@
 use std::{path::Path, time::Duration};
 
+use kube::Client;
 use mirrord_analytics::NullReporter;
 use mirrord_config::{
     config::{ConfigContext, MirrordConfig},
-    LayerFileConfig,
+    LayerConfig, LayerFileConfig,
+};
+use mirrord_kube::{
+    api::{kubernetes::create_kube_config, proxy::redact_credentials},
+    error::KubeApiError,
 };
 use mirrord_progress::{Progress, ProgressTracker};
-use mirrord_protocol::{ClientMessage, DaemonMessage};
+use mirrord_protocol::{
+    file::{
+        CloseFileRequest, OpenFileRequest, OpenFileResponse, OpenOptionsInternal, ReadFileRequest,
+        WriteFileRequest,
+    },
+    ClientMessage, DaemonMessage, FileRequest, FileResponse,
+};
 use tokio::{sync::mpsc, time::Instant};
 
 use crate::{
-    connection::create_and_connect, util::remove_proxy_env, CliError, DiagnoseArgs,
-    DiagnoseCommand, Result,
+    connection::{create_and_connect, AgentConnection},
+    util::set_proxy_env,
+    CliError, DiagnoseArgs, DiagnoseCommand, Result,
 };
 
 /// Sends a ping the connection and expects a pong.
@@ -44,11 +56,9 @@ async fn ping(
     }
 }
 
-/// Create a targetless session and run pings to diagnose network latency.
-#[tracing::instrument(level = "trace", ret)]
-async fn diagnose_latency(config: Option<&Path>) -> Result<()> {
-    let mut progress = ProgressTracker::from_env("mirrord network diagnosis");
-
+/// Loads the [`LayerConfig`] from the given file, or the default one, and prepares the proxy
+/// environment variables.
+pub(crate) fn load_config(config: Option<&Path>) -> Result<LayerConfig> {
     let mut cfg_context = ConfigContext::default();
     let config = if let Some(path) = config {
         LayerFileConfig::from_path(path)?.generate_config(&mut cfg_context)
@@ -56,9 +66,17 @@ async fn diagnose_latency(config: Option<&Path>) -> Result<()> {
         LayerFileConfig::default().generate_config(&mut cfg_context)
     }?;
 
-    if !config.use_proxy {
-        remove_proxy_env();
-    }
+    set_proxy_env(&config);
+
+    Ok(config)
+}
+
+/// Create a targetless session and run pings to diagnose network latency.
+#[tracing::instrument(level = "trace", ret)]
+async fn diagnose_latency(config: Option<&Path>) -> Result<()> {
+    let mut progress = ProgressTracker::from_env("mirrord network diagnosis");
+
+    let config = load_config(config)?;
 
     let mut analytics = NullReporter::default();
     let (_, mut connection) = create_and_connect(&config, &mut progress, &mut analytics).await?;
@@ -96,9 +114,271 @@ async fn diagnose_latency(config: Option<&Path>) -> Result<()> {
     Ok(())
 }
 
+/// Size of a single chunk transferred in [`diagnose_bandwidth`].
+const BANDWIDTH_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// How many chunks are transferred in each direction in [`diagnose_bandwidth`].
+const BANDWIDTH_CHUNKS: usize = 32;
+
+/// How many chunk requests are sent to the agent ahead of the responses, so that the measured
+/// throughput is not bound by the RTT.
+const BANDWIDTH_REQUESTS_IN_FLIGHT: usize = 4;
+
+/// How many pings are used to measure the RTT in [`diagnose_bandwidth`].
+const BANDWIDTH_PINGS: usize = 20;
+
+/// Below this throughput (bytes per second), reading remote files is likely to slow down the
+/// application.
+const SLOW_THROUGHPUT: f64 = 5.0 * 1024.0 * 1024.0;
+
+/// Above this RTT, each remote file operation adds a noticeable delay.
+const SLOW_RTT: Duration = Duration::from_millis(50);
+
+/// Sends a [`FileRequest`] to the agent.
+async fn send_file_request(connection: &AgentConnection, request: FileRequest) -> Result<()> {
+    connection
+        .sender
+        .send(ClientMessage::FileRequest(request))
+        .await
+        .map_err(|_| {
+            CliError::BandwidthTestFailed("agent unexpectedly closed connection".to_string())
+        })
+}
+
+/// Waits for the next [`FileResponse`] from the agent.
+async fn file_response(connection: &mut AgentConnection) -> Result<FileResponse> {
+    loop {
+        let result = match connection.receiver.recv().await {
+            Some(DaemonMessage::File(response)) => Ok(response),
+            Some(DaemonMessage::LogMessage(..)) => continue,
+            Some(DaemonMessage::Close(message)) => Err(CliError::BandwidthTestFailed(format!(
+                "agent closed connection with message: {message}"
+            ))),
+            Some(message) => Err(CliError::BandwidthTestFailed(format!(
+                "agent sent an unexpected message: {message:?}"
+            ))),
+            None => Err(CliError::BandwidthTestFailed(
+                "agent unexpectedly closed connection".to_string(),
+            )),
+        };
+
+        return result;
+    }
+}
+
+/// Opens a file in the agent and returns its remote fd.
+async fn open_remote(
+    connection: &mut AgentConnection,
+    path: &str,
+    open_options: OpenOptionsInternal,
+) -> Result<u64> {
+    let request = FileRequest::Open(OpenFileRequest {
+        path: path.into(),
+        open_options,
+    });
+    send_file_request(connection, request).await?;
+
+    match file_response(connection).await? {
+        FileResponse::Open(Ok(OpenFileResponse { fd })) => Ok(fd),
+        FileResponse::Open(Err(error)) => Err(CliError::BandwidthTestFailed(format!(
+            "failed to open {path} in the agent: {error}"
+        ))),
+        other => Err(CliError::BandwidthTestFailed(format!(
+            "agent sent an unexpected response: {other:?}"
+        ))),
+    }
+}
+
+/// Sends [`BANDWIDTH_CHUNKS`] copies of the given read or write `request` to the agent and
+/// returns the throughput in bytes per second.
+async fn measure_throughput(connection: &mut AgentConnection, request: FileRequest) -> Result<f64> {
+    let start = Instant::now();
+    let mut sent = 0;
+    let mut transferred = 0;
+
+    while sent < BANDWIDTH_REQUESTS_IN_FLIGHT.min(BANDWIDTH_CHUNKS) {
+        send_file_request(connection, request.clone()).await?;
+        sent += 1;
+    }
+
+    for _ in 0..BANDWIDTH_CHUNKS {
+        transferred += match file_response(connection).await? {
+            FileResponse::Read(Ok(response)) => response.read_amount,
+            FileResponse::Write(Ok(response)) => response.written_amount,
+            FileResponse::Read(Err(error)) | FileResponse::Write(Err(error)) => {
+                return Err(CliError::BandwidthTestFailed(error.to_string()))
+            }
+            other => {
+                return Err(CliError::BandwidthTestFailed(format!(
+                    "agent sent an unexpected response: {other:?}"
+                )))
+            }
+        };
+
+        if sent < BANDWIDTH_CHUNKS {
+            send_file_request(connection, request.clone()).await?;
+            sent += 1;
+        }
+    }
+
+    Ok(transferred as f64 / start.elapsed().as_secs_f64())
+}
+
+/// Create a session and measure RTT and throughput over the agent connection.
+///
+/// Throughput is measured with file operations, by reading from `/dev/zero` and writing to
+/// `/dev/null` in the agent, so the data goes through the same path as in a mirrord session.
+#[tracing::instrument(level = "trace", ret)]
+async fn diagnose_bandwidth(target: Option<&str>, config: Option<&Path>) -> Result<()> {
+    if let Some(target) = target {
+        std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target);
+    }
+
+    let mut progress = ProgressTracker::from_env("mirrord bandwidth diagnosis");
+
+    let config = load_config(config)?;
+
+    let mut analytics = NullReporter::default();
+    let (_, mut connection) = create_and_connect(&config, &mut progress, &mut analytics).await?;
+
+    // ignore first ping as it's part of the initialization.
+    ping(&connection.sender, &mut connection.receiver).await?;
+    let mut rtts = Vec::with_capacity(BANDWIDTH_PINGS);
+    for _ in 0..BANDWIDTH_PINGS {
+        let start = Instant::now();
+        ping(&connection.sender, &mut connection.receiver).await?;
+        rtts.push(start.elapsed());
+    }
+    let rtt = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+    progress.info(&format!(
+        "RTT: min={}ms, max={}ms, avg={}ms",
+        rtts.iter().min().expect("never empty").as_millis(),
+        rtts.iter().max().expect("never empty").as_millis(),
+        rtt.as_millis(),
+    ));
+
+    let fd = open_remote(
+        &mut connection,
+        "/dev/zero",
+        OpenOptionsInternal {
+            read: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+    let download = measure_throughput(
+        &mut connection,
+        FileRequest::Read(ReadFileRequest {
+            remote_fd: fd,
+            buffer_size: BANDWIDTH_CHUNK_SIZE,
+        }),
+    )
+    .await?;
+    send_file_request(&connection, FileRequest::Close(CloseFileRequest { fd })).await?;
+
+    let fd = open_remote(
+        &mut connection,
+        "/dev/null",
+        OpenOptionsInternal {
+            write: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+    let upload = measure_throughput(
+        &mut connection,
+        FileRequest::Write(WriteFileRequest {
+            fd,
+            write_bytes: vec![0; BANDWIDTH_CHUNK_SIZE as usize],
+        }),
+    )
+    .await?;
+    send_file_request(&connection, FileRequest::Close(CloseFileRequest { fd })).await?;
+
+    let mib = |bytes_per_second: f64| bytes_per_second / (1024.0 * 1024.0);
+    progress.info(&format!(
+        "Throughput: download (agent to local)={:.1}MiB/s, upload (local to agent)={:.1}MiB/s",
+        mib(download),
+        mib(upload),
+    ));
+
+    if download < SLOW_THROUGHPUT {
+        progress.warning(
+            "Throughput from the agent is low, reading large remote files will be slow. \
+            Consider reading them locally, with `feature.fs.local` or `feature.fs.rules`.",
+        );
+    }
+    if rtt > SLOW_RTT {
+        progress.warning(
+            "RTT to the agent is high, every remote file operation adds this delay. \
+            Consider reading frequently accessed files locally, with `feature.fs.local` or \
+            `feature.fs.rules`.",
+        );
+    }
+
+    progress.success(Some(&format!(
+        "Bandwidth statistics: rtt={}ms, download={:.1}MiB/s, upload={:.1}MiB/s",
+        rtt.as_millis(),
+        mib(download),
+        mib(upload),
+    )));
+
+    Ok(())
+}
+
+/// Report the proxy used to reach the Kubernetes API server, and check that the server is
+/// reachable through it.
+#[tracing::instrument(level = "trace", ret)]
+async fn diagnose_proxy(config: Option<&Path>) -> Result<()> {
+    let mut progress = ProgressTracker::from_env("mirrord proxy diagnosis");
+
+    let config = load_config(config)?;
+    if !config.use_proxy {
+        progress.info("`use_proxy` is disabled, the proxy environment variables are ignored");
+    }
+
+    let kube_config = create_kube_config(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    .map_err(CliError::CreateKubeApiFailed)?;
+
+    progress.info(&format!("Cluster: {}", kube_config.cluster_url));
+    match kube_config.proxy_url.as_ref() {
+        Some(proxy_url) => progress.info(&format!(
+            "Proxy: {}",
+            redact_credentials(&proxy_url.to_string())
+        )),
+        None => progress.info("Proxy: none, connecting directly"),
+    }
+
+    let client = Client::try_from(kube_config)
+        .map_err(KubeApiError::from)
+        .map_err(CliError::CreateKubeApiFailed)?;
+    let version = client
+        .apiserver_version()
+        .await
+        .map_err(KubeApiError::from)
+        .map_err(CliError::ClusterUnreachable)?;
+
+    progress.success(Some(&format!(
+        "Reached the Kubernetes API server (version {})",
+        version.git_version
+    )));
+
+    Ok(())
+}
+
 /// Handle commands related to the operator `mirrord diagnose ...`
 pub(crate) async fn diagnose_command(args: DiagnoseArgs) -> Result<()> {
     match args.command {
         DiagnoseCommand::Latency { config_file } => diagnose_latency(config_file.as_deref()).await,
+        DiagnoseCommand::Bandwidth {
+            target,
+            config_file,
+        } => diagnose_bandwidth(target.as_deref(), config_file.as_deref()).await,
+        DiagnoseCommand::Proxy { config_file } => diagnose_proxy(config_file.as_deref()).await,
     }
 }
@@ -0,0 +1,451 @@
+//! `mirrord dns serve` runs a local DNS server that resolves names through the agent, like the
+//! `getaddrinfo` hook of the layer does for the application.
+//!
+//! It's for the tools that mirrord doesn't hook, e.g. a browser or `curl`. They can be pointed at
+//! the server directly, or with `--configure-system` the system resolver sends it the queries for
+//! the cluster domains (see [`SystemResolver`]). Only `A` and `AAAA` queries are resolved, the
+//! other types get an empty answer.
+
+use std::{
+    collections::VecDeque,
+    fs, io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use hickory_resolver::proto::{
+    op::{Message, MessageType, ResponseCode},
+    rr::{
+        rdata::{A, AAAA},
+        RData, Record, RecordType,
+    },
+};
+use mirrord_analytics::NullReporter;
+use mirrord_progress::{Progress, ProgressTracker};
+use mirrord_protocol::{
+    dns::{GetAddrInfoRequest, GetAddrInfoResponse},
+    ClientMessage, DaemonMessage, DnsLookupError, ResolveErrorKindInternal, ResponseError,
+};
+use tokio::{net::UdpSocket, time};
+use tracing::{debug, warn};
+
+use crate::{
+    connection::{create_and_connect, AgentConnection},
+    diagnose::load_config,
+    CliError, DnsCommand, DnsServeArgs, Result,
+};
+
+/// How often we ping the agent while idle, so it doesn't consider us gone.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// TTL of the answers, short because the names of the cluster change often.
+const ANSWER_TTL: u32 = 5;
+
+/// Big enough for the queries with EDNS.
+const MAX_QUERY_SIZE: usize = 4096;
+
+/// Query waiting for its [`GetAddrInfoResponse`], the agent answers in order.
+struct PendingQuery {
+    peer: SocketAddr,
+    query: Message,
+}
+
+/// Handle `mirrord dns`.
+pub(crate) async fn dns_command(command: DnsCommand) -> Result<()> {
+    match command {
+        DnsCommand::Serve(args) => serve_command(*args).await,
+    }
+}
+
+async fn serve_command(args: DnsServeArgs) -> Result<()> {
+    if let Some(target) = args.target.as_deref() {
+        std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target);
+    }
+
+    let mut progress = ProgressTracker::from_env("mirrord dns serve");
+
+    let config = load_config(args.config_file.as_deref())?;
+
+    let socket = UdpSocket::bind((args.address, args.port))
+        .await
+        .map_err(|error| {
+            CliError::DnsServeFailed(format!(
+                "failed to bind {}:{}: {error}",
+                args.address, args.port
+            ))
+        })?;
+
+    let mut analytics = NullReporter::default();
+    let (_, mut connection) = create_and_connect(&config, &mut progress, &mut analytics).await?;
+    progress.success(Some("connected to the agent"));
+
+    let system_resolver = if args.configure_system {
+        let resolver =
+            SystemResolver::configure(args.address, args.port, &args.domains).map_err(|error| {
+                CliError::DnsServeFailed(format!(
+                    "failed to configure the system resolver: {error}"
+                ))
+            })?;
+        Some(resolver)
+    } else {
+        None
+    };
+
+    eprintln!(
+        "Resolving names through the cluster at {}:{} (UDP), press Ctrl+C to stop.",
+        args.address, args.port
+    );
+
+    let result = serve(&mut connection, &socket).await;
+
+    if let Some(resolver) = system_resolver {
+        if let Err(error) = resolver.restore() {
+            warn!(%error, "Failed to restore the system resolver");
+            eprintln!("Failed to restore the system resolver configuration: {error}");
+        }
+    }
+
+    result
+}
+
+/// Answers the queries until Ctrl+C.
+async fn serve(connection: &mut AgentConnection, socket: &UdpSocket) -> Result<()> {
+    let closed = || CliError::DnsServeFailed("agent unexpectedly closed connection".into());
+
+    let mut pending = VecDeque::<PendingQuery>::new();
+    let mut buf = vec![0; MAX_QUERY_SIZE];
+    let mut ping = time::interval(PING_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+
+            _ = ping.tick() => {
+                connection.sender.send(ClientMessage::Ping).await.map_err(|_| closed())?;
+            }
+
+            result = socket.recv_from(&mut buf) => {
+                let (len, peer) = match result {
+                    Ok(received) => received,
+                    Err(error) => {
+                        debug!(%error, "Failed to receive a DNS query");
+                        continue;
+                    }
+                };
+
+                let query = match Message::from_vec(buf.get(..len).unwrap_or_default()) {
+                    Ok(query) => query,
+                    Err(error) => {
+                        debug!(%error, %peer, "Received an invalid DNS query");
+                        continue;
+                    }
+                };
+
+                match resolved_name(&query) {
+                    Some(node) => {
+                        connection
+                            .sender
+                            .send(ClientMessage::GetAddrInfoRequest(GetAddrInfoRequest { node }))
+                            .await
+                            .map_err(|_| closed())?;
+                        pending.push_back(PendingQuery { peer, query });
+                    }
+                    None => {
+                        let code = if query.queries().is_empty() {
+                            ResponseCode::FormErr
+                        } else {
+                            ResponseCode::NoError
+                        };
+                        reply(socket, peer, &empty_answer(&query, code)).await;
+                    }
+                }
+            }
+
+            message = connection.receiver.recv() => match message {
+                Some(DaemonMessage::GetAddrInfoResponse(response)) => {
+                    let Some(PendingQuery { peer, query }) = pending.pop_front() else {
+                        debug!(?response, "Received a DNS response without a query");
+                        continue;
+                    };
+
+                    reply(socket, peer, &answer(&query, response)).await;
+                }
+                Some(DaemonMessage::Pong) => {}
+                Some(DaemonMessage::LogMessage(log)) => warn!("Agent: {}", log.message),
+                Some(DaemonMessage::Close(message)) => {
+                    return Err(CliError::DnsServeFailed(format!(
+                        "agent closed connection with message: {message}"
+                    )))
+                }
+                Some(message) => debug!(?message, "Ignoring an unexpected message from the agent"),
+                None => return Err(closed()),
+            },
+        }
+    }
+}
+
+/// Name of an `A` or `AAAA` query, without the trailing dot.
+fn resolved_name(query: &Message) -> Option<String> {
+    let question = query.queries().first()?;
+
+    matches!(question.query_type(), RecordType::A | RecordType::AAAA)
+        .then(|| question.name().to_utf8().trim_end_matches('.').to_string())
+}
+
+/// Response to the query without any records.
+fn empty_answer(query: &Message, code: ResponseCode) -> Message {
+    let mut message = Message::new();
+    message
+        .set_id(query.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(query.op_code())
+        .set_recursion_desired(query.recursion_desired())
+        .set_recursion_available(true)
+        .set_response_code(code)
+        .add_queries(query.queries().iter().cloned());
+
+    message
+}
+
+/// Response to an `A` or `AAAA` query, with the addresses of the matching family.
+fn answer(query: &Message, response: GetAddrInfoResponse) -> Message {
+    let lookup = match response.0 {
+        Ok(lookup) => lookup,
+        Err(ResponseError::DnsLookup(DnsLookupError {
+            kind: ResolveErrorKindInternal::NoRecordsFound(code),
+        })) => return empty_answer(query, ResponseCode::from(code)),
+        Err(error) => {
+            debug!(%error, "Agent failed to resolve a name");
+            return empty_answer(query, ResponseCode::ServFail);
+        }
+    };
+
+    let mut message = empty_answer(query, ResponseCode::NoError);
+    let Some(question) = query.queries().first() else {
+        return message;
+    };
+
+    for record in lookup {
+        let rdata = match (question.query_type(), record.ip) {
+            (RecordType::A, IpAddr::V4(ip)) => RData::A(A(ip)),
+            (RecordType::AAAA, IpAddr::V6(ip)) => RData::AAAA(AAAA(ip)),
+            _ => continue,
+        };
+
+        message.add_answer(Record::from_rdata(
+            question.name().clone(),
+            ANSWER_TTL,
+            rdata,
+        ));
+    }
+
+    message
+}
+
+async fn reply(socket: &UdpSocket, peer: SocketAddr, message: &Message) {
+    let bytes = match message.to_vec() {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            warn!(%error, "Failed to encode a DNS response");
+            return;
+        }
+    };
+
+    if let Err(error) = socket.send_to(&bytes, peer).await {
+        debug!(%error, %peer, "Failed to send a DNS response");
+    }
+}
+
+/// Changes made to the system resolver with `--configure-system`, undone with
+/// [`SystemResolver::restore`].
+enum SystemResolver {
+    /// macOS sends the queries for `<domain>` to the server in `/etc/resolver/<domain>`.
+    ResolverFiles(Vec<PathBuf>),
+    /// A drop-in with a global DNS server, used only for the routing domains (`~<domain>`).
+    SystemdResolved(PathBuf),
+    /// Our server goes first in `/etc/resolv.conf`, the original contents are put back.
+    ResolvConf { original: String },
+}
+
+impl SystemResolver {
+    const RESOLVER_DIR: &'static str = "/etc/resolver";
+    const RESOLVED_DROP_IN: &'static str = "/etc/systemd/resolved.conf.d/mirrord-dns.conf";
+    const RESOLVED_RUNTIME_DIR: &'static str = "/run/systemd/resolve";
+    const RESOLV_CONF: &'static str = "/etc/resolv.conf";
+
+    fn configure(address: IpAddr, port: u16, domains: &[String]) -> io::Result<Self> {
+        if cfg!(target_os = "macos") {
+            Self::configure_resolver_files(address, port, domains)
+        } else if Path::new(Self::RESOLVED_RUNTIME_DIR).exists() {
+            Self::configure_systemd_resolved(address, port, domains)
+        } else {
+            Self::configure_resolv_conf(address, port)
+        }
+    }
+
+    fn configure_resolver_files(
+        address: IpAddr,
+        port: u16,
+        domains: &[String],
+    ) -> io::Result<Self> {
+        fs::create_dir_all(Self::RESOLVER_DIR)?;
+
+        let mut files = Vec::with_capacity(domains.len());
+        for domain in domains {
+            let path = Path::new(Self::RESOLVER_DIR).join(domain);
+            if path.exists() {
+                Self::ResolverFiles(files).restore()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", path.display()),
+                ));
+            }
+
+            fs::write(&path, format!("nameserver {address}\nport {port}\n"))?;
+            files.push(path);
+        }
+
+        Ok(Self::ResolverFiles(files))
+    }
+
+    fn configure_systemd_resolved(
+        address: IpAddr,
+        port: u16,
+        domains: &[String],
+    ) -> io::Result<Self> {
+        let server = match address {
+            IpAddr::V4(address) => format!("{address}:{port}"),
+            IpAddr::V6(address) => format!("[{address}]:{port}"),
+        };
+        let routing_domains = domains
+            .iter()
+            .map(|domain| format!("~{domain}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let path = PathBuf::from(Self::RESOLVED_DROP_IN);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &path,
+            format!("[Resolve]\nDNS={server}\nDomains={routing_domains}\n"),
+        )?;
+
+        let resolver = Self::SystemdResolved(path);
+        if let Err(error) = restart_systemd_resolved() {
+            let _ = resolver.restore();
+            return Err(error);
+        }
+
+        Ok(resolver)
+    }
+
+    fn configure_resolv_conf(address: IpAddr, port: u16) -> io::Result<Self> {
+        if port != 53 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} can only point at port 53, run with `--port 53`",
+                    Self::RESOLV_CONF
+                ),
+            ));
+        }
+
+        let original = fs::read_to_string(Self::RESOLV_CONF)?;
+        fs::write(
+            Self::RESOLV_CONF,
+            format!("# Added by `mirrord dns serve`.\nnameserver {address}\n{original}"),
+        )?;
+
+        Ok(Self::ResolvConf { original })
+    }
+
+    fn restore(self) -> io::Result<()> {
+        match self {
+            Self::ResolverFiles(files) => files.iter().try_for_each(fs::remove_file),
+            Self::SystemdResolved(path) => {
+                fs::remove_file(path)?;
+                restart_systemd_resolved()
+            }
+            Self::ResolvConf { original } => fs::write(Self::RESOLV_CONF, original),
+        }
+    }
+}
+
+/// Makes systemd-resolved read its drop-ins again.
+fn restart_systemd_resolved() -> io::Result<()> {
+    let status = Command::new("systemctl")
+        .args(["restart", "systemd-resolved"])
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "`systemctl restart systemd-resolved` failed with {status}"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use hickory_resolver::proto::{op::Query, rr::Name};
+    use mirrord_protocol::dns::{DnsLookup, LookupRecord};
+
+    use super::*;
+
+    fn query(name: &str, query_type: RecordType) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(7)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), query_type));
+        message
+    }
+
+    #[test]
+    fn answers_with_matching_family() {
+        let query = query("my-svc.my-ns.svc.cluster.local.", RecordType::A);
+        assert_eq!(
+            resolved_name(&query).as_deref(),
+            Some("my-svc.my-ns.svc.cluster.local")
+        );
+
+        let lookup = DnsLookup(vec![
+            LookupRecord {
+                name: "my-svc.my-ns.svc.cluster.local.".to_string(),
+                ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            },
+            LookupRecord {
+                name: "my-svc.my-ns.svc.cluster.local.".to_string(),
+                ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+            },
+        ]);
+        let response = answer(&query, GetAddrInfoResponse(Ok(lookup)));
+
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers().first().and_then(Record::data),
+            Some(&RData::A(A(Ipv4Addr::new(10, 0, 0, 1))))
+        );
+    }
+
+    #[test]
+    fn not_found() {
+        let query = query("missing.cluster.local.", RecordType::AAAA);
+        let error = ResponseError::DnsLookup(DnsLookupError {
+            kind: ResolveErrorKindInternal::NoRecordsFound(ResponseCode::NXDomain.into()),
+        });
+
+        let response = answer(&query, GetAddrInfoResponse(Err(error)));
+
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+    }
+}
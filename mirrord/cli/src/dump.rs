@@ -0,0 +1,443 @@
+//! `mirrord dump` prints the traffic that arrives at ports of the target, mirrored by the agent.
+//!
+//! With [`DumpFormat::Raw`] the data of each connection is printed as it arrives. The other formats
+//! parse it as HTTP/1.1 or HTTP/2 (with prior knowledge), serving each mirrored connection with
+//! [`hyper`], and print a record per request with its headers, timing and body size.
+//!
+//! Mirroring only copies what the clients send to the target, so there are no responses in the
+//! output.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{
+    body::Incoming,
+    server::conn::{http1, http2},
+    service::service_fn,
+    Request, Response,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use mirrord_analytics::NullReporter;
+use mirrord_progress::{Progress, ProgressTracker};
+use mirrord_protocol::{
+    tcp::{DaemonTcp, LayerTcp, NewTcpConnection, TcpClose, TcpData},
+    ClientMessage, ConnectionId, DaemonMessage, Port,
+};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{
+    io::{AsyncWriteExt, DuplexStream, WriteHalf},
+    sync::mpsc,
+    time,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    connection::{create_and_connect, AgentConnection},
+    diagnose::load_config,
+    CliError, DumpArgs, DumpFormat, Result,
+};
+
+/// Clients start HTTP/2 connections with prior knowledge with this preface.
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0";
+
+/// Size of the in-memory pipe between a mirrored connection and its HTTP server.
+const HTTP_PIPE_SIZE: usize = 64 * 1024;
+
+/// How often we ping the agent while waiting for traffic, so it doesn't consider us gone.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A mirrored connection, as reported by the agent.
+#[derive(Debug)]
+struct MirroredConnection {
+    id: ConnectionId,
+    client: SocketAddr,
+    /// Address of the target, where the client connected to.
+    local_address: IpAddr,
+    port: Port,
+}
+
+impl From<NewTcpConnection> for MirroredConnection {
+    fn from(connection: NewTcpConnection) -> Self {
+        Self {
+            id: connection.connection_id,
+            client: SocketAddr::new(connection.remote_address, connection.source_port),
+            local_address: connection.local_address,
+            port: connection.destination_port,
+        }
+    }
+}
+
+/// A HTTP header, in the same shape as in HAR.
+#[derive(Debug, Serialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+/// A HTTP request, parsed from a mirrored connection.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpRequestRecord {
+    connection_id: ConnectionId,
+    client: SocketAddr,
+    port: Port,
+    /// When we got the head of the request, in RFC 3339.
+    started_date_time: String,
+    /// Milliseconds between the head of the request and the end of its body.
+    time: f64,
+    method: String,
+    /// Absolute URL, built from the `Host` header (or HTTP/2 authority) when there is one.
+    url: String,
+    http_version: String,
+    headers: Vec<Header>,
+    body_size: usize,
+}
+
+impl HttpRequestRecord {
+    /// The record as an entry of a HAR log. There's no response, so its status is `0`, which is
+    /// what browsers use for requests that got no response.
+    fn har_entry(&self) -> serde_json::Value {
+        let query_string = self
+            .url
+            .split_once('?')
+            .map(|(_, query)| query)
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                json!({ "name": name, "value": value })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "startedDateTime": self.started_date_time,
+            "time": self.time,
+            "request": {
+                "method": self.method,
+                "url": self.url,
+                "httpVersion": self.http_version,
+                "cookies": [],
+                "headers": self.headers,
+                "queryString": query_string,
+                "headersSize": -1,
+                "bodySize": self.body_size,
+            },
+            "response": {
+                "status": 0,
+                "statusText": "",
+                "httpVersion": "",
+                "cookies": [],
+                "headers": [],
+                "content": { "size": 0, "mimeType": "" },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+            },
+            "cache": {},
+            "timings": { "send": self.time, "wait": 0, "receive": 0 },
+            "serverIPAddress": self.client.ip(),
+            "connection": self.connection_id.to_string(),
+        })
+    }
+}
+
+/// Reads the request to the end and records it, responding with an empty response that nobody
+/// will see.
+async fn record_request(
+    request: Request<Incoming>,
+    connection: Arc<MirroredConnection>,
+    records: mpsc::UnboundedSender<HttpRequestRecord>,
+) -> Result<Response<Empty<Bytes>>, hyper::Error> {
+    let started_date_time = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+    let start = Instant::now();
+
+    let (parts, mut body) = request.into_parts();
+    let mut body_size = 0;
+    while let Some(frame) = body.frame().await {
+        if let Some(data) = frame?.data_ref() {
+            body_size += data.len();
+        }
+    }
+
+    let authority = parts
+        .uri
+        .authority()
+        .map(ToString::to_string)
+        .or_else(|| {
+            parts
+                .headers
+                .get(hyper::header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .map(ToString::to_string)
+        })
+        .unwrap_or_else(|| SocketAddr::new(connection.local_address, connection.port).to_string());
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "/".to_string());
+
+    let _ = records.send(HttpRequestRecord {
+        connection_id: connection.id,
+        client: connection.client,
+        port: connection.port,
+        started_date_time,
+        time: start.elapsed().as_secs_f64() * 1000.0,
+        method: parts.method.to_string(),
+        url: format!("http://{authority}{path}"),
+        http_version: format!("{:?}", parts.version),
+        headers: parts
+            .headers
+            .iter()
+            .map(|(name, value)| Header {
+                name: name.to_string(),
+                value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            })
+            .collect(),
+        body_size,
+    });
+
+    Ok(Response::new(Empty::new()))
+}
+
+/// Serves a mirrored connection with [`hyper`], to parse the requests in `first_data` and the
+/// rest of the data written to the returned pipe.
+fn serve_http(
+    connection: Arc<MirroredConnection>,
+    first_data: &[u8],
+    records: mpsc::UnboundedSender<HttpRequestRecord>,
+) -> WriteHalf<DuplexStream> {
+    let (client, server) = tokio::io::duplex(HTTP_PIPE_SIZE);
+    let (mut responses, data) = tokio::io::split(client);
+
+    let http2 = first_data.starts_with(HTTP2_PREFACE);
+    let connection_id = connection.id;
+    let service =
+        service_fn(move |request| record_request(request, connection.clone(), records.clone()));
+
+    tokio::spawn(async move {
+        let io = TokioIo::new(server);
+        let result = if http2 {
+            http2::Builder::new(TokioExecutor::new())
+                .serve_connection(io, service)
+                .await
+        } else {
+            http1::Builder::new()
+                .half_close(true)
+                .serve_connection(io, service)
+                .await
+        };
+
+        if let Err(error) = result {
+            debug!(connection_id, %error, "Mirrored connection is not HTTP");
+        }
+    });
+
+    // Nobody reads the responses.
+    tokio::spawn(async move { tokio::io::copy(&mut responses, &mut tokio::io::sink()).await });
+
+    data
+}
+
+/// Prints the dump in the requested format, as the traffic arrives.
+struct Printer {
+    format: DumpFormat,
+    connections: HashMap<ConnectionId, Arc<MirroredConnection>>,
+    /// Data of the connections served by [`serve_http`].
+    http_connections: HashMap<ConnectionId, WriteHalf<DuplexStream>>,
+    records_tx: mpsc::UnboundedSender<HttpRequestRecord>,
+    /// HAR entries, printed when the dump is finished.
+    har_entries: Vec<serde_json::Value>,
+}
+
+impl Printer {
+    fn new(format: DumpFormat, records_tx: mpsc::UnboundedSender<HttpRequestRecord>) -> Self {
+        Self {
+            format,
+            connections: Default::default(),
+            http_connections: Default::default(),
+            records_tx,
+            har_entries: Default::default(),
+        }
+    }
+
+    fn new_connection(&mut self, connection: NewTcpConnection) {
+        let connection = MirroredConnection::from(connection);
+
+        if self.format == DumpFormat::Raw {
+            println!(
+                "--- connection {} from {} to port {} opened ---",
+                connection.id, connection.client, connection.port
+            );
+        }
+
+        self.connections.insert(connection.id, Arc::new(connection));
+    }
+
+    async fn data(
+        &mut self,
+        TcpData {
+            connection_id,
+            bytes,
+        }: TcpData,
+    ) -> io::Result<()> {
+        if self.format == DumpFormat::Raw {
+            println!("--- connection {connection_id}, {} bytes ---", bytes.len());
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&bytes)?;
+            return writeln!(stdout);
+        }
+
+        let pipe = match self.http_connections.entry(connection_id) {
+            Entry::Occupied(pipe) => pipe.into_mut(),
+            Entry::Vacant(entry) => {
+                let Some(connection) = self.connections.get(&connection_id) else {
+                    return Ok(());
+                };
+                entry.insert(serve_http(
+                    connection.clone(),
+                    &bytes,
+                    self.records_tx.clone(),
+                ))
+            }
+        };
+
+        if let Err(error) = pipe.write_all(&bytes).await {
+            // The server gave up on the connection, it's not HTTP.
+            debug!(connection_id, %error, "Dropping the data of a mirrored connection");
+            self.http_connections.remove(&connection_id);
+        }
+
+        Ok(())
+    }
+
+    async fn close(&mut self, TcpClose { connection_id }: TcpClose) {
+        if self.format == DumpFormat::Raw {
+            println!("--- connection {connection_id} closed ---");
+        }
+
+        self.connections.remove(&connection_id);
+        if let Some(mut pipe) = self.http_connections.remove(&connection_id) {
+            let _ = pipe.shutdown().await;
+        }
+    }
+
+    fn record(&mut self, record: HttpRequestRecord) -> Result<()> {
+        match self.format {
+            DumpFormat::Raw => {}
+            DumpFormat::Jsonl => println!("{}", serde_json::to_string(&record)?),
+            DumpFormat::Har => self.har_entries.push(record.har_entry()),
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.format == DumpFormat::Har {
+            let har = json!({
+                "log": {
+                    "version": "1.2",
+                    "creator": { "name": "mirrord", "version": env!("CARGO_PKG_VERSION") },
+                    "entries": self.har_entries,
+                }
+            });
+            println!("{}", serde_json::to_string_pretty(&har)?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Subscribes to the ports and prints their traffic until Ctrl+C, or until the agent closes the
+/// connection.
+async fn dump(connection: &mut AgentConnection, ports: &[Port], format: DumpFormat) -> Result<()> {
+    for port in ports {
+        connection
+            .sender
+            .send(ClientMessage::Tcp(LayerTcp::PortSubscribe(*port)))
+            .await
+            .map_err(|_| CliError::DumpFailed("agent unexpectedly closed connection".into()))?;
+    }
+
+    let (records_tx, mut records_rx) = mpsc::unbounded_channel();
+    let mut printer = Printer::new(format, records_tx);
+    let mut ping = time::interval(PING_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+
+            _ = ping.tick() => {
+                connection
+                    .sender
+                    .send(ClientMessage::Ping)
+                    .await
+                    .map_err(|_| CliError::DumpFailed("agent unexpectedly closed connection".into()))?;
+            }
+
+            Some(record) = records_rx.recv() => printer.record(record)?,
+
+            message = connection.receiver.recv() => match message {
+                Some(DaemonMessage::Tcp(DaemonTcp::SubscribeResult(result))) => {
+                    let port = result.map_err(|error| CliError::DumpFailed(error.to_string()))?;
+                    eprintln!("Dumping the traffic of port {port}, press Ctrl+C to stop.");
+                }
+                Some(DaemonMessage::Tcp(DaemonTcp::NewConnection(new_connection))) => {
+                    printer.new_connection(new_connection)
+                }
+                Some(DaemonMessage::Tcp(DaemonTcp::Data(data))) => {
+                    printer.data(data).await.map_err(CliError::DumpOutputFailed)?
+                }
+                Some(DaemonMessage::Tcp(DaemonTcp::Close(close))) => printer.close(close).await,
+                Some(DaemonMessage::Pong) => {}
+                Some(DaemonMessage::LogMessage(log)) => warn!("Agent: {}", log.message),
+                Some(DaemonMessage::Close(message)) => {
+                    return Err(CliError::DumpFailed(format!(
+                        "agent closed connection with message: {message}"
+                    )))
+                }
+                Some(message) => debug!(?message, "Ignoring an unexpected message from the agent"),
+                None => break,
+            },
+        }
+    }
+
+    // Let the servers finish parsing what we already have.
+    let mut pipes = std::mem::take(&mut printer.http_connections);
+    for pipe in pipes.values_mut() {
+        let _ = pipe.shutdown().await;
+    }
+    drop(pipes);
+    while let Ok(Some(record)) = time::timeout(Duration::from_millis(100), records_rx.recv()).await
+    {
+        printer.record(record)?;
+    }
+
+    printer.finish()
+}
+
+/// Handle `mirrord dump`.
+pub(crate) async fn dump_command(args: DumpArgs) -> Result<()> {
+    if let Some(target) = args.target.as_deref() {
+        std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target);
+    }
+
+    let mut progress = ProgressTracker::from_env("mirrord dump");
+
+    let config = load_config(args.config_file.as_deref())?;
+
+    let mut analytics = NullReporter::default();
+    let (_, mut connection) = create_and_connect(&config, &mut progress, &mut analytics).await?;
+    progress.success(Some("connected to the agent"));
+
+    dump(&mut connection, &args.ports, args.format).await
+}
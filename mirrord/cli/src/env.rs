@@ -0,0 +1,135 @@
+//! `mirrord env` prints the environment that `mirrord exec` would give the application, without
+//! running anything. Useful for checking the `feature.env` filters, and for other tools.
+
+use std::collections::{BTreeMap, HashMap};
+
+use mirrord_analytics::NullReporter;
+use mirrord_progress::{Progress, ProgressTracker};
+
+use crate::{
+    connection::create_and_connect, diagnose::load_config, execution::MirrordExecution, EnvArgs,
+    EnvOutput, Result,
+};
+
+/// Quotes the value for a `.env` file, escaping what would be interpreted inside double quotes.
+fn dotenv_value(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for character in value.chars() {
+        match character {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str("\\$"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            other => quoted.push(other),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parses a `.env` file, the reverse of [`dotenv_value`]. Empty lines and `#` comments are
+/// skipped, as is `export` before the names.
+pub(crate) fn parse_dotenv(env_file: &str) -> HashMap<String, String> {
+    env_file
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let key = key.trim();
+            let key = key.strip_prefix("export ").unwrap_or(key).trim();
+            let value = value.trim();
+
+            let value = if let Some(quoted) = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+            {
+                let mut unquoted = String::with_capacity(quoted.len());
+                let mut characters = quoted.chars();
+                while let Some(character) = characters.next() {
+                    match (character, characters.clone().next()) {
+                        ('\\', Some('n')) => unquoted.push('\n'),
+                        ('\\', Some('r')) => unquoted.push('\r'),
+                        ('\\', Some(escaped @ ('\\' | '"' | '$'))) => unquoted.push(escaped),
+                        (character, _) => {
+                            unquoted.push(character);
+                            continue;
+                        }
+                    }
+                    characters.next();
+                }
+                unquoted
+            } else if let Some(quoted) = value
+                .strip_prefix('\'')
+                .and_then(|value| value.strip_suffix('\''))
+            {
+                quoted.to_string()
+            } else {
+                value.to_string()
+            };
+
+            (key.to_string(), value)
+        })
+        .collect()
+}
+
+/// Handle `mirrord env`.
+pub(crate) async fn env_command(args: EnvArgs) -> Result<()> {
+    if let Some(target) = args.target.as_deref() {
+        std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target);
+    }
+
+    let mut progress = ProgressTracker::from_env("mirrord env");
+
+    let config = load_config(args.config_file.as_deref())?;
+
+    let mut analytics = NullReporter::default();
+    let (_, mut connection) = create_and_connect(&config, &mut progress, &mut analytics).await?;
+    progress.success(Some("connected to the agent"));
+
+    let mut env_vars: BTreeMap<String, String> =
+        MirrordExecution::fetch_env_vars(&config, &mut connection)
+            .await?
+            .into_iter()
+            .collect();
+
+    // `mirrord exec` removes these from the application's environment.
+    if let Some(unset) = config.feature.env.unset.clone() {
+        let unset = unset.to_vec();
+        env_vars.retain(|key, _| !unset.iter().any(|name| name.eq_ignore_ascii_case(key)));
+    }
+
+    match args.output {
+        EnvOutput::Json => println!("{}", serde_json::to_string_pretty(&env_vars)?),
+        EnvOutput::Dotenv => {
+            for (key, value) in &env_vars {
+                println!("{key}={}", dotenv_value(value));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dotenv_round_trip() {
+        let value = "multi\nline \"quoted\" $HOME \\ end";
+        let env_file = format!(
+            "# comment\n\nexport FIRST={}\nSECOND='single $quoted'\nTHIRD=plain\n",
+            dotenv_value(value)
+        );
+
+        let parsed = parse_dotenv(&env_file);
+
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed["FIRST"], value);
+        assert_eq!(parsed["SECOND"], "single $quoted");
+        assert_eq!(parsed["THIRD"], "plain");
+    }
+}
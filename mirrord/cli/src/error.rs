@@ -57,10 +57,26 @@ pub(crate) enum InternalProxyError {
     #[diagnostic(help("{GENERAL_HELP}"))]
     OpenLogFile(String, std::io::Error),
 
+    #[error("Failed to create the shadow diff report at `{0}`: {1}")]
+    #[diagnostic(help("Check that `feature.network.incoming.http_filter.shadow_diff` points to a writable path.{GENERAL_HELP}"))]
+    OpenShadowDiffReport(String, std::io::Error),
+
     #[error("Failed to deserialize connect info `{0}`: {1}")]
     #[diagnostic(help("{GENERAL_BUG}"))]
     DeseralizeConnectInfo(String, serde_json::Error),
 
+    #[error("Failed to deserialize CronJob run `{0}`: {1}")]
+    #[diagnostic(help("{GENERAL_BUG}"))]
+    DeserializeCronJobRun(String, serde_json::Error),
+
+    #[error("Failed to deserialize the paused autoscalers `{0}`: {1}")]
+    #[diagnostic(help(
+        "The autoscalers of the target were not resumed, remove the \
+         `mirrord.metalbear.co/paused-autoscaling` annotation from them and restore their \
+         settings by hand. {GENERAL_BUG}"
+    ))]
+    DeserializePausedAutoscaling(String, serde_json::Error),
+
     #[error("Initial ping pong with the agent failed: {0}")]
     #[diagnostic(help("{GENERAL_BUG}"))]
     InitialPingPongFailed(String),
@@ -95,6 +111,38 @@ pub(crate) enum CliError {
     ))]
     CreateAgentFailed(KubeApiError),
 
+    #[error("Failed to get the Job of the CronJob target: {0}")]
+    #[diagnostic(help(
+        "Please check the status of the CronJob and its Jobs, using `kubectl get cronjobs,jobs` \
+        in the relevant namespace. `--wait-for-run=trigger` needs permission to create Jobs.{GENERAL_HELP}"
+    ))]
+    CronJobRunFailed(KubeApiError),
+
+    #[error("Failed to stream the logs of the target: {0}")]
+    #[diagnostic(help(
+        "`feature.target_logs` needs permission to get the target's pods and their logs \
+        (`pods/log`).{GENERAL_HELP}"
+    ))]
+    TargetLogsFailed(KubeApiError),
+
+    #[error("Failed to list the targets: {0}")]
+    #[diagnostic(help(
+        "Check that `--selector` is a valid Kubernetes label selector, e.g. `app=api,tier!=db`, \
+         and that you can list the targets in the namespace.{GENERAL_HELP}"
+    ))]
+    ListTargetsFailed(kube::Error),
+
+    #[error("Failed to start the process that streams the logs of the target: {0}")]
+    #[diagnostic(help("{GENERAL_HELP}"))]
+    TargetLogsSpawnFailed(std::io::Error),
+
+    #[error("Failed to apply the CPU and memory limits of the target: {0}")]
+    #[diagnostic(help(
+        "`experimental.enforce_target_limits` needs the user's systemd instance (`busctl --user`) \
+         and permission to get the target's pod.{GENERAL_HELP}"
+    ))]
+    TargetLimitsFailed(String),
+
     #[error("Failed to connect to the created mirrord-agent: {0}")]
     #[diagnostic(help(
         "Please check the following:
@@ -107,6 +155,10 @@ pub(crate) enum CliError {
     #[diagnostic(help("Please check agent status and logs.{GENERAL_HELP}"))]
     RemoteEnvFetchFailed(String),
 
+    #[error("Failed to negotiate the protocol with the agent: {0}")]
+    #[diagnostic(help("Please check agent status and logs.{GENERAL_HELP}"))]
+    AgentHandshakeFailed(String),
+
     #[error("Failed to execute binary `{0}` with args {1:?}")]
     #[diagnostic(help(
         "Please open an issue on our GitHub repository with binary information:
@@ -227,6 +279,20 @@ pub(crate) enum CliError {
     #[diagnostic(help("{GENERAL_BUG}"))]
     ConnectRequestBuildError(HttpError),
 
+    #[error("Bandwidth test with the agent failed: {0}")]
+    #[diagnostic(help(
+        "This usually means that connectivity was lost during the test.{GENERAL_HELP}"
+    ))]
+    BandwidthTestFailed(String),
+
+    #[error("Failed to reach the Kubernetes API server: {0}")]
+    #[diagnostic(help(
+        "If you connect to the cluster through a proxy, check the `HTTPS_PROXY` and `NO_PROXY` \
+        environment variables, the `proxy` section of the mirrord config, and the `proxy-url` in \
+        your kubeconfig.{GENERAL_HELP}"
+    ))]
+    ClusterUnreachable(KubeApiError),
+
     #[error("Ping pong with the agent failed: {0}")]
     #[diagnostic(help(
         "This usually means that connectivity was lost while pinging.{GENERAL_HELP}"
@@ -242,6 +308,118 @@ pub(crate) enum CliError {
     Please remember that some features are supported only when using mirrord operator (https://mirrord.dev/docs/overview/teams/#supported-features).{GENERAL_HELP}"
     ))]
     OperatorInstallationCheckError(KubeApiError),
+
+    #[error("Invalid file path pattern in the `feature.fs` config: {0}")]
+    #[diagnostic(help(
+        "Please check the regex patterns in the `feature.fs` config.{GENERAL_HELP}"
+    ))]
+    InvalidFsPattern(regex::Error),
+
+    #[error("Failed to serialize the cluster setup: {0}")]
+    #[diagnostic(help("{GENERAL_BUG}"))]
+    SetupPrintFailed(serde_yaml::Error),
+
+    #[error("Dumping the traffic failed: {0}")]
+    #[diagnostic(help(
+        "Make sure the ports are not already stolen by another mirrord session.{GENERAL_HELP}"
+    ))]
+    DumpFailed(String),
+
+    #[error("Failed to print the dumped traffic: {0}")]
+    #[diagnostic(help("{GENERAL_BUG}"))]
+    DumpOutputFailed(std::io::Error),
+
+    #[error("Searching the files of the target failed: {0}")]
+    #[diagnostic(help(
+        "Check the pattern and make sure the paths exist in the target.{GENERAL_HELP}"
+    ))]
+    GrepFailed(String),
+
+    #[error("Observing the files of the target failed: {0}")]
+    #[diagnostic(help(
+        "The agent needs the `SYS_ADMIN` capability to observe the files, make sure it's not in \
+         `agent.disabled_capabilities`.{GENERAL_HELP}"
+    ))]
+    FsUsageFailed(String),
+
+    #[error("Failed to read the recorded outgoing destinations from `{0}`: {1}")]
+    #[diagnostic(help(
+        "Run a session with `feature.network.outgoing.record` set to this file first.{GENERAL_HELP}"
+    ))]
+    OutgoingRecordFailed(PathBuf, std::io::Error),
+
+    #[error("Failed to write the debug bundle to `{0}`: {1}")]
+    #[diagnostic(help(
+        "Make sure the directory exists and is writable, or pick another one with \
+         `--output`.{GENERAL_HELP}"
+    ))]
+    DebugBundleFailed(PathBuf, std::io::Error),
+
+    #[error("Failed to print the logs of the agent: {0}")]
+    #[diagnostic(help(
+        "Run `mirrord status` to see the sessions, only the agents spawned without the operator \
+         can be found.{GENERAL_HELP}"
+    ))]
+    AgentLogsFailed(String),
+
+    #[error("Failed to write the config to `{}`: {1}", .0.display())]
+    #[diagnostic(help(
+        "Pass the path of a `.json`, `.toml` or `.yaml` file, and `--force` to overwrite an \
+         existing one.{GENERAL_HELP}"
+    ))]
+    ConfigInitFailed(PathBuf, String),
+
+    #[error("Failed to select the target: {0}")]
+    #[diagnostic(help(
+        "`--select-target` asks for the target in the terminal, pass it with `--target` (or \
+         `target` in the config) instead when not running in one.{GENERAL_HELP}"
+    ))]
+    SelectTargetFailed(String),
+
+    #[error("Failed to read the policies from `{}`: {1}", .0.display())]
+    #[diagnostic(help(
+        "Pass a YAML or JSON file with `MirrordPolicy` resources, as applied with `kubectl apply \
+         -f`.{GENERAL_HELP}"
+    ))]
+    PolicyTestFailed(PathBuf, String),
+
+    #[error("Failed to read the `fallback.env_file` `{}`: {1}", .0.display())]
+    #[diagnostic(help(
+        "The cluster couldn't be reached, and mirrord tried to run with the local substitutes of \
+         `fallback`. Check that the file exists, the path is relative to the working directory.\
+         {GENERAL_HELP}"
+    ))]
+    FallbackEnvFileFailed(PathBuf, std::io::Error),
+
+    #[error("Serving DNS failed: {0}")]
+    #[diagnostic(help(
+        "Make sure the port is free, and run with root privileges when using \
+         `--configure-system`.{GENERAL_HELP}"
+    ))]
+    DnsServeFailed(String),
+
+    #[error("Failed to list the remote file descriptors of the session: {0}")]
+    #[diagnostic(help(
+        "Make sure the session is still running, and pass the pid of the application with \
+         `--pid` when not running from its environment.{GENERAL_HELP}"
+    ))]
+    SessionFdsFailed(String),
+
+    #[error("Failed to read the session state files in `{}`: {1}", .0.display())]
+    #[diagnostic(help("Check the permissions of the directory.{GENERAL_HELP}"))]
+    SessionStatusFailed(PathBuf, std::io::Error),
+
+    #[error("Failed to change the log filter of the session: {0}")]
+    #[diagnostic(help(
+        "The filter uses the `RUST_LOG` syntax, e.g. `mirrord=trace`. Make sure the session is \
+         still running, and pass the pid of the application with `--pid` when not running from \
+         its environment.{GENERAL_HELP}"
+    ))]
+    SetLogLevelFailed(String),
+
+    #[error("Failed to create the local shared scratch directory at `{}`: {1}", .0.display())]
+    #[diagnostic(help("Check that the temporary directory is writable.{GENERAL_HELP}"))]
+    SharedScratchDirFailed(PathBuf, std::io::Error),
 }
 
 impl From<OperatorApiError> for CliError {
@@ -1,19 +1,35 @@
 use std::{
     collections::{HashMap, HashSet},
-    time::Duration,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use mirrord_analytics::{AnalyticsError, AnalyticsReporter, Reporter};
-use mirrord_config::{config::ConfigError, LayerConfig};
+use mirrord_config::{
+    config::ConfigError, fallback::FALLBACK_ACTIVE_ENV, feature::fs::SHARED_SCRATCH_DIR_ENV,
+    LayerConfig,
+};
+use mirrord_ext_schema::{execution::ExecutionInfo, SCHEMA_VERSION};
 use mirrord_intproxy::agent_conn::AgentConnectInfo;
+use mirrord_intproxy_protocol::{
+    INTPROXY_AUTH_TOKEN_ENV, OUTGOING_PROXY_SERVER_ENV, SESSION_DEADLINE_ENV,
+};
 use mirrord_progress::Progress;
 use mirrord_protocol::{ClientMessage, DaemonMessage, EnvVars, GetEnvVarsRequest, LogLevel};
 #[cfg(target_os = "macos")]
 use mirrord_sip::sip_patch;
-use serde::Serialize;
+use nix::{
+    libc,
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use rand::distributions::{Alphanumeric, DistString};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::{Child, ChildStderr, Command},
+    io::{AsyncBufReadExt, BufReader, Lines},
+    process::{Child, ChildStderr, ChildStdout, Command},
     select,
     sync::mpsc::{self, UnboundedReceiver},
 };
@@ -21,10 +37,15 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, trace, warn};
 
 use crate::{
-    connection::{create_and_connect, AgentConnection, AGENT_CONNECT_INFO_ENV_KEY},
+    connection::{
+        check_agent_capabilities, create_and_connect, pause_autoscaling, wait_for_cron_job_run,
+        AgentConnection, CronJobSession, AGENT_CONNECT_INFO_ENV_KEY, CRON_JOB_RUN_ENV_KEY,
+        PAUSED_AUTOSCALING_ENV_KEY,
+    },
+    env::parse_dotenv,
     error::CliError,
     extract::extract_library,
-    util::remove_proxy_env,
+    util::{set_proxy_env, ROUTING_VALUE_ENV},
     Result,
 };
 
@@ -34,14 +55,107 @@ const INJECTION_ENV_VAR: &str = "LD_PRELOAD";
 #[cfg(target_os = "macos")]
 const INJECTION_ENV_VAR: &str = "DYLD_INSERT_LIBRARIES";
 
+/// Host the layers use to reach the internal proxy, see
+/// [`advertised_address`](mirrord_config::internal_proxy::InternalProxyConfig::advertised_address).
+async fn intproxy_host(config: &LayerConfig) -> String {
+    let internal_proxy = &config.internal_proxy;
+
+    if let Some(address) = internal_proxy.advertised_address.as_ref() {
+        return address.clone();
+    }
+
+    let ip = match internal_proxy.bind_address {
+        Some(ip) if ip.is_unspecified() => match docker_host_address().await {
+            Some(host) => return host,
+            None => {
+                warn!(
+                    "Could not detect the address of the host in Docker, the layers will connect \
+                     to the internal proxy on 127.0.0.1. Set `internal_proxy.advertised_address` \
+                     if they run in a container."
+                );
+                IpAddr::V4(Ipv4Addr::LOCALHOST)
+            }
+        },
+        Some(ip) => ip,
+        None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    };
+
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+    }
+}
+
+/// Address of the host as seen from Docker containers: `host.docker.internal` with Docker Desktop,
+/// the gateway of the `bridge` network with native Docker.
+async fn docker_host_address() -> Option<String> {
+    let docker_output = |args: &'static [&'static str]| async move {
+        let output = Command::new("docker")
+            .args(args)
+            .output()
+            .await
+            .inspect_err(|error| debug!(%error, "Failed to run docker"))
+            .ok()?;
+
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let operating_system = docker_output(&["info", "--format", "{{.OperatingSystem}}"]).await?;
+    if operating_system.contains("Docker Desktop") {
+        return Some("host.docker.internal".to_string());
+    }
+
+    docker_output(&[
+        "network",
+        "inspect",
+        "bridge",
+        "--format",
+        "{{(index .IPAM.Config 0).Gateway}}",
+    ])
+    .await
+    .filter(|gateway| !gateway.is_empty())
+}
+
+/// Reads the next line the internal proxy prints to its stdout on startup.
+async fn read_proxy_line<T>(stdout: &mut Lines<BufReader<ChildStdout>>, what: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    stdout
+        .next_line()
+        .await
+        .map_err(|e| {
+            CliError::InternalProxySpawnError(format!("failed to read proxy stdout: {e}"))
+        })?
+        .ok_or_else(|| {
+            CliError::InternalProxySpawnError(format!("proxy did not print {what} to stdout"))
+        })?
+        .parse()
+        .map_err(|e| {
+            CliError::InternalProxySpawnError(format!(
+                "failed to parse {what} printed by proxy: {e}"
+            ))
+        })
+}
+
 /// Struct for holding the execution information
 /// What agent to connect to, what environment variables to set
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub(crate) struct MirrordExecution {
     pub environment: HashMap<String, String>,
 
-    #[serde(skip)]
-    child: Child,
+    /// The internal proxy, or the process it was forked from when detached (see
+    /// [`InternalProxyConfig::detach`](mirrord_config::internal_proxy::InternalProxyConfig::detach)).
+    ///
+    /// [`None`] in [`LayerConfig::simulate`] mode.
+    child: Option<Child>,
+
+    /// Pid of the internal proxy, which is not the pid of [`Self::child`] when it's detached.
+    proxy_pid: Option<Pid>,
 
     /// The path to the patched binary, if patched for SIP sidestepping.
     pub patched_path: Option<String>,
@@ -130,11 +244,19 @@ where
 impl MirrordExecution {
     /// Starts the internal proxy (`intproxy`), and mirrord-layer, even if a bogus binary
     /// was passed by the user.
-    #[tracing::instrument(level = "trace", skip_all)]
+    ///
+    /// `app_pid` is the pid the application will have, when we `execve` into it. The internal
+    /// proxy stops it when the session ends on its own, e.g. when a
+    /// [`CronJobRun`](mirrord_kube::api::runtime::cron_job::CronJobRun) finishes.
+    ///
+    /// In [`LayerConfig::simulate`] mode, only prepares the layer, see [`Self::simulate`].
+    #[tracing::instrument(level = "info", name = "session_setup", skip_all)]
     pub(crate) async fn start<P>(
         config: &LayerConfig,
         // We only need the executable on macos, for SIP handling.
         #[cfg(target_os = "macos")] executable: Option<&str>,
+        detach_proxy: bool,
+        app_pid: Option<Pid>,
         progress: &mut P,
         analytics: &mut AnalyticsReporter,
     ) -> Result<Self>
@@ -143,13 +265,54 @@ impl MirrordExecution {
     {
         let lib_path = extract_library(None, progress, true)?;
 
-        if !config.use_proxy {
-            remove_proxy_env();
+        if config.simulate {
+            return Self::simulate(
+                config,
+                #[cfg(target_os = "macos")]
+                executable,
+                lib_path,
+                progress,
+            );
         }
 
-        let (connect_info, mut connection) = create_and_connect(config, progress, analytics)
-            .await
-            .inspect_err(|_| analytics.set_error(AnalyticsError::AgentConnection))?;
+        set_proxy_env(config);
+
+        // From here on we target the pod of the CronJob's Job.
+        let (cron_job_run, run_config) = wait_for_cron_job_run(config, progress).await?.unzip();
+        let config = run_config.as_ref().unwrap_or(config);
+
+        // Set before the agent starts, so its own limit passes after ours.
+        let max_duration = config.session.max_duration.map(Duration::from_secs);
+        let session_deadline = max_duration.map(|duration| SystemTime::now() + duration);
+
+        let (connect_info, mut connection) =
+            match create_and_connect(config, progress, analytics).await {
+                Ok(connected) => connected,
+                Err(error) if config.fallback.is_set() => {
+                    return Self::fallback(
+                        config,
+                        #[cfg(target_os = "macos")]
+                        executable,
+                        lib_path,
+                        error,
+                        progress,
+                    );
+                }
+                Err(error) => {
+                    analytics.set_error(AnalyticsError::AgentConnection);
+                    return Err(error);
+                }
+            };
+
+        let communication_timeout =
+            Duration::from_secs(config.agent.communication_timeout.unwrap_or(30).into());
+        tokio::time::timeout(
+            communication_timeout,
+            check_agent_capabilities(config, &connect_info, &mut connection, progress),
+        )
+        .await
+        .unwrap_or_else(|_| Err(CliError::AgentHandshakeFailed("timeout".to_string())))
+        .inspect_err(|_| analytics.set_error(AnalyticsError::AgentConnection))?;
 
         let mut env_vars = if config.feature.env.load_from_process.unwrap_or(false) {
             Default::default()
@@ -159,14 +322,40 @@ impl MirrordExecution {
                 .inspect_err(|_| analytics.set_error(AnalyticsError::EnvFetch))?
         };
 
-        let lib_path: String = lib_path.to_string_lossy().into();
-        // Set LD_PRELOAD/DYLD_INSERT_LIBRARIES
-        // If already exists, we append.
-        if let Ok(v) = std::env::var(INJECTION_ENV_VAR) {
-            env_vars.insert(INJECTION_ENV_VAR.to_string(), format!("{v}:{lib_path}"))
-        } else {
-            env_vars.insert(INJECTION_ENV_VAR.to_string(), lib_path)
-        };
+        let http_filter = &config.feature.network.incoming.http_filter;
+        if let (Some(header), Some(value)) = (
+            http_filter.routing_header.as_deref(),
+            http_filter.routing_value.as_deref(),
+        ) {
+            progress.info(&format!(
+                "mirrord steals the requests with the `{header}: {value}` header, send your \
+                 requests with it (or have your mesh inject it) to reach the local application"
+            ));
+            env_vars.insert(ROUTING_VALUE_ENV.to_string(), value.to_string());
+        }
+
+        // Synchronized with the remote directory by the internal proxy, the layer serves the
+        // remote path from it.
+        if let Some(remote) = config.feature.fs.shared_scratch.as_deref() {
+            let local = std::env::temp_dir().join(format!(
+                "mirrord-scratch-{}",
+                Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+            ));
+            std::fs::create_dir_all(&local)
+                .map_err(|error| CliError::SharedScratchDirFailed(local.clone(), error))?;
+
+            progress.info(&format!(
+                "`{remote}` of the target is synchronized with the local directory `{}` \
+                 (feature.fs.shared_scratch)",
+                local.display()
+            ));
+            env_vars.insert(
+                SHARED_SCRATCH_DIR_ENV.to_string(),
+                local.to_string_lossy().into_owned(),
+            );
+        }
+
+        insert_injection_env(&mut env_vars, lib_path);
 
         // stderr is inherited so we can see logs/errors.
         let mut proxy_command =
@@ -186,6 +375,60 @@ impl MirrordExecution {
             serde_json::to_string(&connect_info)?,
         );
 
+        if let Some(local) = env_vars.get(SHARED_SCRATCH_DIR_ENV) {
+            proxy_command.env(SHARED_SCRATCH_DIR_ENV, local);
+        }
+
+        if let Some(run) = cron_job_run {
+            let session = CronJobSession {
+                run,
+                app_pid: app_pid.map(Pid::as_raw),
+            };
+            proxy_command.env(CRON_JOB_RUN_ENV_KEY, serde_json::to_string(&session)?);
+        }
+
+        // The internal proxy ends the session when it expires, the layer warns about it and stops
+        // the application.
+        if let (Some(deadline), Some(max_duration)) = (session_deadline, max_duration) {
+            let secs = deadline
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                .to_string();
+            proxy_command.env(SESSION_DEADLINE_ENV, &secs);
+            env_vars.insert(SESSION_DEADLINE_ENV.to_string(), secs);
+
+            progress.info(&format!(
+                "the session expires in {} (session.max_duration)",
+                humantime::format_duration(max_duration)
+            ));
+        }
+
+        // Paused right before the internal proxy starts, it resumes the autoscalers when the
+        // session ends.
+        if let Some(pause) = pause_autoscaling(config, progress).await {
+            proxy_command.env(PAUSED_AUTOSCALING_ENV_KEY, serde_json::to_string(&pause)?);
+        }
+
+        // Only the processes we start get the token, so other local users can't use the proxy.
+        let auth_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        proxy_command.env(INTPROXY_AUTH_TOKEN_ENV, &auth_token);
+        env_vars.insert(INTPROXY_AUTH_TOKEN_ENV.to_string(), auth_token);
+
+        if detach_proxy {
+            // Fork the proxy from a process that exits right away, so it's orphaned instead of
+            // becoming a child of the application once we `execve` into it.
+            //
+            // SAFETY: only async-signal-safe functions are called between the fork and `execve`.
+            unsafe {
+                proxy_command.pre_exec(|| match libc::fork() {
+                    -1 => Err(io::Error::last_os_error()),
+                    0 => Ok(()),
+                    _ => libc::_exit(0),
+                });
+            }
+        }
+
         let mut proxy_process = proxy_command.spawn().map_err(|e| {
             CliError::InternalProxySpawnError(format!("failed to spawn child process: {e}"))
         })?;
@@ -194,31 +437,30 @@ impl MirrordExecution {
         let _stderr_guard = watch_stderr(stderr, progress).await;
 
         let stdout = proxy_process.stdout.take().expect("stdout was piped");
+        let mut stdout = BufReader::new(stdout).lines();
 
-        let port: u16 = BufReader::new(stdout)
-            .lines()
-            .next_line()
-            .await
-            .map_err(|e| {
-                CliError::InternalProxySpawnError(format!("failed to read proxy stdout: {e}"))
-            })?
-            .ok_or_else(|| {
-                CliError::InternalProxySpawnError(
-                    "proxy did not print port number to stdout".to_string(),
-                )
-            })?
-            .parse()
-            .map_err(|e| {
-                CliError::InternalProxySpawnError(format!(
-                    "failed to parse port number printed by proxy: {e}"
-                ))
-            })?;
+        let port: u16 = read_proxy_line(&mut stdout, "port number").await?;
+        let proxy_pid = Pid::from_raw(read_proxy_line(&mut stdout, "process id").await?);
+        let proxy_server_port: Option<u16> = if config.feature.network.outgoing.proxy_server {
+            Some(read_proxy_line(&mut stdout, "proxy server port").await?)
+        } else {
+            None
+        };
+
+        if detach_proxy {
+            // Reap the process in between, it exits as soon as the proxy is forked.
+            let _ = proxy_process.wait().await;
+        }
 
         // Provide details for layer to connect to agent via internal proxy
-        env_vars.insert(
-            "MIRRORD_CONNECT_TCP".to_string(),
-            format!("127.0.0.1:{port}"),
-        );
+        let host = intproxy_host(config).await;
+        env_vars.insert("MIRRORD_CONNECT_TCP".to_string(), format!("{host}:{port}"));
+        if let Some(proxy_server_port) = proxy_server_port {
+            env_vars.insert(
+                OUTGOING_PROXY_SERVER_ENV.to_string(),
+                format!("{host}:{proxy_server_port}"),
+            );
+        }
 
         // Fix <https://github.com/metalbear-co/mirrord/issues/1745>
         // by disabling the fork safety check in the Objective-C runtime.
@@ -229,42 +471,114 @@ impl MirrordExecution {
         );
 
         #[cfg(target_os = "macos")]
-        let patched_path = executable
-            .and_then(|exe| {
-                sip_patch(
-                    exe,
-                    &config
-                        .sip_binaries
-                        .clone()
-                        .map(|x| x.to_vec())
-                        .unwrap_or_default(),
-                )
-                .transpose() // We transpose twice to propagate a possible error out of this
-                             // closure.
-            })
-            .transpose()?;
+        let patched_path = patch_sip(config, executable)?;
 
         #[cfg(not(target_os = "macos"))]
         let patched_path = None;
 
         Ok(Self {
             environment: env_vars,
-            child: proxy_process,
+            child: Some(proxy_process),
+            proxy_pid: Some(proxy_pid),
             patched_path,
-            env_to_unset: config
-                .feature
-                .env
-                .unset
-                .clone()
-                .map(|unset| unset.to_vec())
-                .unwrap_or_default(),
+            env_to_unset: env_to_unset(config),
             uses_operator: matches!(connect_info, AgentConnectInfo::Operator(..)),
         })
     }
 
+    /// [`LayerConfig::simulate`] mode: no agent and no internal proxy, the layer prints what it
+    /// would do remotely and does it locally instead.
+    fn simulate<P>(
+        config: &LayerConfig,
+        #[cfg(target_os = "macos")] executable: Option<&str>,
+        lib_path: PathBuf,
+        progress: &mut P,
+    ) -> Result<Self>
+    where
+        P: Progress + Send + Sync,
+    {
+        progress.warning(
+            "running in simulation mode (simulate), nothing is sent to the cluster, the remote \
+             operations are printed and done locally",
+        );
+
+        let mut env_vars = HashMap::new();
+        insert_injection_env(&mut env_vars, lib_path);
+
+        #[cfg(target_os = "macos")]
+        let patched_path = patch_sip(config, executable)?;
+
+        #[cfg(not(target_os = "macos"))]
+        let patched_path = None;
+
+        Ok(Self {
+            environment: env_vars,
+            child: None,
+            proxy_pid: None,
+            patched_path,
+            env_to_unset: env_to_unset(config),
+            uses_operator: false,
+        })
+    }
+
+    /// What the IDE plugins need to run the application, printed by `mirrord ext`.
+    pub(crate) fn info(&self) -> ExecutionInfo {
+        ExecutionInfo {
+            schema_version: SCHEMA_VERSION,
+            environment: self.environment.clone(),
+            patched_path: self.patched_path.clone(),
+            env_to_unset: self.env_to_unset.clone(),
+            uses_operator: self.uses_operator,
+        }
+    }
+
+    /// Runs the application with the local substitutes of `fallback` when the cluster can't be
+    /// reached. Like [`Self::simulate`], but the layer doesn't print the remote operations, and
+    /// resolves `fallback.hosts` itself.
+    fn fallback<P>(
+        config: &LayerConfig,
+        #[cfg(target_os = "macos")] executable: Option<&str>,
+        lib_path: PathBuf,
+        error: CliError,
+        progress: &mut P,
+    ) -> Result<Self>
+    where
+        P: Progress + Send + Sync,
+    {
+        progress.warning(&format!(
+            "couldn't reach the cluster ({error}), running with the local substitutes of \
+             `fallback`, files, connections and incoming traffic stay local"
+        ));
+
+        let mut env_vars = match config.fallback.env_file.as_deref() {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|env_file| parse_dotenv(&env_file))
+                .map_err(|error| CliError::FallbackEnvFileFailed(path.to_path_buf(), error))?,
+            None => HashMap::new(),
+        };
+        env_vars.insert("MIRRORD_SIMULATE".to_string(), "true".to_string());
+        env_vars.insert(FALLBACK_ACTIVE_ENV.to_string(), "true".to_string());
+        insert_injection_env(&mut env_vars, lib_path);
+
+        #[cfg(target_os = "macos")]
+        let patched_path = patch_sip(config, executable)?;
+
+        #[cfg(not(target_os = "macos"))]
+        let patched_path = None;
+
+        Ok(Self {
+            environment: env_vars,
+            child: None,
+            proxy_pid: None,
+            patched_path,
+            env_to_unset: env_to_unset(config),
+            uses_operator: false,
+        })
+    }
+
     /// Construct filter and retrieve remote environment from the connected agent using
     /// `MirrordExecution::get_remote_env`.
-    async fn fetch_env_vars(
+    pub(crate) async fn fetch_env_vars(
         config: &LayerConfig,
         connection: &mut AgentConnection,
     ) -> Result<HashMap<String, String>> {
@@ -372,21 +686,67 @@ impl MirrordExecution {
     /// cleans up the process when the parent process exits, so we need the parent to stay alive
     /// while the internal proxy is running.
     /// See <https://github.com/metalbear-co/mirrord/issues/1211>
-    pub(crate) async fn wait(mut self) -> Result<()> {
-        self.child
-            .wait()
-            .await
-            .map_err(CliError::InternalProxyWaitError)?;
+    ///
+    /// Returns right away when the proxy is detached.
+    pub(crate) async fn wait(self) -> Result<()> {
+        if let Some(mut child) = self.child {
+            child
+                .wait()
+                .await
+                .map_err(CliError::InternalProxyWaitError)?;
+        }
 
         Ok(())
     }
 
-    /// Kills the child process, stopping the internal proxy, and completing the agent.
+    /// Kills the internal proxy, completing the agent.
     ///
     /// Used when mirrord execution fails inside `execvp`.
     pub async fn stop(self) {
-        let Self { mut child, .. } = self;
-
-        let _ = child.start_kill();
+        if let Some(proxy_pid) = self.proxy_pid {
+            let _ = signal::kill(proxy_pid, Signal::SIGKILL);
+        }
     }
 }
+
+/// Sets `LD_PRELOAD`/`DYLD_INSERT_LIBRARIES` to the layer, appending to it if it's already set.
+fn insert_injection_env(env_vars: &mut HashMap<String, String>, lib_path: PathBuf) {
+    let lib_path: String = lib_path.to_string_lossy().into();
+    if let Ok(v) = std::env::var(INJECTION_ENV_VAR) {
+        env_vars.insert(INJECTION_ENV_VAR.to_string(), format!("{v}:{lib_path}"))
+    } else {
+        env_vars.insert(INJECTION_ENV_VAR.to_string(), lib_path)
+    };
+}
+
+/// Patches the `executable` for SIP sidestepping, returns the path to the patched binary.
+#[cfg(target_os = "macos")]
+fn patch_sip(config: &LayerConfig, executable: Option<&str>) -> Result<Option<String>> {
+    let patched_path = executable
+        .and_then(|exe| {
+            sip_patch(
+                exe,
+                &config
+                    .sip_binaries
+                    .clone()
+                    .map(|x| x.to_vec())
+                    .unwrap_or_default(),
+            )
+            .transpose() // We transpose twice to propagate a possible error out of this
+                         // closure.
+        })
+        .transpose()?;
+
+    Ok(patched_path)
+}
+
+/// `feature.env.unset`
+fn env_to_unset(config: &LayerConfig) -> Vec<String> {
+    config
+        .feature
+        .env
+        .unset
+        .clone()
+        .map(|unset| unset.to_vec())
+        .unwrap_or_default()
+}
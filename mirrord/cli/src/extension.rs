@@ -4,7 +4,10 @@ use mirrord_analytics::{AnalyticsError, AnalyticsReporter, Reporter};
 use mirrord_config::LayerConfig;
 use mirrord_progress::{JsonProgress, Progress, ProgressTracker};
 
-use crate::{config::ExtensionExecArgs, error::CliError, execution::MirrordExecution, Result};
+use crate::{
+    config::ExtensionExecArgs, error::CliError, execution::MirrordExecution, otlp,
+    util::generate_routing_value, Result,
+};
 
 /// Actually facilitate execution after all preparations were complete
 async fn mirrord_exec<P>(
@@ -19,17 +22,21 @@ where
 {
     // extension needs more timeout since it might need to build
     // or run tasks before actually launching.
+    //
+    // The proxy is never detached here, we don't `execve` into the application and have to wait
+    // for the proxy.
     #[cfg(target_os = "macos")]
     let mut execution_info =
-        MirrordExecution::start(&config, executable, &mut progress, analytics).await?;
+        MirrordExecution::start(&config, executable, false, None, &mut progress, analytics).await?;
     #[cfg(not(target_os = "macos"))]
-    let mut execution_info = MirrordExecution::start(&config, &mut progress, analytics).await?;
+    let mut execution_info =
+        MirrordExecution::start(&config, false, None, &mut progress, analytics).await?;
 
     // We don't execute so set envs aren't passed, so we need to add config file and target to
     // env.
     execution_info.environment.extend(env);
 
-    let output = serde_json::to_string(&execution_info)?;
+    let output = serde_json::to_string(&execution_info.info())?;
     progress.success(Some(&output));
     execution_info.wait().await?;
 
@@ -57,14 +64,21 @@ pub(crate) async fn extension_exec(args: ExtensionExecArgs, watch: drain::Watch)
         std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target.clone());
         env.insert("MIRRORD_IMPERSONATED_TARGET".into(), target.to_string());
     }
-    let (config, mut context) = LayerConfig::from_env_with_warnings()?;
+    let (mut config, mut context) = LayerConfig::from_env_with_warnings()?;
 
-    let mut analytics = AnalyticsReporter::only_error(config.telemetry, watch);
+    let mut analytics = AnalyticsReporter::only_error(config.telemetry.enabled, watch);
 
     config.verify(&mut context)?;
     for warning in context.get_warnings() {
         progress.warning(warning);
     }
+    generate_routing_value(&mut config);
+
+    if let Err(error) = otlp::init(&config.telemetry, "mirrord-cli") {
+        progress.warning(&format!(
+            "traces won't be exported to `telemetry.otlp_endpoint`: {error}"
+        ));
+    }
 
     #[cfg(target_os = "macos")]
     let execution_result = mirrord_exec(
@@ -78,6 +92,8 @@ pub(crate) async fn extension_exec(args: ExtensionExecArgs, watch: drain::Watch)
     #[cfg(not(target_os = "macos"))]
     let execution_result = mirrord_exec(env, config, progress, &mut analytics).await;
 
+    otlp::flush().await;
+
     if execution_result.is_err() && !analytics.has_error() {
         analytics.set_error(AnalyticsError::Unknown);
     }
@@ -0,0 +1,155 @@
+//! `mirrord grep` searches the files of the target for lines that match a regex.
+//!
+//! The search runs in the agent, so only the matching lines are sent over, instead of whole
+//! directories going through the file operations protocol. The agent runs it with the lowest
+//! scheduling priority, to keep it from taking CPU time from the target.
+
+use std::time::Duration;
+
+use mirrord_analytics::NullReporter;
+use mirrord_progress::{Progress, ProgressTracker};
+use mirrord_protocol::{
+    grep::{DaemonGrep, GrepMatch, GrepRequest, GrepSummary, GREP_VERSION},
+    ClientMessage, DaemonMessage,
+};
+use tokio::time;
+use tracing::{debug, warn};
+
+use crate::{
+    connection::{create_and_connect, AgentConnection},
+    diagnose::load_config,
+    CliError, GrepArgs, Result,
+};
+
+/// How often we ping the agent while waiting for results, so it doesn't consider us gone.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Makes sure that the agent knows the [`ClientMessage::Grep`].
+async fn check_protocol_version(connection: &mut AgentConnection) -> Result<()> {
+    connection
+        .sender
+        .send(ClientMessage::SwitchProtocolVersion(
+            mirrord_protocol::VERSION.clone(),
+        ))
+        .await
+        .map_err(|_| CliError::GrepFailed("agent unexpectedly closed connection".into()))?;
+
+    loop {
+        match connection.receiver.recv().await {
+            Some(DaemonMessage::SwitchProtocolVersionResponse(version)) => {
+                return if GREP_VERSION.matches(&version) {
+                    Ok(())
+                } else {
+                    Err(CliError::GrepFailed(format!(
+                        "the agent's protocol version {version} is too old, it has to match \
+                         {}, please update the agent",
+                        *GREP_VERSION
+                    )))
+                };
+            }
+            Some(DaemonMessage::LogMessage(log)) => warn!("Agent: {}", log.message),
+            Some(DaemonMessage::Close(message)) => {
+                return Err(CliError::GrepFailed(format!(
+                    "agent closed connection with message: {message}"
+                )))
+            }
+            Some(message) => debug!(?message, "Ignoring an unexpected message from the agent"),
+            None => {
+                return Err(CliError::GrepFailed(
+                    "agent unexpectedly closed connection".into(),
+                ))
+            }
+        }
+    }
+}
+
+/// Starts the search and prints the matches until it's done, or until Ctrl+C.
+async fn grep(connection: &mut AgentConnection, request: GrepRequest) -> Result<()> {
+    let max_matches = request.max_matches;
+    connection
+        .sender
+        .send(ClientMessage::Grep(request))
+        .await
+        .map_err(|_| CliError::GrepFailed("agent unexpectedly closed connection".into()))?;
+
+    let mut ping = time::interval(PING_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+
+            _ = ping.tick() => {
+                connection
+                    .sender
+                    .send(ClientMessage::Ping)
+                    .await
+                    .map_err(|_| CliError::GrepFailed("agent unexpectedly closed connection".into()))?;
+            }
+
+            message = connection.receiver.recv() => match message {
+                Some(DaemonMessage::Grep(DaemonGrep::Match(GrepMatch { path, line_number, line }))) => {
+                    println!("{}:{line_number}:{line}", path.display());
+                }
+                Some(DaemonMessage::Grep(DaemonGrep::Finished(result))) => {
+                    let GrepSummary { files_searched, files_skipped, matches, truncated } =
+                        result.map_err(|error| CliError::GrepFailed(error.to_string()))?;
+
+                    eprintln!(
+                        "{matches} matches in {files_searched} files ({files_skipped} skipped)."
+                    );
+                    if truncated {
+                        eprintln!(
+                            "Stopped after {max_matches} matches, use `--max-count` to get more."
+                        );
+                    }
+
+                    return Ok(());
+                }
+                Some(DaemonMessage::Pong) => {}
+                Some(DaemonMessage::LogMessage(log)) => warn!("Agent: {}", log.message),
+                Some(DaemonMessage::Close(message)) => {
+                    return Err(CliError::GrepFailed(format!(
+                        "agent closed connection with message: {message}"
+                    )))
+                }
+                Some(message) => debug!(?message, "Ignoring an unexpected message from the agent"),
+                None => {
+                    return Err(CliError::GrepFailed(
+                        "agent unexpectedly closed connection".into(),
+                    ))
+                }
+            },
+        }
+    }
+}
+
+/// Handle `mirrord grep`.
+pub(crate) async fn grep_command(args: GrepArgs) -> Result<()> {
+    // Fail before connecting to the cluster.
+    regex::RegexBuilder::new(&args.pattern)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .map_err(|error| CliError::GrepFailed(error.to_string()))?;
+
+    if let Some(target) = args.target.as_deref() {
+        std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target);
+    }
+
+    let mut progress = ProgressTracker::from_env("mirrord grep");
+
+    let config = load_config(args.config_file.as_deref())?;
+
+    let mut analytics = NullReporter::default();
+    let (_, mut connection) = create_and_connect(&config, &mut progress, &mut analytics).await?;
+    check_protocol_version(&mut connection).await?;
+    progress.success(Some("connected to the agent"));
+
+    let request = GrepRequest {
+        pattern: args.pattern,
+        paths: args.paths,
+        ignore_case: args.ignore_case,
+        max_matches: args.max_count,
+    };
+
+    grep(&mut connection, request).await
+}
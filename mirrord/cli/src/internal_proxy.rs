@@ -12,33 +12,54 @@
 
 use std::{
     env,
-    fs::OpenOptions,
     io::{self, Write},
-    net::{Ipv4Addr, SocketAddrV4},
-    time::Duration,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use mirrord_analytics::{AnalyticsReporter, CollectAnalytics, Reporter};
-use mirrord_config::LayerConfig;
+use mirrord_config::{feature::fs::SHARED_SCRATCH_DIR_ENV, LayerConfig};
 use mirrord_intproxy::{
-    agent_conn::{AgentConnectInfo, AgentConnection},
+    agent_conn::{AgentConnectInfo, AgentConnection, AgentHandover},
     error::IntProxyError,
+    event_hooks::{EventHooks, SessionEvent},
+    shadow_diff::ShadowDiff,
     IntProxy,
 };
+use mirrord_intproxy_protocol::{AuthToken, INTPROXY_AUTH_TOKEN_ENV, SESSION_DEADLINE_ENV};
+use mirrord_kube::api::{autoscaling::AutoscalingPause, kubernetes::create_kube_api};
 use mirrord_protocol::{ClientMessage, DaemonMessage, LogLevel, LogMessage};
 use nix::{
     libc,
-    sys::resource::{setrlimit, Resource},
+    sys::{
+        resource::{setrlimit, Resource},
+        signal::{self, Signal},
+    },
+    unistd::Pid,
 };
 use tokio::net::TcpListener;
-use tracing::warn;
-use tracing_subscriber::EnvFilter;
+use tracing::{info, warn};
+use tracing_subscriber::{fmt, prelude::*, registry, reload, EnvFilter};
 
 use crate::{
-    connection::AGENT_CONNECT_INFO_ENV_KEY,
+    connection::{
+        CronJobSession, AGENT_CONNECT_INFO_ENV_KEY, CRON_JOB_RUN_ENV_KEY,
+        PAUSED_AUTOSCALING_ENV_KEY,
+    },
     error::{InternalProxyError, Result},
+    otlp,
+    rotating_log::RotatingLogFile,
+    status::{AgentPod, AgentState, SessionState, SessionStateFile},
 };
 
+/// How long after the session expires we end it, so the layer can stop the application first.
+const SESSION_EXPIRY_GRACE: Duration = Duration::from_secs(10);
+
+/// Shortest wait for the layers to connect in `debugger_mode`, where the application can sit at a
+/// breakpoint before the layer connects, or be restarted by the debugger.
+const DEBUGGER_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
 unsafe fn redirect_fd_to_dev_null(fd: libc::c_int) {
     let devnull_fd = libc::open(b"/dev/null\0" as *const [u8; 10] as _, libc::O_RDWR);
     libc::dup2(devnull_fd, fd);
@@ -63,10 +84,14 @@ unsafe fn detach_io() -> Result<(), InternalProxyError> {
 }
 
 /// Print the port for the caller (mirrord cli execution flow) so it can pass it
-/// back to the layer instances via env var.
-fn print_port(listener: &TcpListener) -> io::Result<()> {
+/// back to the layer instances via env var, followed by our pid, so it can stop us when we're
+/// detached from it, and by the port of the proxy server, when it's enabled.
+fn print_port(listener: &TcpListener, proxy_server: Option<&TcpListener>) -> io::Result<()> {
     let port = listener.local_addr()?.port();
-    println!("{port}\n");
+    println!("{port}\n{}", std::process::id());
+    if let Some(proxy_server) = proxy_server {
+        println!("{}", proxy_server.local_addr()?.port());
+    }
     Ok(())
 }
 
@@ -75,17 +100,38 @@ fn print_port(listener: &TcpListener) -> io::Result<()> {
 /// the proxy is under heavy load.
 /// <https://github.com/metalbear-co/mirrord/issues/1716#issuecomment-1663736500>
 /// in macOS backlog is documented to be hardcoded limited to 128.
-fn create_listen_socket() -> io::Result<TcpListener> {
-    let socket = socket2::Socket::new(
-        socket2::Domain::IPV4,
-        socket2::Type::STREAM,
-        Some(socket2::Protocol::TCP),
-    )?;
-
-    socket.bind(&socket2::SockAddr::from(SocketAddrV4::new(
-        Ipv4Addr::LOCALHOST,
-        0,
-    )))?;
+///
+/// Listens on `bind_address` (see
+/// [`InternalProxyConfig::bind_address`](mirrord_config::internal_proxy::InternalProxyConfig::bind_address)),
+/// on the first free port from `port_range` or on a random port.
+fn create_listen_socket(
+    bind_address: Option<IpAddr>,
+    port_range: Option<(u16, u16)>,
+) -> io::Result<TcpListener> {
+    let ip = bind_address.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let domain = match ip {
+        IpAddr::V4(..) => socket2::Domain::IPV4,
+        IpAddr::V6(..) => socket2::Domain::IPV6,
+    };
+    let (start, end) = port_range.unwrap_or((0, 0));
+
+    let mut result = Err(io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "empty port range",
+    ));
+    for port in start..=end {
+        let socket =
+            socket2::Socket::new(domain, socket2::Type::STREAM, Some(socket2::Protocol::TCP))?;
+
+        result = socket
+            .bind(&SocketAddr::new(ip, port).into())
+            .map(|()| socket);
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    let socket = result?;
     socket.listen(1024)?;
     socket.set_nonblocking(true)?;
 
@@ -98,26 +144,60 @@ fn create_listen_socket() -> io::Result<TcpListener> {
 pub(crate) async fn proxy(watch: drain::Watch) -> Result<(), InternalProxyError> {
     let config = LayerConfig::from_env()?;
 
-    if let Some(log_destination) = config.internal_proxy.log_destination.as_ref() {
-        let output_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_destination)
-            .map_err(|e| InternalProxyError::OpenLogFile(log_destination.clone(), e))?;
-
-        let tracing_registry = tracing_subscriber::fmt()
-            .with_writer(output_file)
-            .with_ansi(false);
-
-        if let Some(log_level) = config.internal_proxy.log_level.as_ref() {
-            tracing_registry
-                .with_env_filter(EnvFilter::builder().parse_lossy(log_level))
-                .init();
-        } else {
-            tracing_registry.init();
+    // Reloaded for `mirrord loglevel`.
+    let mut log_filter = None;
+    let log_layer = match config.internal_proxy.log_destination.as_ref() {
+        Some(log_destination) => {
+            let output_file =
+                RotatingLogFile::open(log_destination, &config.internal_proxy.log_rotation)
+                    .map_err(|e| InternalProxyError::OpenLogFile(log_destination.clone(), e))?;
+
+            let log_level = config.internal_proxy.log_level.as_deref().unwrap_or("info");
+            let (filter, handle) = reload::Layer::new(EnvFilter::builder().parse_lossy(log_level));
+            log_filter = Some(handle);
+
+            Some(
+                fmt::layer()
+                    .with_writer(Mutex::new(output_file))
+                    .with_ansi(false)
+                    .with_filter(filter),
+            )
         }
+        None => None,
+    };
+
+    registry().with(log_layer).with(otlp::layer()).init();
+
+    if let Err(error) = otlp::init(&config.telemetry, "mirrord-intproxy") {
+        warn!(%error, "Traces won't be exported to `telemetry.otlp_endpoint`");
     }
 
+    let autoscaling_pause = match env::var(PAUSED_AUTOSCALING_ENV_KEY) {
+        Ok(var) => {
+            let pause: AutoscalingPause = serde_json::from_str(&var)
+                .map_err(|e| InternalProxyError::DeserializePausedAutoscaling(var, e))?;
+            Some(pause)
+        }
+        Err(..) => None,
+    };
+
+    let result = run_session(config.clone(), watch).await;
+
+    // Whatever the reason the session ended, the autoscalers must run again.
+    if let Some(pause) = autoscaling_pause {
+        resume_autoscaling(&config, pause).await;
+    }
+
+    otlp::flush().await;
+
+    result
+}
+
+/// Connects to the agent and runs the internal proxy until the session ends.
+async fn run_session(
+    mut config: LayerConfig,
+    watch: drain::Watch,
+) -> Result<(), InternalProxyError> {
     // According to https://wilsonmar.github.io/maximum-limits/ this is the limit on macOS
     // so we assume Linux can be higher and set to that.
     if let Err(error) = setrlimit(Resource::RLIMIT_NOFILE, 12288, 12288) {
@@ -132,7 +212,25 @@ pub(crate) async fn proxy(watch: drain::Watch) -> Result<(), InternalProxyError>
         }
         Err(..) => None,
     };
-    let mut analytics = AnalyticsReporter::new(config.telemetry, watch);
+
+    let cron_job_session = match env::var(CRON_JOB_RUN_ENV_KEY) {
+        Ok(var) => {
+            let session: CronJobSession = serde_json::from_str(&var)
+                .map_err(|e| InternalProxyError::DeserializeCronJobRun(var, e))?;
+            // The handover has to replace the agent of the Job's pod, not wait for another run.
+            config.target.path = Some(session.run.target());
+            config.target.wait_for_run = None;
+            Some(session)
+        }
+        Err(..) => None,
+    };
+
+    let session_deadline = env::var(SESSION_DEADLINE_ENV)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+    let mut analytics = AnalyticsReporter::new(config.telemetry.enabled, watch);
     (&config).collect_analytics(analytics.get_mut());
 
     // The agent is spawned and our parent process already established a connection.
@@ -141,26 +239,267 @@ pub(crate) async fn proxy(watch: drain::Watch) -> Result<(), InternalProxyError>
     // **before** this happens to ensure that the agent does not prematurely exit.
     // We also perform initial ping pong round to ensure that k8s runtime actually made connection
     // with the agent (it's a must, because port forwarding may be done lazily).
+    let handover = AgentHandover::new(&config, agent_connect_info.as_ref());
+    let connection = match agent_connect_info.as_ref() {
+        Some(AgentConnectInfo::Operator(session)) => {
+            format!("operator session {:x}", session.session_id())
+        }
+        Some(AgentConnectInfo::DirectKubernetes(info)) => format!("agent pod {}", info.pod_name),
+        None => match config.connect_tcp.as_deref() {
+            Some(address) => format!("agent at {address}"),
+            None => "unknown".to_string(),
+        },
+    };
+    let agent_pod = match agent_connect_info.as_ref() {
+        Some(AgentConnectInfo::DirectKubernetes(info)) => Some(AgentPod {
+            name: info.pod_name.clone(),
+            namespace: info.namespace.clone(),
+            container: info.container_name.clone(),
+        }),
+        _ => None,
+    };
     let agent_conn = connect_and_ping(&config, agent_connect_info, &mut analytics).await?;
 
-    // Let it assign port for us then print it for the user.
-    let listener = create_listen_socket().map_err(InternalProxyError::ListenerSetup)?;
-    print_port(&listener).map_err(InternalProxyError::ListenerSetup)?;
+    // Bind the listener (on a random port, unless configured) then print the port for the user.
+    let listener = create_listen_socket(
+        config.internal_proxy.bind_address,
+        config.internal_proxy.port_range,
+    )
+    .map_err(InternalProxyError::ListenerSetup)?;
+    // The proxy server always gets a random port, so it doesn't take one from the range.
+    let proxy_server = config
+        .feature
+        .network
+        .outgoing
+        .proxy_server
+        .then(|| create_listen_socket(config.internal_proxy.bind_address, None))
+        .transpose()
+        .map_err(InternalProxyError::ListenerSetup)?;
+    print_port(&listener, proxy_server.as_ref()).map_err(InternalProxyError::ListenerSetup)?;
+
+    let shadow_diff = config
+        .feature
+        .network
+        .incoming
+        .http_filter
+        .shadow_diff
+        .as_deref()
+        .map(|path| {
+            ShadowDiff::new(path)
+                .map_err(|error| InternalProxyError::OpenShadowDiffReport(path.to_string(), error))
+        })
+        .transpose()?;
 
     unsafe {
         detach_io()?;
     }
 
-    let first_connection_timeout = Duration::from_secs(config.internal_proxy.start_idle_timeout);
-    let consecutive_connection_timeout = Duration::from_secs(config.internal_proxy.idle_timeout);
+    let session_state = SessionState {
+        pid: std::process::id(),
+        started_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        target: config
+            .target
+            .path
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_else(|| "targetless".to_string()),
+        namespace: config.target.namespace.clone(),
+        connection,
+        address: listener
+            .local_addr()
+            .map(|address| address.to_string())
+            .unwrap_or_default(),
+        log_file: config
+            .internal_proxy
+            .log_destination
+            .clone()
+            .map(Into::into),
+        agent: AgentState::Connected,
+        agent_pod,
+    };
+
+    let mut event_hooks = EventHooks::new(&config);
+    // For `mirrord status`, removed when the session ends.
+    match SessionStateFile::create(session_state) {
+        Ok(state_file) => {
+            let state_file = Arc::new(state_file);
+            event_hooks = event_hooks.with_listener(move |event| state_file.on_event(event));
+        }
+        Err(error) => warn!(%error, "Failed to create the session state file for `mirrord status`"),
+    }
+    event_hooks.trigger(SessionEvent::SessionStart);
 
-    IntProxy::new_with_connection(agent_conn, listener)
-        .run(first_connection_timeout, consecutive_connection_timeout)
-        .await
-        .map_err(InternalProxyError::from)
+    let mut first_connection_timeout =
+        Duration::from_secs(config.internal_proxy.start_idle_timeout);
+    let mut consecutive_connection_timeout =
+        Duration::from_secs(config.internal_proxy.idle_timeout);
+    if config.debugger_mode {
+        first_connection_timeout = first_connection_timeout.max(DEBUGGER_IDLE_TIMEOUT);
+        consecutive_connection_timeout = consecutive_connection_timeout.max(DEBUGGER_IDLE_TIMEOUT);
+    }
+
+    let auth_token = env::var(INTPROXY_AUTH_TOKEN_ENV).ok().map(AuthToken);
+    let mut intproxy = IntProxy::new_with_connection(
+        agent_conn,
+        listener,
+        auth_token,
+        event_hooks.clone(),
+        shadow_diff,
+        config.feature.network.incoming.on_local_error,
+        (&config.internal_proxy).into(),
+    );
+    if let Some(handover) = handover {
+        intproxy = intproxy.with_agent_handover(handover);
+    }
+    if config.feature.network.incoming.auto_ports {
+        intproxy = intproxy.with_auto_incoming_ports();
+    }
+    if config.debugger_mode {
+        intproxy = intproxy.with_debugger_mode();
+    } else if config.feature.network.incoming.detects_stalls() {
+        intproxy = intproxy.with_stall_detection(
+            config.feature.network.incoming.on_stall,
+            config.feature.network.incoming.stall_timeout(),
+        );
+    }
+    if let Some(max_concurrent) = config.feature.network.incoming.max_concurrent {
+        intproxy = intproxy
+            .with_connection_limit(max_concurrent, config.feature.network.incoming.queue_size());
+    }
+    if config.feature.fs.is_write() {
+        intproxy = intproxy.with_remote_fifos();
+    }
+    if let Some(listener) = proxy_server {
+        intproxy = intproxy.with_proxy_server(listener);
+    }
+    if let Some(handle) = log_filter {
+        intproxy = intproxy.with_log_level_control(Box::new(move |directives| {
+            let filter = EnvFilter::try_new(directives).map_err(|error| error.to_string())?;
+            handle.reload(filter).map_err(|error| error.to_string())
+        }));
+    }
+    if let (Some(remote), Some(local)) = (
+        config.feature.fs.shared_scratch.as_ref(),
+        env::var_os(SHARED_SCRATCH_DIR_ENV),
+    ) {
+        intproxy = intproxy.with_shared_scratch(remote.into(), local.into());
+    }
+    if let Some(path) = env::var_os("MIRRORD_CONFIG_FILE").filter(|_| config.session.hot_reload) {
+        intproxy = intproxy.with_config_reload(path.into());
+    }
+
+    let run = intproxy.run(first_connection_timeout, consecutive_connection_timeout);
+    let job_finished = async {
+        match cron_job_session {
+            Some(session) => cron_job_finished(&config, session).await,
+            None => std::future::pending().await,
+        }
+    };
+    let (result, reason) = tokio::select! {
+        result = run => (result, None),
+        reason = job_finished => (Ok(()), Some(reason)),
+        reason = session_expired(session_deadline) => (Ok(()), Some(reason)),
+    };
+
+    let reason = match (&result, reason) {
+        (Ok(()), Some(reason)) => reason,
+        (Ok(()), None) => "session finished".to_string(),
+        (Err(error), _) => error.to_string(),
+    };
+    event_hooks.trigger(SessionEvent::Disconnect { reason });
+
+    result.map_err(InternalProxyError::from)
+}
+
+/// Resumes the autoscalers paused for the session, see [`AutoscalingPause`].
+async fn resume_autoscaling(config: &LayerConfig, pause: AutoscalingPause) {
+    let resumed = match create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    {
+        Ok(client) => pause.resume(&client).await,
+        Err(error) => Err(error),
+    };
+
+    match resumed {
+        Ok(not_restored) => {
+            for reason in not_restored {
+                warn!("{reason}, its settings were not restored");
+            }
+            info!(?pause, "Resumed the autoscalers of the target");
+        }
+        Err(error) => warn!(
+            %error,
+            ?pause,
+            "Failed to resume the autoscalers of the target, remove the \
+             `mirrord.metalbear.co/paused-autoscaling` annotation from them and restore their \
+             settings by hand"
+        ),
+    }
+}
+
+/// Waits for the Job of the [`CronJobSession`] to finish, then stops the application, if we know
+/// it. Returns the reason for ending the session.
+///
+/// Never returns when we can't watch the Job, the session then ends the usual way.
+async fn cron_job_finished(config: &LayerConfig, session: CronJobSession) -> String {
+    let CronJobSession { run, app_pid } = session;
+
+    let finished = match create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    {
+        Ok(client) => run.finished(&client).await,
+        Err(error) => Err(error),
+    };
+
+    match finished {
+        Ok(true) => info!(job = %run.job, "Job finished, ending the session"),
+        Ok(false) => warn!(job = %run.job, "Job failed, ending the session"),
+        Err(error) => {
+            warn!(%error, job = %run.job, "Failed to watch the Job, the session won't end with it");
+            return std::future::pending().await;
+        }
+    }
+
+    if let Some(pid) = app_pid {
+        if let Err(error) = signal::kill(Pid::from_raw(pid), Signal::SIGTERM) {
+            warn!(%error, pid, "Failed to stop the application");
+        }
+    }
+
+    format!("job/{} finished", run.job)
+}
+
+/// Waits until the session expires (`session.max_duration`) and the layer had the time to stop
+/// the application. Returns the reason for ending the session.
+///
+/// Never returns when the session doesn't expire.
+async fn session_expired(deadline: Option<SystemTime>) -> String {
+    let Some(deadline) = deadline else {
+        return std::future::pending().await;
+    };
+
+    let remaining = deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    tokio::time::sleep(remaining + SESSION_EXPIRY_GRACE).await;
+    info!("Session reached its maximum duration, ending it");
+
+    "session reached its maximum duration (session.max_duration)".to_string()
 }
 
 /// Creates a connection with the agent and handles one round of ping pong.
+#[tracing::instrument(level = "info", name = "agent_connection", skip_all)]
 async fn connect_and_ping(
     config: &LayerConfig,
     connect_info: Option<AgentConnectInfo>,
@@ -0,0 +1,391 @@
+//! `mirrord ls` with `--output`, `--type` or `--selector`: the targets of [`list_targets`] with
+//! their type, containers and status, read from the cluster.
+//!
+//! [`list_targets`]: crate::list_targets
+
+use std::collections::{HashMap, HashSet};
+
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, StatefulSet},
+        batch::v1::{CronJob, Job},
+        core::v1::{Pod, PodSpec},
+    },
+    NamespaceResourceScope,
+};
+use kube::{api::ListParams, Client, ResourceExt};
+use mirrord_config::LayerConfig;
+use mirrord_ext_schema::{
+    list_targets::{ListedTarget, TargetStatus},
+    verify_config::TargetType,
+};
+use mirrord_kube::api::{
+    container::SKIP_NAMES,
+    kubernetes::{create_kube_api, get_k8s_resource_api, rollout::Rollout},
+};
+use prettytable::{row, Table};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{
+    config::{Format, ListTargetArgs, ListTargetType},
+    CliError, Result,
+};
+
+/// Containers and status of a target, by its type and name.
+type TargetDetails = HashMap<(TargetType, String), (Vec<String>, Option<TargetStatus>)>;
+
+/// Prints the targets in `paths` that match the filters of `args`, in the format of `args.output`.
+pub(crate) async fn print_listed_targets(
+    config: &LayerConfig,
+    args: &ListTargetArgs,
+    paths: Vec<String>,
+) -> Result<()> {
+    let targets = listed_targets(config, args, paths).await?;
+
+    match args.output {
+        None => {
+            let paths = targets
+                .iter()
+                .map(|target| target.path.as_str())
+                .collect::<Vec<_>>();
+            println!("{}", json!(paths));
+        }
+        Some(Format::Json) => println!("{}", json!(targets)),
+        Some(format @ (Format::Table | Format::Wide)) => {
+            print_table(&targets, format == Format::Wide)
+        }
+    }
+
+    Ok(())
+}
+
+async fn listed_targets(
+    config: &LayerConfig,
+    args: &ListTargetArgs,
+    paths: Vec<String>,
+) -> Result<Vec<ListedTarget>> {
+    let types = args
+        .target_types
+        .iter()
+        .copied()
+        .map(target_type)
+        .collect::<Vec<_>>();
+
+    let targets = paths
+        .into_iter()
+        .filter_map(|path| {
+            let (target_type, name, container) = parse_path(&path)?;
+            Some(ListedTarget {
+                path,
+                target_type,
+                name,
+                container,
+                containers: Default::default(),
+                status: None,
+            })
+        })
+        .filter(|target| types.is_empty() || types.contains(&target.target_type))
+        .collect::<Vec<_>>();
+
+    let client = create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    .map_err(CliError::CreateKubeApiFailed)?;
+
+    let namespace = config.target.namespace.as_deref();
+    let params = ListParams {
+        label_selector: Some(match &args.selector {
+            Some(selector) => format!("app!=mirrord,{selector}"),
+            None => "app!=mirrord".to_string(),
+        }),
+        ..Default::default()
+    };
+
+    let mut details = TargetDetails::new();
+    let listed_types = targets
+        .iter()
+        .map(|target| target.target_type)
+        .collect::<HashSet<_>>();
+    for target_type in listed_types {
+        match target_details(&client, namespace, &params, target_type).await {
+            Ok(listed) => details.extend(listed),
+            // With a selector we can't tell which targets match it.
+            Err(error) if args.selector.is_some() => {
+                return Err(CliError::ListTargetsFailed(error))
+            }
+            Err(_) => {}
+        }
+    }
+
+    Ok(targets
+        .into_iter()
+        .filter_map(|mut target| {
+            match details.get(&(target.target_type, target.name.clone())) {
+                Some((containers, status)) => {
+                    target.containers = containers.clone();
+                    target.status = *status;
+                }
+                None if args.selector.is_some() => return None,
+                None => {}
+            }
+
+            Some(target)
+        })
+        .collect())
+}
+
+/// Lists the targets of `target_type` that match `params`, with their containers and status.
+async fn target_details(
+    client: &Client,
+    namespace: Option<&str>,
+    params: &ListParams,
+    target_type: TargetType,
+) -> kube::Result<TargetDetails> {
+    let details: Vec<(String, (Vec<String>, Option<TargetStatus>))> = match target_type {
+        TargetType::Pod => list::<Pod>(client, namespace, params)
+            .await?
+            .into_iter()
+            .map(|pod| {
+                let containers = container_names(pod.spec.as_ref());
+                let ready = pod
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.container_statuses.as_ref())
+                    .into_iter()
+                    .flatten()
+                    .filter(|status| status.ready && containers.contains(&status.name))
+                    .count();
+                let status = TargetStatus {
+                    ready: ready as u32,
+                    desired: containers.len() as u32,
+                };
+                (pod.name_any(), (containers, Some(status)))
+            })
+            .collect(),
+        TargetType::Deployment => list::<Deployment>(client, namespace, params)
+            .await?
+            .into_iter()
+            .map(|deployment| {
+                let spec = deployment.spec.as_ref();
+                let containers = container_names(spec.and_then(|spec| spec.template.spec.as_ref()));
+                let status = target_status(
+                    deployment
+                        .status
+                        .as_ref()
+                        .and_then(|status| status.ready_replicas),
+                    spec.and_then(|spec| spec.replicas),
+                );
+                (deployment.name_any(), (containers, Some(status)))
+            })
+            .collect(),
+        TargetType::StatefulSet => list::<StatefulSet>(client, namespace, params)
+            .await?
+            .into_iter()
+            .map(|stateful_set| {
+                let spec = stateful_set.spec.as_ref();
+                let containers = container_names(spec.and_then(|spec| spec.template.spec.as_ref()));
+                let status = target_status(
+                    stateful_set
+                        .status
+                        .as_ref()
+                        .and_then(|status| status.ready_replicas),
+                    spec.and_then(|spec| spec.replicas),
+                );
+                (stateful_set.name_any(), (containers, Some(status)))
+            })
+            .collect(),
+        TargetType::Job => list::<Job>(client, namespace, params)
+            .await?
+            .into_iter()
+            .map(|job| {
+                let spec = job.spec.as_ref();
+                let containers = container_names(spec.and_then(|spec| spec.template.spec.as_ref()));
+                let status = target_status(
+                    job.status.as_ref().and_then(|status| status.ready),
+                    spec.and_then(|spec| spec.parallelism),
+                );
+                (job.name_any(), (containers, Some(status)))
+            })
+            .collect(),
+        TargetType::CronJob => list::<CronJob>(client, namespace, params)
+            .await?
+            .into_iter()
+            .map(|cron_job| {
+                let containers = container_names(
+                    cron_job
+                        .spec
+                        .as_ref()
+                        .and_then(|spec| spec.job_template.spec.as_ref())
+                        .and_then(|spec| spec.template.spec.as_ref()),
+                );
+                (cron_job.name_any(), (containers, None))
+            })
+            .collect(),
+        TargetType::Rollout => list::<Rollout>(client, namespace, params)
+            .await?
+            .into_iter()
+            .map(|rollout| {
+                let containers = rollout
+                    .spec
+                    .pointer("/template/spec/containers")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|container| container.get("name")?.as_str())
+                    .filter(|name| !SKIP_NAMES.contains(name))
+                    .map(ToString::to_string)
+                    .collect();
+                let as_i32 = |value: Option<&Value>| {
+                    value
+                        .and_then(Value::as_i64)
+                        .and_then(|value| i32::try_from(value).ok())
+                };
+                let status = target_status(
+                    as_i32(
+                        rollout
+                            .status
+                            .as_ref()
+                            .and_then(|status| status.get("readyReplicas")),
+                    ),
+                    as_i32(rollout.spec.get("replicas")),
+                );
+                (rollout.name_any(), (containers, Some(status)))
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Ok(details
+        .into_iter()
+        .map(|(name, details)| ((target_type, name), details))
+        .collect())
+}
+
+async fn list<K>(
+    client: &Client,
+    namespace: Option<&str>,
+    params: &ListParams,
+) -> kube::Result<Vec<K>>
+where
+    K: kube::Resource<Scope = NamespaceResourceScope>,
+    <K as kube::Resource>::DynamicType: Default,
+    K: Clone + DeserializeOwned + std::fmt::Debug,
+{
+    get_k8s_resource_api(client, namespace)
+        .list(params)
+        .await
+        .map(|resources| resources.items)
+}
+
+/// Names of the containers in `spec`, without the mesh sidecars.
+fn container_names(spec: Option<&PodSpec>) -> Vec<String> {
+    spec.map(|spec| spec.containers.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|container| !SKIP_NAMES.contains(container.name.as_str()))
+        .map(|container| container.name.clone())
+        .collect()
+}
+
+/// Status of a workload, it has 1 replica when `replicas` is not set.
+fn target_status(ready_replicas: Option<i32>, replicas: Option<i32>) -> TargetStatus {
+    TargetStatus {
+        ready: ready_replicas.unwrap_or(0).max(0) as u32,
+        desired: replicas.unwrap_or(1).max(0) as u32,
+    }
+}
+
+fn target_type(target_type: ListTargetType) -> TargetType {
+    match target_type {
+        ListTargetType::Pod => TargetType::Pod,
+        ListTargetType::Deployment => TargetType::Deployment,
+        ListTargetType::Rollout => TargetType::Rollout,
+        ListTargetType::Job => TargetType::Job,
+        ListTargetType::CronJob => TargetType::CronJob,
+        ListTargetType::StatefulSet => TargetType::StatefulSet,
+    }
+}
+
+/// Splits a target path, e.g. `deployment/app/container/main`, into its type, name and
+/// container.
+fn parse_path(path: &str) -> Option<(TargetType, String, Option<String>)> {
+    let mut parts = path.split('/');
+
+    let target_type = match parts.next()? {
+        "pod" => TargetType::Pod,
+        "deployment" => TargetType::Deployment,
+        "rollout" => TargetType::Rollout,
+        "job" => TargetType::Job,
+        "cronjob" | "cron_job" => TargetType::CronJob,
+        "statefulset" | "stateful_set" => TargetType::StatefulSet,
+        _ => return None,
+    };
+    let name = parts.next()?.to_string();
+    let container = match (parts.next(), parts.next()) {
+        (Some("container"), Some(container)) => Some(container.to_string()),
+        _ => None,
+    };
+
+    Some((target_type, name, container))
+}
+
+fn print_table(targets: &[ListedTarget], wide: bool) {
+    let mut table = Table::new();
+    if wide {
+        table.add_row(row!["Type", "Name", "Container", "Ready", "Containers"]);
+    } else {
+        table.add_row(row!["Type", "Name", "Container", "Ready"]);
+    }
+
+    for target in targets {
+        let target_type = serde_json::to_value(target.target_type)
+            .ok()
+            .and_then(|value| value.as_str().map(ToString::to_string))
+            .unwrap_or_default();
+        let container = target.container.as_deref().unwrap_or("-");
+        let ready = target
+            .status
+            .map(|status| format!("{}/{}", status.ready, status.desired))
+            .unwrap_or_else(|| "-".to_string());
+
+        if wide {
+            table.add_row(row![
+                target_type,
+                target.name,
+                container,
+                ready,
+                target.containers.join(",")
+            ]);
+        } else {
+            table.add_row(row![target_type, target.name, container, ready]);
+        }
+    }
+
+    table.printstd();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_paths() {
+        assert_eq!(
+            parse_path("deployment/app/container/main"),
+            Some((
+                TargetType::Deployment,
+                "app".to_string(),
+                Some("main".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_path("cronjob/nightly"),
+            Some((TargetType::CronJob, "nightly".to_string(), None))
+        );
+        assert_eq!(parse_path("targetless"), None);
+    }
+}
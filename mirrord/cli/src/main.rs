@@ -5,14 +5,22 @@
 
 use std::{collections::HashMap, time::Duration};
 
+use agent_logs::agent_command;
+use analyze::analyze_command;
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
 use config::*;
+use config_init::config_command;
+use debug_bundle::debug_bundle_command;
 use diagnose::diagnose_command;
+use dns::dns_command;
+use dump::dump_command;
+use env::env_command;
 use exec::execvp;
 use execution::MirrordExecution;
 use extension::extension_exec;
 use extract::extract_library;
+use grep::grep_command;
 use k8s_openapi::{
     api::{apps::v1::Deployment, core::v1::Pod},
     Metadata, NamespaceResourceScope,
@@ -32,23 +40,48 @@ use mirrord_kube::api::{
 };
 use mirrord_operator::client::OperatorApi;
 use mirrord_progress::{Progress, ProgressTracker};
+use nix::unistd::Pid;
 use operator::operator_command;
+use policy::policy_command;
 use semver::Version;
 use serde::de::DeserializeOwned;
 use serde_json::json;
+use session::{log_level_command, session_command};
+use setup::setup_command;
+use status::status_command;
+use target_logs::{spawn_target_logs, target_logs_command};
 use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, prelude::*, registry, EnvFilter};
 use which::which;
 
+mod agent_logs;
+mod analyze;
 mod config;
+mod config_init;
 mod connection;
+mod debug_bundle;
 mod diagnose;
+mod dns;
+mod dump;
+mod env;
 mod error;
 mod execution;
 mod extension;
 mod extract;
+mod grep;
 mod internal_proxy;
+mod ls;
 mod operator;
+mod otlp;
+mod policy;
+mod rotating_log;
+mod select_target;
+mod session;
+mod setup;
+mod status;
+#[cfg(target_os = "linux")]
+mod target_limits;
+mod target_logs;
 mod teams;
 mod util;
 mod verify_config;
@@ -56,7 +89,33 @@ mod verify_config;
 pub(crate) use error::{CliError, Result};
 use verify_config::verify_config;
 
-use crate::util::remove_proxy_env;
+use crate::util::{generate_routing_value, set_proxy_env};
+
+/// Moves this process into a cgroup with the CPU and memory limits of the target container, see
+/// [`target_limits`]. Warns instead of failing, the process can still run without them.
+#[cfg(target_os = "linux")]
+async fn enforce_target_limits<P: Progress>(config: &LayerConfig, progress: &P) {
+    let limits = match target_limits::TargetLimits::fetch(config).await {
+        Ok(Some(limits)) if !limits.is_empty() => limits,
+        Ok(_) => {
+            progress.warning("the target has no CPU or memory limits to enforce");
+            return;
+        }
+        Err(error) => {
+            progress.warning(&format!(
+                "{error}, the process will run without the limits of the target"
+            ));
+            return;
+        }
+    };
+
+    match limits.enter().await {
+        Ok(()) => progress.info(&format!("running with the limits of the target: {limits}")),
+        Err(error) => progress.warning(&format!(
+            "{error}, the process will run without the limits of the target"
+        )),
+    }
+}
 
 async fn exec_process<P>(
     config: LayerConfig,
@@ -70,10 +129,24 @@ where
     let mut sub_progress = progress.subtask("preparing to launch process");
 
     #[cfg(target_os = "macos")]
-    let execution_info =
-        MirrordExecution::start(&config, Some(&args.binary), &mut sub_progress, analytics).await?;
+    let execution_info = MirrordExecution::start(
+        &config,
+        Some(&args.binary),
+        config.internal_proxy.detach,
+        Some(Pid::this()),
+        &mut sub_progress,
+        analytics,
+    )
+    .await?;
     #[cfg(not(target_os = "macos"))]
-    let execution_info = MirrordExecution::start(&config, &mut sub_progress, analytics).await?;
+    let execution_info = MirrordExecution::start(
+        &config,
+        config.internal_proxy.detach,
+        Some(Pid::this()),
+        &mut sub_progress,
+        analytics,
+    )
+    .await?;
 
     #[cfg(target_os = "macos")]
     let (_did_sip_patch, binary) = match execution_info.patched_path {
@@ -84,6 +157,22 @@ where
     #[cfg(not(target_os = "macos"))]
     let binary = args.binary.clone();
 
+    // Before the env is set, so the layer isn't loaded into it.
+    if config.feature.target_logs && !config.simulate {
+        if let Err(error) = spawn_target_logs(Pid::this()) {
+            progress.warning(&format!("{error}, the logs of the target won't be shown"));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.experimental.enforce_target_limits && !config.simulate {
+        enforce_target_limits(&config, progress).await;
+    }
+    #[cfg(not(target_os = "linux"))]
+    if config.experimental.enforce_target_limits {
+        progress.warning("`experimental.enforce_target_limits` is only supported on Linux");
+    }
+
     // Stop confusion with layer
     std::env::set_var(mirrord_progress::MIRRORD_PROGRESS_ENV, "off");
 
@@ -113,6 +202,8 @@ where
     );
     sub_progress_config.success(Some("config summary"));
 
+    otlp::flush().await;
+
     // The execve hook is not yet active and does not hijack this call.
     let err = execvp(binary.clone(), binary_args.clone());
     error!("Couldn't execute {:?}", err);
@@ -284,6 +375,10 @@ fn print_config<P>(
 }
 
 async fn exec(args: &ExecArgs, watch: drain::Watch) -> Result<()> {
+    if args.quiet {
+        std::env::set_var(mirrord_progress::MIRRORD_PROGRESS_ENV, "off");
+    }
+
     let progress = ProgressTracker::from_env("mirrord exec");
     if !args.disable_version_check {
         prompt_outdated_version(&progress).await;
@@ -305,6 +400,10 @@ async fn exec(args: &ExecArgs, watch: drain::Watch) -> Result<()> {
         std::env::set_var("MIRRORD_TELEMETRY", "false");
     }
 
+    if args.simulate {
+        std::env::set_var("MIRRORD_SIMULATE", "true");
+    }
+
     if let Some(skip_processes) = &args.skip_processes {
         std::env::set_var("MIRRORD_SKIP_PROCESSES", skip_processes.clone());
     }
@@ -313,6 +412,10 @@ async fn exec(args: &ExecArgs, watch: drain::Watch) -> Result<()> {
         std::env::set_var("MIRRORD_TARGET_NAMESPACE", namespace.clone());
     }
 
+    if let Some(wait_for_run) = args.wait_for_run {
+        std::env::set_var("MIRRORD_TARGET_WAIT_FOR_RUN", wait_for_run.to_string());
+    }
+
     if let Some(namespace) = &args.agent_namespace {
         std::env::set_var("MIRRORD_AGENT_NAMESPACE", namespace.clone());
     }
@@ -370,6 +473,10 @@ async fn exec(args: &ExecArgs, watch: drain::Watch) -> Result<()> {
         std::env::set_var("MIRRORD_AGENT_TCP_STEAL_TRAFFIC", "true");
     };
 
+    if let Some(join_session) = &args.join_session {
+        std::env::set_var("MIRRORD_SESSION_JOIN", join_session);
+    }
+
     if args.no_outgoing || args.no_tcp_outgoing {
         std::env::set_var("MIRRORD_TCP_OUTGOING", "false");
     }
@@ -390,15 +497,38 @@ async fn exec(args: &ExecArgs, watch: drain::Watch) -> Result<()> {
         std::env::set_var("MIRRORD_CONFIG_FILE", full_path);
     }
 
-    let (config, mut context) = LayerConfig::from_env_with_warnings()?;
+    let (mut config, mut context) = LayerConfig::from_env_with_warnings()?;
 
-    let mut analytics = AnalyticsReporter::only_error(config.telemetry, watch);
+    if args.select_target && config.target.path.is_none() {
+        select_target::select_target(&mut config).await?;
+    }
+
+    let mut analytics = AnalyticsReporter::only_error(config.telemetry.enabled, watch);
     (&config).collect_analytics(analytics.get_mut());
 
     config.verify(&mut context)?;
     for warning in context.get_warnings() {
         progress.warning(warning);
     }
+    generate_routing_value(&mut config);
+
+    if args.update_agent && !config.agent.image.is_current_version() {
+        let image = config.agent.image.with_current_version();
+        progress.info(&format!(
+            "using the agent image `{}` instead of `{}` (--update-agent)",
+            image.0,
+            config.agent.image()
+        ));
+        // The internal proxy reads the config again, e.g. to replace the agent.
+        std::env::set_var("MIRRORD_AGENT_IMAGE", &image.0);
+        config.agent.image = image;
+    }
+
+    if let Err(error) = otlp::init(&config.telemetry, "mirrord-cli") {
+        progress.warning(&format!(
+            "traces won't be exported to `telemetry.otlp_endpoint`: {error}"
+        ));
+    }
 
     let execution_result = exec_process(config, args, &progress, &mut analytics).await;
 
@@ -499,7 +629,7 @@ where
         .unwrap_or_default()
 }
 
-async fn list_pods(layer_config: &LayerConfig, args: &ListTargetArgs) -> Result<Vec<String>> {
+async fn list_pods(layer_config: &LayerConfig) -> Result<Vec<String>> {
     let client = create_kube_api(
         layer_config.accept_invalid_certificates,
         layer_config.kubeconfig.clone(),
@@ -508,10 +638,7 @@ async fn list_pods(layer_config: &LayerConfig, args: &ListTargetArgs) -> Result<
     .await
     .map_err(CliError::CreateKubeApiFailed)?;
 
-    let namespace = args
-        .namespace
-        .as_deref()
-        .or(layer_config.target.namespace.as_deref());
+    let namespace = layer_config.target.namespace.as_deref();
 
     let (pods, deployments, rollouts) = futures::join!(
         get_kube_pods(namespace, &client),
@@ -546,6 +673,8 @@ async fn list_pods(layer_config: &LayerConfig, args: &ListTargetArgs) -> Result<
 ///  "deployment/nginx-deployment/container/nginx"
 ///  "rollout/nginx-rollout"
 /// ]```
+///
+/// With `--output`, `--type` or `--selector`, the targets are printed by [`ls`].
 async fn print_targets(args: &ListTargetArgs) -> Result<()> {
     let mut layer_config = if let Some(config) = &args.config_file {
         let mut cfg_context = ConfigContext::default();
@@ -558,14 +687,26 @@ async fn print_targets(args: &ListTargetArgs) -> Result<()> {
         layer_config.target.namespace = Some(namespace.clone());
     };
 
-    if !layer_config.use_proxy {
-        remove_proxy_env();
+    let targets = list_targets(&layer_config).await?;
+
+    if args.output.is_some() || !args.target_types.is_empty() || args.selector.is_some() {
+        return ls::print_listed_targets(&layer_config, args, targets).await;
     }
 
+    let json_obj = json!(targets);
+    println!("{json_obj}");
+    Ok(())
+}
+
+/// The target paths in `target.namespace` (or the default namespace), sorted, see
+/// [`print_targets`].
+pub(crate) async fn list_targets(layer_config: &LayerConfig) -> Result<Vec<String>> {
+    set_proxy_env(layer_config);
+
     // Try operator first if relevant
     let mut targets = match &layer_config.operator {
         Some(true) | None => {
-            let operator_targets = OperatorApi::list_targets(&layer_config).await;
+            let operator_targets = OperatorApi::list_targets(layer_config).await;
             match operator_targets {
                 Ok(targets) => {
                     // adjust format to match non-operator output
@@ -591,18 +732,16 @@ async fn print_targets(args: &ListTargetArgs) -> Result<()> {
                         );
                         return Err(error.into());
                     }
-                    list_pods(&layer_config, args).await?
+                    list_pods(layer_config).await?
                 }
             }
         }
-        Some(false) => list_pods(&layer_config, args).await?,
+        Some(false) => list_pods(layer_config).await?,
     };
 
     targets.sort();
 
-    let json_obj = json!(targets);
-    println!("{json_obj}");
-    Ok(())
+    Ok(targets)
 }
 
 const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -625,9 +764,16 @@ fn main() -> miette::Result<()> {
             mirrord_console::init_async_logger(&console_addr, watch.clone(), 124).await?;
         } else if !init_ext_error_handler(&cli.commands) {
             registry()
-                .with(fmt::layer().with_writer(std::io::stderr))
-                .with(EnvFilter::from_default_env())
+                .with(
+                    fmt::layer()
+                        .with_writer(std::io::stderr)
+                        .with_filter(EnvFilter::from_default_env()),
+                )
+                .with(otlp::layer())
                 .init();
+        } else if matches!(cli.commands, Commands::ExtensionExec(..)) {
+            // No logs here, the extensions read our output.
+            registry().with(otlp::layer()).init();
         }
 
         match cli.commands {
@@ -652,6 +798,20 @@ fn main() -> miette::Result<()> {
             }
             Commands::Teams => teams::navigate_to_intro().await,
             Commands::Diagnose(args) => diagnose_command(*args).await?,
+            Commands::Session(args) => session_command(*args)?,
+            Commands::LogLevel(args) => log_level_command(*args)?,
+            Commands::Status => status_command()?,
+            Commands::Setup(args) => setup_command(*args)?,
+            Commands::Dump(args) => dump_command(*args).await?,
+            Commands::Grep(args) => grep_command(*args).await?,
+            Commands::Analyze(args) => analyze_command(args.command).await?,
+            Commands::DebugBundle(args) => debug_bundle_command(*args).await?,
+            Commands::Env(args) => env_command(*args).await?,
+            Commands::Dns(args) => dns_command(args.command).await?,
+            Commands::Agent(args) => agent_command(args.command).await?,
+            Commands::Config(args) => config_command(args.command)?,
+            Commands::Policy(args) => policy_command(*args)?,
+            Commands::TargetLogs(args) => target_logs_command(args).await?,
         };
 
         Ok(())
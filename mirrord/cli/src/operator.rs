@@ -27,7 +27,7 @@ use self::session::SessionCommandHandler;
 use crate::{
     config::{OperatorArgs, OperatorCommand},
     error::{CliError, OperatorSetupError},
-    util::remove_proxy_env,
+    util::set_proxy_env,
     Result,
 };
 
@@ -126,9 +126,7 @@ async fn get_status_api(config: Option<&Path>) -> Result<Api<MirrordOperatorCrd>
         LayerConfig::from_env()?
     };
 
-    if !layer_config.use_proxy {
-        remove_proxy_env();
-    }
+    set_proxy_env(&layer_config);
 
     let kube_api = create_kube_api(
         layer_config.accept_invalid_certificates,
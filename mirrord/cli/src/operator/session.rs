@@ -1,7 +1,10 @@
-use kube::{core::ErrorResponse, Api};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{api::PostParams, core::ErrorResponse, Api};
 use mirrord_operator::{
-    client::{session_api, OperatorApiError, OperatorOperation},
-    crd::{MirrordOperatorCrd, SessionCrd, OPERATOR_STATUS_NAME},
+    client::{session_api, session_share_api, OperatorApiError, OperatorOperation},
+    crd::{
+        MirrordOperatorCrd, SessionCrd, SessionShareCrd, SessionShareSpec, OPERATOR_STATUS_NAME,
+    },
 };
 use mirrord_progress::{Progress, ProgressTracker};
 
@@ -76,7 +79,7 @@ impl SessionCommandHandler {
         sub_progress.print(&format!("executing `{command}`"));
 
         // We're interested in the `Status`es, so we map the results into those.
-        match command {
+        match &command {
             SessionCommand::Kill { id } => session_api
                 .delete(&format!("{id}"), &Default::default())
                 .await
@@ -89,6 +92,23 @@ impl SessionCommandHandler {
                 .delete("inactive", &Default::default())
                 .await
                 .map(|either| either.right()),
+            // Creates a resource instead of changing the sessions, there's no `Status` to check.
+            SessionCommand::Share { id, with } => {
+                return Self::share(*id, with.clone(), operator_version)
+                    .await
+                    .inspect(|()| {
+                        sub_progress.success(Some(&format!(
+                            "session {id:x} is shared, join it with `mirrord exec \
+                             --join-session {id:x}`"
+                        )));
+                        progress.success(Some("Session operation is completed."));
+                    })
+                    .inspect_err(|fail| {
+                        sub_progress.failure(Some(&fail.to_string()));
+                        progress.failure(Some("Session sharing failed!"));
+                    })
+                    .map_err(Into::into);
+            }
         }
         .map_err(|kube_fail| match kube_fail {
             // The random `reason` we get when the operator returns from a "missing route".
@@ -145,4 +165,46 @@ impl SessionCommandHandler {
 
         Ok(())
     }
+
+    /// Creates a [`SessionShareCrd`] that invites the users `with` into the session `id`.
+    ///
+    /// The operator rejects it if we don't own the session.
+    #[tracing::instrument(level = "trace", ret)]
+    async fn share(
+        id: u64,
+        with: Vec<String>,
+        operator_version: String,
+    ) -> Result<(), OperatorApiError> {
+        let share_api = session_share_api(None).await?;
+
+        // A session can be shared more than once, so every share gets its own name.
+        let share = SessionShareCrd {
+            metadata: ObjectMeta {
+                generate_name: Some(format!("{id:x}-")),
+                ..Default::default()
+            },
+            spec: SessionShareSpec {
+                session_id: format!("{id:x}"),
+                with,
+            },
+        };
+
+        share_api
+            .create(&PostParams::default(), &share)
+            .await
+            .map(|_| ())
+            .map_err(|kube_fail| match kube_fail {
+                // Operators that don't know the resource.
+                kube::Error::Api(ErrorResponse { code: 404, .. }) => {
+                    OperatorApiError::UnsupportedFeature {
+                        feature: "session sharing".to_string(),
+                        operator_version,
+                    }
+                }
+                other => OperatorApiError::KubeError {
+                    error: other,
+                    operation: OperatorOperation::SharingSession,
+                },
+            })
+    }
 }
@@ -0,0 +1,447 @@
+//! Exports the spans of the CLI and the internal proxy to an OTLP endpoint
+//! (`telemetry.otlp_endpoint` in the config), so that the time mirrord spends setting up the
+//! session, connecting to the agent and handling the stolen or mirrored requests shows up next to
+//! the traces of the user's services.
+//!
+//! Only the spans of the mirrord crates on the [`Level::INFO`] level or above are exported. Like
+//! the metrics of the agent, they're encoded as OTLP JSON and sent over plain HTTP, in batches.
+//! The [`layer`] is always installed, and does nothing until [`init`] is called.
+
+use std::{
+    fmt, io,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{
+    client::conn::http1,
+    header::{CONTENT_TYPE, HOST},
+    http::uri::PathAndQuery,
+    Request, StatusCode, Uri,
+};
+use hyper_util::rt::TokioIo;
+use mirrord_config::telemetry::TelemetryConfig;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Metadata, Subscriber,
+};
+use tracing_subscriber::{filter::filter_fn, layer::Context, registry::LookupSpan, Layer};
+
+/// Path of the traces in the OTLP/HTTP API, appended to endpoints that don't have one.
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+/// How often the closed spans are exported.
+const BATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Closed spans are exported right away when there's this many of them.
+const MAX_BATCH_SIZE: usize = 512;
+
+/// How long a single export can take.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set by [`init`], receives the closed spans.
+static EXPORTER: OnceLock<mpsc::UnboundedSender<ExportMessage>> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub(crate) enum OtlpExportError {
+    #[error("invalid endpoint: {0}")]
+    Endpoint(String),
+
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Hyper(#[from] hyper::Error),
+
+    #[error("{0}")]
+    Http(#[from] hyper::http::Error),
+
+    #[error("endpoint responded with {0}")]
+    Status(StatusCode),
+}
+
+enum ExportMessage {
+    /// A closed span, encoded with [`SpanData::encode`].
+    Span(Value),
+    /// Export the spans received so far, and notify the sender when done.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Exported spans: the spans of mirrord on the `INFO` level or above. `ERROR` events are passed
+/// too, they mark the span they happen in as failed.
+fn exported(metadata: &Metadata<'_>) -> bool {
+    let level = *metadata.level();
+
+    metadata.target().starts_with("mirrord")
+        && ((metadata.is_span() && level <= Level::INFO) || level == Level::ERROR)
+}
+
+/// The layer that collects the spans, see the module docs.
+pub(crate) fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    OtlpLayer.with_filter(filter_fn(exported))
+}
+
+/// Starts exporting the spans to [`TelemetryConfig::otlp_endpoint`], if it's set.
+///
+/// `service_name` is the `service.name` resource attribute of the spans.
+pub(crate) fn init(
+    config: &TelemetryConfig,
+    service_name: &'static str,
+) -> Result<(), OtlpExportError> {
+    let Some(endpoint) = config.otlp_endpoint.as_deref() else {
+        return Ok(());
+    };
+
+    let exporter = OtlpTraceExporter {
+        uri: traces_uri(endpoint)?,
+        service_name,
+    };
+    let (tx, rx) = mpsc::unbounded_channel();
+    if EXPORTER.set(tx).is_ok() {
+        tokio::spawn(exporter.run(rx));
+    }
+
+    Ok(())
+}
+
+/// Exports the spans that were closed so far. Called before the process is replaced or exits,
+/// as the last batch would be lost otherwise.
+pub(crate) async fn flush() {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+
+    let (tx, rx) = oneshot::channel();
+    if exporter.send(ExportMessage::Flush(tx)).is_ok() {
+        let _ = tokio::time::timeout(EXPORT_TIMEOUT, rx).await;
+    }
+}
+
+/// Kept in the extensions of the exported spans.
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    start: SystemTime,
+    /// OTLP `KeyValue`s, from the fields of the span.
+    attributes: Vec<Value>,
+    /// Whether an `ERROR` event happened in the span.
+    error: bool,
+}
+
+impl SpanData {
+    /// The span as an OTLP `Span`.
+    fn encode(self, name: &str, end: SystemTime) -> Value {
+        // `STATUS_CODE_UNSET` and `STATUS_CODE_ERROR`.
+        let status_code = if self.error { 2 } else { 0 };
+
+        let mut span = json!({
+            "traceId": hex(&self.trace_id),
+            "spanId": hex(&self.span_id),
+            "name": name,
+            // `SPAN_KIND_INTERNAL`
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(end),
+            "attributes": self.attributes,
+            "status": { "code": status_code },
+        });
+
+        if let Some(parent_span_id) = self.parent_span_id {
+            span["parentSpanId"] = hex(&parent_span_id).into();
+        }
+
+        span
+    }
+
+    fn add_attribute(&mut self, field: &Field, value: Value) {
+        self.attributes
+            .push(json!({ "key": field.name(), "value": value }));
+    }
+}
+
+impl Visit for SpanData {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.add_attribute(field, json!({ "stringValue": format!("{value:?}") }));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.add_attribute(field, json!({ "stringValue": value }));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.add_attribute(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.add_attribute(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.add_attribute(field, json!({ "boolValue": value }));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.add_attribute(field, json!({ "doubleValue": value }));
+    }
+}
+
+struct OtlpLayer;
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if EXPORTER.get().is_none() {
+            return;
+        }
+
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|data| (data.trace_id, data.span_id))
+        });
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, parent_span_id)) => (trace_id, Some(parent_span_id)),
+            None => (rand::random(), None),
+        };
+
+        let mut data = SpanData {
+            trace_id,
+            span_id: rand::random(),
+            parent_span_id,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            error: false,
+        };
+        attrs.record(&mut data);
+
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(data);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+
+        if let Some(span) = ctx.event_span(event) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                data.error = true;
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(exporter) = EXPORTER.get() else {
+            return;
+        };
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+
+        let _ = exporter.send(ExportMessage::Span(
+            data.encode(span.name(), SystemTime::now()),
+        ));
+    }
+}
+
+/// Pushes the closed spans to an OTLP/HTTP endpoint.
+struct OtlpTraceExporter {
+    uri: Uri,
+    service_name: &'static str,
+}
+
+impl OtlpTraceExporter {
+    /// Exports the spans every [`BATCH_INTERVAL`], or when [`MAX_BATCH_SIZE`] of them are
+    /// waiting.
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<ExportMessage>) {
+        let mut interval = tokio::time::interval(BATCH_INTERVAL);
+        let mut batch = Vec::new();
+
+        loop {
+            let flushed = tokio::select! {
+                message = rx.recv() => match message {
+                    Some(ExportMessage::Span(span)) => {
+                        batch.push(span);
+                        if batch.len() < MAX_BATCH_SIZE {
+                            continue;
+                        }
+                        None
+                    }
+                    Some(ExportMessage::Flush(done)) => Some(done),
+                    None => break,
+                },
+                _ = interval.tick() => None,
+            };
+
+            if !batch.is_empty() {
+                let spans = std::mem::take(&mut batch);
+                match tokio::time::timeout(EXPORT_TIMEOUT, self.export(spans)).await {
+                    Ok(Ok(())) => tracing::debug!(uri = %self.uri, "Exported OTLP traces"),
+                    Ok(Err(error)) => {
+                        tracing::warn!(%error, uri = %self.uri, "Failed to export OTLP traces")
+                    }
+                    Err(..) => tracing::warn!(uri = %self.uri, "Exporting OTLP traces timed out"),
+                }
+            }
+
+            if let Some(done) = flushed {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    async fn export(&self, spans: Vec<Value>) -> Result<(), OtlpExportError> {
+        let host = self
+            .uri
+            .host()
+            .ok_or_else(|| OtlpExportError::Endpoint("endpoint has no host".to_string()))?;
+        let port = self.uri.port_u16().unwrap_or(80);
+        let authority = self
+            .uri
+            .authority()
+            .map(|authority| authority.as_str())
+            .unwrap_or(host);
+
+        let stream = TcpStream::connect((host, port)).await?;
+        let (mut request_sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+
+        let request = Request::post(self.uri.clone())
+            .header(HOST, authority)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(
+                export_request(self.service_name, spans).to_string(),
+            )))?;
+
+        let response = request_sender.send_request(request).await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(OtlpExportError::Status(response.status()))
+        }
+    }
+}
+
+/// The spans as an OTLP `ExportTraceServiceRequest`.
+fn export_request(service_name: &str, spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": service_name } },
+                    {
+                        "key": "service.version",
+                        "value": { "stringValue": env!("CARGO_PKG_VERSION") },
+                    },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "mirrord" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Nanoseconds since the epoch, as a string, like OTLP JSON encodes 64 bit integers.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Trace and span ids are hex encoded in OTLP JSON.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Adds [`OTLP_TRACES_PATH`] to an endpoint without a path.
+fn traces_uri(endpoint: &str) -> Result<Uri, OtlpExportError> {
+    let uri = endpoint
+        .parse::<Uri>()
+        .map_err(|error| OtlpExportError::Endpoint(error.to_string()))?;
+
+    if uri.scheme_str() != Some("http") {
+        return Err(OtlpExportError::Endpoint(format!(
+            "{endpoint} is not an http:// URL"
+        )));
+    }
+
+    if uri.path() != "/" {
+        return Ok(uri);
+    }
+
+    let mut parts = uri.into_parts();
+    parts.path_and_query = Some(PathAndQuery::from_static(OTLP_TRACES_PATH));
+    Uri::from_parts(parts).map_err(|error| OtlpExportError::Endpoint(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_path() {
+        assert_eq!(
+            traces_uri("http://collector:4318").unwrap(),
+            "http://collector:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_uri("http://collector:4318/custom/traces").unwrap(),
+            "http://collector:4318/custom/traces"
+        );
+        assert!(traces_uri("https://collector:4318").is_err());
+    }
+
+    #[test]
+    fn encoded_span() {
+        let data = SpanData {
+            trace_id: [0xab; 16],
+            span_id: [1; 8],
+            parent_span_id: Some([2; 8]),
+            start: UNIX_EPOCH + Duration::from_secs(1),
+            attributes: vec![json!({ "key": "port", "value": { "intValue": "80" } })],
+            error: true,
+        };
+
+        let span = data.encode("agent_connection", UNIX_EPOCH + Duration::from_secs(2));
+
+        assert_eq!(span["traceId"], "abababababababababababababababab");
+        assert_eq!(span["spanId"], "0101010101010101");
+        assert_eq!(span["parentSpanId"], "0202020202020202");
+        assert_eq!(span["startTimeUnixNano"], "1000000000");
+        assert_eq!(span["endTimeUnixNano"], "2000000000");
+        assert_eq!(span["status"]["code"], 2);
+    }
+}
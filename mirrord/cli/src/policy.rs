@@ -0,0 +1,146 @@
+//! `mirrord policy test` shows what `MirrordPolicy` resources would block for a session, so that
+//! their authors can check them before applying them to the cluster.
+//!
+//! The policies are evaluated with [`MirrordPolicy::blocked_features`], the same code the operator
+//! uses. Nothing is read from the cluster, the labels of the target are given with `--label`.
+
+use std::{collections::BTreeMap, fs};
+
+use kube::Resource;
+use mirrord_operator::crd::{BlockedFeature, MirrordPolicy, MirrordPolicySpec};
+use prettytable::{row, Table};
+use serde::Deserialize;
+
+use crate::{diagnose::load_config, CliError, PolicyArgs, PolicyCommand, PolicyTestArgs, Result};
+
+/// Handle `mirrord policy`.
+pub(crate) fn policy_command(args: PolicyArgs) -> Result<()> {
+    match args.command {
+        PolicyCommand::Test(args) => policy_test_command(*args),
+    }
+}
+
+/// Handle `mirrord policy test`.
+fn policy_test_command(args: PolicyTestArgs) -> Result<()> {
+    let failed = |error: String| CliError::PolicyTestFailed(args.policy.clone(), error);
+
+    let policies = fs::read_to_string(&args.policy)
+        .map_err(|error| failed(error.to_string()))
+        .and_then(|policies| parse_policies(&policies).map_err(failed))?;
+
+    if let Some(target) = args.target.as_deref() {
+        std::env::set_var("MIRRORD_IMPERSONATED_TARGET", target);
+    }
+    if let Some(namespace) = args.target_namespace.as_deref() {
+        std::env::set_var("MIRRORD_TARGET_NAMESPACE", namespace);
+    }
+    let config = load_config(args.config_file.as_deref())?;
+    let labels: BTreeMap<String, String> = args.labels.into_iter().collect();
+
+    let mut table = Table::new();
+    table.add_row(row!["Policy", "Namespace", "Applies", "Blocks"]);
+
+    let mut blocked = Vec::new();
+    for policy in &policies {
+        let features = policy.blocked_features(&config, &labels);
+
+        table.add_row(row![
+            policy.meta().name.as_deref().unwrap_or_default(),
+            policy.meta().namespace.as_deref().unwrap_or("N/A"),
+            if policy.applies_to(&config.target, &labels) {
+                "yes"
+            } else {
+                "no"
+            },
+            features
+                .iter()
+                .map(feature_name)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ]);
+        blocked.extend(features);
+    }
+
+    println!(
+        "Session on {} in namespace {}:",
+        MirrordPolicySpec::path_of(&config.target),
+        config.target.namespace.as_deref().unwrap_or("N/A")
+    );
+    table.printstd();
+
+    blocked.sort_by_key(feature_name);
+    blocked.dedup();
+    if blocked.is_empty() {
+        println!("\nNo feature of the session would be blocked.");
+    } else {
+        println!("\nThe operator would block the session, it uses:");
+        for feature in blocked {
+            println!("- {}", feature_name(&feature));
+        }
+    }
+
+    Ok(())
+}
+
+/// The `MirrordPolicy` resources in the YAML (or JSON) documents, other resources are skipped.
+fn parse_policies(policies: &str) -> Result<Vec<MirrordPolicy>, String> {
+    let mut parsed = Vec::new();
+
+    for document in serde_yaml::Deserializer::from_str(policies) {
+        let value = serde_yaml::Value::deserialize(document).map_err(|error| error.to_string())?;
+        if value.get("kind").and_then(serde_yaml::Value::as_str) != Some("MirrordPolicy") {
+            continue;
+        }
+
+        parsed.push(serde_yaml::from_value(value).map_err(|error| error.to_string())?);
+    }
+
+    if parsed.is_empty() {
+        return Err("the file has no MirrordPolicy resources".to_string());
+    }
+
+    Ok(parsed)
+}
+
+/// How the feature is named in the `block` list of the policies.
+fn feature_name(feature: &BlockedFeature) -> &'static str {
+    match feature {
+        BlockedFeature::Steal => "steal",
+        BlockedFeature::StealWithoutFilter => "steal-without-filter",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_only_policies() {
+        let policies = parse_policies(
+            r#"
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: staging
+---
+apiVersion: policies.mirrord.metalbear.co/v1alpha
+kind: MirrordPolicy
+metadata:
+  name: no-unfiltered-steal
+  namespace: staging
+spec:
+  targetPath: "deploy/*"
+  block:
+    - steal-without-filter
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].spec.target_path.as_deref(), Some("deploy/*"));
+        assert_eq!(
+            policies[0].spec.block,
+            vec![BlockedFeature::StealWithoutFilter]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Log file of the internal proxy (`internal_proxy.log_destination`), rotated according to
+//! `internal_proxy.log_rotation`.
+//!
+//! The rotated files are `<file>.1` (the newest) to `<file>.<max_files>`. Several proxies may log
+//! to the same file, each of them rotates it when it sees that it's too big, so the limits are
+//! approximate.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use mirrord_config::internal_proxy::LogRotationConfig;
+
+/// [`Write`]s to the log file, rotating it when it grows over [`Self::max_size`].
+pub(crate) struct RotatingLogFile {
+    path: PathBuf,
+    file: File,
+    /// Size of the file, as far as we know.
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingLogFile {
+    /// Opens the log file for appending, rotating it first if it's already too big, and removes
+    /// the rotated files over the limit.
+    pub(crate) fn open<P: AsRef<Path>>(path: P, config: &LogRotationConfig) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        // Left behind when `max_files` was bigger.
+        let mut stale = config.max_files + 1;
+        loop {
+            match fs::remove_file(rotated_path(&path, stale)) {
+                Ok(()) => stale += 1,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        let file = Self::open_file(&path)?;
+        let size = file.metadata()?.len();
+
+        let mut log_file = Self {
+            path,
+            file,
+            size,
+            max_size: config.max_size_mb.saturating_mul(1024 * 1024),
+            max_files: config.max_files,
+        };
+
+        if log_file.size >= log_file.max_size {
+            log_file.rotate()?;
+        }
+
+        Ok(log_file)
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Moves the current file to `<file>.1`, shifting the older ones, and starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            // Another proxy may have rotated the file already, then we just reopen it.
+            let rotated = match fs::metadata(&self.path) {
+                Ok(metadata) if metadata.len() < self.size => {
+                    self.file = Self::open_file(&self.path)?;
+                    self.size = metadata.len();
+                    Ok(())
+                }
+                _ => self.rotate(),
+            };
+
+            if let Err(error) = rotated {
+                // Keep logging to the big file rather than losing the logs.
+                self.size = 0;
+                eprintln!("Failed to rotate the internal proxy log file: {error}");
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// `<file>.<index>`
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+    rotated.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rotates_and_removes_stale_files() {
+        let dir =
+            std::env::temp_dir().join(format!("mirrord-log-rotation-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("intproxy.log");
+
+        // From a run with a bigger `max_files`.
+        fs::write(rotated_path(&path, 3), "stale").unwrap();
+        fs::write(rotated_path(&path, 4), "stale").unwrap();
+
+        let config = LogRotationConfig {
+            max_size_mb: 1,
+            max_files: 2,
+        };
+        let mut log_file = RotatingLogFile::open(&path, &config).unwrap();
+        assert!(!rotated_path(&path, 3).exists());
+        assert!(!rotated_path(&path, 4).exists());
+
+        let line = vec![b'x'; 1024 * 1024 - 1];
+        for _ in 0..4 {
+            log_file.write_all(&line).unwrap();
+            log_file.write_all(b"\n").unwrap();
+        }
+        log_file.flush().unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1024 * 1024);
+        assert_eq!(
+            fs::metadata(rotated_path(&path, 1)).unwrap().len(),
+            1024 * 1024
+        );
+        assert_eq!(
+            fs::metadata(rotated_path(&path, 2)).unwrap().len(),
+            1024 * 1024
+        );
+        assert!(!rotated_path(&path, 3).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
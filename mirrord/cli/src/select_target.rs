@@ -0,0 +1,149 @@
+//! `mirrord exec --select-target` asks for the target in the terminal when the config has none:
+//! first its namespace, then the type of the target, its name, and the container.
+//!
+//! The targets are the ones `mirrord ls` lists, see [`list_targets`].
+
+use std::{
+    collections::BTreeSet,
+    io::{self, IsTerminal},
+    str::FromStr,
+};
+
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{api::ListParams, Api};
+use mirrord_config::{target::Target, LayerConfig};
+use mirrord_kube::api::kubernetes::create_kube_api;
+
+use crate::{config_init::ask_value, list_targets, util::set_proxy_env, CliError, Result};
+
+/// Asks for the target of the session and writes it to `config.target`, and to the env variables
+/// the internal proxy and the layer read the config from.
+pub(crate) async fn select_target(config: &mut LayerConfig) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        return Err(CliError::SelectTargetFailed(
+            "stdin is not a terminal".to_string(),
+        ));
+    }
+
+    let namespace = match config.target.namespace.clone() {
+        Some(namespace) => namespace,
+        None => select_namespace(config).await?,
+    };
+
+    let mut namespaced = config.clone();
+    namespaced.target.namespace = Some(namespace.clone());
+    let targets = list_targets(&namespaced).await?;
+
+    let path =
+        pick_target(&targets).map_err(|error| CliError::SelectTargetFailed(error.to_string()))?;
+    let target =
+        Target::from_str(&path).map_err(|error| CliError::SelectTargetFailed(error.to_string()))?;
+
+    std::env::set_var("MIRRORD_IMPERSONATED_TARGET", &path);
+    std::env::set_var("MIRRORD_TARGET_NAMESPACE", &namespace);
+    config.target.path = Some(target);
+    config.target.namespace = Some(namespace);
+
+    Ok(())
+}
+
+/// Asks for one of the namespaces the user can list, the default one of the kube context when
+/// they can't list them.
+async fn select_namespace(config: &LayerConfig) -> Result<String> {
+    set_proxy_env(config);
+
+    let client = create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    .map_err(CliError::CreateKubeApiFailed)?;
+
+    let namespaces = Api::<Namespace>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+        .map(|namespaces| {
+            namespaces
+                .items
+                .into_iter()
+                .filter_map(|namespace| namespace.metadata.name)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if namespaces.is_empty() {
+        return Ok(client.default_namespace().to_string());
+    }
+
+    pick("Namespace", &namespaces, client.default_namespace())
+        .map_err(|error| CliError::SelectTargetFailed(error.to_string()))
+}
+
+/// Asks for the type, name and container of one of the `targets`, paths like
+/// `deployment/name/container/container`, and returns its path.
+fn pick_target(targets: &[String]) -> io::Result<String> {
+    let types = targets
+        .iter()
+        .filter_map(|target| target.split('/').next())
+        .chain(["targetless"])
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let target_type = pick("Target type", &types, "")?;
+    if target_type == "targetless" {
+        return Ok(target_type);
+    }
+
+    let names = targets
+        .iter()
+        .filter_map(|target| {
+            let (type_name, rest) = target.split_once('/')?;
+            (type_name == target_type).then(|| rest.split('/').next().unwrap_or(rest))
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let name = pick(&format!("Name of the {target_type}"), &names, "")?;
+
+    let prefix = format!("{target_type}/{name}/container/");
+    let containers = targets
+        .iter()
+        .filter_map(|target| target.strip_prefix(&prefix))
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+
+    let path = match containers.as_slice() {
+        [] => format!("{target_type}/{name}"),
+        [container] => format!("{prefix}{container}"),
+        _ => format!("{prefix}{}", pick("Container", &containers, "")?),
+    };
+
+    Ok(path)
+}
+
+/// Lists the `options` and asks for one of them, by number or by name.
+fn pick(question: &str, options: &[String], default: &str) -> io::Result<String> {
+    eprintln!();
+    for (index, option) in options.iter().enumerate() {
+        eprintln!("{:>3}) {option}", index + 1);
+    }
+
+    let default = if options.iter().any(|option| option == default) {
+        default
+    } else {
+        ""
+    };
+
+    ask_value(question, default, |answer| {
+        answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| options.get(number.checked_sub(1)?))
+            .or_else(|| options.iter().find(|option| *option == answer))
+            .cloned()
+            .ok_or_else(|| format!("expected a number from 1 to {}, or a name", options.len()))
+    })
+}
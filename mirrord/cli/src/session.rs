@@ -0,0 +1,251 @@
+//! `mirrord session why fs <path>` explains how mirrord handles file operations on a path with the
+//! given config, so the user doesn't have to work out the precedence of the `feature.fs` patterns.
+//!
+//! `mirrord session fds` asks the internal proxy of a running session for the remote resources
+//! behind the application's file descriptors.
+//!
+//! `mirrord loglevel` changes the log filter of the internal proxy and the layers of a running
+//! session.
+use std::{
+    env, fs,
+    net::TcpStream,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use mirrord_config::{
+    config::{ConfigContext, MirrordConfig},
+    feature::fs::filter::FsFilter,
+    LayerFileConfig,
+};
+use mirrord_intproxy_protocol::{
+    codec, AdminRequest, AdminResponse, AuthToken, LayerToProxyMessage, LocalMessage,
+    ProxyToLayerMessage, RemoteFdInfo, RemoteFdResource, INTPROXY_AUTH_TOKEN_ENV,
+};
+use mirrord_protocol::file::OpenOptionsInternal;
+use tracing_subscriber::EnvFilter;
+
+use crate::{CliError, LogLevelArgs, Result, SessionArgs, SessionInspectCommand, WhyCommand};
+
+/// Env var with the address of the internal proxy, set for the application by `mirrord exec`.
+const INTPROXY_ADDRESS_ENV: &str = "MIRRORD_CONNECT_TCP";
+
+/// Prints the [`FsFilter`] decisions for reading and writing the `path`.
+#[tracing::instrument(level = "trace", ret)]
+fn why_fs(path: &Path, config: Option<&Path>) -> Result<()> {
+    let mut cfg_context = ConfigContext::default();
+    let config = if let Some(config) = config {
+        LayerFileConfig::from_path(config)?.generate_config(&mut cfg_context)
+    } else {
+        LayerFileConfig::default().generate_config(&mut cfg_context)
+    }?;
+
+    let filter = FsFilter::new(&config.feature.fs).map_err(CliError::InvalidFsPattern)?;
+    println!("{}", path.display());
+
+    let remapped = filter.remap(path);
+    if let Some(remapped) = remapped.as_deref() {
+        println!("  mapped to {} by feature.fs.mapping", remapped.display());
+    }
+    let path = remapped.as_deref().unwrap_or(path).to_string_lossy();
+
+    for (operation, write) in [("read", false), ("write", true)] {
+        let decision = filter.decide(&path, write);
+        println!("  {operation}: {} ({})", decision.action, decision.reason);
+    }
+
+    Ok(())
+}
+
+/// Returns the value of the env var `key` in the environment of the process with the given `pid`,
+/// or in our own environment.
+fn session_env(pid: Option<u32>, key: &str) -> Result<Option<String>> {
+    let Some(pid) = pid else {
+        return Ok(env::var(key).ok());
+    };
+
+    let environ = fs::read(format!("/proc/{pid}/environ")).map_err(|error| {
+        CliError::SessionFdsFailed(format!("could not read the environment of {pid}: {error}"))
+    })?;
+
+    Ok(environ
+        .split(|byte| *byte == 0)
+        .filter_map(|var| std::str::from_utf8(var).ok())
+        .find_map(|var| var.strip_prefix(key)?.strip_prefix('='))
+        .map(ToString::to_string))
+}
+
+/// Sends the [`AdminRequest`] to the internal proxy of the session, `error` makes the
+/// [`CliError`] of the command.
+fn admin_request(
+    pid: Option<u32>,
+    request: AdminRequest,
+    error: fn(String) -> CliError,
+) -> Result<AdminResponse> {
+    let address = session_env(pid, INTPROXY_ADDRESS_ENV)?.ok_or_else(|| {
+        error(format!(
+            "{INTPROXY_ADDRESS_ENV} is not set, the application is not running with mirrord"
+        ))
+    })?;
+    let auth_token = session_env(pid, INTPROXY_AUTH_TOKEN_ENV)?.map(AuthToken);
+
+    let failed =
+        |message: &dyn std::fmt::Display| error(format!("internal proxy at {address}: {message}"));
+
+    let stream = TcpStream::connect(&address).map_err(|error| failed(&error))?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .map_err(|error| failed(&error))?;
+    let (mut encoder, mut decoder) = codec::make_sync_framed::<
+        LocalMessage<LayerToProxyMessage>,
+        LocalMessage<ProxyToLayerMessage>,
+    >(stream)
+    .map_err(|error| failed(&error))?;
+
+    if let Some(token) = auth_token {
+        encoder
+            .send(&LocalMessage {
+                message_id: 0,
+                inner: LayerToProxyMessage::Authenticate(token),
+            })
+            .map_err(|error| failed(&error))?;
+    }
+    encoder
+        .send(&LocalMessage {
+            message_id: 0,
+            inner: LayerToProxyMessage::Admin(request),
+        })
+        .map_err(|error| failed(&error))?;
+    encoder.flush().map_err(|error| failed(&error))?;
+
+    match decoder.receive().map_err(|error| failed(&error))? {
+        Some(LocalMessage {
+            inner: ProxyToLayerMessage::Admin(response),
+            ..
+        }) => Ok(response),
+        Some(other) => Err(failed(&format!("unexpected response {other:?}"))),
+        None => Err(failed(&"connection closed")),
+    }
+}
+
+/// Sends [`AdminRequest::RemoteFds`] to the internal proxy of the session.
+fn request_remote_fds(pid: Option<u32>) -> Result<Vec<RemoteFdInfo>> {
+    match admin_request(pid, AdminRequest::RemoteFds, CliError::SessionFdsFailed)? {
+        AdminResponse::RemoteFds(fds) => Ok(fds),
+        other => Err(CliError::SessionFdsFailed(format!(
+            "unexpected response {other:?}"
+        ))),
+    }
+}
+
+/// Formats the [`OpenOptionsInternal`] like `read|write|create`.
+fn open_flags(options: &OpenOptionsInternal) -> String {
+    let flags = [
+        ("read", options.read),
+        ("write", options.write),
+        ("append", options.append),
+        ("truncate", options.truncate),
+        ("create", options.create),
+        ("create_new", options.create_new),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect::<Vec<_>>();
+
+    if flags.is_empty() {
+        "-".to_string()
+    } else {
+        flags.join("|")
+    }
+}
+
+/// Prints the remote resources of the session, the ones idle for the longest time last, as they
+/// are the likely suspects when the application hangs.
+fn fds(pid: Option<u32>) -> Result<()> {
+    let mut fds = request_remote_fds(pid)?;
+    fds.sort_by_key(|fd| std::cmp::Reverse(fd.last_activity));
+
+    if fds.is_empty() {
+        println!("The session has no remote file descriptors.");
+        return Ok(());
+    }
+
+    println!(
+        "{:<8} {:<6} {:<24} {:>12} {:>12} {:>10}  RESOURCE",
+        "FD", "KIND", "FLAGS", "READ", "WRITTEN", "IDLE"
+    );
+
+    let now = SystemTime::now();
+    for fd in fds {
+        let (kind, flags, resource) = match &fd.resource {
+            RemoteFdResource::File { path, open_options } => (
+                "file",
+                open_flags(open_options),
+                path.as_deref().map(|path| path.display().to_string()),
+            ),
+            RemoteFdResource::Dir { path } => (
+                "dir",
+                "-".to_string(),
+                path.as_deref().map(|path| path.display().to_string()),
+            ),
+            RemoteFdResource::Outgoing {
+                protocol,
+                remote_address,
+            } => (
+                "conn",
+                protocol.to_string(),
+                Some(remote_address.to_string()),
+            ),
+        };
+        let idle = now
+            .duration_since(fd.last_activity)
+            .map(|idle| Duration::from_secs(idle.as_secs()))
+            .unwrap_or_default();
+
+        println!(
+            "{:<8} {:<6} {:<24} {:>12} {:>12} {:>10}  {}",
+            fd.remote_fd,
+            kind,
+            flags,
+            fd.bytes_read,
+            fd.bytes_written,
+            humantime::format_duration(idle).to_string(),
+            resource.as_deref().unwrap_or("<unknown>")
+        );
+    }
+
+    Ok(())
+}
+
+/// Handle `mirrord loglevel`, sends [`AdminRequest::SetLogLevel`] to the internal proxy of the
+/// session.
+pub(crate) fn log_level_command(args: LogLevelArgs) -> Result<()> {
+    // Checked here, the layers can only ignore a bad filter.
+    EnvFilter::try_new(&args.directives)
+        .map_err(|error| CliError::SetLogLevelFailed(format!("invalid filter: {error}")))?;
+
+    let request = AdminRequest::SetLogLevel(args.directives.clone());
+    match admin_request(args.pid, request, CliError::SetLogLevelFailed)? {
+        AdminResponse::SetLogLevel(Ok(layers)) => {
+            println!(
+                "Changed the log filter of the internal proxy and {layers} layer(s) to `{}`.",
+                args.directives
+            );
+            Ok(())
+        }
+        AdminResponse::SetLogLevel(Err(error)) => Err(CliError::SetLogLevelFailed(error)),
+        other => Err(CliError::SetLogLevelFailed(format!(
+            "unexpected response {other:?}"
+        ))),
+    }
+}
+
+/// Handle commands related to the session `mirrord session ...`
+pub(crate) fn session_command(args: SessionArgs) -> Result<()> {
+    match args.command {
+        SessionInspectCommand::Why {
+            command: WhyCommand::Fs { path, config_file },
+        } => why_fs(&path, config_file.as_deref()),
+        SessionInspectCommand::Fds { pid } => fds(pid),
+    }
+}
@@ -0,0 +1,262 @@
+//! `mirrord setup print` outputs the cluster setup that developers need to run mirrord with a
+//! given config: the agent namespace, the Role/RoleBinding with the required permissions (see
+//! [`required_permissions`]) or, when the config uses the operator, the binding to the operator's
+//! user role.
+//!
+//! Nothing is applied, the output is meant to be reviewed and applied by whoever manages the
+//! cluster, e.g. `mirrord setup print -f config.json | kubectl apply -f -`.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use k8s_openapi::{
+    api::{
+        core::v1::Namespace,
+        rbac::v1::{PolicyRule, Role, RoleBinding, RoleRef, Subject},
+    },
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+};
+use mirrord_config::{
+    config::{ConfigContext, MirrordConfig},
+    LayerConfig, LayerFileConfig,
+};
+use mirrord_kube::api::rbac::{required_permissions, RequiredPermission};
+use serde::Serialize;
+
+use crate::{CliError, Result, SetupArgs, SetupCommand, SetupFormat};
+
+/// Name of the Role (and RoleBinding) with the permissions mirrord needs without the operator.
+const USER_ROLE_NAME: &str = "mirrord-user";
+
+/// ClusterRole created by `mirrord operator setup` for the operator users.
+const OPERATOR_USER_ROLE_NAME: &str = "mirrord-operator-user";
+
+const RBAC_API_GROUP: &str = "rbac.authorization.k8s.io";
+
+/// Cluster setup required by a [`LayerConfig`].
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ClusterSetup {
+    /// Namespace the config explicitly puts the agents in, it has to exist.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    agent_namespace: Option<String>,
+    /// Permissions required in each namespace, when running without the operator.
+    roles: Vec<NamespaceRules>,
+    /// Who the roles are bound to.
+    subjects: Vec<Subject>,
+    operator: OperatorValues,
+}
+
+#[derive(Debug, Serialize)]
+struct NamespaceRules {
+    namespace: String,
+    rules: Vec<PolicyRule>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OperatorValues {
+    enabled: bool,
+    /// Namespaces where the subjects are bound to [`OPERATOR_USER_ROLE_NAME`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    user_role_namespaces: Vec<String>,
+}
+
+impl ClusterSetup {
+    fn new(config: &LayerConfig, default_namespace: &str, subjects: Vec<Subject>) -> Self {
+        let operator = config.operator == Some(true);
+
+        let roles = if operator {
+            vec![]
+        } else {
+            rules_per_namespace(required_permissions(config, default_namespace))
+        };
+
+        let user_role_namespaces = if operator {
+            vec![config
+                .target
+                .namespace
+                .clone()
+                .unwrap_or_else(|| default_namespace.to_string())]
+        } else {
+            vec![]
+        };
+
+        Self {
+            agent_namespace: config
+                .agent
+                .namespace
+                .clone()
+                .filter(|_| !operator)
+                .filter(|namespace| namespace != default_namespace),
+            roles,
+            subjects,
+            operator: OperatorValues {
+                enabled: operator,
+                user_role_namespaces,
+            },
+        }
+    }
+
+    /// Kubernetes manifests for this setup, to be applied with `kubectl apply`.
+    fn manifests(&self) -> Result<Vec<serde_yaml::Value>, serde_yaml::Error> {
+        let mut manifests = vec![];
+
+        if let Some(namespace) = self.agent_namespace.as_ref() {
+            manifests.push(serde_yaml::to_value(Namespace {
+                metadata: ObjectMeta {
+                    name: Some(namespace.clone()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })?);
+        }
+
+        for NamespaceRules { namespace, rules } in &self.roles {
+            manifests.push(serde_yaml::to_value(Role {
+                metadata: metadata(USER_ROLE_NAME, namespace),
+                rules: Some(rules.clone()),
+            })?);
+            manifests.push(serde_yaml::to_value(self.role_binding(
+                "Role",
+                USER_ROLE_NAME,
+                namespace,
+            ))?);
+        }
+
+        for namespace in &self.operator.user_role_namespaces {
+            manifests.push(serde_yaml::to_value(self.role_binding(
+                "ClusterRole",
+                OPERATOR_USER_ROLE_NAME,
+                namespace,
+            ))?);
+        }
+
+        Ok(manifests)
+    }
+
+    fn role_binding(&self, kind: &str, role: &str, namespace: &str) -> RoleBinding {
+        RoleBinding {
+            metadata: metadata(role, namespace),
+            role_ref: RoleRef {
+                api_group: RBAC_API_GROUP.to_string(),
+                kind: kind.to_string(),
+                name: role.to_string(),
+            },
+            subjects: Some(self.subjects.clone()),
+        }
+    }
+}
+
+fn metadata(name: &str, namespace: &str) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(name.to_string()),
+        namespace: Some(namespace.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Groups the permissions into [`PolicyRule`]s per namespace, merging the verbs of each resource.
+fn rules_per_namespace(permissions: Vec<RequiredPermission>) -> Vec<NamespaceRules> {
+    let mut namespaces: BTreeMap<String, BTreeMap<(&str, String), BTreeSet<&str>>> =
+        Default::default();
+
+    for permission in permissions {
+        let resource = match permission.subresource {
+            Some(subresource) => format!("{}/{subresource}", permission.resource),
+            None => permission.resource.to_string(),
+        };
+
+        namespaces
+            .entry(permission.namespace)
+            .or_default()
+            .entry((permission.group, resource))
+            .or_default()
+            .insert(permission.verb);
+    }
+
+    namespaces
+        .into_iter()
+        .map(|(namespace, resources)| NamespaceRules {
+            namespace,
+            rules: resources
+                .into_iter()
+                .map(|((group, resource), verbs)| PolicyRule {
+                    api_groups: Some(vec![group.to_string()]),
+                    resources: Some(vec![resource]),
+                    verbs: verbs.into_iter().map(ToString::to_string).collect(),
+                    ..Default::default()
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Prints the [`ClusterSetup`] for the config in the requested format.
+#[tracing::instrument(level = "trace", ret)]
+fn print_setup(
+    config: Option<&Path>,
+    format: SetupFormat,
+    namespace: &str,
+    groups: Vec<String>,
+    users: Vec<String>,
+) -> Result<()> {
+    let mut cfg_context = ConfigContext::default();
+    let config = if let Some(config) = config {
+        LayerFileConfig::from_path(config)?.generate_config(&mut cfg_context)
+    } else {
+        LayerFileConfig::default().generate_config(&mut cfg_context)
+    }?;
+
+    let subjects = groups
+        .into_iter()
+        .map(|name| ("Group", name))
+        .chain(users.into_iter().map(|name| ("User", name)))
+        .map(|(kind, name)| Subject {
+            api_group: Some(RBAC_API_GROUP.to_string()),
+            kind: kind.to_string(),
+            name,
+            namespace: None,
+        })
+        .collect();
+
+    let setup = ClusterSetup::new(&config, namespace, subjects);
+
+    let output = match format {
+        SetupFormat::Yaml => setup
+            .manifests()
+            .and_then(|manifests| {
+                manifests
+                    .iter()
+                    .map(serde_yaml::to_string)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map(|documents| documents.join("---\n")),
+        SetupFormat::Helm => serde_yaml::to_string(&setup),
+    }
+    .map_err(CliError::SetupPrintFailed)?;
+
+    if setup.operator.enabled {
+        eprintln!(
+            "The config uses the mirrord operator, install it with `mirrord operator setup` \
+             before applying this setup."
+        );
+    }
+    print!("{output}");
+
+    Ok(())
+}
+
+/// Handle commands related to the cluster setup `mirrord setup ...`
+pub(crate) fn setup_command(args: SetupArgs) -> Result<()> {
+    match args.command {
+        SetupCommand::Print {
+            config_file,
+            format,
+            namespace,
+            group,
+            user,
+        } => print_setup(config_file.as_deref(), format, &namespace, group, user),
+    }
+}
@@ -0,0 +1,304 @@
+//! `mirrord status` lists the mirrord sessions running on this machine.
+//!
+//! Every internal proxy keeps a [`SessionState`] file in [`sessions_dir`] while it runs, named
+//! after its pid, see [`SessionStateFile`]. Files of proxies that were killed before they could
+//! remove them are removed by `mirrord status`.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mirrord_intproxy::event_hooks::SessionEvent;
+use nix::{errno::Errno, sys::signal, unistd::Pid};
+use prettytable::{row, Table};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{CliError, Result};
+
+/// Directory with the [`SessionState`] files.
+pub(crate) fn sessions_dir() -> PathBuf {
+    std::env::temp_dir().join("mirrord-sessions")
+}
+
+/// Copy of the [`SessionState`] of the last session that started, kept in [`sessions_dir`] after
+/// the session ends. Without the `.json` extension, so [`running_sessions`] skips it.
+const LAST_SESSION_FILE: &str = "last-session";
+
+/// State of the connection with the agent, updated on the [`SessionEvent`]s.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub(crate) enum AgentState {
+    Connected,
+    /// The session continues with a new agent.
+    Reconnected {
+        count: u32,
+        reason: String,
+    },
+    /// The session is ending.
+    Disconnected {
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for AgentState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connected => write!(f, "connected"),
+            Self::Reconnected { count, reason } => {
+                write!(f, "connected, replaced {count} time(s), last: {reason}")
+            }
+            Self::Disconnected { reason } => write!(f, "disconnected: {reason}"),
+        }
+    }
+}
+
+/// What `mirrord status` shows about a session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct SessionState {
+    /// Pid of the internal proxy.
+    pub pid: u32,
+    /// Seconds since the epoch.
+    pub started_at: u64,
+    pub target: String,
+    pub namespace: Option<String>,
+    /// How we reach the agent, e.g. through the operator.
+    pub connection: String,
+    /// Where the internal proxy accepts the layers.
+    pub address: String,
+    /// `internal_proxy.log_destination`
+    pub log_file: Option<PathBuf>,
+    pub agent: AgentState,
+    /// Where the first agent of the session runs, when the session spawned it without the
+    /// operator.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_pod: Option<AgentPod>,
+}
+
+/// The pod and container of an agent spawned by the CLI, for `mirrord agent logs`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AgentPod {
+    pub name: String,
+    /// [`None`] for the default namespace of the kubeconfig.
+    pub namespace: Option<String>,
+    /// [`None`] for the only container of the pod.
+    pub container: Option<String>,
+}
+
+impl SessionState {
+    fn on_event(&mut self, event: &SessionEvent) {
+        match event {
+            SessionEvent::Reconnect { reason } => {
+                let count = match &self.agent {
+                    AgentState::Reconnected { count, .. } => count + 1,
+                    _ => 1,
+                };
+                self.agent = AgentState::Reconnected {
+                    count,
+                    reason: reason.clone(),
+                };
+            }
+            SessionEvent::Disconnect { reason } => {
+                self.agent = AgentState::Disconnected {
+                    reason: reason.clone(),
+                };
+            }
+            SessionEvent::SessionStart | SessionEvent::StealStart { .. } => {}
+        }
+    }
+}
+
+/// The [`SessionState`] file of this internal proxy, removed when dropped.
+#[derive(Debug)]
+pub(crate) struct SessionStateFile {
+    path: PathBuf,
+    state: Mutex<SessionState>,
+}
+
+impl SessionStateFile {
+    pub(crate) fn create(state: SessionState) -> io::Result<Self> {
+        let dir = sessions_dir();
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(LAST_SESSION_FILE), serde_json::to_vec(&state)?)?;
+
+        let file = Self {
+            path: dir.join(format!("{}.json", state.pid)),
+            state: Mutex::new(state),
+        };
+        file.write()?;
+
+        Ok(file)
+    }
+
+    /// Replaces the file, so `mirrord status` never reads a partial one.
+    fn write(&self) -> io::Result<()> {
+        let state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        let json = serde_json::to_vec(&*state)?;
+
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, json)?;
+        fs::rename(temporary, &self.path)
+    }
+
+    /// Updates the state for `mirrord status`, see
+    /// [`EventHooks::with_listener`](mirrord_intproxy::event_hooks::EventHooks::with_listener).
+    pub(crate) fn on_event(&self, event: &SessionEvent) {
+        self.state
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .on_event(event);
+
+        if let Err(error) = self.write() {
+            warn!(%error, path = %self.path.display(), "Failed to update the session state file");
+        }
+    }
+}
+
+impl Drop for SessionStateFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads the state files in `dir`, removing the ones of proxies that are gone.
+pub(crate) fn running_sessions(dir: &Path) -> io::Result<Vec<SessionState>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+
+    let mut sessions = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
+        }
+
+        let Some(state) = fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<SessionState>(&json).ok())
+        else {
+            continue;
+        };
+
+        // `EPERM` means that it runs as another user.
+        match signal::kill(Pid::from_raw(state.pid as i32), None) {
+            Err(Errno::ESRCH) => {
+                let _ = fs::remove_file(&path);
+            }
+            _ => sessions.push(state),
+        }
+    }
+
+    sessions.sort_by_key(|session| session.started_at);
+    Ok(sessions)
+}
+
+/// The [`SessionState`] of the last session that started in `dir`, whether it still runs or not.
+pub(crate) fn last_session(dir: &Path) -> io::Result<Option<SessionState>> {
+    match fs::read(dir.join(LAST_SESSION_FILE)) {
+        Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Handle `mirrord status`.
+pub(crate) fn status_command() -> Result<()> {
+    let dir = sessions_dir();
+    let sessions = running_sessions(&dir)
+        .map_err(|error| CliError::SessionStatusFailed(dir.clone(), error))?;
+
+    if sessions.is_empty() {
+        println!("No mirrord sessions are running.");
+        return Ok(());
+    }
+
+    let now = SystemTime::now();
+    let mut table = Table::new();
+    table.add_row(row![
+        "Proxy PID",
+        "Target",
+        "Namespace",
+        "Connection",
+        "Agent",
+        "Uptime",
+        "Log file"
+    ]);
+
+    for session in sessions {
+        let uptime = now
+            .duration_since(UNIX_EPOCH + Duration::from_secs(session.started_at))
+            .map(|uptime| Duration::from_secs(uptime.as_secs()))
+            .unwrap_or_default();
+
+        table.add_row(row![
+            session.pid,
+            session.target,
+            session.namespace.as_deref().unwrap_or("N/A"),
+            format!("{} (proxy at {})", session.connection, session.address),
+            session.agent,
+            humantime::format_duration(uptime),
+            session
+                .log_file
+                .as_deref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+
+    table.printstd();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn removes_stale_sessions() {
+        let dir = std::env::temp_dir().join(format!("mirrord-status-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut state = SessionState {
+            pid: std::process::id(),
+            started_at: 0,
+            target: "targetless".to_string(),
+            namespace: None,
+            connection: "agent pod mirrord-agent-abc".to_string(),
+            address: "127.0.0.1:1234".to_string(),
+            log_file: None,
+            agent: AgentState::Connected,
+            agent_pod: None,
+        };
+        state.on_event(&SessionEvent::Reconnect {
+            reason: "agent gone".to_string(),
+        });
+        fs::write(
+            dir.join("running.json"),
+            serde_json::to_vec(&state).unwrap(),
+        )
+        .unwrap();
+
+        // No such process.
+        state.pid = i32::MAX as u32;
+        fs::write(dir.join("stale.json"), serde_json::to_vec(&state).unwrap()).unwrap();
+
+        let sessions = running_sessions(&dir).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(
+            sessions.first().map(|session| &session.agent),
+            Some(&AgentState::Reconnected {
+                count: 1,
+                reason: "agent gone".to_string()
+            })
+        );
+        assert!(!dir.join("stale.json").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
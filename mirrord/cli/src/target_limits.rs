@@ -0,0 +1,282 @@
+//! `experimental.enforce_target_limits`: runs the application with the CPU and memory limits of
+//! the target container, so it performs locally like it does in the cluster.
+//!
+//! `mirrord exec` moves itself into a transient systemd scope (a cgroup) with the limits, right
+//! before the `execve`, so the limits apply to the application and to the processes it spawns,
+//! but not to the internal proxy, which was started before. The scope is created through the
+//! user's systemd instance with `busctl`, as unprivileged users can't create cgroups outside the
+//! subtree systemd delegates to them.
+
+use std::{collections::BTreeMap, process::Command, time::Duration};
+
+use k8s_openapi::{
+    api::core::v1::{Pod, ResourceRequirements},
+    apimachinery::pkg::api::resource::Quantity,
+};
+use kube::Api;
+use mirrord_config::{target::Target, LayerConfig};
+use mirrord_kube::api::{kubernetes::create_kube_api, runtime::RuntimeDataProvider};
+
+use crate::{util::set_proxy_env, CliError, Result};
+
+/// How many times we check that the process was moved into the new scope.
+const SCOPE_CHECK_ATTEMPTS: u32 = 10;
+
+/// How long we wait between the checks that the process was moved into the new scope.
+const SCOPE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// CPU and memory limits of the target container, its requests when it has no limits.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct TargetLimits {
+    /// In millicores, `1000` is one CPU.
+    cpu_millis: Option<u64>,
+    memory_bytes: Option<u64>,
+}
+
+impl TargetLimits {
+    /// Reads the limits (or requests) of the target container from its pod spec.
+    ///
+    /// `None` when targetless.
+    pub(crate) async fn fetch(config: &LayerConfig) -> Result<Option<Self>> {
+        let target = match config.target.path.as_ref() {
+            None | Some(Target::Targetless) => return Ok(None),
+            Some(target) => target,
+        };
+
+        set_proxy_env(config);
+
+        let client = create_kube_api(
+            config.accept_invalid_certificates,
+            config.kubeconfig.clone(),
+            config.kube_context.clone(),
+        )
+        .await
+        .map_err(CliError::CreateKubeApiFailed)?;
+
+        let runtime_data = target
+            .runtime_data(&client, config.target.namespace.as_deref())
+            .await
+            .map_err(|error| CliError::TargetLimitsFailed(error.to_string()))?;
+
+        let pods: Api<Pod> = match runtime_data.pod_namespace.as_deref() {
+            Some(namespace) => Api::namespaced(client, namespace),
+            None => Api::default_namespaced(client),
+        };
+        let pod = pods
+            .get(&runtime_data.pod_name)
+            .await
+            .map_err(|error| CliError::TargetLimitsFailed(error.to_string()))?;
+
+        let resources = pod
+            .spec
+            .into_iter()
+            .flat_map(|spec| spec.containers)
+            .find(|container| container.name == runtime_data.container_name)
+            .and_then(|container| container.resources)
+            .unwrap_or_default();
+
+        Ok(Some(Self::from_resources(&resources)))
+    }
+
+    /// Takes each limit from `resources.limits`, or from `resources.requests` when not limited.
+    fn from_resources(resources: &ResourceRequirements) -> Self {
+        let quantity = |name: &str| {
+            let get = |quantities: &Option<BTreeMap<String, Quantity>>| {
+                quantities
+                    .as_ref()?
+                    .get(name)
+                    .map(|quantity| quantity.0.clone())
+            };
+            get(&resources.limits).or_else(|| get(&resources.requests))
+        };
+
+        Self {
+            cpu_millis: quantity("cpu").as_deref().and_then(parse_cpu),
+            memory_bytes: quantity("memory").as_deref().and_then(parse_memory),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cpu_millis.is_none() && self.memory_bytes.is_none()
+    }
+
+    /// Moves this process into a new systemd scope with the limits, the application inherits it
+    /// when this process `execve`s into it.
+    pub(crate) async fn enter(&self) -> Result<()> {
+        let pid = std::process::id();
+        let unit = format!("mirrord-{pid}.scope");
+
+        // The transient unit properties, `a(sv)`: their count, then the name, type and value of
+        // each.
+        let mut count = 1;
+        let mut properties = vec![
+            "PIDs".to_string(),
+            "au".to_string(),
+            "1".to_string(),
+            pid.to_string(),
+        ];
+        if let Some(memory_bytes) = self.memory_bytes {
+            properties.extend([
+                "MemoryMax".to_string(),
+                "t".to_string(),
+                memory_bytes.to_string(),
+            ]);
+            count += 1;
+        }
+        if let Some(cpu_millis) = self.cpu_millis {
+            // Microseconds of CPU time per second, one CPU is `1_000_000`.
+            properties.extend([
+                "CPUQuotaPerSecUSec".to_string(),
+                "t".to_string(),
+                (cpu_millis * 1000).to_string(),
+            ]);
+            count += 1;
+        }
+
+        let output = Command::new("busctl")
+            .args([
+                "--user",
+                "call",
+                "org.freedesktop.systemd1",
+                "/org/freedesktop/systemd1",
+                "org.freedesktop.systemd1.Manager",
+                "StartTransientUnit",
+                "ssa(sv)a(sa(sv))",
+                &unit,
+                "fail",
+            ])
+            .arg(count.to_string())
+            .args(&properties)
+            .arg("0")
+            .output()
+            .map_err(|error| CliError::TargetLimitsFailed(format!("running busctl: {error}")))?;
+
+        if !output.status.success() {
+            return Err(CliError::TargetLimitsFailed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+
+        // The job that moves the process is queued by systemd, wait for it to run.
+        for _ in 0..SCOPE_CHECK_ATTEMPTS {
+            let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+            if cgroup.lines().any(|line| line.ends_with(&unit)) {
+                return Ok(());
+            }
+
+            tokio::time::sleep(SCOPE_CHECK_INTERVAL).await;
+        }
+
+        Err(CliError::TargetLimitsFailed(format!(
+            "the process was not moved into {unit}"
+        )))
+    }
+}
+
+impl std::fmt::Display for TargetLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cpu = self
+            .cpu_millis
+            .map(|cpu_millis| format!("{cpu_millis}m CPU"));
+        let memory = self
+            .memory_bytes
+            .map(|memory_bytes| format!("{}Mi memory", memory_bytes / (1024 * 1024)));
+
+        let limits = cpu.into_iter().chain(memory).collect::<Vec<_>>();
+        write!(f, "{}", limits.join(", "))
+    }
+}
+
+/// Parses a Kubernetes CPU quantity, e.g. `500m` or `1.5`, into millicores.
+fn parse_cpu(quantity: &str) -> Option<u64> {
+    let (number, scale) = match quantity.strip_suffix('m') {
+        Some(number) => (number, 1.0),
+        None => (quantity, 1000.0),
+    };
+
+    let millis = (number.parse::<f64>().ok()? * scale).ceil();
+    (millis > 0.0).then_some(millis as u64)
+}
+
+/// Parses a Kubernetes memory quantity, e.g. `512Mi`, `1G` or `1e9`, into bytes.
+fn parse_memory(quantity: &str) -> Option<u64> {
+    const SUFFIXES: [(&str, f64); 12] = [
+        ("Ki", 1024.0),
+        ("Mi", 1024.0 * 1024.0),
+        ("Gi", 1024.0 * 1024.0 * 1024.0),
+        ("Ti", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("Pi", 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("Ei", 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+
+    let (number, scale) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, scale)| Some((quantity.strip_suffix(suffix)?, *scale)))
+        .unwrap_or((quantity, 1.0));
+
+    let bytes = (number.parse::<f64>().ok()? * scale).ceil();
+    (bytes > 0.0).then_some(bytes as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantities() {
+        assert_eq!(parse_cpu("500m"), Some(500));
+        assert_eq!(parse_cpu("2"), Some(2000));
+        assert_eq!(parse_cpu("0.25"), Some(250));
+        assert_eq!(parse_cpu("0"), None);
+        assert_eq!(parse_cpu("lots"), None);
+
+        assert_eq!(parse_memory("512Mi"), Some(512 * 1024 * 1024));
+        assert_eq!(parse_memory("1G"), Some(1_000_000_000));
+        assert_eq!(parse_memory("1e9"), Some(1_000_000_000));
+        assert_eq!(parse_memory("128974848"), Some(128974848));
+        assert_eq!(parse_memory("lots"), None);
+    }
+
+    #[test]
+    fn limits_before_requests() {
+        let quantities = |cpu: &str, memory: &str| {
+            Some(BTreeMap::from([
+                ("cpu".to_string(), Quantity(cpu.to_string())),
+                ("memory".to_string(), Quantity(memory.to_string())),
+            ]))
+        };
+
+        let resources = ResourceRequirements {
+            limits: quantities("1", "1Gi"),
+            requests: quantities("250m", "256Mi"),
+            ..Default::default()
+        };
+        assert_eq!(
+            TargetLimits::from_resources(&resources),
+            TargetLimits {
+                cpu_millis: Some(1000),
+                memory_bytes: Some(1024 * 1024 * 1024),
+            }
+        );
+
+        let resources = ResourceRequirements {
+            requests: quantities("250m", "256Mi"),
+            ..Default::default()
+        };
+        assert_eq!(
+            TargetLimits::from_resources(&resources),
+            TargetLimits {
+                cpu_millis: Some(250),
+                memory_bytes: Some(256 * 1024 * 1024),
+            }
+        );
+
+        assert!(TargetLimits::from_resources(&ResourceRequirements::default()).is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+//! `feature.target_logs`: prints the logs of the target container in the user's terminal, next to
+//! the output of the application.
+//!
+//! `mirrord exec` replaces itself with the application, so the logs are streamed by a hidden
+//! `mirrord target-logs` process, started right before the `execve`. It shares the terminal with
+//! the application and exits when the application does.
+
+use std::{
+    io::{IsTerminal, Write},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use mirrord_config::{target::Target, LayerConfig};
+use mirrord_kube::api::{kubernetes::create_kube_api, runtime::RuntimeDataProvider};
+use nix::{sys::signal, unistd::Pid};
+use tokio::time;
+
+use crate::{CliError, Result, TargetLogsArgs};
+
+/// How often we check whether the application is still running.
+const APP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts the `mirrord target-logs` process for the application that will run as `app_pid`.
+///
+/// Must be called before mirrord's env is set in this process, so the layer is not loaded into
+/// the new process.
+pub(crate) fn spawn_target_logs(app_pid: Pid) -> Result<()> {
+    Command::new(std::env::current_exe().map_err(CliError::CliPathError)?)
+        .arg("target-logs")
+        .arg("--pid")
+        .arg(app_pid.to_string())
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(CliError::TargetLogsSpawnFailed)?;
+
+    Ok(())
+}
+
+/// Entry point of the `mirrord target-logs` command.
+///
+/// Prints the logs of the target container, each line prefixed with `[pod/container]`, until the
+/// application exits or the container stops.
+pub(crate) async fn target_logs_command(args: TargetLogsArgs) -> Result<()> {
+    let config = LayerConfig::from_env()?;
+    let target = match config.target.path.as_ref() {
+        None | Some(Target::Targetless) => return Ok(()),
+        Some(target) => target,
+    };
+
+    let client = create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    .map_err(CliError::CreateKubeApiFailed)?;
+
+    let runtime_data = target
+        .runtime_data(&client, config.target.namespace.as_deref())
+        .await
+        .map_err(CliError::TargetLogsFailed)?;
+    let mut logs = std::pin::pin!(runtime_data
+        .follow_logs(&client)
+        .await
+        .map_err(CliError::TargetLogsFailed)?);
+
+    let prefix = format!(
+        "[{}/{}]",
+        runtime_data.pod_name, runtime_data.container_name
+    );
+    // Dimmed, so it's easy to tell apart from the application's output.
+    let prefix = if std::io::stderr().is_terminal() {
+        format!("\x1b[2m{prefix}\x1b[0m")
+    } else {
+        prefix
+    };
+    let print = |line: &str| {
+        let _ = writeln!(std::io::stderr().lock(), "{prefix} {line}");
+    };
+
+    let app_pid = Pid::from_raw(args.pid);
+    let mut app_check = time::interval(APP_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            line = logs.next() => match line {
+                Some(Ok(line)) => print(&line),
+                Some(Err(error)) => {
+                    print(&format!("(failed to read the logs: {error})"));
+                    break;
+                }
+                None => {
+                    print("(the container stopped, its logs are no longer shown)");
+                    break;
+                }
+            },
+
+            _ = app_check.tick() => {
+                // Signal 0 only checks if the process exists.
+                if signal::kill(app_pid, None).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
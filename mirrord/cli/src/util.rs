@@ -1,5 +1,11 @@
+use mirrord_config::LayerConfig;
+use rand::distributions::{Alphanumeric, DistString};
+
+/// Env var of `feature.network.incoming.http_filter.routing_value`.
+pub(crate) const ROUTING_VALUE_ENV: &str = "MIRRORD_HTTP_FILTER_ROUTING_VALUE";
+
 /// Removes `HTTP_PROXY` and `https_proxy` from the environment
-pub(crate) fn remove_proxy_env() {
+fn remove_proxy_env() {
     for (key, _val) in std::env::vars() {
         let lower_key = key.to_lowercase();
         if lower_key == "http_proxy" || lower_key == "https_proxy" {
@@ -9,3 +15,43 @@ pub(crate) fn remove_proxy_env() {
         }
     }
 }
+
+/// Prepares the proxy environment variables according to the [`LayerConfig`].
+///
+/// Removes them when [`LayerConfig::use_proxy`] is disabled, otherwise applies the
+/// [`LayerConfig::proxy`] overrides. The variables are used by everything that talks with the
+/// cluster, including the processes we spawn (e.g. the internal proxy).
+pub(crate) fn set_proxy_env(config: &LayerConfig) {
+    if !config.use_proxy {
+        remove_proxy_env();
+        return;
+    }
+
+    if let Some(https_proxy) = config.proxy.https_proxy.as_deref() {
+        std::env::set_var("HTTPS_PROXY", https_proxy);
+        std::env::set_var("https_proxy", https_proxy);
+    }
+
+    if let Some(no_proxy) = config.proxy.no_proxy.as_ref() {
+        let no_proxy = no_proxy.join(",");
+        std::env::set_var("NO_PROXY", &no_proxy);
+        std::env::set_var("no_proxy", &no_proxy);
+    }
+}
+
+/// Generates the value of the routing header for this session
+/// (`feature.network.incoming.http_filter.routing_header`), unless it's set in the config.
+///
+/// The value is also set in our env, so that the internal proxy gets the same one.
+pub(crate) fn generate_routing_value(config: &mut LayerConfig) {
+    let http_filter = &mut config.feature.network.incoming.http_filter;
+    if http_filter.routing_header.is_none() || http_filter.routing_value.is_some() {
+        return;
+    }
+
+    let value = Alphanumeric
+        .sample_string(&mut rand::thread_rng(), 12)
+        .to_lowercase();
+    std::env::set_var(ROUTING_VALUE_ENV, &value);
+    http_filter.routing_value = Some(value);
+}
@@ -1,137 +1,366 @@
-//! `mirrord verify-config [--ide] {path}` builds a
+//! `mirrord verify-config [--ide] [--rbac] [--cluster] [--strict] {path}` builds a
 //! [`VerifyConfig`](crate::Commands::VerifyConfig) enum after checking the config file passed in
 //! `path`. It's used by the IDE plugins to display errors/warnings quickly, without having to start
 //! mirrord-layer.
+//!
+//! The output types are in [`mirrord_ext_schema::verify_config`], shared with the plugins.
+use std::{path::Path, time::Duration};
+
 use error::Result;
+use k8s_openapi::api::core::v1::{Namespace, Pod};
+use kube::{api::ListParams, Api, Client};
 use mirrord_config::{
-    config::{ConfigContext, MirrordConfig},
+    config::{strict::ConfigLint, ConfigContext, ConfigError, MirrordConfig},
     feature::FeatureConfig,
     target::{
-        cron_job::CronJobTarget, deployment::DeploymentTarget, job::JobTarget, pod::PodTarget,
-        rollout::RolloutTarget, stateful_set::StatefulSetTarget, Target, TargetConfig,
+        cron_job::{CronJobTarget, WaitForRun},
+        deployment::DeploymentTarget,
+        job::JobTarget,
+        pod::PodTarget,
+        rollout::RolloutTarget,
+        stateful_set::StatefulSetTarget,
+        Target, TargetConfig,
     },
+    LayerConfig,
+};
+use mirrord_ext_schema::verify_config::{
+    ClusterCheck, ClusterReport, MissingPermission, RbacReport, StrictWarning, TargetType,
+    VerifiedConfig, VerifiedTarget, VerifiedTargetConfig, WaitForRun as VerifiedWaitForRun,
+};
+use mirrord_kube::api::{
+    kubernetes::create_kube_api,
+    rbac::{missing_permissions, required_permissions},
+    runtime::RuntimeDataProvider,
+};
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+
+use crate::{
+    config::VerifyConfigArgs, connection::check_if_operator_resource_exists, error,
+    util::set_proxy_env, LayerFileConfig,
 };
-use serde::Serialize;
-
-use crate::{config::VerifyConfigArgs, error, LayerFileConfig};
-
-/// Practically the same as [`Target`], but differs in the way the `targetless` option is
-/// serialized. [`Target::Targetless`] serializes as `null`, [`VerifiedTarget::Targetless`]
-/// serializes as string `"targetless"`. This difference allows the IDEs to correctly decide whether
-/// to show the target selection dialog.
-///
-/// Changing the way [`Target::Targetless`] serializes would be cumbersome for two reasons:
-/// 1. It's used in a lot of places, e.g. CRDs
-/// 2. `schemars` crate does not support nested `[serde(untagged)]` tags
-#[derive(Serialize)]
-enum VerifiedTarget {
-    #[serde(rename = "targetless")]
-    Targetless,
-    #[serde(untagged)]
-    Pod(PodTarget),
-    #[serde(untagged)]
-    Deployment(DeploymentTarget),
-    #[serde(untagged)]
-    Rollout(RolloutTarget),
-
-    #[serde(untagged)]
-    Job(JobTarget),
-
-    #[serde(untagged)]
-    CronJob(CronJobTarget),
-
-    #[serde(untagged)]
-    StatefulSet(StatefulSetTarget),
-}
 
-impl From<Target> for VerifiedTarget {
-    fn from(value: Target) -> Self {
-        match value {
-            Target::Deployment(target) => Self::Deployment(target),
-            Target::Pod(target) => Self::Pod(target),
-            Target::Rollout(target) => Self::Rollout(target),
-            Target::Job(target) => Self::Job(target),
-            Target::CronJob(target) => Self::CronJob(target),
-            Target::StatefulSet(target) => Self::StatefulSet(target),
-            Target::Targetless => Self::Targetless,
+/// The config's [`Target`] as printed by `verify-config`, where targetless is `"targetless"`
+/// instead of `null`.
+fn verified_target(target: Target) -> VerifiedTarget {
+    match target {
+        Target::Deployment(DeploymentTarget {
+            deployment,
+            container,
+        }) => VerifiedTarget::Deployment {
+            deployment,
+            container,
+        },
+        Target::Pod(PodTarget { pod, container }) => VerifiedTarget::Pod { pod, container },
+        Target::Rollout(RolloutTarget { rollout, container }) => {
+            VerifiedTarget::Rollout { rollout, container }
         }
+        Target::Job(JobTarget { job, container }) => VerifiedTarget::Job { job, container },
+        Target::CronJob(CronJobTarget {
+            cron_job,
+            container,
+        }) => VerifiedTarget::CronJob {
+            cron_job,
+            container,
+        },
+        Target::StatefulSet(StatefulSetTarget {
+            stateful_set,
+            container,
+        }) => VerifiedTarget::StatefulSet {
+            stateful_set,
+            container,
+        },
+        Target::Targetless => VerifiedTarget::Targetless,
     }
 }
 
-#[derive(Serialize)]
-struct VerifiedTargetConfig {
-    path: Option<VerifiedTarget>,
-    namespace: Option<String>,
+fn verified_target_config(config: TargetConfig) -> VerifiedTargetConfig {
+    VerifiedTargetConfig {
+        path: config.path.map(verified_target),
+        namespace: config.namespace,
+        wait_for_run: config.wait_for_run.map(|wait_for_run| match wait_for_run {
+            WaitForRun::Next => VerifiedWaitForRun::Next,
+            WaitForRun::Trigger => VerifiedWaitForRun::Trigger,
+        }),
+    }
 }
 
-impl From<TargetConfig> for VerifiedTargetConfig {
-    fn from(value: TargetConfig) -> Self {
-        Self {
-            path: value.path.map(Into::into),
-            namespace: value.namespace,
+/// The [`TargetType`]s (one for each variant of [`Target`]) that can be used with the `config`.
+fn compatible_target_types(config: &FeatureConfig) -> Vec<TargetType> {
+    [
+        TargetType::Targetless,
+        TargetType::Pod,
+        TargetType::Deployment,
+        TargetType::Rollout,
+        TargetType::Job,
+        TargetType::CronJob,
+        TargetType::StatefulSet,
+    ]
+    .into_iter()
+    .filter(|target_type| match target_type {
+        TargetType::Targetless | TargetType::Rollout => !config.copy_target.enabled,
+        TargetType::Pod => !(config.copy_target.enabled && config.copy_target.scale_down),
+        TargetType::Job | TargetType::CronJob | TargetType::StatefulSet => {
+            config.copy_target.enabled
         }
+        _ => true,
+    })
+    .collect()
+}
+
+/// Checks the permissions required by [`required_permissions`] with the user's kube credentials.
+async fn check_rbac(config: &LayerConfig) -> RbacReport {
+    set_proxy_env(config);
+
+    let result = async {
+        let client = create_kube_api(
+            config.accept_invalid_certificates,
+            config.kubeconfig.clone(),
+            config.kube_context.clone(),
+        )
+        .await?;
+
+        let permissions = required_permissions(config, client.default_namespace());
+        missing_permissions(&client, permissions).await
+    }
+    .await;
+
+    match result {
+        Ok(missing) => RbacReport::Checked {
+            missing: missing
+                .into_iter()
+                .map(|permission| MissingPermission {
+                    description: permission.to_string(),
+                    verb: permission.verb.to_string(),
+                    group: permission.group.to_string(),
+                    resource: permission.resource.to_string(),
+                    subresource: permission.subresource.map(ToString::to_string),
+                    namespace: permission.namespace,
+                    reason: permission.reason.to_string(),
+                })
+                .collect(),
+            operator: config.operator == Some(true),
+        },
+        Err(error) => RbacReport::Failed {
+            error: error.to_string(),
+        },
     }
 }
 
-/// Corresponds to variants of [`Target`].
-#[derive(Serialize, PartialEq, Eq, Clone, Copy)]
-#[serde(rename_all = "lowercase")]
-enum TargetType {
-    Targetless,
-    Pod,
-    Deployment,
-    Rollout,
-    Job,
-    CronJob,
-    StatefulSet,
+/// [`ClusterCheck::Ok`], or [`ClusterCheck::Failed`] with the error.
+fn cluster_check<E: ToString>(result: Result<(), E>) -> ClusterCheck {
+    match result {
+        Ok(()) => ClusterCheck::Ok,
+        Err(error) => ClusterCheck::Failed {
+            error: error.to_string(),
+        },
+    }
 }
 
-impl TargetType {
-    fn all() -> impl Iterator<Item = Self> {
-        [
-            Self::Targetless,
-            Self::Pod,
-            Self::Deployment,
-            Self::Rollout,
-            Self::Job,
-            Self::CronJob,
-            Self::StatefulSet,
-        ]
-        .into_iter()
+/// Checks the target, its namespace and the agent image of the `config` with the user's kube
+/// credentials.
+async fn check_cluster(config: &LayerConfig) -> ClusterReport {
+    set_proxy_env(config);
+
+    let client = match create_kube_api(
+        config.accept_invalid_certificates,
+        config.kubeconfig.clone(),
+        config.kube_context.clone(),
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => {
+            return ClusterReport::Failed {
+                error: error.to_string(),
+            }
+        }
+    };
+
+    let namespace = config
+        .target
+        .namespace
+        .clone()
+        .unwrap_or_else(|| client.default_namespace().to_string());
+
+    let target = match &config.target.path {
+        Some(Target::Targetless) | None => None,
+        Some(target) => Some(cluster_check(
+            target
+                .runtime_data(&client, Some(&namespace))
+                .await
+                .map(|_| ()),
+        )),
+    };
+
+    let operator = check_if_operator_resource_exists(config).await.ok();
+
+    // The operator spawns the agents with the image it's configured with.
+    let agent_image = if operator == Some(true) && config.operator != Some(false) {
+        ClusterCheck::Unknown {
+            reason: "the agent is spawned by the operator, with the image it's configured with"
+                .to_string(),
+        }
+    } else {
+        check_image(config.agent.image()).await
+    };
+
+    ClusterReport::Checked {
+        namespace: check_namespace(&client, &namespace).await,
+        target,
+        agent_image,
+        operator,
+    }
+}
+
+/// The namespace exists, or at least its pods can be listed when the user can't get namespaces.
+async fn check_namespace(client: &Client, namespace: &str) -> ClusterCheck {
+    match Api::<Namespace>::all(client.clone()).get(namespace).await {
+        Ok(..) => ClusterCheck::Ok,
+        Err(kube::Error::Api(response)) if response.code == 404 => ClusterCheck::Failed {
+            error: format!("namespace `{namespace}` doesn't exist"),
+        },
+        Err(..) => cluster_check(
+            Api::<Pod>::namespaced(client.clone(), namespace)
+                .list(&ListParams::default().limit(1))
+                .await
+                .map(|_| ()),
+        ),
     }
+}
+
+/// Token from the auth server of an image registry, some send it in both fields.
+#[derive(Deserialize)]
+struct RegistryToken {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Checks that the manifest of the `image` can be fetched from its registry, without credentials
+/// (the cluster may have them in `agent.image_pull_secrets`).
+async fn check_image(image: &str) -> ClusterCheck {
+    let (name, reference) = match image.rsplit_once('@') {
+        Some((name, digest)) => (name, digest),
+        None => match image.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, tag),
+            _ => (image, "latest"),
+        },
+    };
+    let (registry, repository) = match name.split_once('/') {
+        Some((registry, repository))
+            if registry.contains(['.', ':']) || registry == "localhost" =>
+        {
+            (registry.to_string(), repository.to_string())
+        }
+        Some(..) => ("registry-1.docker.io".to_string(), name.to_string()),
+        None => (
+            "registry-1.docker.io".to_string(),
+            format!("library/{name}"),
+        ),
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+    let url = format!("https://{registry}/v2/{repository}/manifests/{reference}");
+    let request = || {
+        client.head(&url).header(
+            header::ACCEPT,
+            "application/vnd.oci.image.index.v1+json, \
+             application/vnd.docker.distribution.manifest.list.v2+json, \
+             application/vnd.oci.image.manifest.v1+json, \
+             application/vnd.docker.distribution.manifest.v2+json",
+        )
+    };
 
-    fn compatible_with(&self, config: &FeatureConfig) -> bool {
-        match self {
-            Self::Targetless | Self::Rollout => !config.copy_target.enabled,
-            Self::Pod => !(config.copy_target.enabled && config.copy_target.scale_down),
-            Self::Job | Self::CronJob | Self::StatefulSet => config.copy_target.enabled,
-            Self::Deployment => true,
+    let result = async {
+        let response = request().send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response.status());
         }
+
+        // Anonymous token, from `Bearer realm="...",service="...",scope="..."`.
+        let Some(challenge) = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return Ok(response.status());
+        };
+        let params = challenge
+            // The values are quoted, and the scope may have commas in it.
+            .split("\",")
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim_matches('"')))
+            .collect::<Vec<_>>();
+        let Some((_, realm)) = params.iter().find(|(key, _)| *key == "realm") else {
+            return Ok(response.status());
+        };
+        let query = params
+            .iter()
+            .filter(|(key, _)| *key != "realm")
+            .collect::<Vec<_>>();
+
+        let token: RegistryToken = client
+            .get(*realm)
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let token = token.token.or(token.access_token).unwrap_or_default();
+
+        Ok::<_, reqwest::Error>(request().bearer_auth(token).send().await?.status())
+    }
+    .await;
+
+    match result {
+        Ok(status) if status.is_success() => ClusterCheck::Ok,
+        Ok(StatusCode::NOT_FOUND) => ClusterCheck::Failed {
+            error: format!("image `{image}` not found in `{registry}`"),
+        },
+        Ok(status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)) => ClusterCheck::Unknown {
+            reason: format!(
+                "`{registry}` requires credentials ({status}), the cluster needs them in \
+                 `agent.image_pull_secrets`"
+            ),
+        },
+        Ok(status) => ClusterCheck::Failed {
+            error: format!("`{registry}` responded with {status} for image `{image}`"),
+        },
+        Err(error) => ClusterCheck::Unknown {
+            reason: format!("couldn't reach `{registry}` from here: {error}"),
+        },
     }
 }
 
-/// Produced by calling `verify_config`.
-///
-/// It's consumed by the IDEs to check if a config is valid, or missing something, without starting
-/// mirrord fully.
-#[derive(Serialize)]
-#[serde(tag = "type")]
-enum VerifiedConfig {
-    /// mirrord is able to run with this config, but it might have some issues or weird behavior
-    /// depending on the `warnings`.
-    Success {
-        /// A valid, verified config for the `target` part of mirrord.
-        config: VerifiedTargetConfig,
-        /// Improper combination of features was requested, but mirrord can still run.
-        warnings: Vec<String>,
-        /// Target types compatible with the source config.
-        /// Meant to be used by IDE plugins for customizing target selection.
-        compatible_target_types: Vec<TargetType>,
-    },
-    /// Invalid config was detected, mirrord cannot run.
-    ///
-    /// May be triggered by extra/lacking `,`, or invalid fields, etc.
-    Fail { errors: Vec<String> },
+/// The [`ConfigLint`] as printed by `verify-config --strict`.
+fn strict_warning(lint: &ConfigLint) -> StrictWarning {
+    StrictWarning {
+        rule: lint.rule_id().to_string(),
+        path: lint.path().to_string(),
+        suggestion: match lint {
+            ConfigLint::UnknownField { suggestion, .. } => suggestion.clone(),
+            ConfigLint::Deprecated { .. } => None,
+        },
+        message: lint.to_string(),
+    }
+}
+
+/// Loads the config file at `path` and checks it like mirrord does when it starts, the warnings
+/// are left in `context` (with the [`ConfigLint`]s in [`ConfigContext::strict`] mode).
+pub(super) fn load_and_verify(
+    path: &Path,
+    context: &mut ConfigContext,
+) -> Result<LayerConfig, ConfigError> {
+    let config =
+        LayerFileConfig::from_path_with_context(path, context)?.generate_config(context)?;
+    config.verify(context)?;
+    Ok(config)
 }
 
 /// Verifies a config file specified by `path`.
@@ -161,6 +390,79 @@ enum VerifiedConfig {
 /// }
 /// ```
 ///
+/// With `--rbac`, the output also lists the Kubernetes permissions that the current user is
+/// missing to run mirrord without the operator:
+///
+/// ```sh
+/// mirrord verify-config --rbac ./valid-config.json
+///
+///
+/// {
+///   "type": "Success",
+///   ...
+///   "rbac": {
+///     "type": "Checked",
+///     "missing": [
+///       {
+///         "description": "create pods/portforward in namespace default (connect to the agent)",
+///         "verb": "create",
+///         "group": "",
+///         "resource": "pods",
+///         "subresource": "portforward",
+///         "namespace": "default",
+///         "reason": "connect to the agent"
+///       }
+///     ],
+///     "operator": false
+///   }
+/// }
+/// ```
+///
+/// With `--cluster`, the output also says whether the target, its namespace and the agent image
+/// can be found, and whether the operator is installed. Each check has a `status` of `ok`,
+/// `failed` (with an `error`) or `unknown` (with a `reason`):
+///
+/// ```sh
+/// mirrord verify-config --cluster ./valid-config.json
+///
+///
+/// {
+///   "type": "Success",
+///   ...
+///   "cluster": {
+///     "type": "Checked",
+///     "namespace": { "status": "ok" },
+///     "target": {
+///       "status": "failed",
+///       "error": "deployments.apps \"sample-deployment\" not found"
+///     },
+///     "agent_image": { "status": "ok" },
+///     "operator": false
+///   }
+/// }
+/// ```
+///
+/// With `--strict`, unknown (e.g. misspelled) and deprecated fields are also reported, in
+/// `warnings` and with their paths in `strict`:
+///
+/// ```sh
+/// mirrord verify-config --strict ./typo-config.json
+///
+///
+/// {
+///   "type": "Fail",
+///   "errors": ["mirrord-config: data did not match any variant of untagged enum ..."],
+///   "strict": [
+///     {
+///       "rule": "config/unknown-field",
+///       "path": "feature.network.incoming.htttp_filter",
+///       "suggestion": "feature.network.incoming.http_filter",
+///       "message": "[config/unknown-field] `feature.network.incoming.htttp_filter` is not ..."
+///     }
+///   ]
+/// }
+/// ```
+///
 /// ```sh
 /// mirrord verify-config ./broken-config.json
 ///
@@ -170,26 +472,47 @@ enum VerifiedConfig {
 ///   "errors": ["mirrord-config: IO operation failed with `No such file or directory (os error 2)`"]
 /// }
 /// ```
-pub(super) async fn verify_config(VerifyConfigArgs { ide, path }: VerifyConfigArgs) -> Result<()> {
+pub(super) async fn verify_config(
+    VerifyConfigArgs {
+        ide,
+        rbac,
+        cluster,
+        strict,
+        path,
+    }: VerifyConfigArgs,
+) -> Result<()> {
     let mut config_context = ConfigContext::new(ide);
+    config_context.strict = strict;
 
-    let layer_config = LayerFileConfig::from_path(path)
-        .and_then(|config| config.generate_config(&mut config_context))
-        .and_then(|config| {
-            config.verify(&mut config_context)?;
-            Ok(config)
-        });
+    let layer_config = load_and_verify(&path, &mut config_context);
+    let strict_warnings = strict.then(|| {
+        config_context
+            .get_lints()
+            .iter()
+            .map(strict_warning)
+            .collect::<Vec<_>>()
+    });
 
     let verified = match layer_config {
         Ok(config) => VerifiedConfig::Success {
-            config: config.target.into(),
+            rbac: if rbac {
+                Some(check_rbac(&config).await)
+            } else {
+                None
+            },
+            cluster: if cluster {
+                Some(check_cluster(&config).await)
+            } else {
+                None
+            },
+            config: verified_target_config(config.target),
             warnings: config_context.get_warnings().to_owned(),
-            compatible_target_types: TargetType::all()
-                .filter(|tt| tt.compatible_with(&config.feature))
-                .collect(),
+            compatible_target_types: compatible_target_types(&config.feature),
+            strict: strict_warnings,
         },
         Err(fail) => VerifiedConfig::Fail {
             errors: vec![fail.to_string()],
+            strict: strict_warnings,
         },
     };
 
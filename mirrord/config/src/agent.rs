@@ -1,4 +1,9 @@
-use std::{collections::HashMap, fmt, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::Path,
+    str::FromStr,
+};
 
 use k8s_openapi::api::core::v1::{ResourceRequirements, Toleration};
 use mirrord_analytics::CollectAnalytics;
@@ -45,6 +50,145 @@ impl fmt::Display for LinuxCapability {
     }
 }
 
+/// Which connections to the stolen ports are stolen, see
+/// [`AgentConfig::steal_loopback`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopbackSteal {
+    /// Connections from outside of the target's pod, and the loopback ones made inside of it.
+    #[default]
+    Include,
+    /// Only connections from outside of the target's pod.
+    Exclude,
+    /// Only the loopback connections made inside of the target's pod.
+    Only,
+}
+
+impl FromStr for LoopbackSteal {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "include" => Ok(Self::Include),
+            "exclude" => Ok(Self::Exclude),
+            "only" => Ok(Self::Only),
+            other => Err(ConfigError::InvalidValue(
+                other.to_string(),
+                "MIRRORD_AGENT_STEAL_LOOPBACK",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LoopbackSteal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let as_str = match self {
+            Self::Include => "include",
+            Self::Exclude => "exclude",
+            Self::Only => "only",
+        };
+
+        f.write_str(as_str)
+    }
+}
+
+/// How the agent's sniffer captures the mirrored traffic, see [`AgentConfig::sniffer`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnifferBackend {
+    /// Raw socket with a classic BPF filter.
+    #[default]
+    Raw,
+    /// eBPF program attached to the pod's interface with TC.
+    Ebpf,
+}
+
+impl FromStr for SnifferBackend {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "raw" => Ok(Self::Raw),
+            "ebpf" => Ok(Self::Ebpf),
+            other => Err(ConfigError::InvalidValue(
+                other.to_string(),
+                "MIRRORD_AGENT_SNIFFER",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SnifferBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let as_str = match self {
+            Self::Raw => "raw",
+            Self::Ebpf => "ebpf",
+        };
+
+        f.write_str(as_str)
+    }
+}
+
+/// How mirrord connects to the agent, see [`AgentConfig::transport`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentTransport {
+    /// TCP connection, through a port-forward of the Kubernetes API.
+    #[default]
+    Tcp,
+    /// QUIC connection, straight to the agent's pod.
+    Quic,
+}
+
+impl FromStr for AgentTransport {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "tcp" => Ok(Self::Tcp),
+            "quic" => Ok(Self::Quic),
+            other => Err(ConfigError::InvalidValue(
+                other.to_string(),
+                "MIRRORD_AGENT_TRANSPORT",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AgentTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let as_str = match self {
+            Self::Tcp => "tcp",
+            Self::Quic => "quic",
+        };
+
+        f.write_str(as_str)
+    }
+}
+
+/// Which mirrored traffic the agent drops when the buffer of a client is full, see
+/// [`AgentMirrorBufferConfig::on_full`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorBufferFull {
+    /// Make room by dropping the oldest data in the buffer.
+    #[default]
+    DropOldest,
+    /// Drop the data that doesn't fit.
+    DropNewest,
+}
+
+impl fmt::Display for MirrorBufferFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let as_str = match self {
+            Self::DropOldest => "drop_oldest",
+            Self::DropNewest => "drop_newest",
+        };
+
+        f.write_str(as_str)
+    }
+}
+
 /// Configuration for the mirrord-agent pod that is spawned in the Kubernetes cluster.
 ///
 /// We provide sane defaults for this option, so you don't have to set up anything here.
@@ -139,6 +283,28 @@ pub struct AgentConfig {
     #[config(nested)]
     pub image: AgentImageConfig,
 
+    /// ### agent.arch_images {#agent-arch_images}
+    ///
+    /// Agent images per node architecture, for clusters with nodes of different architectures
+    /// where the agent image is not multi-arch.
+    ///
+    /// When set, mirrord checks the architecture of the node the agent will run on (the node of
+    /// the target), and uses the matching image instead of [`agent.image`](#agent-image). If
+    /// there is no image for that architecture, mirrord fails before creating the agent.
+    ///
+    /// Targetless agents use the first architecture (alphabetically), and are scheduled only on
+    /// nodes of that architecture.
+    ///
+    /// ```json
+    /// {
+    ///   "arch_images": {
+    ///     "amd64": "internal.repo/images/mirrord:3.0.0-amd64",
+    ///     "arm64": "internal.repo/images/mirrord:3.0.0-arm64"
+    ///   }
+    /// }
+    /// ```
+    pub arch_images: Option<BTreeMap<String, String>>,
+
     /// ### agent.image_pull_policy {#agent-image_pull_policy}
     ///
     /// Controls when a new agent image is downloaded.
@@ -231,6 +397,51 @@ pub struct AgentConfig {
     )]
     pub flush_connections: bool,
 
+    /// ### agent.steal_loopback {#agent-steal_loopback}
+    ///
+    /// Which connections to the stolen ports are stolen, based on where they come from:
+    ///
+    /// - `"include"`: connections from outside of the target's pod, and the loopback connections
+    ///   made inside of it, e.g. by a sidecar that talks to the target over `localhost`;
+    /// - `"exclude"`: only connections from outside of the target's pod;
+    /// - `"only"`: only the loopback connections, so you can take over a port that the target uses
+    ///   over `localhost` (like a sidecar's `localhost:9000`) without stealing its external
+    ///   traffic.
+    ///
+    /// Defaults to `"include"`.
+    #[config(env = "MIRRORD_AGENT_STEAL_LOOPBACK", default)]
+    pub steal_loopback: LoopbackSteal,
+
+    /// ### agent.sniffer {#agent-sniffer}
+    ///
+    /// How the agent captures the traffic of the mirrored ports:
+    ///
+    /// - `"raw"`: a raw socket with a classic BPF filter;
+    /// - `"ebpf"`: an eBPF program attached to the target's interface with TC. It also sees the
+    ///   traffic that some CNIs deliver without going through the raw socket, and copies only the
+    ///   mirrored packets out of the kernel. Needs the `SYS_ADMIN` and `NET_ADMIN` capabilities.
+    ///
+    /// When the eBPF program can't be loaded (old kernel, missing permissions), the agent falls
+    /// back to `"raw"`.
+    ///
+    /// Defaults to `"raw"`.
+    #[config(env = "MIRRORD_AGENT_SNIFFER", default)]
+    pub sniffer: SnifferBackend,
+
+    /// ### agent.transport {#agent-transport}
+    ///
+    /// How mirrord connects to the agent:
+    ///
+    /// - `"tcp"`: a TCP connection, through a port-forward of the Kubernetes API;
+    /// - `"quic"`: a QUIC connection made straight to the agent's pod, secured with a certificate
+    ///   generated for the session. It doesn't suffer from head-of-line blocking when many
+    ///   connections are multiplexed over a lossy network, but the port-forward can't carry UDP,
+    ///   so the pod IPs must be reachable from your machine (e.g. over a VPN).
+    ///
+    /// Defaults to `"tcp"`.
+    #[config(env = "MIRRORD_AGENT_TRANSPORT", default)]
+    pub transport: AgentTransport,
+
     /// ### agent.disabled_capabilities {#agent-disabled_capabilities}
     ///
     /// Disables specified Linux capabilities for the agent container.
@@ -292,6 +503,25 @@ pub struct AgentConfig {
     #[config(default = false)]
     pub privileged: bool,
 
+    /// ### agent.unprivileged {#agent-unprivileged}
+    ///
+    /// Run the agent without any added Linux capabilities, for clusters that forbid privileged
+    /// pods and capabilities like `NET_ADMIN`. Defaults to `false`.
+    ///
+    /// The agent provides the features it can without them, and mirrord tells you at the start
+    /// of the session which ones are not available:
+    ///
+    /// - Mirroring incoming traffic needs `NET_RAW`, which some clusters allow.
+    /// - Stealing incoming traffic needs `NET_ADMIN`, so it's never available.
+    /// - The env and files of the target need `SYS_PTRACE` when the target runs as another user
+    ///   than the agent.
+    /// - Outgoing traffic and DNS work, from the network namespace of the target.
+    ///
+    /// Requires `agent.ephemeral` when there's a target, as the agent can't enter the target's
+    /// namespaces (`SYS_ADMIN`) from a pod of its own.
+    #[config(default = false)]
+    pub unprivileged: bool,
+
     /// ### agent.nftables {#agent-nftables}
     ///
     /// Use iptables-nft instead of iptables-legacy.
@@ -301,10 +531,61 @@ pub struct AgentConfig {
     #[config(default = false)]
     pub nftables: bool,
 
+    /// ### agent.handover {#agent-handover}
+    ///
+    /// When the connection with the agent is lost, e.g. because its node is being drained,
+    /// spawn a new agent for the same target and continue the session with it, instead of
+    /// failing.
+    ///
+    /// Active subscriptions and in-flight requests are moved to the new agent, but connections
+    /// and files opened through the old agent are lost. Works best with targets that are recreated
+    /// under the same path, like deployments and rollouts.
+    ///
+    /// Not supported when running with the mirrord operator.
+    ///
+    /// Defaults to `false`.
+    #[config(default = false)]
+    pub handover: bool,
+
     /// ### agent.dns {#agent-dns}
     #[config(nested)]
     pub dns: AgentDnsConfig,
 
+    /// ### agent.otlp_metrics {#agent-otlp_metrics}
+    ///
+    /// Makes the agent push metrics about the sessions it serves (connected clients, and what
+    /// they do in the cluster) to an OpenTelemetry collector, for clusters without the operator
+    /// or a scrape infrastructure.
+    ///
+    /// ```json
+    /// {
+    ///   "otlp_metrics": {
+    ///     "endpoint": "http://otel-collector.monitoring:4318",
+    ///     "headers": { "x-team": "backend" },
+    ///     "interval": 30
+    ///   }
+    /// }
+    /// ```
+    #[config(nested)]
+    pub otlp_metrics: AgentOtlpMetricsConfig,
+
+    /// ### agent.mirror_buffer {#agent-mirror_buffer}
+    ///
+    /// Limits on the mirrored traffic that the agent keeps for a client that reads it slower than
+    /// it comes (e.g. over a slow connection), so the agent doesn't run out of memory.
+    ///
+    /// ```json
+    /// {
+    ///   "mirror_buffer": {
+    ///     "memory": 16777216,
+    ///     "disk": 1073741824,
+    ///     "on_full": "drop_oldest"
+    ///   }
+    /// }
+    /// ```
+    #[config(nested)]
+    pub mirror_buffer: AgentMirrorBufferConfig,
+
     /// ### agent.labels {#agent-labels}
     ///
     /// Allows setting up custom labels for the agent Job and Pod.
@@ -349,6 +630,40 @@ impl Default for AgentImageConfig {
     }
 }
 
+impl AgentImageConfig {
+    /// The image without its tag and digest, e.g. `ghcr.io/metalbear-co/mirrord`.
+    fn repository(&self) -> &str {
+        let image = self.0.split('@').next().unwrap_or_default();
+        match image.rsplit_once(':') {
+            // A `:` before the last `/` is the port of the registry.
+            Some((repository, tag)) if !tag.contains('/') => repository,
+            _ => image,
+        }
+    }
+
+    /// Tag of the image, [`None`] when it doesn't have one (or has only a digest).
+    pub fn tag(&self) -> Option<&str> {
+        let image = self.0.split('@').next().unwrap_or_default();
+        self.0
+            .get(self.repository().len() + 1..image.len())
+            .filter(|tag| !tag.is_empty())
+    }
+
+    /// Whether the image is tagged with the version of this mirrord.
+    pub fn is_current_version(&self) -> bool {
+        self.tag() == Some(env!("CARGO_PKG_VERSION"))
+    }
+
+    /// The same image, tagged with the version of this mirrord.
+    pub fn with_current_version(&self) -> Self {
+        Self(format!(
+            "{}:{}",
+            self.repository(),
+            env!("CARGO_PKG_VERSION")
+        ))
+    }
+}
+
 /// <!--${internal}-->
 /// Allows us to support the dual configuration for the agent image.
 ///
@@ -429,6 +744,17 @@ impl MirrordConfig for AgentImageFileConfig {
 impl CollectAnalytics for &AgentConfig {
     fn collect_analytics(&self, analytics: &mut mirrord_analytics::Analytics) {
         analytics.add("ephemeral", self.ephemeral);
+        analytics.add("unprivileged", self.unprivileged);
+        analytics.add("handover", self.handover);
+        analytics.add("arch_images", self.arch_images.is_some());
+        analytics.add(
+            "steal_loopback",
+            self.steal_loopback != LoopbackSteal::Include,
+        );
+        analytics.add("otlp_metrics", self.otlp_metrics.endpoint.is_some());
+        analytics.add("mirror_buffer_disk", self.mirror_buffer.disk.is_some());
+        analytics.add("sniffer_ebpf", self.sniffer == SnifferBackend::Ebpf);
+        analytics.add("transport_quic", self.transport == AgentTransport::Quic);
     }
 }
 
@@ -472,6 +798,64 @@ pub struct AgentDnsConfig {
     pub attempts: Option<u32>,
 }
 
+#[derive(MirrordConfig, Default, PartialEq, Eq, Clone, Debug)]
+#[config(derive = "JsonSchema")]
+#[cfg_attr(test, config(derive = "PartialEq, Eq"))]
+pub struct AgentOtlpMetricsConfig {
+    /// ### agent.otlp_metrics.endpoint {#agent-otlp_metrics-endpoint}
+    ///
+    /// OTLP/HTTP endpoint of the collector, e.g. `http://otel-collector.monitoring:4318`. The
+    /// metrics are sent to `/v1/metrics` when the endpoint has no path.
+    ///
+    /// Only plain `http://` is supported, the agent doesn't export anything if this is not set.
+    pub endpoint: Option<String>,
+
+    /// ### agent.otlp_metrics.headers {#agent-otlp_metrics-headers}
+    ///
+    /// Headers sent with every export, e.g. for authentication.
+    ///
+    /// They are passed to the agent in its environment, so they're visible to anyone who can
+    /// read the agent's pod.
+    pub headers: Option<BTreeMap<String, String>>,
+
+    /// ### agent.otlp_metrics.interval {#agent-otlp_metrics-interval}
+    ///
+    /// Seconds between the exports, defaults to 30.
+    pub interval: Option<u64>,
+}
+
+#[derive(MirrordConfig, Default, PartialEq, Eq, Clone, Debug)]
+#[config(derive = "JsonSchema")]
+#[cfg_attr(test, config(derive = "PartialEq, Eq"))]
+pub struct AgentMirrorBufferConfig {
+    /// ### agent.mirror_buffer.memory {#agent-mirror_buffer-memory}
+    ///
+    /// Bytes of mirrored traffic kept in memory for each client, defaults to 16 MiB.
+    pub memory: Option<u64>,
+
+    /// ### agent.mirror_buffer.disk {#agent-mirror_buffer-disk}
+    ///
+    /// Bytes of mirrored traffic spilled to disk when the memory of a client is full, for all the
+    /// clients of the agent together. The agent pod gets an `emptyDir` volume of this size for
+    /// it (ephemeral agents spill to their container's filesystem).
+    ///
+    /// Nothing is spilled by default.
+    pub disk: Option<u64>,
+
+    /// ### agent.mirror_buffer.on_full {#agent-mirror_buffer-on_full}
+    ///
+    /// What to drop when both the memory and the disk are full:
+    ///
+    /// - `"drop_oldest"`: the oldest data in the buffer, to make room for the new one;
+    /// - `"drop_newest"`: the data that doesn't fit.
+    ///
+    /// New and closed connections are never dropped, but the connections that lost some of their
+    /// data are mirrored incomplete.
+    ///
+    /// Defaults to `"drop_oldest"`.
+    pub on_full: Option<MirrorBufferFull>,
+}
+
 #[cfg(test)]
 #[allow(clippy::too_many_arguments)]
 mod tests {
@@ -537,4 +921,29 @@ mod tests {
             },
         );
     }
+
+    #[rstest]
+    #[case(
+        "ghcr.io/metalbear-co/mirrord:3.0.0",
+        "ghcr.io/metalbear-co/mirrord",
+        Some("3.0.0")
+    )]
+    #[case(
+        "internal.repo:5000/mirrord:latest",
+        "internal.repo:5000/mirrord",
+        Some("latest")
+    )]
+    #[case("internal.repo:5000/mirrord", "internal.repo:5000/mirrord", None)]
+    #[case("mirrord:1.0@sha256:abcd", "mirrord", Some("1.0"))]
+    #[case("mirrord@sha256:abcd", "mirrord", None)]
+    fn image_tag(#[case] image: &str, #[case] repository: &str, #[case] tag: Option<&str>) {
+        let image = AgentImageConfig(image.to_string());
+
+        assert_eq!(image.tag(), tag);
+        assert_eq!(
+            image.with_current_version().0,
+            format!("{repository}:{}", env!("CARGO_PKG_VERSION"))
+        );
+        assert!(image.with_current_version().is_current_version());
+    }
 }
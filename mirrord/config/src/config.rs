@@ -1,12 +1,16 @@
 pub mod deprecated;
 pub mod from_env;
+pub mod interpolation;
 pub mod source;
+pub mod strict;
 pub mod unstable;
 
 use std::error::Error;
 
 use thiserror::Error;
 
+use self::strict::ConfigLint;
+
 /// <!--${internal}-->
 /// Error that would be returned from [MirrordConfig::generate_config]
 #[derive(Error, Debug)]
@@ -61,6 +65,12 @@ pub enum ConfigError {
 
     #[error("Template rendering failed with: `{0}`! Please check your config file!")]
     TemplateRenderingFailed(String),
+
+    #[error(
+        "The config file uses the env variable `{0}`, which is not set! Set it, or give it a \
+        default value with `${{{0}:-default}}`."
+    )]
+    MissingEnvVar(String),
 }
 
 impl From<tera::Error> for ConfigError {
@@ -91,6 +101,12 @@ pub struct ConfigContext {
     ///
     /// Some _target_ related errors become warning when `ide == true`.
     warnings: Vec<String>,
+
+    /// Check the config file for unknown and deprecated fields, see [`strict`].
+    pub strict: bool,
+
+    /// The issues found in the config file in `strict` mode, also in `warnings`.
+    lints: Vec<ConfigLint>,
 }
 
 impl ConfigContext {
@@ -108,6 +124,15 @@ impl ConfigContext {
     pub fn get_warnings(&self) -> &Vec<String> {
         &self.warnings
     }
+
+    pub fn add_lint(&mut self, lint: ConfigLint) {
+        self.warnings.push(lint.to_string());
+        self.lints.push(lint);
+    }
+
+    pub fn get_lints(&self) -> &[ConfigLint] {
+        &self.lints
+    }
 }
 
 /// <!--${internal}-->
@@ -0,0 +1,122 @@
+//! Shell-like `${NAME}` and `${NAME:-default}` in config files, so that one committed config can
+//! take the values that differ between developers (target, namespace, ...) from their env.
+
+use super::{ConfigError, Result};
+
+/// Replaces `${NAME}` in `text` with the value of the env variable `NAME`, as given by `var`, and
+/// `${NAME:-default}` with that value, or with `default` when the variable is unset or empty.
+/// `$${` is a literal `${`.
+///
+/// Fails when a variable without a default is unset, so that a missing value isn't noticed only
+/// when mirrord runs with an empty target or namespace.
+///
+/// The values are inserted as they are, e.g. quotes in them are not escaped in JSON strings.
+pub fn interpolate_env<F>(text: &str, var: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        let (before, expression) = rest.split_at(start);
+        let expression = expression.strip_prefix("${").unwrap_or(expression);
+
+        if let Some(before) = before.strip_suffix('$') {
+            output.push_str(before);
+            output.push_str("${");
+            rest = expression;
+            continue;
+        }
+        output.push_str(before);
+
+        let Some((expression, after)) = expression.split_once('}') else {
+            return Err(ConfigError::TemplateRenderingFailed(format!(
+                "`${{{}` is not closed with `}}`",
+                expression.lines().next().unwrap_or_default()
+            )));
+        };
+        rest = after;
+
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+
+        let valid_name = name
+            .chars()
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name
+                .chars()
+                .all(|char| char.is_ascii_alphanumeric() || char == '_');
+        if !valid_name {
+            return Err(ConfigError::TemplateRenderingFailed(format!(
+                "`${{{expression}}}` is not a valid env variable, expected `${{NAME}}` or \
+                 `${{NAME:-default}}`"
+            )));
+        }
+
+        match (var(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => output.push_str(default),
+            (Some(value), _) => output.push_str(&value),
+            (None, Some(default)) => output.push_str(default),
+            (None, None) => return Err(ConfigError::MissingEnvVar(name.to_string())),
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Option<String> {
+        match name {
+            "USER_NAMESPACE" => Some("alice".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn replaces_variables() {
+        let text = r#"{ "namespace": "${USER_NAMESPACE}", "target": "${TARGET:-deploy/app}" }"#;
+
+        assert_eq!(
+            interpolate_env(text, var).unwrap(),
+            r#"{ "namespace": "alice", "target": "deploy/app" }"#
+        );
+        assert_eq!(
+            interpolate_env("${EMPTY:-default}", var).unwrap(),
+            "default"
+        );
+        assert_eq!(interpolate_env("${EMPTY}", var).unwrap(), "");
+        assert_eq!(
+            interpolate_env("$${USER_NAMESPACE}", var).unwrap(),
+            "${USER_NAMESPACE}"
+        );
+        assert_eq!(
+            interpolate_env("$USER_NAMESPACE {}", var).unwrap(),
+            "$USER_NAMESPACE {}"
+        );
+    }
+
+    #[test]
+    fn invalid_interpolation() {
+        assert!(matches!(
+            interpolate_env("${TARGET}", var),
+            Err(ConfigError::MissingEnvVar(name)) if name == "TARGET"
+        ));
+        assert!(matches!(
+            interpolate_env("${TARGET", var),
+            Err(ConfigError::TemplateRenderingFailed(..))
+        ));
+        assert!(matches!(
+            interpolate_env("${not a name}", var),
+            Err(ConfigError::TemplateRenderingFailed(..))
+        ));
+    }
+}
@@ -0,0 +1,313 @@
+//! Strict checking of config files (`mirrord verify-config --strict`).
+//!
+//! Serde rejects most unknown fields, but not all of them (e.g. in the Kubernetes types of
+//! `agent.tolerations`), and when it does, the errors of the untagged enums don't say which field
+//! it was. Here the file is checked against the JSON schema of the config instead, so that a typo
+//! like `htttp_filter` is reported with its path.
+
+use std::{collections::BTreeSet, fmt};
+
+use schemars::schema::{RootSchema, Schema, SchemaObject, SingleOrVec};
+use serde_json::Value;
+
+/// Issue found by [`lint_config_file`], reported as a warning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigLint {
+    /// The field doesn't exist in the config, `suggestion` is a known field with a similar name.
+    UnknownField {
+        path: String,
+        suggestion: Option<String>,
+    },
+
+    /// The field is deprecated, `message` is its description.
+    Deprecated { path: String, message: String },
+}
+
+impl ConfigLint {
+    /// Stable identifier of this lint, used as a prefix in the warning message.
+    pub fn rule_id(&self) -> &'static str {
+        match self {
+            Self::UnknownField { .. } => "config/unknown-field",
+            Self::Deprecated { .. } => "config/deprecated",
+        }
+    }
+
+    /// Path of the field in the config file, e.g. `feature.network.incoming.htttp_filter`.
+    pub fn path(&self) -> &str {
+        match self {
+            Self::UnknownField { path, .. } | Self::Deprecated { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for ConfigLint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] ", self.rule_id())?;
+
+        match self {
+            Self::UnknownField {
+                path,
+                suggestion: Some(suggestion),
+            } => write!(
+                f,
+                "`{path}` is not a mirrord config field, did you mean `{suggestion}`?"
+            ),
+            Self::UnknownField {
+                path,
+                suggestion: None,
+            } => write!(f, "`{path}` is not a mirrord config field."),
+            Self::Deprecated { path, message } if message.is_empty() => {
+                write!(f, "`{path}` is deprecated.")
+            }
+            Self::Deprecated { path, message } => write!(f, "`{path}` is deprecated: {message}"),
+        }
+    }
+}
+
+/// Checks the `config` file (already parsed from JSON, TOML or YAML) against the `schema` of the
+/// config, and returns the unknown and deprecated fields.
+///
+/// Values of the wrong type are not reported, that's left to deserialization.
+pub fn lint_config_file(schema: &RootSchema, config: &Value) -> Vec<ConfigLint> {
+    let mut linter = Linter {
+        schema,
+        lints: Vec::new(),
+    };
+    let candidates = linter.resolve(&schema.schema);
+    linter.visit(&candidates, config, "");
+
+    linter.lints
+}
+
+struct Linter<'a> {
+    schema: &'a RootSchema,
+    lints: Vec<ConfigLint>,
+}
+
+impl<'a> Linter<'a> {
+    /// The schemas that `schema` stands for, following `$ref`s and the `anyOf`, `oneOf` and
+    /// `allOf` of the untagged enums and optional fields.
+    fn resolve(&self, schema: &'a SchemaObject) -> Vec<&'a SchemaObject> {
+        let mut resolved = Vec::new();
+
+        if let Some(definition) = schema
+            .reference
+            .as_deref()
+            .and_then(|reference| reference.strip_prefix("#/definitions/"))
+            .and_then(|name| self.schema.definitions.get(name))
+        {
+            resolved.extend(self.resolve_schema(definition));
+        }
+
+        if let Some(subschemas) = &schema.subschemas {
+            for subschema in [&subschemas.any_of, &subschemas.one_of, &subschemas.all_of]
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                resolved.extend(self.resolve_schema(subschema));
+            }
+        }
+
+        resolved.push(schema);
+        resolved
+    }
+
+    fn resolve_schema(&self, schema: &'a Schema) -> Vec<&'a SchemaObject> {
+        match schema {
+            Schema::Object(schema) => self.resolve(schema),
+            Schema::Bool(_) => Vec::new(),
+        }
+    }
+
+    fn visit(&mut self, candidates: &[&'a SchemaObject], value: &Value, path: &str) {
+        match value {
+            Value::Object(fields) => self.visit_object(candidates, fields, path),
+            Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    let item_candidates = candidates
+                        .iter()
+                        .copied()
+                        .filter_map(|candidate| candidate.array.as_ref()?.items.as_ref())
+                        .filter_map(|items| match items {
+                            SingleOrVec::Single(schema) => Some(schema.as_ref()),
+                            SingleOrVec::Vec(schemas) => schemas.get(index),
+                        })
+                        .flat_map(|schema| self.resolve_schema(schema))
+                        .collect::<Vec<_>>();
+
+                    self.visit(&item_candidates, item, &format!("{path}[{index}]"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_object(
+        &mut self,
+        candidates: &[&'a SchemaObject],
+        fields: &serde_json::Map<String, Value>,
+        path: &str,
+    ) {
+        let objects = candidates
+            .iter()
+            .copied()
+            .filter_map(|candidate| candidate.object.as_deref())
+            .collect::<Vec<_>>();
+        if objects.is_empty() {
+            return;
+        }
+
+        // Maps (e.g. `feature.env.override`) take any key.
+        let open = objects.iter().any(|object| {
+            object.properties.is_empty()
+                || matches!(
+                    object.additional_properties.as_deref(),
+                    Some(Schema::Object(_) | Schema::Bool(true))
+                )
+        });
+        let known = objects
+            .iter()
+            .flat_map(|object| object.properties.keys())
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>();
+
+        for (name, value) in fields {
+            let field_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{path}.{name}")
+            };
+
+            let field_schemas = objects
+                .iter()
+                .copied()
+                .filter_map(|object| {
+                    object
+                        .properties
+                        .get(name)
+                        .or(object.additional_properties.as_deref())
+                })
+                .flat_map(|schema| self.resolve_schema(schema))
+                .collect::<Vec<_>>();
+
+            if !open && !known.contains(name.as_str()) {
+                self.lints.push(ConfigLint::UnknownField {
+                    suggestion: closest(name, known.iter().copied()).map(|suggestion| {
+                        let parent = &field_path[..field_path.len() - name.len()];
+                        format!("{parent}{suggestion}")
+                    }),
+                    path: field_path,
+                });
+                continue;
+            }
+
+            if let Some(metadata) = field_schemas
+                .iter()
+                .filter_map(|schema| schema.metadata.as_deref())
+                .find(|metadata| metadata.deprecated)
+            {
+                self.lints.push(ConfigLint::Deprecated {
+                    path: field_path.clone(),
+                    message: metadata
+                        .description
+                        .as_deref()
+                        .and_then(|description| description.split("\n\n").next())
+                        .unwrap_or_default()
+                        .to_string(),
+                });
+            }
+
+            self.visit(&field_schemas, value, &field_path);
+        }
+    }
+}
+
+/// The name from `known` closest to `name`, if it's close enough to be a typo.
+fn closest<'a>(name: &str, known: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).clamp(1, 3);
+
+    known
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::LayerFileConfig;
+
+    #[test]
+    fn unknown_fields() {
+        let schema = schemars::schema_for!(LayerFileConfig);
+        let config = json!({
+            "target": "deploy/app",
+            "feature": {
+                "env": { "override": { "ANY_NAME": "value" } },
+                "network": {
+                    "incoming": {
+                        "mode": "steal",
+                        "htttp_filter": { "header_filter": "x-user: me" }
+                    }
+                }
+            },
+            "agent": {
+                "tolerations": [{ "operator": "Exists", "efect": "NoSchedule" }]
+            },
+            "bogus": true
+        });
+
+        assert_eq!(
+            lint_config_file(&schema, &config),
+            vec![
+                ConfigLint::UnknownField {
+                    path: "agent.tolerations[0].efect".to_string(),
+                    suggestion: Some("agent.tolerations[0].effect".to_string()),
+                },
+                ConfigLint::UnknownField {
+                    path: "bogus".to_string(),
+                    suggestion: None,
+                },
+                ConfigLint::UnknownField {
+                    path: "feature.network.incoming.htttp_filter".to_string(),
+                    suggestion: Some("feature.network.incoming.http_filter".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_config() {
+        let schema = schemars::schema_for!(LayerFileConfig);
+        let config = json!({
+            "target": { "path": "deploy/app", "namespace": "default" },
+            "feature": {
+                "network": { "incoming": "mirror", "outgoing": true },
+                "fs": "read"
+            }
+        });
+
+        assert!(lint_config_file(&schema, &config).is_empty());
+    }
+}
@@ -21,11 +21,86 @@ pub struct ExperimentalConfig {
     /// Enables the `readlink` hook.
     #[config(default = false)]
     pub readlink: bool,
+
+    /// ## _experimental_ resolve_remote_symlinks {#fexperimental-resolve_remote_symlinks}
+    ///
+    /// Resolves the symlinks of remote paths in the target before deciding whether they are read
+    /// locally or remotely (see `feature.fs`), and makes `realpath` return the resolved remote
+    /// path.
+    ///
+    /// Without it, a remote symlink to a path that is read locally (e.g. `/app/config` pointing to
+    /// `/tmp/config`) is still followed in the target. With it, the file is opened locally, on the
+    /// path the symlink points to, like it would be without mirrord in the target. Adds a request
+    /// to the agent for every remote file that is opened.
+    #[config(default = false)]
+    pub resolve_remote_symlinks: bool,
+
+    /// ## _experimental_ low_memory {#fexperimental-low_memory}
+    ///
+    /// Reduces the memory used by mirrord in the local process, at the cost of some performance.
+    ///
+    /// Remote files are read in smaller chunks, fewer remote DNS results are kept for resolving
+    /// outgoing connections locally, and the layer's file descriptor tables are shrunk when
+    /// descriptors are closed.
+    ///
+    /// Useful when running in memory constrained environments, e.g. small containers or CI
+    /// runners.
+    #[config(default = false)]
+    pub low_memory: bool,
+
+    /// ## _experimental_ disabled_hooks {#fexperimental-disabled_hooks}
+    ///
+    /// Names of the functions that the layer should not hook, e.g. `["statx", "sendmmsg"]`.
+    ///
+    /// Calls to these functions go straight to the original implementation, while the rest of the
+    /// feature they belong to keeps working. Useful to work around a hook that doesn't get along
+    /// with an unusual runtime.
+    pub disabled_hooks: Option<Vec<String>>,
+
+    /// ## _experimental_ hide_layer_threads {#fexperimental-hide_layer_threads}
+    ///
+    /// Keeps mirrord's own threads and file descriptors in the local process out of the way of
+    /// profilers and attach tools, e.g. `jcmd`, `jstack` or `async-profiler`.
+    ///
+    /// mirrord's threads are always named with a `mirrord-` prefix, so they can be filtered out
+    /// by name. With this option they also block all signals, so signals sent to the process
+    /// (like the `SIGQUIT` of a JVM attach, or a profiler's `SIGPROF`) are handled by the
+    /// application's threads. The connection to the internal proxy is moved to file descriptor
+    /// 900 or above, away from the application's descriptors.
+    ///
+    /// The JVM attach files (`/tmp/.attach_pid<pid>` and `/tmp/.java_pid<pid>`) are always
+    /// local, as mirrord reads and writes `/tmp` locally by default. Attaching works as long as
+    /// your `feature.fs` config doesn't make `/tmp` remote.
+    #[config(default = false)]
+    pub hide_layer_threads: bool,
+
+    /// ## _experimental_ enforce_target_limits {#fexperimental-enforce_target_limits}
+    ///
+    /// Runs the local process with the CPU and memory limits of the target container (its
+    /// requests, when it has no limits), so performance investigations under mirrord are closer
+    /// to how the application behaves in the cluster.
+    ///
+    /// Linux only, the process is placed in a transient systemd scope (a cgroup) of the user's
+    /// systemd instance. When that's not possible, or the target has no limits, mirrord warns and
+    /// runs the process without them. Only applies to `mirrord exec`.
+    #[config(default = false)]
+    pub enforce_target_limits: bool,
 }
 
 impl CollectAnalytics for &ExperimentalConfig {
     fn collect_analytics(&self, analytics: &mut mirrord_analytics::Analytics) {
         analytics.add("tcp_ping4_mock", self.tcp_ping4_mock);
         analytics.add("readlink", self.readlink);
+        analytics.add("resolve_remote_symlinks", self.resolve_remote_symlinks);
+        analytics.add("low_memory", self.low_memory);
+        analytics.add("hide_layer_threads", self.hide_layer_threads);
+        analytics.add("enforce_target_limits", self.enforce_target_limits);
+        analytics.add(
+            "disabled_hooks",
+            self.disabled_hooks
+                .as_ref()
+                .map(Vec::len)
+                .unwrap_or_default(),
+        );
     }
 }
@@ -0,0 +1,68 @@
+use std::{collections::HashMap, net::IpAddr, path::PathBuf};
+
+use mirrord_analytics::CollectAnalytics;
+use mirrord_config_derive::MirrordConfig;
+use schemars::JsonSchema;
+
+use crate::config::source::MirrordConfigSource;
+
+/// <!--${internal}-->
+/// Set by the CLI for the layer when the session runs with the [`FallbackConfig`].
+pub const FALLBACK_ACTIVE_ENV: &str = "MIRRORD_FALLBACK_ACTIVE";
+
+/// Local substitutes for the cluster, used when mirrord can't reach it (e.g. with no network, or
+/// when the cluster is down), so that the application still starts, against local services like
+/// a `docker-compose` setup.
+///
+/// When the cluster or the agent can't be reached, `mirrord exec` warns and runs the application
+/// locally instead of failing: files, connections and incoming traffic stay local, the
+/// environment variables come from [`fallback.env_file`](#fallback-env_file), and the host names
+/// in [`fallback.hosts`](#fallback-hosts) resolve to the given addresses.
+///
+/// Point the URLs of the remote services at the local ones in the env file.
+///
+/// ```json
+/// {
+///   "fallback": {
+///     "env_file": ".mirrord/offline.env",
+///     "hosts": {
+///       "postgres.db.svc.cluster.local": "127.0.0.1",
+///       "redis": "127.0.0.1"
+///     }
+///   }
+/// }
+/// ```
+#[derive(MirrordConfig, Clone, Debug, Default)]
+#[config(map_to = "FallbackFileConfig", derive = "JsonSchema")]
+#[cfg_attr(test, config(derive = "PartialEq, Eq"))]
+pub struct FallbackConfig {
+    /// ### fallback.env_file {#fallback-env_file}
+    ///
+    /// `.env` file with the environment variables of the application, instead of the ones of the
+    /// target. One `KEY=value` per line, the values can be quoted.
+    #[config(env = "MIRRORD_FALLBACK_ENV_FILE")]
+    pub env_file: Option<PathBuf>,
+
+    /// ### fallback.hosts {#fallback-hosts}
+    ///
+    /// Host names resolved to the given addresses, like entries of `/etc/hosts`. Other names are
+    /// resolved locally.
+    pub hosts: Option<HashMap<String, IpAddr>>,
+}
+
+impl FallbackConfig {
+    /// Whether any substitute is set, mirrord falls back to them only then.
+    pub fn is_set(&self) -> bool {
+        self.env_file.is_some() || self.hosts.is_some()
+    }
+}
+
+impl CollectAnalytics for &FallbackConfig {
+    fn collect_analytics(&self, analytics: &mut mirrord_analytics::Analytics) {
+        analytics.add("env_file", self.env_file.is_some());
+        analytics.add(
+            "hosts",
+            self.hosts.as_ref().map(HashMap::len).unwrap_or_default(),
+        );
+    }
+}
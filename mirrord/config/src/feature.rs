@@ -95,6 +95,38 @@ pub struct FeatureConfig {
     /// Should mirrord return the hostname of the target pod when calling `gethostname`
     #[config(default = true)]
     pub hostname: bool,
+
+    /// ## feature.target_logs {#feature-target_logs}
+    ///
+    /// Streams the logs of the target container into your terminal while your application runs
+    /// with `mirrord exec`, each line prefixed with the pod and container names. Only the logs
+    /// written after the session starts are shown.
+    ///
+    /// Requires a target, and can't be used with `feature.copy_target` or
+    /// `target.wait_for_run`.
+    ///
+    /// Defaults to `false`.
+    #[config(env = "MIRRORD_TARGET_LOGS", default = false)]
+    pub target_logs: bool,
+
+    /// ## feature.pause_autoscaling {#feature-pause_autoscaling}
+    ///
+    /// Stops the autoscalers of the target (HorizontalPodAutoscalers and KEDA ScaledObjects) from
+    /// scaling it while the session runs, so your session isn't disrupted when stealing traffic
+    /// takes load off the target and its pods are scaled down.
+    ///
+    /// The autoscalers are pinned to the current number of replicas, marked with the
+    /// `mirrord.metalbear.co/paused-autoscaling` annotation, and restored when the session
+    /// ends. Autoscalers that are already paused (e.g. by another session) are left alone. If the
+    /// session is killed before it can restore them, the annotation holds their original
+    /// settings.
+    ///
+    /// Only applies to deployment, rollout and stateful set targets, and requires permission to
+    /// patch their autoscalers. Can't be used with `feature.copy_target`.
+    ///
+    /// Defaults to `false`.
+    #[config(env = "MIRRORD_PAUSE_AUTOSCALING", default = false, unstable)]
+    pub pause_autoscaling: bool,
 }
 
 impl CollectAnalytics for &FeatureConfig {
@@ -104,5 +136,7 @@ impl CollectAnalytics for &FeatureConfig {
         analytics.add("network", &self.network);
         analytics.add("copy_target", &self.copy_target);
         analytics.add("hostname", self.hostname);
+        analytics.add("target_logs", self.target_logs);
+        analytics.add("pause_autoscaling", self.pause_autoscaling);
     }
 }
@@ -4,9 +4,8 @@
 //! 1. [`FsUserConfig::Simple`]: controls only the option for enabling read-only, read-write,
 //! or disable file operations;
 //!
-//! 2. [`FsUserConfig::Advanced`]: All of the above, plus allows setting up
-//! `mirrord_layer::file::filter::FileFilter` to control which files should be opened
-//! locally or remotely.
+//! 2. [`FsUserConfig::Advanced`]: All of the above, plus allows setting up the
+//! [`filter::FsFilter`] to control which files should be opened locally or remotely.
 use schemars::JsonSchema;
 use serde::Deserialize;
 
@@ -19,6 +18,7 @@ use crate::{
 };
 
 pub mod advanced;
+pub mod filter;
 pub mod mode;
 
 /// ## feature.fs {#fs}
@@ -90,6 +90,14 @@ impl MirrordConfig for FsUserConfig {
                     .source_value(context)
                     .transpose()?,
                 not_found: None,
+                rules: None,
+                cwd: FromEnv::new("MIRRORD_FILE_CWD")
+                    .source_value(context)
+                    .transpose()?,
+                image_paths: None,
+                local_override: None,
+                mapping: None,
+                shared_scratch: None,
             },
             FsUserConfig::Advanced(advanced) => advanced.generate_config(context)?,
         };
@@ -110,6 +118,9 @@ impl MirrordToggleableConfig for FsUserConfig {
         let local = FromEnv::new("MIRRORD_FILE_LOCAL_PATTERN")
             .source_value(context)
             .transpose()?;
+        let cwd = FromEnv::new("MIRRORD_FILE_CWD")
+            .source_value(context)
+            .transpose()?;
 
         Ok(FsConfig {
             mode,
@@ -117,6 +128,12 @@ impl MirrordToggleableConfig for FsUserConfig {
             read_only,
             local,
             not_found: None,
+            rules: None,
+            cwd,
+            image_paths: None,
+            local_override: None,
+            mapping: None,
+            shared_scratch: None,
         })
     }
 }
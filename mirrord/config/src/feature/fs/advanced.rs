@@ -1,6 +1,12 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
 use mirrord_analytics::{AnalyticValue, CollectAnalytics};
 use mirrord_config_derive::MirrordConfig;
 use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use super::{FsModeConfig, FsUserConfig};
 use crate::{
@@ -32,30 +38,34 @@ use crate::{
 /// 4. `"not_found"` - List of patters that should never be read nor written. These files should be
 /// treated as non-existent.
 ///
+/// For full control over the order, use [`rules`](#feature-fs-rules) instead: an ordered list of
+/// patterns where the first match wins.
+///
 /// The logic for choosing the behavior is as follows:
 ///
-/// 1. Check if one of the patterns match the file path, do the corresponding action. There's
-/// no specified order if two lists match the same path, we will use the first one (and we
-/// do not guarantee what is first).
+/// 1. Check if one of the [`rules`](#feature-fs-rules) matches the file path, the first one that
+/// matches decides.
 ///
-///     **Warning**: Specifying the same path in two lists is unsupported and can lead to undefined
-///     behaviour.
+/// 2. Check if one of the patterns match the file path, do the corresponding action. The lists are
+/// checked in this order: `not_found`, `read_write`, `read_only`, `local`.
 ///
-/// 2. There are pre-defined exceptions to the set FS mode.
-///     1. Paths that match [the patterns defined here](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/layer/src/file/filter/read_local_by_default.rs)
+/// 3. There are pre-defined exceptions to the set FS mode.
+///     1. Paths that match [the patterns defined here](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/config/src/feature/fs/filter/read_local_by_default.rs)
 ///        are read locally by default.
-///     2. Paths that match [the patterns defined here](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/layer/src/file/filter/read_remote_by_default.rs)
+///     2. Paths that match [the patterns defined here](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/config/src/feature/fs/filter/read_remote_by_default.rs)
 ///        are read remotely by default when the mode is `localwithoverrides`.
-///     3. Paths that match [the patterns defined here](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/layer/src/file/filter/not_found_by_default.rs)
+///     3. Paths that match [the patterns defined here](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/config/src/feature/fs/filter/not_found_by_default.rs)
 ///        under the running user's home directory will not be found by the application when the
 ///        mode is not `local`.
 ///
 ///     In order to override that default setting for a path, or a pattern, include it the
 ///     appropriate pattern set from above. E.g. in order to read files under `/etc/` remotely even
-///     though it is covered by [the set of patterns that are read locally by default](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/layer/src/file/filter/read_local_by_default.rs),
+///     though it is covered by [the set of patterns that are read locally by default](https://github.com/metalbear-co/mirrord/tree/latest/mirrord/config/src/feature/fs/filter/read_local_by_default.rs),
 ///     add `"^/etc/."` to the `read_only` set.
 ///
-/// 3. If none of the above match, use the default behavior (mode).
+/// 4. If none of the above match, use the default behavior (mode).
+///
+/// Use `mirrord session why fs <path>` to see which of these decides for a given path.
 ///
 /// For more information, check the file operations
 /// [technical reference](https://mirrord.dev/docs/reference/fileops/).
@@ -106,6 +116,217 @@ pub struct FsConfig {
     ///
     /// Specify file path patterns that if matched will be treated as non-existent.
     pub not_found: Option<VecOrSingle<String>>,
+
+    /// ### feature.fs.rules {#feature-fs-rules}
+    ///
+    /// Ordered list of rules, each with a file path `pattern` (case insensitive regex) and the
+    /// `access` for the matching paths. The first rule that matches the path wins, and rules
+    /// take precedence over all the other pattern lists.
+    ///
+    /// - `"read"`: read from the remote, writes are local;
+    /// - `"write"`: read and write from the remote;
+    /// - `"deny"`: the file is treated as non-existent;
+    /// - `"local"`: read and write locally.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "fs": {
+    ///       "mode": "read",
+    ///       "rules": [
+    ///         { "pattern": "^/app/config/secrets", "access": "deny" },
+    ///         { "pattern": "^/app/config", "access": "write" },
+    ///         { "pattern": "\\.js$", "access": "local" }
+    ///       ]
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    pub rules: Option<Vec<FsRule>>,
+
+    /// ### feature.fs.cwd {#feature-fs-cwd}
+    ///
+    /// Working directory used to resolve relative paths in file operations.
+    ///
+    /// By default, operations on relative paths are always done locally. When this is set,
+    /// relative paths are resolved against the given directory and then handled like any other
+    /// absolute path, and `getcwd` returns this directory.
+    ///
+    /// - `"remote"`: use the working directory of the target container (e.g. `/app`);
+    /// - an absolute path: use this directory, e.g. `"/app"`.
+    ///
+    /// Note that this doesn't change the real working directory of the local process, and
+    /// `chdir` calls are not tracked.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "fs": {
+    ///       "mode": "read",
+    ///       "cwd": "remote"
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    #[config(env = "MIRRORD_FILE_CWD")]
+    pub cwd: Option<String>,
+
+    /// ### feature.fs.image_paths {#feature-fs-image_paths}
+    ///
+    /// Specify absolute path prefixes that should be read from the target container's image.
+    ///
+    /// Useful for binaries that hardcode paths to assets baked into the image, e.g.
+    /// `/usr/share/app/assets`. Paths under these prefixes are resolved against the container's
+    /// root filesystem as built from the image, without any of the volumes mounted on top of it,
+    /// and are always read remotely, regardless of the other fs settings.
+    ///
+    /// Files under these prefixes can only be opened for reading and stat'd. Opening them for
+    /// writing is done locally.
+    ///
+    /// Requires an agent that supports reading from the image, otherwise opening these files
+    /// fails.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "fs": {
+    ///       "mode": "localwithoverrides",
+    ///       "image_paths": ["/usr/share/app/assets"]
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    pub image_paths: Option<VecOrSingle<String>>,
+
+    /// ### feature.fs.local_override {#feature-fs-local_override}
+    ///
+    /// Map absolute remote path prefixes to local directories. Files under these prefixes are
+    /// served from the local directory instead, while everything else keeps following the other
+    /// fs settings.
+    ///
+    /// Useful for working on a few files (e.g. configuration or templates) that the application
+    /// reads from a fixed path in the target. With the example below, opening
+    /// `/etc/app/config.yaml` opens `/home/me/app/config/config.yaml` on the local machine.
+    ///
+    /// When the prefixes overlap, the longest one wins.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "fs": {
+    ///       "mode": "read",
+    ///       "local_override": {
+    ///         "/etc/app": "/home/me/app/config"
+    ///       }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    pub local_override: Option<HashMap<String, String>>,
+
+    /// ### feature.fs.mapping {#feature-fs-mapping}
+    ///
+    /// Rewrite the paths of remote file operations before they are sent to the target, with a
+    /// map of regex patterns to their replacements.
+    ///
+    /// Useful when the application uses different paths locally than in the target. With the
+    /// example below, opening `/var/lib/app/db/users.json` opens `/data/db/users.json` in the
+    /// target. The replacement can use the capture groups of the pattern, e.g. `$1`.
+    ///
+    /// Paths are rewritten before the other fs settings are checked, so they apply to the
+    /// rewritten path. Operations done locally use the original path.
+    ///
+    /// Each path is rewritten by at most one pattern, so the patterns should not overlap.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "fs": {
+    ///       "mode": "read",
+    ///       "mapping": {
+    ///         "^/var/lib/app": "/data"
+    ///       }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    pub mapping: Option<BTreeMap<String, String>>,
+
+    /// ### feature.fs.shared_scratch {#feature-fs-shared_scratch}
+    ///
+    /// Absolute path of a directory in the target that is kept in sync with a local directory,
+    /// in both directions, for as long as the session runs.
+    ///
+    /// Useful when the application exchanges files continuously with a sidecar of the target.
+    /// mirrord creates a fresh local directory for the session (its path is printed when the
+    /// session starts), and the application's file operations under this path go to it. Files
+    /// written locally, by the application or by anyone else, show up in the target, and files
+    /// written in the target show up locally. When a file is changed on both sides at once, the
+    /// last change wins.
+    ///
+    /// The directory is created in the target if it doesn't exist. Files over 64MiB are not
+    /// synchronized.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "fs": {
+    ///       "mode": "read",
+    ///       "shared_scratch": "/shared"
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    pub shared_scratch: Option<String>,
+}
+
+/// Environment variable with the local directory of [`FsConfig::shared_scratch`], set by the CLI
+/// for the internal proxy and the layer.
+pub const SHARED_SCRATCH_DIR_ENV: &str = "MIRRORD_SHARED_SCRATCH_DIR";
+
+/// <!--${internal}-->
+/// Rule from [`FsConfig::rules`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FsRule {
+    /// File path pattern, case insensitive regex.
+    pub pattern: String,
+
+    /// What mirrord does with the matching paths.
+    pub access: FsRuleAccess,
+}
+
+/// <!--${internal}-->
+/// What mirrord does with the paths matching an [`FsRule`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+pub enum FsRuleAccess {
+    /// <!--${internal}-->
+    /// Read from the remote, writes are local.
+    Read,
+
+    /// <!--${internal}-->
+    /// Read and write from the remote.
+    Write,
+
+    /// <!--${internal}-->
+    /// The file is treated as non-existent.
+    Deny,
+
+    /// <!--${internal}-->
+    /// Read and write locally.
+    Local,
+}
+
+impl fmt::Display for FsRuleAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Deny => "deny",
+            Self::Local => "local",
+        })
+    }
 }
 
 impl MirrordToggleableConfig for AdvancedFsUserConfig {
@@ -120,6 +341,9 @@ impl MirrordToggleableConfig for AdvancedFsUserConfig {
         let local = FromEnv::new("MIRRORD_FILE_LOCAL_PATTERN")
             .source_value(context)
             .transpose()?;
+        let cwd = FromEnv::new("MIRRORD_FILE_CWD")
+            .source_value(context)
+            .transpose()?;
 
         Ok(Self::Generated {
             mode,
@@ -127,11 +351,20 @@ impl MirrordToggleableConfig for AdvancedFsUserConfig {
             read_only,
             local,
             not_found: None,
+            rules: None,
+            cwd,
+            image_paths: None,
+            local_override: None,
+            mapping: None,
+            shared_scratch: None,
         })
     }
 }
 
 impl FsConfig {
+    /// Value of [`FsConfig::cwd`] that selects the target container's working directory.
+    pub const REMOTE_CWD: &'static str = "remote";
+
     pub fn is_read(&self) -> bool {
         self.mode.is_read()
     }
@@ -144,6 +377,12 @@ impl FsConfig {
     pub fn is_active(&self) -> bool {
         !matches!(self.mode, FsModeConfig::Local)
     }
+
+    /// Whether relative paths should be resolved against the target container's working
+    /// directory, see [`FsConfig::cwd`].
+    pub fn is_remote_cwd(&self) -> bool {
+        self.cwd.as_deref() == Some(Self::REMOTE_CWD)
+    }
 }
 
 impl From<FsModeConfig> for AnalyticValue {
@@ -178,6 +417,30 @@ impl CollectAnalytics for &FsConfig {
                 .map(<[_]>::len)
                 .unwrap_or_default(),
         );
+        analytics.add(
+            "rules",
+            self.rules.as_deref().map(<[_]>::len).unwrap_or_default(),
+        );
+        analytics.add("cwd", self.cwd.is_some());
+        analytics.add(
+            "image_paths",
+            self.image_paths
+                .as_deref()
+                .map(<[_]>::len)
+                .unwrap_or_default(),
+        );
+        analytics.add(
+            "local_override",
+            self.local_override
+                .as_ref()
+                .map(HashMap::len)
+                .unwrap_or_default(),
+        );
+        analytics.add(
+            "mapping",
+            self.mapping.as_ref().map(BTreeMap::len).unwrap_or_default(),
+        );
+        analytics.add("shared_scratch", self.shared_scratch.is_some());
         analytics.add(
             "not_found_paths",
             self.not_found
@@ -0,0 +1,569 @@
+//! Decides what mirrord does with file operations on a given path, based on the [`FsConfig`].
+//!
+//! Used by the layer to filter file operations, and by the CLI to explain the decision for a path
+//! (`mirrord session why fs <path>`).
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+};
+
+use regex::{Regex, RegexSet, RegexSetBuilder};
+
+use super::{FsConfig, FsModeConfig, FsRule, FsRuleAccess};
+use crate::util::VecOrSingle;
+
+mod not_found_by_default;
+mod read_local_by_default;
+mod read_remote_by_default;
+
+/// List of files that mirrord should use locally, as they probably exist only in the local user
+/// machine, or are system configuration files (that could break the process if we used the remote
+/// version).
+///
+/// You most likely do **NOT** want to include any of these, but if have a reason to do so, then
+/// setting any of the overrides - `MIRRORD_FILE_X_PATTERN` allows you to override this list.
+fn generate_local_set() -> RegexSet {
+    // To handle the problem of injecting `open` and friends into project runners (like in a call to
+    // `node app.js`, or `cargo run app`), we're ignoring files from the current working directory.
+    read_local_by_default::regex_set_builder()
+        .case_insensitive(true)
+        .build()
+        .expect("Building local path regex set failed")
+}
+
+/// List of files that mirrord should use remotely read only
+fn generate_remote_ro_set() -> RegexSet {
+    let patterns = read_remote_by_default::PATHS;
+    RegexSetBuilder::new(patterns)
+        .case_insensitive(true)
+        .build()
+        .expect("Building remote readonly path regex set failed")
+}
+
+fn generate_not_found_set() -> RegexSet {
+    let Ok(home) = env::var("HOME") else {
+        tracing::warn!("Unable to resolve $HOME directory, generating empty not-found set");
+        return Default::default();
+    };
+
+    let home_clean = regex::escape(home.trim_end_matches('/'));
+
+    let patterns = not_found_by_default::PATHS
+        .into_iter()
+        .map(|cloud_dir| format!("^{home_clean}/{cloud_dir}"));
+
+    RegexSetBuilder::new(patterns)
+        .case_insensitive(true)
+        .build()
+        .expect("Building not found path regex set failed")
+}
+
+/// What mirrord does with a file operation, see [`FsFilter::decide`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsAction {
+    /// The operation is done in the target.
+    Remote,
+    /// The operation is done in the target, on the file from the container image, see
+    /// [`FsConfig::image_paths`].
+    RemoteImage,
+    /// The operation is done locally.
+    Local,
+    /// The file is treated as non-existent.
+    NotFound,
+}
+
+impl fmt::Display for FsAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Remote => "remote",
+            Self::RemoteImage => "remote (container image)",
+            Self::Local => "local",
+            Self::NotFound => "not found",
+        })
+    }
+}
+
+/// Why [`FsFilter::decide`] picked an [`FsAction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsReason {
+    /// [`FsConfig::mode`] is [`FsModeConfig::Local`], so all operations are local.
+    LocalMode,
+    /// The path is under the prefix from [`FsConfig::image_paths`].
+    ImagePath(PathBuf),
+    /// The path is under the prefix from [`FsConfig::local_override`], so it's served from the
+    /// `local` path.
+    LocalOverride { prefix: PathBuf, local: PathBuf },
+    /// The first matching rule from [`FsConfig::rules`].
+    Rule { index: usize, rule: FsRule },
+    /// Pattern from [`FsConfig::not_found`].
+    NotFound(String),
+    /// Pattern from [`FsConfig::read_write`].
+    ReadWrite(String),
+    /// Pattern from [`FsConfig::read_only`].
+    ReadOnly(String),
+    /// Pattern from [`FsConfig::local`].
+    Local(String),
+    /// Pattern that is not found by default.
+    DefaultNotFound(String),
+    /// Pattern that is read remotely by default.
+    DefaultRemoteReadOnly(String),
+    /// Pattern that is read locally by default.
+    DefaultLocal(String),
+    /// Nothing matched, so [`FsConfig::mode`] decides.
+    Mode(FsModeConfig),
+}
+
+impl fmt::Display for FsReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LocalMode => write!(f, "feature.fs.mode is \"local\""),
+            Self::ImagePath(prefix) => write!(
+                f,
+                "path is under {} from feature.fs.image_paths",
+                prefix.display()
+            ),
+            Self::LocalOverride { prefix, local } => write!(
+                f,
+                "path is under {} from feature.fs.local_override, served from {}",
+                prefix.display(),
+                local.display()
+            ),
+            Self::Rule { index, rule } => write!(
+                f,
+                "matched feature.fs.rules[{index}] (pattern {:?}, access \"{}\")",
+                rule.pattern, rule.access
+            ),
+            Self::NotFound(pattern) => {
+                write!(f, "matched pattern {pattern:?} from feature.fs.not_found")
+            }
+            Self::ReadWrite(pattern) => {
+                write!(f, "matched pattern {pattern:?} from feature.fs.read_write")
+            }
+            Self::ReadOnly(pattern) => {
+                write!(f, "matched pattern {pattern:?} from feature.fs.read_only")
+            }
+            Self::Local(pattern) => write!(f, "matched pattern {pattern:?} from feature.fs.local"),
+            Self::DefaultNotFound(pattern) => {
+                write!(
+                    f,
+                    "matched pattern {pattern:?} that is not found by default"
+                )
+            }
+            Self::DefaultRemoteReadOnly(pattern) => {
+                write!(
+                    f,
+                    "matched pattern {pattern:?} that is read remotely by default"
+                )
+            }
+            Self::DefaultLocal(pattern) => {
+                write!(
+                    f,
+                    "matched pattern {pattern:?} that is read locally by default"
+                )
+            }
+            Self::Mode(mode) => write!(
+                f,
+                "nothing else matched, so feature.fs.mode \"{mode}\" decides"
+            ),
+        }
+    }
+}
+
+/// Result of [`FsFilter::decide`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsDecision {
+    pub action: FsAction,
+    pub reason: FsReason,
+}
+
+/// Regex patterns and the [`RegexSet`] built from them, so we can tell which pattern matched.
+#[derive(Debug, Default)]
+struct PatternSet {
+    patterns: Vec<String>,
+    set: RegexSet,
+}
+
+impl PatternSet {
+    fn new<I, S>(patterns: I) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let patterns = patterns.into_iter().map(Into::into).collect::<Vec<_>>();
+        let set = RegexSetBuilder::new(&patterns)
+            .case_insensitive(true)
+            .build()?;
+
+        Ok(Self { patterns, set })
+    }
+
+    fn from_config(patterns: Option<&VecOrSingle<String>>) -> Result<Self, regex::Error> {
+        Self::new(
+            patterns
+                .map(|patterns| patterns.to_vec())
+                .unwrap_or_default(),
+        )
+    }
+
+    fn from_set(set: RegexSet) -> Self {
+        Self {
+            patterns: set.patterns().to_vec(),
+            set,
+        }
+    }
+
+    /// Returns the index of the first pattern that matches `text`.
+    fn first_match(&self, text: &str) -> Option<usize> {
+        self.set.matches(text).into_iter().next()
+    }
+
+    /// Returns the first pattern that matches `text`.
+    fn matching(&self, text: &str) -> Option<String> {
+        self.first_match(text)
+            .and_then(|index| self.patterns.get(index))
+            .cloned()
+    }
+}
+
+/// Compiled [`FsConfig`], decides what mirrord does with file operations on a given path.
+#[derive(Debug)]
+pub struct FsFilter {
+    rules: PatternSet,
+    rule_list: Vec<FsRule>,
+    read_only: PatternSet,
+    read_write: PatternSet,
+    local: PatternSet,
+    not_found: PatternSet,
+    default_local: PatternSet,
+    default_remote_ro: PatternSet,
+    default_not_found: PatternSet,
+    image_paths: Vec<PathBuf>,
+    /// From [`FsConfig::local_override`], longest remote prefix first.
+    local_overrides: Vec<(PathBuf, PathBuf)>,
+    /// Patterns and replacements from [`FsConfig::mapping`].
+    mapping: Vec<(Regex, String)>,
+    mode: FsModeConfig,
+}
+
+impl FsFilter {
+    /// Compiles the patterns from the [`FsConfig`], together with the default ones.
+    pub fn new(fs_config: &FsConfig) -> Result<Self, regex::Error> {
+        let rule_list = fs_config.rules.clone().unwrap_or_default();
+        let rules = PatternSet::new(rule_list.iter().map(|rule| rule.pattern.clone()))?;
+
+        let image_paths = fs_config
+            .image_paths
+            .as_deref()
+            .map(|paths| paths.iter().map(PathBuf::from).collect())
+            .unwrap_or_default();
+
+        let mut local_overrides = fs_config
+            .local_override
+            .iter()
+            .flatten()
+            .map(|(remote, local)| (PathBuf::from(remote), PathBuf::from(local)))
+            .collect::<Vec<_>>();
+        local_overrides
+            .sort_by(|(a, _), (b, _)| b.components().count().cmp(&a.components().count()));
+
+        let mapping = fs_config
+            .mapping
+            .iter()
+            .flatten()
+            .map(|(pattern, replacement)| Ok((Regex::new(pattern)?, replacement.clone())))
+            .collect::<Result<Vec<_>, regex::Error>>()?;
+
+        Ok(Self {
+            rules,
+            rule_list,
+            read_only: PatternSet::from_config(fs_config.read_only.as_ref())?,
+            read_write: PatternSet::from_config(fs_config.read_write.as_ref())?,
+            local: PatternSet::from_config(fs_config.local.as_ref())?,
+            not_found: PatternSet::from_config(fs_config.not_found.as_ref())?,
+            default_local: PatternSet::from_set(generate_local_set()),
+            default_remote_ro: PatternSet::from_set(generate_remote_ro_set()),
+            default_not_found: PatternSet::from_set(generate_not_found_set()),
+            image_paths,
+            local_overrides,
+            mapping,
+            mode: fs_config.mode,
+        })
+    }
+
+    /// Serves the files under the `remote` prefix from the `local` directory, just like the
+    /// [`FsConfig::local_override`] prefixes, e.g. for [`FsConfig::shared_scratch`].
+    pub fn add_local_override(&mut self, remote: PathBuf, local: PathBuf) {
+        self.local_overrides.push((remote, local));
+        self.local_overrides
+            .sort_by(|(a, _), (b, _)| b.components().count().cmp(&a.components().count()));
+    }
+
+    /// Checks if `path` is under one of the [`FsConfig::image_paths`], meaning it should be read
+    /// from the target container's image, regardless of the other settings.
+    pub fn is_image_path(&self, path: &Path) -> bool {
+        self.image_path_prefix(path).is_some()
+    }
+
+    fn image_path_prefix(&self, path: &Path) -> Option<&PathBuf> {
+        if self.mode == FsModeConfig::Local {
+            return None;
+        }
+
+        self.image_paths
+            .iter()
+            .find(|prefix| path.starts_with(prefix))
+    }
+
+    /// Rewrites `path` with the first matching pattern from [`FsConfig::mapping`], returns
+    /// [`None`] when no pattern matches.
+    ///
+    /// The rewritten path is the one to use in [`FsFilter::decide`].
+    pub fn remap(&self, path: &Path) -> Option<PathBuf> {
+        let path = path.to_str()?;
+
+        self.mapping
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(path))
+            .map(|(pattern, replacement)| {
+                PathBuf::from(pattern.replace(path, replacement.as_str()).into_owned())
+            })
+    }
+
+    /// Returns the local path that serves `path`, when it's under one of the
+    /// [`FsConfig::local_override`] prefixes.
+    pub fn local_override(&self, path: &Path) -> Option<PathBuf> {
+        self.local_override_prefix(path)
+            .and_then(|(prefix, local)| Some(local.join(path.strip_prefix(prefix).ok()?)))
+    }
+
+    fn local_override_prefix(&self, path: &Path) -> Option<&(PathBuf, PathBuf)> {
+        if self.mode == FsModeConfig::Local {
+            return None;
+        }
+
+        self.local_overrides
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+    }
+
+    /// Decides what to do with a file operation on `path`, `write` stating whether the file is
+    /// accessed for writing.
+    ///
+    /// The order is:
+    ///
+    /// 1. [`FsConfig::mode`] `"local"`, everything is local;
+    /// 2. [`FsConfig::image_paths`];
+    /// 3. [`FsConfig::local_override`], always local;
+    /// 4. [`FsConfig::rules`], first match wins;
+    /// 5. [`FsConfig::not_found`], [`FsConfig::read_write`], [`FsConfig::read_only`],
+    ///    [`FsConfig::local`];
+    /// 6. the default patterns;
+    /// 7. [`FsConfig::mode`].
+    pub fn decide(&self, path: &str, write: bool) -> FsDecision {
+        let decision = |action, reason| FsDecision { action, reason };
+        // Paths that are only readable remotely are local when written.
+        let read_only = |reason| {
+            if write {
+                decision(FsAction::Local, reason)
+            } else {
+                decision(FsAction::Remote, reason)
+            }
+        };
+
+        if self.mode == FsModeConfig::Local {
+            return decision(FsAction::Local, FsReason::LocalMode);
+        }
+
+        if let Some(prefix) = self.image_path_prefix(Path::new(path)) {
+            let action = if write {
+                FsAction::Local
+            } else {
+                FsAction::RemoteImage
+            };
+            return decision(action, FsReason::ImagePath(prefix.clone()));
+        }
+
+        if let Some((prefix, local)) = self.local_override_prefix(Path::new(path)) {
+            return decision(
+                FsAction::Local,
+                FsReason::LocalOverride {
+                    prefix: prefix.clone(),
+                    local: local.clone(),
+                },
+            );
+        }
+
+        if let Some((index, rule)) = self
+            .rules
+            .first_match(path)
+            .and_then(|index| self.rule_list.get(index).map(|rule| (index, rule.clone())))
+        {
+            let access = rule.access;
+            let reason = FsReason::Rule { index, rule };
+
+            return match access {
+                FsRuleAccess::Read => read_only(reason),
+                FsRuleAccess::Write => decision(FsAction::Remote, reason),
+                FsRuleAccess::Deny => decision(FsAction::NotFound, reason),
+                FsRuleAccess::Local => decision(FsAction::Local, reason),
+            };
+        }
+
+        if let Some(pattern) = self.not_found.matching(path) {
+            decision(FsAction::NotFound, FsReason::NotFound(pattern))
+        } else if let Some(pattern) = self.read_write.matching(path) {
+            decision(FsAction::Remote, FsReason::ReadWrite(pattern))
+        } else if let Some(pattern) = self.read_only.matching(path) {
+            read_only(FsReason::ReadOnly(pattern))
+        } else if let Some(pattern) = self.local.matching(path) {
+            decision(FsAction::Local, FsReason::Local(pattern))
+        } else if let Some(pattern) = self.default_not_found.matching(path) {
+            decision(FsAction::NotFound, FsReason::DefaultNotFound(pattern))
+        } else if let Some(pattern) = self.default_remote_ro.matching(path).filter(|_| !write) {
+            decision(FsAction::Remote, FsReason::DefaultRemoteReadOnly(pattern))
+        } else if let Some(pattern) = self.default_local.matching(path) {
+            decision(FsAction::Local, FsReason::DefaultLocal(pattern))
+        } else {
+            let reason = FsReason::Mode(self.mode);
+            match self.mode {
+                FsModeConfig::Local | FsModeConfig::LocalWithOverrides => {
+                    decision(FsAction::Local, reason)
+                }
+                FsModeConfig::Write => decision(FsAction::Remote, reason),
+                FsModeConfig::Read => read_only(reason),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Sanity test for empty [`RegexSet`] behaviour.
+    #[test]
+    fn empty_regex_set() {
+        let set = PatternSet::from_config(None).unwrap();
+        assert!(set.first_match("/path/to/some/file").is_none());
+    }
+
+    fn rule(pattern: &str, access: FsRuleAccess) -> FsRule {
+        FsRule {
+            pattern: pattern.to_string(),
+            access,
+        }
+    }
+
+    #[rstest]
+    #[case("/app/config/secret.json", false, FsAction::NotFound, 0)]
+    #[case("/app/config/app.json", true, FsAction::Remote, 1)]
+    #[case("/app/data.txt", false, FsAction::Remote, 2)]
+    #[case("/app/data.txt", true, FsAction::Local, 2)]
+    fn rules_first_match_wins(
+        #[case] path: &str,
+        #[case] write: bool,
+        #[case] expected_action: FsAction,
+        #[case] expected_rule: usize,
+    ) {
+        let fs_config = FsConfig {
+            mode: FsModeConfig::Read,
+            rules: Some(vec![
+                rule("^/app/config/secret", FsRuleAccess::Deny),
+                rule("^/app/config", FsRuleAccess::Write),
+                rule("^/app", FsRuleAccess::Read),
+                rule("^/app/config/app", FsRuleAccess::Local),
+            ]),
+            // Rules go before the pattern lists.
+            local: Some(VecOrSingle::Single("^/app".to_string())),
+            ..Default::default()
+        };
+
+        let decision = FsFilter::new(&fs_config).unwrap().decide(path, write);
+
+        assert_eq!(decision.action, expected_action);
+        assert!(
+            matches!(decision.reason, FsReason::Rule { index, .. } if index == expected_rule),
+            "unexpected reason {:?}",
+            decision.reason
+        );
+    }
+
+    #[test]
+    fn mode_decides_when_nothing_matches() {
+        let fs_config = FsConfig {
+            mode: FsModeConfig::Write,
+            ..Default::default()
+        };
+
+        let decision = FsFilter::new(&fs_config).unwrap().decide("/a/test.a", true);
+
+        assert_eq!(
+            decision,
+            FsDecision {
+                action: FsAction::Remote,
+                reason: FsReason::Mode(FsModeConfig::Write),
+            }
+        );
+    }
+
+    #[rstest]
+    #[case("/app/config/app.yaml", Some("/home/me/config/app.yaml"))]
+    #[case("/app/config/templates/a.html", Some("/home/me/templates/a.html"))]
+    #[case("/app/config", Some("/home/me/config"))]
+    #[case("/app/configuration.yaml", None)]
+    #[case("/srv/config/app.yaml", None)]
+    fn local_override_longest_prefix_wins(#[case] path: &str, #[case] expected: Option<&str>) {
+        let fs_config = FsConfig {
+            mode: FsModeConfig::Read,
+            local_override: Some(
+                [
+                    ("/app/config", "/home/me/config"),
+                    ("/app/config/templates", "/home/me/templates"),
+                ]
+                .into_iter()
+                .map(|(remote, local)| (remote.to_string(), local.to_string()))
+                .collect(),
+            ),
+            ..Default::default()
+        };
+        let filter = FsFilter::new(&fs_config).unwrap();
+
+        assert_eq!(
+            filter.local_override(Path::new(path)),
+            expected.map(PathBuf::from)
+        );
+        assert_eq!(
+            matches!(
+                filter.decide(path, false).reason,
+                FsReason::LocalOverride { .. }
+            ),
+            expected.is_some()
+        );
+    }
+
+    #[rstest]
+    #[case("/var/lib/app/db/users.json", Some("/data/db/users.json"))]
+    #[case("/var/lib/app", Some("/data"))]
+    #[case("/srv/cache/2024/index", Some("/cache/2024/index"))]
+    #[case("/home/me/var/lib/app", None)]
+    fn mapping_rewrites_paths(#[case] path: &str, #[case] expected: Option<&str>) {
+        let fs_config = FsConfig {
+            mapping: Some(
+                [
+                    ("^/var/lib/app", "/data"),
+                    ("^/srv/cache/(\\d+)", "/cache/$1"),
+                ]
+                .into_iter()
+                .map(|(pattern, replacement)| (pattern.to_string(), replacement.to_string()))
+                .collect(),
+            ),
+            ..Default::default()
+        };
+
+        let remapped = FsFilter::new(&fs_config).unwrap().remap(Path::new(path));
+
+        assert_eq!(remapped, expected.map(PathBuf::from));
+    }
+}
@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -45,6 +45,17 @@ pub enum FsModeConfig {
     Write,
 }
 
+impl fmt::Display for FsModeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FsModeConfig::Local => "local",
+            FsModeConfig::LocalWithOverrides => "localwithoverrides",
+            FsModeConfig::Read => "read",
+            FsModeConfig::Write => "write",
+        })
+    }
+}
+
 impl FsModeConfig {
     pub fn is_local(self) -> bool {
         matches!(self, FsModeConfig::Local)
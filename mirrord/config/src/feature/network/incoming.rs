@@ -1,4 +1,4 @@
-use std::{collections::HashSet, fmt, str::FromStr};
+use std::{collections::HashSet, fmt, str::FromStr, time::Duration};
 
 use bimap::BiMap;
 use mirrord_analytics::{AnalyticValue, Analytics, CollectAnalytics};
@@ -135,7 +135,17 @@ impl MirrordConfig for IncomingFileConfig {
                     .source_value(context)
                     .transpose()?
                     .unwrap_or_default(),
-                ports: advanced.ports.map(|ports| ports.into_iter().collect()),
+                auto_ports: matches!(advanced.ports, Some(IncomingPortsFileConfig::Auto(..))),
+                ports: match advanced.ports {
+                    Some(IncomingPortsFileConfig::List(ports)) => Some(ports.into_iter().collect()),
+                    _ => None,
+                },
+                privileged_bind: advanced.privileged_bind.unwrap_or_default(),
+                on_local_error: advanced.on_local_error.unwrap_or_default(),
+                on_stall: advanced.on_stall.unwrap_or_default(),
+                stall_timeout: advanced.stall_timeout,
+                max_concurrent: advanced.max_concurrent,
+                queue_size: advanced.queue_size,
             },
         };
 
@@ -232,8 +242,60 @@ pub struct IncomingAdvancedFileConfig {
     ///
     /// List of ports to mirror/steal traffic from. Other ports will remain local.
     ///
+    /// Can also be `"auto"`, to mirror/steal only the ports the target actually listens on.
+    ///
+    /// UDP ports are mirrored/stolen only when listed here, when the application binds a UDP
+    /// socket to them.
+    ///
+    /// HTTP/3 (QUIC) traffic is stolen this way too, as opaque UDP datagrams: the agent can't
+    /// decrypt it, so the [`http_filter`](###http_filter) doesn't apply to it and the
+    /// application has to terminate QUIC itself, with the certificate the clients expect.
+    ///
     /// Mutually exclusive with [`ignore_ports`](###ignore_ports).
-    pub ports: Option<Vec<u16>>,
+    pub ports: Option<IncomingPortsFileConfig>,
+
+    /// ### privileged_bind
+    ///
+    /// What to do when the application binds a privileged port (below `1024`) that is handled by
+    /// mirrord.
+    ///
+    /// See [`privileged_bind`](##privileged_bind) for details.
+    pub privileged_bind: Option<PrivilegedBind>,
+
+    /// ### on_local_error
+    ///
+    /// What to do when the local application fails to handle a stolen HTTP request.
+    ///
+    /// See [`on_local_error`](##on_local_error) for details.
+    pub on_local_error: Option<OnLocalError>,
+
+    /// ### on_stall
+    ///
+    /// What to do with the stolen traffic when the local application is stopped (`SIGSTOP`, or a
+    /// native debugger) for longer than [`stall_timeout`](###stall_timeout).
+    ///
+    /// See [`on_stall`](##on_stall) for details.
+    pub on_stall: Option<OnStall>,
+
+    /// ### stall_timeout
+    ///
+    /// How long (in seconds) the local application can be stopped before
+    /// [`on_stall`](###on_stall) applies.
+    pub stall_timeout: Option<u64>,
+
+    /// ### max_concurrent
+    ///
+    /// How many stolen connections can be delivered to the local application at once.
+    ///
+    /// See [`max_concurrent`](##max_concurrent) for details.
+    pub max_concurrent: Option<usize>,
+
+    /// ### queue_size
+    ///
+    /// How many stolen connections can wait for [`max_concurrent`](###max_concurrent).
+    ///
+    /// See [`queue_size`](##queue_size) for details.
+    pub queue_size: Option<usize>,
 }
 
 /// Controls the incoming TCP traffic feature.
@@ -359,9 +421,78 @@ pub struct IncomingConfig {
     ///
     /// List of ports to mirror/steal traffic from. Other ports will remain local.
     ///
+    /// Can also be `"auto"`, in which case the agent watches the ports the target listens on,
+    /// and only those are mirrored/stolen. A port the application listens on is subscribed as
+    /// soon as the target starts listening on it as well, also later in the session. Requires a
+    /// recent agent, with older ones all ports are mirrored/stolen.
+    ///
+    /// UDP ports are mirrored/stolen only when listed explicitly: the datagrams arriving to the
+    /// port are sent to the application's UDP socket bound to it, and with `"steal"` its replies
+    /// are sent back from the target. `"auto"` doesn't apply to UDP.
+    ///
+    /// HTTP/3 (QUIC) traffic is stolen this way too, as opaque UDP datagrams: the agent can't
+    /// decrypt it, so the [`http_filter`](#feature-network-incoming-http-filter) doesn't apply to
+    /// it and the application has to terminate QUIC itself, with the certificate the clients
+    /// expect.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "network": {
+    ///       "incoming": {
+    ///         "mode": "steal",
+    ///         "ports": "auto"
+    ///       }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ///
     /// Mutually exclusive with
     /// [`feature.network.incoming.ignore_ports`](#feature-network-ignore_ports).
     pub ports: Option<HashSet<u16>>,
+
+    /// <!--${internal}-->
+    /// Whether [`feature.network.incoming.ports`](#feature-network-incoming-ports) is `"auto"`.
+    pub auto_ports: bool,
+
+    /// #### feature.network.incoming.privileged_bind {#feature-network-incoming-privileged_bind}
+    pub privileged_bind: PrivilegedBind,
+
+    /// #### feature.network.incoming.on_local_error {#feature-network-incoming-on_local_error}
+    pub on_local_error: OnLocalError,
+
+    /// #### feature.network.incoming.on_stall {#feature-network-incoming-on_stall}
+    pub on_stall: OnStall,
+
+    /// #### feature.network.incoming.stall_timeout {#feature-network-incoming-stall_timeout}
+    ///
+    /// How long (in seconds) the local application can be stopped before
+    /// [`feature.network.incoming.on_stall`](#feature-network-incoming-on_stall) applies.
+    ///
+    /// Defaults to `30`.
+    pub stall_timeout: Option<u64>,
+
+    /// #### feature.network.incoming.max_concurrent {#feature-network-incoming-max_concurrent}
+    ///
+    /// How many stolen connections can be delivered to the local application at once. Useful
+    /// when stealing a busy port, so the connections don't use up all the local file descriptors.
+    ///
+    /// The connections over the limit wait in the agent (their data is kept by mirrord) until one
+    /// of the delivered connections is closed, see
+    /// [`feature.network.incoming.queue_size`](#feature-network-incoming-queue_size).
+    ///
+    /// Unlimited by default.
+    pub max_concurrent: Option<usize>,
+
+    /// #### feature.network.incoming.queue_size {#feature-network-incoming-queue_size}
+    ///
+    /// How many stolen connections can wait for
+    /// [`feature.network.incoming.max_concurrent`](#feature-network-incoming-max_concurrent).
+    /// New connections that don't fit in the queue are closed right away.
+    ///
+    /// Defaults to `128`.
+    pub queue_size: Option<usize>,
 }
 
 impl IncomingConfig {
@@ -372,6 +503,73 @@ impl IncomingConfig {
     pub fn is_steal(&self) -> bool {
         matches!(self.mode, IncomingMode::Steal)
     }
+
+    /// <!--${internal}-->
+    /// [`feature.network.incoming.stall_timeout`](#feature-network-incoming-stall_timeout), with
+    /// its default.
+    pub fn stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.stall_timeout.unwrap_or(30))
+    }
+
+    /// <!--${internal}-->
+    /// Whether the internal proxy watches the stolen traffic's application for stalls: to apply
+    /// [`feature.network.incoming.on_stall`](#feature-network-incoming-on_stall), and to find it
+    /// stopped by a debugger (see [`debugger_mode`](#root-debugger_mode)) when a stolen request
+    /// could otherwise fall back to the remote target.
+    pub fn detects_stalls(&self) -> bool {
+        self.is_steal()
+            && (self.on_stall != OnStall::Hold || self.on_local_error == OnLocalError::Fallback)
+    }
+
+    /// <!--${internal}-->
+    /// [`feature.network.incoming.queue_size`](#feature-network-incoming-queue_size), with its
+    /// default.
+    pub fn queue_size(&self) -> usize {
+        self.queue_size.unwrap_or(128)
+    }
+
+    /// <!--${internal}-->
+    /// Checks the [`HttpFilterConfig`] for common mistakes, including the ones that depend on
+    /// other parts of the incoming config (mode, ignored ports, port mapping).
+    ///
+    /// Returns an empty list when no HTTP filter is set.
+    pub fn http_filter_lints(&self) -> Vec<HttpFilterLint> {
+        let Some(filtered_ports) = self.http_filter.get_filtered_ports() else {
+            return Vec::new();
+        };
+
+        let mut lints = self.http_filter.lints();
+
+        if !self.is_steal() {
+            lints.push(HttpFilterLint::FilterWithoutSteal);
+        }
+
+        let mut ignored_ports = filtered_ports
+            .iter()
+            .copied()
+            .filter(|port| self.ignore_ports.contains(port))
+            .collect::<Vec<_>>();
+        if !ignored_ports.is_empty() {
+            ignored_ports.sort_unstable();
+            lints.push(HttpFilterLint::IgnoredFilterPorts(ignored_ports));
+        }
+
+        let mut local_ports = filtered_ports
+            .iter()
+            .filter_map(|port| {
+                self.port_mapping
+                    .get_by_left(port)
+                    .filter(|remote| !filtered_ports.contains(*remote))
+                    .map(|remote| (*port, *remote))
+            })
+            .collect::<Vec<_>>();
+        if !local_ports.is_empty() {
+            local_ports.sort_unstable();
+            lints.push(HttpFilterLint::LocalFilterPorts(local_ports));
+        }
+
+        lints
+    }
 }
 
 /// Allows selecting between mirrorring or stealing traffic.
@@ -498,6 +696,164 @@ impl fmt::Display for ConcurrentSteal {
     }
 }
 
+/// What to do when the application binds a privileged port (below `1024`) that is handled by the
+/// incoming feature.
+///
+/// The traffic comes from the remote port, so the local port doesn't have to be the same, and
+/// binding a privileged port locally usually requires root.
+///
+/// Can be set to either `"unprivileged"` (default) or `"local"`.
+///
+/// - `"unprivileged"`: Bind a random unprivileged port locally instead, the application still sees
+///   the port it requested. Use
+///   [`feature.network.incoming.listen_ports`](#feature-network-incoming-listen_ports) to pick the
+///   local port yourself.
+/// - `"local"`: Try to bind the requested port locally first, falling back to a random port if that
+///   fails.
+///
+/// ```json
+/// {
+///   "feature": {
+///     "network": {
+///       "incoming": {
+///         "mode": "steal",
+///         "privileged_bind": "local"
+///       }
+///     }
+///   }
+/// }
+/// ```
+#[derive(Default, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+pub enum PrivilegedBind {
+    /// <!--${internal}-->
+    /// ### unprivileged
+    ///
+    /// Bind a random unprivileged port locally instead of the privileged one.
+    #[default]
+    Unprivileged,
+    /// <!--${internal}-->
+    /// ### local
+    ///
+    /// Try to bind the requested privileged port locally.
+    Local,
+}
+
+/// What to do when the local application fails to handle a stolen HTTP request, i.e. responds
+/// with a server error (`5xx`), does not respond at all or does not respond within 30 seconds.
+///
+/// Only applies to the requests stolen with an
+/// [`http_filter`](#feature-network-incoming-http_filter), since the whole TCP connection is stolen
+/// otherwise.
+///
+/// Can be set to either `"respond"` (default) or `"fallback"`.
+///
+/// - `"respond"`: Send the response of the local application (or a `502 Bad Gateway` when there's
+///   no response) to the remote client.
+/// - `"fallback"`: Pass the original request to the remote target instead, which responds to it as
+///   if it was never stolen. This makes it safer to leave filtered stealing running in shared
+///   environments.
+///
+/// ```json
+/// {
+///   "feature": {
+///     "network": {
+///       "incoming": {
+///         "mode": "steal",
+///         "http_filter": {
+///           "header_filter": "x-debug: me"
+///         },
+///         "on_local_error": "fallback"
+///       }
+///     }
+///   }
+/// }
+/// ```
+#[derive(Default, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+pub enum OnLocalError {
+    /// <!--${internal}-->
+    /// ### respond
+    ///
+    /// Respond with whatever the local application responded.
+    #[default]
+    Respond,
+    /// <!--${internal}-->
+    /// ### fallback
+    ///
+    /// Pass the request to the remote target.
+    Fallback,
+}
+
+/// What to do with the stolen traffic when the local application is stopped for longer than
+/// [`stall_timeout`](#feature-network-incoming-stall_timeout), e.g. with `SIGSTOP` or at a
+/// breakpoint of a native debugger that stops the whole process. Remote clients would otherwise
+/// wait on connections that the application doesn't serve.
+///
+/// Can be set to `"hold"` (default), `"reset"` or `"fallback"`.
+///
+/// - `"hold"`: Keep the stolen connections waiting for the application.
+/// - `"reset"`: Close the stolen connections, and the ones stolen while the application is stopped,
+///   so that the remote clients can retry.
+/// - `"fallback"`: Close the stolen connections and stop stealing until the application runs again,
+///   so that the new connections are served by the remote target.
+///
+/// Only detects that the whole process is stopped, not a breakpoint of a debugger that pauses a
+/// single thread (most interpreted languages).
+///
+/// ```json
+/// {
+///   "feature": {
+///     "network": {
+///       "incoming": {
+///         "mode": "steal",
+///         "on_stall": "fallback",
+///         "stall_timeout": 60
+///       }
+///     }
+///   }
+/// }
+/// ```
+#[derive(Default, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+pub enum OnStall {
+    /// <!--${internal}-->
+    /// ### hold
+    ///
+    /// Keep the stolen connections waiting for the application.
+    #[default]
+    Hold,
+    /// <!--${internal}-->
+    /// ### reset
+    ///
+    /// Close the stolen connections.
+    Reset,
+    /// <!--${internal}-->
+    /// ### fallback
+    ///
+    /// Close the stolen connections, and let the remote target serve the new ones.
+    Fallback,
+}
+
+/// <!--${internal}-->
+/// Value of [`feature.network.incoming.ports`](#feature-network-incoming-ports), either a list of
+/// ports or `"auto"`.
+#[derive(Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+#[serde(untagged)]
+pub enum IncomingPortsFileConfig {
+    Auto(AutoPorts),
+    List(Vec<u16>),
+}
+
+/// <!--${internal}-->
+/// The `"auto"` value of [`feature.network.incoming.ports`](#feature-network-incoming-ports).
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoPorts {
+    Auto,
+}
+
 impl From<&IncomingMode> for AnalyticValue {
     fn from(value: &IncomingMode) -> Self {
         match value {
@@ -518,6 +874,34 @@ impl From<&ConcurrentSteal> for AnalyticValue {
     }
 }
 
+impl From<&PrivilegedBind> for AnalyticValue {
+    fn from(value: &PrivilegedBind) -> Self {
+        match value {
+            PrivilegedBind::Unprivileged => AnalyticValue::Number(0),
+            PrivilegedBind::Local => AnalyticValue::Number(1),
+        }
+    }
+}
+
+impl From<&OnLocalError> for AnalyticValue {
+    fn from(value: &OnLocalError) -> Self {
+        match value {
+            OnLocalError::Respond => AnalyticValue::Number(0),
+            OnLocalError::Fallback => AnalyticValue::Number(1),
+        }
+    }
+}
+
+impl From<&OnStall> for AnalyticValue {
+    fn from(value: &OnStall) -> Self {
+        match value {
+            OnStall::Hold => AnalyticValue::Number(0),
+            OnStall::Reset => AnalyticValue::Number(1),
+            OnStall::Fallback => AnalyticValue::Number(2),
+        }
+    }
+}
+
 impl CollectAnalytics for &IncomingConfig {
     fn collect_analytics(&self, analytics: &mut Analytics) {
         analytics.add("mode", &self.mode);
@@ -526,6 +910,11 @@ impl CollectAnalytics for &IncomingConfig {
         analytics.add("listen_ports_count", self.listen_ports.len());
         analytics.add("ignore_localhost", self.ignore_localhost);
         analytics.add("ignore_ports_count", self.ignore_ports.len());
+        analytics.add("auto_ports", self.auto_ports);
         analytics.add("http", &self.http_filter);
+        analytics.add("privileged_bind", &self.privileged_bind);
+        analytics.add("on_local_error", &self.on_local_error);
+        analytics.add("on_stall", &self.on_stall);
+        analytics.add("max_concurrent", self.max_concurrent.unwrap_or_default());
     }
 }
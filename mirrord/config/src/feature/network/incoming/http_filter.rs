@@ -1,4 +1,4 @@
-use std::{collections::HashSet, ops::Deref, str::FromStr};
+use std::{collections::HashSet, fmt, ops::Deref, str::FromStr};
 
 use mirrord_analytics::CollectAnalytics;
 use mirrord_config_derive::MirrordConfig;
@@ -55,6 +55,15 @@ use crate::{
 /// ```
 /// Setting this filter will make mirrord only steal requests to URIs that do not start with
 /// "/health/".
+///
+/// For gRPC traffic, you can filter by service and method instead:
+/// ```json
+/// {
+///   "grpc_filter": "my.package.Service/MyMethod"
+/// }
+/// ```
+/// Setting this filter will make mirrord only steal calls to the `MyMethod` method of the
+/// `my.package.Service` service.
 #[derive(MirrordConfig, Default, PartialEq, Eq, Clone, Debug)]
 #[config(map_to = "HttpFilterFileConfig", derive = "JsonSchema")]
 #[cfg_attr(test, config(derive = "PartialEq, Eq"))]
@@ -80,6 +89,20 @@ pub struct HttpFilterConfig {
     #[config(env = "MIRRORD_HTTP_PATH_FILTER")]
     pub path_filter: Option<String>,
 
+    /// ##### feature.network.incoming.http_filter.grpc_filter {#feature-network-incoming-http-grpc-filter}
+    ///
+    /// Steal only the gRPC calls to this service, in the `<service>/<method>` format, e.g.
+    /// `my.package.Service/MyMethod`. Use just the service name, e.g. `my.package.Service`, to
+    /// steal the calls to all of its methods.
+    ///
+    /// Matches only requests with a `content-type` of `application/grpc`, the names are taken
+    /// from the HTTP/2 `:path` pseudo-header and are case-sensitive.
+    ///
+    /// Mutually exclusive with [`header_filter`](#feature-network-incoming-http-header-filter)
+    /// and [`path_filter`](#feature-network-incoming-http-path-filter).
+    #[config(env = "MIRRORD_HTTP_GRPC_FILTER")]
+    pub grpc_filter: Option<String>,
+
     /// ##### feature.network.incoming.http_filter.ports {#feature-network-incoming-http_filter-ports}
     ///
     /// Activate the HTTP traffic filter only for these ports.
@@ -90,16 +113,293 @@ pub struct HttpFilterConfig {
     /// Set to [80, 8080] by default.
     #[config(env = "MIRRORD_HTTP_FILTER_PORTS", default)]
     pub ports: PortList,
+
+    /// ##### feature.network.incoming.http_filter.shadow_diff {#feature-network-incoming-http_filter-shadow_diff}
+    ///
+    /// Shadow the requests that match the filter instead of stealing them, and write a report of
+    /// the differences between the responses to this file.
+    ///
+    /// The local application gets a copy of each matching request, while the remote target still
+    /// handles it and responds to the client, so the traffic of the target is not affected. The
+    /// internal proxy compares the two responses and writes one JSON object per request to the
+    /// file, e.g. to validate a refactor against production traffic.
+    ///
+    /// Requires [`header_filter`](#feature-network-incoming-http-header-filter),
+    /// [`path_filter`](#feature-network-incoming-http-path-filter) or
+    /// [`grpc_filter`](#feature-network-incoming-http-grpc-filter).
+    #[config(env = "MIRRORD_HTTP_FILTER_SHADOW_DIFF")]
+    pub shadow_diff: Option<String>,
+
+    /// ##### feature.network.incoming.http_filter.routing_header {#feature-network-incoming-http_filter-routing_header}
+    ///
+    /// Steal only the requests that carry this header with the value of this session, for
+    /// service meshes that route the requests of each developer by a header (e.g. canary
+    /// routing in Istio).
+    ///
+    /// mirrord generates the value when the session starts (unless it's set in
+    /// [`routing_value`](#feature-network-incoming-http_filter-routing_value)), labels the
+    /// operator session with it and prints it, so you can send your requests with
+    /// `<routing_header>: <value>` and have the mesh inject it upstream.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": {
+    ///     "network": {
+    ///       "incoming": {
+    ///         "mode": "steal",
+    ///         "http_filter": {
+    ///           "routing_header": "x-mirrord-route"
+    ///         }
+    ///       }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ///
+    /// Mutually exclusive with [`header_filter`](#feature-network-incoming-http-header-filter),
+    /// [`path_filter`](#feature-network-incoming-http-path-filter) and
+    /// [`grpc_filter`](#feature-network-incoming-http-grpc-filter).
+    #[config(env = "MIRRORD_HTTP_FILTER_ROUTING_HEADER")]
+    pub routing_header: Option<String>,
+
+    /// ##### feature.network.incoming.http_filter.routing_value {#feature-network-incoming-http_filter-routing_value}
+    ///
+    /// Value of the [`routing_header`](#feature-network-incoming-http_filter-routing_header) for
+    /// this session. Set it to keep the same value across sessions, otherwise mirrord
+    /// generates a new one each time.
+    #[config(env = "MIRRORD_HTTP_FILTER_ROUTING_VALUE")]
+    pub routing_value: Option<String>,
 }
 
 impl HttpFilterConfig {
     pub fn is_filter_set(&self) -> bool {
-        self.header_filter.is_some() || self.path_filter.is_some()
+        self.header_filter.is_some()
+            || self.path_filter.is_some()
+            || self.grpc_filter.is_some()
+            || self.routing_header.is_some()
+    }
+
+    /// <!--${internal}-->
+    /// The service and (optional) method from [`HttpFilterConfig::grpc_filter`].
+    ///
+    /// [`None`] if the filter is not set or is not in the `<service>[/<method>]` format.
+    pub fn grpc_service_method(&self) -> Option<(&str, Option<&str>)> {
+        let filter = self.grpc_filter.as_deref()?;
+        let filter = filter.strip_prefix('/').unwrap_or(filter);
+
+        let (service, method) = match filter.split_once('/') {
+            Some((service, method)) => (service, Some(method)),
+            None => (filter, None),
+        };
+
+        let valid = !service.is_empty()
+            && method.map_or(true, |method| !method.is_empty() && !method.contains('/'));
+        valid.then_some((service, method))
+    }
+
+    /// <!--${internal}-->
+    /// The header filter that matches the requests routed to this session, when
+    /// [`HttpFilterConfig::routing_header`] and [`HttpFilterConfig::routing_value`] are set.
+    pub fn routing_filter(&self) -> Option<String> {
+        let header = self.routing_header.as_deref()?;
+        let value = self.routing_value.as_deref()?;
+
+        Some(format!(
+            "^{}: {}$",
+            regex::escape(header),
+            regex::escape(value)
+        ))
     }
 
     pub fn get_filtered_ports(&self) -> Option<&[u16]> {
         self.is_filter_set().then(|| &*self.ports.0)
     }
+
+    /// <!--${internal}-->
+    /// Checks the filter regexes for common mistakes that don't make the config invalid, but
+    /// most likely make the filter behave differently than the user expects.
+    ///
+    /// Checks that depend on other parts of the incoming config are done in
+    /// [`IncomingConfig::http_filter_lints`](super::IncomingConfig::http_filter_lints).
+    pub fn lints(&self) -> Vec<HttpFilterLint> {
+        let mut lints = Vec::new();
+
+        if let Some(header_filter) = self.header_filter.as_deref() {
+            if is_match_all_regex(header_filter) {
+                lints.push(HttpFilterLint::MatchAll {
+                    field: "header_filter",
+                    filter: header_filter.to_string(),
+                });
+            } else if has_misplaced_anchor(header_filter) {
+                lints.push(HttpFilterLint::MisplacedHeaderAnchor(
+                    header_filter.to_string(),
+                ));
+            }
+        }
+
+        if let Some(path_filter) = self.path_filter.as_deref() {
+            if is_match_all_regex(path_filter) {
+                lints.push(HttpFilterLint::MatchAll {
+                    field: "path_filter",
+                    filter: path_filter.to_string(),
+                });
+            }
+
+            if has_query_string(path_filter) {
+                lints.push(HttpFilterLint::PathWithQuery(path_filter.to_string()));
+            }
+        }
+
+        lints
+    }
+}
+
+/// <!--${internal}-->
+/// A common mistake found in the HTTP filter configuration by [`HttpFilterConfig::lints`].
+///
+/// These are reported as warnings (never errors), each one prefixed with its
+/// [`HttpFilterLint::rule_id`], so users (and IDEs) can tell them apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpFilterLint {
+    /// Header filter uses `^` or `$` somewhere other than the start/end of the regex, e.g.
+    /// `host: ^api`. The header is matched as a single `HeaderKey: HeaderValue` string, so such
+    /// an anchor never matches.
+    MisplacedHeaderAnchor(String),
+
+    /// The filter regex matches every request, e.g. `.*`, making the filter pointless.
+    MatchAll { field: &'static str, filter: String },
+
+    /// Path filter contains a query string, but the filter is matched only against the path
+    /// part of the URI.
+    PathWithQuery(String),
+
+    /// Some `http_filter.ports` are also in `incoming.ignore_ports`, so the filter is never
+    /// applied to them.
+    IgnoredFilterPorts(Vec<u16>),
+
+    /// Some `http_filter.ports` are local ports from `incoming.port_mapping`, but the filter
+    /// ports refer to the remote ports.
+    LocalFilterPorts(Vec<(u16, u16)>),
+
+    /// Filter is set, but `incoming.mode` is not `"steal"`, so the filter is ignored.
+    FilterWithoutSteal,
+}
+
+impl HttpFilterLint {
+    /// Stable identifier of this lint, used as a prefix in the warning message.
+    pub fn rule_id(&self) -> &'static str {
+        match self {
+            Self::MisplacedHeaderAnchor(..) => "http-filter/misplaced-anchor",
+            Self::MatchAll { .. } => "http-filter/match-all",
+            Self::PathWithQuery(..) => "http-filter/path-with-query",
+            Self::IgnoredFilterPorts(..) => "http-filter/ignored-port",
+            Self::LocalFilterPorts(..) => "http-filter/local-port",
+            Self::FilterWithoutSteal => "http-filter/not-stealing",
+        }
+    }
+}
+
+impl fmt::Display for HttpFilterLint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] ", self.rule_id())?;
+
+        match self {
+            Self::MisplacedHeaderAnchor(filter) => write!(
+                f,
+                "`feature.network.incoming.http_filter.header_filter` ({filter:?}) uses `^` or \
+                `$` in the middle of the regex. Headers are matched as `HeaderKey: HeaderValue`, \
+                so an anchor placed after the header name never matches. Use `^HeaderKey: ...` \
+                instead."
+            ),
+            Self::MatchAll { field, filter } => write!(
+                f,
+                "`feature.network.incoming.http_filter.{field}` ({filter:?}) matches every \
+                request. If you want to steal all the traffic, use \
+                `feature.network.incoming.ports` instead."
+            ),
+            Self::PathWithQuery(filter) => write!(
+                f,
+                "`feature.network.incoming.http_filter.path_filter` ({filter:?}) contains a query \
+                string, but the filter is matched only against the path of the request URI, \
+                without the query."
+            ),
+            Self::IgnoredFilterPorts(ports) => write!(
+                f,
+                "ports {ports:?} are in both `feature.network.incoming.http_filter.ports` and \
+                `feature.network.incoming.ignore_ports`, so the HTTP filter will never be applied \
+                to them."
+            ),
+            Self::LocalFilterPorts(mappings) => write!(
+                f,
+                "`feature.network.incoming.http_filter.ports` contains local ports from \
+                `feature.network.incoming.port_mapping` ({mappings:?}), but the filter ports \
+                refer to the remote ports. Use the remote ports in the filter instead."
+            ),
+            Self::FilterWithoutSteal => write!(
+                f,
+                "an HTTP filter is set, but `feature.network.incoming.mode` is not `\"steal\"`, \
+                so the filter is ignored."
+            ),
+        }
+    }
+}
+
+/// <!--${internal}-->
+/// Whether the regex (ignoring anchors and a wrapping group) is just a wildcard, e.g. `^.*$`.
+fn is_match_all_regex(filter: &str) -> bool {
+    let mut filter = filter.trim();
+    filter = filter.strip_prefix("(?i)").unwrap_or(filter);
+    filter = filter.strip_prefix('^').unwrap_or(filter);
+    filter = filter.strip_suffix('$').unwrap_or(filter);
+
+    let filter = filter
+        .strip_prefix('(')
+        .and_then(|inner| inner.strip_suffix(')'))
+        .unwrap_or(filter);
+
+    matches!(filter, "" | ".*" | ".+" | ".*?" | ".+?" | "[\\s\\S]*")
+}
+
+/// <!--${internal}-->
+/// Whether the regex contains an unescaped `^` or `$` outside of its first/last position, and
+/// outside of character classes (where `^` means negation).
+fn has_misplaced_anchor(filter: &str) -> bool {
+    let chars = filter.trim().chars().collect::<Vec<_>>();
+    let last = chars.len().saturating_sub(1);
+
+    let mut escaped = false;
+    let mut in_class = false;
+
+    for (position, character) in chars.iter().copied().enumerate() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '^' if !in_class
+                && position != 0
+                && !matches!(chars.get(position - 1), Some('(' | '|')) =>
+            {
+                return true
+            }
+            '$' if !in_class
+                && position != last
+                && !matches!(chars.get(position + 1), Some(')' | '|')) =>
+            {
+                return true
+            }
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// <!--${internal}-->
+/// Whether the path filter looks like it's trying to match a query string, e.g.
+/// `/api\?user=1`.
+fn has_query_string(filter: &str) -> bool {
+    filter.contains("\\?") || filter.contains("[?]")
 }
 
 /// <!--${internal}-->
@@ -123,15 +423,35 @@ impl MirrordToggleableConfig for HttpFilterFileConfig {
             .source_value(context)
             .transpose()?;
 
+        let grpc_filter = FromEnv::new("MIRRORD_HTTP_GRPC_FILTER")
+            .source_value(context)
+            .transpose()?;
+
         let ports = FromEnv::new("MIRRORD_HTTP_FILTER_PORTS")
             .source_value(context)
             .transpose()?
             .unwrap_or_default();
 
+        let shadow_diff = FromEnv::new("MIRRORD_HTTP_FILTER_SHADOW_DIFF")
+            .source_value(context)
+            .transpose()?;
+
+        let routing_header = FromEnv::new("MIRRORD_HTTP_FILTER_ROUTING_HEADER")
+            .source_value(context)
+            .transpose()?;
+
+        let routing_value = FromEnv::new("MIRRORD_HTTP_FILTER_ROUTING_VALUE")
+            .source_value(context)
+            .transpose()?;
+
         Ok(Self::Generated {
             header_filter,
             path_filter,
+            grpc_filter,
             ports,
+            shadow_diff,
+            routing_header,
+            routing_value,
         })
     }
 }
@@ -174,6 +494,153 @@ impl CollectAnalytics for &HttpFilterConfig {
     fn collect_analytics(&self, analytics: &mut mirrord_analytics::Analytics) {
         analytics.add("header_filter", self.header_filter.is_some());
         analytics.add("path_filter", self.path_filter.is_some());
+        analytics.add("grpc_filter", self.grpc_filter.is_some());
         analytics.add("ports", self.ports.len());
+        analytics.add("shadow_diff", self.shadow_diff.is_some());
+        analytics.add("routing_header", self.routing_header.is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bimap::BiMap;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::feature::network::incoming::{IncomingConfig, IncomingMode};
+
+    fn header_filter(filter: &str) -> HttpFilterConfig {
+        HttpFilterConfig {
+            header_filter: Some(filter.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn path_filter(filter: &str) -> HttpFilterConfig {
+        HttpFilterConfig {
+            path_filter: Some(filter.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case("host: api\\..+")]
+    #[case("^User-Agent: (?!kube-probe)")]
+    #[case("^x-user: (alice|bob)$")]
+    #[case("^x-id: [^0-9]+")]
+    fn header_filter_no_lints(#[case] filter: &str) {
+        assert!(header_filter(filter).lints().is_empty());
+    }
+
+    #[rstest]
+    #[case(".*")]
+    #[case("^.*$")]
+    #[case("(.+)")]
+    #[case("")]
+    fn header_filter_match_all(#[case] filter: &str) {
+        assert_eq!(
+            header_filter(filter).lints(),
+            vec![HttpFilterLint::MatchAll {
+                field: "header_filter",
+                filter: filter.to_string()
+            }]
+        );
+    }
+
+    #[rstest]
+    #[case("host: ^api")]
+    #[case("^x-user$: alice")]
+    fn header_filter_misplaced_anchor(#[case] filter: &str) {
+        assert_eq!(
+            header_filter(filter).lints(),
+            vec![HttpFilterLint::MisplacedHeaderAnchor(filter.to_string())]
+        );
+    }
+
+    #[rstest]
+    #[case("^/api/")]
+    #[case("^(?!/health/)")]
+    fn path_filter_no_lints(#[case] filter: &str) {
+        assert!(path_filter(filter).lints().is_empty());
+    }
+
+    #[test]
+    fn path_filter_with_query() {
+        let filter = "^/api\\?user=.+";
+
+        assert_eq!(
+            path_filter(filter).lints(),
+            vec![HttpFilterLint::PathWithQuery(filter.to_string())]
+        );
+    }
+
+    #[test]
+    fn routing_filter() {
+        let mut config = HttpFilterConfig {
+            routing_header: Some("x-route.id".to_string()),
+            ..Default::default()
+        };
+        assert!(config.is_filter_set());
+        assert_eq!(config.routing_filter(), None);
+
+        config.routing_value = Some("alice+1".to_string());
+        assert_eq!(
+            config.routing_filter().as_deref(),
+            Some("^x-route\\.id: alice\\+1$")
+        );
+    }
+
+    #[test]
+    fn incoming_config_lints() {
+        let incoming = IncomingConfig {
+            mode: IncomingMode::Mirror,
+            http_filter: HttpFilterConfig {
+                header_filter: Some("^x-user: alice$".to_string()),
+                ports: "80;8080;9999".parse().unwrap(),
+                ..Default::default()
+            },
+            ignore_ports: [8080].into(),
+            port_mapping: BiMap::from_iter([(9999, 3000)]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            incoming.http_filter_lints(),
+            vec![
+                HttpFilterLint::FilterWithoutSteal,
+                HttpFilterLint::IgnoredFilterPorts(vec![8080]),
+                HttpFilterLint::LocalFilterPorts(vec![(9999, 3000)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn incoming_config_no_filter_no_lints() {
+        let incoming = IncomingConfig {
+            ignore_ports: [80].into(),
+            ..Default::default()
+        };
+
+        assert!(incoming.http_filter_lints().is_empty());
+    }
+
+    #[rstest]
+    #[case("my.package.Service/MyMethod", Some(("my.package.Service", Some("MyMethod"))))]
+    #[case("/my.package.Service/MyMethod", Some(("my.package.Service", Some("MyMethod"))))]
+    #[case("my.package.Service", Some(("my.package.Service", None)))]
+    #[case("", None)]
+    #[case("/MyMethod", None)]
+    #[case("my.package.Service/", None)]
+    #[case("my.package.Service/MyMethod/extra", None)]
+    fn grpc_filter_service_method(
+        #[case] filter: &str,
+        #[case] expected: Option<(&str, Option<&str>)>,
+    ) {
+        let config = HttpFilterConfig {
+            grpc_filter: Some(filter.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(config.grpc_service_method(), expected);
     }
 }
@@ -4,7 +4,7 @@ use std::net::SocketAddr;
 use mirrord_analytics::CollectAnalytics;
 use mirrord_config_derive::MirrordConfig;
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
@@ -134,6 +134,53 @@ pub struct OutgoingConfig {
     /// to happen locally on your machine.
     #[config(unstable, env = "MIRRORD_OUTGOING_REMOTE_UNIX_STREAMS")]
     pub unix_streams: Option<VecOrSingle<String>>,
+
+    /// #### feature.network.outgoing.proxy_server {#feature.network.outgoing.proxy_server}
+    ///
+    /// Run a local SOCKS5 and HTTP proxy in the internal proxy, for the processes mirrord can't be
+    /// loaded into, e.g. statically linked binaries.
+    ///
+    /// When the application starts such a process, mirrord sets `HTTP_PROXY`, `HTTPS_PROXY` and
+    /// `ALL_PROXY` for it (unless they're already set), so its outgoing connections go out from
+    /// the target. Host names are resolved in the cluster as well.
+    ///
+    /// Only TCP is supported, and `feature.network.outgoing.filter` doesn't apply to these
+    /// connections.
+    ///
+    /// Defaults to `false`.
+    #[config(unstable, env = "MIRRORD_OUTGOING_PROXY_SERVER", default = false)]
+    pub proxy_server: bool,
+
+    /// #### feature.network.outgoing.connect_timeout {#feature.network.outgoing.connect_timeout}
+    ///
+    /// Timeout in milliseconds of each attempt the agent makes to connect to the remote address of
+    /// an outgoing TCP connection. When it passes, `connect` fails with `ETIMEDOUT`.
+    ///
+    /// Defaults to `3000`.
+    #[config(env = "MIRRORD_OUTGOING_CONNECT_TIMEOUT")]
+    pub connect_timeout: Option<u64>,
+
+    /// #### feature.network.outgoing.retries {#feature.network.outgoing.retries}
+    ///
+    /// How many times the agent retries an outgoing TCP connection that failed with a transient
+    /// error (refused, reset, unreachable or timed out), waiting a bit longer before each retry.
+    ///
+    /// When all the attempts fail, `connect` fails with the error of the last one.
+    ///
+    /// Defaults to `0`.
+    #[config(env = "MIRRORD_OUTGOING_CONNECT_RETRIES", default = 0)]
+    pub retries: u32,
+
+    /// #### feature.network.outgoing.record {#feature.network.outgoing.record}
+    ///
+    /// Append every outgoing TCP and UDP destination that the application connects to, and
+    /// whether it was connected remotely or locally, to this file.
+    ///
+    /// Run `mirrord analyze outgoing <file>` afterwards to get a suggested
+    /// [`filter`](#feature.network.outgoing.filter) that connects only the destinations in the
+    /// cluster remotely.
+    #[config(unstable, env = "MIRRORD_OUTGOING_RECORD")]
+    pub record: Option<String>,
 }
 
 impl MirrordToggleableConfig for OutgoingFileConfig {
@@ -153,6 +200,37 @@ impl MirrordToggleableConfig for OutgoingFileConfig {
     }
 }
 
+/// <!--${internal}-->
+/// A line of the [`OutgoingConfig::record`] file, in JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutgoingRecord {
+    pub protocol: RecordedProtocol,
+    pub address: SocketAddr,
+    /// Name that the application resolved to the [`Self::address`], when it was resolved in the
+    /// cluster.
+    pub hostname: Option<String>,
+    /// Whether the connection was made from the target.
+    pub remote: bool,
+}
+
+/// <!--${internal}-->
+/// Protocol of an [`OutgoingRecord`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordedProtocol {
+    Tcp,
+    Udp,
+}
+
+impl std::fmt::Display for RecordedProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp => f.write_str("tcp"),
+            Self::Udp => f.write_str("udp"),
+        }
+    }
+}
+
 /// <!--${internal}-->
 /// Errors related to parsing an [`OutgoingFilter`].
 #[derive(Debug, Error)]
@@ -365,6 +443,10 @@ impl CollectAnalytics for &OutgoingConfig {
         analytics.add("tcp", self.tcp);
         analytics.add("udp", self.udp);
         analytics.add("ignore_localhost", self.ignore_localhost);
+        analytics.add("proxy_server", self.proxy_server);
+        analytics.add("connect_timeout", self.connect_timeout.is_some());
+        analytics.add("retries", self.retries);
+        analytics.add("record", self.record.is_some());
         analytics.add(
             "unix_streams",
             self.unix_streams
@@ -0,0 +1,76 @@
+use mirrord_analytics::CollectAnalytics;
+use mirrord_config_derive::MirrordConfig;
+use schemars::JsonSchema;
+
+use crate::config::source::MirrordConfigSource;
+
+/// Local commands that mirrord runs when something happens in the session, e.g. to open a
+/// dashboard, seed a database, or send a notification.
+///
+/// Each command is run with `sh -c` on the local machine, in the background, and mirrord does not
+/// wait for it to finish. Its output is not shown.
+///
+/// The commands receive the event context in these environment variables:
+///
+/// - `MIRRORD_EVENT`: name of the event, e.g. `session_start`;
+/// - `MIRRORD_TARGET`: the target path, e.g. `deployment/api`, or `targetless`;
+/// - `MIRRORD_TARGET_NAMESPACE`: the target namespace, if set;
+/// - `MIRRORD_STEAL_PORT`: the stolen port (only in `on_steal_start`);
+/// - `MIRRORD_DISCONNECT_REASON`: why the session ended (only in `on_disconnect`);
+/// - `MIRRORD_RECONNECT_REASON`: why the connection with the previous agent was lost (only in
+///   `on_reconnect`).
+///
+/// ```json
+/// {
+///   "hooks": {
+///     "on_session_start": "open https://grafana.example.com",
+///     "on_steal_start": "notify-send \"mirrord is stealing port $MIRRORD_STEAL_PORT\"",
+///     "on_disconnect": "echo \"$MIRRORD_DISCONNECT_REASON\" >> ~/mirrord-sessions.log"
+///   }
+/// }
+/// ```
+#[derive(MirrordConfig, Clone, Debug, Default)]
+#[config(map_to = "HooksFileConfig", derive = "JsonSchema")]
+#[cfg_attr(test, config(derive = "PartialEq, Eq"))]
+pub struct HooksConfig {
+    /// ### hooks.on_session_start {#hooks-on_session_start}
+    ///
+    /// Runs once the connection with the agent is established.
+    pub on_session_start: Option<String>,
+
+    /// ### hooks.on_steal_start {#hooks-on_steal_start}
+    ///
+    /// Runs when the agent starts stealing traffic from the first port in the session.
+    pub on_steal_start: Option<String>,
+
+    /// ### hooks.on_disconnect {#hooks-on_disconnect}
+    ///
+    /// Runs when the session with the agent ends, either because the application exited or
+    /// because the connection was lost.
+    pub on_disconnect: Option<String>,
+
+    /// ### hooks.on_reconnect {#hooks-on_reconnect}
+    ///
+    /// Runs when the session continues with a new agent, after the connection with the previous
+    /// one was lost. See [`agent.handover`](#agent-handover).
+    pub on_reconnect: Option<String>,
+}
+
+impl HooksConfig {
+    /// Whether any of the hooks is set.
+    pub fn is_set(&self) -> bool {
+        self.on_session_start.is_some()
+            || self.on_steal_start.is_some()
+            || self.on_disconnect.is_some()
+            || self.on_reconnect.is_some()
+    }
+}
+
+impl CollectAnalytics for &HooksConfig {
+    fn collect_analytics(&self, analytics: &mut mirrord_analytics::Analytics) {
+        analytics.add("on_session_start", self.on_session_start.is_some());
+        analytics.add("on_steal_start", self.on_steal_start.is_some());
+        analytics.add("on_disconnect", self.on_disconnect.is_some());
+        analytics.add("on_reconnect", self.on_reconnect.is_some());
+    }
+}
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use mirrord_config_derive::MirrordConfig;
 use schemars::JsonSchema;
 
@@ -63,5 +65,137 @@ pub struct InternalProxyConfig {
 
     /// ### internal_proxy.log_destination {#internal_proxy-log_destination}
     /// Set the log file destination for the internal proxy.
+    /// The file is rotated according to [`log_rotation`](#internal_proxy-log_rotation).
     pub log_destination: Option<String>,
+
+    /// ### internal_proxy.log_rotation {#internal_proxy-log_rotation}
+    #[config(nested)]
+    pub log_rotation: LogRotationConfig,
+
+    /// ### internal_proxy.bind_address {#internal_proxy-bind_address}
+    ///
+    /// Address the internal proxy listens on for the layers' connections.
+    ///
+    /// Defaults to `127.0.0.1`. Use `0.0.0.0` when the application runs in a container that
+    /// doesn't share the host's network, so it can reach the proxy from there.
+    pub bind_address: Option<IpAddr>,
+
+    /// ### internal_proxy.advertised_address {#internal_proxy-advertised_address}
+    ///
+    /// Host (name or IP) the layers use to connect to the internal proxy, when it's not the
+    /// [`bind_address`](#internal_proxy-bind_address).
+    ///
+    /// When the proxy listens on all addresses and this is not set, we detect how the containers
+    /// reach the host: `host.docker.internal` with Docker Desktop, or the gateway of Docker's
+    /// `bridge` network with native Docker.
+    ///
+    /// ```json
+    /// {
+    ///   "internal_proxy": {
+    ///     "bind_address": "0.0.0.0",
+    ///     "advertised_address": "host.docker.internal"
+    ///   }
+    /// }
+    /// ```
+    pub advertised_address: Option<String>,
+
+    /// ### internal_proxy.port_range {#internal_proxy-port_range}
+    ///
+    /// Inclusive range of ports the internal proxy may listen on, e.g. `[40000, 40100]`, for
+    /// when only some ports are published or allowed through a firewall.
+    ///
+    /// By default, the proxy listens on a random port.
+    pub port_range: Option<(u16, u16)>,
+
+    /// ### internal_proxy.detach {#internal_proxy-detach}
+    ///
+    /// Starts the internal proxy of `mirrord exec` outside of the application's process tree, in
+    /// its own session, instead of as a child of the application.
+    ///
+    /// Applications that reap all of their children (e.g. app servers with their own `SIGCHLD`
+    /// handler) may otherwise mistake the proxy for one of their own processes. Set it to
+    /// `false` if something relies on the proxy being a child of the application.
+    ///
+    /// Defaults to `true`.
+    #[config(default = true)]
+    pub detach: bool,
+
+    /// ### internal_proxy.max_file_read_size {#internal_proxy-max_file_read_size}
+    ///
+    /// Maximum number of bytes read from a remote file with a single `read` call.
+    ///
+    /// Bigger reads are shortened to this size, and the application gets the rest of the file
+    /// with the next calls, like it would from a pipe, so the whole file is never held in memory.
+    ///
+    /// Defaults to `67108864` (64 MiB).
+    #[config(default = 67108864)]
+    pub max_file_read_size: u64,
+
+    /// ### internal_proxy.max_file_write_size {#internal_proxy-max_file_write_size}
+    ///
+    /// Maximum number of bytes written to a remote file with a single `write` call.
+    ///
+    /// Bigger writes fail with `EFBIG`, and the error that names the limit is logged.
+    ///
+    /// By default, writes are not limited.
+    pub max_file_write_size: Option<u64>,
+
+    /// ### internal_proxy.max_http_body_size {#internal_proxy-max_http_body_size}
+    ///
+    /// Maximum size in bytes of the body of a stolen HTTP request, or of the response of the
+    /// local application to it, that the internal proxy holds in memory.
+    ///
+    /// Requests with bigger bodies are answered with `413 Payload Too Large`, and bigger
+    /// responses are replaced with `502 Bad Gateway`, both with a message that names this limit.
+    ///
+    /// By default, bodies are not limited.
+    ///
+    /// ```json
+    /// {
+    ///   "internal_proxy": {
+    ///     "max_file_write_size": 16777216,
+    ///     "max_http_body_size": 104857600
+    ///   }
+    /// }
+    /// ```
+    pub max_http_body_size: Option<u64>,
+}
+
+/// Rotation of the [`log_destination`](#internal_proxy-log_destination) file.
+///
+/// When the file grows over `max_size_mb`, it's renamed to `<file>.1` (the older files become
+/// `<file>.2` and so on) and a new one is started, keeping at most `max_files` old files. The
+/// internal proxy also removes the old files over the limit when it starts, e.g. after the limit
+/// was lowered.
+///
+/// ```json
+/// {
+///   "internal_proxy": {
+///     "log_destination": "/tmp/mirrord-intproxy.log",
+///     "log_rotation": {
+///       "max_size_mb": 16,
+///       "max_files": 2
+///     }
+///   }
+/// }
+/// ```
+#[derive(MirrordConfig, Clone, Debug)]
+#[config(map_to = "LogRotationFileConfig", derive = "JsonSchema")]
+#[cfg_attr(test, config(derive = "PartialEq, Eq"))]
+pub struct LogRotationConfig {
+    /// ### internal_proxy.log_rotation.max_size_mb {#internal_proxy-log_rotation-max_size_mb}
+    ///
+    /// Size in megabytes after which the log file is rotated.
+    ///
+    /// Defaults to `64`.
+    #[config(default = 64)]
+    pub max_size_mb: u64,
+
+    /// ### internal_proxy.log_rotation.max_files {#internal_proxy-log_rotation-max_files}
+    ///
+    /// How many rotated log files are kept, `0` keeps none.
+    ///
+    /// Defaults to `3`.
+    #[config(default = 3)]
+    pub max_files: usize,
 }
@@ -10,25 +10,41 @@
 pub mod agent;
 pub mod config;
 pub mod experimental;
+pub mod fallback;
 pub mod feature;
+pub mod hooks;
 pub mod internal_proxy;
+pub mod process_override;
+pub mod proxy;
+pub mod session;
 pub mod target;
+pub mod telemetry;
 pub mod util;
 
 use std::{collections::HashSet, ops::Not, path::Path};
 
-use config::{ConfigContext, ConfigError, MirrordConfig};
+use config::{
+    interpolation::interpolate_env, strict::lint_config_file, ConfigContext, ConfigError,
+    MirrordConfig,
+};
 use experimental::ExperimentalConfig;
-use feature::network::outgoing::OutgoingFilterConfig;
+use fallback::FallbackConfig;
+use feature::{
+    fs::{filter::FsFilter, FsConfig},
+    network::outgoing::OutgoingFilterConfig,
+};
 use mirrord_analytics::CollectAnalytics;
 use mirrord_config_derive::MirrordConfig;
 use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use tera::Tera;
 use tracing::warn;
 
 use crate::{
     agent::AgentConfig, config::source::MirrordConfigSource, feature::FeatureConfig,
-    internal_proxy::InternalProxyConfig, target::TargetConfig, util::VecOrSingle,
+    hooks::HooksConfig, internal_proxy::InternalProxyConfig,
+    process_override::ProcessOverrideConfig, proxy::ProxyConfig, session::SessionConfig,
+    target::TargetConfig, telemetry::TelemetryConfig, util::VecOrSingle,
 };
 
 /// mirrord allows for a high degree of customization when it comes to which features you want to
@@ -39,7 +55,12 @@ use crate::{
 ///
 /// The configuration supports templating using the [Tera](https://keats.github.io/tera/docs/) template engine.
 /// Currently we don't provide additional values to the context, if you have anything you want us to
-/// provide please let us know.
+/// provide please let us know. Tera blocks like `{% if get_env(name="CI", default="") %}` can
+/// add or leave out parts of the configuration.
+///
+/// After templating, `${NAME}` is replaced with the value of the environment variable `NAME`,
+/// and `${NAME:-default}` with that value, or with `default` when it's unset or empty. mirrord
+/// fails to start when a variable without a default is unset. Write `$${` for a literal `${`.
 ///
 /// To use a configuration file in the CLI, use the `-f <CONFIG_PATH>` flag.
 /// Or if using VSCode Extension or JetBrains plugin, simply create a `.mirrord/mirrord.json` file
@@ -74,6 +95,17 @@ use crate::{
 /// }
 /// ```
 ///
+/// ### Basic `config.json` with environment variables {#root-basic-env}
+///
+/// ```json
+/// {
+///   "target": {
+///     "path": "${MIRRORD_TARGET:-deployment/app}",
+///     "namespace": "${USER}-dev"
+///   }
+/// }
+/// ```
+///
 /// ### Complete `config.json` {#root-complete}
 ///
 ///  Don't use this example as a starting point, it's just here to show you all the available
@@ -82,6 +114,7 @@ use crate::{
 /// {
 ///   "accept_invalid_certificates": false,
 ///   "skip_processes": "ide-debugger",
+///   "process_overrides": [{ "process": "esbuild", "fs": "local" }],
 ///   "target": {
 ///     "path": "pod/bear-pod",
 ///     "namespace": "default"
@@ -147,7 +180,11 @@ use crate::{
 ///   "kubeconfig": "~/.kube/config",
 ///   "sip_binaries": "bash",
 ///   "telemetry": true,
-///   "kube_context": "my-cluster"
+///   "kube_context": "my-cluster",
+///   "hooks": {
+///     "on_session_start": "open https://grafana.example.com",
+///     "on_disconnect": "echo \"$MIRRORD_DISCONNECT_REASON\""
+///   }
 /// }
 /// ```
 ///
@@ -194,6 +231,72 @@ pub struct LayerConfig {
     #[config(env = "MIRRORD_SKIP_BUILD_TOOLS", default = true)]
     pub skip_build_tools: bool,
 
+    /// ## process_overrides {#root-process_overrides}
+    ///
+    /// Different features for some of the processes started by the application, matched by the
+    /// name of their executable. The first matching override is used.
+    ///
+    /// Useful when the application starts tools that should work locally, while the rest of it
+    /// uses the cluster. [`skip_processes`](#root-skip_processes) doesn't load mirrord into the
+    /// process at all.
+    ///
+    /// ```json
+    /// {
+    ///   "feature": { "fs": "read" },
+    ///   "process_overrides": [
+    ///     { "process": "esbuild", "fs": "local", "incoming": "off", "outgoing": false }
+    ///   ]
+    /// }
+    /// ```
+    pub process_overrides: Option<Vec<ProcessOverrideConfig>>,
+
+    /// ## simulate {#root-simulate}
+    ///
+    /// Runs the application without a cluster, to check what mirrord would do with your
+    /// configuration.
+    ///
+    /// mirrord doesn't start an agent, and every file, connection, DNS lookup and port
+    /// subscription that would be handled remotely is printed to stderr, prefixed with
+    /// `mirrord (simulated):`, and done locally instead.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// ```json
+    /// {
+    ///   "simulate": true
+    /// }
+    /// ```
+    #[config(env = "MIRRORD_SIMULATE", default = false)]
+    pub simulate: bool,
+
+    /// ## debugger_mode {#root-debugger_mode}
+    ///
+    /// Keeps the session alive while the application sits at a breakpoint, for as long as it
+    /// takes.
+    ///
+    /// - The internal proxy waits at least 30 minutes for the application to connect, and for it
+    ///   to reconnect when the debugger restarts it (see
+    ///   [`internal_proxy`](#root-internal_proxy)). The agent, and the operator session, last as
+    ///   long as the internal proxy.
+    /// - Stolen connections wait for the application,
+    ///   [`feature.network.incoming.on_stall`](#feature-network-incoming-on_stall) doesn't apply.
+    /// - Stolen HTTP requests wait for the application's response, with
+    ///   [`feature.network.incoming.on_local_error`](#feature-network-incoming-on_local_error) set
+    ///   to `"fallback"` they are no longer passed to the remote target after 30 seconds.
+    ///
+    /// When `on_stall` or `on_local_error: "fallback"` is set, the session also enters this mode
+    /// on its own once it finds the application stopped by a debugger.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// ```json
+    /// {
+    ///   "debugger_mode": true
+    /// }
+    /// ```
+    #[config(env = "MIRRORD_DEBUGGER_MODE", default = false)]
+    pub debugger_mode: bool,
+
     /// ## connect_tcp {#root-connect_tpc}
     ///
     /// IP:PORT to connect to instead of using k8s api, for testing purposes.
@@ -257,12 +360,8 @@ pub struct LayerConfig {
     pub feature: FeatureConfig,
 
     /// ## telemetry {#root-telemetry}
-    /// Controls whether or not mirrord sends telemetry data to MetalBear cloud.
-    /// Telemetry sent doesn't contain personal identifiers or any data that
-    /// should be considered sensitive. It is used to improve the product.
-    /// [For more information](https://github.com/metalbear-co/mirrord/blob/main/TELEMETRY.md)
-    #[config(env = "MIRRORD_TELEMETRY", default = true)]
-    pub telemetry: bool,
+    #[config(nested)]
+    pub telemetry: TelemetryConfig,
 
     /// ## kube_context {#root-kube_context}
     ///
@@ -291,9 +390,25 @@ pub struct LayerConfig {
     #[config(env = "MIRRORD_PROXY", default = true)]
     pub use_proxy: bool,
 
+    /// # proxy {#root-proxy}
+    #[config(nested)]
+    pub proxy: ProxyConfig,
+
     /// # experimental {#root-experimental}
     #[config(nested)]
     pub experimental: ExperimentalConfig,
+
+    /// # hooks {#root-hooks}
+    #[config(nested)]
+    pub hooks: HooksConfig,
+
+    /// # session {#root-session}
+    #[config(nested)]
+    pub session: SessionConfig,
+
+    /// # fallback {#root-fallback}
+    #[config(nested)]
+    pub fallback: FallbackConfig,
 }
 
 impl LayerConfig {
@@ -326,7 +441,27 @@ impl LayerConfig {
     /// - `ide`: Identifies if this is being called from an IDE context, when using
     /// `mirrord verify-config`. Turns some _target missing_ errors into warnings, as the target can
     /// be selected after `verify-config` is run.
+    ///
+    /// The unknown and deprecated fields of [`ConfigContext::strict`] mode are checked in the file
+    /// itself, see [`LayerFileConfig::from_path_with_context`].
     pub fn verify(&self, context: &mut ConfigContext) -> Result<(), ConfigError> {
+        if self.agent.unprivileged && self.agent.privileged {
+            return Err(ConfigError::Conflict(
+                "`agent.unprivileged` and `agent.privileged` can't be used together.".to_string(),
+            ));
+        }
+
+        if self.agent.unprivileged
+            && !self.agent.ephemeral
+            && !matches!(self.target.path, None | Some(target::Target::Targetless))
+        {
+            return Err(ConfigError::Conflict(
+                "`agent.unprivileged` requires `agent.ephemeral` when there's a target, as the \
+                 agent can't enter the target's namespaces without privileges."
+                    .to_string(),
+            ));
+        }
+
         if self.agent.ephemeral && self.agent.namespace.is_some() {
             context.add_warning(
                 "Agent namespace is ignored when using an ephemeral container for the agent."
@@ -346,6 +481,15 @@ impl LayerConfig {
             );
         }
 
+        for process_override in self.process_overrides.iter().flatten() {
+            if process_override.regex().is_err() {
+                Err(ConfigError::InvalidValue(
+                    process_override.process.clone(),
+                    "process_overrides.process",
+                ))?
+            }
+        }
+
         if self
             .feature
             .network
@@ -366,6 +510,62 @@ impl LayerConfig {
             ))?
         }
 
+        let http_filter = &self.feature.network.incoming.http_filter;
+        if let Some(grpc_filter) = &http_filter.grpc_filter {
+            if http_filter.header_filter.is_some() || http_filter.path_filter.is_some() {
+                Err(ConfigError::Conflict(
+                    "Cannot use the HTTP gRPC filter together with the header filter or path \
+                    filter"
+                        .to_string(),
+                ))?
+            }
+
+            if http_filter.grpc_service_method().is_none() {
+                Err(ConfigError::InvalidValue(
+                    grpc_filter.clone(),
+                    "feature.network.incoming.http_filter.grpc_filter",
+                ))?
+            }
+        }
+
+        if http_filter.routing_header.is_some()
+            && (http_filter.header_filter.is_some()
+                || http_filter.path_filter.is_some()
+                || http_filter.grpc_filter.is_some())
+        {
+            Err(ConfigError::Conflict(
+                "Cannot use `feature.network.incoming.http_filter.routing_header` together with \
+                the HTTP header filter, path filter or gRPC filter, the routing header is the \
+                filter"
+                    .to_string(),
+            ))?
+        }
+
+        if http_filter.shadow_diff.is_some() && !http_filter.is_filter_set() {
+            Err(ConfigError::Conflict(
+                "`feature.network.incoming.http_filter.shadow_diff` requires an HTTP header \
+                filter, path filter or gRPC filter, only the requests that match it are shadowed"
+                    .to_string(),
+            ))?
+        }
+
+        self.feature
+            .network
+            .incoming
+            .http_filter_lints()
+            .into_iter()
+            .for_each(|lint| context.add_warning(lint.to_string()));
+
+        if let Some((start, end)) = self
+            .internal_proxy
+            .port_range
+            .filter(|(start, end)| start > end)
+        {
+            Err(ConfigError::Conflict(format!(
+                "`internal_proxy.port_range` starts after it ends ({start} > {end})"
+            )))?
+        }
+
         if !self.feature.network.incoming.ignore_ports.is_empty()
             && self.feature.network.incoming.ports.is_some()
         {
@@ -397,18 +597,50 @@ impl LayerConfig {
             }
         }
 
+        if self.target.wait_for_run.is_some() {
+            if !matches!(self.target.path, Some(target::Target::CronJob(_))) {
+                Err(ConfigError::Conflict(
+                    "`target.wait_for_run` can only be used when the target is a CronJob".into(),
+                ))?
+            }
+
+            if self.feature.copy_target.enabled {
+                Err(ConfigError::Conflict(
+                    "`target.wait_for_run` targets the Job started by the CronJob, it can't be \
+                     used with `feature.copy_target`"
+                        .into(),
+                ))?
+            }
+        }
+
+        if self.feature.target_logs
+            && (self.feature.copy_target.enabled || self.target.wait_for_run.is_some())
+        {
+            Err(ConfigError::Conflict(
+                "`feature.target_logs` streams the logs of the original target, it can't be used \
+                 with `feature.copy_target` or `target.wait_for_run`"
+                    .into(),
+            ))?
+        }
+
+        if self.feature.pause_autoscaling && self.feature.copy_target.enabled {
+            Err(ConfigError::Conflict(
+                "`feature.pause_autoscaling` pauses the autoscalers of the original target, it \
+                 can't be used with `feature.copy_target`"
+                    .into(),
+            ))?
+        }
+
         if !self.feature.copy_target.enabled
             && self
                 .target
                 .path
                 .as_ref()
-                .map(|target| {
-                    matches!(
-                        target,
-                        target::Target::Job(_)
-                            | target::Target::CronJob(_)
-                            | target::Target::StatefulSet(_)
-                    )
+                .map(|target| match target {
+                    target::Target::Job(_) | target::Target::StatefulSet(_) => true,
+                    // With `wait_for_run` we target the Job's pod directly.
+                    target::Target::CronJob(_) => self.target.wait_for_run.is_none(),
+                    _ => false,
                 })
                 .unwrap_or_default()
         {
@@ -435,6 +667,36 @@ impl LayerConfig {
                         .into(),
                 ))?
             }
+
+            if self.feature.target_logs {
+                Err(ConfigError::Conflict(
+                    "`feature.target_logs` needs a target, please either disable this option or \
+                     specify a target."
+                        .into(),
+                ))?
+            }
+        }
+
+        if let Some(join) = &self.session.join {
+            if u64::from_str_radix(join, 16).is_err() {
+                Err(ConfigError::InvalidValue(join.clone(), "session.join"))?
+            }
+
+            if self.operator == Some(false) {
+                Err(ConfigError::Conflict(
+                    "`session.join` attaches to an operator session, it requires the mirrord \
+                     operator"
+                        .into(),
+                ))?
+            }
+
+            if self.feature.copy_target.enabled {
+                Err(ConfigError::Conflict(
+                    "`session.join` attaches to the target of the shared session, it can't be \
+                     used with `feature.copy_target`"
+                        .into(),
+                ))?
+            }
         }
 
         if self.feature.copy_target.enabled {
@@ -483,6 +745,63 @@ impl LayerConfig {
             );
         }
 
+        if let Some(cwd) = self.feature.fs.cwd.as_deref() {
+            if cwd != FsConfig::REMOTE_CWD && !Path::new(cwd).is_absolute() {
+                return Err(ConfigError::InvalidValue(
+                    cwd.to_string(),
+                    "feature.fs.cwd (must be \"remote\" or an absolute path)",
+                ));
+            }
+        }
+
+        if let Err(fail) = FsFilter::new(&self.feature.fs) {
+            return Err(ConfigError::InvalidValue(
+                fail.to_string(),
+                "feature.fs (invalid file path pattern)",
+            ));
+        }
+
+        if let Some(path) = self
+            .feature
+            .fs
+            .image_paths
+            .iter()
+            .flat_map(|paths| paths.iter())
+            .find(|path| !Path::new(path).is_absolute())
+        {
+            return Err(ConfigError::InvalidValue(
+                path.to_string(),
+                "feature.fs.image_paths (must be absolute paths)",
+            ));
+        }
+
+        if let Some(path) = self
+            .feature
+            .fs
+            .local_override
+            .iter()
+            .flat_map(|overrides| overrides.keys())
+            .find(|path| !Path::new(path).is_absolute())
+        {
+            return Err(ConfigError::InvalidValue(
+                path.to_string(),
+                "feature.fs.local_override (remote prefixes must be absolute paths)",
+            ));
+        }
+
+        if let Some(path) = self
+            .feature
+            .fs
+            .shared_scratch
+            .as_ref()
+            .filter(|path| !Path::new(path).is_absolute())
+        {
+            return Err(ConfigError::InvalidValue(
+                path.to_string(),
+                "feature.fs.shared_scratch (must be an absolute path)",
+            ));
+        }
+
         if self.feature.env.exclude.is_some() && self.feature.env.include.is_some() {
             return Err(ConfigError::Conflict(
                 "cannot use both `include` and `exclude` filters for environment variables"
@@ -501,9 +820,16 @@ impl CollectAnalytics for &LayerConfig {
             self.accept_invalid_certificates,
         );
         analytics.add("use_kubeconfig", self.kubeconfig.is_some());
+        analytics.add("simulate", self.simulate);
+        analytics.add("debugger_mode", self.debugger_mode);
         (&self.target).collect_analytics(analytics);
         (&self.agent).collect_analytics(analytics);
         (&self.feature).collect_analytics(analytics);
+        analytics.add("hooks", &self.hooks);
+        analytics.add("session", &self.session);
+        analytics.add("fallback", &self.fallback);
+        analytics.add("proxy", &self.proxy);
+        analytics.add("telemetry", &self.telemetry);
     }
 }
 
@@ -512,14 +838,41 @@ impl LayerFileConfig {
     where
         P: AsRef<Path>,
     {
+        Self::parse(path.as_ref())
+    }
+
+    /// Like [`LayerFileConfig::from_path`], but in [`ConfigContext::strict`] mode the unknown and
+    /// deprecated fields of the file are first added to the `context` as
+    /// [`ConfigLint`](config::strict::ConfigLint)s, so that they're reported even when the file
+    /// fails to deserialize.
+    pub fn from_path_with_context<P>(
+        path: P,
+        context: &mut ConfigContext,
+    ) -> Result<Self, ConfigError>
+    where
+        P: AsRef<Path>,
+    {
+        if context.strict {
+            let file = Self::parse::<serde_json::Value>(path.as_ref())?;
+            lint_config_file(&schemars::schema_for!(LayerFileConfig), &file)
+                .into_iter()
+                .for_each(|lint| context.add_lint(lint));
+        }
+
+        Self::parse(path.as_ref())
+    }
+
+    /// Renders the template at `path` and deserializes it according to its extension.
+    fn parse<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
         let mut template_engine = Tera::default();
-        template_engine.add_template_file(path.as_ref(), Some("main"))?;
+        template_engine.add_template_file(path, Some("main"))?;
         let rendered = template_engine.render("main", &tera::Context::new())?;
+        let rendered = interpolate_env(&rendered, |name| std::env::var(name).ok())?;
 
-        match path.as_ref().extension().and_then(|os_val| os_val.to_str()) {
-            Some("json") => Ok(serde_json::from_str::<Self>(&rendered)?),
-            Some("toml") => Ok(toml::from_str::<Self>(&rendered)?),
-            Some("yaml" | "yml") => Ok(serde_yaml::from_str::<Self>(&rendered)?),
+        match path.extension().and_then(|os_val| os_val.to_str()) {
+            Some("json") => Ok(serde_json::from_str::<T>(&rendered)?),
+            Some("toml") => Ok(toml::from_str::<T>(&rendered)?),
+            Some("yaml" | "yml") => Ok(serde_yaml::from_str::<T>(&rendered)?),
             _ => Err(ConfigError::UnsupportedFormat),
         }
     }
@@ -724,6 +1077,9 @@ mod tests {
             }),
             skip_processes: None,
             skip_build_tools: None,
+            process_overrides: None,
+            simulate: None,
+            debugger_mode: None,
             agent: Some(AgentFileConfig {
                 privileged: None,
                 log_level: Some("info".to_owned()),
@@ -762,6 +1118,12 @@ mod tests {
                             listen_ports: None,
                             on_concurrent_steal: None,
                             ports: None,
+                            privileged_bind: None,
+                            on_local_error: None,
+                            on_stall: None,
+                            stall_timeout: None,
+                            max_concurrent: None,
+                            queue_size: None,
                         }),
                     ))),
                     outgoing: Some(ToggleableConfig::Config(OutgoingFileConfig {
@@ -772,6 +1134,8 @@ mod tests {
                 })),
                 copy_target: None,
                 hostname: None,
+                target_logs: None,
+                pause_autoscaling: None,
             }),
             connect_tcp: None,
             operator: None,
@@ -779,7 +1143,11 @@ mod tests {
             kube_context: None,
             internal_proxy: None,
             use_proxy: None,
+            proxy: None,
             experimental: None,
+            hooks: None,
+            session: None,
+            fallback: None,
         };
 
         assert_eq!(config, expect);
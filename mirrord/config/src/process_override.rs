@@ -0,0 +1,90 @@
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::feature::{fs::FsModeConfig, network::incoming::IncomingMode, FeatureConfig};
+
+/// Features of the processes whose executable name matches `process`, instead of the ones in
+/// [`feature`](#feature).
+///
+/// Only the given features are replaced, the rest of the config stays the same.
+///
+/// ```json
+/// {
+///   "process_overrides": [
+///     { "process": "esbuild", "fs": "local", "incoming": "off", "outgoing": false },
+///     { "process": "python3(\\.\\d+)?", "fs": "write" }
+///   ]
+/// }
+/// ```
+#[derive(Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessOverrideConfig {
+    /// ### process_overrides[].process {#root-process_overrides-process}
+    ///
+    /// Regex for the name of the executable, e.g. `node`, or `python3(\\.\\d+)?`. It has to
+    /// match the whole name (so `node` doesn't match `node-gyp`), either of the executable file
+    /// or as the process was invoked.
+    pub process: String,
+
+    /// ### process_overrides[].fs {#root-process_overrides-fs}
+    ///
+    /// Replaces [`feature.fs.mode`](#feature-fs-mode), e.g. `"local"`.
+    pub fs: Option<FsModeConfig>,
+
+    /// ### process_overrides[].incoming {#root-process_overrides-incoming}
+    ///
+    /// Replaces [`feature.network.incoming.mode`](#feature-network-incoming-mode), e.g.
+    /// `"off"`.
+    pub incoming: Option<IncomingMode>,
+
+    /// ### process_overrides[].outgoing {#root-process_overrides-outgoing}
+    ///
+    /// Replaces both [`feature.network.outgoing.tcp`](#feature-network-outgoing-tcp) and
+    /// [`feature.network.outgoing.udp`](#feature-network-outgoing-udp).
+    pub outgoing: Option<bool>,
+}
+
+impl ProcessOverrideConfig {
+    /// [`ProcessOverrideConfig::process`] as a regex matching the whole name.
+    pub fn regex(&self) -> Result<Regex, regex::Error> {
+        Regex::new(&format!("^(?:{})$", self.process))
+    }
+
+    /// Replaces the features given in this override.
+    pub fn apply(&self, feature: &mut FeatureConfig) {
+        if let Some(mode) = self.fs {
+            feature.fs.mode = mode;
+        }
+
+        if let Some(mode) = self.incoming {
+            feature.network.incoming.mode = mode;
+        }
+
+        if let Some(outgoing) = self.outgoing {
+            feature.network.outgoing.tcp = outgoing;
+            feature.network.outgoing.udp = outgoing;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_name() {
+        let process_override = ProcessOverrideConfig {
+            process: "node|python3(\\.\\d+)?".to_string(),
+            fs: None,
+            incoming: None,
+            outgoing: None,
+        };
+        let regex = process_override.regex().unwrap();
+
+        assert!(regex.is_match("node"));
+        assert!(regex.is_match("python3.11"));
+        assert!(!regex.is_match("node-gyp"));
+        assert!(!regex.is_match("python3-config"));
+    }
+}
@@ -0,0 +1,78 @@
+use mirrord_analytics::CollectAnalytics;
+use mirrord_config_derive::MirrordConfig;
+use schemars::JsonSchema;
+
+use crate::config::source::MirrordConfigSource;
+
+/// Limits of the mirrord session, the operator session it joins, and whether it follows changes
+/// of the config file.
+///
+/// ```json
+/// {
+///   "session": {
+///     "max_duration": 14400,
+///     "join": "a1b2c3d4e5f60718",
+///     "hot_reload": true
+///   }
+/// }
+/// ```
+#[derive(MirrordConfig, Clone, Debug, Default)]
+#[config(map_to = "SessionFileConfig", derive = "JsonSchema")]
+#[cfg_attr(test, config(derive = "PartialEq, Eq"))]
+pub struct SessionConfig {
+    /// ### session.max_duration {#session-max_duration}
+    ///
+    /// Maximum duration of the session in seconds, after which it ends on its own.
+    ///
+    /// mirrord warns 5 minutes before the session expires. When it does, the application gets a
+    /// `SIGTERM`, and if it's still running a few seconds later, it exits with code 124. The
+    /// agent enforces the limit as well, so the session ends even if the local mirrord processes
+    /// were killed.
+    ///
+    /// The reason is passed to the [`hooks.on_disconnect`](#hooks-on_disconnect) hook.
+    #[config(env = "MIRRORD_SESSION_MAX_DURATION")]
+    pub max_duration: Option<u64>,
+
+    /// ### session.join {#session-join}
+    ///
+    /// Id (hex) of an operator session that was shared with you with
+    /// `mirrord operator session share`, to attach to it instead of starting a new one.
+    ///
+    /// Your application gets a copy of the traffic that the session steals (as if it was
+    /// mirroring), so you can debug the same requests as your teammate. The operator checks that
+    /// the session was shared with you, and that the [`target`](#root-target) is the target of
+    /// the session.
+    ///
+    /// Requires the mirrord operator.
+    #[config(env = "MIRRORD_SESSION_JOIN")]
+    pub join: Option<String>,
+
+    /// ### session.hot_reload {#session-hot_reload}
+    ///
+    /// Apply the changes of the config file to the running session, without restarting the
+    /// application.
+    ///
+    /// The internal proxy checks the file every second, and when it changes (and is still valid),
+    /// the running processes start using:
+    ///
+    /// - the HTTP filter of [`feature.network.incoming`](#feature-network-incoming), for the ports
+    ///   that are already stolen too;
+    /// - the path lists of [`feature.fs`](#feature-fs) (`read_only`, `read_write`, `local`, ...);
+    /// - the [`feature.network.outgoing.filter`](#feature.network.outgoing.filter);
+    /// - the values of [`feature.env.override`](#feature-env-override).
+    ///
+    /// Other changes (e.g. the target, or switching between `mirror` and `steal`) need a new
+    /// session, they are ignored.
+    ///
+    /// Defaults to `false`.
+    #[config(env = "MIRRORD_SESSION_HOT_RELOAD", default = false)]
+    pub hot_reload: bool,
+}
+
+impl CollectAnalytics for &SessionConfig {
+    fn collect_analytics(&self, analytics: &mut mirrord_analytics::Analytics) {
+        analytics.add("max_duration", self.max_duration.is_some());
+        analytics.add("join", self.join.is_some());
+        analytics.add("hot_reload", self.hot_reload);
+    }
+}
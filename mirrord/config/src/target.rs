@@ -3,7 +3,7 @@ use std::{
     str::FromStr,
 };
 
-use cron_job::CronJobTarget;
+use cron_job::{CronJobTarget, WaitForRun};
 use mirrord_analytics::CollectAnalytics;
 use schemars::{gen::SchemaGenerator, schema::SchemaObject, JsonSchema};
 use serde::{Deserialize, Serialize};
@@ -44,6 +44,7 @@ pub enum TargetFileConfig {
         #[serde(default, deserialize_with = "string_or_struct_option")]
         path: Option<Target>,
         namespace: Option<String>,
+        wait_for_run: Option<WaitForRun>,
     },
 }
 
@@ -128,6 +129,18 @@ pub struct TargetConfig {
     ///
     /// Defaults to `"default"`.
     pub namespace: Option<String>,
+
+    /// ### target.wait_for_run {#target-wait_for_run}
+    ///
+    /// When targeting a `cronjob/{sample-cronjob}`, don't look for a running Job, get the pod of
+    /// the next one instead, and end the session when that Job finishes.
+    ///
+    /// - `"next"`: wait for the CronJob to start its next Job on schedule;
+    /// - `"trigger"`: start a Job from the CronJob's template right away.
+    ///
+    /// Set with `mirrord exec --wait-for-run [next|trigger]`.
+    #[serde(default)]
+    pub wait_for_run: Option<WaitForRun>,
 }
 
 impl Default for TargetFileConfig {
@@ -154,6 +167,14 @@ impl TargetFileConfig {
             .source_value(context)
             .transpose()
     }
+
+    /// Get how to wait for a CronJob run from the env var, `Ok(None)` if not set, `Err` if
+    /// invalid value.
+    fn get_wait_for_run_from_env(context: &mut ConfigContext) -> Result<Option<WaitForRun>> {
+        FromEnv::new("MIRRORD_TARGET_WAIT_FOR_RUN")
+            .source_value(context)
+            .transpose()
+    }
 }
 
 impl MirrordConfig for TargetFileConfig {
@@ -162,15 +183,27 @@ impl MirrordConfig for TargetFileConfig {
     /// Generate the final config object, out of the configuration parsed from a configuration file,
     /// factoring in environment variables (which are also set by the front end - CLI/IDE-plugin).
     fn generate_config(self, context: &mut ConfigContext) -> Result<Self::Generated> {
-        let (path_from_conf_file, namespace_from_conf_file) = match self {
-            TargetFileConfig::Simple(path) => (path, None),
-            TargetFileConfig::Advanced { path, namespace } => (path, namespace),
-        };
+        let (path_from_conf_file, namespace_from_conf_file, wait_for_run_from_conf_file) =
+            match self {
+                TargetFileConfig::Simple(path) => (path, None, None),
+                TargetFileConfig::Advanced {
+                    path,
+                    namespace,
+                    wait_for_run,
+                } => (path, namespace, wait_for_run),
+            };
 
         // Env overrides configuration if both there.
         let path = Self::get_target_path_from_env(context)?.or(path_from_conf_file);
         let namespace = Self::get_target_namespace_from_env(context)?.or(namespace_from_conf_file);
-        Ok(TargetConfig { path, namespace })
+        let wait_for_run =
+            Self::get_wait_for_run_from_env(context)?.or(wait_for_run_from_conf_file);
+
+        Ok(TargetConfig {
+            path,
+            namespace,
+            wait_for_run,
+        })
     }
 }
 
@@ -475,7 +508,8 @@ mod tests {
     #[case(None, None,
         TargetConfig {
             path: None,
-            namespace: None
+            namespace: None,
+            wait_for_run: None
         }
     )] // Nothing specified - no target config (targetless mode).
     #[case(
@@ -483,7 +517,8 @@ mod tests {
         Some("ns"),
         TargetConfig{
             path: None,
-            namespace: Some("ns".to_string())
+            namespace: Some("ns".to_string()),
+            wait_for_run: None
         }
     )] // Namespace without target - error.
     #[case(
@@ -491,7 +526,8 @@ mod tests {
         None,
         TargetConfig{
             path: Some(Target::Pod(PodTarget {pod: "foo".to_string(), container: None})),
-            namespace: None
+            namespace: None,
+            wait_for_run: None
         }
     )] // Only pod specified
     #[case(
@@ -502,7 +538,8 @@ mod tests {
                 pod: "foo".to_string(),
                 container: Some("bar".to_string())
             })),
-            namespace: None
+            namespace: None,
+            wait_for_run: None
         }
     )] // Pod and container specified.
     #[case(
@@ -510,7 +547,8 @@ mod tests {
         Some("baz"),
         TargetConfig{
             path: Some(Target::Pod(PodTarget {pod: "foo".to_string(), container: None})),
-            namespace: Some("baz".to_string())
+            namespace: Some("baz".to_string()),
+            wait_for_run: None
         }
     )] // Pod and namespace specified.
     #[case(
@@ -521,7 +559,8 @@ mod tests {
                 rollout: "foo".to_string(),
                 container: None
             })),
-            namespace: None
+            namespace: None,
+            wait_for_run: None
         }
     )] // Rollout specified.
     fn default(
@@ -563,7 +602,8 @@ mod tests {
         r#"{ "namespace": "my-test-namespace" }"#,
         TargetConfig {
             path: None,
-            namespace: Some("my-test-namespace".to_string())
+            namespace: Some("my-test-namespace".to_string()),
+            wait_for_run: None
         }
     )]
     // simple variant of file config - path string, not an object.
@@ -571,7 +611,8 @@ mod tests {
         r#""pod/my-cool-pod""#,
         TargetConfig{
             path: Some(Target::Pod(PodTarget {pod: "my-cool-pod".to_string(), container: None})),
-            namespace: None
+            namespace: None,
+            wait_for_run: None
         }
     )]
     // advanced variant of file config.
@@ -579,7 +620,8 @@ mod tests {
         r#"{ "path": "pod/my-cool-pod" }"#,
         TargetConfig{
             path: Some(Target::Pod(PodTarget {pod: "my-cool-pod".to_string(), container: None})),
-            namespace: None
+            namespace: None,
+            wait_for_run: None
         }
     )]
     // advanced variant of file config, with object as path.
@@ -591,7 +633,8 @@ mod tests {
         }"#,
         TargetConfig{
             path: Some(Target::Pod(PodTarget {pod: "my-cool-pod".to_string(), container: None})),
-            namespace: None
+            namespace: None,
+            wait_for_run: None
         }
     )]
     fn parse_target_config_from_json(
@@ -618,4 +661,28 @@ mod tests {
             || verify_config(config_json_string, &expected_target_config),
         );
     }
+
+    #[test]
+    fn wait_for_run_from_env() {
+        with_env_vars(
+            vec![
+                ("MIRRORD_IMPERSONATED_TARGET", Some("cronjob/nightly")),
+                ("MIRRORD_TARGET_NAMESPACE", None),
+                ("MIRRORD_TARGET_WAIT_FOR_RUN", Some("trigger")),
+            ],
+            || {
+                verify_config(
+                    r#"{ "wait_for_run": "next" }"#,
+                    &TargetConfig {
+                        path: Some(Target::CronJob(CronJobTarget {
+                            cron_job: "nightly".to_string(),
+                            container: None,
+                        })),
+                        namespace: None,
+                        wait_for_run: Some(WaitForRun::Trigger),
+                    },
+                )
+            },
+        );
+    }
 }
@@ -0,0 +1,147 @@
+//! Config for `telemetry`. Like [`copy_target`](crate::feature::copy_target), it's either a bool
+//! or an object, so that `"telemetry": false` keeps working.
+
+use mirrord_analytics::CollectAnalytics;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::config::{
+    from_env::FromEnv, source::MirrordConfigSource, ConfigContext, FromMirrordConfig,
+    MirrordConfig, Result,
+};
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+#[serde(untagged, deny_unknown_fields)]
+pub enum TelemetryFileConfig {
+    Simple(bool),
+    Advanced {
+        enabled: Option<bool>,
+        otlp_endpoint: Option<String>,
+    },
+}
+
+impl Default for TelemetryFileConfig {
+    fn default() -> Self {
+        Self::Simple(true)
+    }
+}
+
+impl MirrordConfig for TelemetryFileConfig {
+    type Generated = TelemetryConfig;
+
+    fn generate_config(self, context: &mut ConfigContext) -> Result<Self::Generated> {
+        let (enabled, otlp_endpoint) = match self {
+            Self::Simple(enabled) => (enabled, None),
+            Self::Advanced {
+                enabled,
+                otlp_endpoint,
+            } => (enabled.unwrap_or(true), otlp_endpoint),
+        };
+
+        // Env overrides configuration if both there.
+        let enabled = FromEnv::new("MIRRORD_TELEMETRY")
+            .source_value(context)
+            .transpose()?
+            .unwrap_or(enabled);
+        let otlp_endpoint = FromEnv::new("MIRRORD_TELEMETRY_OTLP_ENDPOINT")
+            .source_value(context)
+            .transpose()?
+            .or(otlp_endpoint);
+
+        Ok(TelemetryConfig {
+            enabled,
+            otlp_endpoint,
+        })
+    }
+}
+
+impl FromMirrordConfig for TelemetryConfig {
+    type Generator = TelemetryFileConfig;
+}
+
+/// Controls whether or not mirrord sends telemetry data to MetalBear cloud.
+/// Telemetry sent doesn't contain personal identifiers or any data that
+/// should be considered sensitive. It is used to improve the product.
+/// [For more information](https://github.com/metalbear-co/mirrord/blob/main/TELEMETRY.md)
+///
+/// ```json
+/// {
+///   "telemetry": false
+/// }
+/// ```
+///
+/// It can also export the traces of mirrord to your own OpenTelemetry collector:
+///
+/// ```json
+/// {
+///   "telemetry": {
+///     "enabled": false,
+///     "otlp_endpoint": "http://localhost:4318"
+///   }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct TelemetryConfig {
+    /// ### telemetry.enabled {#telemetry-enabled}
+    ///
+    /// Whether telemetry data is sent to MetalBear cloud.
+    ///
+    /// Defaults to `true`.
+    pub enabled: bool,
+
+    /// ### telemetry.otlp_endpoint {#telemetry-otlp_endpoint}
+    ///
+    /// OTLP/HTTP endpoint (e.g. an OpenTelemetry Collector) to which the mirrord CLI and the
+    /// internal proxy export their traces, e.g. `"http://localhost:4318"`. `/v1/traces` is added
+    /// to endpoints without a path, and only plain HTTP is supported.
+    ///
+    /// The traces have spans for the session setup, the connection with the agent, and the
+    /// connections and HTTP requests that mirrord steals or mirrors to your application, so you
+    /// can tell how much time mirrord adds to them.
+    ///
+    /// Nothing is exported to MetalBear, this is independent of
+    /// [`telemetry.enabled`](#telemetry-enabled).
+    pub otlp_endpoint: Option<String>,
+}
+
+impl CollectAnalytics for &TelemetryConfig {
+    fn collect_analytics(&self, analytics: &mut mirrord_analytics::Analytics) {
+        analytics.add("otlp_endpoint", self.otlp_endpoint.is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::with_env_vars;
+
+    #[test]
+    fn simple_and_advanced() {
+        with_env_vars(
+            vec![
+                ("MIRRORD_TELEMETRY", None),
+                ("MIRRORD_TELEMETRY_OTLP_ENDPOINT", None),
+            ],
+            || {
+                let mut context = ConfigContext::default();
+
+                let config: TelemetryFileConfig = serde_json::from_str("false").unwrap();
+                let config = config.generate_config(&mut context).unwrap();
+                assert!(!config.enabled);
+                assert_eq!(config.otlp_endpoint, None);
+
+                let config: TelemetryFileConfig =
+                    serde_json::from_str(r#"{ "otlp_endpoint": "http://localhost:4318" }"#)
+                        .unwrap();
+                let config = config.generate_config(&mut context).unwrap();
+                assert!(config.enabled);
+                assert_eq!(
+                    config.otlp_endpoint.as_deref(),
+                    Some("http://localhost:4318")
+                );
+            },
+        );
+    }
+}
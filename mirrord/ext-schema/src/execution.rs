@@ -0,0 +1,31 @@
+//! The session that `mirrord ext` started, for the plugin to run the application with.
+
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Printed by `mirrord ext` once the session is ready, as a JSON string in the `message` of the
+/// last [`FinishedTaskMessage`](crate::progress::FinishedTaskMessage).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct ExecutionInfo {
+    /// [`SCHEMA_VERSION`](crate::SCHEMA_VERSION) of the mirrord that printed this.
+    ///
+    /// Missing in the output of mirrord versions from before it was versioned, `0` then.
+    #[serde(default)]
+    pub schema_version: u32,
+
+    /// Environment variables to run the application with, including the remote ones and the
+    /// ones that load the layer.
+    pub environment: HashMap<String, String>,
+
+    /// Path of the executable to run instead of the requested one, when it was patched for SIP
+    /// on macOS.
+    pub patched_path: Option<String>,
+
+    /// Environment variables to remove from the environment of the application.
+    pub env_to_unset: Vec<String>,
+
+    /// Whether the session uses the mirrord operator.
+    pub uses_operator: bool,
+}
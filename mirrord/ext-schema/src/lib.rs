@@ -0,0 +1,27 @@
+//! Types of the JSON that mirrord prints for the IDE plugins and other tools that run it, so that
+//! they can be parsed with these types (or with the JSON schemas generated from them) instead of
+//! hand-written parsers.
+//!
+//! - [`progress::ProgressMessage`]: the lines that `mirrord ext` (and the other commands with
+//!   `MIRRORD_PROGRESS_MODE=json`) print while starting the session. The last one is a
+//!   [`progress::FinishedTaskMessage`] of the root task, with the [`execution::ExecutionInfo`] as a
+//!   JSON string in its `message`.
+//! - [`verify_config::VerifiedConfig`]: the output of `mirrord verify-config`.
+//! - [`list_targets::ListedTarget`]: the targets printed by `mirrord ls --output json`.
+//!
+//! # Versioning
+//!
+//! [`SCHEMA_VERSION`] is bumped when the JSON changes in a way that breaks existing parsers, i.e.
+//! when a field is removed, renamed, or changes its type. New fields and new variants don't bump
+//! it, so parsers should ignore the fields they don't know, and handle the variants they don't
+//! know (the enums are `#[non_exhaustive]`).
+//!
+//! The version is printed in [`execution::ExecutionInfo::schema_version`].
+
+pub mod execution;
+pub mod list_targets;
+pub mod progress;
+pub mod verify_config;
+
+/// Version of the JSON printed by mirrord, see [Versioning](crate#versioning).
+pub const SCHEMA_VERSION: u32 = 1;
@@ -0,0 +1,61 @@
+//! The output of `mirrord ls --output json`.
+//!
+//! Without `--output`, `mirrord ls` prints only the paths of the targets, as a JSON list of
+//! strings.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::verify_config::TargetType;
+
+/// One of the targets listed by `mirrord ls --output json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct ListedTarget {
+    /// As in `target.path`, e.g. `deployment/app/container/main`.
+    pub path: String,
+    #[serde(rename = "type")]
+    pub target_type: TargetType,
+    /// Name of the pod, deployment, etc.
+    pub name: String,
+    /// The container in the `path`, if any.
+    pub container: Option<String>,
+    /// The containers of the target's pods, without the mesh sidecars.
+    ///
+    /// Empty when mirrord couldn't get the target, e.g. it's missing the permission to.
+    pub containers: Vec<String>,
+    /// `null` when mirrord couldn't get the target, and for CronJobs.
+    pub status: Option<TargetStatus>,
+}
+
+/// How many of the target's pods (or, for a pod, its containers) are ready.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+pub struct TargetStatus {
+    /// Ready replicas of a workload, ready containers of a pod.
+    pub ready: u32,
+    /// Desired replicas of a workload (its parallelism, for a Job), containers of a pod.
+    pub desired: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn listed_target() {
+        let output = json!({
+            "path": "deployment/app/container/main",
+            "type": "deployment",
+            "name": "app",
+            "container": "main",
+            "containers": ["main", "worker"],
+            "status": { "ready": 2, "desired": 3 }
+        });
+
+        let target = serde_json::from_value::<ListedTarget>(output.clone()).unwrap();
+
+        assert_eq!(target.target_type, TargetType::Deployment);
+        assert_eq!(serde_json::to_value(&target).unwrap(), output);
+    }
+}
@@ -0,0 +1,137 @@
+//! The progress messages printed one per line with `MIRRORD_PROGRESS_MODE=json`.
+
+use std::collections::HashSet;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Message sent when a new task is created using subtask/new
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+pub struct NewTaskMessage {
+    /// Task name (indentifier)
+    pub name: String,
+    /// Parent task name, if subtask.
+    pub parent: Option<String>,
+}
+
+/// Message sent when a task is finished.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+pub struct FinishedTaskMessage {
+    /// Finished task name
+    pub name: String,
+    /// Was the task successful?
+    pub success: bool,
+    /// Finish message
+    pub message: Option<String>,
+}
+
+/// Message sent when something might not work as the user expects.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+pub struct WarningMessage {
+    /// Warning message
+    pub message: String,
+}
+
+/// Indicates what type of notification should appear in the IDEs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+#[non_exhaustive]
+pub enum NotificationLevel {
+    /// Normal info box.
+    #[default]
+    Info,
+
+    /// Warning box.
+    Warning,
+}
+
+/// Action/button type that appears in the pop-up notifications.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(tag = "kind")]
+#[non_exhaustive]
+pub enum IdeAction {
+    /// A link action, where `label` is the text, and `link` is the _href_.
+    Link { label: String, link: String },
+}
+
+/// Messages sent to the IDEs with full context.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+pub struct IdeMessage {
+    /// Allows us to identify this message and map it to something meaningful in the IDEs.
+    ///
+    /// Not shown to the user.
+    ///
+    /// In vscode, this should map to a `configEntry` defined in `package.json`.
+    pub id: String,
+
+    /// The level of the notification, the type of pop-up it'll be displayed in the IDEs.
+    pub level: NotificationLevel,
+
+    /// Message content.
+    pub text: String,
+
+    /// Actions/buttons that appears in the pop-up notification.
+    pub actions: HashSet<IdeAction>,
+}
+
+/// The message types that mirrord reports on its progress, one JSON object per line.
+///
+/// These are used by the extensions (vscode and intellij) to show nice notifications.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "type")]
+#[non_exhaustive]
+pub enum ProgressMessage {
+    NewTask(NewTaskMessage),
+    Warning(WarningMessage),
+    FinishedTask(FinishedTaskMessage),
+    Info {
+        message: String,
+    },
+    /// Messages that are passed to the IDE and shown to the user in notification boxes.
+    IdeMessage {
+        /// It's a generic json [`Value`].
+        ///
+        /// Should be an [`IdeMessage`] converted to [`Value`].
+        message: Value,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_lines() {
+        let messages = [
+            (
+                r#"{"type":"NewTask","name":"mirrord preparing to launch","parent":null}"#,
+                ProgressMessage::NewTask(NewTaskMessage {
+                    name: "mirrord preparing to launch".to_string(),
+                    parent: None,
+                }),
+            ),
+            (
+                r#"{"type":"FinishedTask","name":"agent running","success":true,"message":null}"#,
+                ProgressMessage::FinishedTask(FinishedTaskMessage {
+                    name: "agent running".to_string(),
+                    success: true,
+                    message: None,
+                }),
+            ),
+            (
+                r#"{"type":"Warning","message":"no target"}"#,
+                ProgressMessage::Warning(WarningMessage {
+                    message: "no target".to_string(),
+                }),
+            ),
+        ];
+
+        for (line, message) in messages {
+            assert_eq!(serde_json::to_string(&message).unwrap(), line);
+            assert_eq!(
+                serde_json::from_str::<ProgressMessage>(line).unwrap(),
+                message
+            );
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! The output of `mirrord verify-config [--ide] [--rbac] [--cluster] [--strict] {path}`.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The target of the config, as in `target.path`.
+///
+/// Unlike in the config, targetless is the string `"targetless"` and not `null`, so that the IDEs
+/// can tell it apart from a missing target (`null`), for which they show the target selection
+/// dialog.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[non_exhaustive]
+pub enum VerifiedTarget {
+    #[serde(rename = "targetless")]
+    Targetless,
+    #[serde(untagged)]
+    Pod {
+        pod: String,
+        container: Option<String>,
+    },
+    #[serde(untagged)]
+    Deployment {
+        deployment: String,
+        container: Option<String>,
+    },
+    #[serde(untagged)]
+    Rollout {
+        rollout: String,
+        container: Option<String>,
+    },
+    #[serde(untagged)]
+    Job {
+        job: String,
+        container: Option<String>,
+    },
+    #[serde(untagged)]
+    CronJob {
+        cron_job: String,
+        container: Option<String>,
+    },
+    #[serde(untagged)]
+    StatefulSet {
+        stateful_set: String,
+        container: Option<String>,
+    },
+}
+
+/// How the Job is picked when the target is a CronJob, as in `target.wait_for_run`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum WaitForRun {
+    Next,
+    Trigger,
+}
+
+/// The `target` part of the config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct VerifiedTargetConfig {
+    pub path: Option<VerifiedTarget>,
+    pub namespace: Option<String>,
+    pub wait_for_run: Option<WaitForRun>,
+}
+
+/// The types of targets.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum TargetType {
+    Targetless,
+    Pod,
+    Deployment,
+    Rollout,
+    Job,
+    CronJob,
+    StatefulSet,
+}
+
+/// Result of the `--rbac` check, the Kubernetes permissions that mirrord needs to run without the
+/// operator.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "type")]
+#[non_exhaustive]
+pub enum RbacReport {
+    /// We were able to check all permissions, `missing` lists the ones the user doesn't have.
+    Checked {
+        missing: Vec<MissingPermission>,
+        /// When the config uses the operator, these permissions are not needed.
+        operator: bool,
+    },
+    /// We couldn't check the permissions, e.g. the cluster is unreachable.
+    Failed { error: String },
+}
+
+/// A permission that mirrord needs, i.e. a `verb` on a `resource` in a `namespace`, that the user
+/// doesn't have.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct MissingPermission {
+    /// Human readable description, e.g. `create jobs.batch in namespace default (spawn the agent
+    /// job)`.
+    pub description: String,
+    pub verb: String,
+    /// API group of the resource, empty for the core group.
+    pub group: String,
+    pub resource: String,
+    pub subresource: Option<String>,
+    pub namespace: String,
+    /// What mirrord needs this permission for.
+    pub reason: String,
+}
+
+/// Result of one of the `--cluster` checks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "status", rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum ClusterCheck {
+    Ok,
+    Failed {
+        error: String,
+    },
+    /// Can't be checked from here, e.g. the image registry requires credentials.
+    Unknown {
+        reason: String,
+    },
+}
+
+/// Result of the `--cluster` check, whether the session would find what it needs in the cluster.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "type")]
+#[non_exhaustive]
+pub enum ClusterReport {
+    Checked {
+        /// The namespace of the target (the default one of the kube context if not set) exists
+        /// and can be accessed.
+        namespace: ClusterCheck,
+        /// The target exists and has a running container, not checked when targetless.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<ClusterCheck>,
+        /// The agent image exists in its registry.
+        agent_image: ClusterCheck,
+        /// The mirrord operator is installed, `null` if we couldn't check.
+        operator: Option<bool>,
+    },
+    /// We couldn't connect to the cluster.
+    Failed { error: String },
+}
+
+/// An unknown or deprecated field of the config file, found with `--strict`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct StrictWarning {
+    /// E.g. `config/unknown-field`.
+    pub rule: String,
+    /// Path of the field in the config file, e.g. `feature.network.incoming.htttp_filter`.
+    pub path: String,
+    /// For unknown fields, a known field with a similar name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+    /// The same message as in `warnings`.
+    pub message: String,
+}
+
+/// Produced by calling `verify_config`.
+///
+/// It's consumed by the IDEs to check if a config is valid, or missing something, without starting
+/// mirrord fully.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "type")]
+#[non_exhaustive]
+pub enum VerifiedConfig {
+    /// mirrord is able to run with this config, but it might have some issues or weird behavior
+    /// depending on the `warnings`.
+    Success {
+        /// A valid, verified config for the `target` part of mirrord.
+        config: VerifiedTargetConfig,
+        /// Improper combination of features was requested, but mirrord can still run.
+        warnings: Vec<String>,
+        /// Target types compatible with the source config.
+        /// Meant to be used by IDE plugins for customizing target selection.
+        compatible_target_types: Vec<TargetType>,
+        /// Missing Kubernetes permissions, only checked with `--rbac`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rbac: Option<RbacReport>,
+        /// Target, namespace and agent image in the cluster, only checked with `--cluster`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cluster: Option<ClusterReport>,
+        /// Unknown and deprecated fields, only checked with `--strict`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        strict: Option<Vec<StrictWarning>>,
+    },
+    /// Invalid config was detected, mirrord cannot run.
+    ///
+    /// May be triggered by extra/lacking `,`, or invalid fields, etc.
+    Fail {
+        errors: Vec<String>,
+        /// With `--strict`, the unknown and deprecated fields, which often explain the `errors`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        strict: Option<Vec<StrictWarning>>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn targets() {
+        let targets = [
+            (json!("targetless"), VerifiedTarget::Targetless),
+            (
+                json!({ "deployment": "app", "container": null }),
+                VerifiedTarget::Deployment {
+                    deployment: "app".to_string(),
+                    container: None,
+                },
+            ),
+            (
+                json!({ "cron_job": "nightly", "container": "main" }),
+                VerifiedTarget::CronJob {
+                    cron_job: "nightly".to_string(),
+                    container: Some("main".to_string()),
+                },
+            ),
+        ];
+
+        for (value, target) in targets {
+            assert_eq!(serde_json::to_value(&target).unwrap(), value);
+            assert_eq!(
+                serde_json::from_value::<VerifiedTarget>(value).unwrap(),
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn success() {
+        let output = json!({
+            "type": "Success",
+            "config": {
+                "path": { "pod": "app-7c9f", "container": null },
+                "namespace": null,
+                "wait_for_run": null
+            },
+            "warnings": [],
+            "compatible_target_types": ["targetless", "cronjob", "statefulset"]
+        });
+
+        let verified = serde_json::from_value::<VerifiedConfig>(output.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(&verified).unwrap(), output);
+    }
+}
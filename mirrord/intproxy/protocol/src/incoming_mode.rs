@@ -0,0 +1,126 @@
+//! How the layer subscribes ports, from the incoming config. The internal proxy builds it too,
+//! to update the subscriptions when the config file changes (`session.hot_reload`).
+
+use std::collections::HashSet;
+
+use mirrord_config::feature::network::incoming::IncomingConfig;
+use mirrord_protocol::{
+    tcp::{Filter, GrpcFilter, HttpFilter, StealType},
+    Port,
+};
+
+use crate::PortSubscription;
+
+/// HTTP filter used by the layer with the `steal` feature.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StealHttpFilter {
+    /// No filter.
+    None,
+    /// More recent filter (header, path or gRPC).
+    Filter(HttpFilter),
+    /// Filter (header, path or gRPC), the requests that match it are shadowed instead of stolen
+    /// (`feature.network.incoming.http_filter.shadow_diff`).
+    Shadow(HttpFilter),
+}
+
+/// Settings for handling HTTP with the `steal` feature.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StealHttpSettings {
+    /// The HTTP filter to use.
+    pub filter: StealHttpFilter,
+    /// Ports to filter HTTP on.
+    pub ports: HashSet<Port>,
+}
+
+/// Operation mode for the `incoming` feature.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IncomingMode {
+    /// The agent sends data to both the user application and the remote target.
+    /// Data coming from the layer is discarded.
+    Mirror,
+    /// The agent sends data only to the user application.
+    /// Data coming from the layer is sent to the agent.
+    Steal(StealHttpSettings),
+}
+
+impl IncomingMode {
+    /// Creates a new instance from the given [`IncomingConfig`].
+    ///
+    /// Fails when the HTTP filter is invalid.
+    pub fn new(config: &IncomingConfig) -> Result<Self, String> {
+        if !config.is_steal() {
+            return Ok(Self::Mirror);
+        }
+
+        let http_filter_config = &config.http_filter;
+
+        let ports = { http_filter_config.ports.iter().copied().collect() };
+
+        // The value of the routing header is generated by the CLI when the session starts.
+        let header_filter = match &http_filter_config.routing_header {
+            Some(..) => Some(
+                http_filter_config
+                    .routing_filter()
+                    .ok_or("missing routing header value")?,
+            ),
+            None => http_filter_config.header_filter.clone(),
+        };
+
+        let grpc_filter = http_filter_config
+            .grpc_filter
+            .as_ref()
+            .map(|_| {
+                let (service, method) = http_filter_config
+                    .grpc_service_method()
+                    .ok_or("invalid gRPC filter")?;
+
+                Ok::<_, String>(GrpcFilter {
+                    service: service.to_string(),
+                    method: method.map(ToString::to_string),
+                })
+            })
+            .transpose()?;
+
+        let new_filter = |filter: &String| {
+            Filter::new(filter.into())
+                .map_err(|error| format!("invalid filter expression: {error}"))
+        };
+
+        let filter = match (&http_filter_config.path_filter, &header_filter, grpc_filter) {
+            (Some(path), None, None) => {
+                StealHttpFilter::Filter(HttpFilter::Path(new_filter(path)?))
+            }
+            (None, Some(header), None) => {
+                StealHttpFilter::Filter(HttpFilter::Header(new_filter(header)?))
+            }
+            (None, None, Some(grpc)) => StealHttpFilter::Filter(HttpFilter::Grpc(grpc)),
+            (None, None, None) => StealHttpFilter::None,
+            _ => return Err("multiple HTTP filters specified".to_string()),
+        };
+
+        let filter = match filter {
+            StealHttpFilter::Filter(filter) if http_filter_config.shadow_diff.is_some() => {
+                StealHttpFilter::Shadow(filter)
+            }
+            filter => filter,
+        };
+
+        Ok(Self::Steal(StealHttpSettings { filter, ports }))
+    }
+
+    /// Returns [`PortSubscription`] request to be used for the given port.
+    pub fn subscription(&self, port: Port) -> PortSubscription {
+        let Self::Steal(steal) = self else {
+            return PortSubscription::Mirror(port);
+        };
+
+        let steal_type = match &steal.filter {
+            _ if !steal.ports.contains(&port) => StealType::All(port),
+            StealHttpFilter::None => StealType::All(port),
+            StealHttpFilter::Filter(filter) => StealType::FilteredHttpEx(port, filter.clone()),
+            StealHttpFilter::Shadow(filter) => StealType::FilteredHttpShadow(port, filter.clone()),
+        };
+
+        PortSubscription::Steal(steal_type)
+    }
+}
@@ -6,18 +6,24 @@ use std::{
     collections::HashMap,
     fmt,
     net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::SystemTime,
 };
 
 use bincode::{Decode, Encode};
 use mirrord_protocol::{
     dns::{GetAddrInfoRequest, GetAddrInfoResponse},
     file::{
-        AccessFileRequest, AccessFileResponse, CloseDirRequest, CloseFileRequest, FdOpenDirRequest,
-        GetDEnts64Request, GetDEnts64Response, OpenDirResponse, OpenFileRequest, OpenFileResponse,
-        OpenRelativeFileRequest, ReadDirRequest, ReadDirResponse, ReadFileRequest,
-        ReadFileResponse, ReadLimitedFileRequest, ReadLinkFileRequest, ReadLinkFileResponse,
-        SeekFileRequest, SeekFileResponse, WriteFileRequest, WriteFileResponse,
-        WriteLimitedFileRequest, XstatFsRequest, XstatFsResponse, XstatRequest, XstatResponse,
+        AccessFileRequest, AccessFileResponse, CanonicalizeRequest, CanonicalizeResponse,
+        CloseDirRequest, CloseFileRequest, FdOpenDirRequest, FlockRequest, GetDEnts64Request,
+        GetDEnts64Response, GetLockRequest, GetLockResponse, OpenDirResponse, OpenFileRequest,
+        OpenFileResponse, OpenImageFileRequest, OpenOptionsInternal, OpenRelativeFileRequest,
+        ReadDirBatchRequest, ReadDirBatchResponse, ReadDirRequest, ReadDirResponse,
+        ReadFileRequest, ReadFileResponse, ReadLimitedFileRequest, ReadLinkFileRequest,
+        ReadLinkFileResponse, SeekFileRequest, SeekFileResponse, SetLockRequest, WatchAddRequest,
+        WatchAddResponse, WatchEventsRequest, WatchEventsResponse, WatchRemoveRequest,
+        WriteFileRequest, WriteFileResponse, WriteLimitedFileRequest, XstatFsRequest,
+        XstatFsResponse, XstatRequest, XstatResponse,
     },
     outgoing::SocketAddress,
     tcp::StealType,
@@ -26,8 +32,11 @@ use mirrord_protocol::{
 
 #[cfg(feature = "codec")]
 pub mod codec;
+mod incoming_mode;
 mod macros;
 
+pub use incoming_mode::{IncomingMode, StealHttpFilter, StealHttpSettings};
+
 /// An identifier for a message sent from the layer to the internal proxy.
 /// The layer uses this to match proxy responses with awaiting requests.
 pub type MessageId = u64;
@@ -41,12 +50,61 @@ pub struct LocalMessage<T> {
     pub inner: T,
 }
 
+/// Env var with the token that the layers use to authenticate to the internal proxy, see
+/// [`LayerToProxyMessage::Authenticate`].
+pub const INTPROXY_AUTH_TOKEN_ENV: &str = "MIRRORD_INTPROXY_AUTH_TOKEN";
+
+/// Env var with the time when the session expires (`session.max_duration`), in seconds since the
+/// Unix epoch.
+pub const SESSION_DEADLINE_ENV: &str = "MIRRORD_SESSION_DEADLINE";
+
+/// Env var with the address of the internal proxy's SOCKS5 and HTTP proxy server, set when
+/// `feature.network.outgoing.proxy_server` is enabled.
+///
+/// The layer passes it to the processes it can't be loaded into.
+pub const OUTGOING_PROXY_SERVER_ENV: &str = "MIRRORD_OUTGOING_PROXY_SERVER_ADDR";
+
+/// Secret shared by the internal proxy and the layers of a mirrord session, see
+/// [`LayerToProxyMessage::Authenticate`].
+///
+/// Its [`fmt::Debug`] implementation doesn't reveal it, so it doesn't end up in the logs.
+#[derive(Encode, Decode, Clone)]
+pub struct AuthToken(pub String);
+
+impl AuthToken {
+    /// Compares the tokens in constant time, so the time it takes doesn't tell how much of the
+    /// token was guessed right.
+    pub fn matches(&self, other: &Self) -> bool {
+        let (this, other) = (self.0.as_bytes(), other.0.as_bytes());
+
+        this.len() == other.len()
+            && this
+                .iter()
+                .zip(other)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuthToken(..)")
+    }
+}
+
 /// Messages sent by the layer and handled by the internal proxy.
 #[derive(Encode, Decode, Debug)]
 pub enum LayerToProxyMessage {
+    /// Proves that the layer belongs to the processes started by the same mirrord session as the
+    /// internal proxy, with the token from [`INTPROXY_AUTH_TOKEN_ENV`].
+    ///
+    /// When the proxy was given a token, this must be the first message sent by the layer after
+    /// opening a new connection to the internal proxy, followed by
+    /// [`LayerToProxyMessage::NewSession`].
+    Authenticate(AuthToken),
     /// A request to start new `layer <-> proxy` session.
     /// This should be the first message sent by the layer after opening a new connection to the
-    /// internal proxy.
+    /// internal proxy (or the second one, after [`LayerToProxyMessage::Authenticate`]).
     NewSession(NewSessionRequest),
     /// A file operation request.
     File(FileRequest),
@@ -58,6 +116,14 @@ pub enum LayerToProxyMessage {
     Incoming(IncomingRequest),
     /// Fetch environment variables from the target.
     GetEnv(GetEnvVarsRequest),
+    /// Inspects the state of the internal proxy, for the `mirrord session` commands.
+    ///
+    /// Sent instead of [`LayerToProxyMessage::NewSession`], the proxy responds with
+    /// [`ProxyToLayerMessage::Admin`] and closes the connection.
+    Admin(AdminRequest),
+    /// Sent periodically while the application runs, when `feature.network.incoming.on_stall`
+    /// needs to know that the application is stopped.
+    Heartbeat(LayerHeartbeat),
 }
 
 /// Layer process information
@@ -97,6 +163,14 @@ pub enum NewSessionRequest {
     Forked(LayerId),
 }
 
+/// Tells the internal proxy that the application is not stopped, see
+/// [`LayerToProxyMessage::Heartbeat`].
+#[derive(Encode, Decode, Debug)]
+pub struct LayerHeartbeat {
+    /// Of the application, to check whether a debugger stopped it when the heartbeats stop.
+    pub pid: u32,
+}
+
 /// Supported network protocols when intercepting outgoing connections.
 #[derive(Encode, Decode, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum NetProtocol {
@@ -143,6 +217,13 @@ pub enum IncomingRequest {
     /// A request made by the layer when it accepts a connection on the socket that is listening
     /// for mirrored connections.
     ConnMetadata(ConnMetadataRequest),
+    /// A request made by layer when it binds a UDP socket to a port that should receive the
+    /// remote datagrams.
+    UdpPortSubscribe(UdpPortSubscribe),
+    /// A request made by the layer when it closes the UDP socket.
+    UdpPortUnsubscribe(UdpPortUnsubscribe),
+    /// A request made by the layer when it receives a datagram on the UDP socket.
+    UdpPeer(UdpPeerRequest),
 }
 
 /// A request for additional metadata for accepted connection.
@@ -191,7 +272,7 @@ pub struct PortSubscribe {
 }
 
 /// Instructions for the internal proxy and the agent on how to execute port mirroring.
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
 pub enum PortSubscription {
     /// Wrapped [`StealType`] specifies how to execute port mirroring.
     Steal(StealType),
@@ -208,6 +289,61 @@ pub struct PortUnsubscribe {
     pub listening_on: SocketAddr,
 }
 
+/// A request to start proxying datagrams incoming to a remote UDP port.
+///
+/// For each remote peer, the internal proxy sends the datagrams to `listening_on` from a separate
+/// local socket, see [`UdpPeerRequest`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct UdpPortSubscribe {
+    /// Local address to which the layer bound the UDP socket.
+    pub listening_on: SocketAddr,
+    /// Instructions on how to execute the subscription.
+    pub subscription: UdpPortSubscription,
+}
+
+/// Instructions for the internal proxy and the agent on how to execute the UDP port subscription.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpPortSubscription {
+    /// Datagrams coming to the wrapped [`Port`] should be redirected to the layer, and the layer's
+    /// replies sent back to the peers.
+    Steal(Port),
+    /// Datagrams coming to the wrapped [`Port`] should be copied and sent to the layer.
+    Mirror(Port),
+}
+
+impl UdpPortSubscription {
+    /// Returns the remote port of this subscription.
+    pub fn port(&self) -> Port {
+        match self {
+            Self::Steal(port) | Self::Mirror(port) => *port,
+        }
+    }
+}
+
+/// A request to stop proxying datagrams incoming to a remote UDP port.
+#[derive(Encode, Decode, Debug)]
+pub struct UdpPortUnsubscribe {
+    /// Port on the remote pod that layer subscribed to.
+    pub port: Port,
+    /// Local address to which the layer bound the UDP socket.
+    pub listening_on: SocketAddr,
+}
+
+/// A request for the remote peer behind a local address that sent a datagram to the layer's UDP
+/// socket subscribed with [`UdpPortSubscribe`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct UdpPeerRequest {
+    /// Local address from which the datagram was sent.
+    pub local_address: SocketAddr,
+}
+
+/// A response to layer's [`UdpPeerRequest`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct UdpPeerResponse {
+    /// The remote peer, [`None`] if the datagram was not sent by the internal proxy.
+    pub remote_address: Option<SocketAddr>,
+}
+
 /// Messages sent by the internal proxy and handled by the layer.
 #[derive(Encode, Decode, Debug)]
 pub enum ProxyToLayerMessage {
@@ -224,6 +360,71 @@ pub enum ProxyToLayerMessage {
     Incoming(IncomingResponse),
     /// A response to layer's [`LayerToProxyMessage::GetEnv`].
     GetEnv(RemoteResult<HashMap<String, String>>),
+    /// A response to [`LayerToProxyMessage::Admin`].
+    Admin(AdminResponse),
+    /// Sent by the proxy on its own, replaces the log filter of the layer, see
+    /// [`AdminRequest::SetLogLevel`].
+    ///
+    /// The layer reads it with the next response it waits for.
+    SetLogLevel(String),
+    /// Sent by the proxy on its own when the config file changed (`session.hot_reload`), the
+    /// layer loads it again and applies what it can.
+    ///
+    /// The layer reads it with the next response it waits for.
+    ReloadConfig,
+}
+
+/// Requests of the `mirrord session` commands, see [`LayerToProxyMessage::Admin`].
+#[derive(Encode, Decode, Debug)]
+pub enum AdminRequest {
+    /// Lists the remote resources the layers use through file descriptors.
+    RemoteFds,
+    /// Replaces the log filter (`RUST_LOG` directives) of the proxy and of all its layers, for
+    /// `mirrord loglevel`.
+    SetLogLevel(String),
+}
+
+/// A response to [`AdminRequest`].
+#[derive(Encode, Decode, Debug)]
+pub enum AdminResponse {
+    /// A response to [`AdminRequest::RemoteFds`].
+    RemoteFds(Vec<RemoteFdInfo>),
+    /// A response to [`AdminRequest::SetLogLevel`], with the number of layers that were sent the
+    /// new filter, or why the proxy couldn't use it.
+    SetLogLevel(Result<usize, String>),
+}
+
+/// A resource in the agent that backs a file descriptor of the layers, see
+/// [`AdminRequest::RemoteFds`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct RemoteFdInfo {
+    /// Remote descriptor of a file or directory, or id of an outgoing connection.
+    pub remote_fd: u64,
+    pub resource: RemoteFdResource,
+    /// Bytes the layers read from the resource.
+    pub bytes_read: u64,
+    /// Bytes the layers wrote to the resource.
+    pub bytes_written: u64,
+    /// When the last request or data for this resource went through the internal proxy.
+    pub last_activity: SystemTime,
+}
+
+/// What is behind a [`RemoteFdInfo`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum RemoteFdResource {
+    /// File opened in the agent, `path` is [`None`] when it's relative to a directory we don't
+    /// know about.
+    File {
+        path: Option<PathBuf>,
+        open_options: OpenOptionsInternal,
+    },
+    /// Directory opened in the agent from a file descriptor.
+    Dir { path: Option<PathBuf> },
+    /// Outgoing connection made by the agent.
+    Outgoing {
+        protocol: NetProtocol,
+        remote_address: SocketAddress,
+    },
 }
 
 /// A response to layer's [`IncomingRequest`].
@@ -237,6 +438,10 @@ pub enum IncomingResponse {
     PortSubscribe(RemoteResult<()>),
     /// A response to layers' [`ConnMetadataRequest`].
     ConnMetadata(ConnMetadataResponse),
+    /// A response to layer's [`UdpPortSubscribe`].
+    UdpPortSubscribe(RemoteResult<()>),
+    /// A response to layer's [`UdpPeerRequest`].
+    UdpPeer(UdpPeerResponse),
 }
 
 /// A response to layer's [`OutgoingConnectRequest`].
@@ -288,6 +493,13 @@ impl_request!(
     res_path = ProxyToLayerMessage::File => FileResponse::Open,
 );
 
+impl_request!(
+    req = OpenImageFileRequest,
+    res = RemoteResult<OpenFileResponse>,
+    req_path = LayerToProxyMessage::File => FileRequest::OpenImage,
+    res_path = ProxyToLayerMessage::File => FileResponse::Open,
+);
+
 impl_request!(
     req = OpenRelativeFileRequest,
     res = RemoteResult<OpenFileResponse>,
@@ -372,6 +584,20 @@ impl_request!(
     res_path = ProxyToLayerMessage::File => FileResponse::ReadDir,
 );
 
+impl_request!(
+    req = ReadDirBatchRequest,
+    res = RemoteResult<ReadDirBatchResponse>,
+    req_path = LayerToProxyMessage::File => FileRequest::ReadDirBatch,
+    res_path = ProxyToLayerMessage::File => FileResponse::ReadDirBatch,
+);
+
+impl_request!(
+    req = CanonicalizeRequest,
+    res = RemoteResult<CanonicalizeResponse>,
+    req_path = LayerToProxyMessage::File => FileRequest::Canonicalize,
+    res_path = ProxyToLayerMessage::File => FileResponse::Canonicalize,
+);
+
 impl_request!(
     req = GetDEnts64Request,
     res = RemoteResult<GetDEnts64Response>,
@@ -389,6 +615,46 @@ impl_request!(
     req_path = LayerToProxyMessage::File => FileRequest::CloseDir,
 );
 
+impl_request!(
+    req = WatchAddRequest,
+    res = RemoteResult<WatchAddResponse>,
+    req_path = LayerToProxyMessage::File => FileRequest::WatchAdd,
+    res_path = ProxyToLayerMessage::File => FileResponse::WatchAdd,
+);
+
+impl_request!(
+    req = WatchRemoveRequest,
+    req_path = LayerToProxyMessage::File => FileRequest::WatchRemove,
+);
+
+impl_request!(
+    req = WatchEventsRequest,
+    res = RemoteResult<WatchEventsResponse>,
+    req_path = LayerToProxyMessage::File => FileRequest::WatchEvents,
+    res_path = ProxyToLayerMessage::File => FileResponse::WatchEvents,
+);
+
+impl_request!(
+    req = FlockRequest,
+    res = RemoteResult<()>,
+    req_path = LayerToProxyMessage::File => FileRequest::Flock,
+    res_path = ProxyToLayerMessage::File => FileResponse::Flock,
+);
+
+impl_request!(
+    req = SetLockRequest,
+    res = RemoteResult<()>,
+    req_path = LayerToProxyMessage::File => FileRequest::SetLock,
+    res_path = ProxyToLayerMessage::File => FileResponse::SetLock,
+);
+
+impl_request!(
+    req = GetLockRequest,
+    res = RemoteResult<GetLockResponse>,
+    req_path = LayerToProxyMessage::File => FileRequest::GetLock,
+    res_path = ProxyToLayerMessage::File => FileResponse::GetLock,
+);
+
 impl_request!(
     req = GetAddrInfoRequest,
     res = GetAddrInfoResponse,
@@ -422,9 +688,33 @@ impl_request!(
     res_path = ProxyToLayerMessage::Incoming => IncomingResponse::ConnMetadata,
 );
 
+impl_request!(
+    req = UdpPortSubscribe,
+    res = RemoteResult<()>,
+    req_path = LayerToProxyMessage::Incoming => IncomingRequest::UdpPortSubscribe,
+    res_path = ProxyToLayerMessage::Incoming => IncomingResponse::UdpPortSubscribe,
+);
+
+impl_request!(
+    req = UdpPortUnsubscribe,
+    req_path = LayerToProxyMessage::Incoming => IncomingRequest::UdpPortUnsubscribe,
+);
+
+impl_request!(
+    req = UdpPeerRequest,
+    res = UdpPeerResponse,
+    req_path = LayerToProxyMessage::Incoming => IncomingRequest::UdpPeer,
+    res_path = ProxyToLayerMessage::Incoming => IncomingResponse::UdpPeer,
+);
+
 impl_request!(
     req = GetEnvVarsRequest,
     res = RemoteResult<HashMap<String, String>>,
     req_path = LayerToProxyMessage::GetEnv,
     res_path = ProxyToLayerMessage::GetEnv,
 );
+
+impl_request!(
+    req = LayerHeartbeat,
+    req_path = LayerToProxyMessage::Heartbeat,
+);
@@ -1,7 +1,7 @@
 //! Implementation of `proxy <-> agent` connection through [`mpsc`](tokio::sync::mpsc) channels
 //! created in different mirrord crates.
 
-use std::{io, net::SocketAddr};
+use std::{io, net::SocketAddr, time::Duration};
 
 use mirrord_analytics::Reporter;
 use mirrord_config::LayerConfig;
@@ -13,6 +13,7 @@ use mirrord_kube::{
     error::KubeApiError,
 };
 use mirrord_operator::client::{OperatorApi, OperatorApiError, OperatorSessionInformation};
+use mirrord_progress::NullProgress;
 use mirrord_protocol::{ClientMessage, DaemonMessage};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -52,6 +53,54 @@ pub enum AgentConnectInfo {
     DirectKubernetes(AgentKubernetesConnectInfo),
 }
 
+/// Spawns a new agent for the session when the connection with the current one is lost, see
+/// [`AgentConfig::handover`](mirrord_config::agent::AgentConfig::handover).
+///
+/// Only sessions that connect directly to the agent are supported, the operator manages its agents
+/// on its own.
+#[derive(Debug)]
+pub struct AgentHandover {
+    config: LayerConfig,
+}
+
+impl AgentHandover {
+    /// Returns [`None`] if the handover is disabled in the [`LayerConfig`], or not supported with
+    /// the given [`AgentConnectInfo`].
+    pub fn new(config: &LayerConfig, connect_info: Option<&AgentConnectInfo>) -> Option<Self> {
+        let supported = matches!(connect_info, Some(AgentConnectInfo::DirectKubernetes(..)));
+
+        (config.agent.handover && supported).then(|| Self {
+            config: config.clone(),
+        })
+    }
+
+    /// Spawns a new agent for the session target and connects to it.
+    ///
+    /// The target is resolved again, so that e.g. a deployment target picks up the pod that
+    /// replaced the evicted one.
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    pub async fn connect(&self) -> Result<AgentConnection, AgentConnectionError> {
+        let k8s_api = KubernetesAPI::create(&self.config).await?;
+
+        let connect_info = tokio::time::timeout(
+            Duration::from_secs(self.config.agent.startup_timeout),
+            k8s_api.create_agent(
+                &mut NullProgress,
+                &self.config.target,
+                Some(&self.config),
+                Default::default(),
+            ),
+        )
+        .await
+        .unwrap_or(Err(KubeApiError::AgentReadyTimeout))?;
+
+        let stream = k8s_api.create_connection(connect_info).await?;
+        let (agent_tx, agent_rx) = wrap_raw_connection(stream);
+
+        Ok(AgentConnection { agent_tx, agent_rx })
+    }
+}
+
 /// Handles logic of the `proxy <-> agent` connection as a [`BackgroundTask`].
 ///
 /// # Note
@@ -129,6 +129,9 @@ where
         let msg = match msg {
             Some(msg) => (id, TaskUpdate::Message(msg)),
             None => {
+                // Remove the stream right away, so that the id can be reused.
+                self.streams.remove(&id);
+
                 let res = self
                     .handles
                     .remove(&id)
@@ -144,6 +147,16 @@ where
         Some(msg)
     }
 
+    /// Aborts all registered tasks without waiting for them to finish. Their results are never
+    /// returned from [`BackgroundTasks::next`], and their ids can be reused right away.
+    pub fn abort_all(&mut self) {
+        self.streams.clear();
+
+        for (_, handle) in self.handles.drain() {
+            handle.abort();
+        }
+    }
+
     /// Waits for all registered tasks to finish and returns their results.
     /// This method does not signalize the tasks to finish. Instead, one should drop all
     /// [`TaskSender`]s first.
@@ -0,0 +1,114 @@
+//! Follows the changes of the config file during the session (`session.hot_reload`).
+//!
+//! The file is polled, as editors replace it in different ways (rename, truncate and write, ...)
+//! and we only need to notice the change within a second or so.
+
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use mirrord_config::LayerConfig;
+use mirrord_intproxy_protocol::IncomingMode;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{
+    background_tasks::{BackgroundTask, MessageBus},
+    main_tasks::ProxyMessage,
+};
+
+/// Watches the config file, and sends [`ProxyMessage::ConfigReloaded`] whenever it changes and
+/// is still valid. Invalid changes are logged and ignored, the session continues with the last
+/// valid config.
+///
+/// Run as a [`BackgroundTask`].
+pub struct ConfigWatcher {
+    /// The config file, the same as `MIRRORD_CONFIG_FILE`.
+    path: PathBuf,
+    /// Modification time of the file when it was last checked.
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    /// How often the file is checked.
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(path: PathBuf) -> Self {
+        let modified = Self::modified(&path);
+
+        Self { path, modified }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Loads the changed config, [`None`] when it's invalid.
+    fn load(&self) -> Option<IncomingMode> {
+        let result = LayerConfig::from_env_with_warnings().and_then(|(config, mut context)| {
+            config.verify(&mut context)?;
+            Ok(config)
+        });
+
+        let config = match result {
+            Ok(config) => config,
+            Err(error) => {
+                tracing::warn!(
+                    %error,
+                    path = %self.path.display(),
+                    "Config file changed but is invalid, keeping the current config"
+                );
+                return None;
+            }
+        };
+
+        match IncomingMode::new(&config.feature.network.incoming) {
+            Ok(incoming_mode) => Some(incoming_mode),
+            Err(error) => {
+                tracing::warn!(
+                    error,
+                    path = %self.path.display(),
+                    "Config file changed but its HTTP filter is invalid, keeping the current config"
+                );
+                None
+            }
+        }
+    }
+}
+
+impl BackgroundTask for ConfigWatcher {
+    type Error = Infallible;
+    type MessageIn = ();
+    type MessageOut = ProxyMessage;
+
+    async fn run(mut self, message_bus: &mut MessageBus<Self>) -> Result<(), Self::Error> {
+        let mut ticker = time::interval(Self::INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                None = message_bus.recv() => {
+                    tracing::trace!("message bus closed, exiting");
+                    break Ok(());
+                }
+
+                _ = ticker.tick() => {
+                    let modified = Self::modified(&self.path);
+                    // Missing while the editor replaces it.
+                    if modified.is_none() || modified == self.modified {
+                        continue;
+                    }
+                    self.modified = modified;
+
+                    if let Some(incoming_mode) = self.load() {
+                        tracing::info!(path = %self.path.display(), "Config file reloaded");
+                        message_bus.send(ProxyMessage::ConfigReloaded(incoming_mode)).await;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::io;
+use std::{convert::Infallible, io};
 
 use mirrord_intproxy_protocol::{codec::CodecError, LayerToProxyMessage};
 use mirrord_protocol::DaemonMessage;
@@ -8,7 +8,10 @@ use crate::{
     agent_conn::{AgentChannelError, AgentConnectionError},
     layer_initializer::LayerInitializerError,
     ping_pong::PingPongError,
-    proxies::{incoming::IncomingProxyError, outgoing::OutgoingProxyError},
+    proxies::{
+        incoming::IncomingProxyError, outgoing::OutgoingProxyError, proxy_server::ProxyServerError,
+        scratch::ScratchProxyError,
+    },
     request_queue::RequestQueueEmpty,
     MainTaskId,
 };
@@ -48,6 +51,16 @@ pub enum IntProxyError {
     OutgoingProxy(#[from] OutgoingProxyError),
     #[error("incoming proxy failed: {0}")]
     IncomingProxy(#[from] IncomingProxyError),
+    #[error("proxy server failed: {0}")]
+    ProxyServer(#[from] ProxyServerError),
+    #[error("scratch proxy failed: {0}")]
+    ScratchProxy(#[from] ScratchProxyError),
+}
+
+impl From<Infallible> for IntProxyError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
 }
 
 pub type Result<T> = core::result::Result<T, IntProxyError>;
@@ -0,0 +1,149 @@
+//! Runs the user commands configured in [`HooksConfig`] when something happens in the session.
+//!
+//! The commands are spawned in the background with `sh -c` and are never awaited by the proxy, so
+//! a slow or failing hook can't affect the session.
+
+use std::{fmt, process::Stdio, sync::Arc};
+
+use mirrord_config::{hooks::HooksConfig, LayerConfig};
+use mirrord_protocol::Port;
+use tokio::process::Command;
+
+/// Something that happened in the session, that the user may want to react to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// Connection with the agent was established.
+    SessionStart,
+    /// Agent confirmed the first steal subscription in this session.
+    StealStart { port: Port },
+    /// Session with the agent ended.
+    Disconnect { reason: String },
+    /// Session continues with a new agent, after the connection with the previous one was lost.
+    Reconnect { reason: String },
+}
+
+impl SessionEvent {
+    /// Value of the `MIRRORD_EVENT` variable passed to the hook command.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::SessionStart => "session_start",
+            Self::StealStart { .. } => "steal_start",
+            Self::Disconnect { .. } => "disconnect",
+            Self::Reconnect { .. } => "reconnect",
+        }
+    }
+
+    /// Event specific variables passed to the hook command.
+    fn env(&self) -> Option<(&'static str, String)> {
+        match self {
+            Self::SessionStart => None,
+            Self::StealStart { port } => Some(("MIRRORD_STEAL_PORT", port.to_string())),
+            Self::Disconnect { reason } => Some(("MIRRORD_DISCONNECT_REASON", reason.clone())),
+            Self::Reconnect { reason } => Some(("MIRRORD_RECONNECT_REASON", reason.clone())),
+        }
+    }
+}
+
+/// Called with every [`SessionEvent`], see [`EventHooks::with_listener`].
+#[derive(Clone)]
+struct EventListener(Arc<dyn Fn(&SessionEvent) + Send + Sync>);
+
+impl fmt::Debug for EventListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventListener").finish_non_exhaustive()
+    }
+}
+
+/// Spawns the commands from [`HooksConfig`] for [`SessionEvent`]s.
+///
+/// Cheap to clone, so it can be shared between the proxy tasks.
+#[derive(Debug, Clone, Default)]
+pub struct EventHooks {
+    config: HooksConfig,
+    /// Session context passed to every hook command, e.g. the target.
+    context: Vec<(&'static str, String)>,
+    listener: Option<EventListener>,
+}
+
+impl EventHooks {
+    /// Creates hooks from the [`LayerConfig::hooks`], with the session context taken from the
+    /// [`LayerConfig::target`].
+    pub fn new(config: &LayerConfig) -> Self {
+        let target = config
+            .target
+            .path
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_else(|| "targetless".to_string());
+
+        let mut context = vec![("MIRRORD_TARGET", target)];
+        if let Some(namespace) = config.target.namespace.clone() {
+            context.push(("MIRRORD_TARGET_NAMESPACE", namespace));
+        }
+
+        Self {
+            config: config.hooks.clone(),
+            context,
+            listener: None,
+        }
+    }
+
+    /// Makes [`Self::trigger`] also call the `listener`, e.g. to keep track of the session state.
+    pub fn with_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(&SessionEvent) + Send + Sync + 'static,
+    {
+        self.listener = Some(EventListener(Arc::new(listener)));
+        self
+    }
+
+    /// Spawns the command configured for the given [`SessionEvent`], if there is one.
+    ///
+    /// Failures are only logged, hooks never fail the session.
+    pub fn trigger(&self, event: SessionEvent) {
+        if let Some(listener) = self.listener.as_ref() {
+            (listener.0)(&event);
+        }
+
+        let command = match event {
+            SessionEvent::SessionStart => self.config.on_session_start.as_ref(),
+            SessionEvent::StealStart { .. } => self.config.on_steal_start.as_ref(),
+            SessionEvent::Disconnect { .. } => self.config.on_disconnect.as_ref(),
+            SessionEvent::Reconnect { .. } => self.config.on_reconnect.as_ref(),
+        };
+        let Some(command) = command else {
+            return;
+        };
+
+        let spawned = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(self.context.iter().map(|(key, value)| (*key, value)))
+            .env("MIRRORD_EVENT", event.name())
+            .envs(event.env())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        match spawned {
+            Ok(mut child) => {
+                tracing::debug!(?event, command, "spawned event hook");
+
+                // Reap the child, so it doesn't stay around as a zombie.
+                tokio::spawn(async move {
+                    match child.wait().await {
+                        Ok(status) if !status.success() => {
+                            tracing::warn!(?event, %status, "event hook command failed")
+                        }
+                        Err(error) => {
+                            tracing::warn!(?event, %error, "failed to wait for event hook command")
+                        }
+                        Ok(..) => {}
+                    }
+                });
+            }
+            Err(error) => tracing::warn!(?event, command, %error, "failed to spawn event hook"),
+        }
+    }
+}
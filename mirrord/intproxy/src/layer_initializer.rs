@@ -2,15 +2,15 @@ use std::io;
 
 use mirrord_intproxy_protocol::{
     codec::{AsyncDecoder, AsyncEncoder, CodecError},
-    LayerId, LayerToProxyMessage, LocalMessage, NewSessionRequest, ProxyToLayerMessage,
+    AuthToken, LayerId, LayerToProxyMessage, LocalMessage, NewSessionRequest, ProxyToLayerMessage,
 };
 use thiserror::Error;
 use tokio::net::{TcpListener, TcpStream};
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
     background_tasks::{BackgroundTask, MessageBus},
-    main_tasks::NewLayer,
+    main_tasks::{AdminConnection, NewLayer},
     ProxyMessage,
 };
 
@@ -24,6 +24,8 @@ pub enum LayerInitializerError {
     NoMessage,
     #[error("layer sent unexpected message: {0:?}")]
     UnexpectedMessage(LayerToProxyMessage),
+    #[error("connection did not authenticate with the session's token")]
+    Unauthenticated,
 }
 
 /// Handles logic for accepting new layer connections.
@@ -32,31 +34,45 @@ pub enum LayerInitializerError {
 pub struct LayerInitializer {
     listener: TcpListener,
     next_layer_id: LayerId,
+    /// When set, the layers have to send it in [`LayerToProxyMessage::Authenticate`] before
+    /// starting their session, so other local users can't use the proxy.
+    auth_token: Option<AuthToken>,
 }
 
 impl LayerInitializer {
-    pub fn new(listener: TcpListener) -> Self {
+    pub fn new(listener: TcpListener, auth_token: Option<AuthToken>) -> Self {
         Self {
             listener,
             next_layer_id: LayerId(0),
+            auth_token,
         }
     }
 
     /// Initialize connection with the new layer, assigning fresh [`LayerId`].
+    ///
+    /// Connections of the `mirrord session` commands are returned as [`AdminConnection`]s.
     #[tracing::instrument(level = "trace" ret)]
     async fn handle_new_stream(
         &mut self,
         stream: TcpStream,
-    ) -> Result<NewLayer, LayerInitializerError> {
+    ) -> Result<ProxyMessage, LayerInitializerError> {
         let mut decoder: AsyncDecoder<LocalMessage<LayerToProxyMessage>, _> =
             AsyncDecoder::new(stream);
-        let msg = decoder
-            .receive()
-            .await?
-            .ok_or(LayerInitializerError::NoMessage)?;
+        let first_msg = decoder.receive().await;
 
-        let id = self.next_layer_id;
-        self.next_layer_id.0 += 1;
+        // Until it authenticates, anything that goes wrong is the peer's fault.
+        let session_msg = match (self.auth_token.as_ref(), first_msg) {
+            (None, first_msg) => first_msg?,
+            (
+                Some(auth_token),
+                Ok(Some(LocalMessage {
+                    inner: LayerToProxyMessage::Authenticate(token),
+                    ..
+                })),
+            ) if token.matches(auth_token) => decoder.receive().await?,
+            (Some(..), _) => return Err(LayerInitializerError::Unauthenticated),
+        };
+        let msg = session_msg.ok_or(LayerInitializerError::NoMessage)?;
 
         let parent_id = match msg.inner {
             LayerToProxyMessage::NewSession(NewSessionRequest::New(process_info)) => {
@@ -64,9 +80,20 @@ impl LayerInitializer {
                 None
             }
             LayerToProxyMessage::NewSession(NewSessionRequest::Forked(parent)) => Some(parent),
+            LayerToProxyMessage::Admin(request) => {
+                return Ok(AdminConnection {
+                    stream: decoder.into_inner(),
+                    message_id: msg.message_id,
+                    request,
+                }
+                .into())
+            }
             other => return Err(LayerInitializerError::UnexpectedMessage(other)),
         };
 
+        let id = self.next_layer_id;
+        self.next_layer_id.0 += 1;
+
         let mut encoder: AsyncEncoder<LocalMessage<ProxyToLayerMessage>, _> =
             AsyncEncoder::new(decoder.into_inner());
         encoder
@@ -83,7 +110,8 @@ impl LayerInitializer {
             stream,
             id,
             parent_id,
-        })
+        }
+        .into())
     }
 }
 
@@ -103,7 +131,11 @@ impl BackgroundTask for LayerInitializer {
                 res = self.listener.accept() => {
                     let (stream, peer) = res.map_err(LayerInitializerError::Accept)?;
                     match self.handle_new_stream(stream).await {
-                        Ok(new_layer) => message_bus.send(new_layer).await,
+                        Ok(message) => message_bus.send(message).await,
+                        // Not one of our layers, it doesn't affect the session.
+                        Err(LayerInitializerError::Unauthenticated) => {
+                            warn!(%peer, "rejected connection without the session's token");
+                        }
                         Err(e) => {
                             tracing::error!("failed to initialize connection with peer {peer}: {e}");
                             break Err(e)
@@ -1,29 +1,55 @@
 #![warn(clippy::indexing_slicing)]
 
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
 
 use background_tasks::{BackgroundTasks, TaskSender, TaskUpdate};
 use layer_conn::LayerConnection;
 use layer_initializer::LayerInitializer;
-use main_tasks::{FromLayer, LayerForked, MainTaskId, ProxyMessage, ToLayer};
-use mirrord_intproxy_protocol::{LayerId, LayerToProxyMessage, LocalMessage};
-use mirrord_protocol::{ClientMessage, DaemonMessage, LogLevel, CLIENT_READY_FOR_LOGS};
-use ping_pong::{AgentSentPong, PingPong};
+use main_tasks::{AdminConnection, FromLayer, LayerForked, MainTaskId, ProxyMessage, ToLayer};
+use mirrord_config::feature::network::incoming::{OnLocalError, OnStall};
+use mirrord_intproxy_protocol::{
+    codec::AsyncEncoder, AdminRequest, AdminResponse, AuthToken, LayerId, LayerToProxyMessage,
+    LocalMessage, MessageId, ProxyToLayerMessage,
+};
+use mirrord_protocol::{
+    capabilities::{Capability, ProtocolCapabilities, CAPABILITIES_VERSION},
+    ClientMessage, DaemonMessage, LogLevel,
+};
+use ping_pong::{PingPong, PingPongMessage};
 use proxies::{
     incoming::{IncomingProxy, IncomingProxyMessage},
     outgoing::{OutgoingProxy, OutgoingProxyMessage},
+    proxy_server::ProxyServer,
+    scratch::{ScratchProxy, ScratchProxyMessage},
     simple::{SimpleProxy, SimpleProxyMessage},
 };
-use tokio::{net::TcpListener, time};
+use semver::Version;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    time,
+};
 
 use crate::{
-    agent_conn::AgentConnection, background_tasks::TaskError, error::IntProxyError,
+    agent_conn::{AgentConnection, AgentHandover},
+    background_tasks::TaskError,
+    config_watcher::ConfigWatcher,
+    error::IntProxyError,
+    event_hooks::{EventHooks, SessionEvent},
     main_tasks::LayerClosed,
+    shadow_diff::ShadowDiff,
+    size_limits::SizeLimits,
 };
 
 pub mod agent_conn;
 mod background_tasks;
+mod config_watcher;
 pub mod error;
+pub mod event_hooks;
 mod layer_conn;
 mod layer_initializer;
 mod main_tasks;
@@ -31,6 +57,8 @@ mod ping_pong;
 mod proxies;
 mod remote_resources;
 mod request_queue;
+pub mod shadow_diff;
+pub mod size_limits;
 
 /// [`TaskSender`]s for main background tasks. See [`MainTaskId`].
 struct TaskTxs {
@@ -41,8 +69,19 @@ struct TaskTxs {
     outgoing: TaskSender<OutgoingProxy>,
     incoming: TaskSender<IncomingProxy>,
     ping_pong: TaskSender<PingPong>,
+    /// Present when the proxy server is enabled, see [`IntProxy::with_proxy_server`].
+    proxy_server: Option<TaskSender<ProxyServer>>,
+    /// Present when the shared scratch directory is enabled, see
+    /// [`IntProxy::with_shared_scratch`].
+    scratch: Option<TaskSender<ScratchProxy>>,
+    /// Present when the config file is followed, see [`IntProxy::with_config_reload`].
+    _config_watcher: Option<TaskSender<ConfigWatcher>>,
 }
 
+/// Replaces the log filter of the proxy with the given `RUST_LOG` directives, see
+/// [`IntProxy::with_log_level_control`].
+pub type LogLevelControl = Box<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
 /// This struct contains logic for proxying between multiple layer instances and one agent.
 /// It maintains a singe agent connection.
 ///
@@ -52,6 +91,33 @@ pub struct IntProxy {
     any_connection_accepted: bool,
     background_tasks: BackgroundTasks<MainTaskId, ProxyMessage, IntProxyError>,
     task_txs: TaskTxs,
+    /// For running the user's `on_reconnect` hook.
+    event_hooks: EventHooks,
+    /// Used to replace the agent when the connection with it is lost.
+    handover: Option<AgentHandover>,
+    /// Main tasks that were notified about the new agent, but did not yet respond with
+    /// [`ProxyMessage::AgentReconnected`]. Their messages to the agent are meant for the previous
+    /// one and are dropped.
+    reconnecting_tasks: HashSet<MainTaskId>,
+    /// Whether only the ports the target listens on should be subscribed, see
+    /// [`Self::with_auto_incoming_ports`].
+    auto_incoming_ports: bool,
+    /// What to do when a layer stops sending heartbeats, and after how long, see
+    /// [`Self::with_stall_detection`].
+    stall_detection: Option<(OnStall, Duration)>,
+    /// Whether the application is being debugged, see [`Self::with_debugger_mode`].
+    debugger_mode: bool,
+    /// How many stolen connections can be delivered at once, and how many can wait, see
+    /// [`Self::with_connection_limit`].
+    connection_limit: Option<(usize, usize)>,
+    /// Whether the layers can open the named pipes of the target, see
+    /// [`Self::with_remote_fifos`].
+    remote_fifos: bool,
+    /// Used for [`AdminRequest::SetLogLevel`], see [`Self::with_log_level_control`].
+    log_level_control: Option<LogLevelControl>,
+    /// mirrord-protocol version negotiated with the agent, to interpret its
+    /// [`DaemonMessage::Capabilities`].
+    agent_protocol_version: Option<Version>,
 }
 
 impl IntProxy {
@@ -62,15 +128,27 @@ impl IntProxy {
 
     /// Creates a new [`IntProxy`] using existing [`AgentConnection`].
     /// The returned instance will accept connections from the layers using the given
-    /// [`TcpListener`].
-    pub fn new_with_connection(agent_conn: AgentConnection, listener: TcpListener) -> Self {
+    /// [`TcpListener`] (only from the ones that authenticate with `auth_token`, when given), and
+    /// run the user's [`EventHooks`] on session events.
+    ///
+    /// `shadow_diff` and `on_local_error` come from the incoming config, see [`IncomingProxy`].
+    /// `size_limits` come from the internal proxy config, see [`SizeLimits`].
+    pub fn new_with_connection(
+        agent_conn: AgentConnection,
+        listener: TcpListener,
+        auth_token: Option<AuthToken>,
+        event_hooks: EventHooks,
+        shadow_diff: Option<ShadowDiff>,
+        on_local_error: OnLocalError,
+        size_limits: SizeLimits,
+    ) -> Self {
         let mut background_tasks: BackgroundTasks<MainTaskId, ProxyMessage, IntProxyError> =
             Default::default();
 
         let agent =
             background_tasks.register(agent_conn, MainTaskId::AgentConnection, Self::CHANNEL_SIZE);
         let layer_initializer = background_tasks.register(
-            LayerInitializer::new(listener),
+            LayerInitializer::new(listener, auth_token),
             MainTaskId::LayerInitializer,
             Self::CHANNEL_SIZE,
         );
@@ -80,7 +158,7 @@ impl IntProxy {
             Self::CHANNEL_SIZE,
         );
         let simple = background_tasks.register(
-            SimpleProxy::default(),
+            SimpleProxy::new(size_limits),
             MainTaskId::SimpleProxy,
             Self::CHANNEL_SIZE,
         );
@@ -90,7 +168,12 @@ impl IntProxy {
             Self::CHANNEL_SIZE,
         );
         let incoming = background_tasks.register(
-            IncomingProxy::default(),
+            IncomingProxy::new(
+                event_hooks.clone(),
+                shadow_diff,
+                on_local_error,
+                size_limits.max_http_body_size,
+            ),
             MainTaskId::IncomingProxy,
             Self::CHANNEL_SIZE,
         );
@@ -106,10 +189,110 @@ impl IntProxy {
                 outgoing,
                 incoming,
                 ping_pong,
+                proxy_server: None,
+                scratch: None,
+                _config_watcher: None,
             },
+            event_hooks,
+            handover: None,
+            reconnecting_tasks: Default::default(),
+            auto_incoming_ports: false,
+            stall_detection: None,
+            debugger_mode: false,
+            connection_limit: None,
+            remote_fifos: false,
+            log_level_control: None,
+            agent_protocol_version: None,
         }
     }
 
+    /// Makes this proxy continue the session with a new agent when the connection with the
+    /// current one is lost, instead of failing. See [`AgentHandover`].
+    pub fn with_agent_handover(mut self, handover: AgentHandover) -> Self {
+        self.handover = Some(handover);
+        self
+    }
+
+    /// Makes this proxy subscribe only the ports the target listens on (`incoming.ports: "auto"`),
+    /// as reported by the agent.
+    pub fn with_auto_incoming_ports(mut self) -> Self {
+        self.auto_incoming_ports = true;
+        self
+    }
+
+    /// Makes this proxy apply the given [`OnStall`] policy to the stolen traffic of the layers that
+    /// don't send a heartbeat for `timeout` (`incoming.on_stall`).
+    pub fn with_stall_detection(mut self, policy: OnStall, timeout: Duration) -> Self {
+        self.stall_detection = Some((policy, timeout));
+        self
+    }
+
+    /// Makes the stolen requests wait for the application's responses for as long as it takes,
+    /// since it can sit at a breakpoint (`debugger_mode`).
+    pub fn with_debugger_mode(mut self) -> Self {
+        self.debugger_mode = true;
+        self
+    }
+
+    /// Makes this proxy deliver at most `max_concurrent` stolen connections to the application at
+    /// once, keeping up to `queue_size` more waiting in the agent and closing the rest
+    /// (`incoming.max_concurrent`, `incoming.queue_size`).
+    pub fn with_connection_limit(mut self, max_concurrent: usize, queue_size: usize) -> Self {
+        self.connection_limit = Some((max_concurrent, queue_size));
+        self
+    }
+
+    /// Lets the layers open the named pipes (FIFOs) of the target, when the agent supports it
+    /// (`feature.fs.mode: "write"`).
+    pub fn with_remote_fifos(mut self) -> Self {
+        self.remote_fifos = true;
+        self
+    }
+
+    /// Makes this proxy change its own log filter with `control` on [`AdminRequest::SetLogLevel`].
+    /// Without it, only the layers get the new filter.
+    pub fn with_log_level_control(mut self, control: LogLevelControl) -> Self {
+        self.log_level_control = Some(control);
+        self
+    }
+
+    /// Makes this proxy serve SOCKS5 and HTTP proxy clients on the given [`TcpListener`], for the
+    /// processes that can't load the layer. See [`ProxyServer`].
+    pub fn with_proxy_server(mut self, listener: TcpListener) -> Self {
+        let proxy_server = self.background_tasks.register(
+            ProxyServer::new(listener),
+            MainTaskId::ProxyServer,
+            Self::CHANNEL_SIZE,
+        );
+        self.task_txs.proxy_server = Some(proxy_server);
+        self
+    }
+
+    /// Makes this proxy keep the `local` directory in sync with the `remote` directory of the
+    /// target (`feature.fs.shared_scratch`), when the agent supports it. See [`ScratchProxy`].
+    pub fn with_shared_scratch(mut self, remote: PathBuf, local: PathBuf) -> Self {
+        let scratch = self.background_tasks.register(
+            ScratchProxy::new(remote, local),
+            MainTaskId::ScratchProxy,
+            Self::CHANNEL_SIZE,
+        );
+        self.task_txs.scratch = Some(scratch);
+        self
+    }
+
+    /// Makes this proxy follow the changes of the config file at `path` (`session.hot_reload`).
+    /// The layers are told to load it again, and the steal subscriptions get the new HTTP filter.
+    /// See [`ConfigWatcher`].
+    pub fn with_config_reload(mut self, path: PathBuf) -> Self {
+        let config_watcher = self.background_tasks.register(
+            ConfigWatcher::new(path),
+            MainTaskId::ConfigWatcher,
+            Self::CHANNEL_SIZE,
+        );
+        self.task_txs._config_watcher = Some(config_watcher);
+        self
+    }
+
     /// Runs main event loop of this proxy.
     /// Expects to accept the first layer connection within the given `first_timeout`.
     /// Exits after `idle_timeout` when there are no more layer connections.
@@ -124,6 +307,39 @@ impl IntProxy {
                 mirrord_protocol::VERSION.clone(),
             ))
             .await;
+        if self.auto_incoming_ports {
+            self.task_txs
+                .incoming
+                .send(IncomingProxyMessage::WatchListeners)
+                .await;
+        }
+        if let Some((policy, timeout)) = self.stall_detection {
+            self.task_txs
+                .incoming
+                .send(IncomingProxyMessage::DetectStalls { policy, timeout })
+                .await;
+        }
+        if self.debugger_mode {
+            self.task_txs
+                .incoming
+                .send(IncomingProxyMessage::DebuggerMode)
+                .await;
+        }
+        if let Some((max_concurrent, queue_size)) = self.connection_limit {
+            self.task_txs
+                .incoming
+                .send(IncomingProxyMessage::LimitConnections {
+                    max_concurrent,
+                    queue_size,
+                })
+                .await;
+        }
+        if self.remote_fifos {
+            self.task_txs
+                .simple
+                .send(SimpleProxyMessage::AllowFifos)
+                .await;
+        }
 
         loop {
             tokio::select! {
@@ -156,6 +372,73 @@ impl IntProxy {
         Ok(())
     }
 
+    /// Replaces the lost agent with a new one, using the configured [`AgentHandover`].
+    ///
+    /// The other main tasks are notified, so that they can move their state to the new agent.
+    /// Returns the original `error` if the handover is not configured.
+    async fn handover_agent(&mut self, error: IntProxyError) -> Result<(), IntProxyError> {
+        let Some(handover) = self.handover.as_ref() else {
+            return Err(error);
+        };
+
+        tracing::info!(%error, "connection with the agent was lost, starting a new agent");
+        self.task_txs
+            .ping_pong
+            .send(PingPongMessage::AgentLost)
+            .await;
+
+        let agent_conn = handover.connect().await?;
+        self.task_txs.agent = self.background_tasks.register(
+            agent_conn,
+            MainTaskId::AgentConnection,
+            Self::CHANNEL_SIZE,
+        );
+        self.task_txs
+            .agent
+            .send(ClientMessage::SwitchProtocolVersion(
+                mirrord_protocol::VERSION.clone(),
+            ))
+            .await;
+
+        self.reconnecting_tasks = HashSet::from([
+            MainTaskId::SimpleProxy,
+            MainTaskId::OutgoingProxy,
+            MainTaskId::IncomingProxy,
+            MainTaskId::PingPong,
+        ]);
+        self.task_txs
+            .simple
+            .send(SimpleProxyMessage::AgentReconnected)
+            .await;
+        self.task_txs
+            .outgoing
+            .send(OutgoingProxyMessage::AgentReconnected)
+            .await;
+        self.task_txs
+            .incoming
+            .send(IncomingProxyMessage::AgentReconnected)
+            .await;
+        self.task_txs
+            .ping_pong
+            .send(PingPongMessage::AgentReconnected)
+            .await;
+        if let Some(scratch) = self.task_txs.scratch.as_ref() {
+            self.reconnecting_tasks.insert(MainTaskId::ScratchProxy);
+            scratch.send(ScratchProxyMessage::AgentReconnected).await;
+        }
+
+        tracing::warn!(
+            %error,
+            "connection with the agent was lost, session continues with a new agent, \
+             open connections and files were lost"
+        );
+        self.event_hooks.trigger(SessionEvent::Reconnect {
+            reason: error.to_string(),
+        });
+
+        Ok(())
+    }
+
     /// Routes a [`ProxyMessage`] to the correct background task.
     /// [`ProxyMessage::NewLayer`] is handled here, as an exception.
     async fn handle(&mut self, msg: ProxyMessage) -> Result<(), IntProxyError> {
@@ -186,9 +469,26 @@ impl IntProxy {
                         .await;
                 }
             }
+            ProxyMessage::Admin(admin) => self.handle_admin(admin).await,
             ProxyMessage::FromAgent(msg) => self.handle_agent_message(msg).await?,
             ProxyMessage::FromLayer(msg) => self.handle_layer_message(msg).await?,
             ProxyMessage::ToAgent(msg) => self.task_txs.agent.send(msg).await,
+            // Handled in `handle_task_update`, as it depends on the task.
+            ProxyMessage::AgentReconnected => {}
+            ProxyMessage::ConfigReloaded(incoming_mode) => {
+                for tx in self.task_txs.layers.values() {
+                    tx.send(LocalMessage {
+                        message_id: 0,
+                        inner: ProxyToLayerMessage::ReloadConfig,
+                    })
+                    .await;
+                }
+
+                self.task_txs
+                    .incoming
+                    .send(IncomingProxyMessage::ConfigReloaded(incoming_mode))
+                    .await;
+            }
             ProxyMessage::ToLayer(msg) => {
                 let ToLayer {
                     message,
@@ -196,12 +496,17 @@ impl IntProxy {
                     layer_id,
                 } = msg;
 
-                if let Some(tx) = self.task_txs.layers.get(&layer_id) {
-                    tx.send(LocalMessage {
-                        message_id,
-                        inner: message,
-                    })
-                    .await;
+                let message = LocalMessage {
+                    message_id,
+                    inner: message,
+                };
+
+                if layer_id == ProxyServer::LAYER_ID {
+                    if let Some(tx) = self.task_txs.proxy_server.as_ref() {
+                        tx.send(message).await;
+                    }
+                } else if let Some(tx) = self.task_txs.layers.get(&layer_id) {
+                    tx.send(message).await;
                 }
             }
         }
@@ -209,6 +514,78 @@ impl IntProxy {
         Ok(())
     }
 
+    /// Collects the answer to an [`AdminRequest`] from the main tasks, then sends it in a
+    /// separate task, so a slow `mirrord session` command doesn't hold up the proxy.
+    async fn handle_admin(&self, admin: AdminConnection) {
+        let AdminConnection {
+            stream,
+            message_id,
+            request,
+        } = admin;
+
+        match request {
+            AdminRequest::RemoteFds => {
+                let (files_tx, files_rx) = oneshot::channel();
+                let (outgoing_tx, outgoing_rx) = oneshot::channel();
+                self.task_txs
+                    .simple
+                    .send(SimpleProxyMessage::RemoteFds(files_tx))
+                    .await;
+                self.task_txs
+                    .outgoing
+                    .send(OutgoingProxyMessage::RemoteFds(outgoing_tx))
+                    .await;
+
+                tokio::spawn(async move {
+                    let mut fds = files_rx.await.unwrap_or_default();
+                    fds.extend(outgoing_rx.await.unwrap_or_default());
+
+                    Self::respond_admin(stream, message_id, AdminResponse::RemoteFds(fds)).await;
+                });
+            }
+            AdminRequest::SetLogLevel(directives) => {
+                let result = self
+                    .log_level_control
+                    .as_ref()
+                    .map_or(Ok(()), |control| control(&directives));
+
+                if result.is_ok() {
+                    tracing::info!(directives, "log filter changed");
+
+                    for tx in self.task_txs.layers.values() {
+                        tx.send(LocalMessage {
+                            message_id: 0,
+                            inner: ProxyToLayerMessage::SetLogLevel(directives.clone()),
+                        })
+                        .await;
+                    }
+                }
+                let result = result.map(|()| self.task_txs.layers.len());
+
+                tokio::spawn(Self::respond_admin(
+                    stream,
+                    message_id,
+                    AdminResponse::SetLogLevel(result),
+                ));
+            }
+        }
+    }
+
+    /// Sends the [`AdminResponse`] to the `mirrord session` (or `mirrord loglevel`) command.
+    async fn respond_admin(stream: TcpStream, message_id: MessageId, response: AdminResponse) {
+        let mut encoder: AsyncEncoder<LocalMessage<ProxyToLayerMessage>, _> =
+            AsyncEncoder::new(stream);
+        let message = LocalMessage {
+            message_id,
+            inner: ProxyToLayerMessage::Admin(response),
+        };
+        if let Err(error) = encoder.send(&message).await {
+            tracing::warn!(%error, "failed to respond to an admin request");
+        } else if let Err(error) = encoder.flush().await {
+            tracing::warn!(%error, "failed to respond to an admin request");
+        }
+    }
+
     /// Handles a [`TaskUpdate`] from one of the main tasks (see [`MainTaskId`]).
     async fn handle_task_update(
         &mut self,
@@ -232,6 +609,10 @@ impl IntProxy {
 
                 self.task_txs.layers.remove(&LayerId(id));
             }
+            (
+                MainTaskId::AgentConnection,
+                TaskUpdate::Finished(Err(TaskError::Error(e @ IntProxyError::AgentChannel(..)))),
+            ) if self.handover.is_some() => self.handover_agent(e).await?,
             (task_id, TaskUpdate::Finished(res)) => match res {
                 Ok(()) => {
                     tracing::error!("task {task_id} finished unexpectedly");
@@ -246,6 +627,17 @@ impl IntProxy {
                     return Err(IntProxyError::TaskPanic(task_id));
                 }
             },
+            (task_id, TaskUpdate::Message(ProxyMessage::AgentReconnected)) => {
+                self.reconnecting_tasks.remove(&task_id);
+            }
+            (task_id, TaskUpdate::Message(ProxyMessage::ToAgent(msg)))
+                if self.reconnecting_tasks.contains(&task_id) =>
+            {
+                tracing::trace!(
+                    ?msg,
+                    "task {task_id} message was meant for the previous agent, dropping"
+                );
+            }
             (_, TaskUpdate::Message(msg)) => self.handle(msg).await?,
         }
 
@@ -257,7 +649,12 @@ impl IntProxy {
     #[tracing::instrument(level = "trace", skip(self), ret)]
     async fn handle_agent_message(&mut self, message: DaemonMessage) -> Result<(), IntProxyError> {
         match message {
-            DaemonMessage::Pong => self.task_txs.ping_pong.send(AgentSentPong).await,
+            DaemonMessage::Pong => {
+                self.task_txs
+                    .ping_pong
+                    .send(PingPongMessage::AgentSentPong)
+                    .await
+            }
             DaemonMessage::Close(reason) => return Err(IntProxyError::AgentFailed(reason)),
             DaemonMessage::TcpOutgoing(msg) => {
                 self.task_txs
@@ -283,6 +680,17 @@ impl IntProxy {
                     .send(SimpleProxyMessage::AddrInfoRes(msg))
                     .await
             }
+            DaemonMessage::ReadStream(msg) => {
+                self.task_txs
+                    .simple
+                    .send(SimpleProxyMessage::ReadStream(msg))
+                    .await
+            }
+            DaemonMessage::Scratch(msg) => {
+                if let Some(scratch) = self.task_txs.scratch.as_ref() {
+                    scratch.send(ScratchProxyMessage::Agent(msg)).await;
+                }
+            }
             DaemonMessage::Tcp(msg) => {
                 self.task_txs
                     .incoming
@@ -296,10 +704,44 @@ impl IntProxy {
                     .await
             }
             DaemonMessage::SwitchProtocolVersionResponse(protocol_version) => {
-                if CLIENT_READY_FOR_LOGS.matches(&protocol_version) {
-                    self.task_txs.agent.send(ClientMessage::ReadyForLogs).await;
+                self.agent_protocol_version = Some(protocol_version.clone());
+                if CAPABILITIES_VERSION.matches(&protocol_version) {
+                    self.task_txs
+                        .agent
+                        .send(ClientMessage::Capabilities(ProtocolCapabilities::all()))
+                        .await;
+                } else {
+                    self.handle_agent_capabilities(ProtocolCapabilities::from_version(
+                        &protocol_version,
+                    ))
+                    .await;
                 }
             }
+            DaemonMessage::Capabilities(capabilities) => {
+                let capabilities = match self.agent_protocol_version.as_ref() {
+                    Some(version) => ProtocolCapabilities::from_agent_answer(capabilities, version),
+                    None => capabilities,
+                };
+                self.handle_agent_capabilities(capabilities).await
+            }
+            DaemonMessage::Listeners(ports) => {
+                self.task_txs
+                    .incoming
+                    .send(IncomingProxyMessage::AgentListeners(ports))
+                    .await
+            }
+            DaemonMessage::Udp(msg) => {
+                self.task_txs
+                    .incoming
+                    .send(IncomingProxyMessage::AgentUdpMirror(msg))
+                    .await
+            }
+            DaemonMessage::UdpSteal(msg) => {
+                self.task_txs
+                    .incoming
+                    .send(IncomingProxyMessage::AgentUdpSteal(msg))
+                    .await
+            }
             DaemonMessage::LogMessage(log) => match log.level {
                 LogLevel::Error => tracing::error!("agent log: {}", log.message),
                 LogLevel::Warn => tracing::warn!("agent log: {}", log.message),
@@ -318,6 +760,31 @@ impl IntProxy {
         Ok(())
     }
 
+    /// Passes the capabilities negotiated with the agent to the background tasks that use them.
+    async fn handle_agent_capabilities(&mut self, capabilities: ProtocolCapabilities) {
+        tracing::debug!(?capabilities, "negotiated capabilities with the agent");
+
+        if capabilities.supports(Capability::ReadyForLogs) {
+            self.task_txs.agent.send(ClientMessage::ReadyForLogs).await;
+        }
+
+        self.task_txs
+            .incoming
+            .send(IncomingProxyMessage::AgentCapabilities(
+                capabilities.clone(),
+            ))
+            .await;
+        if let Some(scratch) = self.task_txs.scratch.as_ref() {
+            scratch
+                .send(ScratchProxyMessage::Capabilities(capabilities.clone()))
+                .await;
+        }
+        self.task_txs
+            .simple
+            .send(SimpleProxyMessage::Capabilities(capabilities))
+            .await;
+    }
+
     /// Routes a message from the layer to the correct background task.
     #[tracing::instrument(level = "trace", skip(self), ret)]
     async fn handle_layer_message(&self, message: FromLayer) -> Result<(), IntProxyError> {
@@ -362,6 +829,15 @@ impl IntProxy {
                     .send(SimpleProxyMessage::GetEnvReq(message_id, layer_id, req))
                     .await
             }
+            LayerToProxyMessage::Heartbeat(heartbeat) => {
+                self.task_txs
+                    .incoming
+                    .send(IncomingProxyMessage::LayerHeartbeat(
+                        layer_id,
+                        heartbeat.pid,
+                    ))
+                    .await
+            }
             other => return Err(IntProxyError::UnexpectedLayerMessage(other)),
         }
 
@@ -1,6 +1,8 @@
 use std::fmt;
 
-use mirrord_intproxy_protocol::{LayerId, LayerToProxyMessage, MessageId, ProxyToLayerMessage};
+use mirrord_intproxy_protocol::{
+    AdminRequest, IncomingMode, LayerId, LayerToProxyMessage, MessageId, ProxyToLayerMessage,
+};
 use mirrord_protocol::{ClientMessage, DaemonMessage};
 use tokio::net::TcpStream;
 
@@ -18,6 +20,14 @@ pub enum ProxyMessage {
     FromLayer(FromLayer),
     /// New layer instance to serve.
     NewLayer(NewLayer),
+    /// New connection of a `mirrord session` command.
+    Admin(AdminConnection),
+    /// The task's following [`ProxyMessage::ToAgent`] messages are meant for the new agent, see
+    /// [`AgentHandover`](crate::agent_conn::AgentHandover).
+    AgentReconnected,
+    /// The config file changed (`session.hot_reload`), with the new [`IncomingMode`], see
+    /// [`ConfigWatcher`](crate::config_watcher::ConfigWatcher).
+    ConfigReloaded(IncomingMode),
 }
 
 #[derive(Debug)]
@@ -42,6 +52,14 @@ pub struct NewLayer {
     pub parent_id: Option<LayerId>,
 }
 
+/// Connection that sent a [`LayerToProxyMessage::Admin`] instead of starting a layer session.
+#[derive(Debug)]
+pub struct AdminConnection {
+    pub stream: TcpStream,
+    pub message_id: MessageId,
+    pub request: AdminRequest,
+}
+
 impl From<ClientMessage> for ProxyMessage {
     fn from(value: ClientMessage) -> Self {
         Self::ToAgent(value)
@@ -72,6 +90,12 @@ impl From<NewLayer> for ProxyMessage {
     }
 }
 
+impl From<AdminConnection> for ProxyMessage {
+    fn from(value: AdminConnection) -> Self {
+        Self::Admin(value)
+    }
+}
+
 /// Enumerated ids of main [`BackgroundTask`](crate::background_tasks::BackgroundTask)s used by
 /// [`IntProxy`](crate::IntProxy).
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -83,6 +107,9 @@ pub enum MainTaskId {
     PingPong,
     AgentConnection,
     LayerConnection(LayerId),
+    ProxyServer,
+    ScratchProxy,
+    ConfigWatcher,
 }
 
 impl fmt::Display for MainTaskId {
@@ -95,6 +122,9 @@ impl fmt::Display for MainTaskId {
             Self::AgentConnection => f.write_str("AGENT_CONNECTION"),
             Self::LayerConnection(id) => write!(f, "LAYER_CONNECTION {}", id.0),
             Self::IncomingProxy => f.write_str("INCOMING_PROXY"),
+            Self::ProxyServer => f.write_str("PROXY_SERVER"),
+            Self::ScratchProxy => f.write_str("SCRATCH_PROXY"),
+            Self::ConfigWatcher => f.write_str("CONFIG_WATCHER"),
         }
     }
 }
@@ -27,9 +27,17 @@ pub enum PingPongError {
     PongTimeout,
 }
 
-/// Notification about a [`DeamonMessage::Pong`](mirrord_protocol::DaemonMessage::Pong) received
-/// from the agent.
-pub struct AgentSentPong;
+/// Messages consumed by the [`PingPong`] task.
+pub enum PingPongMessage {
+    /// Notification about a [`DeamonMessage::Pong`](mirrord_protocol::DaemonMessage::Pong)
+    /// received from the agent.
+    AgentSentPong,
+    /// Connection with the agent was lost, the task should not ping until
+    /// [`PingPongMessage::AgentReconnected`].
+    AgentLost,
+    /// The session continues with a new agent.
+    AgentReconnected,
+}
 
 /// Encapsulates logic of the ping pong mechanism on the proxy side.
 /// Run as a [`BackgroundTask`].
@@ -38,6 +46,8 @@ pub struct PingPong {
     ticker: Interval,
     /// Whether this struct awaits for a pong from the agent.
     awaiting_pong: bool,
+    /// Whether the connection with the agent was lost and the proxy is waiting for a new agent.
+    agent_lost: bool,
 }
 
 impl PingPong {
@@ -53,23 +63,26 @@ impl PingPong {
         Self {
             ticker,
             awaiting_pong: false,
+            agent_lost: false,
         }
     }
 }
 
 impl BackgroundTask for PingPong {
     type Error = PingPongError;
-    type MessageIn = AgentSentPong;
+    type MessageIn = PingPongMessage;
     type MessageOut = ProxyMessage;
 
     /// Pings the agent with a frequency configured in [`PingPong::new`].
     ///
     /// When the time comes to ping the agent and the previous ping was not answered, this task
     /// exits with an error.
+    ///
+    /// Pings are paused while the proxy waits for a new agent (see [`PingPongMessage::AgentLost`]).
     async fn run(mut self, message_bus: &mut MessageBus<Self>) -> Result<(), Self::Error> {
         loop {
             tokio::select! {
-                _ = self.ticker.tick() => {
+                _ = self.ticker.tick(), if !self.agent_lost => {
                     if self.awaiting_pong {
                         tracing::error!("pong timeout");
                         break Err(PingPongError::PongTimeout);
@@ -85,14 +98,25 @@ impl BackgroundTask for PingPong {
                         tracing::trace!("message bus closed, exiting");
                         break Ok(())
                     },
-                    (Some(AgentSentPong), true) => {
+                    (Some(PingPongMessage::AgentSentPong), true) => {
                         tracing::trace!("agent responded to ping");
                         self.awaiting_pong = false;
                     },
-                    (Some(AgentSentPong), false) => {
+                    (Some(PingPongMessage::AgentSentPong), false) => {
                         tracing::error!("agent sent an unexpected pong");
                         break Err(PingPongError::UnmatchedPong)
                     },
+                    (Some(PingPongMessage::AgentLost), _) => {
+                        tracing::trace!("agent lost, pausing pings");
+                        self.agent_lost = true;
+                    },
+                    (Some(PingPongMessage::AgentReconnected), _) => {
+                        tracing::trace!("agent reconnected, resuming pings");
+                        self.agent_lost = false;
+                        self.awaiting_pong = false;
+                        self.ticker.reset();
+                        message_bus.send(ProxyMessage::AgentReconnected).await;
+                    },
                 },
             }
         }
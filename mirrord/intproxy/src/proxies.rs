@@ -3,4 +3,6 @@
 
 pub mod incoming;
 pub mod outgoing;
+pub mod proxy_server;
+pub mod scratch;
 pub mod simple;
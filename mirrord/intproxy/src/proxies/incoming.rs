@@ -1,37 +1,51 @@
 //! Handles the logic of the `incoming` feature.
 
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     fmt, io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 
+use mirrord_config::feature::network::incoming::{OnLocalError, OnStall};
 use mirrord_intproxy_protocol::{
-    ConnMetadataRequest, ConnMetadataResponse, IncomingRequest, IncomingResponse, LayerId,
-    MessageId, PortSubscribe, PortSubscription, PortUnsubscribe, ProxyToLayerMessage,
+    ConnMetadataRequest, ConnMetadataResponse, IncomingMode, IncomingRequest, IncomingResponse,
+    LayerId, MessageId, PortSubscribe, PortSubscription, PortUnsubscribe, ProxyToLayerMessage,
+    UdpPortSubscribe,
 };
 use mirrord_protocol::{
+    capabilities::{Capability, ProtocolCapabilities},
     tcp::{DaemonTcp, HttpRequestFallback, NewTcpConnection},
-    ConnectionId, ResponseError,
+    udp::DaemonUdp,
+    ClientMessage, ConnectionId, Port, ResponseError,
 };
 use thiserror::Error;
-use tokio::net::TcpSocket;
+use tokio::{
+    net::TcpSocket,
+    time::{self, Instant, MissedTickBehavior},
+};
 
 use self::{
+    connection_limit::ConnectionLimit,
     interceptor::{Interceptor, InterceptorError, MessageOut},
     port_subscription_ext::PortSubscriptionExt,
     subscriptions::SubscriptionsManager,
+    udp::UdpIncoming,
 };
 use crate::{
     background_tasks::{BackgroundTask, BackgroundTasks, MessageBus, TaskSender, TaskUpdate},
+    event_hooks::{EventHooks, SessionEvent},
     main_tasks::{LayerClosed, LayerForked, ToLayer},
+    shadow_diff::ShadowDiff,
     ProxyMessage,
 };
 
+mod connection_limit;
 mod http;
 mod interceptor;
 mod port_subscription_ext;
 mod subscriptions;
+mod udp;
 
 /// Creates and binds a new [`TcpSocket`].
 /// The socket has the same IP version and address as the given `addr`.
@@ -91,6 +105,36 @@ pub enum IncomingProxyMessage {
     LayerClosed(LayerClosed),
     AgentMirror(DaemonTcp),
     AgentSteal(DaemonTcp),
+    AgentUdpMirror(DaemonUdp),
+    AgentUdpSteal(DaemonUdp),
+    /// Connection with the previous agent was lost and the session continues with a new one.
+    AgentReconnected,
+    /// Capabilities negotiated with the agent.
+    AgentCapabilities(ProtocolCapabilities),
+    /// Subscribe only the ports the target listens on (`incoming.ports: "auto"`).
+    WatchListeners,
+    /// Ports the target listens on.
+    AgentListeners(Vec<Port>),
+    /// Apply `policy` to the stolen traffic of the layers that don't send a heartbeat for
+    /// `timeout` (`incoming.on_stall`).
+    DetectStalls {
+        policy: OnStall,
+        timeout: Duration,
+    },
+    /// The application of the layer, the process with the given pid, is running.
+    LayerHeartbeat(LayerId, u32),
+    /// The application is being debugged (`debugger_mode`): the stolen requests wait for its
+    /// responses for as long as it takes.
+    DebuggerMode,
+    /// Deliver at most `max_concurrent` stolen connections to the user application at once,
+    /// keeping up to `queue_size` more waiting in the agent (`incoming.max_concurrent`).
+    LimitConnections {
+        max_concurrent: usize,
+        queue_size: usize,
+    },
+    /// The config file changed (`session.hot_reload`), the steal subscriptions should use the HTTP
+    /// filter of the new [`IncomingMode`].
+    ConfigReloaded(IncomingMode),
 }
 
 /// Handle for an [`Interceptor`].
@@ -101,6 +145,119 @@ struct InterceptorHandle {
     subscription: PortSubscription,
 }
 
+/// Finds the layers whose application is stopped, from their
+/// [`IncomingProxyMessage::LayerHeartbeat`]s.
+struct StallDetector {
+    /// What to do with the stolen traffic of the stalled layers.
+    policy: OnStall,
+    /// How long a layer can go without a heartbeat.
+    timeout: Duration,
+    /// When the layers sent their last heartbeat. Layers that never sent one are not tracked.
+    last_heartbeats: HashMap<LayerId, Instant>,
+    /// Processes of the layers, from their heartbeats.
+    pids: HashMap<LayerId, u32>,
+    /// Layers that did not send a heartbeat for [`Self::timeout`].
+    stalled: HashSet<LayerId>,
+    /// Whether a layer stopped sending heartbeats because a debugger stopped its application, then
+    /// the session enters `debugger_mode` instead of applying [`Self::policy`].
+    debugger_found: bool,
+}
+
+impl StallDetector {
+    fn new(policy: OnStall, timeout: Duration) -> Self {
+        Self {
+            policy,
+            timeout,
+            last_heartbeats: Default::default(),
+            pids: Default::default(),
+            stalled: Default::default(),
+            debugger_found: false,
+        }
+    }
+
+    /// Records a heartbeat from the layer, returns whether the layer was stalled.
+    fn heartbeat(&mut self, layer_id: LayerId, pid: u32) -> bool {
+        self.last_heartbeats.insert(layer_id, Instant::now());
+        self.pids.insert(layer_id, pid);
+        let resumed = self.stalled.remove(&layer_id);
+        if resumed {
+            tracing::info!(
+                ?layer_id,
+                "Application runs again, restoring the stolen traffic"
+            );
+        }
+
+        resumed
+    }
+
+    /// Marks the layers that did not send a heartbeat for [`Self::timeout`] as stalled, returns
+    /// whether there are new ones.
+    fn check(&mut self) -> bool {
+        let mut changed = false;
+
+        for (layer_id, last_heartbeat) in &self.last_heartbeats {
+            if last_heartbeat.elapsed() < self.timeout || self.stalled.contains(layer_id) {
+                continue;
+            }
+
+            if self
+                .pids
+                .get(layer_id)
+                .is_some_and(|pid| stopped_by_debugger(*pid))
+            {
+                tracing::info!(
+                    ?layer_id,
+                    "Application is stopped by a debugger, entering debugger mode"
+                );
+                self.debugger_found = true;
+                continue;
+            }
+
+            if self.stalled.insert(*layer_id) {
+                tracing::warn!(
+                    ?layer_id,
+                    policy = ?self.policy,
+                    "Application was stopped for {:?}, applying `on_stall`",
+                    self.timeout,
+                );
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn layer_closed(&mut self, layer_id: LayerId) {
+        self.last_heartbeats.remove(&layer_id);
+        self.pids.remove(&layer_id);
+        self.stalled.remove(&layer_id);
+    }
+}
+
+/// Whether the process is stopped by a debugger, from its `/proc/{pid}/status`. Always `false`
+/// without procfs, e.g. on macOS.
+fn stopped_by_debugger(pid: u32) -> bool {
+    std::fs::read_to_string(format!("/proc/{pid}/status"))
+        .is_ok_and(|status| is_debugger_stop(&status))
+}
+
+/// Whether the process of the `/proc/{pid}/status` is in a tracing stop (`t`), or stopped (`T`)
+/// with a tracer attached. A stopped process without a tracer got a `SIGSTOP`.
+fn is_debugger_stop(status: &str) -> bool {
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .map(str::trim)
+    };
+
+    match field("State:").and_then(|state| state.chars().next()) {
+        Some('t') => true,
+        Some('T') => field("TracerPid:").is_some_and(|tracer| tracer != "0"),
+        _ => false,
+    }
+}
+
 /// Store for mapping [`Interceptor`] socket addresses to addresses of the original peers.
 #[derive(Default)]
 struct MetadataStore {
@@ -141,16 +298,40 @@ impl MetadataStore {
 ///
 /// Incoming connections are created by the agent either explicitly ([`NewTcpConnection`] message)
 /// or implicitly ([`HttpRequest`](mirrord_protocol::tcp::HttpRequest)).
+///
+/// Incoming UDP datagrams are handled separately, by [`UdpIncoming`].
 #[derive(Default)]
 pub struct IncomingProxy {
     /// Active port subscriptions for all layers.
     subscriptions: SubscriptionsManager,
+    /// Active UDP port subscriptions for all layers, and the remote peers that use them.
+    udp: UdpIncoming,
     /// [`TaskSender`]s for active [`Interceptor`]s.
     interceptors: HashMap<InterceptorId, InterceptorHandle>,
     /// For receiving updates from [`Interceptor`]s.
     background_tasks: BackgroundTasks<InterceptorId, MessageOut, InterceptorError>,
     /// For managing intercepted connections metadata.
     metadata_store: MetadataStore,
+    /// For running the user's `on_steal_start` hook.
+    event_hooks: EventHooks,
+    /// Whether the agent already confirmed any steal subscription.
+    steal_started: bool,
+    /// For comparing the responses to the shadowed requests.
+    shadow_diff: Option<ShadowDiff>,
+    /// What to do when the user application fails to handle a stolen HTTP request.
+    on_local_error: OnLocalError,
+    /// Capabilities negotiated with the agent, used to reject subscriptions the agent does not
+    /// understand.
+    capabilities: Option<ProtocolCapabilities>,
+    /// Limit on the bodies of the stolen HTTP requests and the local responses to them, see
+    /// [`Interceptor::with_max_body_size`].
+    max_http_body_size: Option<u64>,
+    /// Applies `incoming.on_stall`, see [`IncomingProxyMessage::DetectStalls`].
+    stalls: Option<StallDetector>,
+    /// Whether the application is being debugged, see [`IncomingProxyMessage::DebuggerMode`].
+    debugger_mode: bool,
+    /// Applies `incoming.max_concurrent`, see [`IncomingProxyMessage::LimitConnections`].
+    connection_limit: Option<ConnectionLimit>,
 }
 
 impl IncomingProxy {
@@ -158,6 +339,35 @@ impl IncomingProxy {
     /// [`BackgroundTasks`] struct.
     // TODO: Update outdated documentation. RawInterceptor, HttpInterceptor do not exist
     const CHANNEL_SIZE: usize = 512;
+    /// How often we look for the layers that stopped sending heartbeats.
+    const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(
+        event_hooks: EventHooks,
+        shadow_diff: Option<ShadowDiff>,
+        on_local_error: OnLocalError,
+        max_http_body_size: Option<u64>,
+    ) -> Self {
+        Self {
+            event_hooks,
+            shadow_diff,
+            on_local_error,
+            max_http_body_size,
+            ..Default::default()
+        }
+    }
+
+    /// Triggers [`SessionEvent::StealStart`] when the agent confirms the first steal
+    /// subscription.
+    fn check_steal_started(&mut self, message: &DaemonTcp) {
+        if let DaemonTcp::SubscribeResult(Ok(port)) = message {
+            if !self.steal_started {
+                self.steal_started = true;
+                self.event_hooks
+                    .trigger(SessionEvent::StealStart { port: *port });
+            }
+        }
+    }
 
     /// Tries to register the new subscription in the [`SubscriptionsManager`].
     #[tracing::instrument(level = "trace", skip(self, message_bus))]
@@ -168,6 +378,41 @@ impl IncomingProxy {
         subscribe: PortSubscribe,
         message_bus: &mut MessageBus<Self>,
     ) {
+        let shadow_supported = self.supports(Capability::HttpShadow);
+        let grpc_supported = self.supports(Capability::GrpcFilter);
+        let (feature, agent_feature) = match &subscribe.subscription {
+            PortSubscription::Steal(..) => ("stealing incoming traffic", Capability::IncomingSteal),
+            PortSubscription::Mirror(..) => {
+                ("mirroring incoming traffic", Capability::IncomingMirror)
+            }
+        };
+        let unsupported = if self.lacks(agent_feature) {
+            Some(feature)
+        } else if subscribe.subscription.is_shadow() && !shadow_supported {
+            Some("shadowing HTTP requests")
+        } else if subscribe.subscription.is_grpc() && !grpc_supported {
+            Some("filtering gRPC requests")
+        } else {
+            None
+        };
+
+        if let Some(feature) = unsupported {
+            tracing::warn!(
+                capabilities = ?self.capabilities,
+                "agent does not support {feature}"
+            );
+            message_bus
+                .send(ToLayer {
+                    message_id,
+                    layer_id,
+                    message: ProxyToLayerMessage::Incoming(IncomingResponse::PortSubscribe(Err(
+                        ResponseError::NotImplemented,
+                    ))),
+                })
+                .await;
+            return;
+        }
+
         let msg = self
             .subscriptions
             .layer_subscribed(layer_id, message_id, subscribe);
@@ -192,6 +437,40 @@ impl IncomingProxy {
         }
     }
 
+    /// Registers the new UDP subscription in the [`UdpIncoming`], if the agent supports it.
+    #[tracing::instrument(level = "trace", skip(self, message_bus))]
+    async fn handle_udp_port_subscribe(
+        &mut self,
+        message_id: MessageId,
+        layer_id: LayerId,
+        subscribe: UdpPortSubscribe,
+        message_bus: &mut MessageBus<Self>,
+    ) {
+        let supported = self.supports(Capability::UdpIncoming);
+        if !supported {
+            tracing::warn!(
+                capabilities = ?self.capabilities,
+                "agent does not support incoming UDP traffic"
+            );
+        }
+
+        self.udp
+            .layer_subscribed(layer_id, message_id, subscribe, supported, message_bus)
+            .await;
+    }
+
+    /// Records the given request in the [`ShadowDiff`], if it's shadowed.
+    fn record_shadowed_request(&mut self, request: &HttpRequestFallback) {
+        let shadowed = self
+            .subscriptions
+            .get(request.port())
+            .is_some_and(|subscribe| subscribe.subscription.is_shadow());
+
+        if let (true, Some(shadow_diff)) = (shadowed, self.shadow_diff.as_mut()) {
+            shadow_diff.request(request);
+        }
+    }
+
     /// Retrieves or creates an [`Interceptor`] for the given [`HttpRequestFallback`].
     /// The request may or may not belong to an existing connection (when stealing with an http
     /// filter, connections are created implicitly).
@@ -216,12 +495,25 @@ impl IncomingProxy {
                 };
 
                 let interceptor_socket = bind_similar(subscription.listening_on)?;
+                let mut interceptor =
+                    Interceptor::new(interceptor_socket, subscription.listening_on)
+                        .with_max_body_size(self.max_http_body_size);
 
-                let interceptor = self.background_tasks.register(
-                    Interceptor::new(interceptor_socket, subscription.listening_on),
-                    id,
-                    Self::CHANNEL_SIZE,
-                );
+                let pass_through_supported = self.supports(Capability::HttpPassThrough);
+                if self.on_local_error == OnLocalError::Fallback
+                    && pass_through_supported
+                    && matches!(subscription.subscription, PortSubscription::Steal(..))
+                    && !subscription.subscription.is_shadow()
+                {
+                    interceptor = interceptor.with_fallback_to_remote();
+                    if self.debugger_mode {
+                        interceptor = interceptor.without_fallback_timeout();
+                    }
+                }
+
+                let interceptor =
+                    self.background_tasks
+                        .register(interceptor, id, Self::CHANNEL_SIZE);
 
                 e.insert(InterceptorHandle {
                     tx: interceptor,
@@ -258,6 +550,13 @@ impl IncomingProxy {
             }
             DaemonTcp::HttpRequest(req) => {
                 let req = HttpRequestFallback::Fallback(req);
+                if self
+                    .close_if_stalled(req.port(), req.connection_id(), message_bus)
+                    .await
+                {
+                    return Ok(());
+                }
+                self.record_shadowed_request(&req);
                 let interceptor = self.get_interceptor_for_http_request(&req)?;
                 if let Some(interceptor) = interceptor {
                     interceptor.send(req).await;
@@ -265,11 +564,23 @@ impl IncomingProxy {
             }
             DaemonTcp::HttpRequestFramed(req) => {
                 let req = HttpRequestFallback::Framed(req);
+                if self
+                    .close_if_stalled(req.port(), req.connection_id(), message_bus)
+                    .await
+                {
+                    return Ok(());
+                }
+                self.record_shadowed_request(&req);
                 let interceptor = self.get_interceptor_for_http_request(&req)?;
                 if let Some(interceptor) = interceptor {
                     interceptor.send(req).await;
                 }
             }
+            DaemonTcp::HttpShadowResponse(response) => {
+                if let Some(shadow_diff) = self.shadow_diff.as_mut() {
+                    shadow_diff.remote_response(&response);
+                }
+            }
             DaemonTcp::NewConnection(NewTcpConnection {
                 connection_id,
                 remote_address,
@@ -282,6 +593,13 @@ impl IncomingProxy {
                     return Ok(());
                 };
 
+                if self
+                    .close_if_stalled(destination_port, connection_id, message_bus)
+                    .await
+                {
+                    return Ok(());
+                }
+
                 let interceptor_socket = bind_similar(subscription.listening_on)?;
 
                 let id = InterceptorId(connection_id);
@@ -324,6 +642,105 @@ impl IncomingProxy {
         Ok(())
     }
 
+    /// Handles the agent messages about stolen connections, applying the [`ConnectionLimit`]
+    /// before [`Self::handle_agent_message`].
+    ///
+    /// New connections that don't fit are queued with their messages, or closed in the agent when
+    /// the queue is full too.
+    #[tracing::instrument(level = "trace", skip(self, message_bus))]
+    async fn handle_agent_steal(
+        &mut self,
+        message: DaemonTcp,
+        message_bus: &mut MessageBus<Self>,
+    ) -> Result<(), IncomingProxyError> {
+        let Some(limit) = self.connection_limit.as_mut() else {
+            return self.handle_agent_message(message, message_bus).await;
+        };
+
+        let (connection_id, port) = match &message {
+            DaemonTcp::Close(close) => {
+                limit.finished(close.connection_id);
+                self.handle_agent_message(message, message_bus).await?;
+                return self.start_queued_connections(message_bus).await;
+            }
+            DaemonTcp::Data(data) => {
+                let connection_id = data.connection_id;
+                if limit.is_queued(connection_id) {
+                    limit.queue(connection_id, message);
+                    return Ok(());
+                }
+                return self.handle_agent_message(message, message_bus).await;
+            }
+            DaemonTcp::NewConnection(connection) => {
+                (connection.connection_id, connection.destination_port)
+            }
+            DaemonTcp::HttpRequest(req) => (req.connection_id, req.port),
+            DaemonTcp::HttpRequestFramed(req) => (req.connection_id, req.port),
+            DaemonTcp::HttpShadowResponse(..) | DaemonTcp::SubscribeResult(..) => {
+                return self.handle_agent_message(message, message_bus).await;
+            }
+        };
+
+        let id = InterceptorId(connection_id);
+        let is_new = !self.interceptors.contains_key(&id);
+
+        if limit.is_queued(connection_id) || (is_new && limit.is_full()) {
+            if !limit.queue(connection_id, message) {
+                tracing::debug!(
+                    connection_id,
+                    port,
+                    "too many stolen connections, closing a new one"
+                );
+                let msg = self.subscriptions.get(port).map(|subscribe| {
+                    subscribe
+                        .subscription
+                        .wrap_agent_unsubscribe_connection(connection_id)
+                });
+                if let Some(msg) = msg {
+                    message_bus.send(msg).await;
+                }
+            }
+            return Ok(());
+        }
+
+        self.handle_agent_message(message, message_bus).await?;
+
+        if is_new && self.interceptors.contains_key(&id) {
+            if let Some(limit) = self.connection_limit.as_mut() {
+                limit.started(connection_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delivers the queued stolen connections that fit in the [`ConnectionLimit`] now.
+    async fn start_queued_connections(
+        &mut self,
+        message_bus: &mut MessageBus<Self>,
+    ) -> Result<(), IncomingProxyError> {
+        while let Some((connection_id, messages)) = self
+            .connection_limit
+            .as_mut()
+            .and_then(ConnectionLimit::next_queued)
+        {
+            for message in messages {
+                self.handle_agent_message(message, message_bus).await?;
+            }
+
+            if self
+                .interceptors
+                .contains_key(&InterceptorId(connection_id))
+            {
+                if let Some(limit) = self.connection_limit.as_mut() {
+                    limit.started(connection_id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn handle_layer_fork(&mut self, msg: LayerForked) {
         let LayerForked { child, parent } = msg;
         self.subscriptions.layer_forked(parent, child);
@@ -335,6 +752,165 @@ impl IncomingProxy {
         for msg in msgs {
             message_bus.send(msg).await;
         }
+
+        self.udp.layer_closed(msg.id, message_bus).await;
+
+        if let Some(stalls) = self.stalls.as_mut() {
+            stalls.layer_closed(msg.id);
+            self.handle_stalls_changed(message_bus).await;
+        }
+    }
+
+    /// Moves the port subscriptions to the new agent, after the connection with the previous one
+    /// was lost.
+    ///
+    /// Connections intercepted through the previous agent can't be continued, so all
+    /// [`Interceptor`]s are aborted.
+    #[tracing::instrument(level = "trace", skip_all)]
+    async fn handle_agent_reconnected(&mut self, message_bus: &MessageBus<Self>) {
+        self.interceptors.clear();
+        self.background_tasks.abort_all();
+        if let Some(limit) = self.connection_limit.as_mut() {
+            limit.clear();
+        }
+        self.metadata_store = Default::default();
+        message_bus.send(ProxyMessage::AgentReconnected).await;
+
+        for msg in self.subscriptions.agent_reconnected() {
+            message_bus.send(msg).await;
+        }
+
+        self.udp.agent_reconnected(message_bus).await;
+    }
+
+    /// Asks the agent for the listeners of the target, if [`IncomingProxyMessage::WatchListeners`]
+    /// was received. Subscriptions wait for them from now on, unless the agent can't report them.
+    async fn handle_agent_capabilities(
+        &mut self,
+        capabilities: &ProtocolCapabilities,
+        message_bus: &MessageBus<Self>,
+    ) {
+        if !self.subscriptions.is_watching_listeners() {
+            return;
+        }
+
+        if capabilities.supports(Capability::ListenersWatch) {
+            message_bus.send(ClientMessage::WatchListeners).await;
+            return;
+        }
+
+        tracing::warn!(
+            ?capabilities,
+            "agent does not report the ports the target listens on, `ports: \"auto\"` is ignored \
+             and all ports are subscribed"
+        );
+        for msg in self.subscriptions.stop_watching_listeners() {
+            message_bus.send(msg).await;
+        }
+    }
+
+    /// Whether the agent supports the given [`Capability`], [`false`] until the capabilities are
+    /// negotiated.
+    fn supports(&self, capability: Capability) -> bool {
+        self.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports(capability))
+    }
+
+    /// Whether the agent is known not to provide the given feature, e.g. an unprivileged agent
+    /// can't steal. [`false`] until the capabilities are negotiated.
+    fn lacks(&self, feature: Capability) -> bool {
+        self.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| !capabilities.supports(feature))
+    }
+
+    /// Whether the connections stolen from the given port should be closed right away, because
+    /// the layer that subscribed it is stalled and `on_stall` is not [`OnStall::Hold`].
+    fn is_stalled(&self, port: Port) -> bool {
+        let Some(stalls) = self.stalls.as_ref() else {
+            return false;
+        };
+
+        stalls.policy != OnStall::Hold
+            && self.subscriptions.get(port).is_some_and(|subscribe| {
+                matches!(subscribe.subscription, PortSubscription::Steal(..))
+                    && !subscribe.subscription.is_shadow()
+            })
+            && self
+                .subscriptions
+                .get_layer(port)
+                .is_some_and(|layer_id| stalls.stalled.contains(&layer_id))
+    }
+
+    /// Closes the given stolen connection in the agent, if its port [`Self::is_stalled`].
+    /// Returns whether it was closed.
+    async fn close_if_stalled(
+        &self,
+        port: Port,
+        connection_id: ConnectionId,
+        message_bus: &MessageBus<Self>,
+    ) -> bool {
+        if !self.is_stalled(port) {
+            return false;
+        }
+
+        let msg = self.subscriptions.get(port).map(|subscribe| {
+            subscribe
+                .subscription
+                .wrap_agent_unsubscribe_connection(connection_id)
+        });
+        if let Some(msg) = msg {
+            message_bus.send(msg).await;
+        }
+
+        true
+    }
+
+    /// Enters `debugger_mode` after a debugger was found stopping the application: restores the
+    /// traffic of the stalled layers and stops detecting stalls, which are now expected.
+    async fn enter_debugger_mode(&mut self, message_bus: &MessageBus<Self>) {
+        self.debugger_mode = true;
+
+        if let Some(stalls) = self.stalls.as_mut() {
+            stalls.stalled.clear();
+            self.handle_stalls_changed(message_bus).await;
+        }
+        self.stalls = None;
+    }
+
+    /// Applies `on_stall` after the set of stalled layers changed: closes the connections stolen
+    /// for the stalled layers and, with [`OnStall::Fallback`], moves their subscriptions out of
+    /// the agent (or back into it).
+    #[tracing::instrument(level = "trace", skip_all)]
+    async fn handle_stalls_changed(&mut self, message_bus: &MessageBus<Self>) {
+        let Some(stalls) = self.stalls.as_ref() else {
+            return;
+        };
+
+        if stalls.policy == OnStall::Fallback {
+            for msg in self.subscriptions.apply_stalls(&stalls.stalled) {
+                message_bus.send(msg).await;
+            }
+        }
+
+        let stalled_connections = self
+            .interceptors
+            .iter()
+            .filter(|(_, handle)| self.is_stalled(handle.subscription.port()))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in stalled_connections {
+            let Some(handle) = self.interceptors.remove(&id) else {
+                continue;
+            };
+
+            self.metadata_store.no_longer_expect(id);
+            message_bus
+                .send(handle.subscription.wrap_agent_unsubscribe_connection(id.0))
+                .await;
+        }
     }
 
     fn get_subscription(&self, interceptor_id: InterceptorId) -> Option<&PortSubscription> {
@@ -350,6 +926,9 @@ impl BackgroundTask for IncomingProxy {
     type MessageOut = ProxyMessage;
 
     async fn run(mut self, message_bus: &mut MessageBus<Self>) -> Result<(), Self::Error> {
+        let mut stall_checks = time::interval(Self::STALL_CHECK_INTERVAL);
+        stall_checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         loop {
             tokio::select! {
                 msg = message_bus.recv() => match msg {
@@ -364,37 +943,126 @@ impl BackgroundTask for IncomingProxy {
                             let res = self.metadata_store.get(req);
                             message_bus.send(ToLayer { message_id, layer_id, message: ProxyToLayerMessage::Incoming(IncomingResponse::ConnMetadata(res))  }).await;
                         }
+                        IncomingRequest::UdpPortSubscribe(subscribe) => self.handle_udp_port_subscribe(message_id, layer_id, subscribe, message_bus).await,
+                        IncomingRequest::UdpPortUnsubscribe(unsubscribe) => self.udp.layer_unsubscribed(layer_id, unsubscribe, message_bus).await,
+                        IncomingRequest::UdpPeer(req) => {
+                            let res = self.udp.peer(req);
+                            message_bus.send(ToLayer { message_id, layer_id, message: ProxyToLayerMessage::Incoming(IncomingResponse::UdpPeer(res)) }).await;
+                        }
                     },
                     Some(IncomingProxyMessage::AgentMirror(msg)) => {
                         self.handle_agent_message(msg, message_bus).await?;
                     }
                     Some(IncomingProxyMessage::AgentSteal(msg)) => {
-                        self.handle_agent_message(msg, message_bus).await?;
+                        self.check_steal_started(&msg);
+                        self.handle_agent_steal(msg, message_bus).await?;
+                    }
+                    Some(IncomingProxyMessage::AgentUdpMirror(msg)) => {
+                        self.udp.agent_message(msg, false, message_bus).await?;
+                    }
+                    Some(IncomingProxyMessage::AgentUdpSteal(msg)) => {
+                        self.udp.agent_message(msg, true, message_bus).await?;
                     }
                     Some(IncomingProxyMessage::LayerClosed(msg)) => self.handle_layer_close(msg, message_bus).await,
                     Some(IncomingProxyMessage::LayerForked(msg)) => self.handle_layer_fork(msg),
+                    Some(IncomingProxyMessage::AgentReconnected) => self.handle_agent_reconnected(message_bus).await,
+                    Some(IncomingProxyMessage::AgentCapabilities(capabilities)) => {
+                        if self.on_local_error == OnLocalError::Fallback && !capabilities.supports(Capability::HttpPassThrough) {
+                            tracing::warn!(
+                                ?capabilities,
+                                "agent does not support passing stolen requests to the remote target, `on_local_error: fallback` is ignored"
+                            );
+                        }
+                        self.handle_agent_capabilities(&capabilities, message_bus).await;
+                        self.capabilities.replace(capabilities);
+                    }
+                    Some(IncomingProxyMessage::WatchListeners) => self.subscriptions.watch_listeners(),
+                    Some(IncomingProxyMessage::AgentListeners(ports)) => {
+                        for msg in self.subscriptions.listeners_changed(ports) {
+                            message_bus.send(msg).await;
+                        }
+                    }
+                    Some(IncomingProxyMessage::DetectStalls { policy, timeout }) => {
+                        self.stalls = Some(StallDetector::new(policy, timeout));
+                    }
+                    Some(IncomingProxyMessage::DebuggerMode) => self.debugger_mode = true,
+                    Some(IncomingProxyMessage::LayerHeartbeat(layer_id, pid)) => {
+                        if self.stalls.as_mut().is_some_and(|stalls| stalls.heartbeat(layer_id, pid)) {
+                            self.handle_stalls_changed(message_bus).await;
+                        }
+                    }
+                    Some(IncomingProxyMessage::LimitConnections { max_concurrent, queue_size }) => {
+                        self.connection_limit = Some(ConnectionLimit::new(max_concurrent, queue_size));
+                    }
+                    Some(IncomingProxyMessage::ConfigReloaded(incoming_mode)) => {
+                        for msg in self.subscriptions.incoming_mode_changed(&incoming_mode) {
+                            message_bus.send(msg).await;
+                        }
+                    }
                 },
 
+                _ = stall_checks.tick(), if self.stalls.is_some() => {
+                    if self.stalls.as_mut().is_some_and(StallDetector::check) {
+                        self.handle_stalls_changed(message_bus).await;
+                    }
+                    if self.stalls.as_ref().is_some_and(|stalls| stalls.debugger_found) {
+                        self.enter_debugger_mode(message_bus).await;
+                    }
+                }
+
                 Some(task_update) = self.background_tasks.next() => match task_update {
                     (id, TaskUpdate::Finished(res)) => {
                         tracing::trace!("{id} finished: {res:?}");
 
                         self.metadata_store.no_longer_expect(id);
+                        if let Some(shadow_diff) = self.shadow_diff.as_mut() {
+                            shadow_diff.connection_finished(id.0);
+                        }
 
                         let msg = self.get_subscription(id).map(|s| s.wrap_agent_unsubscribe_connection(id.0));
                         if let Some(msg) = msg {
                             message_bus.send(msg).await;
                         }
+
+                        if let Some(limit) = self.connection_limit.as_mut() {
+                            limit.finished(id.0);
+                            self.start_queued_connections(message_bus).await?;
+                        }
                     },
 
                     (id, TaskUpdate::Message(msg)) => {
+                        let shadowed = self.get_subscription(id).is_some_and(|s| s.is_shadow());
+                        if let (true, MessageOut::Http(response), Some(shadow_diff)) = (shadowed, &msg, self.shadow_diff.as_mut()) {
+                            shadow_diff.local_response(response);
+                        }
+
                         let msg = self.get_subscription(id).and_then(|s| s.wrap_response(msg, id.0));
                         if let Some(msg) = msg {
                             message_bus.send(msg).await;
                         }
                     },
                 },
+
+                Some((id, update)) = self.udp.next_peer_update() => self.udp.peer_update(id, update, message_bus).await,
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debugger_stops() {
+        let status = |state: &str, tracer: &str| {
+            format!("Name:\tapp\nState:\t{state}\nTgid:\t42\nPid:\t42\nTracerPid:\t{tracer}\n")
+        };
+
+        assert!(is_debugger_stop(&status("t (tracing stop)", "7")));
+        assert!(is_debugger_stop(&status("T (stopped)", "7")));
+        assert!(!is_debugger_stop(&status("T (stopped)", "0")));
+        assert!(!is_debugger_stop(&status("S (sleeping)", "7")));
+        assert!(!is_debugger_stop(""));
+    }
+}
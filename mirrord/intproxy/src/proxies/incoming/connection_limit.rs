@@ -0,0 +1,137 @@
+//! Limits the number of stolen connections delivered to the user application at once
+//! (`incoming.max_concurrent`), so that stealing a busy port doesn't exhaust the local file
+//! descriptors.
+
+use std::collections::{HashSet, VecDeque};
+
+use mirrord_protocol::{tcp::DaemonTcp, ConnectionId};
+
+/// Tracks the stolen connections that have an [`Interceptor`](super::interceptor::Interceptor),
+/// and queues the new ones while there are too many of them.
+///
+/// Queued connections stay open in the agent, and the messages that come for them are kept here
+/// until they get a free slot. When the queue is full too, new connections are closed in the
+/// agent right away.
+#[derive(Debug)]
+pub struct ConnectionLimit {
+    /// How many connections can be delivered at once.
+    max_concurrent: usize,
+    /// How many connections can wait for a free slot.
+    queue_size: usize,
+    /// Connections delivered to the user application.
+    active: HashSet<ConnectionId>,
+    /// Connections waiting for a free slot, in the order they came in, with their messages.
+    queued: VecDeque<(ConnectionId, Vec<DaemonTcp>)>,
+}
+
+impl ConnectionLimit {
+    pub fn new(max_concurrent: usize, queue_size: usize) -> Self {
+        Self {
+            max_concurrent,
+            queue_size,
+            active: Default::default(),
+            queued: Default::default(),
+        }
+    }
+
+    /// Whether new connections have to wait.
+    pub fn is_full(&self) -> bool {
+        self.active.len() >= self.max_concurrent
+    }
+
+    /// Whether the connection is waiting for a free slot.
+    pub fn is_queued(&self, connection_id: ConnectionId) -> bool {
+        self.queued.iter().any(|(id, _)| *id == connection_id)
+    }
+
+    /// Records that the connection was delivered to the user application.
+    pub fn started(&mut self, connection_id: ConnectionId) {
+        self.active.insert(connection_id);
+    }
+
+    /// Keeps the message until the connection gets a free slot. Returns [`false`] when this is a
+    /// new connection and the queue is full, then the connection should be closed.
+    pub fn queue(&mut self, connection_id: ConnectionId, message: DaemonTcp) -> bool {
+        if let Some((_, messages)) = self.queued.iter_mut().find(|(id, _)| *id == connection_id) {
+            messages.push(message);
+            return true;
+        }
+
+        if self.queued.len() >= self.queue_size {
+            return false;
+        }
+
+        self.queued.push_back((connection_id, vec![message]));
+        true
+    }
+
+    /// Frees the slot of the connection, or drops it from the queue.
+    pub fn finished(&mut self, connection_id: ConnectionId) {
+        self.active.remove(&connection_id);
+        self.queued.retain(|(id, _)| *id != connection_id);
+    }
+
+    /// Takes the first queued connection with its messages, if there is a free slot for it.
+    pub fn next_queued(&mut self) -> Option<(ConnectionId, Vec<DaemonTcp>)> {
+        if self.is_full() {
+            return None;
+        }
+
+        self.queued.pop_front()
+    }
+
+    /// Forgets all connections, they can't be continued with a new agent.
+    pub fn clear(&mut self) {
+        self.active.clear();
+        self.queued.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mirrord_protocol::tcp::TcpData;
+
+    use super::*;
+
+    fn data(connection_id: ConnectionId) -> DaemonTcp {
+        DaemonTcp::Data(TcpData {
+            connection_id,
+            bytes: b"hello".to_vec(),
+        })
+    }
+
+    #[test]
+    fn connections_wait_for_a_free_slot() {
+        let mut limit = ConnectionLimit::new(1, 1);
+
+        assert!(!limit.is_full());
+        limit.started(0);
+        assert!(limit.is_full());
+
+        assert!(limit.queue(1, data(1)));
+        assert!(limit.queue(1, data(1)));
+        assert!(limit.is_queued(1));
+        assert!(!limit.queue(2, data(2)), "queue should be full");
+        assert!(limit.next_queued().is_none());
+
+        limit.finished(0);
+        let (connection_id, messages) = limit.next_queued().unwrap();
+        assert_eq!(connection_id, 1);
+        assert_eq!(messages, vec![data(1), data(1)]);
+        assert!(!limit.is_queued(1));
+    }
+
+    #[test]
+    fn closed_connections_leave_the_queue() {
+        let mut limit = ConnectionLimit::new(1, 2);
+        limit.started(0);
+
+        assert!(limit.queue(1, data(1)));
+        assert!(limit.queue(2, data(2)));
+        limit.finished(1);
+
+        limit.finished(0);
+        assert_eq!(limit.next_queued().map(|(id, _)| id), Some(2));
+        assert!(limit.next_queued().is_none());
+    }
+}
@@ -8,6 +8,7 @@ use std::{
 };
 
 use bytes::BytesMut;
+use http_body_util::{LengthLimitError, Limited};
 use hyper::{upgrade::OnUpgrade, StatusCode, Version};
 use hyper_util::rt::TokioIo;
 use mirrord_protocol::tcp::{
@@ -21,7 +22,10 @@ use tokio::{
 };
 
 use super::http::HttpSender;
-use crate::background_tasks::{BackgroundTask, MessageBus};
+use crate::{
+    background_tasks::{BackgroundTask, MessageBus},
+    size_limits::{SizeLimits, MAX_HTTP_BODY_SIZE_KEY},
+};
 
 /// Messages consumed by the [`Interceptor`] when it runs as a [`BackgroundTask`].
 pub enum MessageIn {
@@ -38,6 +42,9 @@ pub enum MessageOut {
     Http(HttpResponseFallback),
     /// Data received from the user application.
     Raw(Vec<u8>),
+    /// The user application failed to handle the request, it should be passed to the remote
+    /// target instead (see [`Interceptor::with_fallback_to_remote`]).
+    PassThrough(HttpRequestFallback),
 }
 
 impl From<HttpRequestFallback> for MessageIn {
@@ -88,9 +95,16 @@ pub type InterceptorResult<T, E = InterceptorError> = core::result::Result<T, E>
 pub struct Interceptor {
     socket: TcpSocket,
     peer: SocketAddr,
+    fallback_to_remote: bool,
+    no_fallback_timeout: bool,
+    max_body_size: Option<u64>,
 }
 
 impl Interceptor {
+    /// How long the user application has to respond to an HTTP request, when the request can be
+    /// passed to the remote target (see [`Self::with_fallback_to_remote`]).
+    const FALLBACK_TIMEOUT: Duration = Duration::from_secs(30);
+
     /// Creates a new instance. When run, this instance will use the given `socket` (must be already
     /// bound) to communicate with the given `peer`.
     ///
@@ -98,7 +112,37 @@ impl Interceptor {
     ///
     /// The socket can be replaced when retrying HTTP requests.
     pub fn new(socket: TcpSocket, peer: SocketAddr) -> Self {
-        Self { socket, peer }
+        Self {
+            socket,
+            peer,
+            fallback_to_remote: false,
+            no_fallback_timeout: false,
+            max_body_size: None,
+        }
+    }
+
+    /// When the user application responds to an HTTP request with a server error (`5xx`) or does
+    /// not respond within [`Self::FALLBACK_TIMEOUT`], this instance will produce
+    /// [`MessageOut::PassThrough`] instead of the response.
+    pub fn with_fallback_to_remote(mut self) -> Self {
+        self.fallback_to_remote = true;
+        self
+    }
+
+    /// With [`Self::with_fallback_to_remote`], waits for the response of the user application for
+    /// as long as it takes, as it can sit at a breakpoint (`debugger_mode`). Only the server
+    /// errors are passed to the remote target.
+    pub fn without_fallback_timeout(mut self) -> Self {
+        self.no_fallback_timeout = true;
+        self
+    }
+
+    /// HTTP requests with bodies bigger than `max_body_size` bytes are answered with
+    /// `413 Payload Too Large` without reaching the user application, and bigger responses of the
+    /// user application are replaced with `502 Bad Gateway`.
+    pub fn with_max_body_size(mut self, max_body_size: Option<u64>) -> Self {
+        self.max_body_size = max_body_size;
+        self
     }
 }
 
@@ -107,6 +151,12 @@ impl BackgroundTask for Interceptor {
     type MessageIn = MessageIn;
     type MessageOut = MessageOut;
 
+    #[tracing::instrument(
+        level = "info",
+        name = "incoming_connection",
+        skip_all,
+        fields(peer = %self.peer)
+    )]
     async fn run(self, message_bus: &mut MessageBus<Self>) -> InterceptorResult<(), Self::Error> {
         let mut stream = self.socket.connect(self.peer).await?;
 
@@ -139,9 +189,11 @@ impl BackgroundTask for Interceptor {
         let mut http_conn = HttpConnection {
             sender,
             peer: self.peer,
+            fallback_to_remote: self.fallback_to_remote,
+            max_body_size: self.max_body_size,
         };
-        let (response, on_upgrade) = http_conn.send(request).await?;
-        message_bus.send(MessageOut::Http(response)).await;
+        let (message, on_upgrade) = http_conn.handle(request).await?;
+        message_bus.send(message).await;
 
         let raw = if let Some(on_upgrade) = on_upgrade {
             let upgraded = on_upgrade.await?;
@@ -176,6 +228,10 @@ struct HttpConnection {
     peer: SocketAddr,
     /// Handle to the HTTP connection between the [`Interceptor`] the server.
     sender: HttpSender,
+    /// See [`Interceptor::with_fallback_to_remote`].
+    fallback_to_remote: bool,
+    /// See [`Interceptor::with_max_body_size`].
+    max_body_size: Option<u64>,
 }
 
 impl HttpConnection {
@@ -237,6 +293,12 @@ impl HttpConnection {
                     None
                 };
 
+                let limit = self
+                    .max_body_size
+                    .and_then(|limit| usize::try_from(limit).ok())
+                    .unwrap_or(usize::MAX);
+                let res = res.map(|body| Limited::new(body, limit));
+
                 let result = match &request {
                     HttpRequestFallback::Framed(..) => {
                         HttpResponse::<InternalHttpBody>::from_hyper_response(
@@ -263,6 +325,27 @@ impl HttpConnection {
                 Ok(result
                     .map(|response| (response, upgrade))
                     .unwrap_or_else(|e| {
+                        if let Some(limit) = self
+                            .max_body_size
+                            .filter(|_| e.downcast_ref::<LengthLimitError>().is_some())
+                        {
+                            let body_message = format!(
+                                "mirrord: response body of the local application is over the \
+                                 limit of {limit} bytes, set `{MAX_HTTP_BODY_SIZE_KEY}` in the \
+                                 mirrord config to change it."
+                            );
+                            tracing::warn!("{body_message}");
+
+                            return (
+                                HttpResponseFallback::response_from_request(
+                                    request,
+                                    StatusCode::BAD_GATEWAY,
+                                    &body_message,
+                                ),
+                                None,
+                            );
+                        }
+
                         tracing::error!(
                             "Failed to read response to filtered http request: {e:?}. \
                             Please consider reporting this issue on \
@@ -300,15 +383,103 @@ impl HttpConnection {
         tracing::trace!("Request {request:?} connection was closed too soon, retrying once");
 
         // Create a new connection for this second attempt.
-        let socket = super::bind_similar(self.peer)?;
-        let stream = socket.connect(self.peer).await?;
-        let new_sender = super::http::handshake(request.version(), stream).await?;
-        self.sender = new_sender;
+        self.reconnect(request.version()).await?;
 
         let response = self.sender.send(request.clone()).await;
         self.handle_response(request, response).await
     }
 
+    /// Returns the `413 Payload Too Large` response for a request with a body over
+    /// [`Self::max_body_size`], which is not sent to the server.
+    fn body_too_large_response(
+        &self,
+        request: &HttpRequestFallback,
+    ) -> Option<HttpResponseFallback> {
+        let limit = self.max_body_size?;
+        let size = match request {
+            HttpRequestFallback::Framed(request) => request.internal_request.body.data_len(),
+            HttpRequestFallback::Fallback(request) => request.internal_request.body.len(),
+        } as u64;
+        if size <= limit {
+            return None;
+        }
+
+        let message = SizeLimits::http_body_error("Body of the stolen HTTP request", size, limit);
+        tracing::warn!(request_id = request.request_id(), "{message}");
+
+        Some(HttpResponseFallback::response_from_request(
+            request.clone(),
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("mirrord: {message}"),
+        ))
+    }
+
+    /// Replaces the HTTP connection with the server with a new one.
+    async fn reconnect(&mut self, version: Version) -> InterceptorResult<()> {
+        let socket = super::bind_similar(self.peer)?;
+        let stream = socket.connect(self.peer).await?;
+        self.sender = super::http::handshake(version, stream).await?;
+
+        Ok(())
+    }
+
+    /// Sends the given [`HttpRequestFallback`] to the server with [`Self::send`], and returns the
+    /// [`MessageOut`] to be sent to the parent task.
+    ///
+    /// Returns [`MessageOut::PassThrough`] if [`Self::fallback_to_remote`] is set and the server
+    /// fails to handle the request.
+    #[tracing::instrument(
+        level = "info",
+        name = "incoming_http_request",
+        skip_all,
+        fields(request_id = request.request_id(), port = request.port())
+    )]
+    async fn handle(
+        &mut self,
+        request: HttpRequestFallback,
+    ) -> InterceptorResult<(MessageOut, Option<OnUpgrade>)> {
+        if let Some(response) = self.body_too_large_response(&request) {
+            return Ok((MessageOut::Http(response), None));
+        }
+
+        if !self.fallback_to_remote {
+            let (response, on_upgrade) = self.send(request).await?;
+            return Ok((MessageOut::Http(response), on_upgrade));
+        }
+
+        let version = request.version();
+        let response = if self.no_fallback_timeout {
+            Ok(self.send(request.clone()).await)
+        } else {
+            time::timeout(Interceptor::FALLBACK_TIMEOUT, self.send(request.clone())).await
+        };
+        match response {
+            Ok(Ok((response, on_upgrade))) if !response.status().is_server_error() => {
+                Ok((MessageOut::Http(response), on_upgrade))
+            }
+            Ok(Ok((response, _))) => {
+                tracing::info!(
+                    status = %response.status(),
+                    request_id = request.request_id(),
+                    "Local application failed to handle the request, passing it to the remote target",
+                );
+                Ok((MessageOut::PassThrough(request), None))
+            }
+            Ok(Err(error)) => Err(error),
+            Err(..) => {
+                tracing::info!(
+                    request_id = request.request_id(),
+                    "Local application did not respond in {:?}, passing the request to the remote \
+                     target",
+                    Interceptor::FALLBACK_TIMEOUT,
+                );
+                // The request may still be in flight on the old connection.
+                self.reconnect(version).await?;
+                Ok((MessageOut::PassThrough(request), None))
+            }
+        }
+    }
+
     /// Proxies HTTP messages until an HTTP upgrade happens or the [`MessageBus`] closes.
     /// Support retries (with reconnecting to the HTTP server).
     ///
@@ -331,9 +502,8 @@ impl HttpConnection {
                 }
 
                 MessageIn::Http(req) => {
-                    let (res, on_upgrade) = self.send(req).await?;
-                    println!("{} has upgrade: {}", res.request_id(), on_upgrade.is_some());
-                    message_bus.send(MessageOut::Http(res)).await;
+                    let (message, on_upgrade) = self.handle(req).await?;
+                    message_bus.send(message).await;
 
                     if let Some(on_upgrade) = on_upgrade {
                         break on_upgrade.await?;
@@ -429,7 +599,7 @@ mod test {
     use std::convert::Infallible;
 
     use bytes::Bytes;
-    use http_body_util::Empty;
+    use http_body_util::{Empty, Full};
     use hyper::{
         body::Incoming,
         header::{HeaderValue, CONNECTION, UPGRADE},
@@ -618,4 +788,133 @@ mod test {
         let _ = shutdown_tx.send(true);
         server_task.await.expect("dummy echo server panicked");
     }
+
+    /// The user application responds with a server error, so the request is passed to the remote
+    /// target.
+    #[tokio::test]
+    async fn fallback_to_remote() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_destination = listener.local_addr().unwrap();
+
+        let server_task = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|_| async {
+                let mut response = Response::new(Empty::<Bytes>::new());
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                Ok::<_, Infallible>(response)
+            });
+
+            http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+                .unwrap();
+        });
+
+        let mut tasks: BackgroundTasks<(), MessageOut, InterceptorError> = Default::default();
+        let interceptor = {
+            let socket = TcpSocket::new_v4().unwrap();
+            socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            tasks.register(
+                Interceptor::new(socket, local_destination).with_fallback_to_remote(),
+                (),
+                8,
+            )
+        };
+
+        interceptor
+            .send(HttpRequestFallback::Fallback(HttpRequest {
+                connection_id: 0,
+                request_id: 3,
+                port: 80,
+                internal_request: InternalHttpRequest {
+                    method: Method::GET,
+                    uri: "http://www.mirrord.dev/".parse().unwrap(),
+                    headers: Default::default(),
+                    version: Version::HTTP_11,
+                    body: b"hello".to_vec(),
+                },
+            }))
+            .await;
+
+        let (_, update) = tasks.next().await.expect("no task result");
+        match update {
+            TaskUpdate::Message(MessageOut::PassThrough(request)) => {
+                assert_eq!(request.request_id(), 3);
+                let request = request.into_framed();
+                assert_eq!(request.internal_request.body.data(), b"hello");
+            }
+            _ => panic!("unexpected task update: {update:?}"),
+        }
+
+        server_task.abort();
+    }
+
+    /// Bodies over the limit are rejected in both directions.
+    #[tokio::test]
+    async fn max_body_size() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_destination = listener.local_addr().unwrap();
+
+        let server_task = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|_| async {
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(
+                    b"response over the limit",
+                ))))
+            });
+
+            http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+                .unwrap();
+        });
+
+        let mut tasks: BackgroundTasks<(), MessageOut, InterceptorError> = Default::default();
+        let interceptor = {
+            let socket = TcpSocket::new_v4().unwrap();
+            socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            tasks.register(
+                Interceptor::new(socket, local_destination).with_max_body_size(Some(8)),
+                (),
+                8,
+            )
+        };
+
+        let request = |request_id, body: &[u8]| {
+            HttpRequestFallback::Fallback(HttpRequest {
+                connection_id: 0,
+                request_id,
+                port: 80,
+                internal_request: InternalHttpRequest {
+                    method: Method::POST,
+                    uri: "http://www.mirrord.dev/".parse().unwrap(),
+                    headers: Default::default(),
+                    version: Version::HTTP_11,
+                    body: body.to_vec(),
+                },
+            })
+        };
+
+        interceptor
+            .send(request(0, b"request over the limit"))
+            .await;
+        let (_, update) = tasks.next().await.expect("no task result");
+        match update {
+            TaskUpdate::Message(MessageOut::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            _ => panic!("unexpected task update: {update:?}"),
+        }
+
+        interceptor.send(request(1, b"hello")).await;
+        let (_, update) = tasks.next().await.expect("no task result");
+        match update {
+            TaskUpdate::Message(MessageOut::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+            }
+            _ => panic!("unexpected task update: {update:?}"),
+        }
+
+        server_task.abort();
+    }
 }
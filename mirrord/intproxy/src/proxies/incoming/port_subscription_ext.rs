@@ -2,7 +2,7 @@
 
 use mirrord_intproxy_protocol::PortSubscription;
 use mirrord_protocol::{
-    tcp::{HttpResponseFallback, LayerTcp, LayerTcpSteal, StealType, TcpData},
+    tcp::{HttpFilter, HttpResponseFallback, LayerTcp, LayerTcpSteal, StealType, TcpData},
     ClientMessage, ConnectionId, Port,
 };
 
@@ -14,6 +14,7 @@ fn get_port(steal_type: &StealType) -> Port {
         StealType::All(port) => *port,
         StealType::FilteredHttp(port, _) => *port,
         StealType::FilteredHttpEx(port, _) => *port,
+        StealType::FilteredHttpShadow(port, _) => *port,
     }
 }
 
@@ -23,6 +24,13 @@ pub trait PortSubscriptionExt {
     /// Returns the subscribed port.
     fn port(&self) -> Port;
 
+    /// Whether the requests are shadowed instead of stolen (see
+    /// [`StealType::FilteredHttpShadow`]).
+    fn is_shadow(&self) -> bool;
+
+    /// Whether the requests are filtered with [`HttpFilter::Grpc`].
+    fn is_grpc(&self) -> bool;
+
     /// Returns a subscribe request to be sent to the agent.
     fn agent_subscribe(&self) -> ClientMessage;
 
@@ -45,6 +53,20 @@ impl PortSubscriptionExt for PortSubscription {
         }
     }
 
+    fn is_shadow(&self) -> bool {
+        matches!(self, Self::Steal(StealType::FilteredHttpShadow(..)))
+    }
+
+    fn is_grpc(&self) -> bool {
+        matches!(
+            self,
+            Self::Steal(
+                StealType::FilteredHttpEx(_, HttpFilter::Grpc(..))
+                    | StealType::FilteredHttpShadow(_, HttpFilter::Grpc(..))
+            )
+        )
+    }
+
     /// [`LayerTcp::PortSubscribe`] or [`LayerTcpSteal::PortSubscribe`].
     fn agent_subscribe(&self) -> ClientMessage {
         match self {
@@ -76,10 +98,11 @@ impl PortSubscriptionExt for PortSubscription {
     }
 
     /// Always [`None`] for the `mirror` mode - data coming from the layer is discarded.
+    /// Also [`None`] for shadowed requests, the agent doesn't need the responses.
     /// Corrent [`LayerTcpSteal`] variant for the `steal` mode.
     fn wrap_response(&self, res: MessageOut, connection_id: ConnectionId) -> Option<ClientMessage> {
         match self {
-            Self::Mirror(..) => None,
+            Self::Mirror(..) | Self::Steal(StealType::FilteredHttpShadow(..)) => None,
             Self::Steal(..) => match res {
                 MessageOut::Raw(bytes) => {
                     Some(ClientMessage::TcpSteal(LayerTcpSteal::Data(TcpData {
@@ -93,6 +116,9 @@ impl PortSubscriptionExt for PortSubscription {
                 MessageOut::Http(HttpResponseFallback::Framed(res)) => Some(
                     ClientMessage::TcpSteal(LayerTcpSteal::HttpResponseFramed(res)),
                 ),
+                MessageOut::PassThrough(req) => Some(ClientMessage::TcpSteal(
+                    LayerTcpSteal::HttpRequestPassThrough(req.into_framed()),
+                )),
             },
         }
     }
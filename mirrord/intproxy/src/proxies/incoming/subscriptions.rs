@@ -1,10 +1,11 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     net::SocketAddr,
 };
 
 use mirrord_intproxy_protocol::{
-    IncomingResponse, LayerId, MessageId, PortSubscribe, PortUnsubscribe, ProxyToLayerMessage,
+    IncomingMode, IncomingResponse, LayerId, MessageId, PortSubscribe, PortSubscription,
+    PortUnsubscribe, ProxyToLayerMessage,
 };
 use mirrord_protocol::{BlockedAction, ClientMessage, Port, RemoteResult, ResponseError};
 
@@ -32,6 +33,14 @@ struct Subscription {
     active_source: Source,
     /// Whether this subscription is confirmed.
     confirmed: bool,
+    /// Whether this subscription waits for the target to listen on its port, see
+    /// [`SubscriptionsManager::watch_listeners`]. It was not sent to the agent yet, but the layers
+    /// were already answered.
+    deferred: bool,
+    /// Whether this subscription was removed from the agent because the layer of its active
+    /// source is stopped, see [`SubscriptionsManager::apply_stalls`]. The layers don't know about
+    /// it.
+    suspended: bool,
 }
 
 impl Subscription {
@@ -45,11 +54,104 @@ impl Subscription {
                 queued_sources: Default::default(),
                 active_source: source,
                 confirmed: false,
+                deferred: false,
+                suspended: false,
             },
             message,
         )
     }
 
+    /// Creates a new subscription from the given [`Source`], that is not sent to the agent until
+    /// [`Self::activate`] is called.
+    /// Additionally returns a message to be sent to the layer.
+    fn new_deferred(source: Source) -> (Self, ToLayer) {
+        let message = ToLayer {
+            message_id: source.message,
+            layer_id: source.layer,
+            message: ProxyToLayerMessage::Incoming(IncomingResponse::PortSubscribe(Ok(()))),
+        };
+
+        (
+            Self {
+                queued_sources: Default::default(),
+                active_source: source,
+                confirmed: true,
+                deferred: true,
+                suspended: false,
+            },
+            message,
+        )
+    }
+
+    /// Returns a message to be sent to the agent, if this subscription was deferred.
+    fn activate(&mut self) -> Option<ClientMessage> {
+        if !self.deferred {
+            return None;
+        }
+
+        self.deferred = false;
+        (!self.suspended).then(|| self.active_source.request.subscription.agent_subscribe())
+    }
+
+    /// Removes this subscription from the agent, until [`Self::resume`] is called.
+    /// Returns a message to be sent to the agent, if the subscription is there.
+    ///
+    /// Subscriptions that were not confirmed yet are left alone, the layers still wait for them.
+    fn suspend(&mut self) -> Option<ClientMessage> {
+        if self.suspended || !self.confirmed {
+            return None;
+        }
+
+        self.suspended = true;
+        (!self.deferred).then(|| {
+            self.active_source
+                .request
+                .subscription
+                .wrap_agent_unsubscribe()
+        })
+    }
+
+    /// Returns a message to be sent to the agent, if this subscription was suspended with
+    /// [`Self::suspend`].
+    fn resume(&mut self) -> Option<ClientMessage> {
+        if !self.suspended {
+            return None;
+        }
+
+        self.suspended = false;
+        (!self.deferred).then(|| self.active_source.request.subscription.agent_subscribe())
+    }
+
+    /// Replaces the [`PortSubscription`] of the steal sources with `subscription`, e.g. to use a
+    /// new HTTP filter.
+    /// Returns messages to be sent to the agent, if the active source changed and the
+    /// subscription is there.
+    ///
+    /// The agent doesn't allow a client to subscribe a port twice, so the old subscription is
+    /// removed first.
+    fn replace_steal(&mut self, subscription: PortSubscription) -> Vec<ClientMessage> {
+        for source in &mut self.queued_sources {
+            if matches!(source.request.subscription, PortSubscription::Steal(..)) {
+                source.request.subscription = subscription.clone();
+            }
+        }
+
+        let active = &mut self.active_source.request.subscription;
+        if !matches!(active, PortSubscription::Steal(..)) || *active == subscription {
+            return vec![];
+        }
+        let previous = std::mem::replace(active, subscription);
+
+        if self.deferred || self.suspended {
+            return vec![];
+        }
+
+        vec![
+            previous.wrap_agent_unsubscribe(),
+            self.active_source.request.subscription.agent_subscribe(),
+        ]
+    }
+
     /// Overwrites the active subscription [`Source`].
     /// Returns a message to be sent to the layer.
     /// Returns [`None`] if this subscription is still waiting for confirmation.
@@ -117,8 +219,9 @@ impl Subscription {
     }
 
     /// Removed a source from this subscription.
-    /// If this source is the last one, returns [`Err`] with a message to be sent to the agent.
-    fn remove_source(mut self, listening_on: SocketAddr) -> Result<Self, ClientMessage> {
+    /// If this source is the last one, returns [`Err`] with a message to be sent to the agent
+    /// ([`None`] if the subscription was deferred or suspended).
+    fn remove_source(mut self, listening_on: SocketAddr) -> Result<Self, Option<ClientMessage>> {
         let queue_size = self.queued_sources.len();
         self.queued_sources
             .retain(|source| source.request.listening_on != listening_on);
@@ -135,11 +238,13 @@ impl Subscription {
                 self.active_source = next_in_queue;
                 Ok(self)
             }
-            None => Err(self
-                .active_source
-                .request
-                .subscription
-                .wrap_agent_unsubscribe()),
+            None if self.deferred || self.suspended => Err(None),
+            None => Err(Some(
+                self.active_source
+                    .request
+                    .subscription
+                    .wrap_agent_unsubscribe(),
+            )),
         }
     }
 }
@@ -156,6 +261,9 @@ impl Subscription {
 pub struct SubscriptionsManager {
     remote_ports: RemoteResources<(Port, SocketAddr)>,
     subscriptions: HashMap<Port, Subscription>,
+    /// Ports the target listens on, when subscriptions wait for them, see
+    /// [`Self::watch_listeners`].
+    remote_listeners: Option<HashSet<Port>>,
 }
 
 impl SubscriptionsManager {
@@ -166,6 +274,13 @@ impl SubscriptionsManager {
             .map(|sub| &sub.active_source.request)
     }
 
+    /// Returns the layer of the active [`PortSubscribe`] request for the given [`Port`].
+    pub fn get_layer(&self, port: Port) -> Option<LayerId> {
+        self.subscriptions
+            .get(&port)
+            .map(|sub| sub.active_source.layer)
+    }
+
     /// Registers a new port subscription in this struct.
     /// Optionally returns a message to be sent.
     ///
@@ -192,6 +307,20 @@ impl SubscriptionsManager {
 
         match self.subscriptions.entry(port) {
             Entry::Occupied(mut e) => e.get_mut().push_source(source).map(ProxyMessage::ToLayer),
+            Entry::Vacant(e)
+                if self
+                    .remote_listeners
+                    .as_ref()
+                    .is_some_and(|listeners| !listeners.contains(&port)) =>
+            {
+                tracing::info!(
+                    port,
+                    "Target does not listen on the port, it will be subscribed when it does"
+                );
+                let (subscription, message) = Subscription::new_deferred(source);
+                e.insert(subscription);
+                Some(ProxyMessage::ToLayer(message))
+            }
             Entry::Vacant(e) => {
                 let (subscription, message) = Subscription::new(source);
                 e.insert(subscription);
@@ -221,7 +350,7 @@ impl SubscriptionsManager {
                 self.subscriptions.insert(request.port, subscription);
                 None
             }
-            Err(message) => Some(message),
+            Err(message) => message,
         }
     }
 
@@ -286,7 +415,7 @@ impl SubscriptionsManager {
                         self.subscriptions.insert(port, subscription);
                         None
                     }
-                    Err(message) => Some(message),
+                    Err(message) => message,
                 }
             })
             .collect()
@@ -296,12 +425,126 @@ impl SubscriptionsManager {
     pub fn layer_forked(&mut self, parent: LayerId, child: LayerId) {
         self.remote_ports.clone_all(parent, child);
     }
+
+    /// Notifies this struct about the session continuing with a new agent.
+    /// Returns messages to be sent to the new agent, so that it has the same subscriptions.
+    ///
+    /// Subscriptions that were already confirmed by the previous agent remain confirmed, the
+    /// layers don't need to know about the new agent.
+    pub fn agent_reconnected(&self) -> Vec<ClientMessage> {
+        self.subscriptions
+            .values()
+            .filter(|subscription| !subscription.deferred && !subscription.suspended)
+            .map(|subscription| {
+                subscription
+                    .active_source
+                    .request
+                    .subscription
+                    .agent_subscribe()
+            })
+            .collect()
+    }
+
+    /// From now on, new subscriptions are sent to the agent only when the target listens on their
+    /// ports, see [`Self::listeners_changed`]. The layers are answered right away, so that the
+    /// application can listen locally in the meantime.
+    pub fn watch_listeners(&mut self) {
+        self.remote_listeners.get_or_insert_with(Default::default);
+    }
+
+    /// Whether [`Self::watch_listeners`] was called.
+    pub fn is_watching_listeners(&self) -> bool {
+        self.remote_listeners.is_some()
+    }
+
+    /// Stops waiting for the listeners of the target, e.g. when the agent can't report them.
+    /// Returns messages to be sent to the agent, for the subscriptions that were waiting.
+    pub fn stop_watching_listeners(&mut self) -> Vec<ClientMessage> {
+        self.remote_listeners = None;
+
+        self.subscriptions
+            .values_mut()
+            .filter_map(Subscription::activate)
+            .collect()
+    }
+
+    /// Removes the steal subscriptions of the `stalled` layers from the agent, so that the remote
+    /// target handles their traffic, and brings back the ones of the layers that are no longer
+    /// stalled (`feature.network.incoming.on_stall: "fallback"`).
+    /// Returns messages to be sent to the agent.
+    ///
+    /// Only the active source of a subscription counts, so this should be called again whenever
+    /// `stalled` or the subscriptions change.
+    pub fn apply_stalls(&mut self, stalled: &HashSet<LayerId>) -> Vec<ClientMessage> {
+        self.subscriptions
+            .values_mut()
+            .filter(|subscription| {
+                matches!(
+                    subscription.active_source.request.subscription,
+                    PortSubscription::Steal(..)
+                )
+            })
+            .filter_map(|subscription| {
+                if stalled.contains(&subscription.active_source.layer) {
+                    subscription.suspend()
+                } else {
+                    subscription.resume()
+                }
+            })
+            .collect()
+    }
+
+    /// Notifies this struct about the new [`IncomingMode`], after the config file changed
+    /// (`session.hot_reload`). The steal subscriptions are replaced with the ones of the new mode,
+    /// e.g. to use a new HTTP filter.
+    /// Returns messages to be sent to the agent.
+    ///
+    /// Mirror subscriptions are left alone, switching between `mirror` and `steal` needs a new
+    /// session.
+    pub fn incoming_mode_changed(&mut self, mode: &IncomingMode) -> Vec<ClientMessage> {
+        if !matches!(mode, IncomingMode::Steal(..)) {
+            return vec![];
+        }
+
+        self.subscriptions
+            .iter_mut()
+            .flat_map(|(port, subscription)| {
+                let messages = subscription.replace_steal(mode.subscription(*port));
+                if !messages.is_empty() {
+                    tracing::info!(port, "HTTP filter changed, subscribing again");
+                }
+                messages
+            })
+            .collect()
+    }
+
+    /// Notifies this struct about the ports the target listens on.
+    /// Returns messages to be sent to the agent, for the subscriptions that were waiting for
+    /// these ports.
+    ///
+    /// Subscriptions are not removed when the target stops listening on their ports.
+    pub fn listeners_changed(&mut self, ports: Vec<Port>) -> Vec<ClientMessage> {
+        let Some(remote_listeners) = self.remote_listeners.as_mut() else {
+            return vec![];
+        };
+        *remote_listeners = ports.into_iter().collect();
+
+        self.subscriptions
+            .iter_mut()
+            .filter(|(port, _)| remote_listeners.contains(port))
+            .filter_map(|(port, subscription)| {
+                let message = subscription.activate()?;
+                tracing::info!(port, "Target listens on the port, subscribing");
+                Some(message)
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use mirrord_intproxy_protocol::PortSubscription;
-    use mirrord_protocol::tcp::LayerTcp;
+    use mirrord_intproxy_protocol::{PortSubscription, StealHttpFilter, StealHttpSettings};
+    use mirrord_protocol::tcp::{Filter, HttpFilter, LayerTcp, LayerTcpSteal, StealType};
 
     use super::*;
 
@@ -451,6 +694,37 @@ mod test {
         assert!(manager.get(80).is_none());
     }
 
+    #[test]
+    fn with_agent_reconnect() {
+        let listening_on = "127.0.0.1:1111".parse().unwrap();
+
+        let mut manager = SubscriptionsManager::default();
+
+        manager.layer_subscribed(
+            LayerId(0),
+            0,
+            PortSubscribe {
+                listening_on,
+                subscription: PortSubscription::Mirror(80),
+            },
+        );
+        let responses = manager.agent_responded(Ok(80)).unwrap();
+        assert_eq!(responses.len(), 1, "{responses:?}");
+
+        let messages = manager.agent_reconnected();
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [ClientMessage::Tcp(LayerTcp::PortSubscribe(80))]
+            ),
+            "{messages:?}"
+        );
+
+        let responses = manager.agent_responded(Ok(80)).unwrap();
+        assert!(responses.is_empty(), "{responses:?}");
+        assert_eq!(manager.get(80).unwrap().listening_on, listening_on);
+    }
+
     #[test]
     fn with_double_response() {
         let listening_on = "127.0.0.1:1111".parse().unwrap();
@@ -496,4 +770,183 @@ mod test {
             .unwrap();
         assert!(responses.is_empty(), "{responses:?}");
     }
+
+    #[test]
+    fn with_remote_listeners() {
+        let listening_on = "127.0.0.1:1111".parse().unwrap();
+
+        let mut manager = SubscriptionsManager::default();
+        manager.watch_listeners();
+        let messages = manager.listeners_changed(vec![8080]);
+        assert!(messages.is_empty(), "{messages:?}");
+
+        let response = manager.layer_subscribed(
+            LayerId(0),
+            0,
+            PortSubscribe {
+                listening_on,
+                subscription: PortSubscription::Mirror(80),
+            },
+        );
+        assert!(
+            matches!(
+                response,
+                Some(ProxyMessage::ToLayer(ToLayer {
+                    layer_id: LayerId(0),
+                    message: ProxyToLayerMessage::Incoming(IncomingResponse::PortSubscribe(Ok(()))),
+                    message_id: 0,
+                }))
+            ),
+            "{response:?}"
+        );
+        assert!(manager.agent_reconnected().is_empty());
+
+        let messages = manager.listeners_changed(vec![80, 8080]);
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [ClientMessage::Tcp(LayerTcp::PortSubscribe(80))]
+            ),
+            "{messages:?}"
+        );
+
+        let responses = manager.agent_responded(Ok(80)).unwrap();
+        assert!(responses.is_empty(), "{responses:?}");
+
+        let messages = manager.listeners_changed(vec![80]);
+        assert!(messages.is_empty(), "{messages:?}");
+
+        let response = manager.layer_unsubscribed(
+            LayerId(0),
+            PortUnsubscribe {
+                port: 80,
+                listening_on,
+            },
+        );
+        assert!(
+            matches!(
+                response,
+                Some(ClientMessage::Tcp(LayerTcp::PortUnsubscribe(80)))
+            ),
+            "{response:?}"
+        );
+    }
+
+    #[test]
+    fn deferred_until_not_watching() {
+        let listening_on = "127.0.0.1:1111".parse().unwrap();
+
+        let mut manager = SubscriptionsManager::default();
+        manager.watch_listeners();
+
+        manager.layer_subscribed(
+            LayerId(0),
+            0,
+            PortSubscribe {
+                listening_on,
+                subscription: PortSubscription::Mirror(80),
+            },
+        );
+
+        let messages = manager.stop_watching_listeners();
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [ClientMessage::Tcp(LayerTcp::PortSubscribe(80))]
+            ),
+            "{messages:?}"
+        );
+        assert!(!manager.is_watching_listeners());
+    }
+
+    #[test]
+    fn suspended_while_stalled() {
+        let listening_on = "127.0.0.1:1111".parse().unwrap();
+
+        let mut manager = SubscriptionsManager::default();
+
+        manager.layer_subscribed(
+            LayerId(0),
+            0,
+            PortSubscribe {
+                listening_on,
+                subscription: PortSubscription::Steal(StealType::All(80)),
+            },
+        );
+        manager.agent_responded(Ok(80)).unwrap();
+
+        let stalled = HashSet::from([LayerId(0)]);
+        let messages = manager.apply_stalls(&stalled);
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [ClientMessage::TcpSteal(LayerTcpSteal::PortUnsubscribe(80))]
+            ),
+            "{messages:?}"
+        );
+        assert!(manager.apply_stalls(&stalled).is_empty());
+        assert!(manager.agent_reconnected().is_empty());
+
+        let messages = manager.apply_stalls(&HashSet::new());
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [ClientMessage::TcpSteal(LayerTcpSteal::PortSubscribe(
+                    StealType::All(80)
+                ))]
+            ),
+            "{messages:?}"
+        );
+
+        let responses = manager.agent_responded(Ok(80)).unwrap();
+        assert!(responses.is_empty(), "{responses:?}");
+        assert_eq!(manager.get_layer(80), Some(LayerId(0)));
+    }
+
+    #[test]
+    fn steal_filter_replaced() {
+        let listening_on = "127.0.0.1:1111".parse().unwrap();
+        let filter = HttpFilter::Header(Filter::new("^x-user: alice$".to_string()).unwrap());
+
+        let mut manager = SubscriptionsManager::default();
+
+        manager.layer_subscribed(
+            LayerId(0),
+            0,
+            PortSubscribe {
+                listening_on,
+                subscription: PortSubscription::Steal(StealType::All(80)),
+            },
+        );
+        manager.agent_responded(Ok(80)).unwrap();
+
+        let mode = IncomingMode::Steal(StealHttpSettings {
+            filter: StealHttpFilter::Filter(filter.clone()),
+            ports: HashSet::from([80]),
+        });
+        let messages = manager.incoming_mode_changed(&mode);
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [
+                    ClientMessage::TcpSteal(LayerTcpSteal::PortUnsubscribe(80)),
+                    ClientMessage::TcpSteal(LayerTcpSteal::PortSubscribe(
+                        StealType::FilteredHttpEx(80, new_filter)
+                    )),
+                ] if *new_filter == filter
+            ),
+            "{messages:?}"
+        );
+        assert!(manager.incoming_mode_changed(&mode).is_empty());
+
+        let responses = manager.agent_responded(Ok(80)).unwrap();
+        assert!(responses.is_empty(), "{responses:?}");
+        assert!(manager
+            .incoming_mode_changed(&IncomingMode::Mirror)
+            .is_empty());
+        assert_eq!(
+            manager.get(80).unwrap().subscription,
+            PortSubscription::Steal(StealType::FilteredHttpEx(80, filter))
+        );
+    }
 }
@@ -0,0 +1,397 @@
+//! Handles the UDP part of the `incoming` feature, see [`UdpIncoming`].
+
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use mirrord_intproxy_protocol::{
+    IncomingResponse, LayerId, MessageId, ProxyToLayerMessage, UdpPeerRequest, UdpPeerResponse,
+    UdpPortSubscribe, UdpPortSubscription, UdpPortUnsubscribe,
+};
+use mirrord_protocol::{
+    udp::{DaemonUdp, IncomingDatagram, LayerUdp, LayerUdpSteal, OutgoingDatagram},
+    ClientMessage, Port, ResponseError,
+};
+use tokio::net::UdpSocket;
+
+use super::IncomingProxy;
+use crate::{
+    background_tasks::{BackgroundTask, BackgroundTasks, MessageBus, TaskSender, TaskUpdate},
+    main_tasks::ToLayer,
+};
+
+/// Id of a single [`UdpPeer`] task. Used to manage the tasks with the [`BackgroundTasks`] struct.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct UdpPeerId(u64);
+
+impl fmt::Display for UdpPeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "incoming UDP peer {}", self.0)
+    }
+}
+
+/// Proxies the datagrams of a single remote peer of a subscribed UDP port.
+///
+/// Sends the datagrams to the layer's socket from its own local socket, so the layer can tell the
+/// peers apart, see [`UdpPeerRequest`]. The datagrams the application sends back to this socket
+/// are passed through the [`MessageBus`].
+struct UdpPeer {
+    socket: UdpSocket,
+}
+
+impl UdpPeer {
+    /// The task exits when the peer is silent for this long, UDP has no other way of telling us
+    /// that it's gone.
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+    /// Big enough for any UDP datagram.
+    const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+    /// Binds a new socket similar to the given `listening_on`, and connects it there.
+    async fn new(listening_on: SocketAddr) -> io::Result<Self> {
+        let ip = match listening_on.ip() {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED) => Ipv4Addr::LOCALHOST.into(),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED) => Ipv6Addr::LOCALHOST.into(),
+            ip => ip,
+        };
+
+        let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).await?;
+        socket
+            .connect(SocketAddr::new(ip, listening_on.port()))
+            .await?;
+
+        Ok(Self { socket })
+    }
+}
+
+impl BackgroundTask for UdpPeer {
+    type Error = io::Error;
+    type MessageIn = Vec<u8>;
+    type MessageOut = Vec<u8>;
+
+    async fn run(self, message_bus: &mut MessageBus<Self>) -> Result<(), Self::Error> {
+        let mut buffer = Vec::with_capacity(Self::MAX_DATAGRAM_SIZE);
+
+        loop {
+            tokio::select! {
+                msg = message_bus.recv() => match msg {
+                    Some(bytes) => {
+                        self.socket.send(&bytes).await?;
+                    }
+                    None => {
+                        tracing::trace!("incoming UDP peer -> no more messages from the agent, exiting");
+                        break Ok(());
+                    }
+                },
+
+                received = self.socket.recv_buf(&mut buffer) => {
+                    received?;
+                    message_bus.send(buffer.clone()).await;
+                    buffer.clear();
+                }
+
+                _ = tokio::time::sleep(Self::IDLE_TIMEOUT) => {
+                    tracing::trace!("incoming UDP peer -> idle, exiting");
+                    break Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// A UDP port subscription of a layer.
+struct Subscription {
+    layer_id: LayerId,
+    listening_on: SocketAddr,
+    subscription: UdpPortSubscription,
+    /// The layer's request, waiting for the agent's confirmation.
+    pending: Option<MessageId>,
+}
+
+impl Subscription {
+    fn agent_subscribe(&self) -> ClientMessage {
+        match self.subscription {
+            UdpPortSubscription::Steal(port) => {
+                ClientMessage::UdpSteal(LayerUdpSteal::PortSubscribe(port))
+            }
+            UdpPortSubscription::Mirror(port) => ClientMessage::Udp(LayerUdp::PortSubscribe(port)),
+        }
+    }
+
+    fn agent_unsubscribe(&self) -> ClientMessage {
+        match self.subscription {
+            UdpPortSubscription::Steal(port) => {
+                ClientMessage::UdpSteal(LayerUdpSteal::PortUnsubscribe(port))
+            }
+            UdpPortSubscription::Mirror(port) => {
+                ClientMessage::Udp(LayerUdp::PortUnsubscribe(port))
+            }
+        }
+    }
+}
+
+/// Handle for a [`UdpPeer`].
+struct PeerHandle {
+    tx: TaskSender<UdpPeer>,
+    port: Port,
+    remote_address: SocketAddr,
+    /// Address of the [`UdpPeer`]'s socket, the layer sees the datagrams coming from it.
+    local_address: SocketAddr,
+}
+
+/// Handles the UDP port subscriptions of the layers, as part of the [`IncomingProxy`].
+///
+/// A remote port can be subscribed by one layer at a time. Datagrams of each remote peer are
+/// proxied by a separate [`UdpPeer`].
+#[derive(Default)]
+pub struct UdpIncoming {
+    subscriptions: HashMap<Port, Subscription>,
+    peers: HashMap<UdpPeerId, PeerHandle>,
+    peer_ids: HashMap<(Port, SocketAddr), UdpPeerId>,
+    next_peer_id: u64,
+    background_tasks: BackgroundTasks<UdpPeerId, Vec<u8>, io::Error>,
+}
+
+impl UdpIncoming {
+    const CHANNEL_SIZE: usize = 512;
+
+    /// Registers the layer's subscription and sends it to the agent.
+    ///
+    /// `supported` tells whether the agent understands the UDP subscriptions.
+    pub async fn layer_subscribed(
+        &mut self,
+        layer_id: LayerId,
+        message_id: MessageId,
+        request: UdpPortSubscribe,
+        supported: bool,
+        message_bus: &MessageBus<IncomingProxy>,
+    ) {
+        let port = request.subscription.port();
+        let error = if !supported {
+            Some(ResponseError::NotImplemented)
+        } else if self.subscriptions.contains_key(&port) {
+            Some(ResponseError::PortAlreadyStolen(port))
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            message_bus
+                .send(ToLayer {
+                    message_id,
+                    layer_id,
+                    message: ProxyToLayerMessage::Incoming(IncomingResponse::UdpPortSubscribe(
+                        Err(error),
+                    )),
+                })
+                .await;
+            return;
+        }
+
+        let subscription = Subscription {
+            layer_id,
+            listening_on: request.listening_on,
+            subscription: request.subscription,
+            pending: Some(message_id),
+        };
+        message_bus.send(subscription.agent_subscribe()).await;
+        self.subscriptions.insert(port, subscription);
+    }
+
+    /// Removes the layer's subscription, if it still owns it.
+    pub async fn layer_unsubscribed(
+        &mut self,
+        layer_id: LayerId,
+        request: UdpPortUnsubscribe,
+        message_bus: &MessageBus<IncomingProxy>,
+    ) {
+        let owned = self.subscriptions.get(&request.port).is_some_and(|sub| {
+            sub.layer_id == layer_id && sub.listening_on == request.listening_on
+        });
+
+        if owned {
+            self.remove_subscription(request.port, message_bus).await;
+        }
+    }
+
+    /// Removes all subscriptions of the closed layer.
+    pub async fn layer_closed(
+        &mut self,
+        layer_id: LayerId,
+        message_bus: &MessageBus<IncomingProxy>,
+    ) {
+        let ports = self
+            .subscriptions
+            .iter()
+            .filter(|(_, sub)| sub.layer_id == layer_id)
+            .map(|(port, _)| *port)
+            .collect::<Vec<_>>();
+
+        for port in ports {
+            self.remove_subscription(port, message_bus).await;
+        }
+    }
+
+    async fn remove_subscription(&mut self, port: Port, message_bus: &MessageBus<IncomingProxy>) {
+        let Some(subscription) = self.subscriptions.remove(&port) else {
+            return;
+        };
+
+        message_bus.send(subscription.agent_unsubscribe()).await;
+
+        self.peer_ids.retain(|(peer_port, _), _| *peer_port != port);
+        self.peers.retain(|_, peer| peer.port != port);
+    }
+
+    /// Handles a message from the agent, `steal` tells whether it came as
+    /// [`DaemonMessage::UdpSteal`](mirrord_protocol::DaemonMessage::UdpSteal).
+    pub async fn agent_message(
+        &mut self,
+        message: DaemonUdp,
+        steal: bool,
+        message_bus: &MessageBus<IncomingProxy>,
+    ) -> io::Result<()> {
+        match message {
+            DaemonUdp::SubscribeResult { port, result } => {
+                let Some(subscription) = self.subscriptions.get_mut(&port) else {
+                    return Ok(());
+                };
+                let Some(message_id) = subscription.pending.take() else {
+                    return Ok(());
+                };
+                let layer_id = subscription.layer_id;
+
+                if result.is_err() {
+                    self.subscriptions.remove(&port);
+                }
+
+                message_bus
+                    .send(ToLayer {
+                        message_id,
+                        layer_id,
+                        message: ProxyToLayerMessage::Incoming(IncomingResponse::UdpPortSubscribe(
+                            result,
+                        )),
+                    })
+                    .await;
+            }
+
+            DaemonUdp::Datagram(IncomingDatagram {
+                port,
+                source,
+                bytes,
+            }) => {
+                let Some(subscription) = self.subscriptions.get(&port) else {
+                    return Ok(());
+                };
+                if matches!(subscription.subscription, UdpPortSubscription::Steal(..)) != steal {
+                    return Ok(());
+                }
+
+                let id = match self.peer_ids.get(&(port, source)).copied() {
+                    Some(id) => id,
+                    None => {
+                        let id = UdpPeerId(self.next_peer_id);
+                        self.next_peer_id += 1;
+
+                        let peer = UdpPeer::new(subscription.listening_on).await?;
+                        let local_address = peer.socket.local_addr()?;
+                        let tx = self.background_tasks.register(peer, id, Self::CHANNEL_SIZE);
+
+                        self.peer_ids.insert((port, source), id);
+                        self.peers.insert(
+                            id,
+                            PeerHandle {
+                                tx,
+                                port,
+                                remote_address: source,
+                                local_address,
+                            },
+                        );
+                        id
+                    }
+                };
+
+                if let Some(peer) = self.peers.get(&id) {
+                    peer.tx.send(bytes).await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the next update from the [`UdpPeer`]s.
+    pub async fn next_peer_update(
+        &mut self,
+    ) -> Option<(UdpPeerId, TaskUpdate<Vec<u8>, io::Error>)> {
+        self.background_tasks.next().await
+    }
+
+    /// Handles an update from a [`UdpPeer`]. The application's replies are sent to the agent
+    /// only if the port is stolen.
+    pub async fn peer_update(
+        &mut self,
+        id: UdpPeerId,
+        update: TaskUpdate<Vec<u8>, io::Error>,
+        message_bus: &MessageBus<IncomingProxy>,
+    ) {
+        match update {
+            TaskUpdate::Finished(res) => {
+                tracing::trace!("{id} finished: {res:?}");
+
+                if let Some(peer) = self.peers.remove(&id) {
+                    self.peer_ids.remove(&(peer.port, peer.remote_address));
+                }
+            }
+
+            TaskUpdate::Message(bytes) => {
+                let Some(peer) = self.peers.get(&id) else {
+                    return;
+                };
+                let stolen = self
+                    .subscriptions
+                    .get(&peer.port)
+                    .is_some_and(|sub| matches!(sub.subscription, UdpPortSubscription::Steal(..)));
+
+                if stolen {
+                    message_bus
+                        .send(ClientMessage::UdpSteal(LayerUdpSteal::Reply(
+                            OutgoingDatagram {
+                                port: peer.port,
+                                destination: peer.remote_address,
+                                bytes,
+                            },
+                        )))
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Returns the remote peer whose datagrams are sent from the given local address.
+    pub fn peer(&self, request: UdpPeerRequest) -> UdpPeerResponse {
+        let remote_address = self
+            .peers
+            .values()
+            .find(|peer| peer.local_address == request.local_address)
+            .map(|peer| peer.remote_address);
+
+        UdpPeerResponse { remote_address }
+    }
+
+    /// Moves the subscriptions to the new agent, after the connection with the previous one was
+    /// lost.
+    pub async fn agent_reconnected(&mut self, message_bus: &MessageBus<IncomingProxy>) {
+        self.peers.clear();
+        self.peer_ids.clear();
+        self.background_tasks.abort_all();
+
+        for subscription in self.subscriptions.values() {
+            message_bus.send(subscription.agent_subscribe()).await;
+        }
+    }
+}
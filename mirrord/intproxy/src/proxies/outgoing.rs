@@ -1,16 +1,19 @@
 //! Handles the logic of the `outgoing` feature.
 
-use std::{collections::HashMap, fmt, io};
+use std::{collections::HashMap, fmt, io, time::SystemTime};
 
 use mirrord_intproxy_protocol::{
     LayerId, MessageId, NetProtocol, OutgoingConnectRequest, OutgoingConnectResponse,
-    ProxyToLayerMessage,
+    ProxyToLayerMessage, RemoteFdInfo, RemoteFdResource,
 };
 use mirrord_protocol::{
-    outgoing::{tcp::DaemonTcpOutgoing, udp::DaemonUdpOutgoing, DaemonConnect, DaemonRead},
+    outgoing::{
+        tcp::DaemonTcpOutgoing, udp::DaemonUdpOutgoing, DaemonConnect, DaemonRead, SocketAddress,
+    },
     ConnectionId, RemoteResult, ResponseError,
 };
 use thiserror::Error;
+use tokio::sync::oneshot;
 
 use self::interceptor::Interceptor;
 use crate::{
@@ -61,6 +64,15 @@ impl fmt::Display for InterceptorId {
     }
 }
 
+/// What we know about an intercepted connection, reported with
+/// [`OutgoingProxyMessage::RemoteFds`].
+struct ConnectionStats {
+    remote_address: SocketAddress,
+    bytes_read: u64,
+    bytes_written: u64,
+    last_activity: SystemTime,
+}
+
 /// Handles logic and state of the `outgoing` feature.
 /// Run as a [`BackgroundTask`].
 ///
@@ -79,11 +91,14 @@ impl fmt::Display for InterceptorId {
 #[derive(Default)]
 pub struct OutgoingProxy {
     /// For [`OutgoingConnectRequest`]s related to [`NetProtocol::Datagrams`].
-    datagrams_reqs: RequestQueue,
+    datagrams_reqs: RequestQueue<SocketAddress>,
     /// For [`OutgoingConnectRequest`]s related to [`NetProtocol::Stream`].
-    stream_reqs: RequestQueue,
+    stream_reqs: RequestQueue<SocketAddress>,
     /// [`TaskSender`]s for active [`Interceptor`] tasks.
     txs: HashMap<InterceptorId, TaskSender<Interceptor>>,
+    /// Addresses and usage of the connections with active [`Interceptor`] tasks, for debugging
+    /// stuck sessions.
+    stats: HashMap<InterceptorId, ConnectionStats>,
     /// For managing [`Interceptor`] tasks.
     background_tasks: BackgroundTasks<InterceptorId, Vec<u8>, io::Error>,
 }
@@ -93,7 +108,7 @@ impl OutgoingProxy {
     const CHANNEL_SIZE: usize = 512;
 
     /// Retrieves correct [`RequestQueue`] for the given [`NetProtocol`].
-    fn queue(&mut self, protocol: NetProtocol) -> &mut RequestQueue {
+    fn queue(&mut self, protocol: NetProtocol) -> &mut RequestQueue<SocketAddress> {
         match protocol {
             NetProtocol::Datagrams => &mut self.datagrams_reqs,
             NetProtocol::Stream => &mut self.stream_reqs,
@@ -126,6 +141,10 @@ impl OutgoingProxy {
             return Ok(());
         };
 
+        if let Some(stats) = self.stats.get_mut(&id) {
+            stats.bytes_read += bytes.len() as u64;
+            stats.last_activity = SystemTime::now();
+        }
         interceptor.send(bytes).await;
 
         Ok(())
@@ -141,7 +160,7 @@ impl OutgoingProxy {
         protocol: NetProtocol,
         message_bus: &mut MessageBus<Self>,
     ) -> Result<(), OutgoingProxyError> {
-        let (message_id, layer_id) = self.queue(protocol).get()?;
+        let (message_id, layer_id, _) = self.queue(protocol).get_with()?;
 
         let connect = match connect {
             Ok(connect) => connect,
@@ -164,7 +183,7 @@ impl OutgoingProxy {
             local_address,
         } = connect;
 
-        let prepared_socket = protocol.prepare_socket(remote_address).await?;
+        let prepared_socket = protocol.prepare_socket(remote_address.clone()).await?;
         let layer_address = prepared_socket.local_address()?;
 
         let id = InterceptorId {
@@ -178,6 +197,15 @@ impl OutgoingProxy {
             Self::CHANNEL_SIZE,
         );
         self.txs.insert(id, interceptor);
+        self.stats.insert(
+            id,
+            ConnectionStats {
+                remote_address,
+                bytes_read: 0,
+                bytes_written: 0,
+                last_activity: SystemTime::now(),
+            },
+        );
 
         message_bus
             .send(ToLayer {
@@ -202,11 +230,53 @@ impl OutgoingProxy {
         request: OutgoingConnectRequest,
         message_bus: &mut MessageBus<Self>,
     ) {
-        self.queue(request.protocol).insert(message_id, session_id);
+        self.queue(request.protocol).insert_with(
+            message_id,
+            session_id,
+            request.remote_address.clone(),
+        );
 
         let msg = request.protocol.wrap_agent_connect(request.remote_address);
         message_bus.send(ProxyMessage::ToAgent(msg)).await;
     }
+
+    /// Lists the [`Self::stats`], for
+    /// [`AdminRequest::RemoteFds`](mirrord_intproxy_protocol::AdminRequest::RemoteFds).
+    fn remote_fds_info(&self) -> Vec<RemoteFdInfo> {
+        self.stats
+            .iter()
+            .map(|(id, stats)| RemoteFdInfo {
+                remote_fd: id.connection_id,
+                resource: RemoteFdResource::Outgoing {
+                    protocol: id.protocol,
+                    remote_address: stats.remote_address.clone(),
+                },
+                bytes_read: stats.bytes_read,
+                bytes_written: stats.bytes_written,
+                last_activity: stats.last_activity,
+            })
+            .collect()
+    }
+
+    /// Prepares this proxy for a new agent, after the connection with the previous one was lost.
+    ///
+    /// Connections made through the previous agent can't be continued, so all [`Interceptor`]s
+    /// are aborted. Pending connection requests are sent again to the new agent.
+    #[tracing::instrument(level = "trace", skip_all)]
+    async fn handle_agent_reconnected(&mut self, message_bus: &mut MessageBus<Self>) {
+        self.txs.clear();
+        self.stats.clear();
+        self.background_tasks.abort_all();
+        message_bus.send(ProxyMessage::AgentReconnected).await;
+
+        for protocol in [NetProtocol::Datagrams, NetProtocol::Stream] {
+            let requests = self.queue(protocol).data().cloned().collect::<Vec<_>>();
+            for remote_address in requests {
+                let msg = protocol.wrap_agent_connect(remote_address);
+                message_bus.send(ProxyMessage::ToAgent(msg)).await;
+            }
+        }
+    }
 }
 
 /// Messages consumed by the [`OutgoingProxy`] running as a [`BackgroundTask`].
@@ -214,6 +284,11 @@ pub enum OutgoingProxyMessage {
     AgentStream(DaemonTcpOutgoing),
     AgentDatagrams(DaemonUdpOutgoing),
     LayerConnect(OutgoingConnectRequest, MessageId, LayerId),
+    /// Connection with the previous agent was lost and the session continues with a new one.
+    AgentReconnected,
+    /// Asks for the intercepted connections, see
+    /// [`AdminRequest::RemoteFds`](mirrord_intproxy_protocol::AdminRequest::RemoteFds).
+    RemoteFds(oneshot::Sender<Vec<RemoteFdInfo>>),
 }
 
 impl BackgroundTask for OutgoingProxy {
@@ -233,6 +308,7 @@ impl BackgroundTask for OutgoingProxy {
                         DaemonTcpOutgoing::Close(close) => {
                             let id = InterceptorId { connection_id: close, protocol: NetProtocol::Stream};
                             self.txs.remove(&id);
+                            self.stats.remove(&id);
                         },
                         DaemonTcpOutgoing::Read(read) => self.handle_agent_read(read, NetProtocol::Stream).await?,
                         DaemonTcpOutgoing::Connect(connect) => self.handle_connect_response(connect, NetProtocol::Stream, message_bus).await?,
@@ -241,6 +317,7 @@ impl BackgroundTask for OutgoingProxy {
                         DaemonUdpOutgoing::Close(close) => {
                             let id = InterceptorId { connection_id: close, protocol: NetProtocol::Datagrams};
                             self.txs.remove(&id);
+                            self.stats.remove(&id);
                         }
                         DaemonUdpOutgoing::Read(read) => self.handle_agent_read(read, NetProtocol::Datagrams).await?,
                         DaemonUdpOutgoing::Connect(connect) => self.handle_connect_response(connect, NetProtocol::Datagrams, message_bus).await?,
@@ -251,16 +328,25 @@ impl BackgroundTask for OutgoingProxy {
                         req,
                         message_bus
                     ).await,
+                    Some(OutgoingProxyMessage::AgentReconnected) => self.handle_agent_reconnected(message_bus).await,
+                    Some(OutgoingProxyMessage::RemoteFds(tx)) => {
+                        let _ = tx.send(self.remote_fds_info());
+                    }
                 },
 
                 Some(task_update) = self.background_tasks.next() => match task_update {
                     (id, TaskUpdate::Message(bytes)) => {
+                        if let Some(stats) = self.stats.get_mut(&id) {
+                            stats.bytes_written += bytes.len() as u64;
+                            stats.last_activity = SystemTime::now();
+                        }
                         let msg = id.protocol.wrap_agent_write(id.connection_id, bytes);
                         message_bus.send(ProxyMessage::ToAgent(msg)).await;
                     }
                     (id, TaskUpdate::Finished(res)) => {
                         tracing::trace!("{id} finished: {res:?}");
 
+                        self.stats.remove(&id);
                         if self.txs.remove(&id).is_some() {
                             tracing::trace!("local connection closed, notifying the agent");
                             let msg = id.protocol.wrap_agent_close(id.connection_id);
@@ -1,7 +1,7 @@
 //! [`BackgroundTask`] used by [`OutgoingProxy`](super::OutgoingProxy) to manage a single
 //! intercepted connection.
 
-use std::io;
+use std::{io, time::Duration};
 
 use crate::{
     background_tasks::{BackgroundTask, MessageBus},
@@ -16,6 +16,14 @@ pub struct Interceptor {
 }
 
 impl Interceptor {
+    /// How long we wait for the layer to connect to the [`PreparedSocket`].
+    ///
+    /// The layer connects right after it receives the connect response, so if it doesn't, the
+    /// connect was aborted in the meantime (e.g. the layer request timed out, the socket was closed
+    /// by another thread or the process exited). Exiting lets the proxy close the connection on
+    /// the agent side, instead of leaving it open forever.
+    const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
     /// Creates a new instance. This instance will use the provided [`PreparedSocket`] to accept the
     /// layer's connection and manage it.
     pub fn new(socket: PreparedSocket) -> Self {
@@ -40,10 +48,17 @@ impl BackgroundTask for Interceptor {
     /// 2. A 0-sized read received from the [`MessageBus`] is treated as a shutdown on the agent
     ///    side. Connection with the peer is shut down as well.
     ///
-    /// 3. This implementation exits only when an error is encountered or the [`MessageBus`] is
-    ///    closed.
+    /// 3. This implementation exits only when an error is encountered, the [`MessageBus`] is
+    ///    closed, or the layer does not connect within [`Self::ACCEPT_TIMEOUT`].
     async fn run(self, message_bus: &mut MessageBus<Self>) -> Result<(), Self::Error> {
-        let mut connected_socket = self.socket.accept().await?;
+        let mut connected_socket = tokio::time::timeout(Self::ACCEPT_TIMEOUT, self.socket.accept())
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "layer did not connect to the intercepted connection",
+                )
+            })??;
         let mut reading_closed = false;
 
         loop {
@@ -0,0 +1,642 @@
+//! Local SOCKS5 and HTTP proxy for the processes that can't load the layer (e.g. statically linked
+//! binaries). Their outgoing connections are made through the agent, just like the layer's.
+//!
+//! The clients of this proxy don't have a [`LayerId`], so [`ProxyServer`] talks to the other
+//! main tasks as if it were a layer with [`ProxyServer::LAYER_ID`].
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use mirrord_intproxy_protocol::{
+    LayerId, LayerToProxyMessage, LocalMessage, MessageId, NetProtocol, OutgoingConnectRequest,
+    ProxyToLayerMessage,
+};
+use mirrord_protocol::{
+    dns::{GetAddrInfoRequest, GetAddrInfoResponse},
+    outgoing::SocketAddress,
+    ResponseError,
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    task::JoinSet,
+};
+
+use crate::{
+    background_tasks::{BackgroundTask, MessageBus},
+    main_tasks::FromLayer,
+    ProxyMessage,
+};
+
+#[derive(Error, Debug)]
+pub enum ProxyServerError {
+    #[error("failed to accept proxy client connection: {0}")]
+    Accept(io::Error),
+}
+
+/// Errors that end the session of a single proxy client.
+#[derive(Error, Debug)]
+enum ClientError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("request failed in the cluster: {0}")]
+    Remote(#[from] ResponseError),
+    #[error("host `{0}` was not found in the cluster")]
+    NotFound(String),
+    #[error("internal proxy sent unexpected response: {0:?}")]
+    UnexpectedResponse(ProxyToLayerMessage),
+    #[error("internal proxy is shutting down")]
+    ProxyClosed,
+    #[error("SOCKS client does not support the no authentication method")]
+    UnsupportedAuth,
+    #[error("unsupported SOCKS command {0}")]
+    UnsupportedCommand(u8),
+    #[error("unsupported SOCKS address type {0}")]
+    UnsupportedAddressType(u8),
+    #[error("malformed request: {0}")]
+    BadRequest(&'static str),
+}
+
+impl ClientError {
+    /// SOCKS5 reply code reported to the client when its request fails with this error.
+    fn socks_reply(&self) -> u8 {
+        match self {
+            Self::NotFound(..) => socks::HOST_UNREACHABLE,
+            Self::Remote(..) => socks::CONNECTION_REFUSED,
+            Self::UnsupportedCommand(..) => socks::COMMAND_NOT_SUPPORTED,
+            Self::UnsupportedAddressType(..) => socks::ADDRESS_TYPE_NOT_SUPPORTED,
+            _ => socks::GENERAL_FAILURE,
+        }
+    }
+}
+
+/// Where the client wants to connect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Destination {
+    Ip(SocketAddr),
+    /// Host name, resolved in the cluster.
+    Name(String, u16),
+}
+
+/// Constants from [RFC 1928](https://datatracker.ietf.org/doc/html/rfc1928).
+mod socks {
+    pub const VERSION: u8 = 0x05;
+
+    pub const NO_AUTH: u8 = 0x00;
+    pub const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+
+    pub const CMD_CONNECT: u8 = 0x01;
+
+    pub const ATYP_IPV4: u8 = 0x01;
+    pub const ATYP_DOMAIN: u8 = 0x03;
+    pub const ATYP_IPV6: u8 = 0x04;
+
+    pub const SUCCEEDED: u8 = 0x00;
+    pub const GENERAL_FAILURE: u8 = 0x01;
+    pub const HOST_UNREACHABLE: u8 = 0x04;
+    pub const CONNECTION_REFUSED: u8 = 0x05;
+    pub const COMMAND_NOT_SUPPORTED: u8 = 0x07;
+    pub const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+}
+
+/// Max size of the head of an HTTP request (request line and headers).
+const MAX_HTTP_HEAD_SIZE: usize = 16 * 1024;
+
+/// Hop-by-hop headers dropped from plain HTTP requests before they're forwarded.
+const HOP_BY_HOP_HEADERS: [&str; 4] = [
+    "connection",
+    "proxy-connection",
+    "keep-alive",
+    "proxy-authorization",
+];
+
+type Request = (LayerToProxyMessage, oneshot::Sender<ProxyToLayerMessage>);
+
+/// Handle the client tasks use to make requests to the other main tasks, through the
+/// [`ProxyServer`].
+#[derive(Clone)]
+struct Requests(mpsc::Sender<Request>);
+
+impl Requests {
+    async fn request(
+        &self,
+        message: LayerToProxyMessage,
+    ) -> Result<ProxyToLayerMessage, ClientError> {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .send((message, tx))
+            .await
+            .map_err(|_| ClientError::ProxyClosed)?;
+        rx.await.map_err(|_| ClientError::ProxyClosed)
+    }
+
+    /// Resolves the [`Destination`] with a DNS query made in the cluster.
+    async fn resolve(&self, destination: Destination) -> Result<SocketAddr, ClientError> {
+        let (node, port) = match destination {
+            Destination::Ip(address) => return Ok(address),
+            Destination::Name(node, port) => (node, port),
+        };
+
+        let response = self
+            .request(LayerToProxyMessage::GetAddrInfo(GetAddrInfoRequest {
+                node: node.clone(),
+            }))
+            .await?;
+        let ProxyToLayerMessage::GetAddrInfo(GetAddrInfoResponse(lookup)) = response else {
+            return Err(ClientError::UnexpectedResponse(response));
+        };
+
+        lookup?
+            .0
+            .into_iter()
+            .next()
+            .map(|record| SocketAddr::new(record.ip, port))
+            .ok_or(ClientError::NotFound(node))
+    }
+
+    /// Makes an outgoing connection through the agent, returns the local end of it.
+    async fn connect(&self, address: SocketAddr) -> Result<TcpStream, ClientError> {
+        let response = self
+            .request(LayerToProxyMessage::OutgoingConnect(
+                OutgoingConnectRequest {
+                    remote_address: SocketAddress::Ip(address),
+                    protocol: NetProtocol::Stream,
+                },
+            ))
+            .await?;
+        let ProxyToLayerMessage::OutgoingConnect(connect) = response else {
+            return Err(ClientError::UnexpectedResponse(response));
+        };
+
+        let layer_address = SocketAddr::try_from(connect?.layer_address)?;
+        Ok(TcpStream::connect(layer_address).await?)
+    }
+
+    async fn open(&self, destination: Destination) -> Result<TcpStream, ClientError> {
+        let address = self.resolve(destination).await?;
+        self.connect(address).await
+    }
+}
+
+/// Parses `host:port`, where the host can be a name, an IPv4 address or a bracketed IPv6
+/// address. The port can be omitted, then `default_port` is used.
+fn parse_authority(authority: &str, default_port: u16) -> Option<Destination> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+            let port = match rest {
+                "" => None,
+                rest => Some(rest.strip_prefix(':')?),
+            };
+            (host, port)
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+
+    match host.parse::<IpAddr>() {
+        Ok(ip) => Some(Destination::Ip(SocketAddr::new(ip, port))),
+        Err(..) => Some(Destination::Name(host.to_string(), port)),
+    }
+}
+
+/// Negotiates a SOCKS5 CONNECT with the client, returns the requested [`Destination`].
+///
+/// Failures after the method selection are not reported to the client here, see
+/// [`socks_reply`].
+async fn socks_handshake<S>(stream: &mut S) -> Result<Destination, ClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let [version, methods_len] = read_array(stream).await?;
+    if version != socks::VERSION {
+        return Err(ClientError::BadRequest("invalid SOCKS version"));
+    }
+    let mut methods = vec![0; methods_len.into()];
+    stream.read_exact(&mut methods).await?;
+
+    if !methods.contains(&socks::NO_AUTH) {
+        stream
+            .write_all(&[socks::VERSION, socks::NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Err(ClientError::UnsupportedAuth);
+    }
+    stream.write_all(&[socks::VERSION, socks::NO_AUTH]).await?;
+
+    let [version, command, _reserved, address_type] = read_array(stream).await?;
+    if version != socks::VERSION {
+        return Err(ClientError::BadRequest("invalid SOCKS version"));
+    }
+
+    // The address has to be read in full before the request is rejected.
+    let destination = match address_type {
+        socks::ATYP_IPV4 => {
+            let ip = Ipv4Addr::from(read_array::<_, 4>(stream).await?);
+            let port = u16::from_be_bytes(read_array(stream).await?);
+            Destination::Ip(SocketAddr::new(ip.into(), port))
+        }
+        socks::ATYP_IPV6 => {
+            let ip = Ipv6Addr::from(read_array::<_, 16>(stream).await?);
+            let port = u16::from_be_bytes(read_array(stream).await?);
+            Destination::Ip(SocketAddr::new(ip.into(), port))
+        }
+        socks::ATYP_DOMAIN => {
+            let [len] = read_array(stream).await?;
+            let mut name = vec![0; len.into()];
+            stream.read_exact(&mut name).await?;
+            let name = String::from_utf8(name)
+                .map_err(|_| ClientError::BadRequest("invalid host name"))?;
+            let port = u16::from_be_bytes(read_array(stream).await?);
+            Destination::Name(name, port)
+        }
+        other => return Err(ClientError::UnsupportedAddressType(other)),
+    };
+
+    if command != socks::CMD_CONNECT {
+        return Err(ClientError::UnsupportedCommand(command));
+    }
+
+    Ok(destination)
+}
+
+/// Sends a SOCKS5 reply with the given code. We don't expose the bound address, so it's always
+/// `0.0.0.0:0`.
+async fn socks_reply<S: AsyncWrite + Unpin>(stream: &mut S, reply: u8) -> io::Result<()> {
+    stream
+        .write_all(&[socks::VERSION, reply, 0, socks::ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+async fn read_array<S: AsyncRead + Unpin, const N: usize>(stream: &mut S) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn handle_socks(mut stream: TcpStream, requests: Requests) -> Result<(), ClientError> {
+    let result = match socks_handshake(&mut stream).await {
+        Ok(destination) => requests.open(destination).await,
+        // Can't or shouldn't be reported with a SOCKS reply.
+        Err(error @ (ClientError::Io(..) | ClientError::UnsupportedAuth)) => return Err(error),
+        Err(error) => Err(error),
+    };
+
+    match result {
+        Ok(mut remote) => {
+            socks_reply(&mut stream, socks::SUCCEEDED).await?;
+            tokio::io::copy_bidirectional(&mut stream, &mut remote).await?;
+            Ok(())
+        }
+        Err(error) => {
+            socks_reply(&mut stream, error.socks_reply()).await?;
+            Err(error)
+        }
+    }
+}
+
+/// Head of an HTTP request, with the raw bytes the client sent after it.
+#[derive(Debug)]
+struct HttpHead {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<String>,
+    rest: Vec<u8>,
+}
+
+impl HttpHead {
+    async fn read<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Self, ClientError> {
+        let mut buf = Vec::new();
+        let head_len = loop {
+            if let Some(position) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                break position;
+            }
+            if buf.len() > MAX_HTTP_HEAD_SIZE {
+                return Err(ClientError::BadRequest("request head too large"));
+            }
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Err(ClientError::BadRequest("unexpected end of request head"));
+            }
+        };
+
+        let (head, rest) = buf.split_at(head_len);
+        let rest = rest.get(4..).unwrap_or_default().to_vec();
+        let head =
+            std::str::from_utf8(head).map_err(|_| ClientError::BadRequest("invalid head"))?;
+
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(target), Some(version), None) = (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) else {
+            return Err(ClientError::BadRequest("invalid request line"));
+        };
+
+        Ok(Self {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers: lines.map(ToString::to_string).collect(),
+            rest,
+        })
+    }
+
+    /// Returns the [`Destination`] of a plain HTTP request, and its head, rewritten for the origin
+    /// server.
+    fn into_origin_request(self) -> Result<(Destination, Vec<u8>), ClientError> {
+        let target = self
+            .target
+            .strip_prefix("http://")
+            .ok_or(ClientError::BadRequest("expected an absolute http:// URI"))?;
+        let (authority, path) = match target.find('/') {
+            Some(position) => target.split_at(position),
+            None => (target, "/"),
+        };
+        let destination = parse_authority(authority, 80)
+            .ok_or(ClientError::BadRequest("invalid host in request URI"))?;
+
+        let mut head = format!("{} {path} {}\r\n", self.method, self.version);
+        for header in &self.headers {
+            let name = header.split(':').next().unwrap_or_default().trim();
+            if !HOP_BY_HOP_HEADERS
+                .iter()
+                .any(|hop_by_hop| name.eq_ignore_ascii_case(hop_by_hop))
+            {
+                head.push_str(header);
+                head.push_str("\r\n");
+            }
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        let mut request = head.into_bytes();
+        request.extend_from_slice(&self.rest);
+
+        Ok((destination, request))
+    }
+}
+
+async fn handle_http(mut stream: TcpStream, requests: Requests) -> Result<(), ClientError> {
+    let head = match HttpHead::read(&mut stream).await {
+        Ok(head) => head,
+        Err(error) => {
+            http_error(&mut stream, "400 Bad Request").await?;
+            return Err(error);
+        }
+    };
+
+    let (destination, initial_data, tunnel) = if head.method.eq_ignore_ascii_case("CONNECT") {
+        match parse_authority(&head.target, 443) {
+            Some(destination) => (destination, head.rest, true),
+            None => {
+                http_error(&mut stream, "400 Bad Request").await?;
+                return Err(ClientError::BadRequest("invalid CONNECT authority"));
+            }
+        }
+    } else {
+        match head.into_origin_request() {
+            Ok((destination, request)) => (destination, request, false),
+            Err(error) => {
+                http_error(&mut stream, "400 Bad Request").await?;
+                return Err(error);
+            }
+        }
+    };
+
+    let mut remote = match requests.open(destination).await {
+        Ok(remote) => remote,
+        Err(error) => {
+            http_error(&mut stream, "502 Bad Gateway").await?;
+            return Err(error);
+        }
+    };
+
+    if tunnel {
+        stream
+            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+            .await?;
+    }
+    remote.write_all(&initial_data).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut remote).await?;
+
+    Ok(())
+}
+
+async fn http_error(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    stream
+        .write_all(
+            format!("HTTP/1.1 {status}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n")
+                .as_bytes(),
+        )
+        .await
+}
+
+#[tracing::instrument(level = "trace", skip(stream, requests))]
+async fn handle_client(stream: TcpStream, peer: SocketAddr, requests: Requests) {
+    let mut first_byte = [0];
+    let result = match stream.peek(&mut first_byte).await {
+        Ok(0) => Ok(()),
+        Ok(..) if first_byte == [socks::VERSION] => handle_socks(stream, requests).await,
+        Ok(..) => handle_http(stream, requests).await,
+        Err(error) => Err(error.into()),
+    };
+
+    if let Err(error) = result {
+        tracing::debug!(%peer, %error, "proxy client connection failed");
+    }
+}
+
+/// Accepts SOCKS5 and HTTP proxy connections and makes their outgoing connections through the
+/// agent. Run as a [`BackgroundTask`].
+///
+/// Host names are resolved in the cluster. Only TCP connections are supported (SOCKS5 CONNECT,
+/// HTTP CONNECT and plain HTTP requests with an absolute URI), and SOCKS5 clients must not
+/// require authentication.
+pub struct ProxyServer {
+    listener: TcpListener,
+}
+
+impl ProxyServer {
+    /// [`LayerId`] this proxy uses in its requests to the other main tasks.
+    pub const LAYER_ID: LayerId = LayerId(u64::MAX);
+
+    pub fn new(listener: TcpListener) -> Self {
+        Self { listener }
+    }
+}
+
+impl BackgroundTask for ProxyServer {
+    type Error = ProxyServerError;
+    type MessageIn = LocalMessage<ProxyToLayerMessage>;
+    type MessageOut = ProxyMessage;
+
+    async fn run(self, message_bus: &mut MessageBus<Self>) -> Result<(), Self::Error> {
+        let (requests_tx, mut requests_rx) = mpsc::channel::<Request>(32);
+        let mut responses: HashMap<MessageId, oneshot::Sender<ProxyToLayerMessage>> =
+            Default::default();
+        let mut next_message_id: MessageId = 0;
+        let mut clients = JoinSet::new();
+
+        loop {
+            tokio::select! {
+                msg = message_bus.recv() => match msg {
+                    None => {
+                        tracing::trace!("message bus closed, exiting");
+                        break Ok(());
+                    }
+                    Some(LocalMessage { message_id, inner }) => {
+                        if let Some(tx) = responses.remove(&message_id) {
+                            let _ = tx.send(inner);
+                        }
+                    }
+                },
+
+                res = self.listener.accept() => {
+                    let (stream, peer) = res.map_err(ProxyServerError::Accept)?;
+                    clients.spawn(handle_client(stream, peer, Requests(requests_tx.clone())));
+                }
+
+                Some((message, tx)) = requests_rx.recv() => {
+                    let message_id = next_message_id;
+                    next_message_id += 1;
+                    responses.insert(message_id, tx);
+
+                    message_bus
+                        .send(FromLayer {
+                            message_id,
+                            layer_id: Self::LAYER_ID,
+                            message,
+                        })
+                        .await;
+                }
+
+                Some(res) = clients.join_next() => {
+                    if let Err(error) = res {
+                        tracing::error!(%error, "proxy client task panicked");
+                    }
+                    // Drop the responses nobody waits for anymore.
+                    responses.retain(|_, tx| !tx.is_closed());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_authorities() {
+        let ip = |address: &str| Some(Destination::Ip(address.parse().unwrap()));
+        let name = |name: &str, port| Some(Destination::Name(name.to_string(), port));
+
+        assert_eq!(parse_authority("1.2.3.4:8080", 80), ip("1.2.3.4:8080"));
+        assert_eq!(parse_authority("1.2.3.4", 80), ip("1.2.3.4:80"));
+        assert_eq!(parse_authority("[::1]:443", 80), ip("[::1]:443"));
+        assert_eq!(parse_authority("[::1]", 80), ip("[::1]:80"));
+        assert_eq!(
+            parse_authority("example.svc.cluster.local:443", 80),
+            name("example.svc.cluster.local", 443)
+        );
+        assert_eq!(parse_authority("example", 80), name("example", 80));
+        assert_eq!(parse_authority("example:port", 80), None);
+        assert_eq!(parse_authority(":80", 80), None);
+        assert_eq!(parse_authority("[::1]x", 80), None);
+    }
+
+    #[tokio::test]
+    async fn socks_connect_domain() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        client.write_all(&[5, 2, 2, 0]).await.unwrap();
+        client
+            .write_all(&[
+                5, 1, 0, 3, 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x1F, 0x90,
+            ])
+            .await
+            .unwrap();
+
+        let destination = socks_handshake(&mut server).await.unwrap();
+        assert_eq!(destination, Destination::Name("example".to_string(), 8080));
+
+        let method_selection = read_array::<_, 2>(&mut client).await.unwrap();
+        assert_eq!(method_selection, [5, 0]);
+    }
+
+    #[tokio::test]
+    async fn socks_rejects_auth_and_bind() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&[5, 1, 2]).await.unwrap();
+        assert!(matches!(
+            socks_handshake(&mut server).await,
+            Err(ClientError::UnsupportedAuth)
+        ));
+        assert_eq!(read_array::<_, 2>(&mut client).await.unwrap(), [5, 0xFF]);
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        client
+            .write_all(&[5, 2, 0, 1, 127, 0, 0, 1, 0, 80])
+            .await
+            .unwrap();
+        let error = socks_handshake(&mut server).await.unwrap_err();
+        assert_eq!(error.socks_reply(), socks::COMMAND_NOT_SUPPORTED);
+    }
+
+    #[tokio::test]
+    async fn http_origin_request() {
+        let request = b"GET http://example:8080/path?query HTTP/1.1\r\n\
+            Host: example:8080\r\n\
+            Proxy-Connection: keep-alive\r\n\
+            Accept: */*\r\n\
+            \r\n\
+            body";
+
+        let head = HttpHead::read(&mut request.as_slice()).await.unwrap();
+        let (destination, request) = head.into_origin_request().unwrap();
+
+        assert_eq!(destination, Destination::Name("example".to_string(), 8080));
+        assert_eq!(
+            String::from_utf8(request).unwrap(),
+            "GET /path?query HTTP/1.1\r\n\
+            Host: example:8080\r\n\
+            Accept: */*\r\n\
+            Connection: close\r\n\
+            \r\n\
+            body"
+        );
+    }
+
+    #[tokio::test]
+    async fn http_connect_head() {
+        let request = b"CONNECT 10.0.0.1:443 HTTP/1.1\r\nHost: 10.0.0.1:443\r\n\r\n";
+
+        let head = HttpHead::read(&mut request.as_slice()).await.unwrap();
+
+        assert_eq!(head.method, "CONNECT");
+        assert_eq!(
+            parse_authority(&head.target, 443),
+            Some(Destination::Ip("10.0.0.1:443".parse().unwrap()))
+        );
+        assert!(head.rest.is_empty());
+    }
+}
@@ -0,0 +1,379 @@
+//! Keeps a local directory in sync with a directory of the target (`feature.fs.shared_scratch`).
+//!
+//! The agent watches its side and sends us the changes, while [`ScratchProxy`] polls the local
+//! directory and sends the changes it finds. We remember what every local entry looked like after
+//! we last synchronized it, so that the changes made by the agent are not sent back.
+
+use std::{
+    collections::HashMap,
+    io,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use mirrord_protocol::{
+    capabilities::{Capability, ProtocolCapabilities},
+    scratch::{DaemonScratch, LayerScratch, ScratchEntry, ScratchEntryKind, MAX_SCRATCH_FILE_SIZE},
+    ClientMessage,
+};
+use thiserror::Error;
+use tokio::{fs, time};
+
+use crate::{
+    background_tasks::{BackgroundTask, MessageBus},
+    ProxyMessage,
+};
+
+#[derive(Error, Debug)]
+pub enum ScratchProxyError {
+    #[error("failed to read the local shared scratch directory: {0}")]
+    Scan(io::Error),
+}
+
+/// Messages consumed by the [`ScratchProxy`].
+#[derive(Debug)]
+pub enum ScratchProxyMessage {
+    /// Capabilities negotiated with the agent, the synchronization starts if the agent supports
+    /// it.
+    Capabilities(ProtocolCapabilities),
+    Agent(DaemonScratch),
+    /// The agent was replaced, the synchronization starts again with the
+    /// [`ScratchProxyMessage::Capabilities`] of the new one.
+    AgentReconnected,
+}
+
+/// What a local entry looked like, compared between scans to find the changes.
+///
+/// Directories change only with their mode, adding or removing their entries doesn't count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    dir: bool,
+    len: u64,
+    /// Modification time, in nanoseconds.
+    mtime: i128,
+    mode: u32,
+}
+
+impl Stamp {
+    fn of(meta: &std::fs::Metadata) -> Self {
+        let mode = meta.permissions().mode() & 0o7777;
+
+        if meta.is_dir() {
+            Self {
+                dir: true,
+                len: 0,
+                mtime: 0,
+                mode,
+            }
+        } else {
+            Self {
+                dir: false,
+                len: meta.len(),
+                mtime: i128::from(meta.mtime()) * 1_000_000_000 + i128::from(meta.mtime_nsec()),
+                mode,
+            }
+        }
+    }
+}
+
+/// Synchronizes the local directory with the remote one, see the module docs.
+pub struct ScratchProxy {
+    /// Absolute path of the directory in the target.
+    remote: PathBuf,
+    /// The directory on the user's machine.
+    local: PathBuf,
+    /// Local entries as they were after we last synchronized them, relative to [`Self::local`].
+    seen: HashMap<PathBuf, Stamp>,
+    /// Whether the agent synchronizes its side.
+    started: bool,
+}
+
+impl ScratchProxy {
+    /// How often the local directory is scanned for changes.
+    const SCAN_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn new(remote: PathBuf, local: PathBuf) -> Self {
+        Self {
+            remote,
+            local,
+            seen: Default::default(),
+            started: false,
+        }
+    }
+
+    /// Sends the local changes made since the last scan to the agent.
+    async fn scan(&mut self, message_bus: &MessageBus<Self>) -> Result<(), ScratchProxyError> {
+        let local = self.local.clone();
+        let current = tokio::task::spawn_blocking(move || scan_dir(&local))
+            .await
+            .map_err(|error| ScratchProxyError::Scan(io::Error::other(error)))?
+            .map_err(ScratchProxyError::Scan)?;
+
+        let (removed, mut changed) = changes(&self.seen, &current);
+        let mut failed = Vec::new();
+
+        for path in removed {
+            message_bus
+                .send(ClientMessage::Scratch(LayerScratch::Removed(path)))
+                .await;
+        }
+
+        changed.sort();
+        for path in changed {
+            let Some(stamp) = current.get(&path) else {
+                continue;
+            };
+
+            let kind = if stamp.dir {
+                ScratchEntryKind::Dir
+            } else {
+                match fs::read(self.local.join(&path)).await {
+                    Ok(contents) => ScratchEntryKind::File(contents),
+                    Err(error) => {
+                        // Probably removed in the meantime, we'll find out with the next scan.
+                        tracing::debug!(%error, ?path, "failed to read a shared scratch file");
+                        failed.push(path);
+                        continue;
+                    }
+                }
+            };
+
+            let entry = ScratchEntry {
+                path,
+                mode: stamp.mode,
+                kind,
+            };
+            message_bus
+                .send(ClientMessage::Scratch(LayerScratch::Changed(entry)))
+                .await;
+        }
+
+        self.seen = current;
+        for path in failed {
+            self.seen.remove(&path);
+        }
+
+        Ok(())
+    }
+
+    /// Applies a change made in the target to the local directory.
+    async fn apply(&mut self, message: DaemonScratch) {
+        let result = match message {
+            DaemonScratch::Changed(entry) => self.write_entry(entry).await,
+            DaemonScratch::Removed(path) => self.remove_entry(&path).await,
+            DaemonScratch::Stopped(error) => {
+                tracing::warn!(%error, "agent stopped synchronizing the shared scratch directory");
+                self.started = false;
+                Ok(())
+            }
+        };
+
+        if let Err(error) = result {
+            tracing::warn!(%error, "failed to apply a change of the shared scratch directory");
+        }
+    }
+
+    async fn write_entry(&mut self, entry: ScratchEntry) -> io::Result<()> {
+        let path = local_path(&self.local, &entry.path)?;
+        let existing = fs::symlink_metadata(&path).await.ok();
+
+        match entry.kind {
+            ScratchEntryKind::Dir => {
+                if existing.is_some_and(|meta| !meta.is_dir()) {
+                    fs::remove_file(&path).await?;
+                }
+                fs::create_dir_all(&path).await?;
+            }
+            ScratchEntryKind::File(contents) => {
+                if existing.is_some_and(|meta| meta.is_dir()) {
+                    self.seen.retain(|seen, _| !seen.starts_with(&entry.path));
+                    fs::remove_dir_all(&path).await?;
+                }
+                fs::write(&path, contents).await?;
+            }
+        }
+        fs::set_permissions(&path, std::fs::Permissions::from_mode(entry.mode)).await?;
+
+        let meta = fs::symlink_metadata(&path).await?;
+        self.seen.insert(entry.path, Stamp::of(&meta));
+
+        Ok(())
+    }
+
+    async fn remove_entry(&mut self, relative: &Path) -> io::Result<()> {
+        let path = local_path(&self.local, relative)?;
+        self.seen.retain(|seen, _| !seen.starts_with(relative));
+
+        let result = match fs::symlink_metadata(&path).await {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(&path).await,
+            Ok(..) => fs::remove_file(&path).await,
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl BackgroundTask for ScratchProxy {
+    type Error = ScratchProxyError;
+    type MessageIn = ScratchProxyMessage;
+    type MessageOut = ProxyMessage;
+
+    async fn run(mut self, message_bus: &mut MessageBus<Self>) -> Result<(), Self::Error> {
+        let mut scan_interval = time::interval(Self::SCAN_INTERVAL);
+        scan_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                msg = message_bus.recv() => match msg {
+                    None => {
+                        tracing::trace!("message bus closed, exiting");
+                        break Ok(());
+                    }
+                    Some(ScratchProxyMessage::Capabilities(capabilities)) => {
+                        if capabilities.supports(Capability::Scratch) {
+                            message_bus
+                                .send(ClientMessage::Scratch(LayerScratch::Start(
+                                    self.remote.clone(),
+                                )))
+                                .await;
+                            // Everything that is already here goes to the agent with the next scan.
+                            self.seen.clear();
+                            self.started = true;
+                        } else {
+                            tracing::warn!(
+                                ?capabilities,
+                                "agent does not support `feature.fs.shared_scratch`, the \
+                                 directory is not synchronized"
+                            );
+                        }
+                    }
+                    Some(ScratchProxyMessage::Agent(message)) => self.apply(message).await,
+                    Some(ScratchProxyMessage::AgentReconnected) => {
+                        self.started = false;
+                        message_bus.send(ProxyMessage::AgentReconnected).await;
+                    }
+                },
+
+                _ = scan_interval.tick(), if self.started => self.scan(message_bus).await?,
+            }
+        }
+    }
+}
+
+/// Path of the entry in the local directory. Fails when the agent sends a path that is not
+/// inside the directory.
+fn local_path(local: &Path, relative: &Path) -> io::Result<PathBuf> {
+    let valid = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(..)));
+    if !valid || relative.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid path in the shared scratch directory: {relative:?}"),
+        ));
+    }
+
+    Ok(local.join(relative))
+}
+
+/// Returns the synchronized entries of the directory, relative to it. Symlinks, special files and
+/// files bigger than [`MAX_SCRATCH_FILE_SIZE`] are skipped.
+fn scan_dir(root: &Path) -> io::Result<HashMap<PathBuf, Stamp>> {
+    let mut entries = HashMap::new();
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(dir) = dirs.pop() {
+        let read_dir = match std::fs::read_dir(root.join(&dir)) {
+            Ok(read_dir) => read_dir,
+            // The directory itself must be there, subdirectories may be removed while we scan.
+            Err(error) if dir.as_os_str().is_empty() => return Err(error),
+            Err(..) => continue,
+        };
+
+        for dir_entry in read_dir.flatten() {
+            let Ok(meta) = dir_entry.metadata() else {
+                continue;
+            };
+            let relative = dir.join(dir_entry.file_name());
+
+            if meta.is_dir() {
+                dirs.push(relative.clone());
+            } else if !meta.is_file() || meta.len() > MAX_SCRATCH_FILE_SIZE {
+                continue;
+            }
+
+            entries.insert(relative, Stamp::of(&meta));
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Compares two scans of the directory, returning the entries that were removed and the ones
+/// that were created or changed.
+///
+/// Entries removed along with their parent directory are not returned.
+fn changes(
+    old: &HashMap<PathBuf, Stamp>,
+    new: &HashMap<PathBuf, Stamp>,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let removed = old
+        .keys()
+        .filter(|path| !new.contains_key(*path))
+        .filter(|path| {
+            !path
+                .ancestors()
+                .skip(1)
+                .any(|parent| old.contains_key(parent) && !new.contains_key(parent))
+        })
+        .cloned()
+        .collect();
+
+    let changed = new
+        .iter()
+        .filter(|(path, stamp)| old.get(*path) != Some(*stamp))
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    (removed, changed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stamp(dir: bool, len: u64) -> Stamp {
+        Stamp {
+            dir,
+            len,
+            mtime: 0,
+            mode: 0o644,
+        }
+    }
+
+    #[test]
+    fn changes_skip_removed_children() {
+        let old = HashMap::from([
+            (PathBuf::from("dir"), stamp(true, 0)),
+            (PathBuf::from("dir/file"), stamp(false, 1)),
+            (PathBuf::from("kept"), stamp(false, 1)),
+            (PathBuf::from("gone"), stamp(false, 1)),
+        ]);
+        let new = HashMap::from([
+            (PathBuf::from("kept"), stamp(false, 2)),
+            (PathBuf::from("added"), stamp(false, 1)),
+        ]);
+
+        let (mut removed, mut changed) = changes(&old, &new);
+        removed.sort();
+        changed.sort();
+
+        assert_eq!(removed, vec![PathBuf::from("dir"), PathBuf::from("gone")]);
+        assert_eq!(changed, vec![PathBuf::from("added"), PathBuf::from("kept")]);
+    }
+}
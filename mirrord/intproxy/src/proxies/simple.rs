@@ -1,23 +1,39 @@
 //! The most basic proxying logic. Handles cases when the only job to do in the internal proxy is to
 //! pass requests and responses between the layer and the agent.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf, time::SystemTime};
 
-use mirrord_intproxy_protocol::{LayerId, MessageId, ProxyToLayerMessage};
+use mirrord_intproxy_protocol::{
+    LayerId, MessageId, ProxyToLayerMessage, RemoteFdInfo, RemoteFdResource,
+};
 use mirrord_protocol::{
+    capabilities::{Capability, ProtocolCapabilities},
     dns::{GetAddrInfoRequest, GetAddrInfoResponse},
-    file::{CloseDirRequest, CloseFileRequest, OpenDirResponse, OpenFileResponse},
-    ClientMessage, FileRequest, FileResponse, GetEnvVarsRequest, RemoteResult,
+    file::{
+        AccessFileRequest, AllowFifosRequest, CloseDirRequest, CloseFileRequest, FdOpenDirRequest,
+        FlockRequest, GetDEnts64Request, GetLockRequest, OpenDirResponse, OpenFileRequest,
+        OpenFileResponse, OpenOptionsInternal, OpenRelativeFileRequest, ReadDirBatchRequest,
+        ReadDirRequest, ReadFileRequest, ReadFileResponse, ReadLimitedFileRequest,
+        ReadLinkFileRequest, ReadStreamChunk, SeekFileRequest, SetLockRequest, WatchAddResponse,
+        WatchEvent, WatchEventsResponse, WatchRemoveRequest, WriteFileRequest, WriteFileResponse,
+        WriteLimitedFileRequest, XstatFsRequest, XstatRequest,
+    },
+    ClientMessage, FileRequest, FileResponse, GetEnvVarsRequest, RemoteResult, ResponseError,
 };
+use tokio::sync::oneshot;
 
+use self::read_streams::{ReadStreamAction, ReadStreams};
 use crate::{
     background_tasks::{BackgroundTask, MessageBus},
     main_tasks::{LayerClosed, LayerForked, ToLayer},
     remote_resources::RemoteResources,
     request_queue::{RequestQueue, RequestQueueEmpty},
+    size_limits::SizeLimits,
     ProxyMessage,
 };
 
+mod read_streams;
+
 pub enum SimpleProxyMessage {
     FileReq(MessageId, LayerId, FileRequest),
     FileRes(FileResponse),
@@ -27,6 +43,18 @@ pub enum SimpleProxyMessage {
     LayerClosed(LayerClosed),
     GetEnvReq(MessageId, LayerId, GetEnvVarsRequest),
     GetEnvRes(RemoteResult<HashMap<String, String>>),
+    /// Capabilities negotiated with the agent.
+    Capabilities(ProtocolCapabilities),
+    /// Connection with the previous agent was lost and the session continues with a new one.
+    AgentReconnected,
+    /// Asks for the open remote files and directories, see
+    /// [`AdminRequest::RemoteFds`](mirrord_intproxy_protocol::AdminRequest::RemoteFds).
+    RemoteFds(oneshot::Sender<Vec<RemoteFdInfo>>),
+    /// Let the layers open the named pipes (FIFOs) of the target (`feature.fs.mode: "write"`), see
+    /// [`FileRequest::AllowFifos`].
+    AllowFifos,
+    /// Part of a remote file streamed by the agent, see [`ReadStreams`].
+    ReadStream(ReadStreamChunk),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -35,18 +63,431 @@ enum RemoteFd {
     Dir(u64),
 }
 
+impl RemoteFd {
+    /// Returns the remote descriptor used by the given [`FileRequest`], if any.
+    fn from_request(request: &FileRequest) -> Option<Self> {
+        match request {
+            FileRequest::Read(ReadFileRequest { remote_fd, .. })
+            | FileRequest::ReadLimited(ReadLimitedFileRequest { remote_fd, .. })
+            | FileRequest::WriteLimited(WriteLimitedFileRequest { remote_fd, .. })
+            | FileRequest::FdOpenDir(FdOpenDirRequest { remote_fd })
+            | FileRequest::GetDEnts64(GetDEnts64Request { remote_fd, .. }) => {
+                Some(Self::File(*remote_fd))
+            }
+            FileRequest::Seek(SeekFileRequest { fd, .. })
+            | FileRequest::Write(WriteFileRequest { fd, .. })
+            | FileRequest::XstatFs(XstatFsRequest { fd })
+            | FileRequest::Flock(FlockRequest { fd, .. })
+            | FileRequest::SetLock(SetLockRequest { fd, .. })
+            | FileRequest::GetLock(GetLockRequest { fd, .. })
+            | FileRequest::Xstat(XstatRequest { fd: Some(fd), .. })
+            | FileRequest::OpenRelative(OpenRelativeFileRequest {
+                relative_fd: fd, ..
+            }) => Some(Self::File(*fd)),
+            FileRequest::ReadDir(ReadDirRequest { remote_fd })
+            | FileRequest::ReadDirBatch(ReadDirBatchRequest { remote_fd, .. }) => {
+                Some(Self::Dir(*remote_fd))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What we know about a [`RemoteFd`], reported with [`SimpleProxyMessage::RemoteFds`].
+struct RemoteFdStats {
+    resource: RemoteFdResource,
+    bytes_read: u64,
+    bytes_written: u64,
+    last_activity: SystemTime,
+}
+
+impl RemoteFdStats {
+    fn new(resource: RemoteFdResource) -> Self {
+        Self {
+            resource,
+            bytes_read: 0,
+            bytes_written: 0,
+            last_activity: SystemTime::now(),
+        }
+    }
+}
+
+/// Identifies a [`FileRequest`] that is safe to deduplicate: it does not change any state in the
+/// agent, so one response can be shared between all identical requests in flight.
+///
+/// Opens are never deduplicated, as every layer request must get its own remote fd.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum DedupKey {
+    Xstat {
+        path: Option<PathBuf>,
+        fd: Option<u64>,
+        follow_symlink: bool,
+    },
+    Access {
+        pathname: PathBuf,
+        mode: u8,
+    },
+    ReadLink {
+        path: PathBuf,
+    },
+}
+
+impl DedupKey {
+    fn from_request(request: &FileRequest) -> Option<Self> {
+        match request {
+            FileRequest::Xstat(XstatRequest {
+                path,
+                fd,
+                follow_symlink,
+            }) => Some(Self::Xstat {
+                path: path.clone(),
+                fd: *fd,
+                follow_symlink: *follow_symlink,
+            }),
+            FileRequest::Access(AccessFileRequest { pathname, mode }) => Some(Self::Access {
+                pathname: pathname.clone(),
+                mode: *mode,
+            }),
+            FileRequest::ReadLink(ReadLinkFileRequest { path }) => {
+                Some(Self::ReadLink { path: path.clone() })
+            }
+            _ => None,
+        }
+    }
+}
+
 /// For passing messages between the layer and the agent without custom internal logic.
 /// Run as a [`BackgroundTask`].
 #[derive(Default)]
 pub struct SimpleProxy {
     /// Remote descriptors for open files and directories. Allows tracking across layer forks.
     remote_fds: RemoteResources<RemoteFd>,
+    /// Paths and usage of the [`Self::remote_fds`], for debugging stuck sessions.
+    remote_fd_stats: HashMap<RemoteFd, RemoteFdStats>,
     /// For [`FileRequest`]s.
-    file_reqs: RequestQueue,
+    file_reqs: RequestQueue<FileRequest>,
+    /// Layer requests that were not sent to the agent, because an identical request was already
+    /// in flight. They get a copy of its response.
+    file_req_waiters: HashMap<DedupKey, Vec<(MessageId, LayerId)>>,
     /// For [`GetAddrInfoRequest`]s.
-    addr_info_reqs: RequestQueue,
+    addr_info_reqs: RequestQueue<GetAddrInfoRequest>,
     /// For [`GetEnvVarsRequest`]s.
-    get_env_reqs: RequestQueue,
+    get_env_reqs: RequestQueue<GetEnvVarsRequest>,
+    /// Capabilities negotiated with the agent, used to reject requests the agent does not
+    /// understand.
+    capabilities: Option<ProtocolCapabilities>,
+    /// Applied to [`FileRequest`]s before they are sent to the agent.
+    size_limits: SizeLimits,
+    /// Layers that made the remote file watches, by `watch_id`.
+    ///
+    /// The agent reports the events of all watches together, we give each layer only its own.
+    watch_owners: HashMap<u64, LayerId>,
+    /// Watch events that the agent reported with the [`FileRequest::WatchEvents`] of another
+    /// layer, waiting for the next request of their owner.
+    watch_events: HashMap<LayerId, Vec<WatchEvent>>,
+    /// Whether we send [`FileRequest::AllowFifos`] to every agent that supports it, see
+    /// [`SimpleProxyMessage::AllowFifos`].
+    allow_fifos: bool,
+    /// Serve the sequential reads of remote files without waiting for the agent.
+    read_streams: ReadStreams,
+}
+
+impl SimpleProxy {
+    pub fn new(size_limits: SizeLimits) -> Self {
+        Self {
+            size_limits,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the error response for a [`FileRequest`] that the agent does not support, so that
+    /// we don't send it to the agent at all.
+    fn unsupported_file_response(&self, request: &FileRequest) -> Option<FileResponse> {
+        let supports = |capability: Capability| {
+            self.capabilities
+                .as_ref()
+                .is_some_and(|capabilities| capabilities.supports(capability))
+        };
+
+        match request {
+            FileRequest::OpenImage(..) if !supports(Capability::OpenImageFile) => {
+                Some(FileResponse::Open(Err(ResponseError::NotImplemented)))
+            }
+            FileRequest::WatchAdd(..) if !supports(Capability::Watch) => {
+                Some(FileResponse::WatchAdd(Err(ResponseError::NotImplemented)))
+            }
+            FileRequest::WatchEvents(..) if !supports(Capability::Watch) => Some(
+                FileResponse::WatchEvents(Err(ResponseError::NotImplemented)),
+            ),
+            FileRequest::Flock(..) if !supports(Capability::Lock) => {
+                Some(FileResponse::Flock(Err(ResponseError::NotImplemented)))
+            }
+            FileRequest::SetLock(..) if !supports(Capability::Lock) => {
+                Some(FileResponse::SetLock(Err(ResponseError::NotImplemented)))
+            }
+            FileRequest::GetLock(..) if !supports(Capability::Lock) => {
+                Some(FileResponse::GetLock(Err(ResponseError::NotImplemented)))
+            }
+            FileRequest::ReadDirBatch(..) if !supports(Capability::ReadDirBatch) => Some(
+                FileResponse::ReadDirBatch(Err(ResponseError::NotImplemented)),
+            ),
+            FileRequest::Canonicalize(..) if !supports(Capability::Canonicalize) => Some(
+                FileResponse::Canonicalize(Err(ResponseError::NotImplemented)),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Pops the next [`FileRequest`] from [`Self::file_reqs`] and returns all layer requests that
+    /// should receive its response.
+    ///
+    /// Also updates the [`Self::remote_fd_stats`] with the `response`.
+    fn next_file_requests(
+        &mut self,
+        response: &FileResponse,
+    ) -> Result<Vec<(MessageId, LayerId)>, RequestQueueEmpty> {
+        let (message_id, layer_id, request) = self.file_reqs.get_with()?;
+        let waiters = DedupKey::from_request(&request)
+            .and_then(|key| self.file_req_waiters.remove(&key))
+            .unwrap_or_default();
+
+        self.update_remote_fd_stats(&request, response);
+
+        Ok(std::iter::once((message_id, layer_id))
+            .chain(waiters)
+            .collect())
+    }
+
+    /// Path of the remote file or directory, if we know it.
+    fn remote_fd_path(&self, fd: RemoteFd) -> Option<PathBuf> {
+        match &self.remote_fd_stats.get(&fd)?.resource {
+            RemoteFdResource::File { path, .. } | RemoteFdResource::Dir { path } => path.clone(),
+            RemoteFdResource::Outgoing { .. } => None,
+        }
+    }
+
+    /// Marks the remote descriptor used by the `request` as active.
+    fn touch_remote_fd(&mut self, request: &FileRequest) {
+        if let Some(stats) =
+            RemoteFd::from_request(request).and_then(|fd| self.remote_fd_stats.get_mut(&fd))
+        {
+            stats.last_activity = SystemTime::now();
+        }
+    }
+
+    /// Records the new remote descriptors and the bytes transferred, from the agent's `response`
+    /// to the `request`.
+    fn update_remote_fd_stats(&mut self, request: &FileRequest, response: &FileResponse) {
+        match (request, response) {
+            (
+                FileRequest::Open(OpenFileRequest { path, open_options }),
+                FileResponse::Open(Ok(OpenFileResponse { fd })),
+            ) => {
+                let resource = RemoteFdResource::File {
+                    path: Some(path.clone()),
+                    open_options: *open_options,
+                };
+                self.remote_fd_stats
+                    .insert(RemoteFd::File(*fd), RemoteFdStats::new(resource));
+            }
+            (FileRequest::OpenImage(open), FileResponse::Open(Ok(OpenFileResponse { fd }))) => {
+                let resource = RemoteFdResource::File {
+                    path: Some(open.path.clone()),
+                    open_options: OpenOptionsInternal {
+                        read: true,
+                        ..Default::default()
+                    },
+                };
+                self.remote_fd_stats
+                    .insert(RemoteFd::File(*fd), RemoteFdStats::new(resource));
+            }
+            (
+                FileRequest::OpenRelative(OpenRelativeFileRequest {
+                    relative_fd,
+                    path,
+                    open_options,
+                }),
+                FileResponse::Open(Ok(OpenFileResponse { fd })),
+            ) => {
+                let resource = RemoteFdResource::File {
+                    path: self
+                        .remote_fd_path(RemoteFd::File(*relative_fd))
+                        .map(|parent| parent.join(path)),
+                    open_options: *open_options,
+                };
+                self.remote_fd_stats
+                    .insert(RemoteFd::File(*fd), RemoteFdStats::new(resource));
+            }
+            (
+                FileRequest::FdOpenDir(FdOpenDirRequest { remote_fd }),
+                FileResponse::OpenDir(Ok(OpenDirResponse { fd })),
+            ) => {
+                let resource = RemoteFdResource::Dir {
+                    path: self.remote_fd_path(RemoteFd::File(*remote_fd)),
+                };
+                self.remote_fd_stats
+                    .insert(RemoteFd::Dir(*fd), RemoteFdStats::new(resource));
+            }
+            (request, response) => {
+                let Some(stats) = RemoteFd::from_request(request)
+                    .and_then(|fd| self.remote_fd_stats.get_mut(&fd))
+                else {
+                    return;
+                };
+
+                stats.last_activity = SystemTime::now();
+                match response {
+                    FileResponse::Read(Ok(ReadFileResponse { read_amount, .. }))
+                    | FileResponse::ReadLimited(Ok(ReadFileResponse { read_amount, .. })) => {
+                        stats.bytes_read += read_amount;
+                    }
+                    FileResponse::Write(Ok(WriteFileResponse { written_amount }))
+                    | FileResponse::WriteLimited(Ok(WriteFileResponse { written_amount })) => {
+                        stats.bytes_written += written_amount;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Hands the watch `events` to the layers that own the watches, and returns the ones that
+    /// belong to `layer_id`, along with the events that were waiting for it.
+    fn route_watch_events(
+        &mut self,
+        layer_id: LayerId,
+        events: Vec<WatchEvent>,
+    ) -> Vec<WatchEvent> {
+        for event in events {
+            let Some(owner) = self.watch_owners.get(&event.watch_id) else {
+                tracing::trace!(?event, "watch event for an unknown watch");
+                continue;
+            };
+
+            self.watch_events.entry(*owner).or_default().push(event);
+        }
+
+        self.watch_events.remove(&layer_id).unwrap_or_default()
+    }
+
+    /// Lists the [`Self::remote_fd_stats`], for
+    /// [`AdminRequest::RemoteFds`](mirrord_intproxy_protocol::AdminRequest::RemoteFds).
+    fn remote_fds_info(&self) -> Vec<RemoteFdInfo> {
+        self.remote_fd_stats
+            .iter()
+            .map(|(fd, stats)| RemoteFdInfo {
+                remote_fd: match fd {
+                    RemoteFd::File(fd) | RemoteFd::Dir(fd) => *fd,
+                },
+                resource: stats.resource.clone(),
+                bytes_read: stats.bytes_read,
+                bytes_written: stats.bytes_written,
+                last_activity: stats.last_activity,
+            })
+            .collect()
+    }
+
+    /// Sends the layer's [`FileRequest`] to the agent, unless an identical request is already in
+    /// flight, see [`DedupKey`].
+    async fn forward_file_request(
+        &mut self,
+        message_id: MessageId,
+        layer_id: LayerId,
+        req: FileRequest,
+        message_bus: &mut MessageBus<Self>,
+    ) {
+        let key = DedupKey::from_request(&req);
+        if let Some(waiters) = key
+            .as_ref()
+            .and_then(|key| self.file_req_waiters.get_mut(key))
+        {
+            tracing::trace!(?req, "identical file request in flight, deduplicating");
+            waiters.push((message_id, layer_id));
+            return;
+        }
+
+        if let Some(key) = key {
+            self.file_req_waiters.insert(key, Vec::new());
+        }
+        self.touch_remote_fd(&req);
+        self.file_reqs
+            .insert_with(message_id, layer_id, req.clone());
+        message_bus
+            .send(ProxyMessage::ToAgent(ClientMessage::FileRequest(req)))
+            .await;
+    }
+
+    /// Carries out what the [`Self::read_streams`] ask for.
+    async fn run_read_stream_actions(
+        &mut self,
+        actions: Vec<ReadStreamAction>,
+        message_bus: &mut MessageBus<Self>,
+    ) {
+        for action in actions {
+            match action {
+                ReadStreamAction::ToAgent(request) => {
+                    message_bus
+                        .send(ProxyMessage::ToAgent(ClientMessage::FileRequest(request)))
+                        .await
+                }
+                ReadStreamAction::Respond {
+                    message_id,
+                    layer_id,
+                    remote_fd,
+                    response,
+                } => {
+                    if let Some(stats) = self.remote_fd_stats.get_mut(&RemoteFd::File(remote_fd)) {
+                        stats.bytes_read += response.read_amount;
+                        stats.last_activity = SystemTime::now();
+                    }
+
+                    message_bus
+                        .send(ToLayer {
+                            message_id,
+                            message: ProxyToLayerMessage::File(FileResponse::Read(Ok(response))),
+                            layer_id,
+                        })
+                        .await
+                }
+                ReadStreamAction::Forward(message_id, layer_id, request) => {
+                    self.forward_file_request(message_id, layer_id, request, message_bus)
+                        .await
+                }
+            }
+        }
+    }
+
+    /// Prepares this proxy for a new agent, after the connection with the previous one was lost.
+    ///
+    /// Returns the requests that were in flight, so that they can be sent again to the new agent.
+    /// Remote fds opened by the previous agent are forgotten, as they are not valid anymore.
+    fn agent_reconnected(&mut self) -> Vec<ClientMessage> {
+        self.remote_fds = Default::default();
+        self.remote_fd_stats.clear();
+        self.watch_owners.clear();
+        self.watch_events.clear();
+
+        let file_reqs = self
+            .file_reqs
+            .data()
+            .cloned()
+            .map(ClientMessage::FileRequest);
+        let addr_info_reqs = self
+            .addr_info_reqs
+            .data()
+            .cloned()
+            .map(ClientMessage::GetAddrInfoRequest);
+        let get_env_reqs = self
+            .get_env_reqs
+            .data()
+            .cloned()
+            .map(ClientMessage::GetEnvVarsRequest);
+
+        file_reqs
+            .chain(addr_info_reqs)
+            .chain(get_env_reqs)
+            .collect()
+    }
 }
 
 impl BackgroundTask for SimpleProxy {
@@ -64,11 +505,15 @@ impl BackgroundTask for SimpleProxy {
                 ) => {
                     let do_close = self.remote_fds.remove(layer_id, RemoteFd::File(fd));
                     if do_close {
+                        self.remote_fd_stats.remove(&RemoteFd::File(fd));
                         message_bus
                             .send(ClientMessage::FileRequest(FileRequest::Close(
                                 CloseFileRequest { fd },
                             )))
                             .await;
+
+                        let actions = self.read_streams.closed(fd);
+                        self.run_read_stream_actions(actions, message_bus).await;
                     }
                 }
                 SimpleProxyMessage::FileReq(
@@ -78,6 +523,7 @@ impl BackgroundTask for SimpleProxy {
                 ) => {
                     let do_close = self.remote_fds.remove(layer_id, RemoteFd::Dir(remote_fd));
                     if do_close {
+                        self.remote_fd_stats.remove(&RemoteFd::Dir(remote_fd));
                         message_bus
                             .send(ClientMessage::FileRequest(FileRequest::CloseDir(
                                 CloseDirRequest { remote_fd },
@@ -85,54 +531,169 @@ impl BackgroundTask for SimpleProxy {
                             .await;
                     }
                 }
+                SimpleProxyMessage::FileReq(
+                    _,
+                    layer_id,
+                    FileRequest::WatchRemove(WatchRemoveRequest { watch_id }),
+                ) => {
+                    if self.watch_owners.remove(&watch_id) == Some(layer_id) {
+                        if let Some(events) = self.watch_events.get_mut(&layer_id) {
+                            events.retain(|event| event.watch_id != watch_id);
+                        }
+                        message_bus
+                            .send(ClientMessage::FileRequest(FileRequest::WatchRemove(
+                                WatchRemoveRequest { watch_id },
+                            )))
+                            .await;
+                    }
+                }
                 SimpleProxyMessage::FileReq(message_id, session_id, req) => {
-                    self.file_reqs.insert(message_id, session_id);
-                    message_bus
-                        .send(ProxyMessage::ToAgent(ClientMessage::FileRequest(req)))
+                    let req = match self.size_limits.limit_file_request(req) {
+                        Ok(req) => req,
+                        Err(response) => {
+                            tracing::warn!(?response, "file request is over the size limit");
+                            message_bus
+                                .send(ToLayer {
+                                    message_id,
+                                    message: ProxyToLayerMessage::File(response),
+                                    layer_id: session_id,
+                                })
+                                .await;
+                            continue;
+                        }
+                    };
+
+                    if let Some(response) = self.unsupported_file_response(&req) {
+                        tracing::warn!(
+                            ?req,
+                            capabilities = ?self.capabilities,
+                            "agent does not support the file request"
+                        );
+                        message_bus
+                            .send(ToLayer {
+                                message_id,
+                                message: ProxyToLayerMessage::File(response),
+                                layer_id: session_id,
+                            })
+                            .await;
+                        continue;
+                    }
+
+                    match (&req, RemoteFd::from_request(&req)) {
+                        (FileRequest::Read(read), _) => {
+                            let actions =
+                                self.read_streams.read(message_id, session_id, read.clone());
+                            self.run_read_stream_actions(actions, message_bus).await;
+                            continue;
+                        }
+                        (FileRequest::ReadLimited(read), _) => {
+                            if let Some(response) = self.read_streams.read_limited(read) {
+                                message_bus
+                                    .send(ToLayer {
+                                        message_id,
+                                        message: ProxyToLayerMessage::File(
+                                            FileResponse::ReadLimited(Ok(response)),
+                                        ),
+                                        layer_id: session_id,
+                                    })
+                                    .await;
+                                continue;
+                            }
+                        }
+                        (_, Some(RemoteFd::File(fd))) => {
+                            let actions = self.read_streams.other_request(fd, &req);
+                            self.run_read_stream_actions(actions, message_bus).await;
+                        }
+                        _ => {}
+                    }
+
+                    self.forward_file_request(message_id, session_id, req, message_bus)
                         .await;
                 }
-                SimpleProxyMessage::FileRes(FileResponse::Open(Ok(OpenFileResponse { fd }))) => {
-                    let (message_id, layer_id) = self.file_reqs.get()?;
+                SimpleProxyMessage::ReadStream(chunk) => {
+                    let actions = self.read_streams.chunk(chunk);
+                    self.run_read_stream_actions(actions, message_bus).await;
+                }
+                SimpleProxyMessage::FileRes(
+                    res @ FileResponse::Open(Ok(OpenFileResponse { fd })),
+                ) => {
+                    for (message_id, layer_id) in self.next_file_requests(&res)? {
+                        self.remote_fds.add(layer_id, RemoteFd::File(fd));
 
-                    self.remote_fds.add(layer_id, RemoteFd::File(fd));
+                        message_bus
+                            .send(ToLayer {
+                                message_id,
+                                message: ProxyToLayerMessage::File(FileResponse::Open(Ok(
+                                    OpenFileResponse { fd },
+                                ))),
+                                layer_id,
+                            })
+                            .await;
+                    }
+                }
+                SimpleProxyMessage::FileRes(
+                    res @ FileResponse::OpenDir(Ok(OpenDirResponse { fd })),
+                ) => {
+                    for (message_id, layer_id) in self.next_file_requests(&res)? {
+                        self.remote_fds.add(layer_id, RemoteFd::Dir(fd));
 
-                    message_bus
-                        .send(ToLayer {
-                            message_id,
-                            message: ProxyToLayerMessage::File(FileResponse::Open(Ok(
-                                OpenFileResponse { fd },
-                            ))),
-                            layer_id,
-                        })
-                        .await;
+                        message_bus
+                            .send(ToLayer {
+                                message_id,
+                                message: ProxyToLayerMessage::File(FileResponse::OpenDir(Ok(
+                                    OpenDirResponse { fd },
+                                ))),
+                                layer_id,
+                            })
+                            .await;
+                    }
                 }
-                SimpleProxyMessage::FileRes(FileResponse::OpenDir(Ok(OpenDirResponse { fd }))) => {
-                    let (message_id, layer_id) = self.file_reqs.get()?;
+                SimpleProxyMessage::FileRes(
+                    res @ FileResponse::WatchAdd(Ok(WatchAddResponse { watch_id })),
+                ) => {
+                    for (message_id, layer_id) in self.next_file_requests(&res)? {
+                        self.watch_owners.insert(watch_id, layer_id);
 
-                    self.remote_fds.add(layer_id, RemoteFd::Dir(fd));
+                        message_bus
+                            .send(ToLayer {
+                                message_id,
+                                message: ProxyToLayerMessage::File(res.clone()),
+                                layer_id,
+                            })
+                            .await;
+                    }
+                }
+                SimpleProxyMessage::FileRes(FileResponse::WatchEvents(Ok(
+                    WatchEventsResponse { events },
+                ))) => {
+                    // Never deduplicated, there is only one layer request waiting.
+                    let (message_id, layer_id, _) = self.file_reqs.get_with()?;
+                    let events = self.route_watch_events(layer_id, events);
 
                     message_bus
                         .send(ToLayer {
                             message_id,
-                            message: ProxyToLayerMessage::File(FileResponse::OpenDir(Ok(
-                                OpenDirResponse { fd },
+                            message: ProxyToLayerMessage::File(FileResponse::WatchEvents(Ok(
+                                WatchEventsResponse { events },
                             ))),
                             layer_id,
                         })
                         .await;
                 }
                 SimpleProxyMessage::FileRes(res) => {
-                    let (message_id, layer_id) = self.file_reqs.get()?;
-                    message_bus
-                        .send(ToLayer {
-                            message_id,
-                            message: ProxyToLayerMessage::File(res),
-                            layer_id,
-                        })
-                        .await;
+                    for (message_id, layer_id) in self.next_file_requests(&res)? {
+                        message_bus
+                            .send(ToLayer {
+                                message_id,
+                                message: ProxyToLayerMessage::File(res.clone()),
+                                layer_id,
+                            })
+                            .await;
+                    }
                 }
                 SimpleProxyMessage::AddrInfoReq(message_id, session_id, req) => {
-                    self.addr_info_reqs.insert(message_id, session_id);
+                    self.addr_info_reqs
+                        .insert_with(message_id, session_id, req.clone());
                     message_bus
                         .send(ProxyMessage::ToAgent(ClientMessage::GetAddrInfoRequest(
                             req,
@@ -140,7 +701,7 @@ impl BackgroundTask for SimpleProxy {
                         .await;
                 }
                 SimpleProxyMessage::AddrInfoRes(res) => {
-                    let (message_id, layer_id) = self.addr_info_reqs.get()?;
+                    let (message_id, layer_id, _) = self.addr_info_reqs.get_with()?;
                     message_bus
                         .send(ToLayer {
                             message_id,
@@ -151,6 +712,7 @@ impl BackgroundTask for SimpleProxy {
                 }
                 SimpleProxyMessage::LayerClosed(LayerClosed { id }) => {
                     for to_close in self.remote_fds.remove_all(id) {
+                        self.remote_fd_stats.remove(&to_close);
                         let req = match to_close {
                             RemoteFd::Dir(remote_fd) => {
                                 FileRequest::CloseDir(CloseDirRequest { remote_fd })
@@ -159,19 +721,66 @@ impl BackgroundTask for SimpleProxy {
                         };
 
                         message_bus.send(ClientMessage::FileRequest(req)).await;
+
+                        if let RemoteFd::File(fd) = to_close {
+                            let actions = self.read_streams.closed(fd);
+                            self.run_read_stream_actions(actions, message_bus).await;
+                        }
+                    }
+
+                    self.watch_events.remove(&id);
+                    let watches = self
+                        .watch_owners
+                        .iter()
+                        .filter(|(_, owner)| **owner == id)
+                        .map(|(watch_id, _)| *watch_id)
+                        .collect::<Vec<_>>();
+                    for watch_id in watches {
+                        self.watch_owners.remove(&watch_id);
+                        message_bus
+                            .send(ClientMessage::FileRequest(FileRequest::WatchRemove(
+                                WatchRemoveRequest { watch_id },
+                            )))
+                            .await;
                     }
                 }
                 SimpleProxyMessage::LayerForked(LayerForked { child, parent }) => {
                     self.remote_fds.clone_all(parent, child);
                 }
                 SimpleProxyMessage::GetEnvReq(message_id, layer_id, req) => {
-                    self.get_env_reqs.insert(message_id, layer_id);
+                    self.get_env_reqs
+                        .insert_with(message_id, layer_id, req.clone());
                     message_bus
                         .send(ProxyMessage::ToAgent(ClientMessage::GetEnvVarsRequest(req)))
                         .await;
                 }
+                SimpleProxyMessage::RemoteFds(tx) => {
+                    let _ = tx.send(self.remote_fds_info());
+                }
+                SimpleProxyMessage::Capabilities(capabilities) => {
+                    if self.allow_fifos && capabilities.supports(Capability::Fifo) {
+                        message_bus
+                            .send(ProxyMessage::ToAgent(ClientMessage::FileRequest(
+                                FileRequest::AllowFifos(AllowFifosRequest),
+                            )))
+                            .await;
+                    }
+                    self.read_streams
+                        .set_enabled(capabilities.supports(Capability::StreamedReads));
+                    self.capabilities.replace(capabilities);
+                }
+                SimpleProxyMessage::AllowFifos => self.allow_fifos = true,
+                SimpleProxyMessage::AgentReconnected => {
+                    message_bus.send(ProxyMessage::AgentReconnected).await;
+                    for request in self.agent_reconnected() {
+                        message_bus.send(ProxyMessage::ToAgent(request)).await;
+                    }
+
+                    let actions = self.read_streams.agent_reconnected();
+                    self.run_read_stream_actions(actions, message_bus).await;
+                }
                 SimpleProxyMessage::GetEnvRes(res) => {
-                    let (message_id, layer_id) = self.get_env_reqs.get()?;
+                    let (message_id, layer_id, _) = self.get_env_reqs.get_with()?;
                     message_bus
                         .send(ToLayer {
                             message_id,
@@ -187,3 +796,79 @@ impl BackgroundTask for SimpleProxy {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn remote_fd_stats_follow_responses() {
+        let mut proxy = SimpleProxy::default();
+        let open_options = OpenOptionsInternal {
+            read: true,
+            ..Default::default()
+        };
+
+        proxy.update_remote_fd_stats(
+            &FileRequest::Open(OpenFileRequest {
+                path: "/app/data".into(),
+                open_options,
+            }),
+            &FileResponse::Open(Ok(OpenFileResponse { fd: 3 })),
+        );
+        proxy.update_remote_fd_stats(
+            &FileRequest::OpenRelative(OpenRelativeFileRequest {
+                relative_fd: 3,
+                path: "file.txt".into(),
+                open_options,
+            }),
+            &FileResponse::Open(Ok(OpenFileResponse { fd: 4 })),
+        );
+        proxy.update_remote_fd_stats(
+            &FileRequest::Read(ReadFileRequest {
+                remote_fd: 4,
+                buffer_size: 16,
+            }),
+            &FileResponse::Read(Ok(ReadFileResponse {
+                bytes: vec![0; 10],
+                read_amount: 10,
+            })),
+        );
+
+        let mut fds = proxy.remote_fds_info();
+        fds.sort_by_key(|fd| fd.remote_fd);
+        let [dir, file] = fds.as_slice() else {
+            panic!("unexpected remote fds {fds:?}");
+        };
+
+        assert_eq!(dir.bytes_read, 0);
+        assert_eq!(file.bytes_read, 10);
+        let RemoteFdResource::File { path, .. } = &file.resource else {
+            panic!("unexpected resource {:?}", file.resource);
+        };
+        assert_eq!(path.as_deref(), Some(Path::new("/app/data/file.txt")));
+    }
+
+    #[test]
+    fn watch_events_go_to_their_layers() {
+        let mut proxy = SimpleProxy::default();
+        proxy.watch_owners.insert(0, LayerId(1));
+        proxy.watch_owners.insert(1, LayerId(2));
+
+        let event = |watch_id| WatchEvent {
+            watch_id,
+            mask: 0x2,
+            cookie: 0,
+            name: None,
+        };
+
+        let first = proxy.route_watch_events(LayerId(1), vec![event(0), event(1), event(2)]);
+        assert_eq!(first, vec![event(0)]);
+
+        let second = proxy.route_watch_events(LayerId(2), vec![event(1)]);
+        assert_eq!(second, vec![event(1), event(1)]);
+        assert!(proxy.watch_events.is_empty());
+    }
+}
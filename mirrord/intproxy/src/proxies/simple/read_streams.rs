@@ -0,0 +1,451 @@
+//! Streaming of remote files that the layers read sequentially, see [`ReadStreams`].
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use mirrord_intproxy_protocol::{LayerId, MessageId};
+use mirrord_protocol::{
+    file::{
+        ReadFileRequest, ReadFileResponse, ReadLimitedFileRequest, ReadStreamAckRequest,
+        ReadStreamChunk, ReadStreamRequest, ReadStreamStopRequest,
+    },
+    FileRequest,
+};
+
+/// How many bytes of a stream can be on their way from the agent or waiting in our buffer.
+const READ_STREAM_WINDOW: u64 = 8 * 1024 * 1024;
+
+/// We acknowledge the consumed bytes in batches, so that the agent does not get a request for
+/// every read.
+///
+/// Reads bigger than this are served as soon as we have this much, so that a read never waits for
+/// more than the agent can send.
+const READ_STREAM_ACK_THRESHOLD: u64 = READ_STREAM_WINDOW / 4;
+
+/// What the [`SimpleProxy`](super::SimpleProxy) should do for the [`ReadStreams`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadStreamAction {
+    /// Send the request to the agent, there is no response.
+    ToAgent(FileRequest),
+    /// Respond to the layer's read of the `remote_fd`.
+    Respond {
+        message_id: MessageId,
+        layer_id: LayerId,
+        remote_fd: u64,
+        response: ReadFileResponse,
+    },
+    /// Handle the layer's request as usual, we can't serve it from a stream.
+    Forward(MessageId, LayerId, FileRequest),
+}
+
+/// A layer's `read` that waits for more data from the stream.
+#[derive(Debug)]
+struct WaitingRead {
+    message_id: MessageId,
+    layer_id: LayerId,
+    buffer_size: u64,
+}
+
+/// A remote file streamed with [`FileRequest::ReadStream`].
+#[derive(Debug)]
+struct ReadStream {
+    id: u64,
+    /// Received from the agent, not yet read by the layers.
+    buffer: VecDeque<u8>,
+    /// Position of the [`ReadStream::buffer`] in the file, known after the first chunk.
+    buffer_offset: Option<u64>,
+    /// How many bytes the layers read from this stream.
+    consumed: u64,
+    /// How many of the [`ReadStream::consumed`] bytes we did not acknowledge yet.
+    unacked: u64,
+    /// The agent sent the end of the file.
+    eof: bool,
+    /// The agent failed to read the file, it sends nothing more.
+    failed: bool,
+    waiting: VecDeque<WaitingRead>,
+}
+
+impl ReadStream {
+    /// Takes bytes for the next waiting read, if we have enough of them.
+    fn take_ready(&mut self) -> Option<(WaitingRead, Vec<u8>)> {
+        let read = self.waiting.front()?;
+        let available = self.buffer.len() as u64;
+        let ready = available >= read.buffer_size.min(READ_STREAM_ACK_THRESHOLD)
+            || self.eof
+            || (self.failed && available > 0);
+        if !ready {
+            return None;
+        }
+
+        let read = self.waiting.pop_front()?;
+        let amount = read.buffer_size.min(available);
+        let bytes = self.buffer.drain(..amount as usize).collect();
+        self.buffer_offset = self.buffer_offset.map(|offset| offset + amount);
+        self.consumed += amount;
+        self.unacked += amount;
+
+        Some((read, bytes))
+    }
+}
+
+/// Streams the remote files that the layers read sequentially, so that their reads don't wait for
+/// a round trip to the agent.
+///
+/// When a layer reads the same remote fd twice in a row, we start a [`FileRequest::ReadStream`]
+/// right after the second read. The following reads of the fd are served from the stream, until a
+/// request that moves the position of the file (or writes to it) stops the stream, and the agent
+/// moves the position back to what the layers actually read.
+#[derive(Debug, Default)]
+pub struct ReadStreams {
+    /// Whether the agent supports [`FileRequest::ReadStream`].
+    enabled: bool,
+    next_stream_id: u64,
+    /// Remote fds whose last request was a [`FileRequest::Read`].
+    sequential: HashSet<u64>,
+    /// Remote fds that the agent failed to stream, e.g. pipes.
+    unstreamable: HashSet<u64>,
+    /// By remote fd.
+    streams: HashMap<u64, ReadStream>,
+    /// Remote fds of the [`ReadStreams::streams`], by stream id.
+    stream_fds: HashMap<u64, u64>,
+}
+
+impl ReadStreams {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Handles a layer's [`FileRequest::Read`].
+    pub fn read(
+        &mut self,
+        message_id: MessageId,
+        layer_id: LayerId,
+        request: ReadFileRequest,
+    ) -> Vec<ReadStreamAction> {
+        let ReadFileRequest {
+            remote_fd,
+            buffer_size,
+        } = request;
+
+        if let Some(stream) = self.streams.get_mut(&remote_fd) {
+            stream.waiting.push_back(WaitingRead {
+                message_id,
+                layer_id,
+                buffer_size,
+            });
+
+            return self.serve(remote_fd);
+        }
+
+        let mut actions = vec![ReadStreamAction::Forward(
+            message_id,
+            layer_id,
+            FileRequest::Read(request),
+        )];
+
+        let sequential = !self.sequential.insert(remote_fd);
+        if self.enabled && sequential && !self.unstreamable.contains(&remote_fd) {
+            let stream_id = self.next_stream_id;
+            self.next_stream_id += 1;
+
+            self.streams.insert(
+                remote_fd,
+                ReadStream {
+                    id: stream_id,
+                    buffer: Default::default(),
+                    buffer_offset: None,
+                    consumed: 0,
+                    unacked: 0,
+                    eof: false,
+                    failed: false,
+                    waiting: Default::default(),
+                },
+            );
+            self.stream_fds.insert(stream_id, remote_fd);
+
+            actions.push(ReadStreamAction::ToAgent(FileRequest::ReadStream(
+                ReadStreamRequest {
+                    fd: remote_fd,
+                    stream_id,
+                    window: READ_STREAM_WINDOW,
+                },
+            )));
+        }
+
+        actions
+    }
+
+    /// Serves a layer's [`FileRequest::ReadLimited`] from the stream of the fd, if we have the
+    /// requested part of the file.
+    pub fn read_limited(&self, request: &ReadLimitedFileRequest) -> Option<ReadFileResponse> {
+        let stream = self.streams.get(&request.remote_fd)?;
+        let start = request.start_from.checked_sub(stream.buffer_offset?)?;
+        let available = (stream.buffer.len() as u64).checked_sub(start)?;
+        if available < request.buffer_size && !stream.eof {
+            return None;
+        }
+
+        let bytes: Vec<u8> = stream
+            .buffer
+            .range(start as usize..)
+            .take(request.buffer_size as usize)
+            .copied()
+            .collect();
+
+        Some(ReadFileResponse {
+            read_amount: bytes.len() as u64,
+            bytes,
+        })
+    }
+
+    /// Handles a layer's request on the `remote_fd`, other than a read, before it is sent to the
+    /// agent.
+    ///
+    /// Stops the stream if the request moves the position of the file or writes to it.
+    pub fn other_request(
+        &mut self,
+        remote_fd: u64,
+        request: &FileRequest,
+    ) -> Vec<ReadStreamAction> {
+        match request {
+            FileRequest::Seek(..) | FileRequest::Write(..) | FileRequest::WriteLimited(..) => {
+                self.sequential.remove(&remote_fd);
+                self.stop(remote_fd)
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The `remote_fd` was closed, the agent drops its stream.
+    pub fn closed(&mut self, remote_fd: u64) -> Vec<ReadStreamAction> {
+        self.sequential.remove(&remote_fd);
+        self.unstreamable.remove(&remote_fd);
+
+        let Some(stream) = self.streams.remove(&remote_fd) else {
+            return vec![];
+        };
+        self.stream_fds.remove(&stream.id);
+
+        Self::forward_waiting(remote_fd, stream)
+    }
+
+    /// Handles a [`ReadStreamChunk`] from the agent.
+    pub fn chunk(&mut self, chunk: ReadStreamChunk) -> Vec<ReadStreamAction> {
+        let ReadStreamChunk {
+            stream_id,
+            offset,
+            bytes,
+        } = chunk;
+
+        let Some(remote_fd) = self.stream_fds.get(&stream_id).copied() else {
+            tracing::trace!(stream_id, "chunk of a stopped read stream");
+            return vec![];
+        };
+        let Some(stream) = self.streams.get_mut(&remote_fd) else {
+            return vec![];
+        };
+
+        match bytes {
+            Ok(bytes) if bytes.is_empty() => stream.eof = true,
+            Ok(bytes) => {
+                let buffer_offset = *stream.buffer_offset.get_or_insert(offset);
+                if buffer_offset + stream.buffer.len() as u64 == offset {
+                    stream.buffer.extend(bytes);
+                } else {
+                    tracing::error!(stream_id, offset, "read stream chunk out of order");
+                    stream.failed = true;
+                }
+            }
+            Err(error) => {
+                tracing::debug!(stream_id, %error, "agent failed to stream a remote file");
+                stream.failed = true;
+            }
+        }
+
+        self.serve(remote_fd)
+    }
+
+    /// Forgets all streams, they were lost with the previous agent.
+    pub fn agent_reconnected(&mut self) -> Vec<ReadStreamAction> {
+        self.sequential.clear();
+        self.unstreamable.clear();
+        self.stream_fds.clear();
+
+        self.streams
+            .drain()
+            .flat_map(|(remote_fd, stream)| Self::forward_waiting(remote_fd, stream))
+            .collect()
+    }
+
+    /// Responds to the reads waiting for the stream of the `remote_fd`, acknowledges the data
+    /// they consumed, and stops the stream if it failed.
+    fn serve(&mut self, remote_fd: u64) -> Vec<ReadStreamAction> {
+        let Some(stream) = self.streams.get_mut(&remote_fd) else {
+            return vec![];
+        };
+
+        let mut actions = vec![];
+        while let Some((read, bytes)) = stream.take_ready() {
+            actions.push(ReadStreamAction::Respond {
+                message_id: read.message_id,
+                layer_id: read.layer_id,
+                remote_fd,
+                response: ReadFileResponse {
+                    read_amount: bytes.len() as u64,
+                    bytes,
+                },
+            });
+        }
+
+        if stream.failed && stream.buffer.is_empty() {
+            self.unstreamable.insert(remote_fd);
+            actions.extend(self.stop(remote_fd));
+        } else if stream.unacked >= READ_STREAM_ACK_THRESHOLD && !stream.eof {
+            actions.push(ReadStreamAction::ToAgent(FileRequest::ReadStreamAck(
+                ReadStreamAckRequest {
+                    stream_id: stream.id,
+                    amount: stream.unacked,
+                },
+            )));
+            stream.unacked = 0;
+        }
+
+        actions
+    }
+
+    /// Stops the stream of the `remote_fd`, the reads that were waiting for it are sent to the
+    /// agent after the [`FileRequest::ReadStreamStop`].
+    fn stop(&mut self, remote_fd: u64) -> Vec<ReadStreamAction> {
+        let Some(stream) = self.streams.remove(&remote_fd) else {
+            return vec![];
+        };
+        self.stream_fds.remove(&stream.id);
+
+        let stop = ReadStreamAction::ToAgent(FileRequest::ReadStreamStop(ReadStreamStopRequest {
+            stream_id: stream.id,
+            consumed: stream.consumed,
+        }));
+
+        std::iter::once(stop)
+            .chain(Self::forward_waiting(remote_fd, stream))
+            .collect()
+    }
+
+    fn forward_waiting(remote_fd: u64, stream: ReadStream) -> Vec<ReadStreamAction> {
+        stream
+            .waiting
+            .into_iter()
+            .map(|read| {
+                ReadStreamAction::Forward(
+                    read.message_id,
+                    read.layer_id,
+                    FileRequest::Read(ReadFileRequest {
+                        remote_fd,
+                        buffer_size: read.buffer_size,
+                    }),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mirrord_protocol::file::{SeekFileRequest, SeekFromInternal};
+
+    use super::*;
+
+    fn read(streams: &mut ReadStreams, message_id: MessageId) -> Vec<ReadStreamAction> {
+        streams.read(
+            message_id,
+            LayerId(0),
+            ReadFileRequest {
+                remote_fd: 1,
+                buffer_size: 4,
+            },
+        )
+    }
+
+    fn chunk(offset: u64, bytes: &[u8]) -> ReadStreamChunk {
+        ReadStreamChunk {
+            stream_id: 0,
+            offset,
+            bytes: Ok(bytes.to_vec()),
+        }
+    }
+
+    fn respond(message_id: MessageId, bytes: &[u8]) -> ReadStreamAction {
+        ReadStreamAction::Respond {
+            message_id,
+            layer_id: LayerId(0),
+            remote_fd: 1,
+            response: ReadFileResponse {
+                bytes: bytes.to_vec(),
+                read_amount: bytes.len() as u64,
+            },
+        }
+    }
+
+    #[test]
+    fn sequential_reads_are_streamed() {
+        let mut streams = ReadStreams::default();
+        streams.set_enabled(true);
+
+        assert_eq!(read(&mut streams, 0).len(), 1);
+        let actions = read(&mut streams, 1);
+        assert!(matches!(
+            actions.as_slice(),
+            [
+                ReadStreamAction::Forward(1, ..),
+                ReadStreamAction::ToAgent(FileRequest::ReadStream(ReadStreamRequest {
+                    fd: 1,
+                    stream_id: 0,
+                    ..
+                })),
+            ]
+        ));
+
+        assert!(read(&mut streams, 2).is_empty());
+        assert_eq!(
+            streams.chunk(chunk(8, b"abcdef")),
+            vec![respond(2, b"abcd")]
+        );
+        assert!(read(&mut streams, 3).is_empty());
+        assert_eq!(streams.chunk(chunk(14, b"")), vec![respond(3, b"ef")]);
+        assert_eq!(read(&mut streams, 4), vec![respond(4, b"")]);
+    }
+
+    #[test]
+    fn seek_stops_the_stream() {
+        let mut streams = ReadStreams::default();
+        streams.set_enabled(true);
+
+        read(&mut streams, 0);
+        read(&mut streams, 1);
+        streams.chunk(chunk(8, b"abcdef"));
+        assert_eq!(read(&mut streams, 2), vec![respond(2, b"abcd")]);
+        read(&mut streams, 3);
+
+        let seek = FileRequest::Seek(SeekFileRequest {
+            fd: 1,
+            seek_from: SeekFromInternal::Current(0),
+        });
+        assert_eq!(
+            streams.other_request(1, &seek),
+            vec![
+                ReadStreamAction::ToAgent(FileRequest::ReadStreamStop(ReadStreamStopRequest {
+                    stream_id: 0,
+                    consumed: 4,
+                })),
+                ReadStreamAction::Forward(
+                    3,
+                    LayerId(0),
+                    FileRequest::Read(ReadFileRequest {
+                        remote_fd: 1,
+                        buffer_size: 4,
+                    }),
+                ),
+            ]
+        );
+        assert!(streams.chunk(chunk(14, b"gh")).is_empty());
+    }
+}
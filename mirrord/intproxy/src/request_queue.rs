@@ -26,17 +26,39 @@ pub struct RequestQueueEmpty;
 /// A queue used to match agent responses with layer requests.
 /// A single queue can be used for multiple types of requests only if the agent preserves order
 /// between them.
-#[derive(Default)]
-pub struct RequestQueue {
-    inner: VecDeque<(MessageId, LayerId)>,
+///
+/// Each entry can carry some extra data `T`, e.g. a copy of the request, so that it can be sent
+/// again to a new agent.
+pub struct RequestQueue<T = ()> {
+    inner: VecDeque<(MessageId, LayerId, T)>,
 }
 
-impl fmt::Debug for RequestQueue {
+impl<T> Default for RequestQueue<T> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for RequestQueue<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RequestQueue")
             .field("queue_len", &self.inner.len())
-            .field("front", &self.inner.front().copied())
-            .field("back", &self.inner.back().copied())
+            .field(
+                "front",
+                &self
+                    .inner
+                    .front()
+                    .map(|(message_id, layer_id, _)| (*message_id, *layer_id)),
+            )
+            .field(
+                "back",
+                &self
+                    .inner
+                    .back()
+                    .map(|(message_id, layer_id, _)| (*message_id, *layer_id)),
+            )
             .finish()
     }
 }
@@ -45,12 +67,32 @@ impl RequestQueue {
     /// Save the request at the end of this queue.
     #[tracing::instrument(level = "trace")]
     pub fn insert(&mut self, message_id: MessageId, layer_id: LayerId) {
-        self.inner.push_back((message_id, layer_id));
+        self.insert_with(message_id, layer_id, ());
     }
 
     /// Retrieve and remove a request from the front of this queue.
     #[tracing::instrument(level = "trace")]
     pub fn get(&mut self) -> Result<(MessageId, LayerId), RequestQueueEmpty> {
+        self.get_with()
+            .map(|(message_id, layer_id, ())| (message_id, layer_id))
+    }
+}
+
+impl<T> RequestQueue<T> {
+    /// Save the request at the end of this queue, together with its extra data.
+    #[tracing::instrument(level = "trace", skip(data))]
+    pub fn insert_with(&mut self, message_id: MessageId, layer_id: LayerId, data: T) {
+        self.inner.push_back((message_id, layer_id, data));
+    }
+
+    /// Retrieve and remove a request from the front of this queue, together with its extra data.
+    #[tracing::instrument(level = "trace")]
+    pub fn get_with(&mut self) -> Result<(MessageId, LayerId, T), RequestQueueEmpty> {
         self.inner.pop_front().ok_or(RequestQueueEmpty)
     }
+
+    /// Extra data of all requests in this queue, from front to back.
+    pub fn data(&self) -> impl Iterator<Item = &T> {
+        self.inner.iter().map(|(_, _, data)| data)
+    }
 }
@@ -0,0 +1,358 @@
+//! Compares the responses to the requests shadowed with
+//! [`StealType::FilteredHttpShadow`](mirrord_protocol::tcp::StealType::FilteredHttpShadow)
+//! (`feature.network.incoming.http_filter.shadow_diff`).
+//!
+//! The local application gets a copy of each shadowed request, while the remote target handles the
+//! original and the agent sends us its response. The two responses come in any order, so we keep
+//! what we have until both are here, and then write a [`DiffRecord`] to the report file, one JSON
+//! object per line.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{self, LineWriter, Write},
+    path::Path,
+};
+
+use hyper::{HeaderMap, StatusCode};
+use mirrord_protocol::{
+    tcp::{HttpRequestFallback, HttpResponse, HttpResponseFallback, InternalHttpBody},
+    ConnectionId, RequestId,
+};
+use serde::Serialize;
+
+/// Headers that differ between any two responses, so there's no point in comparing them.
+const IGNORED_HEADERS: &[&str] = &["date", "content-length"];
+
+/// The parts of a response that we compare.
+#[derive(Debug)]
+struct ResponseSummary {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl From<&HttpResponseFallback> for ResponseSummary {
+    fn from(response: &HttpResponseFallback) -> Self {
+        match response {
+            HttpResponseFallback::Framed(response) => response.into(),
+            HttpResponseFallback::Fallback(response) => Self {
+                status: response.internal_response.status(),
+                headers: response.internal_response.headers().clone(),
+                body: response.internal_response.body().clone(),
+            },
+        }
+    }
+}
+
+impl From<&HttpResponse<InternalHttpBody>> for ResponseSummary {
+    fn from(response: &HttpResponse<InternalHttpBody>) -> Self {
+        Self {
+            status: response.internal_response.status(),
+            headers: response.internal_response.headers().clone(),
+            body: response.internal_response.body().data(),
+        }
+    }
+}
+
+/// A difference between the response of the local application and the response of the remote
+/// target.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Difference {
+    /// One of the responses never came, e.g. the local application failed.
+    MissingResponse {
+        from: &'static str,
+    },
+    Status {
+        local: u16,
+        remote: u16,
+    },
+    /// A header is missing in one of the responses or has different values, multiple values are
+    /// joined with `, `.
+    Header {
+        name: String,
+        local: Option<String>,
+        remote: Option<String>,
+    },
+    Body {
+        local_size: usize,
+        remote_size: usize,
+        /// Offset of the first byte that differs.
+        first_difference: usize,
+    },
+}
+
+/// A line of the report, for one shadowed request.
+#[derive(Debug, Serialize)]
+struct DiffRecord {
+    connection_id: ConnectionId,
+    request_id: RequestId,
+    method: Option<String>,
+    uri: Option<String>,
+    local_status: Option<u16>,
+    remote_status: Option<u16>,
+    /// Both responses are here and they don't differ.
+    matches: bool,
+    differences: Vec<Difference>,
+}
+
+/// A shadowed request that is still waiting for its responses.
+#[derive(Debug, Default)]
+struct PendingDiff {
+    /// Method and URI of the request.
+    request: Option<(String, String)>,
+    local: Option<ResponseSummary>,
+    remote: Option<ResponseSummary>,
+}
+
+/// Values of the header with the given name, joined with `, `.
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .collect::<Vec<_>>();
+
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+/// Compares the two responses.
+fn differences(local: &ResponseSummary, remote: &ResponseSummary) -> Vec<Difference> {
+    let mut differences = vec![];
+
+    if local.status != remote.status {
+        differences.push(Difference::Status {
+            local: local.status.as_u16(),
+            remote: remote.status.as_u16(),
+        });
+    }
+
+    let names = local
+        .headers
+        .keys()
+        .chain(remote.headers.keys())
+        .map(|name| name.as_str())
+        .filter(|name| !IGNORED_HEADERS.contains(name))
+        .collect::<BTreeSet<_>>();
+    for name in names {
+        let local = header_value(&local.headers, name);
+        let remote = header_value(&remote.headers, name);
+
+        if local != remote {
+            differences.push(Difference::Header {
+                name: name.to_string(),
+                local,
+                remote,
+            });
+        }
+    }
+
+    if local.body != remote.body {
+        let first_difference = local
+            .body
+            .iter()
+            .zip(&remote.body)
+            .position(|(local, remote)| local != remote)
+            .unwrap_or_else(|| local.body.len().min(remote.body.len()));
+
+        differences.push(Difference::Body {
+            local_size: local.body.len(),
+            remote_size: remote.body.len(),
+            first_difference,
+        });
+    }
+
+    differences
+}
+
+/// Writes the report of the differences between the responses to the shadowed requests.
+pub struct ShadowDiff {
+    report: LineWriter<File>,
+    pending: HashMap<(ConnectionId, RequestId), PendingDiff>,
+}
+
+impl ShadowDiff {
+    /// Creates the report file, truncating it if it exists.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            report: LineWriter::new(File::create(path)?),
+            pending: Default::default(),
+        })
+    }
+
+    /// A shadowed request was sent to the local application.
+    pub(crate) fn request(&mut self, request: &HttpRequestFallback) {
+        let (method, uri) = match request {
+            HttpRequestFallback::Framed(request) => (
+                &request.internal_request.method,
+                &request.internal_request.uri,
+            ),
+            HttpRequestFallback::Fallback(request) => (
+                &request.internal_request.method,
+                &request.internal_request.uri,
+            ),
+        };
+
+        self.pending
+            .entry((request.connection_id(), request.request_id()))
+            .or_default()
+            .request = Some((method.to_string(), uri.to_string()));
+    }
+
+    /// The local application responded to a shadowed request.
+    pub(crate) fn local_response(&mut self, response: &HttpResponseFallback) {
+        let key = (response.connection_id(), response.request_id());
+        self.pending.entry(key).or_default().local = Some(response.into());
+        self.write_if_complete(key);
+    }
+
+    /// The remote target responded to a shadowed request.
+    pub(crate) fn remote_response(&mut self, response: &HttpResponse<InternalHttpBody>) {
+        let key = (response.connection_id, response.request_id);
+        self.pending.entry(key).or_default().remote = Some(response.into());
+        self.write_if_complete(key);
+    }
+
+    /// The connection with the local application is done, the requests that are still pending
+    /// won't get their local responses.
+    pub(crate) fn connection_finished(&mut self, connection_id: ConnectionId) {
+        let keys = self
+            .pending
+            .keys()
+            .filter(|(id, _)| *id == connection_id)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            if let Some(pending) = self.pending.remove(&key) {
+                self.write(key, pending);
+            }
+        }
+    }
+
+    fn write_if_complete(&mut self, key: (ConnectionId, RequestId)) {
+        let complete = self
+            .pending
+            .get(&key)
+            .is_some_and(|pending| pending.local.is_some() && pending.remote.is_some());
+
+        if complete {
+            if let Some(pending) = self.pending.remove(&key) {
+                self.write(key, pending);
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        (connection_id, request_id): (ConnectionId, RequestId),
+        pending: PendingDiff,
+    ) {
+        let differences = match (&pending.local, &pending.remote) {
+            (Some(local), Some(remote)) => differences(local, remote),
+            (None, _) => vec![Difference::MissingResponse { from: "local" }],
+            (_, None) => vec![Difference::MissingResponse { from: "remote" }],
+        };
+        let (method, uri) = pending.request.unzip();
+
+        let record = DiffRecord {
+            connection_id,
+            request_id,
+            method,
+            uri,
+            local_status: pending.local.map(|local| local.status.as_u16()),
+            remote_status: pending.remote.map(|remote| remote.status.as_u16()),
+            matches: differences.is_empty(),
+            differences,
+        };
+
+        let result = serde_json::to_writer(&mut self.report, &record)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(self.report));
+        if let Err(error) = result {
+            tracing::warn!(%error, ?record, "failed to write to the shadow diff report");
+        }
+    }
+}
+
+impl Drop for ShadowDiff {
+    fn drop(&mut self) {
+        for (key, pending) in std::mem::take(&mut self.pending) {
+            self.write(key, pending);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{CONTENT_TYPE, DATE};
+
+    use super::*;
+
+    fn response(status: StatusCode, headers: &[(&str, &str)], body: &[u8]) -> ResponseSummary {
+        ResponseSummary {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn same_responses() {
+        let local = response(
+            StatusCode::OK,
+            &[(CONTENT_TYPE.as_str(), "text/plain"), (DATE.as_str(), "a")],
+            b"hello",
+        );
+        let remote = response(
+            StatusCode::OK,
+            &[(CONTENT_TYPE.as_str(), "text/plain"), (DATE.as_str(), "b")],
+            b"hello",
+        );
+
+        assert!(differences(&local, &remote).is_empty());
+    }
+
+    #[test]
+    fn different_responses() {
+        let local = response(
+            StatusCode::OK,
+            &[(CONTENT_TYPE.as_str(), "text/plain"), ("x-local", "1")],
+            b"hello",
+        );
+        let remote = response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &[(CONTENT_TYPE.as_str(), "application/json")],
+            b"help",
+        );
+
+        assert_eq!(
+            differences(&local, &remote),
+            vec![
+                Difference::Status {
+                    local: 200,
+                    remote: 500
+                },
+                Difference::Header {
+                    name: "content-type".to_string(),
+                    local: Some("text/plain".to_string()),
+                    remote: Some("application/json".to_string()),
+                },
+                Difference::Header {
+                    name: "x-local".to_string(),
+                    local: Some("1".to_string()),
+                    remote: None,
+                },
+                Difference::Body {
+                    local_size: 5,
+                    remote_size: 4,
+                    first_difference: 3,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Limits on how much data the internal proxy holds in memory for a single request, see
+//! [`SizeLimits`].
+
+use mirrord_config::internal_proxy::InternalProxyConfig;
+use mirrord_protocol::{
+    file::{ReadFileRequest, ReadLimitedFileRequest, WriteFileRequest, WriteLimitedFileRequest},
+    FileRequest, FileResponse, ResponseError,
+};
+
+/// Config key of [`SizeLimits::max_file_write_size`].
+pub const MAX_FILE_WRITE_SIZE_KEY: &str = "internal_proxy.max_file_write_size";
+/// Config key of [`SizeLimits::max_http_body_size`].
+pub const MAX_HTTP_BODY_SIZE_KEY: &str = "internal_proxy.max_http_body_size";
+
+/// Maximum sizes of the requests and responses that pass through the internal proxy, so that one
+/// huge file operation or HTTP body does not exhaust its memory.
+///
+/// [`None`] means no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// Reads of remote files are shortened to this size.
+    pub max_file_read_size: Option<u64>,
+    /// Bigger writes to remote files fail with [`ResponseError::SizeLimitExceeded`].
+    pub max_file_write_size: Option<u64>,
+    /// Bigger bodies of stolen HTTP requests, and of the local responses to them, are rejected.
+    pub max_http_body_size: Option<u64>,
+}
+
+impl From<&InternalProxyConfig> for SizeLimits {
+    fn from(config: &InternalProxyConfig) -> Self {
+        Self {
+            max_file_read_size: Some(config.max_file_read_size),
+            max_file_write_size: config.max_file_write_size,
+            max_http_body_size: config.max_http_body_size,
+        }
+    }
+}
+
+impl SizeLimits {
+    /// Applies the file limits to the given [`FileRequest`].
+    ///
+    /// Reads are shortened, the application gets the rest with the next calls. Writes over the
+    /// limit are not sent to the agent, and the returned [`FileResponse`] should be sent to the
+    /// layer instead.
+    pub(crate) fn limit_file_request(
+        &self,
+        request: FileRequest,
+    ) -> Result<FileRequest, FileResponse> {
+        let write_error = |size: usize, limit: u64| ResponseError::SizeLimitExceeded {
+            what: "File write".to_string(),
+            size: size as u64,
+            limit,
+            config_key: MAX_FILE_WRITE_SIZE_KEY.to_string(),
+        };
+
+        match (request, self.max_file_read_size, self.max_file_write_size) {
+            (
+                FileRequest::Read(ReadFileRequest {
+                    remote_fd,
+                    buffer_size,
+                }),
+                Some(limit),
+                _,
+            ) => Ok(FileRequest::Read(ReadFileRequest {
+                remote_fd,
+                buffer_size: buffer_size.min(limit),
+            })),
+            (
+                FileRequest::ReadLimited(ReadLimitedFileRequest {
+                    remote_fd,
+                    buffer_size,
+                    start_from,
+                }),
+                Some(limit),
+                _,
+            ) => Ok(FileRequest::ReadLimited(ReadLimitedFileRequest {
+                remote_fd,
+                buffer_size: buffer_size.min(limit),
+                start_from,
+            })),
+            (FileRequest::Write(WriteFileRequest { write_bytes, .. }), _, Some(limit))
+                if write_bytes.len() as u64 > limit =>
+            {
+                Err(FileResponse::Write(Err(write_error(
+                    write_bytes.len(),
+                    limit,
+                ))))
+            }
+            (
+                FileRequest::WriteLimited(WriteLimitedFileRequest { write_bytes, .. }),
+                _,
+                Some(limit),
+            ) if write_bytes.len() as u64 > limit => Err(FileResponse::WriteLimited(Err(
+                write_error(write_bytes.len(), limit),
+            ))),
+            (request, ..) => Ok(request),
+        }
+    }
+
+    /// Error message for an HTTP body that is over [`Self::max_http_body_size`].
+    pub(crate) fn http_body_error(what: &str, size: u64, limit: u64) -> String {
+        ResponseError::SizeLimitExceeded {
+            what: what.to_string(),
+            size,
+            limit,
+            config_key: MAX_HTTP_BODY_SIZE_KEY.to_string(),
+        }
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: SizeLimits = SizeLimits {
+        max_file_read_size: Some(16),
+        max_file_write_size: Some(8),
+        max_http_body_size: None,
+    };
+
+    #[test]
+    fn reads_are_shortened() {
+        let request = FileRequest::Read(ReadFileRequest {
+            remote_fd: 1,
+            buffer_size: 1024,
+        });
+
+        assert_eq!(
+            LIMITS.limit_file_request(request),
+            Ok(FileRequest::Read(ReadFileRequest {
+                remote_fd: 1,
+                buffer_size: 16,
+            }))
+        );
+    }
+
+    #[test]
+    fn big_writes_are_rejected() {
+        let request = FileRequest::WriteLimited(WriteLimitedFileRequest {
+            remote_fd: 1,
+            start_from: 0,
+            write_bytes: vec![0; 9],
+        });
+
+        assert_eq!(
+            LIMITS.limit_file_request(request),
+            Err(FileResponse::WriteLimited(Err(
+                ResponseError::SizeLimitExceeded {
+                    what: "File write".to_string(),
+                    size: 9,
+                    limit: 8,
+                    config_key: MAX_FILE_WRITE_SIZE_KEY.to_string(),
+                }
+            )))
+        );
+
+        let request = FileRequest::Write(WriteFileRequest {
+            fd: 1,
+            write_bytes: vec![0; 8],
+        });
+        assert_eq!(LIMITS.limit_file_request(request.clone()), Ok(request));
+    }
+
+    #[test]
+    fn no_limits() {
+        let request = FileRequest::Write(WriteFileRequest {
+            fd: 1,
+            write_bytes: vec![0; 1024],
+        });
+
+        assert_eq!(
+            SizeLimits::default().limit_file_request(request.clone()),
+            Ok(request)
+        );
+    }
+}
@@ -4,8 +4,13 @@ use mirrord_protocol::{ClientCodec, ClientMessage, DaemonMessage};
 use tokio::sync::mpsc;
 use tracing::Instrument;
 
+pub mod autoscaling;
+pub mod cilium;
 pub mod container;
 pub mod kubernetes;
+pub mod proxy;
+pub mod quic;
+pub mod rbac;
 pub mod runtime;
 
 const CONNECTION_CHANNEL_SIZE: usize = 1000;
@@ -0,0 +1,458 @@
+//! Pausing the autoscalers of the target for the duration of a session, see
+//! [`FeatureConfig::pause_autoscaling`](mirrord_config::feature::FeatureConfig::pause_autoscaling).
+//!
+//! When mirrord steals the traffic of a workload, its pods see less load, so a
+//! HorizontalPodAutoscaler (or a KEDA ScaledObject, which manages its own HPA) scales it down,
+//! and the pod we target may be removed in the middle of the session. We pin the autoscalers to
+//! the current number of replicas:
+//!
+//! - an HPA gets `minReplicas` and `maxReplicas` set to its current replicas;
+//! - a ScaledObject gets KEDA's `autoscaling.keda.sh/paused-replicas` annotation.
+//!
+//! Both are marked with the [`PAUSED_AUTOSCALING_ANNOTATION`], which holds what we changed, so we
+//! (or the user, if the session was killed) can restore them. An autoscaler that already has the
+//! annotation is left alone, it's paused by another session. Every patch is conditional on the
+//! `resourceVersion` we read, so we never overwrite changes made in the meantime.
+
+use k8s_openapi::api::autoscaling::v2::HorizontalPodAutoscaler;
+use kube::{
+    api::{DynamicObject, GroupVersionKind, ListParams, Patch, PatchParams},
+    core::ApiResource,
+    Api, Client, ResourceExt,
+};
+use mirrord_config::target::Target;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+
+use crate::{api::kubernetes::get_k8s_resource_api, error::Result};
+
+/// Marks the autoscalers paused by mirrord, see the [module docs](self).
+pub const PAUSED_AUTOSCALING_ANNOTATION: &str = "mirrord.metalbear.co/paused-autoscaling";
+
+/// KEDA doesn't scale a ScaledObject with this annotation, and keeps its target at the given
+/// number of replicas.
+const KEDA_PAUSED_REPLICAS_ANNOTATION: &str = "autoscaling.keda.sh/paused-replicas";
+
+/// Value of the [`PAUSED_AUTOSCALING_ANNOTATION`] of an HPA, its original settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HpaOriginal {
+    min_replicas: Option<i32>,
+    max_replicas: i32,
+    /// The value we pinned `minReplicas` and `maxReplicas` to.
+    pinned_replicas: i32,
+}
+
+/// The autoscalers paused for a session, resumed with [`AutoscalingPause::resume`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoscalingPause {
+    pub namespace: Option<String>,
+    /// Names of the HorizontalPodAutoscalers we pinned.
+    pub hpas: Vec<String>,
+    /// Names of the KEDA ScaledObjects we paused.
+    pub scaled_objects: Vec<String>,
+    /// Autoscalers of the target we didn't pause, and why.
+    #[serde(skip)]
+    pub skipped: Vec<String>,
+}
+
+impl AutoscalingPause {
+    /// Pauses the autoscalers of the `target` workload. Targets that are not scaled (pods, jobs)
+    /// get an empty pause.
+    ///
+    /// On failure, the autoscalers paused so far are resumed.
+    #[tracing::instrument(level = "trace", skip(client), ret, err)]
+    pub async fn pause(client: &Client, target: &Target, namespace: Option<&str>) -> Result<Self> {
+        let mut pause = Self {
+            namespace: namespace.map(ToString::to_string),
+            ..Default::default()
+        };
+
+        let Some((kind, name)) = scale_target(target) else {
+            return Ok(pause);
+        };
+
+        match pause.pause_autoscalers(client, kind, name).await {
+            Ok(()) => Ok(pause),
+            Err(error) => {
+                if let Err(error) = pause.resume(client).await {
+                    warn!(%error, ?pause, "Failed to resume the autoscalers after a failed pause");
+                }
+                Err(error)
+            }
+        }
+    }
+
+    async fn pause_autoscalers(&mut self, client: &Client, kind: &str, name: &str) -> Result<()> {
+        let namespace = self.namespace.as_deref();
+
+        let keda_api = scaled_object_api(client, namespace);
+        // Not found when KEDA is not installed.
+        let scaled_objects = match keda_api.list(&ListParams::default()).await {
+            Ok(list) => list.items,
+            Err(kube::Error::Api(response)) if response.code == 404 => Default::default(),
+            Err(error) => return Err(error.into()),
+        };
+        for scaled_object in scaled_objects {
+            if !scaled_object_targets(&scaled_object, kind, name) {
+                continue;
+            }
+
+            let object_name = scaled_object.name_any();
+            if let Some(reason) = scaled_object_paused(&scaled_object) {
+                self.skipped
+                    .push(format!("scaledobject/{object_name} {reason}"));
+                continue;
+            }
+
+            // KEDA defaults to the current replicas when the annotation is set, but only since
+            // 2.13, so we pass them explicitly.
+            let hpa_name = format!("keda-hpa-{object_name}");
+            let replicas = get_k8s_resource_api::<HorizontalPodAutoscaler>(client, namespace)
+                .get_opt(&hpa_name)
+                .await?
+                .map(|hpa| current_replicas(&hpa))
+                .unwrap_or(1);
+
+            let patch = json!({
+                "metadata": {
+                    "resourceVersion": scaled_object.resource_version(),
+                    "annotations": {
+                        PAUSED_AUTOSCALING_ANNOTATION: replicas.to_string(),
+                        KEDA_PAUSED_REPLICAS_ANNOTATION: replicas.to_string(),
+                    },
+                },
+            });
+            keda_api
+                .patch(&object_name, &PatchParams::default(), &Patch::Merge(patch))
+                .await?;
+            self.scaled_objects.push(object_name);
+        }
+
+        let hpa_api: Api<HorizontalPodAutoscaler> = get_k8s_resource_api(client, namespace);
+        for hpa in hpa_api.list(&ListParams::default()).await?.items {
+            if !hpa_targets(&hpa, kind, name) || owned_by_keda(&hpa) {
+                continue;
+            }
+
+            let hpa_name = hpa.name_any();
+            if hpa
+                .annotations()
+                .contains_key(PAUSED_AUTOSCALING_ANNOTATION)
+            {
+                self.skipped.push(format!(
+                    "hpa/{hpa_name} is already paused by another mirrord session"
+                ));
+                continue;
+            }
+
+            let Some(spec) = hpa.spec.as_ref() else {
+                continue;
+            };
+            let original = HpaOriginal {
+                min_replicas: spec.min_replicas,
+                max_replicas: spec.max_replicas,
+                pinned_replicas: current_replicas(&hpa),
+            };
+
+            let patch = json!({
+                "metadata": {
+                    "resourceVersion": hpa.resource_version(),
+                    "annotations": {
+                        PAUSED_AUTOSCALING_ANNOTATION: json!(original).to_string(),
+                    },
+                },
+                "spec": {
+                    "minReplicas": original.pinned_replicas,
+                    "maxReplicas": original.pinned_replicas,
+                },
+            });
+            hpa_api
+                .patch(&hpa_name, &PatchParams::default(), &Patch::Merge(patch))
+                .await?;
+            self.hpas.push(hpa_name);
+        }
+
+        Ok(())
+    }
+
+    /// Whether we paused anything.
+    pub fn is_empty(&self) -> bool {
+        self.hpas.is_empty() && self.scaled_objects.is_empty()
+    }
+
+    /// Restores the autoscalers we paused. Those changed by someone else during the session only
+    /// lose the [`PAUSED_AUTOSCALING_ANNOTATION`].
+    ///
+    /// Returns the autoscalers that were not fully restored, and why.
+    #[tracing::instrument(level = "trace", skip(client), ret, err)]
+    pub async fn resume(&self, client: &Client) -> Result<Vec<String>> {
+        let namespace = self.namespace.as_deref();
+        let mut not_restored = vec![];
+
+        let keda_api = scaled_object_api(client, namespace);
+        for name in &self.scaled_objects {
+            let Some(scaled_object) = keda_api.get_opt(name).await? else {
+                continue;
+            };
+            let annotations = scaled_object.annotations();
+            let Some(ours) = annotations.get(PAUSED_AUTOSCALING_ANNOTATION) else {
+                continue;
+            };
+
+            let mut removed = json!({ PAUSED_AUTOSCALING_ANNOTATION: null });
+            if annotations.get(KEDA_PAUSED_REPLICAS_ANNOTATION) == Some(ours) {
+                removed[KEDA_PAUSED_REPLICAS_ANNOTATION] = json!(null);
+            } else {
+                not_restored.push(format!(
+                    "scaledobject/{name} was paused by someone else during the session"
+                ));
+            }
+
+            let patch = json!({
+                "metadata": {
+                    "resourceVersion": scaled_object.resource_version(),
+                    "annotations": removed,
+                },
+            });
+            keda_api
+                .patch(name, &PatchParams::default(), &Patch::Merge(patch))
+                .await?;
+        }
+
+        let hpa_api: Api<HorizontalPodAutoscaler> = get_k8s_resource_api(client, namespace);
+        for name in &self.hpas {
+            let Some(hpa) = hpa_api.get_opt(name).await? else {
+                continue;
+            };
+            let Some(original) = hpa
+                .annotations()
+                .get(PAUSED_AUTOSCALING_ANNOTATION)
+                .and_then(|original| serde_json::from_str::<HpaOriginal>(original).ok())
+            else {
+                continue;
+            };
+
+            let mut patch = json!({
+                "metadata": {
+                    "resourceVersion": hpa.resource_version(),
+                    "annotations": { PAUSED_AUTOSCALING_ANNOTATION: null },
+                },
+            });
+            let pinned = hpa.spec.as_ref().is_some_and(|spec| {
+                spec.min_replicas == Some(original.pinned_replicas)
+                    && spec.max_replicas == original.pinned_replicas
+            });
+            if pinned {
+                patch["spec"] = json!({
+                    "minReplicas": original.min_replicas,
+                    "maxReplicas": original.max_replicas,
+                });
+            } else {
+                not_restored.push(format!(
+                    "hpa/{name} was changed by someone else during the session"
+                ));
+            }
+
+            hpa_api
+                .patch(name, &PatchParams::default(), &Patch::Merge(patch))
+                .await?;
+        }
+
+        Ok(not_restored)
+    }
+}
+
+/// Kind and name of the workload behind the `target`, as in the `scaleTargetRef` of its
+/// autoscalers.
+fn scale_target(target: &Target) -> Option<(&'static str, &str)> {
+    match target {
+        Target::Deployment(target) => Some(("Deployment", &target.deployment)),
+        Target::Rollout(target) => Some(("Rollout", &target.rollout)),
+        Target::StatefulSet(target) => Some(("StatefulSet", &target.stateful_set)),
+        Target::Pod(..) | Target::Job(..) | Target::CronJob(..) | Target::Targetless => None,
+    }
+}
+
+fn scaled_object_api(client: &Client, namespace: Option<&str>) -> Api<DynamicObject> {
+    let resource = ApiResource::from_gvk(&GroupVersionKind::gvk(
+        "keda.sh",
+        "v1alpha1",
+        "ScaledObject",
+    ));
+
+    match namespace {
+        Some(namespace) => Api::namespaced_with(client.clone(), namespace, &resource),
+        None => Api::default_namespaced_with(client.clone(), &resource),
+    }
+}
+
+fn hpa_targets(hpa: &HorizontalPodAutoscaler, kind: &str, name: &str) -> bool {
+    hpa.spec.as_ref().is_some_and(|spec| {
+        spec.scale_target_ref.kind == kind && spec.scale_target_ref.name == name
+    })
+}
+
+/// KEDA creates an HPA for every ScaledObject, and reverts any change made to it.
+fn owned_by_keda(hpa: &HorizontalPodAutoscaler) -> bool {
+    hpa.owner_references()
+        .iter()
+        .any(|owner| owner.kind == "ScaledObject")
+}
+
+fn scaled_object_targets(scaled_object: &DynamicObject, kind: &str, name: &str) -> bool {
+    let Some(target) = scaled_object.data.pointer("/spec/scaleTargetRef") else {
+        return false;
+    };
+
+    // KEDA defaults to a Deployment.
+    let target_kind = target
+        .get("kind")
+        .and_then(|kind| kind.as_str())
+        .unwrap_or("Deployment");
+    let target_name = target.get("name").and_then(|name| name.as_str());
+
+    target_kind == kind && target_name == Some(name)
+}
+
+/// Why we can't pause the ScaledObject, if it's already paused.
+fn scaled_object_paused(scaled_object: &DynamicObject) -> Option<&'static str> {
+    let annotations = scaled_object.annotations();
+
+    if annotations.contains_key(PAUSED_AUTOSCALING_ANNOTATION) {
+        Some("is already paused by another mirrord session")
+    } else if annotations.contains_key(KEDA_PAUSED_REPLICAS_ANNOTATION)
+        || annotations.contains_key("autoscaling.keda.sh/paused")
+    {
+        Some("is already paused")
+    } else {
+        None
+    }
+}
+
+/// The replicas the HPA's target currently has, at least 1.
+fn current_replicas(hpa: &HorizontalPodAutoscaler) -> i32 {
+    hpa.status
+        .as_ref()
+        .and_then(|status| status.current_replicas)
+        .unwrap_or_default()
+        .max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::{
+        api::autoscaling::v2::{
+            CrossVersionObjectReference, HorizontalPodAutoscalerSpec, HorizontalPodAutoscalerStatus,
+        },
+        apimachinery::pkg::apis::meta::v1::OwnerReference,
+    };
+    use kube::api::ObjectMeta;
+
+    use super::*;
+
+    fn hpa(kind: &str, name: &str, current_replicas: Option<i32>) -> HorizontalPodAutoscaler {
+        HorizontalPodAutoscaler {
+            metadata: ObjectMeta {
+                name: Some("hpa".to_string()),
+                ..Default::default()
+            },
+            spec: Some(HorizontalPodAutoscalerSpec {
+                scale_target_ref: CrossVersionObjectReference {
+                    kind: kind.to_string(),
+                    name: name.to_string(),
+                    api_version: Some("apps/v1".to_string()),
+                },
+                min_replicas: Some(2),
+                max_replicas: 10,
+                ..Default::default()
+            }),
+            status: Some(HorizontalPodAutoscalerStatus {
+                current_replicas,
+                desired_replicas: 3,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn scaled_object(spec: serde_json::Value) -> DynamicObject {
+        let resource = ApiResource::from_gvk(&GroupVersionKind::gvk(
+            "keda.sh",
+            "v1alpha1",
+            "ScaledObject",
+        ));
+
+        DynamicObject::new("scaled-object", &resource).data(json!({ "spec": spec }))
+    }
+
+    #[test]
+    fn scale_targets() {
+        let target = "deployment/app/container/main".parse().unwrap();
+        assert_eq!(scale_target(&target), Some(("Deployment", "app")));
+
+        let target = "pod/app".parse().unwrap();
+        assert_eq!(scale_target(&target), None);
+    }
+
+    #[test]
+    fn hpa_target() {
+        let hpa = hpa("Deployment", "app", Some(3));
+
+        assert!(hpa_targets(&hpa, "Deployment", "app"));
+        assert!(!hpa_targets(&hpa, "Deployment", "other"));
+        assert!(!hpa_targets(&hpa, "StatefulSet", "app"));
+    }
+
+    #[test]
+    fn keda_hpa() {
+        let mut hpa = hpa("Deployment", "app", Some(3));
+        assert!(!owned_by_keda(&hpa));
+
+        hpa.metadata.owner_references = Some(vec![OwnerReference {
+            kind: "ScaledObject".to_string(),
+            name: "app".to_string(),
+            ..Default::default()
+        }]);
+        assert!(owned_by_keda(&hpa));
+    }
+
+    #[test]
+    fn pinned_replicas() {
+        assert_eq!(current_replicas(&hpa("Deployment", "app", Some(3))), 3);
+        assert_eq!(current_replicas(&hpa("Deployment", "app", Some(0))), 1);
+        assert_eq!(current_replicas(&hpa("Deployment", "app", None)), 1);
+    }
+
+    #[test]
+    fn scaled_object_target() {
+        let explicit = scaled_object(json!({
+            "scaleTargetRef": { "kind": "StatefulSet", "name": "app" },
+        }));
+        assert!(scaled_object_targets(&explicit, "StatefulSet", "app"));
+        assert!(!scaled_object_targets(&explicit, "Deployment", "app"));
+
+        let default_kind = scaled_object(json!({ "scaleTargetRef": { "name": "app" } }));
+        assert!(scaled_object_targets(&default_kind, "Deployment", "app"));
+        assert!(!scaled_object_targets(&default_kind, "Deployment", "other"));
+    }
+
+    #[test]
+    fn scaled_object_already_paused() {
+        let mut object = scaled_object(json!({ "scaleTargetRef": { "name": "app" } }));
+        assert_eq!(scaled_object_paused(&object), None);
+
+        object
+            .annotations_mut()
+            .insert(KEDA_PAUSED_REPLICAS_ANNOTATION.to_string(), "2".to_string());
+        assert_eq!(scaled_object_paused(&object), Some("is already paused"));
+
+        object
+            .annotations_mut()
+            .insert(PAUSED_AUTOSCALING_ANNOTATION.to_string(), "2".to_string());
+        assert_eq!(
+            scaled_object_paused(&object),
+            Some("is already paused by another mirrord session")
+        );
+    }
+}
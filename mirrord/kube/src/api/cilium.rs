@@ -0,0 +1,140 @@
+//! Detection of [Cilium](https://cilium.io)'s kube-proxy replacement, which load balances the
+//! services with eBPF instead of iptables.
+//!
+//! With socket load balancing enabled for the pods, Cilium rewrites the destination of the
+//! connections to a service when they're made, in the client's `connect`, so they never go through
+//! the iptables rules that redirect the traffic (the same reason Cilium asks to restrict it to the
+//! host namespace when running with Istio). We can't do much about it from the agent, so we read
+//! the settings from Cilium's ConfigMap and explain to the user what to change.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{Api, Client};
+use tracing::debug;
+
+/// ConfigMap that holds the settings of the Cilium agents.
+const CILIUM_CONFIG_MAP: &str = "cilium-config";
+
+/// Namespace in which Cilium is installed by default.
+const CILIUM_NAMESPACE: &str = "kube-system";
+
+/// The settings of Cilium that affect stealing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CiliumConfig {
+    /// `kube-proxy-replacement` is enabled.
+    pub kube_proxy_replacement: bool,
+    /// Services are load balanced in the sockets' `connect` (`bpf-lb-sock`).
+    pub socket_lb: bool,
+    /// Socket load balancing is restricted to the host namespace (`bpf-lb-sock-hostns-only`).
+    pub socket_lb_host_namespace_only: bool,
+}
+
+impl CiliumConfig {
+    /// Reads the config from the `cilium-config` ConfigMap.
+    ///
+    /// Returns [`None`] when Cilium is not installed, or the user is not allowed to read its
+    /// config, which is not uncommon for `kube-system`.
+    pub async fn fetch(client: &Client) -> Option<Self> {
+        let config_map = Api::<ConfigMap>::namespaced(client.clone(), CILIUM_NAMESPACE)
+            .get_opt(CILIUM_CONFIG_MAP)
+            .await
+            .inspect_err(|error| debug!(%error, "Failed to read the Cilium config"))
+            .ok()
+            .flatten()?;
+
+        Some(Self::from_data(&config_map.data.unwrap_or_default()))
+    }
+
+    fn from_data(data: &BTreeMap<String, String>) -> Self {
+        let enabled = |key: &str| data.get(key).is_some_and(|value| value == "true");
+
+        // `strict` is the pre 1.14 name of `true`.
+        let kube_proxy_replacement = data
+            .get("kube-proxy-replacement")
+            .is_some_and(|value| value == "true" || value == "strict");
+
+        Self {
+            kube_proxy_replacement,
+            // Part of the kube-proxy replacement, unless explicitly disabled.
+            socket_lb: enabled("bpf-lb-sock")
+                || (kube_proxy_replacement
+                    && data.get("bpf-lb-sock").map(String::as_str) != Some("false")),
+            socket_lb_host_namespace_only: enabled("bpf-lb-sock-hostns-only"),
+        }
+    }
+
+    /// Explains why stealing may miss the traffic sent to the target through its services, if it
+    /// does with this config.
+    pub fn steal_warning(&self) -> Option<String> {
+        if !self.socket_lb || self.socket_lb_host_namespace_only {
+            return None;
+        }
+
+        Some(format!(
+            "mirrord has detected Cilium with socket load balancing enabled for the pods \
+             (`bpf-lb-sock: true` and `bpf-lb-sock-hostns-only: false` in the `{CILIUM_CONFIG_MAP}` \
+             ConfigMap{}). Cilium connects the clients of a service straight to its backends, \
+             which bypasses the iptables rules mirrord uses to steal the traffic, so the \
+             connections made through the target's services may not be stolen. To steal them, set \
+             `socketLB.hostNamespaceOnly: true` in Cilium's Helm values \
+             (`bpf-lb-sock-hostns-only: true` in the ConfigMap) and restart the Cilium agents.",
+            if self.kube_proxy_replacement {
+                ", with `kube-proxy-replacement: true`"
+            } else {
+                ""
+            }
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn kube_proxy_replacement() {
+        let config = CiliumConfig::from_data(&data(&[("kube-proxy-replacement", "true")]));
+
+        assert!(config.kube_proxy_replacement);
+        assert!(config.socket_lb);
+        assert!(config.steal_warning().is_some());
+    }
+
+    #[test]
+    fn host_namespace_only() {
+        let config = CiliumConfig::from_data(&data(&[
+            ("kube-proxy-replacement", "strict"),
+            ("bpf-lb-sock-hostns-only", "true"),
+        ]));
+
+        assert!(config.kube_proxy_replacement);
+        assert!(config.steal_warning().is_none());
+    }
+
+    #[test]
+    fn socket_lb_disabled() {
+        let config = CiliumConfig::from_data(&data(&[
+            ("kube-proxy-replacement", "true"),
+            ("bpf-lb-sock", "false"),
+        ]));
+
+        assert!(!config.socket_lb);
+        assert!(config.steal_warning().is_none());
+    }
+
+    #[test]
+    fn without_kube_proxy_replacement() {
+        assert_eq!(CiliumConfig::from_data(&data(&[])), CiliumConfig::default());
+        assert!(CiliumConfig::from_data(&data(&[("bpf-lb-sock", "true")]))
+            .steal_warning()
+            .is_some());
+    }
+}
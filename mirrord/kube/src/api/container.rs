@@ -9,7 +9,10 @@ use rand::{
     Rng,
 };
 
-use crate::{api::kubernetes::AgentKubernetesConnectInfo, error::Result};
+use crate::{
+    api::{kubernetes::AgentKubernetesConnectInfo, quic::AgentQuicCert},
+    error::Result,
+};
 
 pub mod ephemeral;
 pub mod job;
@@ -39,6 +42,23 @@ pub struct ContainerParams {
     /// Value for [`AGENT_OPERATOR_CERT_ENV`](mirrord_protocol::AGENT_OPERATOR_CERT_ENV) set in
     /// the agent container.
     pub tls_cert: Option<String>,
+    /// Architecture of the nodes the agent pod must be scheduled on, used with
+    /// [`AgentConfig::arch_images`] for targetless agents.
+    pub arch: Option<String>,
+    /// Value for [`AGENT_MAX_SESSION_DURATION_ENV`](mirrord_protocol::AGENT_MAX_SESSION_DURATION_ENV)
+    /// set in the agent container.
+    pub max_session_duration: Option<u64>,
+    /// Value for
+    /// [`AGENT_OUTGOING_CONNECT_TIMEOUT_ENV`](mirrord_protocol::AGENT_OUTGOING_CONNECT_TIMEOUT_ENV)
+    /// set in the agent container.
+    pub outgoing_connect_timeout: Option<u64>,
+    /// Value for
+    /// [`AGENT_OUTGOING_CONNECT_RETRIES_ENV`](mirrord_protocol::AGENT_OUTGOING_CONNECT_RETRIES_ENV)
+    /// set in the agent container.
+    pub outgoing_connect_retries: u32,
+    /// Certificate of the agent when it accepts QUIC connections
+    /// ([`AgentTransport::Quic`](mirrord_config::agent::AgentTransport::Quic)).
+    pub quic_cert: Option<AgentQuicCert>,
 }
 
 impl ContainerParams {
@@ -58,6 +78,11 @@ impl ContainerParams {
             gid,
             port,
             tls_cert: None,
+            arch: None,
+            max_session_duration: None,
+            outgoing_connect_timeout: None,
+            outgoing_connect_retries: 0,
+            quic_cert: None,
         }
     }
 }
@@ -153,6 +153,8 @@ where
         agent_port: params.port,
         namespace: runtime_data.pod_namespace.clone(),
         agent_version: version,
+        container_name: Some(params.name.clone()),
+        quic_cert: params.quic_cert.as_ref().map(|cert| cert.cert_pem.clone()),
     })
 }
 
@@ -220,6 +222,7 @@ impl ContainerVariant for EphemeralTargetedVariant<'_> {
                     ..Default::default()
                 }),
                 privileged: Some(agent.privileged),
+                allow_privilege_escalation: agent.unprivileged.then_some(false),
                 run_as_non_root: agent.privileged.then_some(false),
                 run_as_user: agent.privileged.then_some(0),
                 ..Default::default()
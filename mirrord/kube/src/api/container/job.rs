@@ -105,6 +105,8 @@ where
         agent_port: params.port,
         namespace: agent.namespace.clone(),
         agent_version: version,
+        container_name: Some("mirrord-agent".to_string()),
+        quic_cert: params.quic_cert.as_ref().map(|cert| cert.cert_pem.clone()),
     })
 }
 
@@ -245,6 +247,11 @@ mod test {
             port: 3000,
             gid: 13,
             tls_cert: None,
+            arch: None,
+            max_session_duration: None,
+            outgoing_connect_timeout: None,
+            outgoing_connect_retries: 0,
+            quic_cert: None,
         };
 
         let update = JobVariant::new(&agent, &params).as_update();
@@ -327,6 +334,11 @@ mod test {
             port: 3000,
             gid: 13,
             tls_cert: None,
+            arch: None,
+            max_session_duration: None,
+            outgoing_connect_timeout: None,
+            outgoing_connect_retries: 0,
+            quic_cert: None,
         };
 
         let update = JobTargetedVariant::new(
@@ -444,4 +456,37 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn targetless_with_arch() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config_context = ConfigContext::default();
+        let agent = AgentFileConfig::default().generate_config(&mut config_context)?;
+        let params = ContainerParams {
+            name: "foobar".to_string(),
+            port: 3000,
+            gid: 13,
+            tls_cert: None,
+            arch: Some("arm64".to_string()),
+            max_session_duration: None,
+            outgoing_connect_timeout: None,
+            outgoing_connect_retries: 0,
+            quic_cert: None,
+        };
+
+        let update = JobVariant::new(&agent, &params).as_update();
+        let node_selector = update
+            .spec
+            .and_then(|spec| spec.template.spec)
+            .and_then(|spec| spec.node_selector);
+
+        assert_eq!(
+            node_selector,
+            Some(BTreeMap::from([(
+                "kubernetes.io/arch".to_string(),
+                "arm64".to_string()
+            )]))
+        );
+
+        Ok(())
+    }
 }
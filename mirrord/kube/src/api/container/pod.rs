@@ -1,12 +1,16 @@
+use std::collections::BTreeMap;
+
 use k8s_openapi::{
     api::core::v1::{
-        Capabilities, Container, HostPathVolumeSource, LocalObjectReference, Pod, PodSpec,
-        SecurityContext, Volume, VolumeMount,
+        Capabilities, Container, EmptyDirVolumeSource, EnvVar, HostPathVolumeSource,
+        LocalObjectReference, Pod, PodSpec, SecurityContext, Volume, VolumeMount,
     },
+    apimachinery::pkg::api::resource::Quantity,
     DeepMerge,
 };
 use kube::api::ObjectMeta;
 use mirrord_config::agent::AgentConfig;
+use mirrord_protocol::AGENT_MIRROR_BUFFER_DIR_ENV;
 
 use super::util::agent_env;
 use crate::api::{
@@ -14,9 +18,41 @@ use crate::api::{
         util::{base_command_line, get_capabilities, DEFAULT_TOLERATIONS},
         ContainerParams, ContainerVariant,
     },
-    runtime::RuntimeData,
+    runtime::{RuntimeData, NODE_ARCH_LABEL},
 };
 
+/// Name of the `emptyDir` volume to which the agent spills the mirrored traffic
+/// (`agent.mirror_buffer.disk`).
+const MIRROR_BUFFER_VOLUME: &str = "mirror-buffer";
+
+/// Where [`MIRROR_BUFFER_VOLUME`] is mounted in the agent container.
+const MIRROR_BUFFER_MOUNT_PATH: &str = "/mirror-buffer";
+
+/// Room for the file system's own data in [`MIRROR_BUFFER_VOLUME`], and for the spilled messages
+/// that were read but not removed yet, so the agent never gets evicted for filling it.
+const MIRROR_BUFFER_HEADROOM: u64 = 1024 * 1024;
+
+/// The [`MIRROR_BUFFER_VOLUME`] and its mount, when the agent spills the mirrored traffic.
+fn mirror_buffer_volume(agent: &AgentConfig) -> Option<(Volume, VolumeMount)> {
+    let disk = agent.mirror_buffer.disk.filter(|disk| *disk > 0)?;
+
+    Some((
+        Volume {
+            name: MIRROR_BUFFER_VOLUME.to_string(),
+            empty_dir: Some(EmptyDirVolumeSource {
+                size_limit: Some(Quantity((disk + MIRROR_BUFFER_HEADROOM).to_string())),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        VolumeMount {
+            mount_path: MIRROR_BUFFER_MOUNT_PATH.to_string(),
+            name: MIRROR_BUFFER_VOLUME.to_string(),
+            ..Default::default()
+        },
+    ))
+}
+
 pub struct PodVariant<'c> {
     agent: &'c AgentConfig,
     command_line: Vec<String>,
@@ -82,7 +118,18 @@ impl ContainerVariant for PodVariant<'_> {
             .expect("Should be valid ResourceRequirements json")
         });
 
-        let env = agent_env(agent, params);
+        let mut env = agent_env(agent, params);
+        let mirror_buffer = mirror_buffer_volume(agent);
+        if mirror_buffer.is_some() {
+            env.push(EnvVar {
+                name: AGENT_MIRROR_BUFFER_DIR_ENV.to_string(),
+                value: Some(MIRROR_BUFFER_MOUNT_PATH.to_string()),
+                ..Default::default()
+            });
+        }
+        let (volumes, volume_mounts) = mirror_buffer
+            .map(|(volume, mount)| (vec![volume], vec![mount]))
+            .unzip();
         let image_pull_secrets = agent.image_pull_secrets.as_ref().map(|secrets| {
             secrets
                 .iter()
@@ -115,6 +162,10 @@ impl ContainerVariant for PodVariant<'_> {
             },
             spec: Some(PodSpec {
                 restart_policy: Some("Never".to_string()),
+                node_selector: params
+                    .arch
+                    .as_ref()
+                    .map(|arch| BTreeMap::from([(NODE_ARCH_LABEL.to_string(), arch.clone())])),
                 image_pull_secrets,
                 tolerations: Some(tolerations.clone()),
                 containers: vec![Container {
@@ -125,8 +176,10 @@ impl ContainerVariant for PodVariant<'_> {
                     env: Some(env),
                     // Add requests to avoid getting defaulted https://github.com/metalbear-co/mirrord/issues/579
                     resources: Some(resources),
+                    volume_mounts,
                     ..Default::default()
                 }],
+                volumes,
                 ..Default::default()
             }),
             ..Default::default()
@@ -185,28 +238,36 @@ impl ContainerVariant for PodTargetedVariant<'_> {
         let agent = self.agent_config();
         let params = self.params();
 
+        // Repeated here, in case the lists of the update replace the ones of the base pod.
+        let (mirror_buffer_volume, mirror_buffer_mount) = mirror_buffer_volume(agent).unzip();
+
         let update = Pod {
             spec: Some(PodSpec {
                 host_pid: Some(true),
                 node_name: Some(runtime_data.node_name.clone()),
-                volumes: Some(vec![
-                    Volume {
-                        name: "hostrun".to_string(),
-                        host_path: Some(HostPathVolumeSource {
-                            path: "/run".to_string(),
+                volumes: Some(
+                    [
+                        Volume {
+                            name: "hostrun".to_string(),
+                            host_path: Some(HostPathVolumeSource {
+                                path: "/run".to_string(),
+                                ..Default::default()
+                            }),
                             ..Default::default()
-                        }),
-                        ..Default::default()
-                    },
-                    Volume {
-                        name: "hostvar".to_string(),
-                        host_path: Some(HostPathVolumeSource {
-                            path: "/var".to_string(),
+                        },
+                        Volume {
+                            name: "hostvar".to_string(),
+                            host_path: Some(HostPathVolumeSource {
+                                path: "/var".to_string(),
+                                ..Default::default()
+                            }),
                             ..Default::default()
-                        }),
-                        ..Default::default()
-                    },
-                ]),
+                        },
+                    ]
+                    .into_iter()
+                    .chain(mirror_buffer_volume)
+                    .collect(),
+                ),
                 containers: vec![Container {
                     name: "mirrord-agent".to_string(),
                     security_context: Some(SecurityContext {
@@ -223,18 +284,23 @@ impl ContainerVariant for PodTargetedVariant<'_> {
                         }),
                         ..Default::default()
                     }),
-                    volume_mounts: Some(vec![
-                        VolumeMount {
-                            mount_path: "/host/run".to_string(),
-                            name: "hostrun".to_string(),
-                            ..Default::default()
-                        },
-                        VolumeMount {
-                            mount_path: "/host/var".to_string(),
-                            name: "hostvar".to_string(),
-                            ..Default::default()
-                        },
-                    ]),
+                    volume_mounts: Some(
+                        [
+                            VolumeMount {
+                                mount_path: "/host/run".to_string(),
+                                name: "hostrun".to_string(),
+                                ..Default::default()
+                            },
+                            VolumeMount {
+                                mount_path: "/host/var".to_string(),
+                                name: "hostvar".to_string(),
+                                ..Default::default()
+                            },
+                        ]
+                        .into_iter()
+                        .chain(mirror_buffer_mount)
+                        .collect(),
+                    ),
                     ..Default::default()
                 }],
                 ..Default::default()
@@ -3,8 +3,17 @@ use std::sync::LazyLock;
 use futures::{AsyncBufReadExt, TryStreamExt};
 use k8s_openapi::api::core::v1::{EnvVar, Pod, Toleration};
 use kube::{api::LogParams, Api};
-use mirrord_config::agent::{AgentConfig, LinuxCapability};
-use mirrord_protocol::{AGENT_NETWORK_INTERFACE_ENV, AGENT_OPERATOR_CERT_ENV};
+use mirrord_config::agent::{
+    AgentConfig, AgentTransport, LinuxCapability, LoopbackSteal, SnifferBackend,
+};
+use mirrord_protocol::{
+    AGENT_MAX_SESSION_DURATION_ENV, AGENT_MIRROR_BUFFER_DISK_ENV, AGENT_MIRROR_BUFFER_MEMORY_ENV,
+    AGENT_MIRROR_BUFFER_ON_FULL_ENV, AGENT_NETWORK_INTERFACE_ENV, AGENT_OPERATOR_CERT_ENV,
+    AGENT_OTLP_METRICS_ENDPOINT_ENV, AGENT_OTLP_METRICS_HEADERS_ENV,
+    AGENT_OTLP_METRICS_INTERVAL_ENV, AGENT_OUTGOING_CONNECT_RETRIES_ENV,
+    AGENT_OUTGOING_CONNECT_TIMEOUT_ENV, AGENT_QUIC_CERT_ENV, AGENT_QUIC_KEY_ENV, AGENT_SNIFFER_ENV,
+    AGENT_TRANSPORT_ENV, AGENT_UNPRIVILEGED_ENV,
+};
 use regex::Regex;
 use tracing::warn;
 
@@ -21,8 +30,13 @@ pub(super) static DEFAULT_TOLERATIONS: LazyLock<Vec<Toleration>> = LazyLock::new
     }]
 });
 
-/// Retrieve a list of Linux capabilities for the agent container.
+/// Retrieve a list of Linux capabilities for the agent container, none with
+/// [`AgentConfig::unprivileged`].
 pub(super) fn get_capabilities(agent: &AgentConfig) -> Vec<LinuxCapability> {
+    if agent.unprivileged {
+        return Vec::new();
+    }
+
     let disabled = agent.disabled_capabilities.clone().unwrap_or_default();
 
     LinuxCapability::all()
@@ -56,6 +70,21 @@ pub(super) fn agent_env(agent: &AgentConfig, params: &&ContainerParams) -> Vec<E
         ));
     }
 
+    if agent.steal_loopback != LoopbackSteal::Include {
+        env.push((
+            "MIRRORD_AGENT_STEAL_LOOPBACK".to_string(),
+            agent.steal_loopback.to_string(),
+        ));
+    }
+
+    if agent.unprivileged {
+        env.push((AGENT_UNPRIVILEGED_ENV.to_string(), "true".to_string()));
+    }
+
+    if agent.sniffer != SnifferBackend::Raw {
+        env.push((AGENT_SNIFFER_ENV.to_string(), agent.sniffer.to_string()));
+    }
+
     if let Some(interface) = agent.network_interface.as_ref() {
         env.push((AGENT_NETWORK_INTERFACE_ENV.to_string(), interface.into()));
     }
@@ -63,6 +92,75 @@ pub(super) fn agent_env(agent: &AgentConfig, params: &&ContainerParams) -> Vec<E
         env.push(("MIRRORD_AGENT_DNS_TIMEOUT".to_string(), timeout.to_string()));
     };
 
+    if let Some(endpoint) = agent.otlp_metrics.endpoint.as_ref() {
+        env.push((
+            AGENT_OTLP_METRICS_ENDPOINT_ENV.to_string(),
+            endpoint.clone(),
+        ));
+
+        if let Some(headers) = agent.otlp_metrics.headers.as_ref() {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            env.push((AGENT_OTLP_METRICS_HEADERS_ENV.to_string(), headers));
+        }
+
+        if let Some(interval) = agent.otlp_metrics.interval {
+            env.push((
+                AGENT_OTLP_METRICS_INTERVAL_ENV.to_string(),
+                interval.to_string(),
+            ));
+        }
+    }
+
+    if let Some(memory) = agent.mirror_buffer.memory {
+        env.push((
+            AGENT_MIRROR_BUFFER_MEMORY_ENV.to_string(),
+            memory.to_string(),
+        ));
+    }
+    if let Some(disk) = agent.mirror_buffer.disk {
+        env.push((AGENT_MIRROR_BUFFER_DISK_ENV.to_string(), disk.to_string()));
+    }
+    if let Some(on_full) = agent.mirror_buffer.on_full {
+        env.push((
+            AGENT_MIRROR_BUFFER_ON_FULL_ENV.to_string(),
+            on_full.to_string(),
+        ));
+    }
+
+    if let Some(duration) = params.max_session_duration {
+        env.push((
+            AGENT_MAX_SESSION_DURATION_ENV.to_string(),
+            duration.to_string(),
+        ));
+    }
+
+    if let Some(timeout) = params.outgoing_connect_timeout {
+        env.push((
+            AGENT_OUTGOING_CONNECT_TIMEOUT_ENV.to_string(),
+            timeout.to_string(),
+        ));
+    }
+
+    if params.outgoing_connect_retries > 0 {
+        env.push((
+            AGENT_OUTGOING_CONNECT_RETRIES_ENV.to_string(),
+            params.outgoing_connect_retries.to_string(),
+        ));
+    }
+
+    if let Some(quic_cert) = params.quic_cert.as_ref() {
+        env.push((
+            AGENT_TRANSPORT_ENV.to_string(),
+            AgentTransport::Quic.to_string(),
+        ));
+        env.push((AGENT_QUIC_CERT_ENV.to_string(), quic_cert.cert_pem.clone()));
+        env.push((AGENT_QUIC_KEY_ENV.to_string(), quic_cert.key_pem.clone()));
+    }
+
     env.into_iter()
         .chain(
             params
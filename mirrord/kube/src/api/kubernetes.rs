@@ -10,17 +10,18 @@ use kube::{
     Api, Client, Config, Discovery,
 };
 use mirrord_config::{
-    agent::AgentConfig,
+    agent::{AgentConfig, AgentImageConfig, AgentTransport},
     feature::network::incoming::IncomingMode,
     target::{Target, TargetConfig},
     LayerConfig,
 };
 use mirrord_progress::Progress;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 use crate::{
     api::{
+        cilium::CiliumConfig,
         container::{
             ephemeral::EphemeralTargetedVariant,
             job::{JobTargetedVariant, JobVariant},
@@ -28,6 +29,8 @@ use crate::{
             targetless::Targetless,
             ContainerApi, ContainerParams,
         },
+        proxy,
+        quic::AgentQuicCert,
         runtime::{RuntimeData, RuntimeDataProvider},
     },
     error::{KubeApiError, Result},
@@ -35,6 +38,10 @@ use crate::{
 
 pub mod rollout;
 
+/// Seconds the agent waits past `session.max_duration` before it ends the session itself, so the
+/// local application can be stopped gracefully first.
+const AGENT_SESSION_EXPIRY_GRACE: u64 = 30;
+
 pub struct KubernetesAPI {
     client: Client,
     agent: AgentConfig,
@@ -140,6 +147,9 @@ impl KubernetesAPI {
     }
 
     /// Connects to the agent using kube's [`Api::portforward`].
+    ///
+    /// Agents that accept QUIC connections ([`AgentKubernetesConnectInfo::quic_cert`]) are
+    /// connected to directly, as the port-forward can't carry UDP.
     #[cfg(not(feature = "incluster"))]
     pub async fn create_connection(
         &self,
@@ -152,6 +162,33 @@ impl KubernetesAPI {
 
         let pod_api: Api<Pod> =
             get_k8s_resource_api(&self.client, connect_info.namespace.as_deref());
+
+        if let Some(cert_pem) = connect_info.quic_cert.as_deref() {
+            use std::{net::IpAddr, time::Duration};
+
+            let pod = pod_api.get(&connect_info.pod_name).await?;
+            let pod_ip = pod
+                .status
+                .as_ref()
+                .and_then(|status| status.pod_ip.as_ref())
+                .ok_or_else(|| KubeApiError::missing_field(&pod, ".status.podIP"))?
+                .parse::<IpAddr>()
+                .map_err(|e| KubeApiError::invalid_value(&pod, "status.podIp", e))?;
+            trace!(
+                "connecting to pod {pod_ip}:{} over QUIC",
+                connect_info.agent_port
+            );
+
+            let stream = tokio::time::timeout(
+                Duration::from_secs(self.agent.startup_timeout),
+                super::quic::connect((pod_ip, connect_info.agent_port).into(), cert_pem),
+            )
+            .await
+            .map_err(|_| KubeApiError::AgentReadyTimeout)??;
+
+            return Ok(Box::new(stream));
+        }
+
         let retry_strategy = ExponentialBackoff::from_millis(10).map(jitter).take(3);
         let ports = &[connect_info.agent_port];
         let mut port_forwarder = Retry::spawn(retry_strategy, || {
@@ -195,6 +232,56 @@ impl KubernetesAPI {
         Ok((params, runtime_data))
     }
 
+    /// Picks the agent image from [`AgentConfig::arch_images`] for the architecture of the node
+    /// the agent will run on, failing if there is none.
+    ///
+    /// Returns [`None`] when `arch_images` is not set, otherwise a copy of the [`AgentConfig`]
+    /// with the picked image. Targetless agents don't have a node yet, so they get the first
+    /// architecture and are pinned to it with [`ContainerParams::arch`].
+    async fn arch_agent_config(
+        &self,
+        runtime_data: Option<&RuntimeData>,
+        params: &mut ContainerParams,
+    ) -> Result<Option<AgentConfig>> {
+        let Some(arch_images) = self.agent.arch_images.as_ref() else {
+            return Ok(None);
+        };
+
+        let (arch, image) = match runtime_data {
+            Some(runtime_data) => {
+                let arch = runtime_data.node_arch(&self.client).await?;
+                let Some(image) = arch_images.get(&arch) else {
+                    return Err(KubeApiError::UnsupportedNodeArch {
+                        node: runtime_data.node_name.clone(),
+                        arch,
+                        available: arch_images
+                            .keys()
+                            .map(String::as_str)
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    });
+                };
+
+                (arch, image)
+            }
+            None => {
+                let Some((arch, image)) = arch_images.first_key_value() else {
+                    return Ok(None);
+                };
+                params.arch = Some(arch.clone());
+
+                (arch.clone(), image)
+            }
+        };
+
+        debug!(%arch, %image, "Selected agent image for the node architecture");
+
+        let mut agent = self.agent.clone();
+        agent.image = AgentImageConfig(image.clone());
+
+        Ok(Some(agent))
+    }
+
     /// # Params
     ///
     /// * `config` - if passed, will be checked against cluster setup
@@ -212,7 +299,25 @@ impl KubernetesAPI {
     where
         P: Progress + Send + Sync,
     {
-        let (params, runtime_data) = self.create_agent_params(target, tls_cert).await?;
+        let (mut params, runtime_data) = self.create_agent_params(target, tls_cert).await?;
+        let arch_agent = self
+            .arch_agent_config(runtime_data.as_ref(), &mut params)
+            .await?;
+        let agent = arch_agent.as_ref().unwrap_or(&self.agent);
+
+        params.max_session_duration = config
+            .and_then(|config| config.session.max_duration)
+            .map(|duration| duration + AGENT_SESSION_EXPIRY_GRACE);
+
+        if let Some(config) = config {
+            params.outgoing_connect_timeout = config.feature.network.outgoing.connect_timeout;
+            params.outgoing_connect_retries = config.feature.network.outgoing.retries;
+        }
+
+        // Agents created for the operator are reached over its TLS connections.
+        if agent.transport == AgentTransport::Quic && params.tls_cert.is_none() {
+            params.quic_cert = Some(AgentQuicCert::generate()?);
+        }
 
         let incoming_mode = config.map(|config| config.feature.network.incoming.mode);
         let is_mesh = runtime_data
@@ -228,25 +333,35 @@ impl KubernetesAPI {
             );
         }
 
+        if matches!(incoming_mode, Some(IncomingMode::Steal)) {
+            let cilium_warning = CiliumConfig::fetch(&self.client)
+                .await
+                .and_then(|cilium| cilium.steal_warning());
+
+            if let Some(warning) = cilium_warning {
+                progress.warning(&warning);
+            }
+        }
+
         info!(?params, "Spawning new agent");
 
-        let agent_connect_info = match (runtime_data, self.agent.ephemeral) {
+        let agent_connect_info = match (runtime_data, agent.ephemeral) {
             (None, false) => {
-                let variant = JobVariant::new(&self.agent, &params);
+                let variant = JobVariant::new(agent, &params);
 
                 Targetless::new(&self.client, &variant)
                     .create_agent(progress)
                     .await?
             }
             (Some(runtime_data), false) => {
-                let variant = JobTargetedVariant::new(&self.agent, &params, &runtime_data);
+                let variant = JobTargetedVariant::new(agent, &params, &runtime_data);
 
                 Targeted::new(&self.client, &runtime_data, &variant)
                     .create_agent(progress)
                     .await?
             }
             (Some(runtime_data), true) => {
-                let variant = EphemeralTargetedVariant::new(&self.agent, &params, &runtime_data);
+                let variant = EphemeralTargetedVariant::new(agent, &params, &runtime_data);
 
                 Targeted::new(&self.client, &runtime_data, &variant)
                     .create_agent(progress)
@@ -283,6 +398,14 @@ pub struct AgentKubernetesConnectInfo {
     pub agent_port: u16,
     pub namespace: Option<String>,
     pub agent_version: Option<String>,
+    /// Name of the agent container in [`Self::pod_name`], the pod is the target's one when the
+    /// agent is an ephemeral container.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+    /// PEM-encoded certificate of the agent, present when it accepts QUIC connections
+    /// ([`AgentTransport::Quic`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quic_cert: Option<String>,
 }
 
 pub async fn create_kube_api<P>(
@@ -290,6 +413,22 @@ pub async fn create_kube_api<P>(
     kubeconfig: Option<P>,
     kube_context: Option<String>,
 ) -> Result<Client>
+where
+    P: AsRef<str>,
+{
+    let config = create_kube_config(accept_invalid_certificates, kubeconfig, kube_context).await?;
+    Client::try_from(config).map_err(KubeApiError::from)
+}
+
+/// Loads the [`Config`] used by [`create_kube_api`].
+///
+/// When the kubeconfig does not set a `proxy-url` for the cluster, the proxy is taken from the
+/// environment, see [`proxy::proxy_for`].
+pub async fn create_kube_config<P>(
+    accept_invalid_certificates: bool,
+    kubeconfig: Option<P>,
+    kube_context: Option<String>,
+) -> Result<Config>
 where
     P: AsRef<str>,
 {
@@ -312,7 +451,27 @@ where
         Config::infer().await?
     };
     config.accept_invalid_certs = accept_invalid_certificates;
-    Client::try_from(config).map_err(KubeApiError::from)
+
+    if config.proxy_url.is_none() {
+        let proxy_url = config
+            .cluster_url
+            .host()
+            .and_then(|host| proxy::proxy_for(config.cluster_url.scheme_str(), host));
+
+        config.proxy_url = proxy_url.and_then(|url| {
+            url.parse()
+                .inspect_err(|error| {
+                    warn!(
+                        %error,
+                        proxy_url = proxy::redact_credentials(&url),
+                        "Ignoring invalid proxy URL"
+                    )
+                })
+                .ok()
+        });
+    }
+
+    Ok(config)
 }
 
 #[tracing::instrument(level = "debug", skip(client))]
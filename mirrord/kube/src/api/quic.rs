@@ -0,0 +1,119 @@
+//! QUIC connections to the agent, used with
+//! [`AgentTransport::Quic`](mirrord_config::agent::AgentTransport::Quic).
+//!
+//! The agent is started with a self-signed certificate that we generate for it, and we accept
+//! only that certificate when connecting.
+
+use std::{
+    fmt, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use mirrord_protocol::AGENT_QUIC_ALPN;
+use quinn::{
+    crypto::rustls::{NoInitialCipherSuite, QuicClientConfig},
+    ClientConfig, Endpoint, RecvStream, SendStream, TransportConfig,
+};
+use rustls::RootCertStore;
+use thiserror::Error;
+use tokio::io::Join;
+
+/// Name for which the agent's certificate is issued.
+const AGENT_SERVER_NAME: &str = "mirrord-agent";
+
+/// Keeps the connection from timing out when neither side has anything to say.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Stream on which we talk with the agent.
+pub type QuicStream = Join<RecvStream, SendStream>;
+
+#[derive(Debug, Error)]
+pub enum AgentQuicError {
+    #[error("failed to generate the agent's certificate: {0}")]
+    Certificate(#[from] rcgen::Error),
+
+    #[error("rustls failed: {0}")]
+    Rustls(#[from] rustls::Error),
+
+    #[error("{0}")]
+    NoInitialCipherSuite(#[from] NoInitialCipherSuite),
+
+    #[error("failed to start connecting: {0}")]
+    Connect(#[from] quinn::ConnectError),
+
+    #[error("connection failed: {0}")]
+    Connection(#[from] quinn::ConnectionError),
+
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+/// Self-signed certificate generated for one agent, passed to it in
+/// [`AGENT_QUIC_CERT_ENV`](mirrord_protocol::AGENT_QUIC_CERT_ENV) and
+/// [`AGENT_QUIC_KEY_ENV`](mirrord_protocol::AGENT_QUIC_KEY_ENV).
+#[derive(Clone)]
+pub struct AgentQuicCert {
+    /// PEM-encoded X509 certificate.
+    pub cert_pem: String,
+    /// PEM-encoded private key of the certificate.
+    pub key_pem: String,
+}
+
+impl AgentQuicCert {
+    pub fn generate() -> Result<Self, AgentQuicError> {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![AGENT_SERVER_NAME.to_string()])?;
+
+        Ok(Self {
+            cert_pem: cert.pem(),
+            key_pem: key_pair.serialize_pem(),
+        })
+    }
+}
+
+/// Doesn't print the private key.
+impl fmt::Debug for AgentQuicCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentQuicCert")
+            .field("cert_pem", &self.cert_pem)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Connects to the agent at the given address, accepting only the given PEM-encoded
+/// certificate.
+///
+/// The agent reads our messages from the first bidirectional stream we open.
+pub async fn connect(addr: SocketAddr, cert_pem: &str) -> Result<QuicStream, AgentQuicError> {
+    let mut root_store = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut cert_pem.as_bytes()) {
+        root_store.add(cert?)?;
+    }
+
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![AGENT_QUIC_ALPN.to_vec()];
+
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+
+    let mut config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+    config.transport_config(Arc::new(transport));
+
+    let bind_addr = if addr.is_ipv4() {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+    } else {
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+    };
+    let endpoint = Endpoint::client(bind_addr)?;
+
+    let connection = endpoint
+        .connect_with(config, addr, AGENT_SERVER_NAME)?
+        .await?;
+    let (send, recv) = connection.open_bi().await?;
+
+    Ok(tokio::io::join(recv, send))
+}
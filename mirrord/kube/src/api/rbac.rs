@@ -0,0 +1,381 @@
+//! Checks whether the current user has the Kubernetes permissions that mirrord needs to run a
+//! session without the operator, using
+//! [`SelfSubjectAccessReview`](https://kubernetes.io/docs/reference/access-authn-authz/authorization/#checking-api-access)s.
+
+use std::fmt;
+
+use k8s_openapi::api::authorization::v1::{
+    ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+};
+use kube::{api::PostParams, Api, Client};
+use mirrord_config::{
+    target::{cron_job::WaitForRun, Target},
+    LayerConfig,
+};
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// A single permission that mirrord needs, i.e. a `verb` on a `resource` in a `namespace`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RequiredPermission {
+    pub verb: &'static str,
+    /// API group of the resource, empty for the core group.
+    pub group: &'static str,
+    pub resource: &'static str,
+    pub subresource: Option<&'static str>,
+    pub namespace: String,
+    /// What mirrord needs this permission for.
+    pub reason: &'static str,
+}
+
+impl RequiredPermission {
+    fn new(
+        verb: &'static str,
+        (group, resource, subresource): (&'static str, &'static str, Option<&'static str>),
+        namespace: &str,
+        reason: &'static str,
+    ) -> Self {
+        Self {
+            verb,
+            group,
+            resource,
+            subresource,
+            namespace: namespace.to_string(),
+            reason,
+        }
+    }
+
+    /// Whether the current user has this permission.
+    async fn is_allowed(&self, client: &Client) -> Result<bool> {
+        let review = SelfSubjectAccessReview {
+            spec: SelfSubjectAccessReviewSpec {
+                resource_attributes: Some(ResourceAttributes {
+                    verb: Some(self.verb.to_string()),
+                    group: Some(self.group.to_string()),
+                    resource: Some(self.resource.to_string()),
+                    subresource: self.subresource.map(ToString::to_string),
+                    namespace: Some(self.namespace.clone()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let review = Api::<SelfSubjectAccessReview>::all(client.clone())
+            .create(&PostParams::default(), &review)
+            .await?;
+
+        Ok(review.status.is_some_and(|status| status.allowed))
+    }
+}
+
+impl fmt::Display for RequiredPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.verb, self.resource)?;
+        if let Some(subresource) = self.subresource {
+            write!(f, "/{subresource}")?;
+        }
+        if !self.group.is_empty() {
+            write!(f, ".{}", self.group)?;
+        }
+
+        write!(f, " in namespace {} ({})", self.namespace, self.reason)
+    }
+}
+
+const PODS: (&str, &str, Option<&str>) = ("", "pods", None);
+const PODS_LOG: (&str, &str, Option<&str>) = ("", "pods", Some("log"));
+const PODS_PORTFORWARD: (&str, &str, Option<&str>) = ("", "pods", Some("portforward"));
+const PODS_EPHEMERAL: (&str, &str, Option<&str>) = ("", "pods", Some("ephemeralcontainers"));
+const JOBS: (&str, &str, Option<&str>) = ("batch", "jobs", None);
+const HPAS: (&str, &str, Option<&str>) = ("autoscaling", "horizontalpodautoscalers", None);
+const SCALED_OBJECTS: (&str, &str, Option<&str>) = ("keda.sh", "scaledobjects", None);
+
+/// Lists the permissions mirrord needs to run a session with the given config without the
+/// operator: resolving the target, spawning the agent (as a job or an ephemeral container) and
+/// port-forwarding to it.
+///
+/// `default_namespace` is used when the config doesn't specify the target or agent namespace.
+pub fn required_permissions(
+    config: &LayerConfig,
+    default_namespace: &str,
+) -> Vec<RequiredPermission> {
+    let target_namespace = config
+        .target
+        .namespace
+        .as_deref()
+        .unwrap_or(default_namespace);
+    let target = config.target.path.as_ref().unwrap_or(&Target::Targetless);
+
+    let mut permissions = vec![];
+
+    let target_resource = match target {
+        Target::Targetless => None,
+        Target::Pod(..) => Some(PODS),
+        Target::Deployment(..) => Some(("apps", "deployments", None)),
+        Target::Rollout(..) => Some(("argoproj.io", "rollouts", None)),
+        Target::Job(..) => Some(JOBS),
+        Target::CronJob(..) => Some(("batch", "cronjobs", None)),
+        Target::StatefulSet(..) => Some(("apps", "statefulsets", None)),
+    };
+    if let Some(resource) = target_resource {
+        permissions.push(RequiredPermission::new(
+            "get",
+            resource,
+            target_namespace,
+            "resolve the target",
+        ));
+    }
+    if !matches!(target, Target::Targetless | Target::Pod(..)) {
+        permissions.push(RequiredPermission::new(
+            "list",
+            PODS,
+            target_namespace,
+            "find the pods of the target",
+        ));
+    }
+
+    if let Some(wait_for_run) = config.target.wait_for_run {
+        let reason = "wait for the run of the CronJob";
+        permissions.extend([
+            RequiredPermission::new("list", JOBS, target_namespace, reason),
+            RequiredPermission::new("watch", JOBS, target_namespace, reason),
+            RequiredPermission::new("watch", PODS, target_namespace, reason),
+        ]);
+        if wait_for_run == WaitForRun::Trigger {
+            permissions.push(RequiredPermission::new(
+                "create",
+                JOBS,
+                target_namespace,
+                "start a run of the CronJob",
+            ));
+        }
+    }
+
+    if config.feature.target_logs {
+        permissions.push(RequiredPermission::new(
+            "get",
+            PODS_LOG,
+            target_namespace,
+            "stream the logs of the target",
+        ));
+    }
+
+    let scaled_target = matches!(
+        target,
+        Target::Deployment(..) | Target::Rollout(..) | Target::StatefulSet(..)
+    );
+    if config.feature.pause_autoscaling && scaled_target {
+        let reason = "pause the autoscalers of the target";
+        for resource in [HPAS, SCALED_OBJECTS] {
+            permissions.extend(
+                ["get", "list", "patch"]
+                    .map(|verb| RequiredPermission::new(verb, resource, target_namespace, reason)),
+            );
+        }
+    }
+
+    // Ephemeral agents live in the target pod, job agents in the agent namespace.
+    let ephemeral = config.agent.ephemeral && !matches!(target, Target::Targetless);
+    let agent_namespace = if ephemeral {
+        target_namespace
+    } else {
+        config
+            .agent
+            .namespace
+            .as_deref()
+            .unwrap_or(default_namespace)
+    };
+
+    if ephemeral {
+        permissions.extend([
+            RequiredPermission::new(
+                "get",
+                PODS_EPHEMERAL,
+                agent_namespace,
+                "add the agent ephemeral container",
+            ),
+            RequiredPermission::new(
+                "update",
+                PODS_EPHEMERAL,
+                agent_namespace,
+                "add the agent ephemeral container",
+            ),
+        ]);
+    } else {
+        permissions.push(RequiredPermission::new(
+            "create",
+            JOBS,
+            agent_namespace,
+            "spawn the agent job",
+        ));
+    }
+
+    permissions.extend([
+        RequiredPermission::new("get", PODS, agent_namespace, "wait for the agent"),
+        RequiredPermission::new("list", PODS, agent_namespace, "wait for the agent"),
+        RequiredPermission::new("watch", PODS, agent_namespace, "wait for the agent"),
+        RequiredPermission::new("get", PODS_LOG, agent_namespace, "wait for the agent"),
+        RequiredPermission::new(
+            "create",
+            PODS_PORTFORWARD,
+            agent_namespace,
+            "connect to the agent",
+        ),
+    ]);
+
+    permissions
+}
+
+/// Checks every permission with a [`SelfSubjectAccessReview`] and returns the ones that the
+/// current user is missing.
+#[tracing::instrument(level = "trace", skip(client), ret, err)]
+pub async fn missing_permissions(
+    client: &Client,
+    permissions: Vec<RequiredPermission>,
+) -> Result<Vec<RequiredPermission>> {
+    let mut missing = vec![];
+
+    for permission in permissions {
+        if !permission.is_allowed(client).await? {
+            missing.push(permission);
+        }
+    }
+
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use mirrord_config::{
+        config::{ConfigContext, MirrordConfig},
+        LayerFileConfig,
+    };
+
+    use super::*;
+
+    fn config(target: &str, ephemeral: bool) -> LayerConfig {
+        let mut config = LayerFileConfig::default()
+            .generate_config(&mut ConfigContext::default())
+            .unwrap();
+        config.target.path = Some(target.parse().unwrap());
+        config.agent.ephemeral = ephemeral;
+        config.agent.namespace = Some("mirrord".to_string());
+        config
+    }
+
+    /// Whether `permissions` contain `verb` on `resource` (`resource/subresource`) in
+    /// `namespace`.
+    fn contains(
+        permissions: &[RequiredPermission],
+        verb: &str,
+        resource: &str,
+        namespace: &str,
+    ) -> bool {
+        permissions.iter().any(|permission| {
+            let name = match permission.subresource {
+                Some(subresource) => format!("{}/{subresource}", permission.resource),
+                None => permission.resource.to_string(),
+            };
+
+            permission.verb == verb && name == resource && permission.namespace == namespace
+        })
+    }
+
+    #[test]
+    fn job_agent_permissions() {
+        let permissions = required_permissions(&config("deploy/app", false), "default");
+
+        assert!(contains(&permissions, "get", "deployments", "default"));
+        assert!(contains(&permissions, "list", "pods", "default"));
+        assert!(contains(&permissions, "create", "jobs", "mirrord"));
+        assert!(contains(
+            &permissions,
+            "create",
+            "pods/portforward",
+            "mirrord"
+        ));
+        assert!(!contains(
+            &permissions,
+            "update",
+            "pods/ephemeralcontainers",
+            "default"
+        ));
+    }
+
+    #[test]
+    fn target_logs_permissions() {
+        let mut config = config("deploy/app", false);
+        assert!(!contains(
+            &required_permissions(&config, "default"),
+            "get",
+            "pods/log",
+            "default"
+        ));
+
+        config.feature.target_logs = true;
+        assert!(contains(
+            &required_permissions(&config, "default"),
+            "get",
+            "pods/log",
+            "default"
+        ));
+    }
+
+    #[test]
+    fn pause_autoscaling_permissions() {
+        let mut deployment = config("deploy/app", false);
+        deployment.feature.pause_autoscaling = true;
+        let permissions = required_permissions(&deployment, "default");
+        assert!(contains(
+            &permissions,
+            "patch",
+            "horizontalpodautoscalers",
+            "default"
+        ));
+        assert!(contains(&permissions, "patch", "scaledobjects", "default"));
+
+        // Pods are not scaled.
+        let mut pod = config("pod/app", false);
+        pod.feature.pause_autoscaling = true;
+        assert!(!contains(
+            &required_permissions(&pod, "default"),
+            "patch",
+            "horizontalpodautoscalers",
+            "default"
+        ));
+    }
+
+    #[test]
+    fn ephemeral_agent_permissions() {
+        let permissions = required_permissions(&config("pod/app", true), "default");
+
+        assert!(contains(&permissions, "get", "pods", "default"));
+        assert!(contains(
+            &permissions,
+            "update",
+            "pods/ephemeralcontainers",
+            "default"
+        ));
+        assert!(contains(
+            &permissions,
+            "create",
+            "pods/portforward",
+            "default"
+        ));
+        assert!(!contains(&permissions, "create", "jobs", "mirrord"));
+    }
+
+    #[test]
+    fn display() {
+        let permission =
+            RequiredPermission::new("create", JOBS, "default", "spawn the agent job").to_string();
+
+        assert_eq!(
+            permission,
+            "create jobs.batch in namespace default (spawn the agent job)"
+        );
+    }
+}
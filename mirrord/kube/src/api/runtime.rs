@@ -6,11 +6,15 @@ use std::{
     str::FromStr,
 };
 
+use futures::{AsyncBufReadExt, Stream};
 use k8s_openapi::{
     api::core::v1::{Node, Pod},
     NamespaceResourceScope,
 };
-use kube::{api::ListParams, Api, Client, Resource};
+use kube::{
+    api::{ListParams, LogParams},
+    Api, Client, Resource,
+};
 use mirrord_config::target::Target;
 use mirrord_protocol::MeshVendor;
 use serde::de::DeserializeOwned;
@@ -62,6 +66,9 @@ impl Display for ContainerRuntime {
     }
 }
 
+/// Well-known label with the architecture of a node, e.g. `amd64`.
+pub const NODE_ARCH_LABEL: &str = "kubernetes.io/arch";
+
 #[derive(Debug)]
 pub struct RuntimeData {
     pub pod_name: String,
@@ -165,6 +172,26 @@ impl RuntimeData {
         })
     }
 
+    /// Architecture of the node where the target pod runs, e.g. `amd64`.
+    #[tracing::instrument(level = "trace", skip(client), ret, err)]
+    pub async fn node_arch(&self, client: &kube::Client) -> Result<String> {
+        let node_api: Api<Node> = Api::all(client.clone());
+        let node = node_api.get(&self.node_name).await?;
+
+        node.status
+            .as_ref()
+            .and_then(|status| status.node_info.as_ref())
+            .map(|node_info| node_info.architecture.clone())
+            .or_else(|| {
+                node.metadata
+                    .labels
+                    .as_ref()
+                    .and_then(|labels| labels.get(NODE_ARCH_LABEL))
+                    .cloned()
+            })
+            .ok_or_else(|| KubeApiError::missing_field(&node, ".status.nodeInfo.architecture"))
+    }
+
     #[tracing::instrument(level = "trace", skip(client), ret)]
     pub async fn check_node(&self, client: &kube::Client) -> NodeCheck {
         let node_api: Api<Node> = Api::all(client.clone());
@@ -207,6 +234,30 @@ impl RuntimeData {
             NodeCheck::Success
         }
     }
+
+    /// Follows the logs of the target container, starting from the moment of the call. The
+    /// stream ends when the container stops.
+    #[tracing::instrument(level = "trace", skip(client), err)]
+    pub async fn follow_logs(
+        &self,
+        client: &Client,
+    ) -> Result<impl Stream<Item = std::io::Result<String>>> {
+        let pod_api: Api<Pod> = get_k8s_resource_api(client, self.pod_namespace.as_deref());
+
+        let logs = pod_api
+            .log_stream(
+                &self.pod_name,
+                &LogParams {
+                    follow: true,
+                    container: Some(self.container_name.clone()),
+                    tail_lines: Some(0),
+                    ..LogParams::default()
+                },
+            )
+            .await?;
+
+        Ok(logs.lines())
+    }
 }
 
 #[derive(Debug)]
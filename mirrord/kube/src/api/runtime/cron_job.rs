@@ -1,10 +1,34 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 
-use k8s_openapi::api::batch::v1::CronJob;
-use mirrord_config::target::cron_job::CronJobTarget;
+use futures::StreamExt;
+use k8s_openapi::api::{
+    batch::v1::{CronJob, Job},
+    core::v1::Pod,
+};
+use kube::{
+    api::{ListParams, ObjectMeta, PostParams},
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, Resource, ResourceExt,
+};
+use mirrord_config::target::{
+    cron_job::{CronJobTarget, WaitForRun},
+    pod::PodTarget,
+    Target,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use tokio::pin;
+use tracing::{debug, warn};
 
-use super::RuntimeDataFromLabels;
-use crate::error::{KubeApiError, Result};
+use super::{RuntimeData, RuntimeDataFromLabels};
+use crate::{
+    api::kubernetes::get_k8s_resource_api,
+    error::{KubeApiError, Result},
+};
+
+/// Annotation the CronJob controller (and `kubectl create job --from`) uses to tell apart the Jobs
+/// that were started by hand.
+const INSTANTIATE_ANNOTATION: &str = "cronjob.kubernetes.io/instantiate";
 
 impl RuntimeDataFromLabels for CronJobTarget {
     type Resource = CronJob;
@@ -38,3 +62,216 @@ impl RuntimeDataFromLabels for CronJobTarget {
             })
     }
 }
+
+/// A run of a CronJob, the Job it started and the pod we target, see
+/// [`TargetConfig::wait_for_run`](mirrord_config::target::TargetConfig::wait_for_run).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CronJobRun {
+    pub job: String,
+    pub namespace: Option<String>,
+    pub pod: String,
+    pub container: String,
+}
+
+impl CronJobRun {
+    /// Gets the next Job of the CronJob (or starts one, with [`WaitForRun::Trigger`]), and waits
+    /// until its pod runs.
+    #[tracing::instrument(level = "trace", skip(client), ret, err)]
+    pub async fn start(
+        client: &Client,
+        target: &CronJobTarget,
+        namespace: Option<&str>,
+        wait_for_run: WaitForRun,
+    ) -> Result<Self> {
+        let cron_job_api: Api<CronJob> = get_k8s_resource_api(client, namespace);
+        let cron_job = cron_job_api.get(&target.cron_job).await?;
+
+        let job_api: Api<Job> = get_k8s_resource_api(client, namespace);
+        let job = match wait_for_run {
+            WaitForRun::Next => Self::next_job(&job_api, &cron_job).await?,
+            WaitForRun::Trigger => Self::trigger_job(&job_api, &cron_job).await?,
+        };
+
+        let pod_api: Api<Pod> = get_k8s_resource_api(client, namespace);
+        let runtime_data = Self::running_pod(&pod_api, &job, target.container.as_deref()).await?;
+
+        Ok(Self {
+            job,
+            namespace: namespace.map(ToString::to_string),
+            pod: runtime_data.pod_name,
+            container: runtime_data.container_name,
+        })
+    }
+
+    /// The pod of this run, as a [`Target`].
+    pub fn target(&self) -> Target {
+        Target::Pod(PodTarget {
+            pod: self.pod.clone(),
+            container: Some(self.container.clone()),
+        })
+    }
+
+    /// Waits until the Job of this run finishes, returns whether it succeeded.
+    #[tracing::instrument(level = "trace", skip(client), ret, err)]
+    pub async fn finished(&self, client: &Client) -> Result<bool> {
+        let job_api: Api<Job> = get_k8s_resource_api(client, self.namespace.as_deref());
+        let watcher_config =
+            watcher::Config::default().fields(&format!("metadata.name={}", self.job));
+
+        let stream = watcher(job_api, watcher_config)
+            .default_backoff()
+            .applied_objects();
+        pin!(stream);
+
+        while let Some(job) = stream.next().await {
+            let job = match job {
+                Ok(job) => job,
+                Err(error) => {
+                    warn!(%error, job = %self.job, "Failed to watch the Job, retrying");
+                    continue;
+                }
+            };
+
+            if let Some(succeeded) = Self::job_result(&job) {
+                return Ok(succeeded);
+            }
+        }
+
+        Err(KubeApiError::CronJobRun(format!(
+            "stopped watching Job `{}` before it finished",
+            self.job
+        )))
+    }
+
+    /// [`None`] while the Job runs, otherwise whether it succeeded.
+    fn job_result(job: &Job) -> Option<bool> {
+        job.status
+            .as_ref()?
+            .conditions
+            .as_ref()?
+            .iter()
+            .filter(|condition| condition.status == "True")
+            .find_map(|condition| match condition.type_.as_str() {
+                "Complete" => Some(true),
+                "Failed" => Some(false),
+                _ => None,
+            })
+    }
+
+    /// Whether `job` was started by the CronJob with the given `uid`.
+    fn is_owned_by(job: &Job, uid: &str) -> bool {
+        job.owner_references()
+            .iter()
+            .any(|owner| owner.kind == "CronJob" && owner.uid == uid)
+    }
+
+    /// Waits for the CronJob to start a Job on schedule, ignoring the ones it already started.
+    async fn next_job(job_api: &Api<Job>, cron_job: &CronJob) -> Result<String> {
+        let uid = cron_job
+            .uid()
+            .ok_or_else(|| KubeApiError::missing_field(cron_job, ".metadata.uid"))?;
+
+        let existing = job_api
+            .list(&ListParams::default())
+            .await?
+            .into_iter()
+            .filter(|job| Self::is_owned_by(job, &uid))
+            .map(|job| job.name_any())
+            .collect::<HashSet<_>>();
+
+        let stream = watcher(job_api.clone(), Default::default())
+            .default_backoff()
+            .applied_objects();
+        pin!(stream);
+
+        while let Some(job) = stream.next().await {
+            let job = match job {
+                Ok(job) => job,
+                Err(error) => {
+                    warn!(%error, "Failed to watch Jobs, retrying");
+                    continue;
+                }
+            };
+
+            if Self::is_owned_by(&job, &uid) && !existing.contains(&job.name_any()) {
+                debug!(job = %job.name_any(), "CronJob started a Job");
+                return Ok(job.name_any());
+            }
+        }
+
+        Err(KubeApiError::CronJobRun(format!(
+            "stopped watching Jobs before the next run of CronJob `{}`",
+            cron_job.name_any()
+        )))
+    }
+
+    /// Starts a Job from the CronJob's template, the same way `kubectl create job --from` does.
+    async fn trigger_job(job_api: &Api<Job>, cron_job: &CronJob) -> Result<String> {
+        let template = cron_job
+            .spec
+            .as_ref()
+            .map(|spec| &spec.job_template)
+            .ok_or_else(|| KubeApiError::missing_field(cron_job, ".spec"))?;
+
+        // Job names end up in the `job-name` label, so they can't be longer than 63 characters.
+        let suffix = Alphanumeric
+            .sample_string(&mut rand::thread_rng(), 5)
+            .to_lowercase();
+        let prefix = cron_job.name_any().chars().take(49).collect::<String>();
+        let name = format!("{prefix}-mirrord-{suffix}");
+
+        let template_meta = template.metadata.clone().unwrap_or_default();
+        let mut annotations = template_meta.annotations.unwrap_or_default();
+        annotations.insert(INSTANTIATE_ANNOTATION.to_string(), "manual".to_string());
+
+        let job = Job {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                labels: template_meta.labels,
+                annotations: Some(annotations),
+                owner_references: cron_job.controller_owner_ref(&()).map(|owner| vec![owner]),
+                ..Default::default()
+            },
+            spec: template.spec.clone(),
+            ..Default::default()
+        };
+
+        job_api.create(&PostParams::default(), &job).await?;
+        debug!(job = %name, "Started a Job from the CronJob");
+
+        Ok(name)
+    }
+
+    /// Waits for the pod of the Job to run the target container.
+    async fn running_pod(
+        pod_api: &Api<Pod>,
+        job: &str,
+        container: Option<&str>,
+    ) -> Result<RuntimeData> {
+        let watcher_config = watcher::Config::default().labels(&format!("job-name={job}"));
+
+        let stream = watcher(pod_api.clone(), watcher_config)
+            .default_backoff()
+            .applied_objects();
+        pin!(stream);
+
+        while let Some(pod) = stream.next().await {
+            let pod = match pod {
+                Ok(pod) => pod,
+                Err(error) => {
+                    warn!(%error, job, "Failed to watch the pods of the Job, retrying");
+                    continue;
+                }
+            };
+
+            match RuntimeData::from_pod(&pod, container) {
+                Ok(runtime_data) => return Ok(runtime_data),
+                Err(error) => debug!(%error, job, "Pod of the Job is not ready yet"),
+            }
+        }
+
+        Err(KubeApiError::CronJobRun(format!(
+            "stopped watching the pods of Job `{job}` before one was running"
+        )))
+    }
+}
@@ -3,6 +3,8 @@ use std::fmt;
 use kube::Resource;
 use thiserror::Error;
 
+use crate::api::quic::AgentQuicError;
+
 pub type Result<T, E = KubeApiError> = std::result::Result<T, E>;
 
 #[derive(Debug, Error)]
@@ -25,6 +27,9 @@ pub enum KubeApiError {
     #[error("Port not found in port forward")]
     PortForwardFailed,
 
+    #[error("QUIC transport to agent failed: {0}")]
+    AgentQuic(#[from] AgentQuicError),
+
     /// This error should never happen, but has to exist if we don't want to unwrap.
     #[error("None runtime data for non-targetless agent. This is a bug.")]
     MissingRuntimeData,
@@ -48,6 +53,21 @@ pub enum KubeApiError {
 
     #[error("Agent Job was created, but Pod is not running")]
     AgentPodNotRunning,
+
+    /// Failed to get the Job of a CronJob target with
+    /// [`TargetConfig::wait_for_run`](mirrord_config::target::TargetConfig::wait_for_run).
+    #[error("Failed to get the run of the CronJob: {0}")]
+    CronJobRun(String),
+
+    #[error(
+        "Node `{node}` has the `{arch}` architecture, but `agent.arch_images` has no image for it \
+        (available: {available})"
+    )]
+    UnsupportedNodeArch {
+        node: String,
+        arch: String,
+        available: String,
+    },
 }
 
 impl KubeApiError {
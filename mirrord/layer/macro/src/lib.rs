@@ -109,6 +109,9 @@ pub fn hook_fn(
 }
 
 /// Same as above but calls the original function if detour guard is active.
+///
+/// Calls that are not bypassed are timed with `HookTimer`, which also attributes the `Bypass`es
+/// returned during the call to the hook, see `mirrord-layer`'s `hook_stats`.
 #[proc_macro_attribute]
 pub fn hook_guard_fn(
     _args: proc_macro::TokenStream,
@@ -132,6 +135,9 @@ pub fn hook_guard_fn(
             Ident::new(&name, Span::call_site())
         });
 
+        // Name used for the hook in `HookTimer`, e.g. `"close"`.
+        let hook_name = ident_string.trim_end_matches("_detour").to_string();
+
         let unsafety = signature.unsafety;
         let abi = signature.abi;
 
@@ -190,6 +196,7 @@ pub fn hook_guard_fn(
                 if __bypass.is_none() {
                     return #static_name (#fn_arg_names);
                 }
+                let __timer = crate::hook_stats::HookTimer::start(#hook_name);
             ))
             .unwrap();
         modified_function.block.stmts.extend(statements);
@@ -9,7 +9,9 @@ use core::{
     convert,
     ops::{FromResidual, Residual, Try},
 };
-use std::{cell::RefCell, ops::Deref, os::unix::prelude::*, path::PathBuf, sync::OnceLock};
+use std::{
+    cell::RefCell, ffi::CString, ops::Deref, os::unix::prelude::*, path::PathBuf, sync::OnceLock,
+};
 
 #[cfg(target_os = "macos")]
 use libc::c_char;
@@ -152,6 +154,16 @@ pub(crate) enum Bypass {
     /// File [`PathBuf`] should be ignored (used for tests).
     IgnoredFile(PathBuf),
 
+    /// The path is under one of the
+    /// [`FsConfig::local_override`](mirrord_config::feature::fs::FsConfig::local_override)
+    /// prefixes, so do the operation locally, on this path instead.
+    LocalOverride(CString),
+
+    /// The path is a remote symlink to this path, which we don't handle remotely, so do the
+    /// operation locally, on this path instead, see
+    /// [`ExperimentalConfig::resolve_remote_symlinks`](mirrord_config::experimental::ExperimentalConfig::resolve_remote_symlinks).
+    ResolvedLocal(CString),
+
     /// Some operations only handle absolute [`PathBuf`]s.
     RelativePath(PathBuf),
 
@@ -204,6 +216,53 @@ pub(crate) enum Bypass {
     /// Hostname should be resolved locally.
     /// Currently this is the case only when the layer operates in the `trace only` mode.
     LocalHostname,
+
+    /// The agent can't lock remote files, so the lock is taken on the local file instead.
+    RemoteLocksUnsupported,
+
+    /// The operation would be remote, but we're in [`simulation`](crate::simulation) mode.
+    Simulated,
+}
+
+impl Bypass {
+    /// Name of the variant, without its data, used to count the bypasses in
+    /// [`hook_stats`](crate::hook_stats).
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Port(..) => "Port",
+            Self::Type(..) => "Type",
+            Self::Domain(..) => "Domain",
+            Self::UnixSocket(..) => "UnixSocket",
+            Self::LocalFdNotFound(..) => "LocalFdNotFound",
+            Self::LocalDirStreamNotFound(..) => "LocalDirStreamNotFound",
+            Self::AddressConversion => "AddressConversion",
+            Self::InvalidState(..) => "InvalidState",
+            Self::CStrConversion => "CStrConversion",
+            #[cfg(target_os = "macos")]
+            Self::FileOperationInMirrordBinTempDir(..) => "FileOperationInMirrordBinTempDir",
+            Self::IgnoredFile(..) => "IgnoredFile",
+            Self::LocalOverride(..) => "LocalOverride",
+            Self::ResolvedLocal(..) => "ResolvedLocal",
+            Self::RelativePath(..) => "RelativePath",
+            Self::ReadOnly(..) => "ReadOnly",
+            Self::EmptyBuffer => "EmptyBuffer",
+            Self::EmptyOption => "EmptyOption",
+            Self::NullNode => "NullNode",
+            #[cfg(target_os = "macos")]
+            Self::NoSipDetected(..) => "NoSipDetected",
+            #[cfg(target_os = "macos")]
+            Self::ExecOnNonExistingFile(..) => "ExecOnNonExistingFile",
+            #[cfg(target_os = "macos")]
+            Self::TooManyArgs => "TooManyArgs",
+            Self::IgnoreLocalhost(..) => "IgnoreLocalhost",
+            Self::BindWhenTargetless => "BindWhenTargetless",
+            Self::DisabledOutgoing => "DisabledOutgoing",
+            Self::DisabledIncoming => "DisabledIncoming",
+            Self::LocalHostname => "LocalHostname",
+            Self::RemoteLocksUnsupported => "RemoteLocksUnsupported",
+            Self::Simulated => "Simulated",
+        }
+    }
 }
 
 /// [`ControlFlow`](std::ops::ControlFlow)-like enum to be used by hooks.
@@ -347,10 +406,15 @@ where
     /// - `Success` -> Return the contained value.
     /// - `Bypass` -> Call the bypass and return its value.
     /// - `Error` -> Convert to libc value and return it.
+    ///
+    /// Bypasses are counted in [`hook_stats`](crate::hook_stats).
     pub(crate) fn unwrap_or_bypass_with<F: FnOnce(Bypass) -> S>(self, op: F) -> S {
         match self {
             Detour::Success(s) => s,
-            Detour::Bypass(b) => op(b),
+            Detour::Bypass(b) => {
+                crate::hook_stats::record_bypass(&b);
+                op(b)
+            }
             Detour::Error(e) => e.into(),
         }
     }
@@ -360,10 +424,15 @@ where
     /// `Success` -> Return the contained value.
     /// `Bypass` -> Return provided value.
     /// `Error` -> Convert to libc value and return it.
+    ///
+    /// Bypasses are counted in [`hook_stats`](crate::hook_stats).
     pub(crate) fn unwrap_or_bypass(self, value: S) -> S {
         match self {
             Detour::Success(s) => s,
-            Detour::Bypass(_) => value,
+            Detour::Bypass(b) => {
+                crate::hook_stats::record_bypass(&b);
+                value
+            }
             Detour::Error(e) => e.into(),
         }
     }
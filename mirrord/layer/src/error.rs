@@ -4,7 +4,7 @@ use errno::set_errno;
 use ignore_codes::*;
 use libc::{c_char, hostent, DIR, FILE};
 use mirrord_config::config::ConfigError;
-use mirrord_protocol::{ResponseError, SerializationError};
+use mirrord_protocol::{ErrorKindInternal, RemoteIOError, ResponseError, SerializationError};
 #[cfg(target_os = "macos")]
 use mirrord_sip::SipError;
 use thiserror::Error;
@@ -132,6 +132,9 @@ pub(crate) enum LayerError {
     #[error("mirrord-layer: Failed to find export for name `{0}`!")]
     NoExportName(String),
 
+    #[error("mirrord-layer: Hook for `{0}` is disabled in `experimental.disabled_hooks`!")]
+    HookDisabled(String),
+
     #[cfg(target_os = "linux")]
     #[error("mirrord-layer: Failed to find symbol for name `{0}`!")]
     NoSymbolName(String),
@@ -218,6 +221,9 @@ impl From<HookError> for i64 {
             HookError::FileNotFound => {
                 info!("mirrord file not found triggered")
             }
+            HookError::ResponseError(ref err @ ResponseError::SizeLimitExceeded { .. }) => {
+                error!("{err}")
+            }
             HookError::SocketUnsuportedIpv6 => {
                 info!("{fail}")
             }
@@ -242,10 +248,10 @@ impl From<HookError> for i64 {
                 ResponseError::NotFound(_) => libc::ENOENT,
                 ResponseError::NotDirectory(_) => libc::ENOTDIR,
                 ResponseError::NotFile(_) => libc::EISDIR,
-                ResponseError::RemoteIO(io_fail) => io_fail.raw_os_error.unwrap_or(libc::EIO),
+                ResponseError::RemoteIO(io_fail) => remote_errno(&io_fail),
                 ResponseError::Remote(remote) => match remote {
-                    // So far only encountered when trying to make requests from golang.
-                    mirrord_protocol::RemoteError::ConnectTimedOut(_) => libc::ENETUNREACH,
+                    // See `feature.network.outgoing.connect_timeout`.
+                    mirrord_protocol::RemoteError::ConnectTimedOut(_) => libc::ETIMEDOUT,
                     _ => libc::EINVAL,
                 },
                 ResponseError::DnsLookup(dns_fail) => {
@@ -267,6 +273,7 @@ impl From<HookError> for i64 {
                 ResponseError::PortAlreadyStolen(_port) => libc::EINVAL,
                 ResponseError::NotImplemented => libc::EINVAL,
                 ResponseError::StripPrefix(_) => libc::EINVAL,
+                ResponseError::SizeLimitExceeded { .. } => libc::EFBIG,
                 err @ ResponseError::Forbidden { .. } => {
                     graceful_exit!(
                         "Stopping mirrord run. Please adjust your mirrord configuration.\n{err}"
@@ -300,6 +307,41 @@ impl From<HookError> for i64 {
     }
 }
 
+/// Errno of an IO error that happened in the agent.
+///
+/// The agent runs on Linux, so its raw error codes are only meaningful to a Linux layer. Elsewhere,
+/// we go by the kind of the error.
+fn remote_errno(error: &RemoteIOError) -> i32 {
+    if cfg!(target_os = "linux") {
+        return error.raw_os_error.unwrap_or(libc::EIO);
+    }
+
+    match error.kind {
+        ErrorKindInternal::NotFound => libc::ENOENT,
+        ErrorKindInternal::PermissionDenied => libc::EACCES,
+        ErrorKindInternal::ConnectionRefused => libc::ECONNREFUSED,
+        ErrorKindInternal::ConnectionReset => libc::ECONNRESET,
+        ErrorKindInternal::HostUnreachable => libc::EHOSTUNREACH,
+        ErrorKindInternal::NetworkUnreachable => libc::ENETUNREACH,
+        ErrorKindInternal::ConnectionAborted => libc::ECONNABORTED,
+        ErrorKindInternal::NotConnected => libc::ENOTCONN,
+        ErrorKindInternal::AddrInUse => libc::EADDRINUSE,
+        ErrorKindInternal::AddrNotAvailable => libc::EADDRNOTAVAIL,
+        ErrorKindInternal::NetworkDown => libc::ENETDOWN,
+        ErrorKindInternal::BrokenPipe => libc::EPIPE,
+        ErrorKindInternal::AlreadyExists => libc::EEXIST,
+        ErrorKindInternal::WouldBlock => libc::EAGAIN,
+        ErrorKindInternal::NotADirectory => libc::ENOTDIR,
+        ErrorKindInternal::IsADirectory => libc::EISDIR,
+        ErrorKindInternal::DirectoryNotEmpty => libc::ENOTEMPTY,
+        ErrorKindInternal::ReadOnlyFilesystem => libc::EROFS,
+        ErrorKindInternal::InvalidInput => libc::EINVAL,
+        ErrorKindInternal::TimedOut => libc::ETIMEDOUT,
+        ErrorKindInternal::Interrupted => libc::EINTR,
+        _ => libc::EIO,
+    }
+}
+
 impl From<HookError> for isize {
     fn from(fail: HookError) -> Self {
         i64::from(fail) as _
@@ -0,0 +1,411 @@
+#![cfg(target_os = "linux")]
+
+//! Keeps the layer loaded into the processes that launchers start with a clean environment.
+//!
+//! Daemonizing with `setsid` or a double `fork` keeps the env (and the forks are handled by
+//! [`fork_detour`](crate::fork_detour)), but some launchers drop it on the way, e.g. `env -i` or
+//! a supervisor that builds the env of the server from scratch. Others, like `systemd-run`, ask
+//! systemd to start the process, so it's not even a descendant of the application.
+//!
+//! We hook `execve` to put mirrord's env (see [`LayerSetup::env_backup`]) back when it's missing,
+//! and pass it to `systemd-run` with `--setenv`. Each time we do it we warn, because the process
+//! escaped from mirrord and we can't always catch it (e.g. when it's started by a daemon that was
+//! already running).
+//!
+//! The layer can't be loaded into statically linked programs at all. When the internal proxy runs
+//! a proxy server (`feature.network.outgoing.proxy_server`), we point their proxy env vars to it,
+//! so at least their outgoing traffic goes through the cluster.
+//!
+//! [`LayerSetup::env_backup`]: crate::setup::LayerSetup::env_backup
+
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    ptr,
+};
+
+use libc::{c_char, c_int};
+use mirrord_layer_macro::hook_guard_fn;
+use tracing::{info, warn};
+
+use crate::{hooks::HookManager, replace, setup::INJECTION_ENV_VAR};
+
+/// Maximal number of items to expect in argv or envp, if there are more we assume something's
+/// wrong and call `execve` untouched.
+const MAX_ITEMS: usize = 4096;
+
+/// ELF program header type of the program interpreter (the dynamic loader).
+const PT_INTERP: u32 = 3;
+
+/// Proxy env vars set for the static programs: upper and lower case names, and the scheme of the
+/// proxy server.
+const PROXY_ENV: [(&str, &str, &str); 3] = [
+    ("HTTP_PROXY", "http_proxy", "http"),
+    ("HTTPS_PROXY", "https_proxy", "http"),
+    ("ALL_PROXY", "all_proxy", "socks5h"),
+];
+
+pub(crate) unsafe fn enable_exec_hooks(hook_manager: &mut HookManager) {
+    replace!(hook_manager, "execve", execve_detour, FnExecve, FN_EXECVE);
+}
+
+/// Copies a null-terminated array of C strings, like `argv` or `envp`.
+unsafe fn c_string_array(array: *const *const c_char) -> Option<Vec<CString>> {
+    if array.is_null() {
+        return Some(vec![]);
+    }
+
+    let mut items = vec![];
+    for index in 0.. {
+        let item = *array.add(index);
+        if item.is_null() {
+            break;
+        }
+        if index >= MAX_ITEMS {
+            return None;
+        }
+
+        items.push(CStr::from_ptr(item).to_owned());
+    }
+
+    Some(items)
+}
+
+/// Pointers to `items`, null-terminated, to be passed to `execve`.
+fn null_terminated(items: &[CString]) -> Vec<*const c_char> {
+    items
+        .iter()
+        .map(|item| item.as_ptr())
+        .chain([ptr::null()])
+        .collect()
+}
+
+/// Name of an `envp` entry (`NAME=value`).
+fn env_name(entry: &CStr) -> &[u8] {
+    let bytes = entry.to_bytes();
+    bytes.split(|byte| *byte == b'=').next().unwrap_or(bytes)
+}
+
+/// Adds the missing variables of `backup` to `envp` when it doesn't load the layer anymore.
+///
+/// Returns whether the env was changed.
+fn restore_env(envp: &mut Vec<CString>, backup: &[(String, String)]) -> bool {
+    let has_injection = envp
+        .iter()
+        .any(|entry| env_name(entry) == INJECTION_ENV_VAR.as_bytes());
+    if has_injection {
+        return false;
+    }
+
+    let missing = backup
+        .iter()
+        .filter(|(name, _)| !envp.iter().any(|entry| env_name(entry) == name.as_bytes()))
+        .filter_map(|(name, value)| CString::new(format!("{name}={value}")).ok())
+        .collect::<Vec<_>>();
+
+    envp.extend(missing);
+    true
+}
+
+/// `systemd-run` starts the command as a transient unit, unless it's run with `--scope`, in which
+/// case it's a child of `systemd-run` and keeps its env.
+fn is_systemd_run(path: &CStr, argv: &[CString]) -> bool {
+    let name = Path::new(path.to_str().unwrap_or_default()).file_name();
+
+    name.is_some_and(|name| name == "systemd-run")
+        && !argv.iter().any(|arg| arg.as_bytes() == b"--scope")
+}
+
+/// Passes `backup` to the unit started by `systemd-run`, with `--setenv` arguments.
+fn systemd_run_setenv(argv: &mut Vec<CString>, backup: &[(String, String)]) {
+    let setenv = backup
+        .iter()
+        .filter_map(|(name, value)| CString::new(format!("--setenv={name}={value}")).ok());
+
+    // Right after `argv[0]`, the options after the command belong to it.
+    let at = argv.len().min(1);
+    argv.splice(at..at, setenv);
+}
+
+/// Whether `file` is a statically linked ELF executable, which has no program interpreter to load
+/// the layer. Returns `None` when it's not an ELF file we understand.
+fn is_static_elf<R: Read + Seek>(file: &mut R) -> Option<bool> {
+    fn field<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+        bytes.get(offset..offset + N)?.try_into().ok()
+    }
+
+    // Enough for both ELF32 (52 bytes) and ELF64 (64 bytes) headers.
+    let mut header = Vec::with_capacity(64);
+    file.take(64).read_to_end(&mut header).ok()?;
+    if header.get(..4)? != b"\x7fELF" {
+        return None;
+    }
+
+    let little_endian = match header.get(5)? {
+        1 => true,
+        2 => false,
+        _ => return None,
+    };
+    let u16_at = |offset| {
+        field(&header, offset).map(|bytes| match little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    };
+    let u32_at = |offset| {
+        field(&header, offset).map(|bytes| match little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    };
+    let u64_at = |offset| {
+        field(&header, offset).map(|bytes| match little_endian {
+            true => u64::from_le_bytes(bytes),
+            false => u64::from_be_bytes(bytes),
+        })
+    };
+
+    let (phoff, phentsize, phnum) = match header.get(4)? {
+        1 => (u32_at(28)?.into(), u16_at(42)?, u16_at(44)?),
+        2 => (u64_at(32)?, u16_at(54)?, u16_at(56)?),
+        _ => return None,
+    };
+
+    for index in 0..u64::from(phnum) {
+        file.seek(SeekFrom::Start(phoff + index * u64::from(phentsize)))
+            .ok()?;
+        let mut p_type = [0; 4];
+        file.read_exact(&mut p_type).ok()?;
+        let p_type = match little_endian {
+            true => u32::from_le_bytes(p_type),
+            false => u32::from_be_bytes(p_type),
+        };
+
+        if p_type == PT_INTERP {
+            return Some(false);
+        }
+    }
+
+    Some(phnum > 0)
+}
+
+/// Whether the program at `path` is statically linked, see [`is_static_elf`].
+fn is_static_executable(path: &CStr) -> io::Result<bool> {
+    let mut file = File::open(Path::new(OsStr::from_bytes(path.to_bytes())))?;
+    Ok(is_static_elf(&mut file).unwrap_or(false))
+}
+
+/// Points the proxy env vars (see [`PROXY_ENV`]) to the internal proxy's proxy server, unless the
+/// program already has them in any case.
+///
+/// Returns whether the env was changed.
+fn set_proxy_env(envp: &mut Vec<CString>, proxy_server: &str) -> bool {
+    let missing = PROXY_ENV
+        .iter()
+        .filter(|(upper, lower, _)| {
+            !envp.iter().any(|entry| {
+                let name = env_name(entry);
+                name == upper.as_bytes() || name == lower.as_bytes()
+            })
+        })
+        .flat_map(|(upper, lower, scheme)| {
+            [upper, lower].map(|name| CString::new(format!("{name}={scheme}://{proxy_server}")))
+        })
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+
+    let changed = !missing.is_empty();
+    envp.extend(missing);
+    changed
+}
+
+/// Hook for `libc::execve`.
+///
+/// Restores mirrord's env for the new program when it was cleared (see [`restore_env`]), and
+/// passes it to the unit when the program is `systemd-run` (see [`systemd_run_setenv`]).
+///
+/// Statically linked programs get the proxy env vars instead, when the proxy server is enabled
+/// (see [`set_proxy_env`]).
+///
+/// If anything goes wrong, we call the original function with the original arguments.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn execve_detour(
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    if path.is_null() {
+        return FN_EXECVE(path, argv, envp);
+    }
+    let (Some(mut new_argv), Some(mut new_envp)) = (c_string_array(argv), c_string_array(envp))
+    else {
+        return FN_EXECVE(path, argv, envp);
+    };
+
+    let path_str = CStr::from_ptr(path);
+    let backup = crate::setup().env_backup();
+
+    if restore_env(&mut new_envp, backup) {
+        warn!(
+            "{path_str:?} is being executed without mirrord's environment ({INJECTION_ENV_VAR}), \
+             which would run it without mirrord. mirrord restored it, if the process doesn't run \
+             with mirrord, make sure the launcher keeps {INJECTION_ENV_VAR} and the MIRRORD_ \
+             variables."
+        );
+    }
+
+    if is_systemd_run(path_str, &new_argv) {
+        systemd_run_setenv(&mut new_argv, backup);
+        warn!(
+            "The application is running {path_str:?}, which starts the process as a systemd unit, \
+             outside of the application's process tree. mirrord passed its environment to the \
+             unit with `--setenv`, but the process will only run with mirrord if it can reach \
+             the internal proxy. Consider using `systemd-run --scope`."
+        );
+    }
+
+    if let Some(proxy_server) = crate::setup().outgoing_proxy_server()
+        && is_static_executable(path_str).unwrap_or(false)
+        && set_proxy_env(&mut new_envp, proxy_server)
+    {
+        info!(
+            "{path_str:?} is statically linked and can't run with mirrord, its proxy env \
+             variables were set to make outgoing connections through the cluster."
+        );
+    }
+
+    let new_argv = null_terminated(&new_argv);
+    let new_envp = null_terminated(&new_envp);
+    FN_EXECVE(path, new_argv.as_ptr(), new_envp.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c_strings(items: &[&str]) -> Vec<CString> {
+        items
+            .iter()
+            .map(|item| CString::new(*item).unwrap())
+            .collect()
+    }
+
+    fn backup() -> Vec<(String, String)> {
+        vec![
+            (
+                INJECTION_ENV_VAR.to_string(),
+                "/tmp/libmirrord_layer.so".to_string(),
+            ),
+            (
+                "MIRRORD_CONNECT_TCP".to_string(),
+                "127.0.0.1:4000".to_string(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn restore_cleared_env() {
+        let mut envp = c_strings(&["PATH=/bin", "MIRRORD_CONNECT_TCP=127.0.0.1:5000"]);
+
+        assert!(restore_env(&mut envp, &backup()));
+        assert_eq!(
+            envp,
+            c_strings(&[
+                "PATH=/bin",
+                "MIRRORD_CONNECT_TCP=127.0.0.1:5000",
+                &format!("{INJECTION_ENV_VAR}=/tmp/libmirrord_layer.so"),
+            ])
+        );
+    }
+
+    #[test]
+    fn keep_injected_env() {
+        let mut envp = c_strings(&[&format!("{INJECTION_ENV_VAR}=/tmp/other.so")]);
+
+        assert!(!restore_env(&mut envp, &backup()));
+        assert_eq!(envp.len(), 1);
+    }
+
+    /// Minimal little-endian ELF64 with the given program header types.
+    fn elf64(program_headers: &[u32]) -> Vec<u8> {
+        let mut elf = b"\x7fELF".to_vec();
+        // 64-bit, little-endian.
+        elf.extend([2, 1]);
+        elf.resize(32, 0);
+        // Program headers right after this header.
+        elf.extend(64_u64.to_le_bytes());
+        elf.resize(54, 0);
+        elf.extend(56_u16.to_le_bytes());
+        elf.extend((program_headers.len() as u16).to_le_bytes());
+        elf.resize(64, 0);
+
+        for p_type in program_headers {
+            elf.extend(p_type.to_le_bytes());
+            elf.extend([0; 52]);
+        }
+
+        elf
+    }
+
+    #[test]
+    fn static_elf() {
+        const PT_LOAD: u32 = 1;
+
+        let mut dynamic = io::Cursor::new(elf64(&[6, PT_INTERP, PT_LOAD]));
+        assert_eq!(is_static_elf(&mut dynamic), Some(false));
+
+        let mut static_ = io::Cursor::new(elf64(&[PT_LOAD, PT_LOAD]));
+        assert_eq!(is_static_elf(&mut static_), Some(true));
+
+        let mut script = io::Cursor::new(b"#!/bin/sh\necho hello\n".to_vec());
+        assert_eq!(is_static_elf(&mut script), None);
+    }
+
+    #[test]
+    fn proxy_env() {
+        let mut envp = c_strings(&["PATH=/bin", "https_proxy=http://corporate:3128"]);
+
+        assert!(set_proxy_env(&mut envp, "127.0.0.1:4001"));
+        assert_eq!(
+            envp,
+            c_strings(&[
+                "PATH=/bin",
+                "https_proxy=http://corporate:3128",
+                "HTTP_PROXY=http://127.0.0.1:4001",
+                "http_proxy=http://127.0.0.1:4001",
+                "ALL_PROXY=socks5h://127.0.0.1:4001",
+                "all_proxy=socks5h://127.0.0.1:4001",
+            ])
+        );
+
+        assert!(!set_proxy_env(&mut envp, "127.0.0.1:4001"));
+    }
+
+    #[test]
+    fn systemd_run() {
+        let path = CString::new("/usr/bin/systemd-run").unwrap();
+        let mut argv = c_strings(&["systemd-run", "--unit=app", "/usr/bin/app", "--port=80"]);
+
+        assert!(is_systemd_run(&path, &argv));
+        assert!(!is_systemd_run(
+            &path,
+            &c_strings(&["systemd-run", "--scope", "/usr/bin/app"])
+        ));
+
+        systemd_run_setenv(&mut argv, &backup());
+        assert_eq!(
+            argv,
+            c_strings(&[
+                "systemd-run",
+                &format!("--setenv={INJECTION_ENV_VAR}=/tmp/libmirrord_layer.so"),
+                "--setenv=MIRRORD_CONNECT_TCP=127.0.0.1:4000",
+                "--unit=app",
+                "/usr/bin/app",
+                "--port=80",
+            ])
+        );
+    }
+}
@@ -27,6 +27,7 @@ use crate::{
     error::HookError,
     hooks::HookManager,
     replace,
+    setup::INJECTION_ENV_VAR,
 };
 
 /// Maximal number of items to expect in argv.
@@ -183,7 +184,7 @@ fn intercept_environment(envp_arr: &Nul<*const c_char>) -> Detour<Argv> {
             continue;
         };
 
-        if arg_str.split('=').next() == Some("DYLD_INSERT_LIBRARIES") {
+        if arg_str.split('=').next() == Some(INJECTION_ENV_VAR) {
             found_dyld = true;
         }
 
@@ -0,0 +1,87 @@
+//! Stops the application when the session reaches its maximum duration (`session.max_duration`).
+//!
+//! The CLI passes the time when the session expires in [`SESSION_DEADLINE_ENV`]. The first layer
+//! that finds it takes it out of the environment, so only the top process of the application warns
+//! about it and gets stopped, its children are expected to end with it.
+
+use std::{
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mirrord_intproxy_protocol::SESSION_DEADLINE_ENV;
+
+use crate::{
+    detour::DetourGuard,
+    hidden::{hide_current_thread, LAYER_THREAD_PREFIX},
+};
+
+/// Exit code of the application when it doesn't stop after the `SIGTERM` we send it when the
+/// session expires. Same as the one of the `timeout` command.
+const SESSION_EXPIRED_EXIT_CODE: i32 = 124;
+
+/// How long before the session expires we warn about it.
+const WARN_BEFORE: Duration = Duration::from_secs(5 * 60);
+
+/// How long the application has to exit after the `SIGTERM`.
+const TERMINATION_GRACE: Duration = Duration::from_secs(5);
+
+/// Starts a thread that warns when the session is about to expire, and stops the application when
+/// it does. Does nothing if the session doesn't expire.
+pub(crate) fn watch_session_deadline(hide_thread: bool) {
+    let Some(deadline) = std::env::var(SESSION_DEADLINE_ENV)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    else {
+        return;
+    };
+    std::env::remove_var(SESSION_DEADLINE_ENV);
+
+    let spawned = thread::Builder::new()
+        .name(format!("{LAYER_THREAD_PREFIX}session_deadline"))
+        .spawn(move || {
+            if hide_thread {
+                hide_current_thread();
+            }
+
+            let _guard = DetourGuard::new();
+
+            let remaining = time_until(deadline);
+            if remaining > WARN_BEFORE {
+                thread::sleep(remaining - WARN_BEFORE);
+            }
+
+            let remaining = time_until(deadline);
+            if !remaining.is_zero() {
+                eprintln!(
+                    "mirrord: the session reaches its maximum duration (session.max_duration) in \
+                     {} minutes, the application will be stopped then",
+                    remaining.as_secs().div_ceil(60)
+                );
+                thread::sleep(remaining);
+            }
+
+            eprintln!(
+                "mirrord: the session reached its maximum duration (session.max_duration), \
+                 stopping the application"
+            );
+            unsafe {
+                libc::kill(libc::getpid(), libc::SIGTERM);
+            }
+
+            thread::sleep(TERMINATION_GRACE);
+            std::process::exit(SESSION_EXPIRED_EXIT_CODE);
+        });
+
+    if let Err(error) = spawned {
+        tracing::warn!(%error, "Failed to spawn the session deadline thread");
+    }
+}
+
+/// Time left until `deadline`, zero when it passed.
+fn time_until(deadline: SystemTime) -> Duration {
+    deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+}
@@ -1,6 +1,7 @@
 use std::{
     os::unix::io::RawFd,
-    sync::{Arc, LazyLock},
+    path::PathBuf,
+    sync::{Arc, LazyLock, OnceLock},
 };
 
 use dashmap::DashMap;
@@ -24,6 +25,8 @@ use mirrord_protocol::file::{GetDEnts64Request, GetDEnts64Response};
 
 pub(crate) mod filter;
 pub(crate) mod hooks;
+#[cfg(target_os = "linux")]
+pub(crate) mod inotify;
 pub(crate) mod open_dirs;
 pub(crate) mod ops;
 
@@ -38,6 +41,12 @@ type DirStreamFd = usize;
 pub(crate) static OPEN_FILES: LazyLock<DashMap<LocalFd, Arc<ops::RemoteFile>>> =
     LazyLock::new(|| DashMap::with_capacity(4));
 
+/// Working directory used to resolve relative paths in file operations, set from
+/// [`FsConfig::cwd`](mirrord_config::feature::fs::FsConfig::cwd) by [`ops::init_remote_cwd`].
+///
+/// When it's not set, operations on relative paths are bypassed.
+pub(crate) static REMOTE_CWD: OnceLock<PathBuf> = OnceLock::new();
+
 /// Extension trait for [`OpenOptionsInternal`], used to convert between `libc`-ish open options and
 /// Rust's [`std::fs::OpenOptions`]
 pub(crate) trait OpenOptionsInternalExt {
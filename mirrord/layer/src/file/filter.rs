@@ -3,125 +3,69 @@
 /// There are 2 ways of setting this up:
 ///
 /// 1. no configuration (default): will bypass file operations for file paths and types that
-/// match [`generate_local_set`];
+/// match the default local patterns;
 ///
-/// 2. Using the overrides for `read_only`, `read_write` and `local`.
-use std::env;
+/// 2. Using the overrides for `rules`, `read_only`, `read_write` and `local`.
+///
+/// The decision itself is made by the [`FsFilter`], shared with the CLI.
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+};
 
-use mirrord_config::{
-    feature::fs::{FsConfig, FsModeConfig},
-    util::VecOrSingle,
+use mirrord_config::feature::fs::{
+    filter::{FsAction, FsFilter, FsReason},
+    FsConfig, FsModeConfig, SHARED_SCRATCH_DIR_ENV,
 };
-use regex::{RegexSet, RegexSetBuilder};
 
 use crate::{
     detour::{Bypass, Detour},
     error::HookError,
 };
 
-mod not_found_by_default;
-mod read_local_by_default;
-mod read_remote_by_default;
-
-/// List of files that mirrord should use locally, as they probably exist only in the local user
-/// machine, or are system configuration files (that could break the process if we used the remote
-/// version).
-///
-/// You most likely do **NOT** want to include any of these, but if have a reason to do so, then
-/// setting any of the overrides - `MIRRORD_FILE_X_PATTERN` allows you to override this list.
-fn generate_local_set() -> RegexSet {
-    // To handle the problem of injecting `open` and friends into project runners (like in a call to
-    // `node app.js`, or `cargo run app`), we're ignoring files from the current working directory.
-    read_local_by_default::regex_set_builder()
-        .case_insensitive(true)
-        .build()
-        .expect("Building local path regex set failed")
-}
-
-/// List of files that mirrord should use remotely read only
-fn generate_remote_ro_set() -> RegexSet {
-    let patterns = read_remote_by_default::PATHS;
-    RegexSetBuilder::new(patterns)
-        .case_insensitive(true)
-        .build()
-        .expect("Building remote readonly path regex set failed")
-}
-
-fn generate_not_found_set() -> RegexSet {
-    let Ok(home) = env::var("HOME") else {
-        tracing::warn!("Unable to resolve $HOME directory, generating empty not-found set");
-        return Default::default();
-    };
-
-    let home_clean = regex::escape(home.trim_end_matches('/'));
-
-    let patterns = not_found_by_default::PATHS
-        .into_iter()
-        .map(|cloud_dir| format!("^{home_clean}/{cloud_dir}"));
-
-    RegexSetBuilder::new(patterns)
-        .case_insensitive(true)
-        .build()
-        .expect("Building not found path regex set failed")
-}
-
 #[derive(Debug)]
 pub struct FileFilter {
-    read_only: RegexSet,
-    read_write: RegexSet,
-    local: RegexSet,
-    not_found: RegexSet,
-    default_local: RegexSet,
-    default_remote_ro: RegexSet,
-    default_not_found: RegexSet,
-    mode: FsModeConfig,
+    filter: FsFilter,
 }
 
 impl FileFilter {
-    fn make_regex_set(patterns: Option<VecOrSingle<String>>) -> Result<RegexSet, regex::Error> {
-        RegexSetBuilder::new(patterns.as_deref().map(<[_]>::to_vec).unwrap_or_default())
-            .case_insensitive(true)
-            .build()
-    }
-
     /// Initializes a `FileFilter` based on the user configuration.
     ///
-    /// The filter first checks if the user specified any include/exclude regexes. (This will be
-    /// removed) If path matches include, it continues to check if the path has specific
-    /// behavior, if not, it checks if the path matches the default exclude list.
-    /// If not, it does the default behavior set by user (default is read only remote).
+    /// See [`FsFilter::decide`] for the order in which the paths are checked.
     #[mirrord_layer_macro::instrument(level = "trace")]
     pub fn new(fs_config: FsConfig) -> Self {
-        let FsConfig {
-            read_write,
-            read_only,
-            local,
-            mode,
-            not_found,
-        } = fs_config;
+        let mut filter = FsFilter::new(&fs_config).expect("building fs filter regex sets failed");
+
+        // The CLI synchronizes the remote directory with this local one.
+        if let (Some(remote), Some(local)) = (
+            fs_config.shared_scratch.as_ref(),
+            std::env::var_os(SHARED_SCRATCH_DIR_ENV),
+        ) {
+            filter.add_local_override(remote.into(), local.into());
+        }
 
-        let read_write =
-            Self::make_regex_set(read_write).expect("building read-write regex set failed");
-        let read_only =
-            Self::make_regex_set(read_only).expect("building read-only regex set failed");
-        let local = Self::make_regex_set(local).expect("building local path regex set failed");
-        let not_found =
-            Self::make_regex_set(not_found).expect("building not-found regex set failed");
+        Self { filter }
+    }
 
-        let default_local = generate_local_set();
-        let default_remote_ro = generate_remote_ro_set();
-        let default_not_found = generate_not_found_set();
+    /// Checks if `path` is under one of the
+    /// [`FsConfig::image_paths`](mirrord_config::feature::fs::FsConfig::image_paths), meaning it
+    /// should be read from the target container's image, regardless of the other settings.
+    pub fn is_image_path(&self, path: &Path) -> bool {
+        self.filter.is_image_path(path)
+    }
 
-        Self {
-            read_only,
-            read_write,
-            local,
-            not_found,
-            default_local,
-            default_remote_ro,
-            default_not_found,
-            mode,
-        }
+    /// Rewrites `path` to the path in the target, see
+    /// [`FsConfig::mapping`](mirrord_config::feature::fs::FsConfig::mapping).
+    pub fn remap(&self, path: &Path) -> Option<PathBuf> {
+        self.filter.remap(path)
+    }
+
+    /// Returns the local path that serves `path`, see
+    /// [`FsConfig::local_override`](mirrord_config::feature::fs::FsConfig::local_override).
+    pub fn local_override(&self, path: &Path) -> Option<CString> {
+        let local_path = self.filter.local_override(path)?;
+        CString::new(local_path.into_os_string().into_vec()).ok()
     }
 
     /// Checks if `text` matches the regex held by the initialized variant of `FileFilter`,
@@ -132,25 +76,19 @@ impl FileFilter {
     where
         F: FnOnce() -> Bypass,
     {
-        match self.mode {
-            FsModeConfig::Local => Detour::Bypass(op()),
-            _ if self.not_found.is_match(text) => Detour::Error(HookError::FileNotFound),
-            _ if self.read_write.is_match(text) => Detour::Success(()),
-            _ if self.read_only.is_match(text) => {
-                if write {
-                    Detour::Bypass(op())
-                } else {
-                    Detour::Success(())
-                }
+        let decision = self.filter.decide(text, write);
+
+        match (decision.action, decision.reason) {
+            (FsAction::Remote | FsAction::RemoteImage, _) => Detour::Success(()),
+            (FsAction::NotFound, _) => Detour::Error(HookError::FileNotFound),
+            (FsAction::Local, FsReason::LocalOverride { .. }) => self
+                .local_override(Path::new(text))
+                .map(Bypass::LocalOverride)
+                .map_or_else(|| Detour::Bypass(op()), Detour::Bypass),
+            (FsAction::Local, FsReason::Mode(FsModeConfig::Read)) => {
+                Detour::Bypass(Bypass::ReadOnly(text.into()))
             }
-            _ if self.local.is_match(text) => Detour::Bypass(op()),
-            _ if self.default_not_found.is_match(text) => Detour::Error(HookError::FileNotFound),
-            _ if self.default_remote_ro.is_match(text) && !write => Detour::Success(()),
-            _ if self.default_local.is_match(text) => Detour::Bypass(op()),
-            FsModeConfig::LocalWithOverrides => Detour::Bypass(op()),
-            FsModeConfig::Write => Detour::Success(()),
-            FsModeConfig::Read if write => Detour::Bypass(Bypass::ReadOnly(text.into())),
-            FsModeConfig::Read => Detour::Success(()),
+            (FsAction::Local, _) => Detour::Bypass(op()),
         }
     }
 }
@@ -163,6 +101,8 @@ impl Default for FileFilter {
 
 #[cfg(test)]
 mod tests {
+    use std::env;
+
     use mirrord_config::{feature::fs::FsConfig, util::VecOrSingle};
     use rstest::*;
 
@@ -399,6 +339,7 @@ mod tests {
             local,
             not_found,
             mode,
+            ..Default::default()
         };
 
         let file_filter = FileFilter::new(fs_config);
@@ -446,11 +387,22 @@ mod tests {
         assert_eq!(res.kind(), expected);
     }
 
-    /// Sanity test for empty [`RegexSet`] behaviour.
-    #[test]
-    fn empty_regex_set() {
-        let set = FileFilter::make_regex_set(None).unwrap();
-        assert!(!set.is_match("/path/to/some/file"));
+    #[rstest]
+    #[case(FsModeConfig::Read, "/usr/share/app/assets/logo.png", true)]
+    #[case(FsModeConfig::LocalWithOverrides, "/usr/share/app/assets", true)]
+    #[case(FsModeConfig::Read, "/usr/share/app/assets-old/logo.png", false)]
+    #[case(FsModeConfig::Read, "/usr/share/app", false)]
+    #[case(FsModeConfig::Local, "/usr/share/app/assets/logo.png", false)]
+    fn image_paths(#[case] mode: FsModeConfig, #[case] path: &str, #[case] expected: bool) {
+        let fs_config = FsConfig {
+            mode,
+            image_paths: Some(VecOrSingle::Single("/usr/share/app/assets".to_string())),
+            ..Default::default()
+        };
+
+        let file_filter = FileFilter::new(fs_config);
+
+        assert_eq!(file_filter.is_image_path(Path::new(path)), expected);
     }
 
     /// Return path to the $HOME directory without trailing slash.
@@ -18,7 +18,7 @@ use libc::{
     AT_FDCWD, DIR, EINVAL, O_DIRECTORY, O_RDONLY,
 };
 #[cfg(target_os = "linux")]
-use libc::{dirent64, stat64, statx, EBADF, ENOENT, ENOTDIR};
+use libc::{c_long, dirent64, stat64, statx, EBADF, ENOENT, ENOTDIR};
 use mirrord_layer_macro::{hook_fn, hook_guard_fn};
 use mirrord_protocol::file::{
     FsMetadataInternal, MetadataInternal, ReadFileResponse, ReadLinkFileResponse, WriteFileResponse,
@@ -31,19 +31,18 @@ use tracing::trace;
 use tracing::{error, info, warn};
 
 use super::{open_dirs, ops::*, OpenOptionsInternalExt};
-#[cfg(target_os = "macos")]
-use crate::detour::Bypass;
 #[cfg(target_os = "linux")]
 use crate::error::HookError::ResponseError;
 use crate::{
     close_layer_fd,
     common::CheckedInto,
-    detour::{Detour, DetourGuard},
+    detour::{Bypass, Detour, DetourGuard},
     error::HookError,
     file::{
         open_dirs::OPEN_DIRS,
-        ops::{access, lseek, open, read, write},
+        ops::{access, flock, lseek, open, read, write},
     },
+    hook_stats::HookTimer,
     hooks::HookManager,
     replace,
 };
@@ -55,14 +54,19 @@ type stat64 = stat;
 /// Take the original raw c_char pointer and a resulting bypass, and either the original pointer or
 /// a different one according to the bypass.
 /// We pass reference to bypass to make sure the bypass lives with the pointer.
-#[cfg(target_os = "macos")]
-fn update_ptr_from_bypass(ptr: *const c_char, bypass: Bypass) -> *const c_char {
+pub(crate) fn update_ptr_from_bypass(ptr: *const c_char, bypass: &Bypass) -> *const c_char {
     match bypass {
         // For some reason, the program is trying to carry out an operation on a path that is
         // inside mirrord's temp bin dir. The detour has returned us the original path of the file
         // (stripped mirrord's dir path), so now we carry out the operation locally, on the stripped
         // path.
-        Bypass::FileOperationInMirrordBinTempDir(stripped_ptr) => stripped_ptr,
+        #[cfg(target_os = "macos")]
+        Bypass::FileOperationInMirrordBinTempDir(stripped_ptr) => *stripped_ptr,
+        // The path is served from a local directory, see `feature.fs.local_override`.
+        Bypass::LocalOverride(local_path) => local_path.as_ptr(),
+        // The remote path is a symlink to a local one, see
+        // `experimental.resolve_remote_symlinks`.
+        Bypass::ResolvedLocal(local_path) => local_path.as_ptr(),
         _ => ptr,
     }
 }
@@ -94,9 +98,9 @@ pub(super) unsafe extern "C" fn open_detour(
     if guard.is_none() {
         FN_OPEN(raw_path, open_flags, mode)
     } else {
-        open_logic(raw_path, open_flags, mode).unwrap_or_bypass_with(|_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        let _timer = HookTimer::start("open");
+        open_logic(raw_path, open_flags, mode).unwrap_or_bypass_with(|bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN_OPEN(raw_path, open_flags, mode)
         })
     }
@@ -117,9 +121,9 @@ pub(super) unsafe extern "C" fn open64_detour(
     if guard.is_none() {
         FN_OPEN64(raw_path, open_flags, mode)
     } else {
-        open_logic(raw_path, open_flags, mode).unwrap_or_bypass_with(|_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        let _timer = HookTimer::start("open64");
+        open_logic(raw_path, open_flags, mode).unwrap_or_bypass_with(|bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN_OPEN64(raw_path, open_flags, mode)
         })
     }
@@ -137,9 +141,9 @@ pub(super) unsafe extern "C" fn open_nocancel_detour(
     if guard.is_none() {
         FN_OPEN_NOCANCEL(raw_path, open_flags, mode)
     } else {
-        open_logic(raw_path, open_flags, mode).unwrap_or_bypass_with(|_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        let _timer = HookTimer::start("open_nocancel");
+        open_logic(raw_path, open_flags, mode).unwrap_or_bypass_with(|bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN_OPEN_NOCANCEL(raw_path, open_flags, mode)
         })
     }
@@ -164,7 +168,9 @@ pub(super) unsafe extern "C" fn opendir_detour(raw_filename: *const c_char) -> u
                 Detour::Error(fail)
             }
         })
-        .unwrap_or_bypass_with(|_| opendir_bypass(raw_filename))
+        .unwrap_or_bypass_with(|bypass| {
+            opendir_bypass(update_ptr_from_bypass(raw_filename, &bypass))
+        })
 }
 
 /// see below, to have nice code we also implement it for other archs.
@@ -292,6 +298,12 @@ pub(crate) unsafe extern "C" fn closedir_detour(dirp: *mut DIR) -> c_int {
         .unwrap_or_bypass_with(|_| FN_CLOSEDIR(dirp))
 }
 
+/// Hook for `libc::flock`, locks remote files on the agent.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn flock_detour(fd: RawFd, operation: c_int) -> c_int {
+    flock(fd, operation).unwrap_or_bypass_with(|_| FN_FLOCK(fd, operation))
+}
+
 #[hook_guard_fn]
 pub(crate) unsafe extern "C" fn dirfd_detour(dirp: *mut DIR) -> c_int {
     OPEN_DIRS
@@ -312,13 +324,55 @@ pub(crate) unsafe extern "C" fn openat_detour(
 ) -> RawFd {
     let open_options = OpenOptionsInternalExt::from_flags(open_flags);
 
-    openat(fd, raw_path.checked_into(), open_options).unwrap_or_bypass_with(|_bypass| {
-        #[cfg(target_os = "macos")]
-        let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+    openat(fd, raw_path.checked_into(), open_options).unwrap_or_bypass_with(|bypass| {
+        let raw_path = update_ptr_from_bypass(raw_path, &bypass);
         FN_OPENAT(fd, raw_path, open_flags)
     })
 }
 
+/// `struct open_how` of `openat2`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[allow(dead_code)]
+pub(crate) struct OpenHow {
+    pub(crate) flags: u64,
+    pub(crate) mode: u64,
+    pub(crate) resolve: u64,
+}
+
+/// Handles the `openat2` syscall, like [`openat_detour`] but with the resolve flags of `how`, see
+/// [`openat2`].
+///
+/// libc doesn't wrap `openat2`, so it comes from our hook of libc's `syscall`, or from the Go
+/// runtime. Returns the descriptor, or `-1` with `errno` set, like `syscall`.
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn openat2_syscall(
+    fd: RawFd,
+    raw_path: *const c_char,
+    how: *const OpenHow,
+    size: size_t,
+) -> c_long {
+    let _guard = DetourGuard::new();
+    let original =
+        |raw_path: *const c_char| libc::syscall(libc::SYS_openat2, fd, raw_path, how, size);
+
+    // The kernel rejects the smaller ones.
+    let Some(open_how) = how
+        .as_ref()
+        .filter(|_| size >= std::mem::size_of::<OpenHow>())
+    else {
+        return original(raw_path);
+    };
+    let open_options = OpenOptionsInternalExt::from_flags(open_how.flags as c_int);
+
+    openat2(fd, raw_path.checked_into(), open_options, open_how.resolve)
+        .map(c_long::from)
+        .unwrap_or_bypass_with(|bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
+            original(raw_path)
+        })
+}
+
 /// Equivalent to `open_detour`, **except** when `raw_path` specifies a relative path.
 ///
 /// If `fd == AT_FDCWD`, the current working directory is used, and the behavior is the same as
@@ -332,9 +386,8 @@ pub(crate) unsafe extern "C" fn openat64_detour(
 ) -> RawFd {
     let open_options = OpenOptionsInternalExt::from_flags(open_flags);
 
-    openat(fd, raw_path.checked_into(), open_options).unwrap_or_bypass_with(|_bypass| {
-        #[cfg(target_os = "macos")]
-        let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+    openat(fd, raw_path.checked_into(), open_options).unwrap_or_bypass_with(|bypass| {
+        let raw_path = update_ptr_from_bypass(raw_path, &bypass);
         FN_OPENAT64(fd, raw_path, open_flags)
     })
 }
@@ -347,9 +400,8 @@ pub(crate) unsafe extern "C" fn _openat_nocancel_detour(
 ) -> RawFd {
     let open_options = OpenOptionsInternalExt::from_flags(open_flags);
 
-    openat(fd, raw_path.checked_into(), open_options).unwrap_or_bypass_with(|_bypass| {
-        #[cfg(target_os = "macos")]
-        let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+    openat(fd, raw_path.checked_into(), open_options).unwrap_or_bypass_with(|bypass| {
+        let raw_path = update_ptr_from_bypass(raw_path, &bypass);
         FN__OPENAT_NOCANCEL(fd, raw_path, open_flags)
     })
 }
@@ -435,6 +487,11 @@ pub(crate) unsafe extern "C" fn read_detour(
     out_buffer: *mut c_void,
     count: size_t,
 ) -> ssize_t {
+    #[cfg(target_os = "linux")]
+    if super::inotify::has_remote_watches(fd) {
+        return super::inotify::read(fd, out_buffer, count);
+    }
+
     read(fd, count as u64)
         .map(|read_file| {
             let ReadFileResponse { bytes, read_amount } = read_file;
@@ -657,9 +714,8 @@ pub(crate) unsafe extern "C" fn _write_nocancel_detour(
 
 /// Implementation of access_detour, used in access_detour and faccessat_detour
 unsafe fn access_logic(raw_path: *const c_char, mode: c_int) -> c_int {
-    access(raw_path.checked_into(), mode as u8).unwrap_or_bypass_with(|_bypass| {
-        #[cfg(target_os = "macos")]
-        let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+    access(raw_path.checked_into(), mode as u8).unwrap_or_bypass_with(|bypass| {
+        let raw_path = update_ptr_from_bypass(raw_path, &bypass);
         FN_ACCESS(raw_path, mode)
     })
 }
@@ -709,6 +765,12 @@ fn nano_to_secs(nano: i64) -> i64 {
     best_effort_cast(Duration::from_nanos(best_effort_cast(nano)).as_secs())
 }
 
+/// Nanoseconds within the second of a time in nano seconds, to match the `st_*time_nsec` fields of
+/// the `stat` struct
+fn subsec_nanos(nano: i64) -> i64 {
+    nano.rem_euclid(1_000_000_000)
+}
+
 /// Fills the `stat` struct with the metadata
 unsafe extern "C" fn fill_stat(out_stat: *mut stat64, metadata: &MetadataInternal) {
     out_stat.write_bytes(0, 1);
@@ -716,9 +778,9 @@ unsafe extern "C" fn fill_stat(out_stat: *mut stat64, metadata: &MetadataInterna
     // on macOS the types might be different, so we try to cast and do our best..
     out.st_mode = best_effort_cast(metadata.mode);
     out.st_size = best_effort_cast(metadata.size);
-    out.st_atime_nsec = metadata.access_time;
-    out.st_mtime_nsec = metadata.modification_time;
-    out.st_ctime_nsec = metadata.creation_time;
+    out.st_atime_nsec = subsec_nanos(metadata.access_time);
+    out.st_mtime_nsec = subsec_nanos(metadata.modification_time);
+    out.st_ctime_nsec = subsec_nanos(metadata.creation_time);
     out.st_atime = nano_to_secs(metadata.access_time);
     out.st_mtime = nano_to_secs(metadata.modification_time);
     out.st_ctime = nano_to_secs(metadata.creation_time);
@@ -771,9 +833,8 @@ fn stat_logic<const FOLLOW_SYMLINK: bool>(
 #[hook_guard_fn]
 unsafe extern "C" fn lstat_detour(raw_path: *const c_char, out_stat: *mut stat) -> c_int {
     stat_logic::<false>(0, None, Some(raw_path), out_stat as *mut _).unwrap_or_bypass_with(
-        |_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        |bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN_LSTAT(raw_path, out_stat)
         },
     )
@@ -790,9 +851,8 @@ pub(crate) unsafe extern "C" fn fstat_detour(fd: RawFd, out_stat: *mut stat) ->
 #[hook_guard_fn]
 unsafe extern "C" fn stat_detour(raw_path: *const c_char, out_stat: *mut stat) -> c_int {
     stat_logic::<true>(0, None, Some(raw_path), out_stat as *mut _).unwrap_or_bypass_with(
-        |_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        |bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN_STAT(raw_path, out_stat)
         },
     )
@@ -808,8 +868,10 @@ unsafe extern "C" fn statx_detour(
     mask: c_int,
     statx_buf: *mut statx,
 ) -> c_int {
-    statx_logic(dir_fd, path_name, flags, mask, statx_buf)
-        .unwrap_or_bypass_with(|_bypass| FN_STATX(dir_fd, path_name, flags, mask, statx_buf))
+    statx_logic(dir_fd, path_name, flags, mask, statx_buf).unwrap_or_bypass_with(|bypass| {
+        let path_name = update_ptr_from_bypass(path_name, &bypass);
+        FN_STATX(dir_fd, path_name, flags, mask, statx_buf)
+    })
 }
 
 /// Hook for libc's stat syscall wrapper.
@@ -820,9 +882,8 @@ pub(crate) unsafe extern "C" fn __xstat_detour(
     out_stat: *mut stat,
 ) -> c_int {
     stat_logic::<true>(ver, None, Some(raw_path), out_stat as *mut _).unwrap_or_bypass_with(
-        |_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        |bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN___XSTAT(ver, raw_path, out_stat)
         },
     )
@@ -836,9 +897,8 @@ pub(crate) unsafe extern "C" fn __lxstat_detour(
     out_stat: *mut stat,
 ) -> c_int {
     stat_logic::<true>(ver, None, Some(raw_path), out_stat as *mut _).unwrap_or_bypass_with(
-        |_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        |bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN___LXSTAT(ver, raw_path, out_stat)
         },
     )
@@ -851,9 +911,8 @@ pub(crate) unsafe extern "C" fn __xstat64_detour(
     raw_path: *const c_char,
     out_stat: *mut stat64,
 ) -> c_int {
-    stat_logic::<true>(ver, None, Some(raw_path), out_stat).unwrap_or_bypass_with(|_bypass| {
-        #[cfg(target_os = "macos")]
-        let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+    stat_logic::<true>(ver, None, Some(raw_path), out_stat).unwrap_or_bypass_with(|bypass| {
+        let raw_path = update_ptr_from_bypass(raw_path, &bypass);
         FN___XSTAT64(ver, raw_path, out_stat)
     })
 }
@@ -865,9 +924,8 @@ pub(crate) unsafe extern "C" fn __lxstat64_detour(
     raw_path: *const c_char,
     out_stat: *mut stat64,
 ) -> c_int {
-    stat_logic::<true>(ver, None, Some(raw_path), out_stat).unwrap_or_bypass_with(|_bypass| {
-        #[cfg(target_os = "macos")]
-        let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+    stat_logic::<true>(ver, None, Some(raw_path), out_stat).unwrap_or_bypass_with(|bypass| {
+        let raw_path = update_ptr_from_bypass(raw_path, &bypass);
         FN___LXSTAT64(ver, raw_path, out_stat)
     })
 }
@@ -899,9 +957,8 @@ unsafe extern "C" fn fstatat_detour(
     out_stat: *mut stat,
     flag: c_int,
 ) -> c_int {
-    fstatat_logic(fd, raw_path, out_stat, flag).unwrap_or_bypass_with(|_bypass| {
-        #[cfg(target_os = "macos")]
-        let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+    fstatat_logic(fd, raw_path, out_stat, flag).unwrap_or_bypass_with(|bypass| {
+        let raw_path = update_ptr_from_bypass(raw_path, &bypass);
         FN_FSTATAT(fd, raw_path, out_stat, flag)
     })
 }
@@ -953,8 +1010,10 @@ unsafe extern "C" fn realpath_detour(
     source_path: *const c_char,
     output_path: *mut c_char,
 ) -> *mut c_char {
-    realpath_logic(source_path, output_path)
-        .unwrap_or_bypass_with(|_| FN_REALPATH(source_path, output_path))
+    realpath_logic(source_path, output_path).unwrap_or_bypass_with(|bypass| {
+        let source_path = update_ptr_from_bypass(source_path, &bypass);
+        FN_REALPATH(source_path, output_path)
+    })
 }
 
 #[hook_guard_fn]
@@ -962,8 +1021,10 @@ unsafe extern "C" fn realpath_darwin_extsn_detour(
     source_path: *const c_char,
     output_path: *mut c_char,
 ) -> *mut c_char {
-    realpath_logic(source_path, output_path)
-        .unwrap_or_bypass_with(|_| FN_REALPATH_DARWIN_EXTSN(source_path, output_path))
+    realpath_logic(source_path, output_path).unwrap_or_bypass_with(|bypass| {
+        let source_path = update_ptr_from_bypass(source_path, &bypass);
+        FN_REALPATH_DARWIN_EXTSN(source_path, output_path)
+    })
 }
 
 fn vec_to_iovec(bytes: &[u8], iovecs: &[iovec]) {
@@ -1054,13 +1115,50 @@ pub(crate) unsafe extern "C" fn readlink_detour(
 
             ssize_t::try_from(path_bytes.len().min(buffer_size)).unwrap()
         })
-        .unwrap_or_bypass_with(|_bypass| {
-            #[cfg(target_os = "macos")]
-            let raw_path = update_ptr_from_bypass(raw_path, _bypass);
+        .unwrap_or_bypass_with(|bypass| {
+            let raw_path = update_ptr_from_bypass(raw_path, &bypass);
             FN_READLINK(raw_path, out_buffer, buffer_size)
         })
 }
 
+/// Hook for [`libc::getcwd`].
+///
+/// Returns the remote working directory when
+/// [`FsConfig::cwd`](mirrord_config::feature::fs::FsConfig::cwd) is set, so that the process sees
+/// the same directory we use to resolve relative paths.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn getcwd_detour(
+    out_buffer: *mut c_char,
+    size: size_t,
+) -> *mut c_char {
+    getcwd()
+        .map(|cwd| {
+            let path_bytes = cwd.as_os_str().as_bytes();
+            let required_size = path_bytes.len() + 1;
+
+            let output = if out_buffer.is_null() {
+                // Same as glibc, allocate the buffer when the caller didn't provide one.
+                let allocated = libc::malloc(required_size.max(size)) as *mut c_char;
+                if allocated.is_null() {
+                    set_errno(Errno(libc::ENOMEM));
+                    return ptr::null_mut();
+                }
+                allocated
+            } else if size < required_size {
+                set_errno(Errno(libc::ERANGE));
+                return ptr::null_mut();
+            } else {
+                out_buffer
+            };
+
+            ptr::copy_nonoverlapping(path_bytes.as_ptr(), output.cast(), path_bytes.len());
+            *output.add(path_bytes.len()) = 0;
+
+            output
+        })
+        .unwrap_or_bypass_with(|_| FN_GETCWD(out_buffer, size))
+}
+
 /// Convenience function to setup file hooks (`x_detour`) with `frida_gum`.
 pub(crate) unsafe fn enable_file_hooks(hook_manager: &mut HookManager) {
     replace!(hook_manager, "open", open_detour, FnOpen, FN_OPEN);
@@ -1115,6 +1213,7 @@ pub(crate) unsafe fn enable_file_hooks(hook_manager: &mut HookManager) {
     );
 
     replace!(hook_manager, "dirfd", dirfd_detour, FnDirfd, FN_DIRFD);
+    replace!(hook_manager, "flock", flock_detour, FnFlock, FN_FLOCK);
 
     replace!(hook_manager, "pread", pread_detour, FnPread, FN_PREAD);
     replace!(hook_manager, "readv", readv_detour, FnReadv, FN_READV);
@@ -1135,6 +1234,8 @@ pub(crate) unsafe fn enable_file_hooks(hook_manager: &mut HookManager) {
         FN_READLINK
     );
 
+    replace!(hook_manager, "getcwd", getcwd_detour, FnGetcwd, FN_GETCWD);
+
     replace!(hook_manager, "lseek", lseek_detour, FnLseek, FN_LSEEK);
 
     replace!(hook_manager, "write", write_detour, FnWrite, FN_WRITE);
@@ -1192,6 +1293,7 @@ pub(crate) unsafe fn enable_file_hooks(hook_manager: &mut HookManager) {
     #[cfg(target_os = "linux")]
     {
         replace!(hook_manager, "statx", statx_detour, FnStatx, FN_STATX);
+        super::inotify::enable_inotify_hooks(hook_manager);
     }
 
     #[cfg(not(all(target_os = "macos", target_arch = "x86_64")))]
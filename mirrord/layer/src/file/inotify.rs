@@ -0,0 +1,567 @@
+//! Delivers `inotify` events of remote files to the local process.
+//!
+//! Watches added with `inotify_add_watch` on paths that go to the remote filesystem are made in the
+//! agent instead ([`WatchAddRequest`]), and get a watch descriptor from a range that the kernel
+//! doesn't use ([`REMOTE_WD_BASE`]). A background thread polls the agent for the events of these
+//! watches ([`WatchEventsRequest`]), and queues them in the [`Instance`] that made the watch.
+//!
+//! The application keeps the real inotify descriptor, so that it can wait on it with
+//! `poll`/`epoll` and get its local events as usual. To make it readable when remote events
+//! arrive, every instance also watches a local file of ours (the wake file), whose attributes we
+//! touch. Reads from an instance with remote watches are hooked: they return the queued remote
+//! events, and drop the events of the wake file.
+//!
+//! Not handled: inotify descriptors created with the raw syscalls (Go), and watches inherited by a
+//! forked child, which only gets the local events.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    ffi::c_void,
+    fs::{self, File},
+    io,
+    mem::size_of,
+    os::{fd::RawFd, unix::ffi::OsStrExt},
+    path::PathBuf,
+    ptr,
+    sync::{LazyLock, Mutex, MutexGuard, Once, PoisonError},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use errno::{set_errno, Errno};
+use libc::{c_char, c_int, size_t, ssize_t};
+use mirrord_layer_macro::hook_guard_fn;
+use mirrord_protocol::file::{
+    WatchAddRequest, WatchAddResponse, WatchEvent, WatchEventsRequest, WatchEventsResponse,
+    WatchRemoveRequest,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use tracing::{trace, warn};
+
+use super::{hooks::FN_READ, ops::remote_path};
+use crate::{
+    common::{self, CheckedInto},
+    detour::{Bypass, Detour, DetourGuard},
+    file::hooks::update_ptr_from_bypass,
+    hidden::{hide_current_thread, LAYER_THREAD_PREFIX},
+    hooks::HookManager,
+    replace,
+};
+
+/// First watch descriptor that we give to remote watches, the kernel counts from 1 for every
+/// instance.
+const REMOTE_WD_BASE: c_int = 1 << 24;
+
+/// How often we ask the agent for the events of the remote watches.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Size of `struct inotify_event` without the name, the name is padded to a multiple of it.
+const EVENT_HEADER_SIZE: usize = size_of::<libc::inotify_event>();
+
+/// inotify instances created by the application, by their descriptor.
+static INSTANCES: LazyLock<Mutex<HashMap<RawFd, Instance>>> = LazyLock::new(Default::default);
+
+/// Starts the [`poll_remote_events`] thread, with the first remote watch.
+static POLLER: Once = Once::new();
+
+fn instances() -> MutexGuard<'static, HashMap<RawFd, Instance>> {
+    INSTANCES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A watch made in the agent.
+#[derive(Debug)]
+struct RemoteWatch {
+    watch_id: u64,
+    /// Path in the target, watching it again returns the same descriptor.
+    path: PathBuf,
+    mask: u32,
+}
+
+/// Local file watched by an [`Instance`], see [`Instance::wake`].
+#[derive(Debug)]
+struct WakeFile {
+    /// Already unlinked, we only keep it open.
+    file: File,
+    wd: c_int,
+}
+
+/// An inotify instance of the application.
+#[derive(Debug, Default)]
+struct Instance {
+    /// Created with the first remote watch.
+    wake_file: Option<WakeFile>,
+    /// Remote watches, by their watch descriptor.
+    watches: HashMap<c_int, RemoteWatch>,
+    next_wd: c_int,
+    /// Remote events that the application didn't read yet.
+    pending: VecDeque<PendingEvent>,
+}
+
+impl Instance {
+    /// Creates the [`WakeFile`] and watches it with the inotify instance `fd`.
+    fn ensure_wake_file(&mut self, fd: RawFd) -> io::Result<()> {
+        if self.wake_file.is_some() {
+            return Ok(());
+        }
+
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let path = env::temp_dir().join(format!("mirrord-inotify-{random_string}"));
+        let file = File::create(&path)?;
+
+        let mut raw_path = path.as_os_str().as_bytes().to_vec();
+        raw_path.push(0);
+        let wd = unsafe { FN_INOTIFY_ADD_WATCH(fd, raw_path.as_ptr().cast(), libc::IN_ATTRIB) };
+        let _ = fs::remove_file(&path);
+
+        if wd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.wake_file = Some(WakeFile { file, wd });
+        Ok(())
+    }
+
+    /// Makes the instance readable, by changing the attributes of the [`WakeFile`].
+    fn wake(&self) {
+        if let Some(WakeFile { file, .. }) = &self.wake_file {
+            if let Err(error) = file.set_modified(SystemTime::now()) {
+                warn!(%error, "Failed to wake an inotify instance with remote events");
+            }
+        }
+    }
+
+    fn wake_wd(&self) -> Option<c_int> {
+        self.wake_file.as_ref().map(|wake_file| wake_file.wd)
+    }
+
+    /// Takes the pending events that fit in `count` bytes, encoded as `struct inotify_event`s.
+    ///
+    /// Returns [`None`] when the next event doesn't fit, `read` fails with `EINVAL` then.
+    fn take_pending(&mut self, count: usize) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+
+        while let Some(event) = self.pending.front() {
+            if bytes.len() + event.encoded_len() > count {
+                break;
+            }
+
+            event.encode(&mut bytes);
+            self.pending.pop_front();
+        }
+
+        if bytes.is_empty() && !self.pending.is_empty() {
+            None
+        } else {
+            Some(bytes)
+        }
+    }
+}
+
+/// A remote event waiting for the application.
+#[derive(Debug)]
+struct PendingEvent {
+    wd: c_int,
+    mask: u32,
+    cookie: u32,
+    name: Option<String>,
+}
+
+impl PendingEvent {
+    /// Length of the name in the `struct inotify_event`, with the null terminator and the padding.
+    fn name_len(&self) -> usize {
+        self.name
+            .as_ref()
+            .map(|name| (name.len() + 1).next_multiple_of(EVENT_HEADER_SIZE))
+            .unwrap_or_default()
+    }
+
+    fn encoded_len(&self) -> usize {
+        EVENT_HEADER_SIZE + self.name_len()
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let name_len = self.name_len();
+
+        bytes.extend_from_slice(&self.wd.to_ne_bytes());
+        bytes.extend_from_slice(&self.mask.to_ne_bytes());
+        bytes.extend_from_slice(&self.cookie.to_ne_bytes());
+        bytes.extend_from_slice(&(name_len as u32).to_ne_bytes());
+
+        if let Some(name) = &self.name {
+            let start = bytes.len();
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.resize(start + name_len, 0);
+        }
+    }
+}
+
+/// Registers a new inotify instance of the application.
+fn register_instance(fd: RawFd) -> RawFd {
+    if fd != -1 {
+        instances().insert(
+            fd,
+            Instance {
+                next_wd: REMOTE_WD_BASE,
+                ..Default::default()
+            },
+        );
+    }
+
+    fd
+}
+
+/// Forgets the inotify instance `fd` when it's closed, removing its remote watches.
+pub(crate) fn forget_instance(fd: RawFd) {
+    let Some(instance) = instances().remove(&fd) else {
+        return;
+    };
+
+    for watch in instance.watches.into_values() {
+        let _ = common::make_proxy_request_no_response(WatchRemoveRequest {
+            watch_id: watch.watch_id,
+        });
+    }
+}
+
+/// Whether `fd` is an inotify instance with remote watches, whose reads go through [`read`].
+///
+/// Called on every `read`, so it doesn't lock the [`INSTANCES`] until there was a remote watch.
+pub(crate) fn has_remote_watches(fd: RawFd) -> bool {
+    POLLER.is_completed()
+        && instances()
+            .get(&fd)
+            .is_some_and(|instance| instance.wake_file.is_some())
+}
+
+/// Adds a watch of `path` to the inotify instance `fd` in the agent, when the path goes to the
+/// remote filesystem.
+#[mirrord_layer_macro::instrument(level = "trace", ret)]
+fn add_watch(fd: RawFd, path: Detour<PathBuf>, mask: u32) -> Detour<c_int> {
+    if !instances().contains_key(&fd) {
+        return Detour::Bypass(Bypass::LocalFdNotFound(fd));
+    }
+
+    let path = remote_path(path?)?;
+    crate::setup().file_filter().continue_or_bypass_with(
+        path.to_str().unwrap_or_default(),
+        false,
+        || Bypass::IgnoredFile(path.clone()),
+    )?;
+    crate::simulation::bypass("inotify watch", path.display())?;
+
+    let existing = instances().get(&fd).and_then(|instance| {
+        instance
+            .watches
+            .iter()
+            .find(|(_, watch)| watch.path == path)
+            .map(|(wd, watch)| (*wd, watch.watch_id, watch.mask))
+    });
+
+    // With `IN_MASK_ADD`, the events are added to the ones of the existing watch.
+    let mask = match existing {
+        Some((.., existing_mask)) if mask & libc::IN_MASK_ADD != 0 => {
+            (mask | existing_mask) & !libc::IN_MASK_ADD
+        }
+        _ => mask & !libc::IN_MASK_ADD,
+    };
+
+    let WatchAddResponse { watch_id } =
+        common::make_proxy_request_with_response(WatchAddRequest {
+            path: path.clone(),
+            mask,
+        })??;
+
+    let mut instances = instances();
+    let Some(instance) = instances.get_mut(&fd) else {
+        // Closed in the meantime.
+        drop(instances);
+        let _ = common::make_proxy_request_no_response(WatchRemoveRequest { watch_id });
+        return Detour::Error(io::Error::from_raw_os_error(libc::EBADF).into());
+    };
+
+    if let Err(error) = instance.ensure_wake_file(fd) {
+        drop(instances);
+        let _ = common::make_proxy_request_no_response(WatchRemoveRequest { watch_id });
+        return Detour::Error(error.into());
+    }
+
+    let wd = match existing {
+        Some((wd, old_watch_id, _)) => {
+            let _ = common::make_proxy_request_no_response(WatchRemoveRequest {
+                watch_id: old_watch_id,
+            });
+            wd
+        }
+        None => {
+            let wd = instance.next_wd;
+            instance.next_wd += 1;
+            wd
+        }
+    };
+    instance.watches.insert(
+        wd,
+        RemoteWatch {
+            watch_id,
+            path,
+            mask,
+        },
+    );
+    drop(instances);
+
+    POLLER.call_once(spawn_poller);
+
+    Detour::Success(wd)
+}
+
+/// Removes the remote watch `wd` from the inotify instance `fd`.
+#[mirrord_layer_macro::instrument(level = "trace", ret)]
+fn rm_watch(fd: RawFd, wd: c_int) -> Detour<c_int> {
+    let mut instances = instances();
+    let instance = instances.get_mut(&fd).ok_or(Bypass::LocalFdNotFound(fd))?;
+    let watch = instance
+        .watches
+        .remove(&wd)
+        .ok_or(Bypass::LocalFdNotFound(fd))?;
+
+    // The kernel reports `IN_IGNORED` when a watch is removed.
+    instance.pending.retain(|event| event.wd != wd);
+    instance.pending.push_back(PendingEvent {
+        wd,
+        mask: libc::IN_IGNORED,
+        cookie: 0,
+        name: None,
+    });
+    instance.wake();
+    drop(instances);
+
+    let _ = common::make_proxy_request_no_response(WatchRemoveRequest {
+        watch_id: watch.watch_id,
+    });
+
+    Detour::Success(0)
+}
+
+/// Reads from the inotify instance `fd` that has remote watches: the pending remote events first,
+/// otherwise the local ones, without the events of the [`WakeFile`].
+///
+/// # Safety
+///
+/// `out_buffer` must be valid for writes of `count` bytes.
+pub(crate) unsafe fn read(fd: RawFd, out_buffer: *mut c_void, count: size_t) -> ssize_t {
+    loop {
+        let (remote, wake_wd) = {
+            let mut instances = instances();
+            let Some(instance) = instances.get_mut(&fd) else {
+                return FN_READ(fd, out_buffer, count);
+            };
+
+            (instance.take_pending(count), instance.wake_wd())
+        };
+
+        let Some(remote) = remote else {
+            set_errno(Errno(libc::EINVAL));
+            return -1;
+        };
+        if !remote.is_empty() {
+            ptr::copy_nonoverlapping(remote.as_ptr(), out_buffer.cast(), remote.len());
+            return remote.len() as ssize_t;
+        }
+
+        let read_amount = FN_READ(fd, out_buffer, count);
+        let Ok(read_len) = usize::try_from(read_amount) else {
+            return read_amount;
+        };
+
+        let buffer = std::slice::from_raw_parts_mut(out_buffer.cast::<u8>(), read_len);
+        let kept = strip_events(buffer, wake_wd);
+        if kept > 0 || read_len == 0 {
+            return kept as ssize_t;
+        }
+
+        // Only the wake file changed: the remote events are pending now, unless another read
+        // took them already.
+        let nonblocking = libc::fcntl(fd, libc::F_GETFL) & libc::O_NONBLOCK != 0;
+        let pending = instances()
+            .get(&fd)
+            .is_some_and(|instance| !instance.pending.is_empty());
+        if nonblocking && !pending {
+            set_errno(Errno(libc::EAGAIN));
+            return -1;
+        }
+    }
+}
+
+/// Removes the events of the watch `wd` from the `struct inotify_event`s in `buffer`, moving the
+/// others to the front. Returns the length of the events that were kept.
+fn strip_events(buffer: &mut [u8], wd: Option<c_int>) -> usize {
+    let Some(wd) = wd else {
+        return buffer.len();
+    };
+
+    let mut read_from = 0;
+    let mut kept = 0;
+    while read_from + EVENT_HEADER_SIZE <= buffer.len() {
+        // SAFETY: the kernel writes whole events, and we checked the length of the header.
+        let header = unsafe {
+            ptr::read_unaligned(buffer.as_ptr().add(read_from).cast::<libc::inotify_event>())
+        };
+        let event_len = (EVENT_HEADER_SIZE + header.len as usize).min(buffer.len() - read_from);
+
+        if header.wd != wd {
+            buffer.copy_within(read_from..read_from + event_len, kept);
+            kept += event_len;
+        }
+        read_from += event_len;
+    }
+
+    kept
+}
+
+/// Starts the thread that runs [`poll_remote_events`].
+fn spawn_poller() {
+    let spawned = thread::Builder::new()
+        .name(format!("{LAYER_THREAD_PREFIX}inotify"))
+        .spawn(|| {
+            if crate::setup().experimental().hide_layer_threads {
+                hide_current_thread();
+            }
+
+            let _guard = DetourGuard::new();
+            poll_remote_events();
+        });
+
+    if let Err(error) = spawned {
+        warn!(%error, "Failed to spawn the thread for remote inotify events");
+    }
+}
+
+/// Asks the agent for the events of the remote watches every [`POLL_INTERVAL`], and queues them
+/// in their [`Instance`]s.
+fn poll_remote_events() {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        if instances()
+            .values()
+            .all(|instance| instance.watches.is_empty())
+        {
+            continue;
+        }
+
+        match common::make_proxy_request_with_response(WatchEventsRequest) {
+            Ok(Ok(WatchEventsResponse { events })) if events.is_empty() => {}
+            Ok(Ok(WatchEventsResponse { events })) => queue_events(events),
+            Ok(Err(error)) => {
+                warn!(%error, "Failed to get the events of remote inotify watches");
+            }
+            Err(error) => {
+                warn!(%error, "Stopped getting the events of remote inotify watches");
+                return;
+            }
+        }
+    }
+}
+
+/// Queues the remote `events` in the [`Instance`]s that have their watches, and wakes them.
+fn queue_events(events: Vec<WatchEvent>) {
+    trace!(?events, "remote inotify events");
+
+    let mut instances = instances();
+    let mut woken = Vec::new();
+
+    for event in events {
+        let Some((fd, instance, wd)) = instances.iter_mut().find_map(|(fd, instance)| {
+            let wd = instance
+                .watches
+                .iter()
+                .find(|(_, watch)| watch.watch_id == event.watch_id)
+                .map(|(wd, _)| *wd)?;
+            Some((*fd, instance, wd))
+        }) else {
+            continue;
+        };
+
+        if event.mask & libc::IN_IGNORED != 0 {
+            instance.watches.remove(&wd);
+        }
+
+        instance.pending.push_back(PendingEvent {
+            wd,
+            mask: event.mask,
+            cookie: event.cookie,
+            name: event.name,
+        });
+        if !woken.contains(&fd) {
+            woken.push(fd);
+        }
+    }
+
+    for fd in woken {
+        if let Some(instance) = instances.get(&fd) {
+            instance.wake();
+        }
+    }
+}
+
+/// Hook for `libc::inotify_init`.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn inotify_init_detour() -> c_int {
+    register_instance(FN_INOTIFY_INIT())
+}
+
+/// Hook for `libc::inotify_init1`.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn inotify_init1_detour(flags: c_int) -> c_int {
+    register_instance(FN_INOTIFY_INIT1(flags))
+}
+
+/// Hook for `libc::inotify_add_watch`.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn inotify_add_watch_detour(
+    fd: c_int,
+    pathname: *const c_char,
+    mask: u32,
+) -> c_int {
+    add_watch(fd, pathname.checked_into(), mask).unwrap_or_bypass_with(|bypass| {
+        let pathname = update_ptr_from_bypass(pathname, &bypass);
+        FN_INOTIFY_ADD_WATCH(fd, pathname, mask)
+    })
+}
+
+/// Hook for `libc::inotify_rm_watch`.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn inotify_rm_watch_detour(fd: c_int, wd: c_int) -> c_int {
+    rm_watch(fd, wd).unwrap_or_bypass_with(|_| FN_INOTIFY_RM_WATCH(fd, wd))
+}
+
+pub(crate) unsafe fn enable_inotify_hooks(hook_manager: &mut HookManager) {
+    replace!(
+        hook_manager,
+        "inotify_init",
+        inotify_init_detour,
+        FnInotify_init,
+        FN_INOTIFY_INIT
+    );
+    replace!(
+        hook_manager,
+        "inotify_init1",
+        inotify_init1_detour,
+        FnInotify_init1,
+        FN_INOTIFY_INIT1
+    );
+    replace!(
+        hook_manager,
+        "inotify_add_watch",
+        inotify_add_watch_detour,
+        FnInotify_add_watch,
+        FN_INOTIFY_ADD_WATCH
+    );
+    replace!(
+        hook_manager,
+        "inotify_rm_watch",
+        inotify_rm_watch_detour,
+        FnInotify_rm_watch,
+        FN_INOTIFY_RM_WATCH
+    );
+}
@@ -1,13 +1,28 @@
 //! Implementation of directory listing in the layer. Used in hooks like `opendir`, `closedir` or
 //! `readdir` family.
+//!
+//! The entries are read from the agent in batches, together with their metadata, which is kept
+//! for a short while to answer the `stat` calls that usually follow (e.g. `ls -l`).
 
 use std::{
+    collections::VecDeque,
     ffi::CString,
-    sync::{Arc, LazyLock, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use dashmap::DashMap;
-use mirrord_protocol::file::{CloseDirRequest, DirEntryInternal, ReadDirRequest, ReadDirResponse};
+use mirrord_protocol::{
+    file::{
+        CloseDirRequest, DirEntryInternal, MetadataInternal, ReadDirBatchRequest,
+        ReadDirBatchResponse, ReadDirRequest, ReadDirResponse,
+    },
+    ResponseError,
+};
 
 use super::{DirStreamFd, LocalFd, RemoteFd, OPEN_FILES};
 use crate::{
@@ -19,9 +34,23 @@ use crate::{
 /// Global instance of [`OpenDirs`]. Used in hooks.
 pub static OPEN_DIRS: LazyLock<OpenDirs> = LazyLock::new(OpenDirs::new);
 
+/// Number of entries asked for in one [`ReadDirBatchRequest`].
+const READ_DIR_BATCH_SIZE: u64 = 512;
+
+/// How long the metadata that came with the directory entries can be used instead of asking the
+/// agent, see [`OpenDirs::cached_metadata`].
+const METADATA_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// Cleared when the agent doesn't support [`ReadDirBatchRequest`], then we read the entries one
+/// by one.
+static READ_DIR_BATCH_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
 /// State related to open remote directories.
 pub struct OpenDirs {
     inner: DashMap<DirStreamFd, Arc<Mutex<OpenDir>>>,
+    /// Metadata of the entries read with [`ReadDirBatchRequest`]s, by their absolute path, with
+    /// the time it was read.
+    metadata: DashMap<PathBuf, (Instant, MetadataInternal)>,
 }
 
 impl OpenDirs {
@@ -29,6 +58,37 @@ impl OpenDirs {
     fn new() -> Self {
         Self {
             inner: DashMap::with_capacity(4),
+            metadata: Default::default(),
+        }
+    }
+
+    /// Returns the metadata of the file at the absolute `path`, if it was read with its
+    /// directory's entries in the last [`METADATA_CACHE_TTL`].
+    ///
+    /// The metadata is used once, the next `stat` of the same file goes to the agent. When
+    /// `follow_symlink` is set, symlinks are not returned, as we have the metadata of the link
+    /// itself.
+    pub fn cached_metadata(&self, path: &Path, follow_symlink: bool) -> Option<MetadataInternal> {
+        let (_, (_, metadata)) = self.metadata.remove_if(path, |_, (read_at, metadata)| {
+            let is_symlink = metadata.mode & libc::S_IFMT as u32 == libc::S_IFLNK as u32;
+            read_at.elapsed() <= METADATA_CACHE_TTL && !(follow_symlink && is_symlink)
+        })?;
+
+        Some(metadata)
+    }
+
+    /// Remembers the metadata of the entries of the directory at the absolute `dir_path`, and
+    /// forgets the outdated ones.
+    fn cache_metadata<'a, I>(&self, dir_path: &Path, entries: I)
+    where
+        I: IntoIterator<Item = (&'a str, MetadataInternal)>,
+    {
+        let now = Instant::now();
+        self.metadata
+            .retain(|_, (read_at, _)| now.duration_since(*read_at) <= METADATA_CACHE_TTL);
+
+        for (name, metadata) in entries {
+            self.metadata.insert(dir_path.join(name), (now, metadata));
         }
     }
 
@@ -39,9 +99,15 @@ impl OpenDirs {
     /// * `local_dir_fd` - opaque identifier
     /// * `remote_fd` - descriptor of the remote directory (received from the agent)
     pub fn insert(&self, local_dir_fd: DirStreamFd, remote_fd: RemoteFd, base_fd: LocalFd) {
+        // Without an absolute path we can't match the `stat` calls with the entries.
+        let path = OPEN_FILES
+            .get(&base_fd)
+            .map(|file| PathBuf::from(&file.path))
+            .filter(|path| path.is_absolute());
+
         self.inner.insert(
             local_dir_fd,
-            Mutex::new(OpenDir::new(local_dir_fd, remote_fd, base_fd)).into(),
+            Mutex::new(OpenDir::new(local_dir_fd, remote_fd, base_fd, path)).into(),
         );
     }
 
@@ -53,9 +119,9 @@ impl OpenDirs {
             .ok_or(Bypass::LocalDirStreamNotFound(local_dir_fd))?
             .clone();
 
-        let guard = dir.lock().expect("lock poisoned");
+        let mut guard = dir.lock().expect("lock poisoned");
 
-        guard.read_r()
+        guard.read_r(self)
     }
 
     /// Gets fd used to open dir [`DirStreamFd`].
@@ -92,7 +158,7 @@ impl OpenDirs {
 
         let mut guard = dir.lock().expect("lock poisoned");
 
-        let Some(entry) = guard.read_r()? else {
+        let Some(entry) = guard.read_r(self)? else {
             return Detour::Success(std::ptr::null());
         };
 
@@ -123,7 +189,7 @@ impl OpenDirs {
 
         let mut guard = dir.lock().expect("lock poisoned");
 
-        let Some(entry) = guard.read_r()? else {
+        let Some(entry) = guard.read_r(self)? else {
             return Detour::Success(std::ptr::null());
         };
 
@@ -156,13 +222,24 @@ struct OpenDir {
     remote_fd: RemoteFd,
     // fd used for opening the dir originally
     base_fd: LocalFd,
+    /// Absolute path of the directory, if known.
+    path: Option<PathBuf>,
+    /// Entries of the last [`ReadDirBatchResponse`] that were not yet returned.
+    batch: VecDeque<DirEntryInternal>,
+    /// We got an empty [`ReadDirBatchResponse`].
+    finished: bool,
     dirent: libc::dirent,
     #[cfg(target_os = "linux")]
     dirent64: libc::dirent64,
 }
 
 impl OpenDir {
-    fn new(local_fd: DirStreamFd, remote_fd: RemoteFd, base_fd: LocalFd) -> Self {
+    fn new(
+        local_fd: DirStreamFd,
+        remote_fd: RemoteFd,
+        base_fd: LocalFd,
+        path: Option<PathBuf>,
+    ) -> Self {
         #[cfg(not(target_os = "macos"))]
         let dirent = libc::dirent {
             d_ino: 0,
@@ -187,6 +264,9 @@ impl OpenDir {
             local_fd,
             remote_fd,
             base_fd,
+            path,
+            batch: Default::default(),
+            finished: false,
             dirent,
             #[cfg(target_os = "linux")]
             dirent64: libc::dirent64 {
@@ -199,12 +279,49 @@ impl OpenDir {
         }
     }
 
-    fn read_r(&self) -> Detour<Option<DirEntryInternal>> {
+    /// Returns the next entry, reading the next batch of entries when needed. The metadata of the
+    /// entries goes to the `open_dirs` cache.
+    fn read_r(&mut self, open_dirs: &OpenDirs) -> Detour<Option<DirEntryInternal>> {
         if self.closed {
             // This thread got this struct from `OpenDirs` before `close` removed it.
             return Detour::Bypass(Bypass::LocalDirStreamNotFound(self.local_fd));
         }
 
+        if let Some(entry) = self.batch.pop_front() {
+            return Detour::Success(Some(entry));
+        } else if self.finished {
+            return Detour::Success(None);
+        }
+
+        if READ_DIR_BATCH_SUPPORTED.load(Ordering::Relaxed) {
+            let response = common::make_proxy_request_with_response(ReadDirBatchRequest {
+                remote_fd: self.remote_fd,
+                amount: READ_DIR_BATCH_SIZE,
+            })?;
+
+            match response {
+                Ok(ReadDirBatchResponse { entries }) => {
+                    if let Some(path) = self.path.as_deref() {
+                        open_dirs.cache_metadata(
+                            path,
+                            entries
+                                .iter()
+                                .map(|(entry, metadata)| (entry.name.as_str(), *metadata)),
+                        );
+                    }
+
+                    self.batch = entries.into_iter().map(|(entry, _)| entry).collect();
+                    self.finished = self.batch.is_empty();
+
+                    return Detour::Success(self.batch.pop_front());
+                }
+                Err(ResponseError::NotImplemented) => {
+                    READ_DIR_BATCH_SUPPORTED.store(false, Ordering::Relaxed);
+                }
+                Err(error) => return Detour::Error(error.into()),
+            }
+        }
+
         let ReadDirResponse { direntry } =
             common::make_proxy_request_with_response(ReadDirRequest {
                 remote_fd: self.remote_fd,
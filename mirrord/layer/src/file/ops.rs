@@ -1,13 +1,31 @@
 #[cfg(target_os = "linux")]
-use std::time::Duration;
-use std::{env, ffi::CString, io::SeekFrom, os::unix::io::RawFd, path::PathBuf};
+use std::path::Component;
+use std::{
+    env,
+    ffi::{CString, OsStr},
+    io::{self, SeekFrom},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        io::RawFd,
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
 
 #[cfg(target_os = "linux")]
 use libc::{c_char, statx, statx_timestamp};
 use libc::{c_int, iovec, unlink, AT_FDCWD};
-use mirrord_protocol::file::{
-    OpenFileRequest, OpenFileResponse, OpenOptionsInternal, ReadFileResponse, ReadLinkFileRequest,
-    ReadLinkFileResponse, SeekFileResponse, WriteFileResponse, XstatFsResponse, XstatResponse,
+use mirrord_protocol::{
+    file::{
+        CanonicalizeRequest, CanonicalizeResponse, FileLock, FileLockType, FlockOperation,
+        FlockRequest, GetLockRequest, GetLockResponse, OpenFileRequest, OpenFileResponse,
+        OpenImageFileRequest, OpenOptionsInternal, ReadFileResponse, ReadLinkFileRequest,
+        ReadLinkFileResponse, SeekFileResponse, SetLockRequest, WriteFileResponse, XstatFsResponse,
+        XstatResponse,
+    },
+    ErrorKindInternal, RemoteIOError, RemoteResult, ResponseError,
 };
 use rand::distributions::{Alphanumeric, DistString};
 use tracing::{error, trace};
@@ -24,10 +42,24 @@ use crate::{
 /// 1 Megabyte. Large read requests can lead to timeouts.
 const MAX_READ_SIZE: u64 = 1024 * 1024;
 
+/// 64 Kilobytes, replaces [`MAX_READ_SIZE`] when
+/// [`ExperimentalConfig::low_memory`](mirrord_config::experimental::ExperimentalConfig::low_memory)
+/// is enabled.
+const LOW_MEMORY_MAX_READ_SIZE: u64 = 64 * 1024;
+
+/// How long we wait before retrying a remote operation that would block, see
+/// [`retry_while_would_block`].
+const RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Cleared when the agent doesn't support [`CanonicalizeRequest`], then we don't resolve the
+/// remote symlinks, see [`resolve_remote_symlinks`].
+static CANONICALIZE_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
 /// Helper macro for checking if the given path should be handled remotely.
 /// Uses global [`crate::setup()`].
 ///
-/// Should the file be ignored, this macro exists current context with [`Bypass::IgnoredFile`].
+/// Should the file be ignored, this macro exists current context with [`Bypass::IgnoredFile`], or
+/// with [`Bypass::Simulated`] in [`simulation`](crate::simulation) mode.
 ///
 /// # Arguments
 ///
@@ -40,6 +72,7 @@ macro_rules! ensure_not_ignored {
             $write,
             || Bypass::IgnoredFile($path.clone()),
         )?;
+        crate::simulation::bypass("file", $path.display())?;
     };
 }
 
@@ -67,6 +100,17 @@ impl RemoteFile {
         Detour::Success(response)
     }
 
+    /// Sends a [`OpenImageFileRequest`] message, opening the file from the container image in the
+    /// agent.
+    #[mirrord_layer_macro::instrument(level = "trace")]
+    pub(crate) fn remote_open_image(path: PathBuf) -> Detour<OpenFileResponse> {
+        let requesting_file = OpenImageFileRequest { path };
+
+        let response = common::make_proxy_request_with_response(requesting_file)??;
+
+        Detour::Success(response)
+    }
+
     /// Sends a [`ReadFileRequest`] message, reading the file in the agent.
     ///
     /// Blocking request and wait on already found remote_fd
@@ -74,7 +118,12 @@ impl RemoteFile {
     pub(crate) fn remote_read(remote_fd: u64, read_amount: u64) -> Detour<ReadFileResponse> {
         // Limit read size because if we read too much it can lead to a timeout
         // Seems also that bincode doesn't do well with large buffers
-        let read_amount = std::cmp::min(read_amount, MAX_READ_SIZE);
+        let max_read_size = if crate::setup().experimental().low_memory {
+            LOW_MEMORY_MAX_READ_SIZE
+        } else {
+            MAX_READ_SIZE
+        };
+        let read_amount = std::cmp::min(read_amount, max_read_size);
         let reading_file = ReadFileRequest {
             remote_fd,
             buffer_size: read_amount,
@@ -120,6 +169,80 @@ fn get_remote_fd(local_fd: RawFd) -> Detour<u64> {
     )
 }
 
+/// Name of the environment variable that holds
+/// [`FsConfig::cwd`](mirrord_config::feature::fs::FsConfig::cwd).
+///
+/// Once the remote working directory is fetched, we store it here, so that child processes don't
+/// have to fetch it again.
+const REMOTE_CWD_ENV: &str = "MIRRORD_FILE_CWD";
+
+/// Path we read from the agent to get the working directory of the target container.
+const TARGET_CWD_LINK: &str = "/proc/1/cwd";
+
+/// Initializes the [`REMOTE_CWD`] from the given `cwd` config value.
+///
+/// When `cwd` is [`FsConfig::REMOTE_CWD`](mirrord_config::feature::fs::FsConfig::REMOTE_CWD),
+/// the working directory of the target container is fetched from the agent. If that fails, the
+/// [`REMOTE_CWD`] remains unset, and relative paths are handled locally.
+pub(crate) fn init_remote_cwd(cwd: &str) {
+    let cwd = if cwd == mirrord_config::feature::fs::FsConfig::REMOTE_CWD {
+        let request = ReadLinkFileRequest {
+            path: TARGET_CWD_LINK.into(),
+        };
+
+        match common::make_proxy_request_with_response(request) {
+            Ok(Ok(ReadLinkFileResponse { path })) => path,
+            fail => {
+                error!(
+                    ?fail,
+                    "Failed to fetch the working directory of the target, relative paths will be \
+                    handled locally."
+                );
+                return;
+            }
+        }
+    } else {
+        PathBuf::from(cwd)
+    };
+
+    env::set_var(REMOTE_CWD_ENV, &cwd);
+    let _ = REMOTE_CWD.set(cwd);
+}
+
+/// Resolves a relative `path` against the [`REMOTE_CWD`].
+///
+/// Bypasses with [`Bypass::RelativePath`] when the path is relative and the [`REMOTE_CWD`] is not
+/// set, as we don't know what the path is relative to.
+fn absolute_remote_path(path: PathBuf) -> Detour<PathBuf> {
+    if path.is_absolute() {
+        Detour::Success(path)
+    } else if let Some(cwd) = REMOTE_CWD.get() {
+        Detour::Success(absolute_path(cwd.join(path)))
+    } else {
+        Detour::Bypass(Bypass::RelativePath(path))
+    }
+}
+
+/// Makes `path` absolute with [`absolute_remote_path`], then rewrites it with
+/// [`FsConfig::mapping`](mirrord_config::feature::fs::FsConfig::mapping), giving the path of the
+/// file in the target.
+pub(crate) fn remote_path(path: PathBuf) -> Detour<PathBuf> {
+    let path = absolute_remote_path(path)?;
+
+    Detour::Success(crate::setup().file_filter().remap(&path).unwrap_or(path))
+}
+
+/// Checks if `path` should be read from the target container's image, see
+/// [`FileFilter::is_image_path`](super::filter::FileFilter::is_image_path).
+fn is_image_path(path: &Path) -> bool {
+    crate::setup().file_filter().is_image_path(path)
+}
+
+/// Returns the [`REMOTE_CWD`] for `getcwd`, bypasses when it's not set.
+pub(crate) fn getcwd() -> Detour<&'static Path> {
+    Detour::Success(REMOTE_CWD.get()?.as_path())
+}
+
 /// Create temporary local file to get a valid local fd.
 #[mirrord_layer_macro::instrument(level = "trace", ret)]
 fn create_local_fake_file(remote_fd: u64) -> Detour<RawFd> {
@@ -159,14 +282,15 @@ fn close_remote_file_on_failure(fd: u64) -> Result<()> {
 /// [`OPEN_FILES`].
 #[mirrord_layer_macro::instrument(level = "trace", ret)]
 pub(crate) fn open(path: Detour<PathBuf>, open_options: OpenOptionsInternal) -> Detour<RawFd> {
-    let path = path?;
+    // Calls with relative paths are sent to libc::open, unless we have the remote cwd.
+    let path = remote_path(path?)?;
 
-    if path.is_relative() {
-        // Calls with non absolute paths are sent to libc::open.
-        Detour::Bypass(Bypass::RelativePath(path.clone()))?
-    };
+    if is_image_path(&path) {
+        return open_image(path, open_options);
+    }
 
     ensure_not_ignored!(path, open_options.is_write());
+    resolve_remote_symlinks(&path, open_options.is_write())?;
 
     let OpenFileResponse { fd: remote_fd } = RemoteFile::remote_open(path.clone(), open_options)?;
 
@@ -183,6 +307,82 @@ pub(crate) fn open(path: Detour<PathBuf>, open_options: OpenOptionsInternal) ->
     Detour::Success(local_file_fd)
 }
 
+/// Resolves the symlinks of the absolute remote `path` in the target, and classifies it by where
+/// they lead, when
+/// [`ExperimentalConfig::resolve_remote_symlinks`](mirrord_config::experimental::ExperimentalConfig::resolve_remote_symlinks)
+/// is set.
+///
+/// Returns the resolved path when we handle it remotely as well. A symlink to a path that we don't
+/// handle remotely is bypassed with [`Bypass::ResolvedLocal`], so that the operation happens
+/// locally, on the path it points to. Too many levels of symlinks fail with `ELOOP`, like they
+/// would in the target.
+///
+/// We return [`None`] when the path can't be resolved (e.g. it doesn't exist yet), it's classified
+/// as it is then.
+fn resolve_remote_symlinks(path: &Path, write: bool) -> Detour<Option<PathBuf>> {
+    if !crate::setup().experimental().resolve_remote_symlinks
+        || !CANONICALIZE_SUPPORTED.load(Ordering::Relaxed)
+    {
+        return Detour::Success(None);
+    }
+
+    let response = common::make_proxy_request_with_response(CanonicalizeRequest {
+        path: path.to_path_buf(),
+    })?;
+    let resolved = match response {
+        Ok(CanonicalizeResponse { path }) => path,
+        Err(ResponseError::NotImplemented) => {
+            CANONICALIZE_SUPPORTED.store(false, Ordering::Relaxed);
+            return Detour::Success(None);
+        }
+        Err(
+            error @ ResponseError::RemoteIO(RemoteIOError {
+                raw_os_error: Some(libc::ELOOP),
+                ..
+            }),
+        ) => return Detour::Error(error.into()),
+        Err(..) => return Detour::Success(None),
+    };
+
+    if resolved != path {
+        crate::setup().file_filter().continue_or_bypass_with(
+            resolved.to_str().unwrap_or_default(),
+            write,
+            || {
+                CString::new(resolved.clone().into_os_string().into_vec()).map_or_else(
+                    |_| Bypass::IgnoredFile(resolved.clone()),
+                    Bypass::ResolvedLocal,
+                )
+            },
+        )?;
+    }
+
+    Detour::Success(Some(resolved))
+}
+
+/// [`open`] for paths from the target container's image.
+///
+/// These files are always read from the image, regardless of the other fs settings, but they can't
+/// be written, so opening them for writing is bypassed.
+#[mirrord_layer_macro::instrument(level = "trace", ret)]
+fn open_image(path: PathBuf, open_options: OpenOptionsInternal) -> Detour<RawFd> {
+    if open_options.is_write() {
+        return Detour::Bypass(Bypass::ReadOnly(path));
+    }
+
+    crate::simulation::bypass("image file", path.display())?;
+    let OpenFileResponse { fd: remote_fd } = RemoteFile::remote_open_image(path.clone())?;
+
+    let local_file_fd = create_local_fake_file(remote_fd)?;
+
+    OPEN_FILES.insert(
+        local_file_fd,
+        Arc::new(RemoteFile::new(remote_fd, path.display().to_string())),
+    );
+
+    Detour::Success(local_file_fd)
+}
+
 /// creates a directory stream for the `remote_fd` in the agent
 #[mirrord_layer_macro::instrument(level = "trace", ret)]
 pub(crate) fn fdopendir(fd: RawFd) -> Detour<usize> {
@@ -243,12 +443,179 @@ pub(crate) fn openat(
     }
 }
 
+/// `RESOLVE_*` flags of `openat2`, from `linux/openat2.h`.
+#[cfg(target_os = "linux")]
+pub(crate) mod resolve {
+    pub(crate) const RESOLVE_NO_XDEV: u64 = 0x01;
+    pub(crate) const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+    pub(crate) const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+    pub(crate) const RESOLVE_BENEATH: u64 = 0x08;
+    pub(crate) const RESOLVE_IN_ROOT: u64 = 0x10;
+    pub(crate) const RESOLVE_CACHED: u64 = 0x20;
+}
+
+/// [`openat`] with the `resolve` flags of `openat2`.
+///
+/// Paths are classified exactly like with [`openat`], the flags only matter for remote files. The
+/// agent opens paths like `openat` does, so we honor a subset of the flags here, and we're stricter
+/// than the kernel where we can't do the same:
+///
+/// - `RESOLVE_NO_SYMLINKS` and `RESOLVE_NO_MAGICLINKS`: every component of the path is checked with
+///   a remote `lstat` before the open, any symlink fails with `ELOOP`;
+/// - `RESOLVE_BENEATH`: absolute paths, `..` components that leave the directory and symlinks fail
+///   with `EXDEV`;
+/// - `RESOLVE_CACHED`: fails with `EAGAIN`, remote paths are never in the kernel's cache, so the
+///   application retries without it;
+/// - `RESOLVE_NO_XDEV` and `RESOLVE_IN_ROOT`: fail with `ENOSYS`, as if the kernel didn't have
+///   `openat2`, so the application falls back to `openat`. So do all flags for the paths from the
+///   target container's image, which we can't `lstat`.
+///
+/// The checks and the open are separate requests, so a remote path that changes in between is not
+/// caught.
+#[cfg(target_os = "linux")]
+#[mirrord_layer_macro::instrument(level = "trace", ret)]
+pub(crate) fn openat2(
+    fd: RawFd,
+    path: Detour<PathBuf>,
+    open_options: OpenOptionsInternal,
+    resolve: u64,
+) -> Detour<RawFd> {
+    use self::resolve::*;
+
+    let path = path?;
+    if resolve == 0 {
+        return openat(fd, Detour::Success(path), open_options);
+    }
+
+    // Bypassed like `openat`, before anything is sent to the agent.
+    let mut image = false;
+    let remote_dir_fd = if path.is_absolute() || fd == AT_FDCWD {
+        let remote_path = remote_path(path.clone())?;
+        image = is_image_path(&remote_path);
+        if !image {
+            ensure_not_ignored!(remote_path, open_options.is_write());
+        } else if open_options.is_write() {
+            return Detour::Bypass(Bypass::ReadOnly(remote_path));
+        }
+        None
+    } else {
+        Some(get_remote_fd(fd)?)
+    };
+
+    let fail = |errno| Detour::Error(io::Error::from_raw_os_error(errno).into());
+
+    if image || resolve & (RESOLVE_NO_XDEV | RESOLVE_IN_ROOT) != 0 {
+        return fail(libc::ENOSYS);
+    }
+    if resolve & RESOLVE_CACHED != 0 {
+        return fail(libc::EAGAIN);
+    }
+    if resolve & RESOLVE_BENEATH != 0 && leaves_dir(&path) {
+        return fail(libc::EXDEV);
+    }
+
+    if resolve & (RESOLVE_NO_SYMLINKS | RESOLVE_NO_MAGICLINKS) != 0 {
+        check_no_symlinks(remote_dir_fd, &path, libc::ELOOP)?;
+    } else if resolve & RESOLVE_BENEATH != 0 {
+        check_no_symlinks(remote_dir_fd, &path, libc::EXDEV)?;
+    }
+
+    openat(fd, Detour::Success(path), open_options)
+}
+
+/// Whether the `path` is absolute, or goes above the directory it's relative to with `..`.
+#[cfg(target_os = "linux")]
+fn leaves_dir(path: &Path) -> bool {
+    let mut depth = 0_usize;
+
+    for component in path.components() {
+        match component {
+            Component::Normal(..) => depth += 1,
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return true,
+            },
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(..) => return true,
+        }
+    }
+
+    false
+}
+
+/// Fails with `errno` if a component of the remote `path` is a symlink, for [`openat2`].
+///
+/// The `path` is relative to the remote directory `remote_dir_fd`, or to the remote cwd when it's
+/// [`None`]. We stop at the first component that we can't `lstat`, the open reports the error (or
+/// creates the file).
+#[cfg(target_os = "linux")]
+fn check_no_symlinks(remote_dir_fd: Option<u64>, path: &Path, errno: c_int) -> Detour<()> {
+    let mut walked = PathBuf::new();
+
+    for component in path.components() {
+        walked.push(component);
+        if !matches!(component, Component::Normal(..)) {
+            continue;
+        }
+
+        let request = match remote_dir_fd {
+            Some(remote_dir_fd) => XstatRequest {
+                path: Some(walked.clone()),
+                fd: Some(remote_dir_fd),
+                follow_symlink: false,
+            },
+            None => XstatRequest {
+                path: Some(remote_path(walked.clone())?),
+                fd: None,
+                follow_symlink: false,
+            },
+        };
+
+        match common::make_proxy_request_with_response(request)? {
+            Ok(XstatResponse { metadata }) if metadata.mode & libc::S_IFMT == libc::S_IFLNK => {
+                return Detour::Error(io::Error::from_raw_os_error(errno).into());
+            }
+            Ok(..) => {}
+            Err(..) => break,
+        }
+    }
+
+    Detour::Success(())
+}
+
 /// Blocking wrapper around [`libc::read`] call.
 ///
 /// **Bypassed** when trying to load system files, and files from the current working directory, see
 /// `open`.
 pub(crate) fn read(local_fd: RawFd, read_amount: u64) -> Detour<ReadFileResponse> {
-    get_remote_fd(local_fd).and_then(|remote_fd| RemoteFile::remote_read(remote_fd, read_amount))
+    get_remote_fd(local_fd).and_then(|remote_fd| {
+        retry_while_would_block(!is_nonblocking(local_fd), || {
+            RemoteFile::remote_read(remote_fd, read_amount)
+        })
+    })
+}
+
+/// Repeats `op` while it fails with `EAGAIN`, if the application expects the call to `block`.
+///
+/// The agent never blocks, neither on the named pipes (FIFOs) of the target (see
+/// [`AllowFifosRequest`](mirrord_protocol::file::AllowFifosRequest)) nor on file locks, so we wait
+/// for the data, the room in the pipe or the lock here instead.
+fn retry_while_would_block<T>(block: bool, op: impl Fn() -> Detour<T>) -> Detour<T> {
+    loop {
+        match op() {
+            Detour::Error(HookError::ResponseError(ResponseError::RemoteIO(RemoteIOError {
+                kind: ErrorKindInternal::WouldBlock,
+                ..
+            }))) if block => thread::sleep(RETRY_INTERVAL),
+            result => return result,
+        }
+    }
+}
+
+/// Whether the application made `local_fd` non-blocking.
+fn is_nonblocking(local_fd: RawFd) -> bool {
+    let flags = unsafe { libc::fcntl(local_fd, libc::F_GETFL) };
+    flags != -1 && flags & libc::O_NONBLOCK != 0
 }
 
 /// Helper for dealing with a potential null pointer being passed to `*const iovec` from
@@ -282,12 +649,8 @@ pub(crate) fn pread(local_fd: RawFd, buffer_size: u64, offset: u64) -> Detour<Re
 #[mirrord_layer_macro::instrument(level = "trace", ret)]
 pub(crate) fn read_link(path: Detour<PathBuf>) -> Detour<ReadLinkFileResponse> {
     if crate::setup().experimental().readlink {
-        let path = path?;
-
-        if path.is_relative() {
-            // Calls with non absolute paths are sent to libc::readlink.
-            Detour::Bypass(Bypass::RelativePath(path.clone()))?
-        };
+        // Calls with relative paths are sent to libc::readlink, unless we have the remote cwd.
+        let path = remote_path(path?)?;
 
         ensure_not_ignored!(path, false);
 
@@ -347,25 +710,121 @@ pub(crate) fn lseek(local_fd: RawFd, offset: i64, whence: i32) -> Detour<u64> {
 pub(crate) fn write(local_fd: RawFd, write_bytes: Option<Vec<u8>>) -> Detour<isize> {
     let remote_fd = get_remote_fd(local_fd)?;
 
-    let writing_file = WriteFileRequest {
-        fd: remote_fd,
-        write_bytes: write_bytes.ok_or(Bypass::EmptyBuffer)?,
-    };
+    let write_bytes = write_bytes.ok_or(Bypass::EmptyBuffer)?;
 
     let WriteFileResponse { written_amount } =
-        common::make_proxy_request_with_response(writing_file)??;
+        retry_while_would_block(!is_nonblocking(local_fd), || {
+            let writing_file = WriteFileRequest {
+                fd: remote_fd,
+                write_bytes: write_bytes.clone(),
+            };
+
+            Detour::Success(common::make_proxy_request_with_response(writing_file)??)
+        })?;
     Detour::Success(written_amount.try_into()?)
 }
 
+/// `flock` on a remote file, waits for the lock unless `LOCK_NB` is given.
 #[mirrord_layer_macro::instrument(level = "trace")]
-pub(crate) fn access(path: Detour<PathBuf>, mode: u8) -> Detour<c_int> {
-    let path = path?;
+pub(crate) fn flock(local_fd: RawFd, operation: c_int) -> Detour<c_int> {
+    let remote_fd = get_remote_fd(local_fd)?;
+
+    let lock_operation = match operation & !libc::LOCK_NB {
+        libc::LOCK_SH => FlockOperation::Shared,
+        libc::LOCK_EX => FlockOperation::Exclusive,
+        libc::LOCK_UN => FlockOperation::Unlock,
+        _ => return Detour::Error(io::Error::from_raw_os_error(libc::EINVAL).into()),
+    };
+
+    retry_while_would_block(operation & libc::LOCK_NB == 0, || {
+        lock_response(common::make_proxy_request_with_response(FlockRequest {
+            fd: remote_fd,
+            operation: lock_operation,
+        })?)
+    })?;
+
+    Detour::Success(0)
+}
+
+/// `fcntl(F_SETLK)`, `fcntl(F_SETLKW)` and `fcntl(F_GETLK)` on a remote file, `F_SETLKW` waits
+/// for the lock.
+///
+/// # Safety
+///
+/// `lock` must be null or point to a valid `struct flock`.
+#[mirrord_layer_macro::instrument(level = "trace")]
+pub(crate) unsafe fn fcntl_lock(
+    local_fd: RawFd,
+    cmd: c_int,
+    lock: *mut libc::flock,
+) -> Detour<c_int> {
+    let remote_fd = get_remote_fd(local_fd)?;
+    let Some(lock) = (unsafe { lock.as_mut() }) else {
+        return Detour::Error(io::Error::from_raw_os_error(libc::EFAULT).into());
+    };
 
-    if path.is_relative() {
-        // Calls with non absolute paths are sent to libc::open.
-        Detour::Bypass(Bypass::RelativePath(path.clone()))?
+    let lock_type = match c_int::from(lock.l_type) {
+        libc::F_RDLCK => FileLockType::Read,
+        libc::F_WRLCK => FileLockType::Write,
+        libc::F_UNLCK => FileLockType::Unlock,
+        _ => return Detour::Error(io::Error::from_raw_os_error(libc::EINVAL).into()),
+    };
+    let file_lock = FileLock {
+        lock_type,
+        whence: lock.l_whence,
+        start: lock.l_start,
+        len: lock.l_len,
+        pid: 0,
     };
 
+    if cmd != libc::F_GETLK {
+        retry_while_would_block(cmd == libc::F_SETLKW, || {
+            lock_response(common::make_proxy_request_with_response(SetLockRequest {
+                fd: remote_fd,
+                lock: file_lock.clone(),
+            })?)
+        })?;
+
+        return Detour::Success(0);
+    }
+
+    let GetLockResponse { conflicting } =
+        lock_response(common::make_proxy_request_with_response(GetLockRequest {
+            fd: remote_fd,
+            lock: file_lock,
+        })?)?;
+
+    match conflicting {
+        Some(conflicting) => {
+            lock.l_type = match conflicting.lock_type {
+                FileLockType::Read => libc::F_RDLCK,
+                FileLockType::Write => libc::F_WRLCK,
+                FileLockType::Unlock => libc::F_UNLCK,
+            } as _;
+            lock.l_whence = conflicting.whence;
+            lock.l_start = conflicting.start;
+            lock.l_len = conflicting.len;
+            lock.l_pid = conflicting.pid;
+        }
+        None => lock.l_type = libc::F_UNLCK as _,
+    }
+
+    Detour::Success(0)
+}
+
+/// Bypasses to the local file when the agent can't lock remote files.
+fn lock_response<T>(response: RemoteResult<T>) -> Detour<T> {
+    match response {
+        Err(ResponseError::NotImplemented) => Detour::Bypass(Bypass::RemoteLocksUnsupported),
+        response => Detour::Success(response?),
+    }
+}
+
+#[mirrord_layer_macro::instrument(level = "trace")]
+pub(crate) fn access(path: Detour<PathBuf>, mode: u8) -> Detour<c_int> {
+    // Calls with relative paths are sent to libc::access, unless we have the remote cwd.
+    let path = remote_path(path?)?;
+
     ensure_not_ignored!(path, false);
 
     let access = AccessFileRequest {
@@ -402,29 +861,39 @@ pub(crate) fn xstat(
         // fstatat
         (Some(path), Some(fd)) => {
             let path = path?;
-            let fd = {
-                if fd == AT_FDCWD {
-                    if path.is_relative() {
-                        // Calls with non absolute paths are sent to libc::fstatat.
-                        return Detour::Bypass(Bypass::RelativePath(path));
-                    } else {
-                        ensure_not_ignored!(path, false);
-                        None
-                    }
-                } else {
-                    Some(get_remote_fd(fd)?)
+            if fd == AT_FDCWD {
+                // Calls with relative paths are sent to libc::fstatat, unless we have the remote
+                // cwd.
+                let path = remote_path(path)?;
+                if is_image_path(&path) {
+                    return image_xstat(path);
                 }
-            };
-            (Some(path), fd)
+                ensure_not_ignored!(path, false);
+                if let Some(response) = cached_xstat(&path, follow_symlink) {
+                    return Detour::Success(response);
+                }
+                (Some(path), None)
+            } else {
+                let dir_path = OPEN_FILES.get(&fd).map(|dir| PathBuf::from(&dir.path));
+                if let Some(response) = dir_path
+                    .and_then(|dir_path| cached_xstat(&dir_path.join(&path), follow_symlink))
+                {
+                    return Detour::Success(response);
+                }
+                (Some(path), Some(get_remote_fd(fd)?))
+            }
         }
         // lstat/stat
         (Some(path), None) => {
-            let path = path?;
-            if path.is_relative() {
-                // Calls with non absolute paths are sent to libc::open.
-                return Detour::Bypass(Bypass::RelativePath(path));
+            // Calls with relative paths are sent to libc::stat, unless we have the remote cwd.
+            let path = remote_path(path?)?;
+            if is_image_path(&path) {
+                return image_xstat(path);
             }
             ensure_not_ignored!(path, false);
+            if let Some(response) = cached_xstat(&path, follow_symlink) {
+                return Detour::Success(response);
+            }
             (Some(path), None)
         }
         // fstat
@@ -444,6 +913,42 @@ pub(crate) fn xstat(
     Detour::Success(response)
 }
 
+/// Metadata of the file at the absolute `path` that came with the entries of its directory, see
+/// [`OpenDirs::cached_metadata`](super::open_dirs::OpenDirs::cached_metadata).
+fn cached_xstat(path: &Path, follow_symlink: bool) -> Option<XstatResponse> {
+    OPEN_DIRS
+        .cached_metadata(path, follow_symlink)
+        .map(|metadata| XstatResponse { metadata })
+}
+
+/// [`xstat`] for paths from the target container's image.
+///
+/// The agent can only open these files, so we get the metadata from a temporarily opened remote
+/// file. Symlinks are always followed.
+fn image_xstat(path: PathBuf) -> Detour<XstatResponse> {
+    let image_file = open_image_temporarily(path)?;
+
+    let request = XstatRequest {
+        fd: Some(image_file.fd),
+        path: None,
+        follow_symlink: true,
+    };
+
+    let response = common::make_proxy_request_with_response(request)??;
+
+    Detour::Success(response)
+}
+
+/// Opens `path` from the target container's image for a single fd based request.
+///
+/// The remote file is closed when the returned [`RemoteFile`] is dropped.
+fn open_image_temporarily(path: PathBuf) -> Detour<RemoteFile> {
+    crate::simulation::bypass("image file", path.display())?;
+    let OpenFileResponse { fd } = RemoteFile::remote_open_image(path.clone())?;
+
+    Detour::Success(RemoteFile::new(fd, path.display().to_string()))
+}
+
 /// Logic for the `libc::statx` function.
 /// See [manual](https://man7.org/linux/man-pages/man2/statx.2.html) for reference.
 ///
@@ -480,11 +985,20 @@ pub(crate) fn statx_logic(
         return Detour::Error(HookError::BadFlag);
     }
 
-    let (fd, path) = if path_name.is_absolute() {
-        ensure_not_ignored!(path_name, false);
-        (None, Some(path_name))
-    } else if !path_name.as_os_str().is_empty() && dir_fd == libc::AT_FDCWD {
-        return Detour::Bypass(Bypass::RelativePath(path_name));
+    // Keeps the file from the container image open until we get the response.
+    let image_file;
+
+    let (fd, path) = if path_name.is_absolute()
+        || (!path_name.as_os_str().is_empty() && dir_fd == libc::AT_FDCWD)
+    {
+        let path_name = remote_path(path_name)?;
+        if is_image_path(&path_name) {
+            image_file = open_image_temporarily(path_name)?;
+            (Some(image_file.fd), None)
+        } else {
+            ensure_not_ignored!(path_name, false);
+            (None, Some(path_name))
+        }
     } else if !path_name.as_os_str().is_empty() {
         (Some(get_remote_fd(dir_fd)?), Some(path_name))
     } else if (flags & libc::AT_EMPTY_PATH) != 0 {
@@ -532,16 +1046,16 @@ pub(crate) fn statx_logic(
     // SAFETY: all-zero statx struct is valid
     *statx_buf = unsafe { std::mem::zeroed() };
     statx_buf.stx_mask = libc::STATX_TYPE
-        & libc::STATX_MODE
-        & libc::STATX_NLINK
-        & libc::STATX_UID
-        & libc::STATX_GID
-        & libc::STATX_ATIME
-        & libc::STATX_MTIME
-        & libc::STATX_CTIME
-        & libc::STATX_INO
-        & libc::STATX_SIZE
-        & libc::STATX_BLOCKS;
+        | libc::STATX_MODE
+        | libc::STATX_NLINK
+        | libc::STATX_UID
+        | libc::STATX_GID
+        | libc::STATX_ATIME
+        | libc::STATX_MTIME
+        | libc::STATX_CTIME
+        | libc::STATX_INO
+        | libc::STATX_SIZE
+        | libc::STATX_BLOCKS;
     statx_buf.stx_attributes_mask = 0;
 
     statx_buf.stx_blksize = response.block_size.try_into().unwrap_or(u32::MAX);
@@ -613,16 +1127,34 @@ fn absolute_path(path: PathBuf) -> PathBuf {
 
 #[mirrord_layer_macro::instrument(level = "trace")]
 pub(crate) fn realpath(path: Detour<PathBuf>) -> Detour<PathBuf> {
-    let path = path?;
-
-    if path.is_relative() {
-        // Calls with non absolute paths are sent to libc::open.
-        Detour::Bypass(Bypass::RelativePath(path.clone()))?
-    };
+    // Calls with relative paths are sent to libc::realpath, unless we have the remote cwd.
+    let realpath = absolute_path(absolute_remote_path(path?)?);
+    // The app keeps seeing its own path, `xstat` rewrites it again.
+    let remote_realpath = crate::setup()
+        .file_filter()
+        .remap(&realpath)
+        .unwrap_or_else(|| realpath.clone());
+
+    // The app keeps seeing the remote path, as long as the local file exists.
+    if let Some(local_path) = crate::setup()
+        .file_filter()
+        .local_override(&remote_realpath)
+    {
+        return if Path::new(OsStr::from_bytes(local_path.as_bytes())).exists() {
+            Detour::Success(realpath)
+        } else {
+            Detour::Error(HookError::FileNotFound)
+        };
+    }
 
-    let realpath = absolute_path(path);
+    ensure_not_ignored!(remote_realpath, false);
 
-    ensure_not_ignored!(realpath, false);
+    // We can only return the resolved path when the app sees the remote paths as they are.
+    if remote_realpath == realpath {
+        if let Some(resolved) = resolve_remote_symlinks(&realpath, false)? {
+            return Detour::Success(resolved);
+        }
+    }
 
     // check that file exists
     xstat(Some(Detour::Success(realpath.clone())), None, true)?;
@@ -8,7 +8,7 @@ use crate::{
 };
 /*
  * Reference for which syscalls are managed by the handlers:
- * SYS_openat: Syscall6
+ * SYS_openat, SYS_openat2: Syscall6
  * SYS_read, SYS_write, SYS_lseek, SYS_faccessat: Syscall
  *
  * SYS_socket, SYS_bind, SYS_listen, SYS_accept, SYS_close: Syscall
@@ -0,0 +1,193 @@
+//! Go on macOS doesn't make raw syscalls, it calls the libSystem functions through its own
+//! trampolines (e.g. `syscall.libc_connect_trampoline`), by passing the trampoline address to
+//! `runtime.syscall` or `runtime.syscall6`.
+//!
+//! Depending on how the binary was linked, these calls may not go through our libc hooks, so we
+//! replace `runtime.syscall` and `runtime.syscall6` and handle the calls to the socket functions
+//! ourselves.
+//!
+//! Refer:
+//!   - <https://cs.opensource.google/go/go/+/refs/tags/go1.21.0:src/runtime/sys_darwin.go>
+//!   - <https://cs.opensource.google/go/go/+/refs/tags/go1.21.0:src/syscall/zsyscall_darwin_arm64.go>
+use std::{ffi::c_void, sync::OnceLock};
+
+use errno::errno;
+use tracing::trace;
+
+use crate::{
+    detour::HookFn,
+    hooks::HookManager,
+    socket::hooks::{bind_detour, connect_detour, send_to_detour},
+};
+
+/// Signature of `runtime.syscall` and `runtime.syscall6`.
+///
+/// They're called with `asmcgocall`, on the system stack and with the C calling convention, so we
+/// can replace them with regular `extern "C"` functions.
+type FnRuntimeSyscall = unsafe extern "C" fn(*mut c_void);
+static FN_RUNTIME_SYSCALL: HookFn<FnRuntimeSyscall> = HookFn::default_const();
+static FN_RUNTIME_SYSCALL6: HookFn<FnRuntimeSyscall> = HookFn::default_const();
+
+/// Addresses of the Go trampolines to the libSystem functions that we handle.
+static TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
+
+/// Arguments of `runtime.syscall`, laid out as in `syscall_syscall`.
+#[repr(C)]
+struct SyscallArgs {
+    function: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    r1: usize,
+    r2: usize,
+    err: usize,
+}
+
+/// Arguments of `runtime.syscall6`, laid out as in `syscall_syscall6`.
+#[repr(C)]
+struct Syscall6Args {
+    function: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+    arg5: usize,
+    arg6: usize,
+    r1: usize,
+    r2: usize,
+    err: usize,
+}
+
+/// Trampolines from both the `syscall` package and `golang.org/x/sys/unix`, whichever the binary
+/// uses.
+#[derive(Debug, Default)]
+struct Trampolines {
+    connect: Vec<usize>,
+    bind: Vec<usize>,
+    sendto: Vec<usize>,
+}
+
+impl Trampolines {
+    const PACKAGES: [&'static str; 2] = ["syscall", "golang.org/x/sys/unix"];
+
+    fn resolve(hook_manager: &HookManager, function: &str) -> Vec<usize> {
+        Self::PACKAGES
+            .iter()
+            .flat_map(|package| {
+                [
+                    format!("{package}.libc_{function}_trampoline.abi0"),
+                    format!("{package}.libc_{function}_trampoline"),
+                ]
+            })
+            .filter_map(|symbol| hook_manager.resolve_symbol_main_module(&symbol))
+            .map(|address| address.0 as usize)
+            .collect()
+    }
+
+    fn new(hook_manager: &HookManager) -> Self {
+        Self {
+            connect: Self::resolve(hook_manager, "connect"),
+            bind: Self::resolve(hook_manager, "bind"),
+            sendto: Self::resolve(hook_manager, "sendto"),
+        }
+    }
+}
+
+/// Stores the result of a detour the way `runtime.syscall` does: libc style `-1` in `r1`, with
+/// the `errno` in `err`.
+fn store_result(result: isize, r1: &mut usize, r2: &mut usize, err: &mut usize) {
+    *r1 = result as usize;
+    *r2 = 0;
+    *err = if result == -1 { errno().0 as usize } else { 0 };
+}
+
+/// Replaces `runtime.syscall`, used for libSystem functions with up to 3 arguments.
+unsafe extern "C" fn runtime_syscall_detour(args: *mut c_void) {
+    let trampolines = TRAMPOLINES.get_or_init(Default::default);
+    let syscall = &mut *(args as *mut SyscallArgs);
+
+    let result = if trampolines.connect.contains(&syscall.function) {
+        trace!("go connect fd={}", syscall.arg1);
+        connect_detour(syscall.arg1 as _, syscall.arg2 as _, syscall.arg3 as _) as isize
+    } else if trampolines.bind.contains(&syscall.function) {
+        trace!("go bind fd={}", syscall.arg1);
+        bind_detour(syscall.arg1 as _, syscall.arg2 as _, syscall.arg3 as _) as isize
+    } else {
+        return FN_RUNTIME_SYSCALL(args);
+    };
+
+    store_result(result, &mut syscall.r1, &mut syscall.r2, &mut syscall.err);
+}
+
+/// Replaces `runtime.syscall6`, used for libSystem functions with up to 6 arguments.
+unsafe extern "C" fn runtime_syscall6_detour(args: *mut c_void) {
+    let trampolines = TRAMPOLINES.get_or_init(Default::default);
+    let syscall = &mut *(args as *mut Syscall6Args);
+
+    let result = if trampolines.sendto.contains(&syscall.function) {
+        trace!("go sendto fd={}", syscall.arg1);
+        send_to_detour(
+            syscall.arg1 as _,
+            syscall.arg2 as _,
+            syscall.arg3 as _,
+            syscall.arg4 as _,
+            syscall.arg5 as _,
+            syscall.arg6 as _,
+        )
+    } else {
+        return FN_RUNTIME_SYSCALL6(args);
+    };
+
+    store_result(result, &mut syscall.r1, &mut syscall.r2, &mut syscall.err);
+}
+
+/// Hooks `symbol` in the main module and stores the original function in `original`.
+fn hook_runtime_syscall(
+    hook_manager: &mut HookManager,
+    symbol: &str,
+    detour: FnRuntimeSyscall,
+    original: &HookFn<FnRuntimeSyscall>,
+) {
+    match hook_manager.hook_symbol_main_module(symbol, detour as *mut c_void) {
+        Ok(replaced) => {
+            let replaced: FnRuntimeSyscall = unsafe { std::mem::transmute(replaced) };
+            let _ = original.set(replaced);
+            trace!("hooked {symbol:?} in main module");
+        }
+        Err(err) => trace!("hook {symbol:?} in main module failed with err {err:?}"),
+    }
+}
+
+/// Hooks `runtime.syscall` and `runtime.syscall6` if the main module is a Go binary that calls
+/// any of the libSystem functions we handle.
+pub(crate) fn enable_hooks(hook_manager: &mut HookManager) {
+    if hook_manager
+        .resolve_symbol_main_module("runtime.buildVersion.str")
+        .is_none()
+    {
+        return;
+    }
+
+    let trampolines = Trampolines::new(hook_manager);
+    trace!(?trampolines, "found go libSystem trampolines");
+    if trampolines.connect.is_empty()
+        && trampolines.bind.is_empty()
+        && trampolines.sendto.is_empty()
+    {
+        return;
+    }
+    let _ = TRAMPOLINES.set(trampolines);
+
+    hook_runtime_syscall(
+        hook_manager,
+        "runtime.syscall.abi0",
+        runtime_syscall_detour,
+        &FN_RUNTIME_SYSCALL,
+    );
+    hook_runtime_syscall(
+        hook_manager,
+        "runtime.syscall6.abi0",
+        runtime_syscall6_detour,
+        &FN_RUNTIME_SYSCALL6,
+    );
+}
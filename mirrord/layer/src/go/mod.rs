@@ -1,10 +1,16 @@
-#![cfg(all(
-    any(target_arch = "x86_64", target_arch = "aarch64"),
-    target_os = "linux"
+#![cfg(any(
+    all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_os = "linux"
+    ),
+    target_os = "macos"
 ))]
+#[cfg(target_os = "linux")]
 use errno::errno;
+#[cfg(target_os = "linux")]
 use tracing::trace;
 
+#[cfg(target_os = "linux")]
 use crate::{close_detour, file::hooks::*, socket::hooks::*};
 
 #[cfg_attr(
@@ -15,10 +21,12 @@ use crate::{close_detour, file::hooks::*, socket::hooks::*};
     all(target_os = "linux", target_arch = "aarch64"),
     path = "linux_aarch64.rs"
 )]
+#[cfg_attr(target_os = "macos", path = "macos.rs")]
 pub(crate) mod go_hooks;
 
 /// Syscall & Syscall6 handler - supports upto 6 params, mainly used for
 /// accept4 Note: Depending on success/failure Syscall may or may not call this handler
+#[cfg(target_os = "linux")]
 #[no_mangle]
 unsafe extern "C" fn c_abi_syscall6_handler(
     syscall: i64,
@@ -43,6 +51,15 @@ unsafe extern "C" fn c_abi_syscall6_handler(
         libc::SYS_accept => accept_detour(param1 as _, param2 as _, param3 as _) as i64,
         libc::SYS_close => close_detour(param1 as _) as i64,
         libc::SYS_connect => connect_detour(param1 as _, param2 as _, param3 as _) as i64,
+        libc::SYS_io_uring_setup => crate::io_uring::io_uring_setup(param1 as _, param2 as _),
+        libc::SYS_io_uring_enter => crate::io_uring::io_uring_enter(
+            param1 as _,
+            param2 as _,
+            param3 as _,
+            param4 as _,
+            param5 as _,
+            param6 as _,
+        ),
 
         _ if crate::setup().fs_config().is_active() => {
             match syscall {
@@ -104,6 +121,9 @@ unsafe extern "C" fn c_abi_syscall6_handler(
                 libc::SYS_fsync => fsync_detour(param1 as _) as i64,
                 libc::SYS_fdatasync => fsync_detour(param1 as _) as i64,
                 libc::SYS_openat => openat_detour(param1 as _, param2 as _, param3 as _) as i64,
+                libc::SYS_openat2 => {
+                    openat2_syscall(param1 as _, param2 as _, param3 as _, param4 as _)
+                }
                 libc::SYS_getdents64 => {
                     getdents64_detour(param1 as _, param2 as _, param3 as _) as i64
                 }
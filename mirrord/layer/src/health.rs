@@ -0,0 +1,188 @@
+//! Quick self-check of the layer, done once at startup, so that a session in which the layer
+//! silently did nothing can be diagnosed right away.
+//!
+//! Checks that our hooks were installed, that the internal proxy answers (which also means that the
+//! protocol handshake with it and the agent went through), and that the enabled features can work:
+//! a remote filesystem operation for `feature.fs`, and the socket hooks for `feature.network`.
+//!
+//! The results are reported in a single structured event (to mirrord-console when it's in use),
+//! and a failed check is also printed to stderr.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use mirrord_config::feature::network::incoming::IncomingMode;
+use mirrord_protocol::file::AccessFileRequest;
+
+use crate::{common, hooks, setup};
+
+/// Socket hooks that `feature.network` relies on, see [`network_check`].
+const NETWORK_HOOKS: [&str; 3] = ["socket", "connect", "bind"];
+
+/// Result of one of the checks.
+enum Check {
+    Passed,
+    /// The check doesn't apply to this session (for example, the feature is disabled).
+    Skipped,
+    Failed(String),
+}
+
+impl Check {
+    fn failed(&self) -> bool {
+        matches!(self, Self::Failed(..))
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passed => f.write_str("ok"),
+            Self::Skipped => f.write_str("skipped"),
+            Self::Failed(reason) => write!(f, "failed ({reason})"),
+        }
+    }
+}
+
+/// Results of [`self_check`].
+struct HealthReport {
+    hooks: Check,
+    intproxy: Check,
+    /// Round trip of the request made to check the internal proxy.
+    round_trip: Option<Duration>,
+    fs: Check,
+    network: Check,
+}
+
+impl HealthReport {
+    fn new() -> Self {
+        let (intproxy, fs, round_trip) = intproxy_check();
+
+        Self {
+            hooks: hooks_check(),
+            intproxy,
+            round_trip,
+            fs,
+            network: network_check(),
+        }
+    }
+
+    fn healthy(&self) -> bool {
+        !(self.hooks.failed()
+            || self.intproxy.failed()
+            || self.fs.failed()
+            || self.network.failed())
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hooks: {}, intproxy: {}, fs: {}, network: {}",
+            self.hooks, self.intproxy, self.fs, self.network
+        )
+    }
+}
+
+/// Runs the checks and reports their results.
+///
+/// Call after the hooks are enabled and the connection to the internal proxy is made.
+pub(crate) fn self_check() {
+    let report = HealthReport::new();
+
+    if report.healthy() {
+        tracing::info!(
+            hooks = %report.hooks,
+            installed_hooks = hooks::installed_hooks(),
+            intproxy = %report.intproxy,
+            round_trip = ?report.round_trip,
+            fs = %report.fs,
+            network = %report.network,
+            "mirrord-layer self-check passed",
+        );
+    } else {
+        tracing::warn!(
+            hooks = %report.hooks,
+            installed_hooks = hooks::installed_hooks(),
+            intproxy = %report.intproxy,
+            round_trip = ?report.round_trip,
+            fs = %report.fs,
+            network = %report.network,
+            "mirrord-layer self-check failed",
+        );
+        eprintln!("mirrord: the layer self-check failed, mirrord might not work: {report}");
+    }
+}
+
+fn hooks_check() -> Check {
+    if hooks::is_hooked("close") || hooks::main_module_hooked() {
+        Check::Passed
+    } else if hooks::installed_hooks() == 0 {
+        Check::Failed("no function was hooked".to_string())
+    } else {
+        Check::Failed("`close` was not hooked".to_string())
+    }
+}
+
+/// Checks the internal proxy with a request that goes through it to the agent, an `access` of `/`
+/// in the target's filesystem, which is also the sanity check of `feature.fs`.
+///
+/// Without a remote filesystem, we only check that the connection to the internal proxy was made.
+fn intproxy_check() -> (Check, Check, Option<Duration>) {
+    // SAFETY: mutation happens only on initialization.
+    if unsafe { crate::PROXY_CONNECTION.get() }.is_none() {
+        let reason = "no connection to the internal proxy";
+        return (Check::Failed(reason.to_string()), Check::Skipped, None);
+    }
+
+    if !setup().fs_config().is_active() {
+        return (Check::Passed, Check::Skipped, None);
+    }
+
+    let start = Instant::now();
+    let response = common::make_proxy_request_with_response(AccessFileRequest {
+        pathname: PathBuf::from("/"),
+        mode: 0,
+    });
+    let round_trip = start.elapsed();
+
+    match response {
+        Ok(Ok(..)) => (Check::Passed, Check::Passed, Some(round_trip)),
+        Ok(Err(error)) => (
+            Check::Passed,
+            Check::Failed(format!("`access(\"/\")` in the target failed with {error}")),
+            Some(round_trip),
+        ),
+        Err(error) => (Check::Failed(error.to_string()), Check::Skipped, None),
+    }
+}
+
+/// Checks that the socket hooks are installed when any network feature is enabled.
+fn network_check() -> Check {
+    let setup = setup();
+    let enabled = setup.outgoing_config().tcp
+        || setup.outgoing_config().udp
+        || setup.remote_dns_enabled()
+        || !matches!(setup.incoming_mode(), IncomingMode::Off);
+    if !enabled {
+        return Check::Skipped;
+    }
+
+    // Go binaries make the syscalls themselves, and we hook them in the Go runtime instead.
+    if hooks::main_module_hooked() {
+        return Check::Passed;
+    }
+
+    let missing = NETWORK_HOOKS
+        .into_iter()
+        .filter(|symbol| !hooks::is_hooked(symbol))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        Check::Passed
+    } else {
+        Check::Failed(format!("`{}` not hooked", missing.join("`, `")))
+    }
+}
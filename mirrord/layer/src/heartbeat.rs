@@ -0,0 +1,52 @@
+//! Lets the internal proxy know that the application is not stopped, for
+//! `feature.network.incoming.on_stall`, and for finding the application stopped by a debugger
+//! (`debugger_mode`).
+//!
+//! The heartbeats come from a separate thread, so they stop only when the whole process is stopped
+//! (`SIGSTOP`, or a native debugger at a breakpoint), not when the application is just busy.
+
+use std::{thread, time::Duration};
+
+use mirrord_intproxy_protocol::LayerHeartbeat;
+
+use crate::{
+    common,
+    detour::DetourGuard,
+    hidden::{hide_current_thread, LAYER_THREAD_PREFIX},
+    setup,
+};
+
+/// How often the layer sends [`LayerHeartbeat`]s, has to be well below the shortest
+/// `stall_timeout`.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts a thread that sends [`LayerHeartbeat`]s to the internal proxy. Does nothing unless
+/// the internal proxy watches for stalls (see `IncomingConfig::detects_stalls`), and not in
+/// `debugger_mode`, where stalls are expected.
+///
+/// Has to be called again in the child of a `fork`, since the thread doesn't survive it.
+pub(crate) fn start_heartbeat() {
+    if setup().debugger_mode() || !setup().incoming_config().detects_stalls() {
+        return;
+    }
+
+    let hide_thread = setup().experimental().hide_layer_threads;
+    let spawned = thread::Builder::new()
+        .name(format!("{LAYER_THREAD_PREFIX}heartbeat"))
+        .spawn(move || {
+            if hide_thread {
+                hide_current_thread();
+            }
+
+            let _guard = DetourGuard::new();
+
+            let pid = std::process::id();
+            while common::make_proxy_request_no_response(LayerHeartbeat { pid }).is_ok() {
+                thread::sleep(HEARTBEAT_INTERVAL);
+            }
+        });
+
+    if let Err(error) = spawned {
+        tracing::warn!(%error, "Failed to spawn the heartbeat thread");
+    }
+}
@@ -0,0 +1,54 @@
+//! Keeps the layer's own threads and file descriptors out of the way of tools that inspect the
+//! local process, like profilers or the JVM attach mechanism (`jcmd`, `jstack`).
+//!
+//! Enabled with [`ExperimentalConfig::hide_layer_threads`]. Our threads are always named with the
+//! [`LAYER_THREAD_PREFIX`], with the option enabled they also block all signals (so process
+//! directed signals, e.g. the `SIGQUIT` of a JVM attach or a profiler's `SIGPROF`, are never
+//! delivered to them), and our descriptors are moved to [`HIDDEN_FD_BASE`] and above.
+//!
+//! [`ExperimentalConfig::hide_layer_threads`]: mirrord_config::experimental::ExperimentalConfig::hide_layer_threads
+
+use std::{
+    io,
+    mem::MaybeUninit,
+    net::TcpStream,
+    os::fd::{AsRawFd, FromRawFd, RawFd},
+};
+
+/// Prefix of the names of the threads spawned by the layer, shows up in `/proc/<pid>/task/*/comm`.
+pub(crate) const LAYER_THREAD_PREFIX: &str = "mirrord-";
+
+/// Lowest descriptor number used for the layer's own descriptors when hiding them.
+///
+/// Stays below the common soft limit of 1024 open files.
+pub(crate) const HIDDEN_FD_BASE: RawFd = 900;
+
+/// Moves the descriptor of the given stream to [`HIDDEN_FD_BASE`] or above.
+///
+/// The new descriptor is close-on-exec, just like the one created by [`std`].
+pub(crate) fn hide_stream(stream: TcpStream) -> io::Result<TcpStream> {
+    let fd = unsafe { libc::fcntl(stream.as_raw_fd(), libc::F_DUPFD_CLOEXEC, HIDDEN_FD_BASE) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The original descriptor is closed when `stream` is dropped.
+    Ok(unsafe { TcpStream::from_raw_fd(fd) })
+}
+
+/// Blocks all signals in the calling thread.
+///
+/// Meant to be called first thing in the threads spawned by the layer.
+pub(crate) fn hide_current_thread() {
+    unsafe {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigfillset(set.as_mut_ptr());
+        let result = libc::pthread_sigmask(libc::SIG_BLOCK, set.as_ptr(), std::ptr::null_mut());
+        if result != 0 {
+            tracing::warn!(
+                error = %io::Error::from_raw_os_error(result),
+                "Failed to block signals in a layer thread"
+            );
+        }
+    }
+}
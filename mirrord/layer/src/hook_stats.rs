@@ -0,0 +1,281 @@
+//! Optional timing statistics for our hooks, used to measure the overhead mirrord adds to calls
+//! like `open`, `read` or `connect`.
+//!
+//! Enabled by setting [`HOOK_STATS_ENV`] to `true`. Every hook that is not bypassed records how
+//! long it took in a per hook histogram, and the histograms are printed when the process exits.
+//! The report goes to mirrord-console when it is in use, and to stderr otherwise.
+//!
+//! We also count the [`Bypass`]es returned to the hooks, per hook and variant, and the report
+//! lists the most frequent ones. A misconfigured `feature.fs` or `feature.network` usually shows
+//! up there as thousands of `IgnoredFile` or `DisabledOutgoing`.
+//!
+//! When disabled, a [`HookTimer`] or [`record_bypass`] costs a single atomic load.
+
+use std::{
+    cell::Cell,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use crate::detour::Bypass;
+
+/// Set to `true` to collect the hook timing statistics.
+pub(crate) const HOOK_STATS_ENV: &str = "MIRRORD_LAYER_HOOK_STATS";
+
+/// Whether we're collecting the statistics, set once in [`init`].
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Histogram for every hook that was called at least once, keyed by the hook name.
+static HOOK_STATS: LazyLock<DashMap<&'static str, HookHistogram>> = LazyLock::new(DashMap::new);
+
+/// Amount of bypasses, keyed by the hook name and the [`Bypass::name`].
+static BYPASS_STATS: LazyLock<DashMap<(&'static str, &'static str), u64>> =
+    LazyLock::new(DashMap::new);
+
+/// How many of the most frequent bypasses are listed in the [`report`].
+const TOP_BYPASSES: usize = 10;
+
+thread_local! {
+    /// Hook that is running on this thread, set by its [`HookTimer`].
+    static CURRENT_HOOK: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Amount of buckets in [`HookHistogram`], the last one holds everything above ~1 second.
+const BUCKETS: usize = 32;
+
+/// Latencies of a single hook, in power of 2 nanoseconds buckets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct HookHistogram {
+    calls: u64,
+    total: Duration,
+    max: Duration,
+    /// `buckets[i]` counts the calls that took less than `2^i` nanoseconds (and at least
+    /// `2^(i-1)`).
+    buckets: [u64; BUCKETS],
+}
+
+impl HookHistogram {
+    fn record(&mut self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+
+        self.calls += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+    }
+
+    fn mean(&self) -> Duration {
+        if self.calls == 0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_nanos((self.total.as_nanos() / u128::from(self.calls)) as u64)
+    }
+
+    /// Upper bound of the bucket that contains the `quantile` (`0.0..=1.0`) of the calls.
+    fn quantile(&self, quantile: f64) -> Duration {
+        let target = ((self.calls as f64) * quantile).ceil().max(1.0) as u64;
+
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Duration::from_nanos(1 << bucket).min(self.max);
+            }
+        }
+
+        self.max
+    }
+}
+
+impl fmt::Display for HookHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "calls={} total={:?} mean={:?} p50<={:?} p99<={:?} max={:?}",
+            self.calls,
+            self.total,
+            self.mean(),
+            self.quantile(0.5),
+            self.quantile(0.99),
+            self.max,
+        )
+    }
+}
+
+/// Records the time between its creation and drop in the histogram of the hook.
+///
+/// Should be created after checking the [`DetourGuard`](crate::detour::DetourGuard), so bypassed
+/// calls are not counted.
+///
+/// While it lives, the [`Bypass`]es recorded on this thread are attributed to its hook.
+pub(crate) struct HookTimer {
+    hook: &'static str,
+    start: Instant,
+    /// Hook that was running on this thread before, restored on drop.
+    previous: Option<&'static str>,
+}
+
+impl HookTimer {
+    /// Starts timing `hook`, if [`HOOK_STATS_ENV`] is enabled.
+    pub(crate) fn start(hook: &'static str) -> Option<Self> {
+        ENABLED.load(Ordering::Relaxed).then(|| Self {
+            hook,
+            start: Instant::now(),
+            previous: CURRENT_HOOK
+                .try_with(|current| current.replace(Some(hook)))
+                .ok()
+                .flatten(),
+        })
+    }
+}
+
+impl Drop for HookTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        HOOK_STATS.entry(self.hook).or_default().record(elapsed);
+        let _ = CURRENT_HOOK.try_with(|current| current.set(self.previous));
+    }
+}
+
+/// Counts the `bypass` for the hook that is running on this thread, if [`HOOK_STATS_ENV`] is
+/// enabled.
+pub(crate) fn record_bypass(bypass: &Bypass) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let hook = CURRENT_HOOK
+        .try_with(Cell::get)
+        .ok()
+        .flatten()
+        .unwrap_or("unknown");
+    *BYPASS_STATS.entry((hook, bypass.name())).or_default() += 1;
+}
+
+/// Returns the `limit` most frequent bypasses, as `((hook, bypass), count)`.
+fn top_bypasses(
+    stats: &DashMap<(&'static str, &'static str), u64>,
+    limit: usize,
+) -> Vec<((&'static str, &'static str), u64)> {
+    let mut bypasses = stats
+        .iter()
+        .map(|entry| (*entry.key(), *entry.value()))
+        .collect::<Vec<_>>();
+    bypasses.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then(a_key.cmp(b_key)));
+    bypasses.truncate(limit);
+
+    bypasses
+}
+
+/// Starts collecting the statistics if [`HOOK_STATS_ENV`] is set, and registers the report to be
+/// printed when the process exits.
+pub(crate) fn init() {
+    let enabled = std::env::var(HOOK_STATS_ENV)
+        .unwrap_or_default()
+        .parse()
+        .unwrap_or(false);
+    if !enabled {
+        return;
+    }
+
+    ENABLED.store(true, Ordering::Relaxed);
+
+    if unsafe { libc::atexit(report_at_exit) } != 0 {
+        tracing::warn!("failed to register the hook stats report, it won't be printed on exit");
+    }
+}
+
+/// Formats the collected statistics, slowest hooks (by total time) first.
+fn report() -> String {
+    let mut stats = HOOK_STATS
+        .iter()
+        .map(|entry| (*entry.key(), entry.value().clone()))
+        .collect::<Vec<_>>();
+    stats.sort_by(|(_, a), (_, b)| b.total.cmp(&a.total));
+
+    let mut report = format!("mirrord hook stats for pid {}:", std::process::id());
+    for (hook, histogram) in stats {
+        report.push_str(&format!("\n  {hook}: {histogram}"));
+    }
+
+    let bypasses = top_bypasses(&BYPASS_STATS, TOP_BYPASSES);
+    if !bypasses.is_empty() {
+        let total = BYPASS_STATS.iter().map(|entry| *entry.value()).sum::<u64>();
+        report.push_str(&format!("\n  top bypass reasons (of {total} bypasses):"));
+        for ((hook, bypass), count) in bypasses {
+            report.push_str(&format!("\n    {hook}: {bypass} x{count}"));
+        }
+    }
+
+    report
+}
+
+/// Prints the [`report`], registered with `atexit` in [`init`].
+extern "C" fn report_at_exit() {
+    // No more hooks should be timed, and our own printing must not be intercepted.
+    ENABLED.store(false, Ordering::Relaxed);
+    let _guard = crate::detour::DetourGuard::new();
+
+    if HOOK_STATS.is_empty() && BYPASS_STATS.is_empty() {
+        return;
+    }
+
+    let report = report();
+    if std::env::var("MIRRORD_CONSOLE_ADDR").is_ok() {
+        tracing::info!("{report}");
+    } else {
+        eprintln!("{report}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_quantiles() {
+        let mut histogram = HookHistogram::default();
+        (0..99).for_each(|_| histogram.record(Duration::from_nanos(900)));
+        histogram.record(Duration::from_millis(2));
+
+        assert_eq!(histogram.calls, 100);
+        assert_eq!(histogram.max, Duration::from_millis(2));
+        assert_eq!(histogram.quantile(0.5), Duration::from_nanos(1024));
+        assert_eq!(histogram.quantile(0.99), Duration::from_nanos(1024));
+        assert_eq!(histogram.quantile(1.0), Duration::from_millis(2));
+    }
+
+    #[test]
+    fn top_bypasses_most_frequent_first() {
+        let stats = DashMap::new();
+        stats.insert(("open", "IgnoredFile"), 7);
+        stats.insert(("connect", "DisabledOutgoing"), 12);
+        stats.insert(("read", "LocalFdNotFound"), 7);
+        stats.insert(("bind", "Port"), 1);
+
+        assert_eq!(
+            top_bypasses(&stats, 3),
+            vec![
+                (("connect", "DisabledOutgoing"), 12),
+                (("open", "IgnoredFile"), 7),
+                (("read", "LocalFdNotFound"), 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn histogram_empty() {
+        let histogram = HookHistogram::default();
+
+        assert_eq!(histogram.mean(), Duration::ZERO);
+        assert_eq!(histogram.quantile(0.5), Duration::ZERO);
+    }
+}
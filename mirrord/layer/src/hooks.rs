@@ -1,5 +1,13 @@
-use std::{ptr::null_mut, sync::LazyLock};
+use std::{
+    collections::HashSet,
+    ptr::null_mut,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock,
+    },
+};
 
+use dashmap::DashSet;
 use frida_gum::{interceptor::Interceptor, Gum, Module, NativePointer};
 use tracing::trace;
 
@@ -7,10 +15,35 @@ use crate::{LayerError, Result};
 
 static GUM: LazyLock<Gum> = LazyLock::new(|| unsafe { Gum::obtain() });
 
+/// Functions we replaced with our hooks, see [`is_hooked`].
+static INSTALLED_HOOKS: LazyLock<DashSet<String>> = LazyLock::new(DashSet::new);
+
+/// Whether we hooked any symbol of the main module (Go binaries).
+static MAIN_MODULE_HOOKED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the `symbol` function was replaced with our hook.
+pub(crate) fn is_hooked(symbol: &str) -> bool {
+    INSTALLED_HOOKS.contains(symbol)
+}
+
+/// Number of functions replaced with our hooks.
+pub(crate) fn installed_hooks() -> usize {
+    INSTALLED_HOOKS.len()
+}
+
+/// Whether we hooked any symbol of the main module, which we do for Go binaries instead of hooking
+/// [`libc`].
+pub(crate) fn main_module_hooked() -> bool {
+    MAIN_MODULE_HOOKED.load(Ordering::Relaxed)
+}
+
 /// Struct for managing the hooks using Frida.
 pub(crate) struct HookManager<'a> {
     interceptor: Interceptor<'a>,
     modules: Vec<String>,
+    /// Functions we don't hook, see
+    /// [`ExperimentalConfig::disabled_hooks`](mirrord_config::experimental::ExperimentalConfig::disabled_hooks).
+    disabled_hooks: HashSet<String>,
 }
 
 /// Gets available modules in current process.
@@ -29,6 +62,11 @@ fn get_export_by_name(module: Option<&str>, symbol: &str) -> Result<NativePointe
 }
 
 impl<'a> HookManager<'a> {
+    /// Skips hooking the given functions from now on.
+    pub(crate) fn disable_hooks<I: IntoIterator<Item = String>>(&mut self, hooks: I) {
+        self.disabled_hooks.extend(hooks);
+    }
+
     /// Hook the first function exported from a lib that is in modules and is hooked succesfully
     fn hook_any_lib_export(
         &mut self,
@@ -69,12 +107,21 @@ impl<'a> HookManager<'a> {
         // provides it.
         let function = get_export_by_name(None, symbol)?;
 
-        self.interceptor
+        // The original function is still needed by the detours that call it.
+        if self.disabled_hooks.contains(symbol) {
+            trace!("{symbol:?} is disabled, not hooking");
+            return Ok(function);
+        }
+
+        let original = self
+            .interceptor
             .replace(function, NativePointer(detour), NativePointer(null_mut()))
-            .or_else(|_| self.hook_any_lib_export(symbol, detour))
+            .or_else(|_| self.hook_any_lib_export(symbol, detour))?;
+        INSTALLED_HOOKS.insert(symbol.to_string());
+
+        Ok(original)
     }
 
-    #[cfg(target_os = "linux")]
     /// Hook a symbol that isn't exported.
     /// It is valuable when hooking internal stuff. (Go)
     fn hook_symbol(
@@ -92,22 +139,32 @@ impl<'a> HookManager<'a> {
             .map_err(Into::into)
     }
 
-    #[cfg(target_os = "linux")]
     /// Hook a symbol in the first module (main module, binary)
     pub(crate) fn hook_symbol_main_module(
         &mut self,
         symbol: &str,
         detour: *mut libc::c_void,
     ) -> Result<NativePointer> {
+        if self.disabled_hooks.contains(symbol) {
+            return Err(LayerError::HookDisabled(symbol.to_string()));
+        }
+
         // This can't fail
         let module = self.modules.first().unwrap().clone();
-        self.hook_symbol(&module, symbol, detour)
+        let original = self.hook_symbol(&module, symbol, detour)?;
+        INSTALLED_HOOKS.insert(symbol.to_string());
+        MAIN_MODULE_HOOKED.store(true, Ordering::Relaxed);
+
+        Ok(original)
     }
 
     /// Resolve symbol in main module
-    #[cfg(all(
-        target_os = "linux",
-        any(target_arch = "x86_64", target_arch = "aarch64")
+    #[cfg(any(
+        all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ),
+        target_os = "macos"
     ))]
     pub(crate) fn resolve_symbol_main_module(&self, symbol: &str) -> Option<NativePointer> {
         // This can't fail
@@ -124,6 +181,7 @@ impl<'a> Default for HookManager<'a> {
         Self {
             interceptor,
             modules,
+            disabled_hooks: Default::default(),
         }
     }
 }
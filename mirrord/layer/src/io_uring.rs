@@ -0,0 +1,734 @@
+#![cfg(target_os = "linux")]
+
+//! Intercepts the operations that applications submit through io_uring.
+//!
+//! Runtimes like libuv (Node) submit their file and socket operations as entries of an io_uring
+//! submission queue, so they never go through the libc functions that we hook. We hook the
+//! `io_uring_setup` and `io_uring_enter` syscalls (made through libc's `syscall`, or through the Go
+//! runtime), and map the submission queue of every ring into the layer as well.
+//!
+//! Before an `io_uring_enter` submits new entries, we go over them and run the ones that concern
+//! mirrord (paths that go to the remote filesystem, remote files, managed sockets) through the same
+//! logic as the classic hooks. The result is then delivered by replacing the entry with an
+//! `IORING_OP_MSG_RING` to the ring itself, which posts a completion with the original `user_data`
+//! and our result. Entries that are bypassed are left for the kernel, with the path replaced when
+//! it's served from a local directory.
+//!
+//! Rings set up with `IORING_SETUP_SQPOLL` are created without it, as the kernel would consume the
+//! entries before we see them. We keep `IORING_SQ_NEED_WAKEUP` set in their submission queue, so
+//! the application calls `io_uring_enter` after each submission, and then submit everything that
+//! is pending.
+//!
+//! Not intercepted: rings that the kernel doesn't let us intercept (no `IORING_OP_MSG_RING`, or
+//! `IORING_SETUP_NO_MMAP`), rings entered through a registered ring descriptor, and entries that
+//! use fixed files, provided buffers, or direct descriptors.
+
+use std::{
+    ffi::{c_long, c_void},
+    mem::size_of,
+    os::fd::RawFd,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, LazyLock, Mutex, PoisonError,
+    },
+};
+
+use dashmap::DashMap;
+use errno::errno;
+use libc::{c_char, c_int, iovec, sockaddr, socklen_t, statx};
+use mirrord_layer_macro::hook_guard_fn;
+use mirrord_protocol::file::{OpenOptionsInternal, ReadFileResponse, WriteFileResponse};
+use tracing::{debug, warn};
+
+use crate::{
+    common::CheckedInto,
+    detour::{Bypass, Detour, DetourGuard},
+    error::HookError,
+    file::{
+        self,
+        hooks::{openat2_syscall, update_ptr_from_bypass, OpenHow},
+        OpenOptionsInternalExt, OPEN_FILES,
+    },
+    hooks::HookManager,
+    replace,
+    socket::{self, SOCKETS},
+};
+
+const IORING_SETUP_IOPOLL: u32 = 1 << 0;
+const IORING_SETUP_SQPOLL: u32 = 1 << 1;
+const IORING_SETUP_SQ_AFF: u32 = 1 << 2;
+const IORING_SETUP_SQE128: u32 = 1 << 10;
+const IORING_SETUP_NO_MMAP: u32 = 1 << 14;
+const IORING_SETUP_NO_SQARRAY: u32 = 1 << 16;
+
+const IORING_FEAT_CQE_SKIP: u32 = 1 << 11;
+
+const IORING_ENTER_REGISTERED_RING: u32 = 1 << 4;
+
+const IORING_SQ_NEED_WAKEUP: u32 = 1 << 0;
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+
+const IORING_REGISTER_PROBE: c_long = 8;
+const IO_URING_OP_SUPPORTED: u16 = 1 << 0;
+
+const IOSQE_FIXED_FILE: u8 = 1 << 0;
+const IOSQE_IO_LINK: u8 = 1 << 2;
+const IOSQE_IO_HARDLINK: u8 = 1 << 3;
+const IOSQE_BUFFER_SELECT: u8 = 1 << 5;
+const IOSQE_CQE_SKIP_SUCCESS: u8 = 1 << 6;
+
+const IORING_OP_READV: u8 = 1;
+const IORING_OP_WRITEV: u8 = 2;
+const IORING_OP_READ_FIXED: u8 = 4;
+const IORING_OP_WRITE_FIXED: u8 = 5;
+const IORING_OP_CONNECT: u8 = 16;
+const IORING_OP_OPENAT: u8 = 18;
+const IORING_OP_CLOSE: u8 = 19;
+const IORING_OP_STATX: u8 = 21;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+const IORING_OP_OPENAT2: u8 = 28;
+const IORING_OP_MSG_RING: u8 = 40;
+
+/// `IORING_MSG_DATA`, the `IORING_OP_MSG_RING` command that posts a completion.
+const IORING_MSG_DATA: u64 = 0;
+
+/// Offset of `-1` in a read or write, the current file position is used.
+const CURRENT_POSITION: u64 = u64::MAX;
+
+/// Number of opcodes we ask about with `IORING_REGISTER_PROBE`.
+const PROBE_OPS: usize = IORING_OP_MSG_RING as usize + 1;
+
+/// `struct io_sqring_offsets`.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_cqring_offsets`.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_uring_params`.
+#[repr(C)]
+#[allow(dead_code)]
+pub(crate) struct IoUringParams {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// `struct io_uring_sqe`, with the unions named after the fields we use.
+#[repr(C)]
+#[allow(dead_code)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    /// Offset of reads and writes, `addrlen` of `IORING_OP_CONNECT`, `struct statx *` of
+    /// `IORING_OP_STATX`, `struct open_how *` of `IORING_OP_OPENAT2`.
+    off: u64,
+    addr: u64,
+    len: u32,
+    /// `rw_flags`, `open_flags`, `statx_flags` and so on.
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    file_index: u32,
+    addr3: u64,
+    pad: u64,
+}
+
+/// `struct io_uring_probe_op`.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+struct ProbeOp {
+    op: u8,
+    resv: u8,
+    flags: u16,
+    resv2: u32,
+}
+
+/// `struct io_uring_probe`, with room for [`PROBE_OPS`] opcodes.
+#[repr(C)]
+#[allow(dead_code)]
+struct Probe {
+    last_op: u8,
+    ops_len: u8,
+    resv: u16,
+    resv2: [u32; 3],
+    ops: [ProbeOp; PROBE_OPS],
+}
+
+/// Rings we intercept, by their descriptor.
+static RINGS: LazyLock<DashMap<RawFd, Arc<Mutex<Ring>>>> = LazyLock::new(DashMap::new);
+
+/// Memory of a ring mapped into the layer, unmapped when dropped.
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mapping {
+    unsafe fn new(fd: RawFd, len: usize, offset: libc::off_t) -> Result<Self, HookError> {
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            fd,
+            offset,
+        );
+
+        if ptr == libc::MAP_FAILED {
+            Err(std::io::Error::last_os_error().into())
+        } else {
+            Ok(Self { ptr, len })
+        }
+    }
+
+    /// Pointer to the value at `offset` bytes into the mapping.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.wrapping_byte_add(offset as usize).cast()
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// The submission queue of an io_uring, mapped into the layer.
+struct Ring {
+    fd: RawFd,
+    sq_ring: Mapping,
+    sqes: Mapping,
+    sq_off: SqRingOffsets,
+    /// Size of the entries, they are twice as big with `IORING_SETUP_SQE128`.
+    sqe_size: usize,
+    /// Without `IORING_SETUP_NO_SQARRAY`, positions in the queue are mapped to entries through an
+    /// array.
+    has_array: bool,
+    /// The application asked for `IORING_SETUP_SQPOLL`, and we created the ring without it.
+    polled: bool,
+    /// Position of the next entry we haven't seen.
+    next: u32,
+}
+
+// The mappings are only accessed with the ring's lock held.
+unsafe impl Send for Ring {}
+
+impl Ring {
+    /// Maps the submission queue of the ring `fd` created with `params`.
+    unsafe fn new(fd: RawFd, params: &IoUringParams, polled: bool) -> Result<Self, HookError> {
+        if params.features & IORING_FEAT_CQE_SKIP == 0 || !msg_ring_supported(fd) {
+            return Err(HookError::IO(std::io::Error::from_raw_os_error(
+                libc::EOPNOTSUPP,
+            )));
+        }
+
+        let sq_off = params.sq_off;
+        let has_array = params.flags & IORING_SETUP_NO_SQARRAY == 0;
+        let sqe_size = if params.flags & IORING_SETUP_SQE128 == 0 {
+            size_of::<Sqe>()
+        } else {
+            2 * size_of::<Sqe>()
+        };
+
+        let sq_ring_len = if has_array {
+            sq_off.array as usize + params.sq_entries as usize * size_of::<u32>()
+        } else {
+            sq_off.dropped as usize + size_of::<u32>()
+        };
+        let sq_ring = Mapping::new(fd, sq_ring_len, IORING_OFF_SQ_RING)?;
+        let sqes = Mapping::new(fd, params.sq_entries as usize * sqe_size, IORING_OFF_SQES)?;
+
+        let mut ring = Self {
+            fd,
+            sq_ring,
+            sqes,
+            sq_off,
+            sqe_size,
+            has_array,
+            polled,
+            next: 0,
+        };
+        ring.next = ring.head();
+
+        if polled {
+            ring.flags()
+                .fetch_or(IORING_SQ_NEED_WAKEUP, Ordering::Relaxed);
+        }
+
+        Ok(ring)
+    }
+
+    fn head(&self) -> u32 {
+        unsafe { &*self.sq_ring.at::<AtomicU32>(self.sq_off.head) }.load(Ordering::Acquire)
+    }
+
+    fn tail(&self) -> u32 {
+        unsafe { &*self.sq_ring.at::<AtomicU32>(self.sq_off.tail) }.load(Ordering::Acquire)
+    }
+
+    fn flags(&self) -> &AtomicU32 {
+        unsafe { &*self.sq_ring.at::<AtomicU32>(self.sq_off.flags) }
+    }
+
+    /// The entry at `position` in the queue.
+    unsafe fn sqe(&self, position: u32) -> *mut Sqe {
+        let mask = *self.sq_ring.at::<u32>(self.sq_off.ring_mask);
+        let index = if self.has_array {
+            *self
+                .sq_ring
+                .at::<u32>(self.sq_off.array)
+                .add((position & mask) as usize)
+                & mask
+        } else {
+            position & mask
+        };
+
+        self.sqes
+            .at::<Sqe>(0)
+            .wrapping_byte_add(index as usize * self.sqe_size)
+    }
+
+    /// Handles the entries that the `io_uring_enter` with `to_submit` is about to submit.
+    ///
+    /// Returns the number of entries to submit, and the bypasses that hold the paths we put into
+    /// the entries, which have to live until the entries are submitted.
+    unsafe fn submit(&mut self, to_submit: u32) -> (u32, Vec<Bypass>) {
+        let head = self.head();
+        let pending = self.tail().wrapping_sub(head);
+        // Without the kernel polling the queue, we submit what it would have picked up.
+        let to_submit = if self.polled {
+            pending
+        } else {
+            to_submit.min(pending)
+        };
+
+        // The kernel consumed entries we haven't seen, we can't do anything about them anymore.
+        if self.next.wrapping_sub(head) > pending {
+            self.next = head;
+        }
+
+        let mut bypasses = Vec::new();
+        while self.next.wrapping_sub(head) < to_submit {
+            let sqe = &mut *self.sqe(self.next);
+            if let Some(result) = handle(sqe, &mut bypasses) {
+                complete(sqe, self.fd, result);
+            }
+            self.next = self.next.wrapping_add(1);
+        }
+
+        if self.polled {
+            self.flags()
+                .fetch_or(IORING_SQ_NEED_WAKEUP, Ordering::Relaxed);
+        }
+
+        (to_submit, bypasses)
+    }
+}
+
+/// Whether the kernel supports `IORING_OP_MSG_RING`, that we use to post our results.
+unsafe fn msg_ring_supported(fd: RawFd) -> bool {
+    let mut probe: Probe = std::mem::zeroed();
+    let result = libc::syscall(
+        libc::SYS_io_uring_register,
+        fd,
+        IORING_REGISTER_PROBE,
+        &mut probe as *mut Probe,
+        PROBE_OPS,
+    );
+
+    result == 0
+        && probe
+            .ops
+            .get(IORING_OP_MSG_RING as usize)
+            .is_some_and(|op| op.flags & IO_URING_OP_SUPPORTED != 0)
+}
+
+/// Replaces `sqe` with an `IORING_OP_MSG_RING` to the ring `ring_fd` itself, which posts a
+/// completion with the `user_data` of `sqe` and `result`, without a completion of its own.
+fn complete(sqe: &mut Sqe, ring_fd: RawFd, result: i32) {
+    *sqe = Sqe {
+        opcode: IORING_OP_MSG_RING,
+        flags: (sqe.flags & (IOSQE_IO_LINK | IOSQE_IO_HARDLINK)) | IOSQE_CQE_SKIP_SUCCESS,
+        ioprio: 0,
+        fd: ring_fd,
+        // `user_data` of the posted completion.
+        off: sqe.user_data,
+        addr: IORING_MSG_DATA,
+        // `res` of the posted completion.
+        len: result as u32,
+        op_flags: 0,
+        user_data: 0,
+        buf_index: 0,
+        personality: 0,
+        file_index: 0,
+        addr3: 0,
+        pad: 0,
+    };
+}
+
+/// Result of a completion for a failed operation, `-errno`.
+fn error_result(error: HookError) -> i32 {
+    let _ = i64::from(error);
+    -errno().0
+}
+
+/// Runs the operation of `sqe` through the logic of the classic hooks.
+///
+/// Returns the result of the operation, or [`None`] when it's left for the kernel.
+unsafe fn handle(sqe: &mut Sqe, bypasses: &mut Vec<Bypass>) -> Option<i32> {
+    if sqe.flags & (IOSQE_FIXED_FILE | IOSQE_BUFFER_SELECT) != 0 {
+        return None;
+    }
+
+    match sqe.opcode {
+        IORING_OP_OPENAT if sqe.file_index == 0 => {
+            let flags = sqe.op_flags as c_int;
+            open(sqe, flags, 0, bypasses)
+        }
+        IORING_OP_OPENAT2 if sqe.file_index == 0 => {
+            let how = (sqe.off as *const OpenHow).as_ref()?;
+            open(sqe, how.flags as c_int, how.resolve, bypasses)
+        }
+        IORING_OP_STATX => {
+            let result = file::ops::statx_logic(
+                sqe.fd,
+                sqe.addr as *const c_char,
+                sqe.op_flags as c_int,
+                sqe.len as c_int,
+                sqe.off as *mut statx,
+            );
+
+            match result {
+                Detour::Success(result) => Some(result),
+                Detour::Bypass(bypass) => {
+                    bypass_path(sqe, bypass, bypasses);
+                    None
+                }
+                Detour::Error(error) => Some(error_result(error)),
+            }
+        }
+        IORING_OP_READ | IORING_OP_READ_FIXED if OPEN_FILES.contains_key(&sqe.fd) => {
+            let buffer = iovec {
+                iov_base: sqe.addr as *mut c_void,
+                iov_len: sqe.len as usize,
+            };
+            read(sqe.fd, slice::from_ref(&buffer), sqe.off)
+        }
+        IORING_OP_READV if OPEN_FILES.contains_key(&sqe.fd) => {
+            let buffers = iovecs(sqe)?;
+            read(sqe.fd, buffers, sqe.off)
+        }
+        IORING_OP_WRITE | IORING_OP_WRITE_FIXED if OPEN_FILES.contains_key(&sqe.fd) => {
+            let buffer = iovec {
+                iov_base: sqe.addr as *mut c_void,
+                iov_len: sqe.len as usize,
+            };
+            write(sqe.fd, slice::from_ref(&buffer), sqe.off)
+        }
+        IORING_OP_WRITEV if OPEN_FILES.contains_key(&sqe.fd) => {
+            let buffers = iovecs(sqe)?;
+            write(sqe.fd, buffers, sqe.off)
+        }
+        // The kernel closes the local descriptor.
+        IORING_OP_CLOSE if sqe.file_index == 0 => {
+            crate::close_layer_fd(sqe.fd);
+            None
+        }
+        IORING_OP_CONNECT if SOCKETS.contains_key(&sqe.fd) => connect(sqe),
+        _ => None,
+    }
+}
+
+/// The buffers of an `IORING_OP_READV` or `IORING_OP_WRITEV`.
+unsafe fn iovecs(sqe: &Sqe) -> Option<&[iovec]> {
+    let iovecs = sqe.addr as *const iovec;
+    (!iovecs.is_null()).then(|| slice::from_raw_parts(iovecs, sqe.len as usize))
+}
+
+/// Keeps the path of a [`Bypass::LocalOverride`] in the entry, see `update_ptr_from_bypass`.
+unsafe fn bypass_path(sqe: &mut Sqe, bypass: Bypass, bypasses: &mut Vec<Bypass>) {
+    sqe.addr = update_ptr_from_bypass(sqe.addr as *const c_char, &bypass) as u64;
+    bypasses.push(bypass);
+}
+
+/// `IORING_OP_OPENAT` and `IORING_OP_OPENAT2`, like `openat_detour` and `openat2_syscall`.
+unsafe fn open(
+    sqe: &mut Sqe,
+    flags: c_int,
+    resolve: u64,
+    bypasses: &mut Vec<Bypass>,
+) -> Option<i32> {
+    let path = (sqe.addr as *const c_char).checked_into();
+    let open_options = OpenOptionsInternal::from_flags(flags);
+
+    match file::ops::openat2(sqe.fd, path, open_options, resolve) {
+        Detour::Success(fd) => Some(fd),
+        Detour::Bypass(bypass) => {
+            bypass_path(sqe, bypass, bypasses);
+            None
+        }
+        Detour::Error(error) => Some(error_result(error)),
+    }
+}
+
+/// Reads from a remote file into `buffers`, like `readv_detour` and `preadv_detour`.
+unsafe fn read(fd: RawFd, buffers: &[iovec], offset: u64) -> Option<i32> {
+    let amount = buffers.iter().map(|buffer| buffer.iov_len as u64).sum();
+    let response = if offset == CURRENT_POSITION {
+        file::ops::read(fd, amount)
+    } else {
+        file::ops::pread(fd, amount, offset)
+    };
+
+    let ReadFileResponse { bytes, read_amount } = match response {
+        Detour::Success(response) => response,
+        Detour::Bypass(..) => return None,
+        Detour::Error(error) => return Some(error_result(error)),
+    };
+
+    let mut remaining = bytes
+        .get(..(read_amount as usize).min(amount as usize))
+        .unwrap_or(&bytes);
+    let read = remaining.len();
+    for buffer in buffers {
+        let (chunk, rest) = remaining.split_at(remaining.len().min(buffer.iov_len));
+        ptr::copy_nonoverlapping(chunk.as_ptr(), buffer.iov_base.cast(), chunk.len());
+        remaining = rest;
+    }
+
+    i32::try_from(read).ok()
+}
+
+/// Writes `buffers` to a remote file, like `write_detour` and `pwrite_detour`.
+unsafe fn write(fd: RawFd, buffers: &[iovec], offset: u64) -> Option<i32> {
+    let bytes = buffers
+        .iter()
+        .filter(|buffer| !buffer.iov_base.is_null())
+        .flat_map(|buffer| slice::from_raw_parts(buffer.iov_base as *const u8, buffer.iov_len))
+        .copied()
+        .collect::<Vec<_>>();
+
+    let written = if offset == CURRENT_POSITION {
+        file::ops::write(fd, Some(bytes)).map(|written| written as i64)
+    } else {
+        file::ops::pwrite(fd, &bytes, offset)
+            .map(|WriteFileResponse { written_amount }| written_amount as i64)
+    };
+
+    match written {
+        Detour::Success(written) => i32::try_from(written).ok(),
+        Detour::Bypass(..) => None,
+        Detour::Error(error) => Some(error_result(error)),
+    }
+}
+
+/// `IORING_OP_CONNECT` on a managed socket, like `connect_detour`.
+///
+/// The socket is connected right away (to the internal proxy's interceptor, or to the local
+/// address), so it's made blocking for the time of the call.
+unsafe fn connect(sqe: &mut Sqe) -> Option<i32> {
+    let fd = sqe.fd;
+    let status_flags = libc::fcntl(fd, libc::F_GETFL);
+    let non_blocking = status_flags != -1 && status_flags & libc::O_NONBLOCK != 0;
+    if non_blocking {
+        libc::fcntl(fd, libc::F_SETFL, status_flags & !libc::O_NONBLOCK);
+    }
+
+    let result = socket::ops::connect(fd, sqe.addr as *const sockaddr, sqe.off as socklen_t);
+
+    if non_blocking {
+        libc::fcntl(fd, libc::F_SETFL, status_flags);
+    }
+
+    match result {
+        Detour::Success(result) => Some(result.completion_result()),
+        Detour::Bypass(..) => None,
+        Detour::Error(error) => Some(error_result(error)),
+    }
+}
+
+/// Whether we need to see the operations submitted through io_uring.
+fn intercept_enabled() -> bool {
+    let setup = crate::setup();
+    setup.fs_config().is_active() || setup.outgoing_config().tcp || setup.outgoing_config().udp
+}
+
+/// Sets up an io_uring, mapping its submission queue when we intercept its operations.
+///
+/// Returns the ring descriptor, or `-1` with `errno` set, like `syscall`.
+pub(crate) unsafe fn io_uring_setup(entries: u32, params: *mut IoUringParams) -> c_long {
+    let _guard = DetourGuard::new();
+    let setup_ring =
+        |params: *mut IoUringParams| libc::syscall(libc::SYS_io_uring_setup, entries, params);
+
+    let Some(requested) = params.as_mut() else {
+        return setup_ring(params);
+    };
+    let flags = requested.flags;
+    if !intercept_enabled() || flags & (IORING_SETUP_IOPOLL | IORING_SETUP_NO_MMAP) != 0 {
+        return setup_ring(params);
+    }
+
+    let polled = flags & IORING_SETUP_SQPOLL != 0;
+    requested.flags &= !(IORING_SETUP_SQPOLL | IORING_SETUP_SQ_AFF);
+    let fd = setup_ring(params);
+    let setup_errno = errno();
+    requested.flags = flags;
+    if fd == -1 {
+        errno::set_errno(setup_errno);
+        return fd;
+    }
+
+    match Ring::new(fd as RawFd, requested, polled) {
+        Ok(ring) => {
+            debug!(fd, polled, "Intercepting the operations of an io_uring");
+            RINGS.insert(fd as RawFd, Arc::new(Mutex::new(ring)));
+            fd
+        }
+        Err(error) => {
+            warn!(
+                %error,
+                "Can't intercept the operations submitted through io_uring, they won't go \
+                 through mirrord"
+            );
+
+            if polled {
+                // The application expects the kernel to poll the queue.
+                libc::close(fd as RawFd);
+                setup_ring(params)
+            } else {
+                fd
+            }
+        }
+    }
+}
+
+/// Submits the entries of an io_uring, after handling the ones that concern mirrord.
+///
+/// Returns the result of the syscall, or `-1` with `errno` set, like `syscall`.
+pub(crate) unsafe fn io_uring_enter(
+    fd: RawFd,
+    to_submit: u32,
+    min_complete: u32,
+    flags: u32,
+    arg: *const c_void,
+    arg_size: usize,
+) -> c_long {
+    let _guard = DetourGuard::new();
+
+    let ring = if flags & IORING_ENTER_REGISTERED_RING == 0 {
+        RINGS.get(&fd).map(|ring| ring.value().clone())
+    } else {
+        None
+    };
+
+    let (to_submit, _bypasses) = match ring {
+        Some(ring) => ring
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .submit(to_submit),
+        None => (to_submit, Vec::new()),
+    };
+
+    libc::syscall(
+        libc::SYS_io_uring_enter,
+        fd,
+        to_submit,
+        min_complete,
+        flags,
+        arg,
+        arg_size,
+    )
+}
+
+/// Stops intercepting the ring `fd` when it's closed, releasing our mapping of it.
+pub(crate) fn forget_ring(fd: RawFd) {
+    if !RINGS.is_empty() {
+        RINGS.remove(&fd);
+    }
+}
+
+/// Hook for libc's `syscall`, through which libuv (and others) make the io_uring syscalls, and
+/// `openat2`, which libc doesn't wrap.
+#[hook_guard_fn]
+pub(crate) unsafe extern "C" fn syscall_detour(
+    number: c_long,
+    arg1: c_long,
+    arg2: c_long,
+    arg3: c_long,
+    arg4: c_long,
+    arg5: c_long,
+    arg6: c_long,
+) -> c_long {
+    match number {
+        libc::SYS_io_uring_setup => io_uring_setup(arg1 as u32, arg2 as *mut IoUringParams),
+        libc::SYS_io_uring_enter => io_uring_enter(
+            arg1 as RawFd,
+            arg2 as u32,
+            arg3 as u32,
+            arg4 as u32,
+            arg5 as *const c_void,
+            arg6 as usize,
+        ),
+        libc::SYS_openat2 => openat2_syscall(
+            arg1 as RawFd,
+            arg2 as *const c_char,
+            arg3 as *const OpenHow,
+            arg4 as usize,
+        ),
+        _ => FN_SYSCALL(number, arg1, arg2, arg3, arg4, arg5, arg6),
+    }
+}
+
+pub(crate) unsafe fn enable_io_uring_hooks(hook_manager: &mut HookManager) {
+    if intercept_enabled() {
+        replace!(
+            hook_manager,
+            "syscall",
+            syscall_detour,
+            FnSyscall,
+            FN_SYSCALL
+        );
+    }
+}
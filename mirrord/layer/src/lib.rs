@@ -69,12 +69,14 @@ use std::{
     cmp::Ordering,
     collections::{HashMap, HashSet},
     net::SocketAddr,
+    os::fd::RawFd,
     panic,
     sync::OnceLock,
     time::Duration,
 };
 
 use ctor::ctor;
+use dashmap::DashMap;
 use error::{LayerError, Result};
 use file::OPEN_FILES;
 use hooks::HookManager;
@@ -86,13 +88,13 @@ use mirrord_config::{
     feature::{fs::FsModeConfig, network::incoming::IncomingMode},
     LayerConfig,
 };
-use mirrord_intproxy_protocol::NewSessionRequest;
+use mirrord_intproxy_protocol::{AuthToken, NewSessionRequest, INTPROXY_AUTH_TOKEN_ENV};
 use mirrord_layer_macro::{hook_fn, hook_guard_fn};
 use mirrord_protocol::{EnvVars, GetEnvVarsRequest};
 use proxy_connection::ProxyConnection;
 use setup::LayerSetup;
 use socket::SOCKETS;
-use tracing_subscriber::{fmt::format::FmtSpan, prelude::*};
+use tracing_subscriber::{fmt::format::FmtSpan, prelude::*, reload, EnvFilter, Registry};
 
 use crate::{
     common::make_proxy_request_with_response, debugger_ports::DebuggerPorts, detour::DetourGuard,
@@ -103,25 +105,42 @@ mod common;
 mod debugger_ports;
 mod detour;
 mod error;
+#[cfg(target_os = "linux")]
+mod exec_hooks;
 #[cfg(target_os = "macos")]
 mod exec_utils;
+mod expiry;
 mod file;
+mod health;
+mod heartbeat;
+mod hidden;
+mod hook_stats;
 mod hooks;
+#[cfg(target_os = "linux")]
+mod io_uring;
 mod load;
 mod macros;
+mod preload;
 mod proxy_connection;
 mod setup;
+mod simulation;
 mod socket;
 
-#[cfg(all(
-    any(target_arch = "x86_64", target_arch = "aarch64"),
-    target_os = "linux"
+#[cfg(any(
+    all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_os = "linux"
+    ),
+    target_os = "macos"
 ))]
 mod go;
 
-#[cfg(all(
-    any(target_arch = "x86_64", target_arch = "aarch64"),
-    target_os = "linux"
+#[cfg(any(
+    all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_os = "linux"
+    ),
+    target_os = "macos"
 ))]
 use crate::go::go_hooks;
 
@@ -156,6 +175,9 @@ static EXECUTABLE_ARGS: OnceLock<ExecuteArgs> = OnceLock::new();
 /// Executable path we're loaded to
 static EXECUTABLE_PATH: OnceLock<String> = OnceLock::new();
 
+/// Replaces the [`EnvFilter`] of our logs, set in [`init_tracing`] when we log to stderr.
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
 /// Proxy Connection timeout
 /// Set to 10 seconds as most agent operations timeout after 5 seconds
 const PROXY_CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
@@ -207,15 +229,20 @@ fn layer_pre_initialization() -> Result<(), LayerError> {
     Ok(())
 }
 
+/// Token to authenticate to the internal proxy, given by the mirrord CLI.
+fn intproxy_auth_token() -> Option<AuthToken> {
+    std::env::var(INTPROXY_AUTH_TOKEN_ENV).ok().map(AuthToken)
+}
+
 /// Initialize a new session with the internal proxy and set [`PROXY_CONNECTION`]
-/// if not in trace only mode.
+/// if not in trace only or [`simulation`] mode.
 fn load_only_layer_start(config: &LayerConfig) {
     // Check if we're in trace only mode (no agent)
     let trace_only = std::env::var(TRACE_ONLY_ENV)
         .unwrap_or_default()
         .parse()
         .unwrap_or(false);
-    if trace_only {
+    if trace_only || config.simulate {
         return;
     }
 
@@ -228,6 +255,7 @@ fn load_only_layer_start(config: &LayerConfig) {
 
     let new_connection = ProxyConnection::new(
         address,
+        intproxy_auth_token(),
         NewSessionRequest::New(
             EXECUTABLE_ARGS
                 .get()
@@ -235,6 +263,7 @@ fn load_only_layer_start(config: &LayerConfig) {
                 .to_process_info(config),
         ),
         PROXY_CONNECTION_TIMEOUT,
+        config.experimental.hide_layer_threads,
     )
     .expect("failed to initialize proxy connection");
 
@@ -277,7 +306,11 @@ fn init_tracing() {
     if let Ok(console_addr) = std::env::var("MIRRORD_CONSOLE_ADDR") {
         mirrord_console::init_logger(&console_addr).expect("logger initialization failed");
     } else {
+        let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+        let _ = LOG_FILTER.set(handle);
+
         tracing_subscriber::registry()
+            .with(filter)
             .with(
                 tracing_subscriber::fmt::layer()
                     .with_thread_ids(true)
@@ -285,11 +318,48 @@ fn init_tracing() {
                     .compact()
                     .with_writer(std::io::stderr),
             )
-            .with(tracing_subscriber::EnvFilter::from_default_env())
             .init();
     };
 }
 
+/// Replaces the filter of our logs with the `directives` (like `RUST_LOG`), sent by the internal
+/// proxy for `mirrord loglevel`.
+///
+/// Does nothing with mirrord-console, it gets all of our logs anyway.
+fn set_log_level(directives: &str) {
+    let Some(handle) = LOG_FILTER.get() else {
+        return;
+    };
+
+    match EnvFilter::try_new(directives) {
+        Ok(filter) => {
+            if let Err(error) = handle.reload(filter) {
+                tracing::warn!(%error, "Failed to change the log filter");
+            }
+        }
+        Err(error) => tracing::warn!(%error, directives, "Invalid log filter"),
+    }
+}
+
+/// Loads the config file again and applies what can change during the session, when the internal
+/// proxy tells us it changed (`session.hot_reload`). See [`LayerSetup::reload`].
+///
+/// An invalid config is ignored, the session continues with the current one.
+fn reload_config() {
+    let _guard = DetourGuard::new();
+
+    match LayerConfig::from_env() {
+        Ok(mut config) => {
+            if let Some(given_process) = EXECUTABLE_ARGS.get() {
+                given_process.apply_process_overrides(&mut config);
+            }
+            setup().reload(config);
+            tracing::info!("Config file reloaded");
+        }
+        Err(error) => tracing::warn!(%error, "Failed to reload the config file"),
+    }
+}
+
 /// Occurs after [`layer_pre_initialization`] has succeeded.
 ///
 /// Initialized the main parts of mirrord-layer.
@@ -307,13 +377,20 @@ fn init_tracing() {
 /// 4. Replaces the [`libc`] calls with our hooks with [`enable_hooks`];
 ///
 /// 5. Fetches remote environment from the agent (if enabled with
-/// [`EnvFileConfig::load_from_process`](mirrord_config::feature::env::EnvFileConfig::load_from_process)).
+/// [`EnvFileConfig::load_from_process`](mirrord_config::feature::env::EnvFileConfig::load_from_process));
+///
+/// 6. Runs the [`health::self_check`].
 fn layer_start(mut config: LayerConfig) {
     if config.target.path.is_none() {
         // Use localwithoverrides on targetless regardless of user config.
         config.feature.fs.mode = FsModeConfig::LocalWithOverrides;
     }
 
+    EXECUTABLE_ARGS
+        .get()
+        .expect("EXECUTABLE_ARGS MUST BE SET")
+        .apply_process_overrides(&mut config);
+
     // Check if we're in trace only mode (no agent)
     let trace_only = std::env::var(TRACE_ONLY_ENV)
         .unwrap_or_default()
@@ -330,9 +407,12 @@ fn layer_start(mut config: LayerConfig) {
     }
 
     init_tracing();
+    hook_stats::init();
+    preload::check_preloaded_libraries();
 
     let debugger_ports = DebuggerPorts::from_env();
-    let local_hostname = trace_only || !config.feature.hostname;
+    let simulate = config.simulate;
+    let local_hostname = trace_only || simulate || !config.feature.hostname;
     let process_info = EXECUTABLE_ARGS
         .get()
         .expect("EXECUTABLE_ARGS MUST BE SET")
@@ -356,12 +436,19 @@ fn layer_start(mut config: LayerConfig) {
         return;
     }
 
+    if simulate {
+        tracing::debug!("Skipping new intproxy connection (simulation)");
+        return;
+    }
+
     unsafe {
         let address = setup().proxy_address();
         let new_connection = ProxyConnection::new(
             address,
+            intproxy_auth_token(),
             NewSessionRequest::New(process_info),
             PROXY_CONNECTION_TIMEOUT,
+            setup().experimental().hide_layer_threads,
         )
         .expect("failed to initialize proxy connection");
         PROXY_CONNECTION
@@ -369,6 +456,9 @@ fn layer_start(mut config: LayerConfig) {
             .expect("setting PROXY_CONNECTION singleton")
     }
 
+    expiry::watch_session_deadline(setup().experimental().hide_layer_threads);
+    heartbeat::start_heartbeat();
+
     let fetch_env = setup().env_config().load_from_process.unwrap_or(false)
         && !std::env::var(REMOTE_ENV_FETCHED)
             .unwrap_or_default()
@@ -383,6 +473,15 @@ fn layer_start(mut config: LayerConfig) {
         std::env::set_var(REMOTE_ENV_FETCHED, "true");
     }
 
+    if let Some(cwd) = setup()
+        .fs_config()
+        .cwd
+        .as_deref()
+        .filter(|_| setup().fs_config().is_active())
+    {
+        file::ops::init_remote_cwd(cwd);
+    }
+
     if let Some(unset) = setup().env_config().unset.as_ref() {
         let unset = unset.iter().map(|s| s.to_lowercase()).collect::<Vec<_>>();
         std::env::vars().for_each(|(key, _)| {
@@ -391,6 +490,8 @@ fn layer_start(mut config: LayerConfig) {
             }
         });
     }
+
+    health::self_check();
 }
 
 /// Name of environment variable used to mark whether remote environment has already been fetched.
@@ -454,6 +555,12 @@ fn sip_only_layer_start(mut config: LayerConfig, patch_binaries: Vec<String>) {
         read_only: None,
         local: None,
         not_found: None,
+        rules: None,
+        cwd: None,
+        image_paths: None,
+        local_override: None,
+        mapping: None,
+        shared_scratch: None,
     };
     let debugger_ports = DebuggerPorts::from_env();
     let setup = LayerSetup::new(config, debugger_ports, true);
@@ -474,9 +581,16 @@ fn sip_only_layer_start(mut config: LayerConfig, patch_binaries: Vec<String>) {
 /// - `enabled_remote_dns`: replaces [`libc::getaddrinfo`] and [`libc::freeaddrinfo`] when this is
 ///   `true`, see [`NetworkConfig`](mirrord_config::feature::network::NetworkConfig), and
 ///   [`hooks::enable_socket_hooks`](socket::hooks::enable_socket_hooks).
+///
+/// The functions in
+/// [`ExperimentalConfig::disabled_hooks`](mirrord_config::experimental::ExperimentalConfig::disabled_hooks)
+/// are left alone.
 #[mirrord_layer_macro::instrument(level = "trace")]
 fn enable_hooks(enabled_file_ops: bool, enabled_remote_dns: bool, patch_binaries: Vec<String>) {
     let mut hook_manager = HookManager::default();
+    if let Some(disabled_hooks) = setup().experimental().disabled_hooks.clone() {
+        hook_manager.disable_hooks(disabled_hooks);
+    }
 
     unsafe {
         replace!(&mut hook_manager, "close", close_detour, FnClose, FN_CLOSE);
@@ -521,6 +635,16 @@ fn enable_hooks(enabled_file_ops: bool, enabled_remote_dns: bool, patch_binaries
 
     unsafe { socket::hooks::enable_socket_hooks(&mut hook_manager, enabled_remote_dns) };
 
+    #[cfg(target_os = "linux")]
+    unsafe {
+        exec_hooks::enable_exec_hooks(&mut hook_manager)
+    };
+
+    #[cfg(target_os = "linux")]
+    unsafe {
+        io_uring::enable_io_uring_hooks(&mut hook_manager)
+    };
+
     #[cfg(target_os = "macos")]
     unsafe {
         exec_utils::enable_execve_hook(&mut hook_manager, patch_binaries)
@@ -530,32 +654,71 @@ fn enable_hooks(enabled_file_ops: bool, enabled_remote_dns: bool, patch_binaries
         unsafe { file::hooks::enable_file_hooks(&mut hook_manager) };
     }
 
-    #[cfg(all(
-        any(target_arch = "x86_64", target_arch = "aarch64"),
-        target_os = "linux"
+    #[cfg(any(
+        all(
+            any(target_arch = "x86_64", target_arch = "aarch64"),
+            target_os = "linux"
+        ),
+        target_os = "macos"
     ))]
     {
         go_hooks::enable_hooks(&mut hook_manager);
     }
 }
 
+/// Releases the unused capacity of an fd map, when
+/// [`ExperimentalConfig::low_memory`](mirrord_config::experimental::ExperimentalConfig::low_memory)
+/// is enabled.
+///
+/// Maps never shrink on their own, so a burst of descriptors would otherwise keep the memory
+/// allocated for the rest of the run.
+fn shrink_fd_map<V>(map: &DashMap<RawFd, V>) {
+    /// Below this capacity shrinking doesn't free enough to be worth it.
+    const MIN_CAPACITY: usize = 64;
+
+    let capacity = map.capacity();
+    if capacity > MIN_CAPACITY && map.len() * 4 < capacity {
+        map.shrink_to_fit();
+    }
+}
+
 /// Shared code for closing `fd` in our data structures.
 ///
 /// Callers should call their respective close before calling this.
 ///
 /// ## Details
 ///
-/// Removes the `fd` key from either [`SOCKETS`] or [`OPEN_FILES`].
+/// Removes the `fd` key from either [`SOCKETS`] or [`OPEN_FILES`], stops intercepting the
+/// io_uring `fd`, and removes the remote watches of the inotify `fd`.
+///
+/// In low memory mode (see [`shrink_fd_map`]), the map is also shrunk once most of its capacity
+/// is unused.
 /// **DON'T ADD LOGS HERE SINCE CALLER MIGHT CLOSE STDOUT/STDERR CAUSING THIS TO CRASH**
 #[mirrord_layer_macro::instrument(level = "trace")]
 pub(crate) fn close_layer_fd(fd: c_int) {
+    let low_memory = setup().experimental().low_memory;
+
     // Remove from sockets.
     if let Some((_, socket)) = SOCKETS.remove(&fd) {
         // Closed file is a socket, so if it's already bound to a port - notify agent to stop
         // mirroring/stealing that port.
         socket.close();
+
+        if low_memory {
+            shrink_fd_map(&SOCKETS);
+        }
     } else if setup().fs_config().is_active() {
         OPEN_FILES.remove(&fd);
+
+        if low_memory {
+            shrink_fd_map(&OPEN_FILES);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        io_uring::forget_ring(fd);
+        file::inotify::forget_instance(fd);
     }
 }
 
@@ -598,8 +761,10 @@ pub(crate) unsafe extern "C" fn fork_detour() -> pid_t {
 
             let new_connection = ProxyConnection::new(
                 parent_connection.proxy_addr(),
+                parent_connection.auth_token(),
                 NewSessionRequest::Forked(parent_connection.layer_id()),
                 PROXY_CONNECTION_TIMEOUT,
+                parent_connection.hidden(),
             )
             .expect("failed to establish proxy connection for child");
             PROXY_CONNECTION
@@ -610,6 +775,8 @@ pub(crate) unsafe extern "C" fn fork_detour() -> pid_t {
             // better implementation would be to somehow close the underlying connections
             // but side effect should be trivial
             std::mem::forget(parent_connection);
+
+            heartbeat::start_heartbeat();
         }
         Ordering::Greater => tracing::debug!("Child process id is {res}."),
         Ordering::Less => tracing::debug!("fork failed"),
@@ -137,6 +137,27 @@ impl ExecuteArgs {
         }
     }
 
+    /// Applies the first of [`LayerConfig::process_overrides`] that matches this process to the
+    /// features of the `config`.
+    pub(crate) fn apply_process_overrides(&self, config: &mut LayerConfig) {
+        let Some(process_override) = config
+            .process_overrides
+            .iter()
+            .flatten()
+            .find(|process_override| {
+                process_override.regex().is_ok_and(|regex| {
+                    regex.is_match(&self.exec_name) || regex.is_match(&self.invoked_as)
+                })
+            })
+            .cloned()
+        else {
+            return;
+        };
+
+        trace!(process = %self, ?process_override, "Applying process override.");
+        process_override.apply(&mut config.feature);
+    }
+
     pub(crate) fn to_process_info(&self, config: &LayerConfig) -> ProcessInfo {
         ProcessInfo {
             pid: std::process::id(),
@@ -0,0 +1,231 @@
+//! Detection of the other libraries loaded with [`INJECTION_ENV_VAR`] next to the layer.
+//!
+//! Some of them hook the same functions we do, most notably the APM agents that instrument the
+//! application (Datadog, Instana), and the result is a crash that has nothing to do with the
+//! application's code. We can't fix that from here, so we look at what else is preloaded, warn
+//! about the libraries we know to conflict with the layer, and tell the user how to run without
+//! them.
+//!
+//! We also put the layer first in [`INJECTION_ENV_VAR`] for the processes started by the
+//! application, so our hooks are installed before those of the other libraries.
+
+use std::path::Path;
+
+use tracing::{debug, warn};
+
+use crate::setup::INJECTION_ENV_VAR;
+
+/// How well a preloaded library gets along with the layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compatibility {
+    /// Doesn't hook anything we do, e.g. allocators.
+    Allowed,
+    /// Hooks functions we hook as well.
+    Conflicting {
+        /// Product the library belongs to.
+        product: &'static str,
+        /// How to run the application without it.
+        workaround: &'static str,
+    },
+}
+
+/// A library we know about, matched by its path.
+struct KnownPreload {
+    /// Part of the path of the library, e.g. the directory of the product it belongs to.
+    pattern: &'static str,
+    compatibility: Compatibility,
+}
+
+const DATADOG: Compatibility = Compatibility::Conflicting {
+    product: "Datadog APM",
+    workaround: "disable the Datadog instrumentation for the local run, e.g. with \
+                 `DD_INSTRUMENT_SERVICE_WITH_APM=false`, or remove the library from the \
+                 environment",
+};
+
+/// Libraries we know about, the first one that matches is used.
+const KNOWN_PRELOADS: &[KnownPreload] = &[
+    KnownPreload {
+        pattern: "datadog",
+        compatibility: DATADOG,
+    },
+    KnownPreload {
+        pattern: "ddtrace",
+        compatibility: DATADOG,
+    },
+    KnownPreload {
+        pattern: "instana",
+        compatibility: Compatibility::Conflicting {
+            product: "Instana AutoTrace",
+            workaround: "disable the Instana instrumentation for the local run, or remove the \
+                         library from the environment",
+        },
+    },
+    KnownPreload {
+        pattern: "libasan",
+        compatibility: Compatibility::Conflicting {
+            product: "AddressSanitizer",
+            workaround: "run a build of the application without the sanitizer",
+        },
+    },
+    KnownPreload {
+        pattern: "libfaketime",
+        compatibility: Compatibility::Conflicting {
+            product: "libfaketime",
+            workaround: "remove the library from the environment",
+        },
+    },
+    KnownPreload {
+        pattern: "jemalloc",
+        compatibility: Compatibility::Allowed,
+    },
+    KnownPreload {
+        pattern: "tcmalloc",
+        compatibility: Compatibility::Allowed,
+    },
+    KnownPreload {
+        pattern: "mimalloc",
+        compatibility: Compatibility::Allowed,
+    },
+    KnownPreload {
+        pattern: "libstdbuf",
+        compatibility: Compatibility::Allowed,
+    },
+];
+
+/// Whether `library` is the layer itself.
+fn is_layer(library: &str) -> bool {
+    file_name(library).contains("mirrord_layer")
+}
+
+fn file_name(library: &str) -> &str {
+    Path::new(library)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(library)
+}
+
+/// Splits the value of [`INJECTION_ENV_VAR`] into libraries, `ld.so` accepts both spaces and
+/// colons as separators, `dyld` only colons.
+fn preloaded_libraries(value: &str) -> Vec<&str> {
+    value
+        .split(|c: char| c == ':' || (cfg!(target_os = "linux") && c.is_ascii_whitespace()))
+        .filter(|library| !library.is_empty())
+        .collect()
+}
+
+/// What we know about `library`, [`None`] if we don't.
+fn compatibility(library: &str) -> Option<Compatibility> {
+    let path = library.to_lowercase();
+
+    KNOWN_PRELOADS
+        .iter()
+        .find(|known| path.contains(known.pattern))
+        .map(|known| known.compatibility)
+}
+
+/// `libraries` with the layer moved to the front, [`None`] if it already is there (or not there
+/// at all).
+fn layer_first(libraries: &[&str]) -> Option<String> {
+    let position = libraries.iter().position(|library| is_layer(library))?;
+    if position == 0 {
+        return None;
+    }
+
+    let mut reordered = libraries.to_vec();
+    let layer = reordered.remove(position);
+    reordered.insert(0, layer);
+
+    Some(reordered.join(":"))
+}
+
+/// Warns about the other libraries in [`INJECTION_ENV_VAR`] that conflict with the layer, and
+/// puts the layer first for the child processes.
+///
+/// Must be called before the [`LayerSetup`](crate::setup::LayerSetup) is created, so the
+/// reordered value is the one restored on `execve`.
+pub(crate) fn check_preloaded_libraries() {
+    let Ok(value) = std::env::var(INJECTION_ENV_VAR) else {
+        return;
+    };
+    let libraries = preloaded_libraries(&value);
+
+    for library in libraries.iter().filter(|library| !is_layer(library)) {
+        match compatibility(library) {
+            Some(Compatibility::Allowed) => {
+                debug!(library, "Preloaded library is compatible with mirrord")
+            }
+            Some(Compatibility::Conflicting {
+                product,
+                workaround,
+            }) => warn!(
+                library,
+                product,
+                workaround,
+                "{library} ({product}) is loaded with {INJECTION_ENV_VAR} next to mirrord and \
+                 hooks the same functions, which is known to crash the application or make \
+                 mirrord miss its calls. To run with mirrord, {workaround}."
+            ),
+            None => debug!(
+                library,
+                "Unknown library is loaded with {INJECTION_ENV_VAR} next to mirrord, if the \
+                 application crashes, try running without it"
+            ),
+        }
+    }
+
+    if let Some(reordered) = layer_first(&libraries) {
+        debug!(%reordered, "Moving mirrord first in {INJECTION_ENV_VAR}");
+        std::env::set_var(INJECTION_ENV_VAR, reordered);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn split_libraries() {
+        assert_eq!(
+            preloaded_libraries("/a/liba.so:/b/libb.so /c/libc.so  "),
+            vec!["/a/liba.so", "/b/libb.so", "/c/libc.so"]
+        );
+    }
+
+    #[test]
+    fn known_libraries() {
+        assert!(matches!(
+            compatibility("/opt/datadog/apm/inject/launcher.preload.so"),
+            Some(Compatibility::Conflicting {
+                product: "Datadog APM",
+                ..
+            })
+        ));
+        assert!(matches!(
+            compatibility("/opt/instana/instrumentation/libinstana_init/libinstana_init.so"),
+            Some(Compatibility::Conflicting { .. })
+        ));
+        assert_eq!(
+            compatibility("/usr/lib/libjemalloc.so.2"),
+            Some(Compatibility::Allowed)
+        );
+        assert_eq!(compatibility("/usr/lib/libsomething.so"), None);
+    }
+
+    #[test]
+    fn reorder_layer_first() {
+        assert_eq!(
+            layer_first(&[
+                "/opt/datadog/launcher.preload.so",
+                "/tmp/libmirrord_layer.so"
+            ]),
+            Some("/tmp/libmirrord_layer.so:/opt/datadog/launcher.preload.so".to_string())
+        );
+        assert_eq!(
+            layer_first(&["/tmp/libmirrord_layer.so", "/usr/lib/libjemalloc.so"]),
+            None
+        );
+        assert_eq!(layer_first(&["/usr/lib/libjemalloc.so"]), None);
+    }
+}
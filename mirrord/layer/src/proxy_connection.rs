@@ -12,11 +12,13 @@ use std::{
 
 use mirrord_intproxy_protocol::{
     codec::{self, CodecError, SyncDecoder, SyncEncoder},
-    IsLayerRequest, IsLayerRequestWithResponse, LayerId, LayerToProxyMessage, LocalMessage,
-    MessageId, NewSessionRequest, ProxyToLayerMessage,
+    AuthToken, IsLayerRequest, IsLayerRequestWithResponse, LayerId, LayerToProxyMessage,
+    LocalMessage, MessageId, NewSessionRequest, ProxyToLayerMessage,
 };
 use thiserror::Error;
 
+use crate::hidden;
+
 #[derive(Debug, Error)]
 pub enum ProxyError {
     #[error("{0}")]
@@ -46,15 +48,28 @@ pub struct ProxyConnection {
     next_message_id: AtomicU64,
     layer_id: LayerId,
     proxy_addr: SocketAddr,
+    auth_token: Option<AuthToken>,
+    /// Whether the connection's descriptor was moved out of the way with
+    /// [`hidden::hide_stream`].
+    hidden: bool,
 }
 
 impl ProxyConnection {
+    /// Connects to the internal proxy, authenticates with `auth_token` (when given, see
+    /// [`LayerToProxyMessage::Authenticate`]) and starts a new session.
+    ///
+    /// When `hidden` is set, the connection's descriptor is moved with [`hidden::hide_stream`].
     pub fn new(
         proxy_addr: SocketAddr,
+        auth_token: Option<AuthToken>,
         session: NewSessionRequest,
         timeout: Duration,
+        hidden: bool,
     ) -> Result<Self> {
-        let connection = TcpStream::connect(proxy_addr)?;
+        let mut connection = TcpStream::connect(proxy_addr)?;
+        if hidden {
+            connection = hidden::hide_stream(connection)?;
+        }
         connection.set_read_timeout(Some(timeout))?;
         connection.set_write_timeout(Some(timeout))?;
 
@@ -63,6 +78,13 @@ impl ProxyConnection {
             LocalMessage<ProxyToLayerMessage>,
         >(connection)?;
 
+        if let Some(auth_token) = auth_token.clone() {
+            sender.send(&LocalMessage {
+                message_id: 0,
+                inner: LayerToProxyMessage::Authenticate(auth_token),
+            })?;
+        }
+
         sender.send(&LocalMessage {
             message_id: 0,
             inner: LayerToProxyMessage::NewSession(session),
@@ -80,6 +102,8 @@ impl ProxyConnection {
             next_message_id: AtomicU64::new(1),
             layer_id: *layer_id,
             proxy_addr,
+            auth_token,
+            hidden,
         })
     }
 
@@ -131,6 +155,14 @@ impl ProxyConnection {
     pub fn proxy_addr(&self) -> SocketAddr {
         self.proxy_addr
     }
+
+    pub fn auth_token(&self) -> Option<AuthToken> {
+        self.auth_token.clone()
+    }
+
+    pub fn hidden(&self) -> bool {
+        self.hidden
+    }
 }
 
 #[derive(Debug)]
@@ -158,6 +190,16 @@ impl ResponseManager {
                 .receive()?
                 .ok_or(ProxyError::ConnectionClosed)?;
 
+            if let ProxyToLayerMessage::SetLogLevel(directives) = &response.inner {
+                crate::set_log_level(directives);
+                continue;
+            }
+
+            if let ProxyToLayerMessage::ReloadConfig = &response.inner {
+                crate::reload_config();
+                continue;
+            }
+
             if response.message_id == response_id {
                 break Ok(response.inner);
             }
@@ -1,7 +1,12 @@
-use std::{collections::HashSet, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
 
 use mirrord_config::{
     experimental::ExperimentalConfig,
+    fallback::FALLBACK_ACTIVE_ENV,
     feature::{
         env::EnvConfig,
         fs::FsConfig,
@@ -10,31 +15,48 @@ use mirrord_config::{
     target::Target,
     LayerConfig,
 };
-use mirrord_intproxy_protocol::PortSubscription;
-use mirrord_protocol::{
-    tcp::{Filter, HttpFilter, StealType},
-    Port,
-};
+pub use mirrord_intproxy_protocol::IncomingMode;
+use mirrord_intproxy_protocol::OUTGOING_PROXY_SERVER_ENV;
 use regex::RegexSet;
 
 use crate::{debugger_ports::DebuggerPorts, file::filter::FileFilter, socket::OutgoingSelector};
 
+/// Env var that loads the layer into the processes.
+#[cfg(target_os = "linux")]
+pub(crate) const INJECTION_ENV_VAR: &str = "LD_PRELOAD";
+
+/// Env var that loads the layer into the processes.
+#[cfg(target_os = "macos")]
+pub(crate) const INJECTION_ENV_VAR: &str = "DYLD_INSERT_LIBRARIES";
+
 /// Complete layer setup.
 /// Contains [`LayerConfig`] and derived from it structs, which are used in multiple places across
 /// the layer.
+///
+/// The structs that can change with the config file during the session (`session.hot_reload`)
+/// are behind [`RwLock`]s, see [`Self::reload`]. Their getters return an [`Arc`], so that no lock
+/// is held while they are used.
 #[derive(Debug)]
 pub struct LayerSetup {
     config: LayerConfig,
-    file_filter: FileFilter,
+    file_filter: RwLock<Arc<FileFilter>>,
     debugger_ports: DebuggerPorts,
     remote_unix_streams: RegexSet,
-    outgoing_selector: OutgoingSelector,
-    proxy_address: SocketAddr,
-    incoming_mode: IncomingMode,
+    outgoing_selector: RwLock<Arc<OutgoingSelector>>,
+    /// Missing in [`simulation`](crate::simulation) mode, there's no internal proxy then.
+    proxy_address: Option<SocketAddr>,
+    incoming_mode: RwLock<Arc<IncomingMode>>,
+    /// `feature.env.override` values that were last applied, see [`Self::reload`].
+    env_overrides: Mutex<HashMap<String, String>>,
     local_hostname: bool,
-    // to be used on macOS to restore env on execv
-    #[cfg(target_os = "macos")]
+    /// mirrord's env (see [`INJECTION_ENV_VAR`]), restored on `execve` when a process clears it.
     env_backup: Vec<(String, String)>,
+    /// Address of the internal proxy's SOCKS5 and HTTP proxy server, see
+    /// [`OutgoingConfig::proxy_server`].
+    outgoing_proxy_server: Option<String>,
+    /// `fallback.hosts`, set when the CLI couldn't reach the cluster and runs the session with
+    /// the local substitutes of `fallback` (in [`simulation`](crate::simulation) mode).
+    fallback_hosts: Option<HashMap<String, IpAddr>>,
 }
 
 impl LayerSetup {
@@ -55,30 +77,36 @@ impl LayerSetup {
         let outgoing_selector: OutgoingSelector =
             OutgoingSelector::new(&config.feature.network.outgoing);
 
-        let proxy_address = config
-            .connect_tcp
-            .as_ref()
-            .expect("missing internal proxy address")
-            .parse()
-            .expect("failed to parse internal proxy address");
+        let proxy_address = config.connect_tcp.as_ref().map(|address| {
+            address
+                .parse()
+                .expect("failed to parse internal proxy address")
+        });
 
-        let incoming_mode = IncomingMode::new(&config.feature.network.incoming);
-        #[cfg(target_os = "macos")]
+        let incoming_mode =
+            IncomingMode::new(&config.feature.network.incoming).expect("invalid incoming config");
+        let env_overrides = config.feature.env.r#override.clone().unwrap_or_default();
         let env_backup = std::env::vars()
-            .filter(|(k, _)| k.starts_with("MIRRORD_") || k == "DYLD_INSERT_LIBRARIES")
+            .filter(|(k, _)| k.starts_with("MIRRORD_") || k == INJECTION_ENV_VAR)
             .collect();
+        let outgoing_proxy_server = std::env::var(OUTGOING_PROXY_SERVER_ENV).ok();
+        let fallback_hosts = std::env::var(FALLBACK_ACTIVE_ENV)
+            .is_ok_and(|active| active == "true")
+            .then(|| config.fallback.hosts.clone().unwrap_or_default());
 
         Self {
             config,
-            file_filter,
+            file_filter: RwLock::new(Arc::new(file_filter)),
             debugger_ports,
             remote_unix_streams,
-            outgoing_selector,
+            outgoing_selector: RwLock::new(Arc::new(outgoing_selector)),
             proxy_address,
-            incoming_mode,
+            incoming_mode: RwLock::new(Arc::new(incoming_mode)),
+            env_overrides: Mutex::new(env_overrides),
             local_hostname,
-            #[cfg(target_os = "macos")]
             env_backup,
+            outgoing_proxy_server,
+            fallback_hosts,
         }
     }
 
@@ -90,8 +118,11 @@ impl LayerSetup {
         &self.config.feature.fs
     }
 
-    pub fn file_filter(&self) -> &FileFilter {
-        &self.file_filter
+    pub fn file_filter(&self) -> Arc<FileFilter> {
+        self.file_filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
     }
 
     pub fn incoming_config(&self) -> &IncomingConfig {
@@ -131,8 +162,11 @@ impl LayerSetup {
         self.debugger_ports.contains(addr)
     }
 
-    pub fn outgoing_selector(&self) -> &OutgoingSelector {
-        &self.outgoing_selector
+    pub fn outgoing_selector(&self) -> Arc<OutgoingSelector> {
+        self.outgoing_selector
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
     }
 
     pub fn remote_unix_streams(&self) -> &RegexSet {
@@ -140,92 +174,91 @@ impl LayerSetup {
     }
 
     pub fn proxy_address(&self) -> SocketAddr {
-        self.proxy_address
+        self.proxy_address.expect("missing internal proxy address")
     }
 
-    pub fn incoming_mode(&self) -> &IncomingMode {
-        &self.incoming_mode
+    pub fn incoming_mode(&self) -> Arc<IncomingMode> {
+        self.incoming_mode
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
     }
 
     pub fn local_hostname(&self) -> bool {
         self.local_hostname
     }
 
-    #[cfg(target_os = "macos")]
-    pub fn env_backup(&self) -> &Vec<(String, String)> {
-        &self.env_backup
+    pub fn simulate(&self) -> bool {
+        self.config.simulate
     }
-}
-
-/// HTTP filter used by the layer with the `steal` feature.
-#[derive(Debug)]
-pub enum StealHttpFilter {
-    /// No filter.
-    None,
-    /// More recent filter (header or path).
-    Filter(HttpFilter),
-}
-
-/// Settings for handling HTTP with the `steal` feature.
-#[derive(Debug)]
-pub struct StealHttpSettings {
-    /// The HTTP filter to use.
-    pub filter: StealHttpFilter,
-    /// Ports to filter HTTP on.
-    pub ports: HashSet<Port>,
-}
 
-/// Operation mode for the `incoming` feature.
-#[derive(Debug)]
-pub enum IncomingMode {
-    /// The agent sends data to both the user application and the remote target.
-    /// Data coming from the layer is discarded.
-    Mirror,
-    /// The agent sends data only to the user application.
-    /// Data coming from the layer is sent to the agent.
-    Steal(StealHttpSettings),
-}
-
-impl IncomingMode {
-    /// Creates a new instance from the given [`LayerConfig`].
-    fn new(config: &IncomingConfig) -> Self {
-        if !config.is_steal() {
-            return Self::Mirror;
-        }
-
-        let http_filter_config = &config.http_filter;
+    pub fn debugger_mode(&self) -> bool {
+        self.config.debugger_mode
+    }
 
-        let ports = { http_filter_config.ports.iter().copied().collect() };
+    /// Whether the session runs with the local substitutes of `fallback`.
+    pub fn fallback(&self) -> bool {
+        self.fallback_hosts.is_some()
+    }
 
-        let filter = match (
-            &http_filter_config.path_filter,
-            &http_filter_config.header_filter,
-        ) {
-            (Some(path), None) => StealHttpFilter::Filter(HttpFilter::Path(
-                Filter::new(path.into()).expect("invalid filter expression"),
-            )),
-            (None, Some(header)) => StealHttpFilter::Filter(HttpFilter::Header(
-                Filter::new(header.into()).expect("invalid filter expression"),
-            )),
-            (None, None) => StealHttpFilter::None,
-            _ => panic!("multiple HTTP filters specified"),
-        };
+    /// Address of the host `name` in `fallback.hosts`, when running with them.
+    pub fn fallback_host(&self, name: &str) -> Option<IpAddr> {
+        self.fallback_hosts.as_ref()?.get(name).copied()
+    }
 
-        Self::Steal(StealHttpSettings { filter, ports })
+    pub fn env_backup(&self) -> &Vec<(String, String)> {
+        &self.env_backup
     }
 
-    /// Returns [`PortSubscription`] request to be used for the given port.
-    pub fn subscription(&self, port: Port) -> PortSubscription {
-        let Self::Steal(steal) = self else {
-            return PortSubscription::Mirror(port);
-        };
+    pub fn outgoing_proxy_server(&self) -> Option<&str> {
+        self.outgoing_proxy_server.as_deref()
+    }
 
-        let steal_type = match &steal.filter {
-            _ if !steal.ports.contains(&port) => StealType::All(port),
-            StealHttpFilter::None => StealType::All(port),
-            StealHttpFilter::Filter(filter) => StealType::FilteredHttpEx(port, filter.clone()),
-        };
+    /// Applies the parts of the reloaded `config` that can change during the session
+    /// (`session.hot_reload`), the rest of it is ignored:
+    ///
+    /// - the path lists of `feature.fs`, the mode stays the same;
+    /// - `feature.network.outgoing.filter`;
+    /// - the HTTP filter of `feature.network.incoming`, used for the ports subscribed from now on
+    ///   (the internal proxy updates the existing subscriptions), when the mode stays `steal`;
+    /// - the changed values of `feature.env.override`, set in the env of this process. Removed
+    ///   overrides keep their value.
+    pub fn reload(&self, config: LayerConfig) {
+        let mut fs_config = config.feature.fs;
+        fs_config.mode = self.config.feature.fs.mode;
+        *self
+            .file_filter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Arc::new(FileFilter::new(fs_config));
+
+        *self
+            .outgoing_selector
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) =
+            Arc::new(OutgoingSelector::new(&config.feature.network.outgoing));
+
+        match IncomingMode::new(&config.feature.network.incoming) {
+            Ok(incoming_mode @ IncomingMode::Steal(..))
+                if matches!(*self.incoming_mode(), IncomingMode::Steal(..)) =>
+            {
+                *self
+                    .incoming_mode
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner) = Arc::new(incoming_mode);
+            }
+            Ok(..) => {}
+            Err(error) => tracing::warn!(error, "Invalid incoming config, keeping the HTTP filter"),
+        }
 
-        PortSubscription::Steal(steal_type)
+        let mut env_overrides = self
+            .env_overrides
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (key, value) in config.feature.env.r#override.unwrap_or_default() {
+            if env_overrides.get(&key) != Some(&value) {
+                std::env::set_var(&key, &value);
+                env_overrides.insert(key, value);
+            }
+        }
     }
 }
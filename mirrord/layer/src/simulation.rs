@@ -0,0 +1,33 @@
+//! Simulation mode, enabled with [`LayerConfig::simulate`](mirrord_config::LayerConfig::simulate).
+//!
+//! The CLI doesn't start the agent nor the internal proxy, and the layer makes no connection to
+//! it. The hooks still go through the config to decide what would be handled remotely, and when
+//! something would be, we print it and do it locally instead, so the user can check their config
+//! without a cluster.
+//!
+//! The CLI also runs the session this way when it can't reach the cluster and the config has
+//! local substitutes in `fallback`, without printing anything.
+
+use std::fmt::Display;
+
+use crate::detour::{Bypass, Detour};
+
+/// Prefix of the lines we print, so they're easy to tell apart from the application's output.
+const PREFIX: &str = "mirrord (simulated):";
+
+/// Prints the `operation` on `target` that would have been remoted, and bypasses it.
+///
+/// Does nothing when not in simulation mode, so call it right before making the request to the
+/// internal proxy.
+pub(crate) fn bypass<T: Display>(operation: &str, target: T) -> Detour<()> {
+    if !crate::setup().simulate() {
+        return Detour::Success(());
+    }
+
+    // The user didn't ask for the simulation, mirrord just couldn't reach the cluster.
+    if !crate::setup().fallback() {
+        eprintln!("{PREFIX} {operation} {target}");
+    }
+
+    Detour::Bypass(Bypass::Simulated)
+}
@@ -13,7 +13,7 @@ use libc::{c_int, sockaddr, socklen_t};
 use mirrord_config::feature::network::outgoing::{
     AddressFilter, OutgoingConfig, OutgoingFilter, OutgoingFilterConfig, ProtocolFilter,
 };
-use mirrord_intproxy_protocol::{NetProtocol, PortUnsubscribe};
+use mirrord_intproxy_protocol::{NetProtocol, PortUnsubscribe, UdpPortUnsubscribe};
 use mirrord_protocol::{
     outgoing::SocketAddress, DnsLookupError, ResolveErrorKindInternal, ResponseError,
 };
@@ -29,9 +29,18 @@ use crate::{
 
 pub(super) mod hooks;
 pub(crate) mod ops;
+mod record;
 
 pub(crate) static SOCKETS: LazyLock<DashMap<RawFd, Arc<UserSocket>>> = LazyLock::new(DashMap::new);
 
+/// Remote peers of the UDP sockets that receive the remote datagrams, by the local addresses of the
+/// internal proxy's sockets that send the datagrams on their behalf.
+///
+/// Filled in [`ops::recv_from`], and used in [`ops::send_to`] to send the replies to the right
+/// internal proxy socket.
+pub(crate) static UDP_PEERS: LazyLock<DashMap<SocketAddr, SocketAddr>> =
+    LazyLock::new(DashMap::new);
+
 /// Contains the addresses of a mirrord connected socket.
 ///
 /// - `layer_address` is only used for the outgoing feature.
@@ -91,6 +100,7 @@ pub enum SocketState {
     #[default]
     Initialized,
     Bound(Bound),
+    /// For UDP sockets, bound to a port that receives the remote datagrams.
     Listening(Bound),
     Connected(Connected),
 }
@@ -164,17 +174,26 @@ impl UserSocket {
     /// Inform internal proxy about closing a listening port.
     #[mirrord_layer_macro::instrument(level = "trace", ret)]
     pub(crate) fn close(&self) {
-        if let Self {
-            state: SocketState::Listening(bound),
-            kind: SocketKind::Tcp(..),
-            ..
-        } = self
-        {
-            let _ = common::make_proxy_request_no_response(PortUnsubscribe {
-                port: bound.requested_address.port(),
+        let SocketState::Listening(bound) = &self.state else {
+            return;
+        };
+
+        let port = bound.requested_address.port();
+        let _ = match self.kind {
+            SocketKind::Tcp(..) => common::make_proxy_request_no_response(PortUnsubscribe {
+                port,
                 listening_on: bound.address,
-            });
-        }
+            }),
+            SocketKind::Udp(..) => common::make_proxy_request_no_response(UdpPortUnsubscribe {
+                port: crate::setup()
+                    .incoming_config()
+                    .port_mapping
+                    .get_by_left(&port)
+                    .copied()
+                    .unwrap_or(port),
+                listening_on: bound.address,
+            }),
+        };
     }
 }
 
@@ -382,7 +401,9 @@ impl OutgoingFilterExt for OutgoingFilter {
 
         match &self.address {
             AddressFilter::Name((name, port)) => {
-                let resolved_ips = if crate::setup().remote_dns_enabled() && !force_local_dns {
+                // Nothing to resolve with in simulation mode.
+                let remote_dns = crate::setup().remote_dns_enabled() && !crate::setup().simulate();
+                let resolved_ips = if remote_dns && !force_local_dns {
                     match remote_getaddrinfo(name.to_string()) {
                         Ok(res) => res.into_iter().map(|(_, ip)| ip).collect(),
                         Err(HookError::ResponseError(ResponseError::DnsLookup(
@@ -1,5 +1,12 @@
 use alloc::ffi::CString;
 use core::{cmp, ffi::CStr};
+#[cfg(target_os = "linux")]
+use std::{
+    collections::HashSet,
+    ptr,
+    sync::{Condvar, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 use std::{os::unix::io::RawFd, sync::LazyLock};
 
 use dashmap::DashSet;
@@ -8,7 +15,12 @@ use libc::{c_char, c_int, c_void, hostent, size_t, sockaddr, socklen_t, ssize_t,
 use mirrord_layer_macro::{hook_fn, hook_guard_fn};
 
 use super::ops::*;
-use crate::{detour::DetourGuard, hooks::HookManager, replace};
+use crate::{
+    detour::DetourGuard,
+    hidden::{hide_current_thread, LAYER_THREAD_PREFIX},
+    hooks::HookManager,
+    replace,
+};
 /// Here we keep addr infos that we allocated so we'll know when to use the original
 /// freeaddrinfo function and when to use our implementation
 pub(crate) static MANAGED_ADDRINFO: LazyLock<DashSet<usize>> = LazyLock::new(DashSet::new);
@@ -185,6 +197,17 @@ pub(super) unsafe extern "C" fn _accept_nocancel_detour(
 #[hook_fn]
 pub(super) unsafe extern "C" fn fcntl_detour(fd: c_int, cmd: c_int, mut arg: ...) -> c_int {
     let arg = arg.arg::<usize>();
+
+    // Locks on remote files are taken on the agent.
+    if matches!(cmd, libc::F_GETLK | libc::F_SETLK | libc::F_SETLKW) {
+        let Some(_guard) = DetourGuard::new() else {
+            return FN_FCNTL(fd, cmd, arg);
+        };
+
+        return crate::file::ops::fcntl_lock(fd, cmd, arg as *mut libc::flock)
+            .unwrap_or_bypass_with(|_| FN_FCNTL(fd, cmd, arg));
+    }
+
     let fcntl_result = FN_FCNTL(fd, cmd, arg);
     let guard = DetourGuard::new();
     if guard.is_none() {
@@ -319,6 +342,258 @@ unsafe extern "C" fn freeaddrinfo_detour(addrinfo: *mut libc::addrinfo) {
         })
 }
 
+/// `struct gaicb` from glibc's `netdb.h`, a request of `getaddrinfo_a`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+pub(super) struct Gaicb {
+    ar_name: *const c_char,
+    ar_service: *const c_char,
+    ar_request: *const libc::addrinfo,
+    ar_result: *mut libc::addrinfo,
+    /// Read by `gai_error`.
+    return_: c_int,
+    _reserved: [c_int; 5],
+}
+
+/// The start of glibc's `struct sigevent`, with the fields of `SIGEV_THREAD` that [`libc`] doesn't
+/// expose.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigEvent {
+    sigev_value: libc::sigval,
+    sigev_signo: c_int,
+    sigev_notify: c_int,
+    sigev_notify_function: Option<unsafe extern "C" fn(libc::sigval)>,
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl Send for SigEvent {}
+
+#[cfg(target_os = "linux")]
+const GAI_WAIT: c_int = 0;
+#[cfg(target_os = "linux")]
+const GAI_NOWAIT: c_int = 1;
+#[cfg(target_os = "linux")]
+const EAI_INPROGRESS: c_int = -100;
+#[cfg(target_os = "linux")]
+const EAI_NOTCANCELED: c_int = -102;
+
+/// Requests of `getaddrinfo_a` (pointers to [`Gaicb`]) that are still being resolved in the
+/// background, the condvar is notified each time one of them is done.
+#[cfg(target_os = "linux")]
+static PENDING_GAICB: LazyLock<(Mutex<HashSet<usize>>, Condvar)> = LazyLock::new(Default::default);
+
+/// Resolves a single request of `getaddrinfo_a` like [`getaddrinfo_detour`] would, and stores the
+/// result where `gai_error` looks for it.
+#[cfg(target_os = "linux")]
+unsafe fn resolve_gaicb(request: *mut Gaicb) {
+    let Gaicb {
+        ar_name,
+        ar_service,
+        ar_request,
+        ..
+    } = *request;
+
+    let rawish_node = (!ar_name.is_null()).then(|| CStr::from_ptr(ar_name));
+    let rawish_service = (!ar_service.is_null()).then(|| CStr::from_ptr(ar_service));
+
+    let result = getaddrinfo(rawish_node, rawish_service, ar_request.as_ref())
+        .map(|c_addr_info_ptr| {
+            (*request).ar_result = c_addr_info_ptr;
+            MANAGED_ADDRINFO.insert(c_addr_info_ptr as usize);
+            0
+        })
+        .unwrap_or_bypass_with(|_| {
+            FN_GETADDRINFO(
+                ar_name,
+                ar_service,
+                ar_request,
+                ptr::addr_of_mut!((*request).ar_result),
+            )
+        });
+
+    ptr::write_volatile(ptr::addr_of_mut!((*request).return_), result);
+}
+
+/// Notifies the caller of `getaddrinfo_a` that all of its requests are done, the way it asked for
+/// in `sevp`.
+#[cfg(target_os = "linux")]
+unsafe fn notify_gai_done(sevp: Option<SigEvent>) {
+    let Some(sevp) = sevp else {
+        return;
+    };
+
+    match sevp.sigev_notify {
+        libc::SIGEV_SIGNAL => {
+            libc::sigqueue(libc::getpid(), sevp.sigev_signo, sevp.sigev_value);
+        }
+        libc::SIGEV_THREAD => {
+            if let Some(function) = sevp.sigev_notify_function {
+                function(sevp.sigev_value);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Hook for `getaddrinfo_a` (glibc's `libanl`), which would otherwise resolve the requests locally
+/// in its own threads.
+///
+/// Each request is resolved with [`getaddrinfo`], in the calling thread for `GAI_WAIT`, and in a
+/// new thread for `GAI_NOWAIT`, after which the caller is notified according to `sevp`. Requests
+/// in progress are reported by `gai_error` as `EAI_INPROGRESS`, since it only reads the status
+/// from the [`Gaicb`].
+#[cfg(target_os = "linux")]
+#[hook_guard_fn]
+pub(super) unsafe extern "C" fn getaddrinfo_a_detour(
+    mode: c_int,
+    list: *mut *mut Gaicb,
+    nitems: c_int,
+    sevp: *mut libc::sigevent,
+) -> c_int {
+    if list.is_null() || nitems < 0 || (mode != GAI_WAIT && mode != GAI_NOWAIT) {
+        return FN_GETADDRINFO_A(mode, list, nitems, sevp);
+    }
+
+    let requests = std::slice::from_raw_parts(list, nitems as usize)
+        .iter()
+        .filter(|request| !request.is_null())
+        .map(|request| *request as usize)
+        .collect::<Vec<_>>();
+
+    if mode == GAI_WAIT {
+        for request in requests {
+            resolve_gaicb(request as *mut Gaicb);
+        }
+
+        return 0;
+    }
+
+    // The caller may free `sevp` as soon as we return.
+    let sevp = sevp.cast::<SigEvent>().as_ref().copied();
+
+    {
+        let mut pending = PENDING_GAICB
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for request in &requests {
+            ptr::write_volatile(
+                ptr::addr_of_mut!((*(*request as *mut Gaicb)).return_),
+                EAI_INPROGRESS,
+            );
+            pending.insert(*request);
+        }
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name(format!("{LAYER_THREAD_PREFIX}getaddrinfo_a"))
+        .spawn(move || {
+            if crate::setup().experimental().hide_layer_threads {
+                hide_current_thread();
+            }
+
+            {
+                let _guard = DetourGuard::new();
+
+                for request in requests {
+                    resolve_gaicb(request as *mut Gaicb);
+
+                    let (pending, done) = &*PENDING_GAICB;
+                    pending
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .remove(&request);
+                    done.notify_all();
+                }
+            }
+
+            // Outside of the guard, the notification function is the user's code.
+            notify_gai_done(sevp);
+        });
+
+    match spawned {
+        Ok(_) => 0,
+        Err(fail) => {
+            tracing::warn!(?fail, "Failed spawning the `getaddrinfo_a` resolver thread");
+            libc::EAI_AGAIN
+        }
+    }
+}
+
+/// Hook for `gai_suspend`, waits for one of the requests of [`getaddrinfo_a_detour`] to be done.
+///
+/// Calls the original function when none of the requests in `list` is ours.
+#[cfg(target_os = "linux")]
+#[hook_guard_fn]
+pub(super) unsafe extern "C" fn gai_suspend_detour(
+    list: *const *const Gaicb,
+    nitems: c_int,
+    timeout: *const libc::timespec,
+) -> c_int {
+    if list.is_null() || nitems <= 0 {
+        return FN_GAI_SUSPEND(list, nitems, timeout);
+    }
+
+    let requests = std::slice::from_raw_parts(list, nitems as usize)
+        .iter()
+        .filter(|request| !request.is_null())
+        .map(|request| *request as usize)
+        .collect::<Vec<_>>();
+
+    let deadline = timeout.as_ref().map(|timeout| {
+        Instant::now()
+            + Duration::new(
+                timeout.tv_sec.max(0) as u64,
+                timeout.tv_nsec.clamp(0, 999_999_999) as u32,
+            )
+    });
+
+    let (pending, done) = &*PENDING_GAICB;
+    let mut pending = pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if !requests.iter().any(|request| pending.contains(request)) {
+        drop(pending);
+        return FN_GAI_SUSPEND(list, nitems, timeout);
+    }
+
+    while requests.iter().all(|request| pending.contains(request)) {
+        pending = match deadline {
+            Some(deadline) => {
+                let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                    return libc::EAI_AGAIN;
+                };
+
+                done.wait_timeout(pending, left)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+            None => done.wait(pending).unwrap_or_else(PoisonError::into_inner),
+        };
+    }
+
+    0
+}
+
+/// Hook for `gai_cancel`, the requests of [`getaddrinfo_a_detour`] can't be cancelled once they're
+/// sent to the agent.
+#[cfg(target_os = "linux")]
+#[hook_guard_fn]
+pub(super) unsafe extern "C" fn gai_cancel_detour(request: *mut Gaicb) -> c_int {
+    let is_pending = PENDING_GAICB
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .contains(&(request as usize));
+
+    if is_pending {
+        EAI_NOTCANCELED
+    } else {
+        FN_GAI_CANCEL(request)
+    }
+}
+
 /// Not a faithful reproduction of what [`libc::recvmsg`] is supposed to do, see [`recv_from`].
 #[hook_guard_fn]
 pub(super) unsafe extern "C" fn recv_from_detour(
@@ -355,7 +630,7 @@ pub(super) unsafe extern "C" fn recv_from_detour(
 
 /// Not a faithful reproduction of what [`libc::sendto`] is supposed to do, see [`send_to`].
 #[hook_guard_fn]
-pub(super) unsafe extern "C" fn send_to_detour(
+pub(crate) unsafe extern "C" fn send_to_detour(
     sockfd: RawFd,
     raw_message: *const c_void,
     message_length: size_t,
@@ -436,6 +711,89 @@ pub(super) unsafe extern "C" fn sendmsg_detour(
     }
 }
 
+/// Not a faithful reproduction of what [`libc::recvmmsg`] is supposed to do, see [`recv_from`].
+///
+/// Receives the whole batch with the original function, then fills the address of each message,
+/// like [`recvmsg_detour`] does.
+#[cfg(target_os = "linux")]
+#[hook_guard_fn]
+pub(super) unsafe extern "C" fn recvmmsg_detour(
+    sockfd: c_int,
+    messages: *mut libc::mmsghdr,
+    length: libc::c_uint,
+    flags: c_int,
+    timeout: *mut libc::timespec,
+) -> c_int {
+    let recvmmsg_result = FN_RECVMMSG(sockfd, messages, length, flags, timeout);
+
+    if recvmmsg_result > 0 && super::SOCKETS.contains_key(&sockfd) {
+        for index in 0..recvmmsg_result as usize {
+            let message = &mut *messages.add(index);
+            if message.msg_hdr.msg_name.is_null() {
+                continue;
+            }
+
+            let _ = recv_from(
+                sockfd,
+                message.msg_len as isize,
+                message.msg_hdr.msg_name as *mut _,
+                &mut message.msg_hdr.msg_namelen,
+            );
+        }
+    }
+
+    recvmmsg_result
+}
+
+/// Not a faithful reproduction of what [`libc::sendmmsg`] is supposed to do, see [`sendmsg`].
+///
+/// Messages with a destination are sent one by one, like [`sendmsg_detour`] does, so each of them
+/// goes through the outgoing (or DNS) handling. Returns the number of messages sent, or `-1` when
+/// the first one fails.
+#[cfg(target_os = "linux")]
+#[hook_guard_fn]
+pub(super) unsafe extern "C" fn sendmmsg_detour(
+    sockfd: c_int,
+    messages: *mut libc::mmsghdr,
+    length: libc::c_uint,
+    flags: c_int,
+) -> c_int {
+    // Without destinations, there's nothing for us to do, same as `sendmsg_detour`.
+    if messages.is_null()
+        || !super::SOCKETS.contains_key(&sockfd)
+        || (0..length as usize).all(|index| (*messages.add(index)).msg_hdr.msg_name.is_null())
+    {
+        return FN_SENDMMSG(sockfd, messages, length, flags);
+    }
+
+    let mut sent = 0;
+    for index in 0..length as usize {
+        let message = &mut *messages.add(index);
+        let message_header = &message.msg_hdr as *const libc::msghdr;
+
+        let sendmsg_result = if message.msg_hdr.msg_name.is_null() {
+            FN_SENDMSG(sockfd, message_header, flags)
+        } else {
+            sendmsg(sockfd, message_header, flags)
+                .unwrap_or_bypass_with(|_| FN_SENDMSG(sockfd, message_header, flags))
+        };
+
+        if sendmsg_result == -1 {
+            break;
+        }
+
+        message.msg_len = sendmsg_result as libc::c_uint;
+        sent += 1;
+    }
+
+    // Like `sendmmsg`, an error is reported only when no message was sent.
+    if sent == 0 {
+        -1
+    } else {
+        sent
+    }
+}
+
 #[cfg(target_os = "macos")]
 #[allow(non_camel_case_types)]
 mod macos {
@@ -598,6 +956,21 @@ pub(crate) unsafe fn enable_socket_hooks(hook_manager: &mut HookManager, enabled
         );
 
         replace!(hook_manager, "dup3", dup3_detour, FnDup3, FN_DUP3);
+
+        replace!(
+            hook_manager,
+            "recvmmsg",
+            recvmmsg_detour,
+            FnRecvmmsg,
+            FN_RECVMMSG
+        );
+        replace!(
+            hook_manager,
+            "sendmmsg",
+            sendmmsg_detour,
+            FnSendmmsg,
+            FN_SENDMMSG
+        );
     }
 
     replace!(hook_manager, "accept", accept_detour, FnAccept, FN_ACCEPT);
@@ -633,6 +1006,31 @@ pub(crate) unsafe fn enable_socket_hooks(hook_manager: &mut HookManager, enabled
             FnFreeaddrinfo,
             FN_FREEADDRINFO
         );
+
+        #[cfg(target_os = "linux")]
+        {
+            replace!(
+                hook_manager,
+                "getaddrinfo_a",
+                getaddrinfo_a_detour,
+                FnGetaddrinfo_a,
+                FN_GETADDRINFO_A
+            );
+            replace!(
+                hook_manager,
+                "gai_suspend",
+                gai_suspend_detour,
+                FnGai_suspend,
+                FN_GAI_SUSPEND
+            );
+            replace!(
+                hook_manager,
+                "gai_cancel",
+                gai_cancel_detour,
+                FnGai_cancel,
+                FN_GAI_CANCEL
+            );
+        }
         #[cfg(target_os = "macos")]
         {
             replace!(
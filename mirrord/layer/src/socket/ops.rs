@@ -7,17 +7,17 @@ use std::{
         fd::{BorrowedFd, FromRawFd, IntoRawFd},
         unix::io::RawFd,
     },
-    path::PathBuf,
+    path::{Path, PathBuf},
     ptr,
     sync::{Arc, OnceLock},
 };
 
 use errno::set_errno;
 use libc::{c_int, c_void, hostent, sockaddr, socklen_t, AF_UNIX};
-use mirrord_config::feature::network::incoming::{IncomingConfig, IncomingMode};
+use mirrord_config::feature::network::incoming::{IncomingConfig, IncomingMode, PrivilegedBind};
 use mirrord_intproxy_protocol::{
     ConnMetadataRequest, ConnMetadataResponse, NetProtocol, OutgoingConnectRequest,
-    OutgoingConnectResponse, PortSubscribe,
+    OutgoingConnectResponse, PortSubscribe, UdpPeerRequest, UdpPortSubscribe, UdpPortSubscription,
 };
 use mirrord_protocol::{
     dns::{GetAddrInfoRequest, LookupRecord},
@@ -25,7 +25,7 @@ use mirrord_protocol::{
 };
 use nix::sys::socket::{sockopt, SockaddrLike, SockaddrStorage};
 use socket2::SockAddr;
-use tracing::{error, trace};
+use tracing::{error, info, trace};
 
 use super::{hooks::*, *};
 use crate::{
@@ -34,6 +34,10 @@ use crate::{
     file::{self, OPEN_FILES},
 };
 
+/// Ports below this one usually can't be bound without root, see
+/// [`PrivilegedBind`].
+const UNPRIVILEGED_PORTS_START: u16 = 1024;
+
 /// Holds the pair of [`IpAddr`] with their hostnames, resolved remotely through
 /// [`remote_getaddrinfo`].
 ///
@@ -42,6 +46,11 @@ use crate::{
 pub(super) static REMOTE_DNS_REVERSE_MAPPING: LazyLock<DashMap<IpAddr, String>> =
     LazyLock::new(|| DashMap::with_capacity(8));
 
+/// How many entries we keep in [`REMOTE_DNS_REVERSE_MAPPING`] when
+/// [`ExperimentalConfig::low_memory`](mirrord_config::experimental::ExperimentalConfig::low_memory)
+/// is enabled.
+const LOW_MEMORY_DNS_MAPPING_LIMIT: usize = 64;
+
 /// Hostname initialized from the agent with [`gethostname`].
 pub(crate) static HOSTNAME: OnceLock<CString> = OnceLock::new();
 
@@ -77,7 +86,7 @@ static mut GETHOSTBYNAME_HOSTENT: hostent = hostent {
 /// this `.into/.from` then the detour would call `.into` on the struct to convert to i32
 /// and would set the according errno.
 #[derive(Debug)]
-pub(super) struct ConnectResult {
+pub(crate) struct ConnectResult {
     result: i32,
     error: Option<errno::Errno>,
 }
@@ -86,6 +95,14 @@ impl ConnectResult {
     pub(super) fn is_failure(&self) -> bool {
         matches!(self.error, Some(err) if err.0 != libc::EINTR && err.0 != libc::EINPROGRESS)
     }
+
+    /// The result as the `res` of an io_uring completion, `-errno` on failure.
+    pub(crate) fn completion_result(&self) -> i32 {
+        match self.error {
+            Some(err) => -err.0,
+            None => self.result,
+        }
+    }
 }
 
 impl From<i32> for ConnectResult {
@@ -191,13 +208,14 @@ fn is_ignored_tcp_port(addr: &SocketAddr, config: &IncomingConfig) -> bool {
 
     // Unfiltered ports were specified and the requested port is not one of them, or an HTTP filter
     // is set and no unfiltered ports were specified.
+    // With `ports: "auto"`, the internal proxy subscribes only the ports the target listens on.
     let not_whitelisted = config
         .ports
         .as_ref()
         .map(|ports| !ports.contains(&mapped_port))
-        .unwrap_or(http_filter_used);
+        .unwrap_or(http_filter_used && !config.auto_ports);
 
-    if http_filter_used && not_a_filtered_port && config.ports.is_none() {
+    if http_filter_used && not_a_filtered_port && config.ports.is_none() && !config.auto_ports {
         // User specified a filter that does not include this port, and did not specify any
         // unfiltered ports.
         // It's plausible that the user did not know the port has to be in either port list to be
@@ -223,6 +241,57 @@ fn is_ignored_tcp_port(addr: &SocketAddr, config: &IncomingConfig) -> bool {
     is_ignored_port(addr) || (not_stolen_with_filter && not_whitelisted)
 }
 
+/// Subscribes the UDP socket bound to the `requested_address` to the datagrams of the remote port,
+/// if the port is listed in `feature.network.incoming.ports`. UDP sockets don't [`listen`], so
+/// this happens right after [`bind`].
+///
+/// Returns whether the socket was subscribed.
+#[mirrord_layer_macro::instrument(level = "trace", ret)]
+fn subscribe_udp(requested_address: SocketAddr, address: SocketAddr) -> Detour<bool> {
+    let setup = crate::setup();
+    let incoming_config = setup.incoming_config();
+
+    let mapped_port = incoming_config
+        .port_mapping
+        .get_by_left(&requested_address.port())
+        .copied()
+        .unwrap_or_else(|| requested_address.port());
+    let listed = incoming_config
+        .ports
+        .as_ref()
+        .is_some_and(|ports| ports.contains(&mapped_port));
+
+    if !listed || incoming_config.mode == IncomingMode::Off || setup.targetless() {
+        return Detour::Success(false);
+    }
+
+    let subscription = match *setup.incoming_mode() {
+        crate::setup::IncomingMode::Mirror => UdpPortSubscription::Mirror(mapped_port),
+        crate::setup::IncomingMode::Steal(..) => UdpPortSubscription::Steal(mapped_port),
+    };
+
+    // The socket is bound already, so we can't bypass.
+    if let Detour::Bypass(..) = crate::simulation::bypass("udp port subscription", mapped_port) {
+        return Detour::Success(false);
+    }
+
+    match common::make_proxy_request_with_response(UdpPortSubscribe {
+        listening_on: address,
+        subscription,
+    })? {
+        Ok(()) => Detour::Success(true),
+        Err(ResponseError::NotImplemented) => {
+            warn!(
+                "The agent does not support incoming UDP traffic, port {} receives the local \
+                datagrams only.",
+                requested_address.port()
+            );
+            Detour::Success(false)
+        }
+        Err(error) => Detour::Error(error.into()),
+    }
+}
+
 /// If the socket is not found in [`SOCKETS`], bypass.
 /// Otherwise, if it's not an ignored port, bind (possibly with a fallback to random port) and
 /// update socket state in [`SOCKETS`]. If it's an ignored port, remove the socket from [`SOCKETS`].
@@ -290,13 +359,23 @@ pub(super) fn bind(
     // try to bind the requested port, if not available get a random port
     // if there's configuration and binding fails with the requested port
     // we return address not available and not fallback to a random port.
+    // Privileged ports handled by incoming go straight to a random port, unless configured
+    // otherwise with `privileged_bind`.
     let listen_port = incoming_config
         .listen_ports
         .get_by_left(&requested_address.port())
         .copied();
+    let unprivileged_bind = listen_port.is_none()
+        && matches!(socket.kind, SocketKind::Tcp(_))
+        && incoming_config.mode != IncomingMode::Off
+        && incoming_config.privileged_bind == PrivilegedBind::Unprivileged
+        && (1..UNPRIVILEGED_PORTS_START).contains(&requested_port);
     if let Some(port) = listen_port {
         // Listen port was specified. If we fail to bind, we should fail the whole operation.
         bind_similar_address(sockfd, &SocketAddr::new(requested_address.ip(), port))
+    } else if unprivileged_bind {
+        // The traffic comes from the remote port, so we don't need the privileged port locally.
+        bind_similar_address(sockfd, &SocketAddr::new(requested_address.ip(), 0))
     } else {
         // Listen port was not specified. If we fail to bind, it's ok to fall back to a random port.
         bind_similar_address(sockfd, &requested_address).or_else(|error| {
@@ -322,10 +401,25 @@ pub(super) fn bind(
     .and_then(|(_, address)| address.as_socket())
     .bypass(Bypass::AddressConversion)?;
 
-    Arc::get_mut(&mut socket).unwrap().state = SocketState::Bound(Bound {
+    if unprivileged_bind {
+        info!(
+            "Port {requested_port} is privileged, bound to local port {} instead. Set \
+            `feature.network.incoming.listen_ports` to choose the local port.",
+            address.port()
+        );
+    }
+
+    let udp_subscribed = socket.kind.is_udp() && subscribe_udp(requested_address, address)?;
+
+    let bound = Bound {
         requested_address,
         address,
-    });
+    };
+    Arc::get_mut(&mut socket).unwrap().state = if udp_subscribed {
+        SocketState::Listening(bound)
+    } else {
+        SocketState::Bound(bound)
+    };
 
     SOCKETS.insert(sockfd, socket);
 
@@ -366,6 +460,15 @@ pub(super) fn listen(sockfd: RawFd, backlog: c_int) -> Detour<i32> {
             requested_address,
             address,
         }) => {
+            let mapped_port = setup
+                .incoming_config()
+                .port_mapping
+                .get_by_left(&requested_address.port())
+                .copied()
+                .unwrap_or_else(|| requested_address.port());
+
+            crate::simulation::bypass("port subscription", mapped_port)?;
+
             let listen_result = unsafe { FN_LISTEN(sockfd, backlog) };
             if listen_result != 0 {
                 let error = io::Error::last_os_error();
@@ -375,13 +478,6 @@ pub(super) fn listen(sockfd: RawFd, backlog: c_int) -> Detour<i32> {
                 Err(error)?
             }
 
-            let mapped_port = setup
-                .incoming_config()
-                .port_mapping
-                .get_by_left(&requested_address.port())
-                .copied()
-                .unwrap_or_else(|| requested_address.port());
-
             common::make_proxy_request_with_response(PortSubscribe {
                 listening_on: address,
                 subscription: setup.incoming_mode().subscription(mapped_port),
@@ -467,17 +563,34 @@ fn connect_outgoing<const CALL_CONNECT: bool>(
     };
 
     if remote_address.is_unix() {
+        crate::simulation::bypass(
+            "connect",
+            remote_address
+                .as_pathname()
+                .unwrap_or_else(|| Path::new("<unnamed unix socket>"))
+                .display(),
+        )?;
+
         let connect_result = remote_connection(remote_address)?;
         Detour::Success(connect_result)
     } else {
         // Can't just connect to whatever `remote_address` is, as it might be a remotely resolved
         // address, in a local connection context (or vice-versa), so we let `remote_connection`
         // handle this address trickery.
-        match crate::setup()
+        let address = remote_address.as_socket()?;
+        let through = crate::setup()
             .outgoing_selector()
-            .get_connection_through(remote_address.as_socket()?, protocol)?
-        {
+            .get_connection_through(address, protocol)?;
+        record::record_outgoing(
+            address,
+            protocol,
+            matches!(through, ConnectionThrough::Remote(..)),
+        );
+
+        match through {
             ConnectionThrough::Remote(addr) => {
+                crate::simulation::bypass("connect", addr)?;
+
                 let connect_result = remote_connection(SockAddr::from(addr))?;
                 Detour::Success(connect_result)
             }
@@ -548,7 +661,7 @@ fn connect_to_local_address(
 ///
 /// 3. `sockt.state` is `Bound`: part of the tcp mirror feature.
 #[mirrord_layer_macro::instrument(level = "trace", ret, skip(raw_address))]
-pub(super) fn connect(
+pub(crate) fn connect(
     sockfd: RawFd,
     raw_address: *const sockaddr,
     address_length: socklen_t,
@@ -833,7 +946,22 @@ pub(super) fn dup<const SWITCH_MAP: bool>(fd: c_int, dup_fd: i32) -> Result<(),
 pub(super) fn remote_getaddrinfo(node: String) -> HookResult<Vec<(String, IpAddr)>> {
     let addr_info_list = common::make_proxy_request_with_response(GetAddrInfoRequest { node })?.0?;
 
+    let low_memory = crate::setup().experimental().low_memory;
     addr_info_list.iter().for_each(|lookup| {
+        if low_memory
+            && REMOTE_DNS_REVERSE_MAPPING.len() >= LOW_MEMORY_DNS_MAPPING_LIMIT
+            && !REMOTE_DNS_REVERSE_MAPPING.contains_key(&lookup.ip)
+        {
+            // Evict any entry, the address will be connected to without the local resolution.
+            let evicted = REMOTE_DNS_REVERSE_MAPPING
+                .iter()
+                .next()
+                .map(|entry| *entry.key());
+            if let Some(evicted) = evicted {
+                REMOTE_DNS_REVERSE_MAPPING.remove(&evicted);
+            }
+        }
+
         REMOTE_DNS_REVERSE_MAPPING.insert(lookup.ip, lookup.name.clone());
     });
 
@@ -905,7 +1033,10 @@ pub(super) fn getaddrinfo(
     let resolved_addr = if node == "::" {
         // name is "" because that's what happens in real flow.
         vec![("".to_string(), IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    } else if let Some(address) = crate::setup().fallback_host(&node) {
+        vec![(node.clone(), address)]
     } else {
+        crate::simulation::bypass("dns lookup", &node)?;
         remote_getaddrinfo(node.clone())?
     };
 
@@ -1003,7 +1134,13 @@ pub(super) fn gethostbyname(raw_name: Option<&CStr>) -> Detour<*mut hostent> {
         })?
         .into();
 
-    let hosts_and_ips = remote_getaddrinfo(name.clone())?;
+    let hosts_and_ips = match crate::setup().fallback_host(&name) {
+        Some(address) => vec![(name.clone(), address)],
+        None => {
+            crate::simulation::bypass("dns lookup", &name)?;
+            remote_getaddrinfo(name.clone())?
+        }
+    };
 
     // We could `unwrap` here, as this would have failed on the previous conversion.
     let host_name = CString::new(name)?;
@@ -1097,6 +1234,9 @@ pub(super) fn gethostname() -> Detour<&'static CString> {
 /// When the socket is in a [`Connected`] state, we call [`fill_address`] with its `remote_address`,
 /// instead of letting whatever came in `raw_source` through.
 ///
+/// When the socket is a UDP socket that receives the remote datagrams, the source is an internal
+/// proxy socket, and we replace it with the remote peer (see [`udp_peer`]).
+///
 /// See [`send_to`] for more information.
 #[mirrord_layer_macro::instrument(level = "trace", ret, skip(raw_source, source_length))]
 pub(super) fn recv_from(
@@ -1105,6 +1245,18 @@ pub(super) fn recv_from(
     raw_source: *mut sockaddr,
     source_length: *mut socklen_t,
 ) -> Detour<isize> {
+    let receives_remote_datagrams = SOCKETS.get(&sockfd).is_some_and(|socket| {
+        socket.kind.is_udp() && matches!(socket.state, SocketState::Listening(..))
+    });
+    if receives_remote_datagrams {
+        let source = SocketAddr::try_from_raw(raw_source, unsafe { *source_length })?;
+        let remote_address = udp_peer(source)?;
+        fill_address(raw_source, source_length, remote_address.into())?;
+
+        errno::set_errno(errno::Errno(0));
+        return Detour::Success(recv_from_result);
+    }
+
     SOCKETS
         .get(&sockfd)
         .and_then(|socket| match &socket.state {
@@ -1120,6 +1272,26 @@ pub(super) fn recv_from(
     Detour::Success(recv_from_result)
 }
 
+/// Returns the remote peer on whose behalf the internal proxy sent a datagram from the given
+/// `local_address`, asking the internal proxy when it's not in [`UDP_PEERS`] yet.
+///
+/// Bypasses when the datagram was not sent by the internal proxy.
+fn udp_peer(local_address: SocketAddr) -> Detour<SocketAddr> {
+    if let Some(remote_address) = UDP_PEERS.get(&local_address) {
+        return Detour::Success(*remote_address);
+    }
+
+    let remote_address =
+        common::make_proxy_request_with_response(UdpPeerRequest { local_address })?
+            .remote_address?;
+
+    // The internal proxy replaces the sockets of the peers that were silent for a while.
+    UDP_PEERS.retain(|_, peer| *peer != remote_address);
+    UDP_PEERS.insert(local_address, remote_address);
+
+    Detour::Success(remote_address)
+}
+
 /// Helps manually resolving DNS on port `53` with UDP, see [`send_to`] and [`sendmsg`].
 #[mirrord_layer_macro::instrument(level = "trace", ret)]
 fn send_dns_patch(
@@ -1127,9 +1299,26 @@ fn send_dns_patch(
     user_socket_info: Arc<UserSocket>,
     destination: SocketAddr,
 ) -> Detour<SockAddr> {
+    // Is the `destination` a remote peer of this socket? If so, then we send it to the internal
+    // proxy socket that sent us the peer's datagrams.
+    let receives_remote_datagrams = user_socket_info.kind.is_udp()
+        && matches!(user_socket_info.state, SocketState::Listening(..));
+    let udp_peer = receives_remote_datagrams
+        .then(|| {
+            UDP_PEERS
+                .iter()
+                .find(|peer| *peer.value() == destination)
+                .map(|peer| *peer.key())
+        })
+        .flatten();
+
     // We want to keep holding this socket.
     SOCKETS.insert(sockfd, user_socket_info);
 
+    if let Some(local_address) = udp_peer {
+        return Detour::Success(SockAddr::from(local_address));
+    }
+
     // Sending a packet on port NOT 53.
     let destination = SOCKETS
         .iter()
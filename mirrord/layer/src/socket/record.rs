@@ -0,0 +1,64 @@
+//! Records the outgoing destinations of the application in
+//! [`OutgoingConfig::record`](mirrord_config::feature::network::outgoing::OutgoingConfig::record),
+//! for `mirrord analyze outgoing`.
+//!
+//! Every layer appends to the same file, each destination once per process. The lines are short
+//! and written with a single `write`, so the ones of different processes don't mix.
+
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::Write,
+    net::SocketAddr,
+    sync::{LazyLock, Mutex},
+};
+
+use mirrord_config::feature::network::outgoing::{OutgoingRecord, RecordedProtocol};
+use mirrord_intproxy_protocol::NetProtocol;
+use tracing::warn;
+
+use super::ops::REMOTE_DNS_REVERSE_MAPPING;
+
+/// Destinations already in the file.
+static RECORDED: LazyLock<Mutex<HashSet<OutgoingRecord>>> = LazyLock::new(Default::default);
+
+/// Appends the destination to the record file, if there is one and it's not there yet.
+pub(super) fn record_outgoing(address: SocketAddr, protocol: NetProtocol, remote: bool) {
+    let Some(path) = crate::setup().outgoing_config().record.as_deref() else {
+        return;
+    };
+
+    let record = OutgoingRecord {
+        protocol: match protocol {
+            NetProtocol::Stream => RecordedProtocol::Tcp,
+            NetProtocol::Datagrams => RecordedProtocol::Udp,
+        },
+        address,
+        hostname: REMOTE_DNS_REVERSE_MAPPING
+            .get(&address.ip())
+            .map(|hostname| hostname.value().clone()),
+        remote,
+    };
+
+    let mut recorded = RECORDED.lock().unwrap_or_else(|error| error.into_inner());
+    if recorded.contains(&record) {
+        return;
+    }
+
+    let Ok(mut line) = serde_json::to_vec(&record) else {
+        return;
+    };
+    line.push(b'\n');
+
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line));
+    match written {
+        Ok(()) => {
+            recorded.insert(record);
+        }
+        Err(error) => warn!(%error, path, "Failed to record an outgoing destination"),
+    }
+}
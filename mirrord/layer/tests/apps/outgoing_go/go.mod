@@ -0,0 +1,3 @@
+module outgoing_go
+
+go 1.20
@@ -0,0 +1,45 @@
+package main
+
+import (
+	"bytes"
+	"fmt"
+	"io"
+	"net"
+	"os"
+	"strings"
+)
+
+const MESSAGE = "FOO BAR HAM"
+
+// Usage: `--tcp <local address> <comma separated peers>`, same as the Rust outgoing test app.
+// The local address is not checked here.
+func main() {
+	if len(os.Args) != 4 || os.Args[1] != "--tcp" {
+		panic(fmt.Sprintf("invalid arguments: %v", os.Args))
+	}
+
+	for _, peer := range strings.Split(os.Args[3], ",") {
+		conn, err := net.Dial("tcp", peer)
+		if err != nil {
+			panic(err)
+		}
+
+		if remote := conn.RemoteAddr().String(); remote != peer {
+			panic(fmt.Sprintf("invalid peer address from RemoteAddr: %s", remote))
+		}
+
+		if _, err := conn.Write([]byte(MESSAGE)); err != nil {
+			panic(err)
+		}
+
+		response, err := io.ReadAll(conn)
+		if err != nil {
+			panic(err)
+		}
+		if !bytes.Equal(response, []byte(MESSAGE)) {
+			panic(fmt.Sprintf("invalid response received: %q", response))
+		}
+
+		conn.Close()
+	}
+}
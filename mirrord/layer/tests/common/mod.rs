@@ -24,9 +24,11 @@ use tokio::{
     process::Command,
 };
 
-/// Configuration for [`Application::RustOutgoingTcp`] and [`Application::RustOutgoingUdp`].
+/// Configuration for [`Application::RustOutgoingTcp`], [`Application::RustOutgoingUdp`] and the Go
+/// outgoing apps.
 pub const RUST_OUTGOING_PEERS: &str = "1.1.1.1:1111,2.2.2.2:2222,3.3.3.3:3333";
-/// Configuration for [`Application::RustOutgoingTcp`] and [`Application::RustOutgoingUdp`].
+/// Configuration for [`Application::RustOutgoingTcp`], [`Application::RustOutgoingUdp`] and the Go
+/// outgoing apps.
 pub const RUST_OUTGOING_LOCAL: &str = "4.4.4.4:4444";
 
 pub struct TestIntProxy {
@@ -43,7 +45,15 @@ impl TestIntProxy {
             let agent_conn = AgentConnection::new_for_raw_address(fake_agent_address)
                 .await
                 .unwrap();
-            let intproxy = IntProxy::new_with_connection(agent_conn, listener);
+            let intproxy = IntProxy::new_with_connection(
+                agent_conn,
+                listener,
+                None,
+                Default::default(),
+                None,
+                Default::default(),
+                Default::default(),
+            );
             intproxy
                 .run(Duration::from_secs(5), Duration::from_secs(5))
                 .await
@@ -67,6 +77,13 @@ impl TestIntProxy {
         res.send(DaemonMessage::SwitchProtocolVersionResponse(version))
             .await;
 
+        let msg = res.recv().await;
+        let ClientMessage::Capabilities(capabilities) = msg else {
+            panic!("unexpected message: {msg:?}");
+        };
+
+        res.send(DaemonMessage::Capabilities(capabilities)).await;
+
         let msg = res.recv().await;
         let ClientMessage::ReadyForLogs = msg else {
             panic!("unexpected message: {msg:?}");
@@ -662,6 +679,8 @@ pub enum Application {
     Go19SelfOpen,
     RustOutgoingUdp,
     RustOutgoingTcp,
+    Go20OutgoingTcp,
+    Go21OutgoingTcp,
     RustIssue1123,
     RustIssue1054,
     RustIssue1458,
@@ -755,6 +774,8 @@ impl Application {
             Application::Go20LSeek => String::from("tests/apps/lseek_go/20.go_test_app"),
             Application::Go21FAccessAt => String::from("tests/apps/faccessat_go/21.go_test_app"),
             Application::Go19FAccessAt => String::from("tests/apps/faccessat_go/19.go_test_app"),
+            Application::Go20OutgoingTcp => String::from("tests/apps/outgoing_go/20.go_test_app"),
+            Application::Go21OutgoingTcp => String::from("tests/apps/outgoing_go/21.go_test_app"),
             Application::Go20FAccessAt => String::from("tests/apps/faccessat_go/20.go_test_app"),
             Application::Go19SelfOpen => String::from("tests/apps/self_open/19.go_test_app"),
             Application::RustIssue1123 => String::from("tests/apps/issue1123/target/issue1123"),
@@ -943,7 +964,9 @@ impl Application {
                 .into_iter()
                 .map(Into::into)
                 .collect(),
-            Application::RustOutgoingTcp => ["--tcp", RUST_OUTGOING_LOCAL, RUST_OUTGOING_PEERS]
+            Application::RustOutgoingTcp
+            | Application::Go20OutgoingTcp
+            | Application::Go21OutgoingTcp => ["--tcp", RUST_OUTGOING_LOCAL, RUST_OUTGOING_PEERS]
                 .into_iter()
                 .map(Into::into)
                 .collect(),
@@ -997,6 +1020,8 @@ impl Application {
             | Application::Go20Dir
             | Application::RustOutgoingUdp
             | Application::RustOutgoingTcp
+            | Application::Go20OutgoingTcp
+            | Application::Go21OutgoingTcp
             | Application::RustIssue1458
             | Application::RustIssue1458PortNot53
             | Application::RustIssue1776
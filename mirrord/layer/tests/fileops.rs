@@ -1,11 +1,9 @@
 #![feature(assert_matches)]
 #![warn(clippy::indexing_slicing)]
 
-#[cfg(target_os = "linux")]
-use std::assert_matches::assert_matches;
+use std::{assert_matches::assert_matches, env::temp_dir, path::PathBuf, time::Duration};
 #[cfg(target_os = "macos")]
 use std::{env, fs};
-use std::{env::temp_dir, path::PathBuf, time::Duration};
 
 use libc::{pid_t, O_RDWR};
 use mirrord_protocol::{file::*, *};
@@ -380,50 +378,52 @@ async fn go_dir(
         ))))
         .await;
 
-    assert_eq!(
-        intproxy.recv().await,
-        ClientMessage::FileRequest(FileRequest::ReadDir(ReadDirRequest { remote_fd: 2 }))
-    );
-
-    intproxy
-        .send(DaemonMessage::File(FileResponse::ReadDir(Ok(
-            ReadDirResponse {
-                direntry: Some(DirEntryInternal {
-                    name: "a".to_string(),
-                    inode: 1,
-                    position: 1,
-                    file_type: libc::DT_REG,
-                }),
-            },
-        ))))
-        .await;
-
-    assert_eq!(
+    assert_matches!(
         intproxy.recv().await,
-        ClientMessage::FileRequest(FileRequest::ReadDir(ReadDirRequest { remote_fd: 2 }))
+        ClientMessage::FileRequest(FileRequest::ReadDirBatch(ReadDirBatchRequest {
+            remote_fd: 2,
+            ..
+        }))
     );
 
     intproxy
-        .send(DaemonMessage::File(FileResponse::ReadDir(Ok(
-            ReadDirResponse {
-                direntry: Some(DirEntryInternal {
-                    name: "b".to_string(),
-                    inode: 2,
-                    position: 2,
-                    file_type: libc::DT_REG,
-                }),
+        .send(DaemonMessage::File(FileResponse::ReadDirBatch(Ok(
+            ReadDirBatchResponse {
+                entries: vec![
+                    (
+                        DirEntryInternal {
+                            name: "a".to_string(),
+                            inode: 1,
+                            position: 1,
+                            file_type: libc::DT_REG,
+                        },
+                        Default::default(),
+                    ),
+                    (
+                        DirEntryInternal {
+                            name: "b".to_string(),
+                            inode: 2,
+                            position: 2,
+                            file_type: libc::DT_REG,
+                        },
+                        Default::default(),
+                    ),
+                ],
             },
         ))))
         .await;
 
-    assert_eq!(
+    assert_matches!(
         intproxy.recv().await,
-        ClientMessage::FileRequest(FileRequest::ReadDir(ReadDirRequest { remote_fd: 2 }))
+        ClientMessage::FileRequest(FileRequest::ReadDirBatch(ReadDirBatchRequest {
+            remote_fd: 2,
+            ..
+        }))
     );
 
     intproxy
-        .send(DaemonMessage::File(FileResponse::ReadDir(Ok(
-            ReadDirResponse { direntry: None },
+        .send(DaemonMessage::File(FileResponse::ReadDirBatch(Ok(
+            ReadDirBatchResponse { entries: vec![] },
         ))))
         .await;
 
@@ -1,7 +1,7 @@
 #![feature(assert_matches)]
 #![warn(clippy::indexing_slicing)]
 
-use std::{path::PathBuf, time::Duration};
+use std::{assert_matches::assert_matches, path::PathBuf, time::Duration};
 
 use mirrord_protocol::{file::*, *};
 use rstest::rstest;
@@ -54,38 +54,38 @@ async fn test_issue2001(
         ))))
         .await;
 
-    assert_eq!(
-        intproxy.recv().await,
-        ClientMessage::FileRequest(FileRequest::ReadDir(ReadDirRequest { remote_fd: 11 })),
-    );
-
-    intproxy
-        .send(DaemonMessage::File(FileResponse::ReadDir(Ok(
-            ReadDirResponse {
-                direntry: Some(DirEntryInternal {
-                    inode: 1,
-                    position: 0,
-                    name: "file1".into(),
-                    file_type: libc::DT_REG,
-                }),
-            },
-        ))))
-        .await;
-
-    assert_eq!(
+    // Both entries come in one batch.
+    assert_matches!(
         intproxy.recv().await,
-        ClientMessage::FileRequest(FileRequest::ReadDir(ReadDirRequest { remote_fd: 11 })),
+        ClientMessage::FileRequest(FileRequest::ReadDirBatch(ReadDirBatchRequest {
+            remote_fd: 11,
+            ..
+        })),
     );
 
     intproxy
-        .send(DaemonMessage::File(FileResponse::ReadDir(Ok(
-            ReadDirResponse {
-                direntry: Some(DirEntryInternal {
-                    inode: 2,
-                    position: 1,
-                    name: "file2".into(),
-                    file_type: libc::DT_REG,
-                }),
+        .send(DaemonMessage::File(FileResponse::ReadDirBatch(Ok(
+            ReadDirBatchResponse {
+                entries: vec![
+                    (
+                        DirEntryInternal {
+                            inode: 1,
+                            position: 0,
+                            name: "file1".into(),
+                            file_type: libc::DT_REG,
+                        },
+                        Default::default(),
+                    ),
+                    (
+                        DirEntryInternal {
+                            inode: 2,
+                            position: 1,
+                            name: "file2".into(),
+                            file_type: libc::DT_REG,
+                        },
+                        Default::default(),
+                    ),
+                ],
             },
         ))))
         .await;
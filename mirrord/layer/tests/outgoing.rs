@@ -88,7 +88,12 @@ async fn outgoing_udp(dylib_path: &PathBuf) {
 /// 2. Connects to the remote peer
 /// 3. Sends some data
 /// 4. Expects the peer to send the same data back
-async fn outgoing_tcp_logic(with_config: Option<&str>, dylib_path: &PathBuf, config_dir: &PathBuf) {
+async fn outgoing_tcp_logic(
+    application: Application,
+    with_config: Option<&str>,
+    dylib_path: &PathBuf,
+    config_dir: &PathBuf,
+) {
     let config = with_config.map(|config| {
         let mut config_path = config_dir.clone();
         config_path.push(config);
@@ -96,7 +101,7 @@ async fn outgoing_tcp_logic(with_config: Option<&str>, dylib_path: &PathBuf, con
     });
     let config = config.as_ref().map(|path_buf| path_buf.to_str().unwrap());
 
-    let (mut test_process, mut intproxy) = Application::RustOutgoingTcp
+    let (mut test_process, mut intproxy) = application
         .start_process_with_layer(dylib_path, vec![], config)
         .await;
 
@@ -157,7 +162,26 @@ async fn outgoing_tcp(
     dylib_path: &PathBuf,
     config_dir: &PathBuf,
 ) {
-    outgoing_tcp_logic(with_config, dylib_path, config_dir).await;
+    outgoing_tcp_logic(
+        Application::RustOutgoingTcp,
+        with_config,
+        dylib_path,
+        config_dir,
+    )
+    .await;
+}
+
+/// Same as [`outgoing_tcp`], but with Go apps, which make the calls through the Go runtime instead
+/// of libc.
+#[rstest]
+#[tokio::test]
+#[timeout(Duration::from_secs(10))]
+async fn outgoing_tcp_go(
+    #[values(Application::Go20OutgoingTcp, Application::Go21OutgoingTcp)] application: Application,
+    dylib_path: &PathBuf,
+    config_dir: &PathBuf,
+) {
+    outgoing_tcp_logic(application, None, dylib_path, config_dir).await;
 }
 
 /// 1. Tries to go through the [`outgoing_tcp_logic`] flow, except that outgoing traffic is
@@ -180,7 +204,13 @@ async fn outgoing_tcp_from_the_local_app_broken(
     dylib_path: &PathBuf,
     config_dir: &PathBuf,
 ) {
-    outgoing_tcp_logic(with_config, dylib_path, config_dir).await;
+    outgoing_tcp_logic(
+        Application::RustOutgoingTcp,
+        with_config,
+        dylib_path,
+        config_dir,
+    )
+    .await;
 }
 
 /// Tests that outgoing connections are properly handled on sockets that were bound by the user
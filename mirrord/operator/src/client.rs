@@ -37,8 +37,8 @@ use tokio_tungstenite::tungstenite::{Error as TungsteniteError, Message};
 use tracing::{debug, error, info, warn};
 
 use crate::crd::{
-    CopyTargetCrd, CopyTargetSpec, MirrordOperatorCrd, OperatorFeatures, SessionCrd, TargetCrd,
-    OPERATOR_STATUS_NAME,
+    CopyTargetCrd, CopyTargetSpec, MirrordOperatorCrd, OperatorFeatures, SessionCrd,
+    SessionShareCrd, TargetCrd, OPERATOR_STATUS_NAME,
 };
 
 static CONNECTION_CHANNEL_SIZE: usize = 1000;
@@ -54,7 +54,9 @@ pub enum OperatorOperation {
     CopyingTarget,
     GettingStatus,
     SessionManagement,
+    SharingSession,
     ListingTargets,
+    ManagingPolicies,
 }
 
 impl Display for OperatorOperation {
@@ -66,7 +68,9 @@ impl Display for OperatorOperation {
             Self::CopyingTarget => "copying target",
             Self::GettingStatus => "getting status",
             Self::SessionManagement => "session management",
+            Self::SharingSession => "sharing session",
             Self::ListingTargets => "listing targets",
+            Self::ManagingPolicies => "managing policies",
         };
 
         f.write_str(as_str)
@@ -174,6 +178,13 @@ pub struct OperatorSessionInformation {
     metadata: OperatorSessionMetadata,
 }
 
+impl OperatorSessionInformation {
+    /// Id of the session, shown in hex to the user (e.g. for `mirrord operator session share`).
+    pub fn session_id(&self) -> u64 {
+        self.metadata.session_id
+    }
+}
+
 pub struct OperatorApi {
     client: Client,
     target_api: Api<TargetCrd>,
@@ -181,6 +192,11 @@ pub struct OperatorApi {
     target_namespace: Option<String>,
     target_config: TargetConfig,
     on_concurrent_steal: ConcurrentSteal,
+    /// `routing_header: routing_value` of the session, from
+    /// `feature.network.incoming.http_filter`.
+    session_routing: Option<String>,
+    /// Id (hex) of the shared session we attach to, from `session.join`.
+    join_session: Option<String>,
 }
 
 /// Connection to existing operator session.
@@ -202,6 +218,15 @@ pub async fn session_api(config: Option<String>) -> Result<Api<SessionCrd>> {
     Ok(Api::all(kube_api))
 }
 
+/// Allows us to access the operator's [`SessionShareCrd`] [`Api`].
+pub async fn session_share_api(config: Option<String>) -> Result<Api<SessionShareCrd>> {
+    let kube_api: Client = create_kube_api(false, config, None)
+        .await
+        .map_err(OperatorApiError::CreateApiError)?;
+
+    Ok(Api::all(kube_api))
+}
+
 impl OperatorApi {
     /// We allow copied pods to live only for 30 seconds before the internal proxy connects.
     const COPIED_POD_IDLE_TTL: u32 = 30;
@@ -216,6 +241,18 @@ impl OperatorApi {
             });
         }
 
+        let session_sharing = operator
+            .spec
+            .features
+            .as_ref()
+            .is_some_and(|features| features.contains(&OperatorFeatures::SessionSharing));
+        if config.session.join.is_some() && !session_sharing {
+            return Err(OperatorApiError::UnsupportedFeature {
+                feature: "session sharing".into(),
+                operator_version: operator.spec.operator_version.clone(),
+            });
+        }
+
         Ok(())
     }
 
@@ -378,6 +415,13 @@ impl OperatorApi {
     pub async fn new(config: &LayerConfig) -> Result<Self> {
         let target_config = config.target.clone();
         let on_concurrent_steal = config.feature.network.incoming.on_concurrent_steal;
+        let http_filter = &config.feature.network.incoming.http_filter;
+        let session_routing = http_filter
+            .routing_header
+            .as_ref()
+            .zip(http_filter.routing_value.as_ref())
+            .map(|(header, value)| format!("{header}: {value}"));
+        let join_session = config.session.join.clone();
 
         let client = create_kube_api(
             config.accept_invalid_certificates,
@@ -406,6 +450,8 @@ impl OperatorApi {
             target_namespace,
             target_config,
             on_concurrent_steal,
+            session_routing,
+            join_session,
         })
     }
 
@@ -444,6 +490,13 @@ impl OperatorApi {
     /// This can be used to create a websocket connection with the operator.
     #[tracing::instrument(level = "debug", skip(self), ret)]
     fn connect_url(&self, session: &OperatorSessionInformation) -> String {
+        // The id is validated as hex in `LayerConfig::verify`.
+        let join_session = self
+            .join_session
+            .as_deref()
+            .map(|id| format!("&join_session={id}"))
+            .unwrap_or_default();
+
         match (session.metadata.proxy_feature_enabled(), &session.target) {
             (true, OperatorSessionTarget::Raw(target)) => {
                 let dt = &();
@@ -452,14 +505,14 @@ impl OperatorApi {
                 let plural = TargetCrd::plural(dt);
 
                 format!(
-                    "/apis/{api_version}/proxy/namespaces/{namespace}/{plural}/{}?on_concurrent_steal={}&connect=true",
+                    "/apis/{api_version}/proxy/namespaces/{namespace}/{plural}/{}?on_concurrent_steal={}&connect=true{join_session}",
                     target.name(),
                     self.on_concurrent_steal,
                 )
             }
             (false, OperatorSessionTarget::Raw(target)) => {
                 format!(
-                    "{}/{}?on_concurrent_steal={}&connect=true",
+                    "{}/{}?on_concurrent_steal={}&connect=true{join_session}",
                     self.target_api.resource_url(),
                     target.name(),
                     self.on_concurrent_steal,
@@ -522,6 +575,11 @@ impl OperatorApi {
                 );
             };
 
+            // Labels the session with the header that routes the requests to it.
+            if let Some(session_routing) = self.session_routing.as_deref() {
+                builder = builder.header("x-session-routing", session_routing);
+            }
+
             match session_info.metadata.client_credentials() {
                 Ok(Some(credentials)) => {
                     builder = builder.header("x-client-der", credentials);
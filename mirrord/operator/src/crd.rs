@@ -7,9 +7,11 @@ use self::label_selector::LabelSelector;
 use crate::types::LicenseInfoOwned;
 
 pub mod label_selector;
+pub mod policy;
 
 pub const TARGETLESS_TARGET_NAME: &str = "targetless";
 
+/// A target the operator can run sessions on, named as [`TargetCrd::target_name`] describes.
 #[derive(CustomResource, Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[kube(
     group = "operator.metalbear.co",
@@ -68,6 +70,7 @@ impl From<TargetCrd> for TargetConfig {
         TargetConfig {
             path: crd.spec.target,
             namespace: crd.metadata.namespace,
+            wait_for_run: None,
         }
     }
 }
@@ -78,8 +81,11 @@ pub struct TargetPortLock {
     pub port: u16,
 }
 
+/// Name of the single [`MirrordOperatorCrd`] resource.
 pub static OPERATOR_STATUS_NAME: &str = "operator";
 
+/// The operator itself, with its status. There's only one, named [`OPERATOR_STATUS_NAME`].
+
 #[derive(CustomResource, Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[kube(
     group = "operator.metalbear.co",
@@ -90,15 +96,20 @@ pub static OPERATOR_STATUS_NAME: &str = "operator";
 )]
 pub struct MirrordOperatorSpec {
     pub operator_version: String,
+    /// Namespace of the sessions that don't give one.
     pub default_namespace: String,
+    /// Features that older operators don't have, [`None`] when it's older than all of them.
     pub features: Option<Vec<OperatorFeatures>>,
     pub license: LicenseInfoOwned,
+    /// Version of `mirrord-protocol` that the operator talks.
     pub protocol_version: Option<String>,
+    /// Whether [`CopyTargetCrd`]s can be created.
     pub copy_target_enabled: Option<bool>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
 pub struct MirrordOperatorStatus {
+    /// The sessions that the operator currently runs.
     pub sessions: Vec<Session>,
     pub statistics: Option<MirrordOperatorStatusStatistics>,
 
@@ -109,17 +120,26 @@ pub struct MirrordOperatorStatus {
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
 pub struct MirrordOperatorStatusStatistics {
+    /// Daily active users.
     pub dau: usize,
+    /// Monthly active users.
     pub mau: usize,
 }
 
+/// A session that the operator runs, see [`MirrordOperatorStatus::sessions`].
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct Session {
+    /// Id of the session, in hex.
     pub id: Option<String>,
+    /// How long the session has been running.
     pub duration_secs: u64,
+    /// The user that started the session.
     pub user: String,
+    /// Name of the target, see [`TargetCrd::target_name`].
     pub target: String,
+    /// Namespace of the target.
     pub namespace: Option<String>,
+    /// Ports that the session steals: the port, how it's stolen, and the HTTP filter.
     pub locked_ports: Option<Vec<(u16, String, Option<String>)>>,
 }
 
@@ -142,9 +162,30 @@ pub struct Session {
 )]
 pub struct SessionSpec;
 
+/// Invites other users into an operator session, created with `mirrord operator session share`.
+///
+/// The operator accepts it only from the owner of the session. The invited users can then attach
+/// to the session with `session.join`, and get a copy of the traffic that it steals.
+#[derive(CustomResource, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[kube(
+    group = "operator.metalbear.co",
+    version = "v1",
+    kind = "SessionShare",
+    root = "SessionShareCrd"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionShareSpec {
+    /// Id of the shared session, in hex.
+    pub session_id: String,
+    /// Users that can join the session, e.g. `user@corp`.
+    pub with: Vec<String>,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, JsonSchema)]
 pub enum OperatorFeatures {
     ProxyApi,
+    /// Supports [`SessionShareCrd`] and joining the shared sessions.
+    SessionSharing,
 }
 
 /// This [`Resource`](kube::Resource) represents a copy pod created from an existing [`Target`]
@@ -0,0 +1,192 @@
+//! Evaluation of [`MirrordPolicy`]s: whether a policy applies to the target of a session, and
+//! which of the features it blocks the session would use.
+
+use std::collections::BTreeMap;
+
+use mirrord_config::{
+    feature::network::incoming::IncomingConfig,
+    target::{Target, TargetConfig},
+    LayerConfig,
+};
+
+use super::{BlockedFeature, MirrordPolicy, MirrordPolicySpec, TARGETLESS_TARGET_NAME};
+
+impl BlockedFeature {
+    /// Does a session with the given incoming config use this feature.
+    pub fn used_by(self, incoming: &IncomingConfig) -> bool {
+        match self {
+            Self::Steal => incoming.is_steal(),
+            Self::StealWithoutFilter => {
+                incoming.is_steal() && !incoming.http_filter.is_filter_set()
+            }
+        }
+    }
+}
+
+impl MirrordPolicySpec {
+    /// The target in the notation of [`MirrordPolicySpec::target_path`], e.g. `deploy/my-deploy`
+    /// or `pod/my-pod/container/my-container`.
+    pub fn path_of(target: &TargetConfig) -> String {
+        let (type_name, name, container) = match &target.path {
+            Some(Target::Deployment(target)) => ("deploy", &target.deployment, &target.container),
+            Some(Target::Pod(target)) => ("pod", &target.pod, &target.container),
+            Some(Target::Rollout(target)) => ("rollout", &target.rollout, &target.container),
+            Some(Target::Job(target)) => ("job", &target.job, &target.container),
+            Some(Target::CronJob(target)) => ("cronjob", &target.cron_job, &target.container),
+            Some(Target::StatefulSet(target)) => {
+                ("statefulset", &target.stateful_set, &target.container)
+            }
+            Some(Target::Targetless) | None => return TARGETLESS_TARGET_NAME.to_string(),
+        };
+
+        match container {
+            Some(container) => format!("{type_name}/{name}/container/{container}"),
+            None => format!("{type_name}/{name}"),
+        }
+    }
+
+    /// Does this policy apply to the `target` with the given `labels`.
+    ///
+    /// A `target_path` without a container matches the target in any of its containers.
+    pub fn applies_to(&self, target: &TargetConfig, labels: &BTreeMap<String, String>) -> bool {
+        let path_matches = self.target_path.as_deref().map_or(true, |pattern| {
+            let path = Self::path_of(target);
+            let without_container = path.split("/container/").next().unwrap_or(&path);
+
+            glob_matches(pattern, &path) || glob_matches(pattern, without_container)
+        });
+
+        let selector_matches = self
+            .selector
+            .as_ref()
+            .map_or(true, |selector| selector.matches(labels));
+
+        path_matches && selector_matches
+    }
+
+    /// The features blocked by this policy that a session with the given config would use,
+    /// empty when the policy doesn't apply to its target.
+    pub fn blocked_features(
+        &self,
+        config: &LayerConfig,
+        labels: &BTreeMap<String, String>,
+    ) -> Vec<BlockedFeature> {
+        if !self.applies_to(&config.target, labels) {
+            return Vec::new();
+        }
+
+        self.block
+            .iter()
+            .copied()
+            .filter(|feature| feature.used_by(&config.feature.network.incoming))
+            .collect()
+    }
+}
+
+impl MirrordPolicy {
+    /// Like [`MirrordPolicySpec::applies_to`], but policies also only apply to the targets in
+    /// their own namespace (when both namespaces are known).
+    pub fn applies_to(&self, target: &TargetConfig, labels: &BTreeMap<String, String>) -> bool {
+        let namespace_matches = match (&self.metadata.namespace, &target.namespace) {
+            (Some(policy), Some(target)) => policy == target,
+            _ => true,
+        };
+
+        namespace_matches && self.spec.applies_to(target, labels)
+    }
+
+    /// Like [`MirrordPolicySpec::blocked_features`], taking the namespace into account.
+    pub fn blocked_features(
+        &self,
+        config: &LayerConfig,
+        labels: &BTreeMap<String, String>,
+    ) -> Vec<BlockedFeature> {
+        if !self.applies_to(&config.target, labels) {
+            return Vec::new();
+        }
+
+        self.spec.blocked_features(config, labels)
+    }
+}
+
+/// Matches `text` with a pattern where `?` is exactly one character and `*` is any number of
+/// characters.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut pattern_position, mut text_position) = (0, 0);
+    // The last `*` in the pattern and the position in the text where what it matches ends, to
+    // backtrack to when the rest of the pattern doesn't match.
+    let mut last_star = None;
+
+    while text_position < text.len() {
+        match (pattern.get(pattern_position), text.get(text_position)) {
+            (Some('*'), _) => {
+                last_star = Some((pattern_position, text_position));
+                pattern_position += 1;
+            }
+            (Some(expected), Some(char)) if *expected == '?' || expected == char => {
+                pattern_position += 1;
+                text_position += 1;
+            }
+            _ => match last_star {
+                Some((star_position, star_end)) => {
+                    pattern_position = star_position + 1;
+                    text_position = star_end + 1;
+                    last_star = Some((star_position, star_end + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern
+        .get(pattern_position..)
+        .is_some_and(|rest| rest.iter().all(|char| *char == '*'))
+}
+
+#[cfg(test)]
+mod tests {
+    use mirrord_config::target::deployment::DeploymentTarget;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("deploy/app", "deploy/app", true)]
+    #[case("deploy/app", "deploy/app-2", false)]
+    #[case("deploy/app-?", "deploy/app-2", true)]
+    #[case("deploy/app-?", "deploy/app-", false)]
+    #[case("deploy/*", "deploy/app", true)]
+    #[case("*/app*", "pod/app-7c9f", true)]
+    #[case("*-prod", "deploy/app-staging", false)]
+    #[case("*", "", true)]
+    fn glob(#[case] pattern: &str, #[case] text: &str, #[case] matches: bool) {
+        assert_eq!(glob_matches(pattern, text), matches);
+    }
+
+    #[rstest]
+    #[case(None, true)]
+    #[case(Some("deploy/app"), true)]
+    #[case(Some("deploy/app/container/main"), true)]
+    #[case(Some("deploy/app/container/sidecar"), false)]
+    #[case(Some("pod/*"), false)]
+    fn applies_to_target_path(#[case] target_path: Option<&str>, #[case] applies: bool) {
+        let policy = MirrordPolicySpec {
+            target_path: target_path.map(ToString::to_string),
+            selector: None,
+            block: vec![BlockedFeature::Steal],
+        };
+        let target = TargetConfig {
+            path: Some(Target::Deployment(DeploymentTarget {
+                deployment: "app".to_string(),
+                container: Some("main".to_string()),
+            })),
+            namespace: None,
+            wait_for_run: None,
+        };
+
+        assert_eq!(policy.applies_to(&target, &BTreeMap::new()), applies);
+    }
+}
@@ -1,3 +1,10 @@
+//! Types and clients of the mirrord operator API.
+//!
+//! - [`crd`] (feature `crd`): the resources of the operator, e.g. its status with the running
+//!   sessions, the targets and the policies;
+//! - [`client`] (feature `client`): starting and connecting to operator sessions, as the CLI does;
+//! - [`sdk`] (feature `client`): inspecting and managing the operator from other programs.
+
 #![feature(let_chains)]
 #![feature(lazy_cell)]
 #![warn(clippy::indexing_slicing)]
@@ -8,6 +15,10 @@ pub mod client;
 #[cfg(feature = "crd")]
 pub mod crd;
 
+/// High-level client for the operator API, see [`sdk::OperatorClient`].
+#[cfg(feature = "client")]
+pub mod sdk;
+
 /// Operator Setup functionality
 #[cfg(feature = "setup")]
 pub mod setup;
@@ -0,0 +1,240 @@
+//! A small client for the mirrord operator API, for programs that manage mirrord sessions without
+//! the CLI (e.g. a platform controller).
+//!
+//! ```no_run
+//! # async fn sessions() -> Result<(), mirrord_operator::client::OperatorApiError> {
+//! use mirrord_operator::sdk::OperatorClient;
+//!
+//! let client = OperatorClient::try_default().await?;
+//! for session in client.sessions().await? {
+//!     println!(
+//!         "{} runs on {} for {}s",
+//!         session.user, session.target, session.duration_secs
+//!     );
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Sessions are started from a [`LayerConfig`] with
+//! [`OperatorApi::create_session`](crate::client::OperatorApi::create_session), like the CLI
+//! does. The returned [`OperatorSessionConnection`](crate::client::OperatorSessionConnection)
+//! talks `mirrord-protocol` with the agent of the session.
+//!
+//! The resources themselves are in [`crate::crd`].
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{
+    api::{DeleteParams, ListParams, PostParams},
+    core::Status,
+    Api, Client,
+};
+use mirrord_config::LayerConfig;
+use mirrord_kube::api::kubernetes::{create_kube_api, get_k8s_resource_api};
+
+use crate::{
+    client::{OperatorApiError, OperatorOperation},
+    crd::{
+        MirrordOperatorCrd, MirrordPolicy, MirrordPolicySpec, Session, SessionCrd, SessionShareCrd,
+        SessionShareSpec, TargetCrd, OPERATOR_STATUS_NAME,
+    },
+};
+
+type Result<T, E = OperatorApiError> = std::result::Result<T, E>;
+
+/// Inspects and manages the sessions, targets and policies of the mirrord operator.
+///
+/// Cheap to clone, it only holds a [`kube::Client`].
+#[derive(Clone)]
+pub struct OperatorClient {
+    client: Client,
+}
+
+impl OperatorClient {
+    /// Uses the given [`kube::Client`], which needs access to the operator resources.
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Uses the default kubeconfig and context, like `kubectl`.
+    pub async fn try_default() -> Result<Self> {
+        let client = create_kube_api(false, None, None)
+            .await
+            .map_err(OperatorApiError::CreateApiError)?;
+
+        Ok(Self::new(client))
+    }
+
+    /// Uses the cluster of the given config (`kubeconfig`, `kube_context` and
+    /// `accept_invalid_certificates`).
+    pub async fn from_config(config: &LayerConfig) -> Result<Self> {
+        let client = create_kube_api(
+            config.accept_invalid_certificates,
+            config.kubeconfig.clone(),
+            config.kube_context.clone(),
+        )
+        .await
+        .map_err(OperatorApiError::CreateApiError)?;
+
+        Ok(Self::new(client))
+    }
+
+    /// The underlying [`kube::Client`], for what this client doesn't cover.
+    pub fn kube_client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The operator resource, with its version, license and status.
+    pub async fn operator(&self) -> Result<MirrordOperatorCrd> {
+        Api::<MirrordOperatorCrd>::all(self.client.clone())
+            .get(OPERATOR_STATUS_NAME)
+            .await
+            .map_err(|error| OperatorApiError::KubeError {
+                error,
+                operation: OperatorOperation::GettingStatus,
+            })
+    }
+
+    /// The sessions that the operator currently runs.
+    pub async fn sessions(&self) -> Result<Vec<Session>> {
+        Ok(self
+            .operator()
+            .await?
+            .status
+            .map(|status| status.sessions)
+            .unwrap_or_default())
+    }
+
+    /// Ends the session with the given id.
+    pub async fn kill_session(&self, id: u64) -> Result<()> {
+        let result = self
+            .session_api()
+            .delete(&id.to_string(), &DeleteParams::default())
+            .await
+            .map(|either| either.right());
+
+        Self::check_status(result)
+    }
+
+    /// Ends all sessions.
+    pub async fn kill_all_sessions(&self) -> Result<()> {
+        let result = self
+            .session_api()
+            .delete_collection(&DeleteParams::default(), &ListParams::default())
+            .await
+            .map(|either| either.right());
+
+        Self::check_status(result)
+    }
+
+    /// Removes the sessions whose clients are gone, which the operator may still keep.
+    pub async fn kill_inactive_sessions(&self) -> Result<()> {
+        let result = self
+            .session_api()
+            .delete("inactive", &DeleteParams::default())
+            .await
+            .map(|either| either.right());
+
+        Self::check_status(result)
+    }
+
+    /// Invites the users `with` into the session with the given id, as `mirrord operator session
+    /// share` does. They can then join it with `session.join`.
+    ///
+    /// The operator accepts it only from the owner of the session.
+    pub async fn share_session(&self, id: u64, with: Vec<String>) -> Result<SessionShareCrd> {
+        let share = SessionShareCrd {
+            metadata: ObjectMeta {
+                generate_name: Some(format!("{id:x}-")),
+                ..Default::default()
+            },
+            spec: SessionShareSpec {
+                session_id: format!("{id:x}"),
+                with,
+            },
+        };
+
+        Api::<SessionShareCrd>::all(self.client.clone())
+            .create(&PostParams::default(), &share)
+            .await
+            .map_err(|error| OperatorApiError::KubeError {
+                error,
+                operation: OperatorOperation::SharingSession,
+            })
+    }
+
+    /// The targets that the operator knows in the namespace, the default one of the client when
+    /// [`None`].
+    pub async fn targets(&self, namespace: Option<&str>) -> Result<Vec<TargetCrd>> {
+        get_k8s_resource_api::<TargetCrd>(&self.client, namespace)
+            .list(&ListParams::default())
+            .await
+            .map(|list| list.items)
+            .map_err(|error| OperatorApiError::KubeError {
+                error,
+                operation: OperatorOperation::ListingTargets,
+            })
+    }
+
+    /// The policies in the namespace, the default one of the client when [`None`].
+    pub async fn policies(&self, namespace: Option<&str>) -> Result<Vec<MirrordPolicy>> {
+        get_k8s_resource_api::<MirrordPolicy>(&self.client, namespace)
+            .list(&ListParams::default())
+            .await
+            .map(|list| list.items)
+            .map_err(|error| OperatorApiError::KubeError {
+                error,
+                operation: OperatorOperation::ManagingPolicies,
+            })
+    }
+
+    /// Creates the policy `name` in the namespace, the default one of the client when [`None`].
+    pub async fn create_policy(
+        &self,
+        namespace: Option<&str>,
+        name: &str,
+        spec: MirrordPolicySpec,
+    ) -> Result<MirrordPolicy> {
+        get_k8s_resource_api::<MirrordPolicy>(&self.client, namespace)
+            .create(&PostParams::default(), &MirrordPolicy::new(name, spec))
+            .await
+            .map_err(|error| OperatorApiError::KubeError {
+                error,
+                operation: OperatorOperation::ManagingPolicies,
+            })
+    }
+
+    /// Deletes the policy `name` from the namespace, the default one of the client when [`None`].
+    pub async fn delete_policy(&self, namespace: Option<&str>, name: &str) -> Result<()> {
+        get_k8s_resource_api::<MirrordPolicy>(&self.client, namespace)
+            .delete(name, &DeleteParams::default())
+            .await
+            .map(|_| ())
+            .map_err(|error| OperatorApiError::KubeError {
+                error,
+                operation: OperatorOperation::ManagingPolicies,
+            })
+    }
+
+    fn session_api(&self) -> Api<SessionCrd> {
+        Api::all(self.client.clone())
+    }
+
+    /// Turns the result of a session route into an error when the operator reports a failure.
+    /// The operator may answer with the [`SessionCrd`] instead of a [`Status`] (`None` here), when
+    /// the operation is still in progress.
+    fn check_status(result: Result<Option<Status>, kube::Error>) -> Result<()> {
+        let status = result.map_err(|error| OperatorApiError::KubeError {
+            error,
+            operation: OperatorOperation::SessionManagement,
+        })?;
+
+        match status {
+            Some(status) if status.is_failure() => Err(OperatorApiError::StatusFailure {
+                operation: OperatorOperation::SessionManagement,
+                status: Box::new(status),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
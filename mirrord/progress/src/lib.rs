@@ -1,9 +1,18 @@
-use std::{collections::HashSet, time::Duration};
+use std::{
+    fs::File,
+    io::Write,
+    mem::ManuallyDrop,
+    os::fd::{FromRawFd, RawFd},
+    time::Duration,
+};
 
 use enum_dispatch::enum_dispatch;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use serde::Serialize;
-use serde_json::{to_string, Value};
+use mirrord_ext_schema::progress::{
+    FinishedTaskMessage, NewTaskMessage, ProgressMessage, WarningMessage,
+};
+pub use mirrord_ext_schema::progress::{IdeAction, IdeMessage, NotificationLevel};
+use serde_json::to_string;
 
 pub mod messages;
 
@@ -11,12 +20,19 @@ pub mod messages;
 /// to determine the mode of progress reporting
 pub const MIRRORD_PROGRESS_ENV: &str = "MIRRORD_PROGRESS_MODE";
 
+/// The environment variable with the file descriptor that [`JsonProgress`] writes to, instead of
+/// stdout, e.g. `MIRRORD_PROGRESS_FD=3 mirrord ext ... 3>progress.jsonl`.
+pub const MIRRORD_PROGRESS_FD_ENV: &str = "MIRRORD_PROGRESS_FD";
+
 /// Progress report API for displaying notifications in cli/extensions.
 ///
 /// This is our IDE friendly way of sending notification messages from the cli, be careful not to
 /// mix it (e.g. calling `progress.info`) with regular [`println!`], as the IDE may fail to parse
 /// the [`ProgressMessage`] (intellij will fail with `"failed to parse a message from mirrord
 /// binary"`), and we end up displaying an error instead.
+///
+/// Only [`JsonProgress`] writes to stdout (or to [`MIRRORD_PROGRESS_FD_ENV`]), the others write to
+/// stderr, so they don't mix with the output of the application that `mirrord exec` runs.
 #[enum_dispatch]
 pub trait Progress: Sized {
     /// Create a subtask report from this task.
@@ -111,7 +127,7 @@ impl JsonProgress {
             name: self.name.clone(),
             parent: self.parent.clone(),
         });
-        message.print();
+        print_message(&message);
     }
 
     fn print_finished_task(&self, success: bool, msg: Option<&str>) {
@@ -120,7 +136,7 @@ impl JsonProgress {
             message: msg.map(|s| s.to_string()),
             success,
         });
-        message.print();
+        print_message(&message);
     }
 }
 
@@ -142,7 +158,7 @@ impl Progress for JsonProgress {
         let message = ProgressMessage::Info {
             message: msg.to_string(),
         };
-        message.print();
+        print_message(&message);
     }
 
     fn ide(&self, value: serde_json::Value) {
@@ -152,7 +168,7 @@ impl Progress for JsonProgress {
             .unwrap_or(false)
         {
             let message = ProgressMessage::IdeMessage { message: value };
-            message.print();
+            print_message(&message);
         }
     }
 
@@ -160,7 +176,7 @@ impl Progress for JsonProgress {
         let message = ProgressMessage::Warning(WarningMessage {
             message: msg.to_string(),
         });
-        message.print();
+        print_message(&message);
     }
 
     fn failure(&mut self, msg: Option<&str>) {
@@ -195,37 +211,37 @@ pub struct SimpleProgress;
 
 impl SimpleProgress {
     fn new(text: &str) -> SimpleProgress {
-        println!("{text}");
+        eprintln!("{text}");
         SimpleProgress {}
     }
 }
 
 impl Progress for SimpleProgress {
     fn subtask(&self, text: &str) -> SimpleProgress {
-        println!("{text}");
+        eprintln!("{text}");
         SimpleProgress {}
     }
 
     fn print(&self, text: &str) {
-        println!("{text}");
+        eprintln!("{text}");
     }
 
     fn warning(&self, msg: &str) {
-        println!("{msg}");
+        eprintln!("{msg}");
     }
 
     fn info(&self, msg: &str) {
-        println!("{msg}");
+        eprintln!("{msg}");
     }
 
     fn ide(&self, _: serde_json::Value) {}
 
     fn failure(&mut self, msg: Option<&str>) {
-        println!("{msg:?}");
+        eprintln!("{msg:?}");
     }
 
     fn success(&mut self, msg: Option<&str>) {
-        println!("{msg:?}");
+        eprintln!("{msg:?}");
     }
 
     fn set_fail_on_drop(&mut self, _: bool) {}
@@ -361,95 +377,22 @@ impl ProgressTracker {
     }
 }
 
-/// Message sent when a new task is created using subtask/new
-#[derive(Serialize, Debug, Clone, Default)]
-struct NewTaskMessage {
-    /// Task name (indentifier)
-    name: String,
-    /// Parent task name, if subtask.
-    parent: Option<String>,
-}
-
-/// Message sent when a task is finished.
-#[derive(Serialize, Debug, Clone, Default)]
-struct FinishedTaskMessage {
-    /// Finished task name
-    name: String,
-    /// Was the task successful?
-    success: bool,
-    /// Finish message
-    message: Option<String>,
-}
-
-/// Message sent when a task is finished.
-#[derive(Serialize, Debug, Clone, Default)]
-struct WarningMessage {
-    /// Warning message
-    message: String,
-}
-
-/// Indicates what type of notification should appear in the IDEs.
-#[derive(Serialize, Debug, Clone, Default)]
-pub enum NotificationLevel {
-    /// Normal info box.
-    #[default]
-    Info,
-
-    /// Warning box.
-    Warning,
-}
-
-/// Action/button type that appears in the pop-up notifications.
-#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(tag = "kind")]
-pub enum IdeAction {
-    /// A link action, where `label` is the text, and `link` is the _href_.
-    Link { label: String, link: String },
-}
-
-/// Messages sent to the IDEs with full context.
-#[derive(Serialize, Debug, Clone, Default)]
-pub struct IdeMessage {
-    /// Allows us to identify this message and map it to something meaningful in the IDEs.
-    ///
-    /// Not shown to the user.
-    ///
-    /// In vscode, this should map to a `configEntry` defined in `package.json`.
-    pub id: String,
-
-    /// The level of the notification, the type of pop-up it'll be displayed in the IDEs.
-    pub level: NotificationLevel,
-
-    /// Message content.
-    pub text: String,
+/// Prints the `message` on its own line, to stdout or [`MIRRORD_PROGRESS_FD_ENV`].
+fn print_message(message: &ProgressMessage) {
+    let message = to_string(message).unwrap();
 
-    /// Actions/buttons that appears in the pop-up notification.
-    pub actions: HashSet<IdeAction>,
-}
-
-/// The message types that we report on [`Progress`].
-///
-/// These are used by the extensions (vscode and intellij) to show nice notifications.
-#[derive(Serialize, Debug, Clone)]
-#[serde(tag = "type")]
-enum ProgressMessage {
-    NewTask(NewTaskMessage),
-    Warning(WarningMessage),
-    FinishedTask(FinishedTaskMessage),
-    Info {
-        message: String,
-    },
-    /// Messages that are passed to the IDE and shown to the user in notification boxes.
-    IdeMessage {
-        /// It's a generic json [`Value`].
-        ///
-        /// Should be an [`IdeMessage`] converted to [`Value`].
-        message: Value,
-    },
+    match progress_fd() {
+        Some(fd) => {
+            // SAFETY: the fd was given to us for the progress in `MIRRORD_PROGRESS_FD`, and
+            // it's not closed here.
+            let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+            let _ = writeln!(file, "{message}");
+        }
+        None => println!("{message}"),
+    }
 }
 
-impl ProgressMessage {
-    pub(crate) fn print(&self) {
-        println!("{}", to_string(self).unwrap());
-    }
+/// [`MIRRORD_PROGRESS_FD_ENV`], if set.
+fn progress_fd() -> Option<RawFd> {
+    std::env::var(MIRRORD_PROGRESS_FD_ENV).ok()?.parse().ok()
 }
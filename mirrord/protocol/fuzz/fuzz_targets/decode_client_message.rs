@@ -0,0 +1,16 @@
+//! What the agent decodes: `ClientMessage`s from the client.
+
+#![no_main]
+
+use actix_codec::Decoder;
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use mirrord_protocol::DaemonCodec;
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = DaemonCodec::default();
+    let mut buffer = BytesMut::from(data);
+
+    // Until the data runs out or is malformed, neither may panic.
+    while let Ok(Some(..)) = codec.decode(&mut buffer) {}
+});
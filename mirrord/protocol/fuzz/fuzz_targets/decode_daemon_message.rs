@@ -0,0 +1,16 @@
+//! What the client decodes: `DaemonMessage`s from the agent.
+
+#![no_main]
+
+use actix_codec::Decoder;
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use mirrord_protocol::ClientCodec;
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = ClientCodec::default();
+    let mut buffer = BytesMut::from(data);
+
+    // Until the data runs out or is malformed, neither may panic.
+    while let Ok(Some(..)) = codec.decode(&mut buffer) {}
+});
@@ -0,0 +1,285 @@
+//! Features supported by both sides of the connection, negotiated with
+//! [`ClientMessage::Capabilities`](crate::ClientMessage::Capabilities) after the protocol version.
+//!
+//! Peers that predate the handshake get their capabilities from their version, see
+//! [`ProtocolCapabilities::from_version`].
+//!
+//! Some capabilities are features of the agent that need Linux capabilities, which an agent
+//! started with `agent.unprivileged` may not have, see [`Capability::agent_requirement`]. The
+//! agent leaves out of its answer the ones it can't provide.
+
+use std::{collections::BTreeSet, fmt, sync::LazyLock};
+
+use bincode::{Decode, Encode};
+use semver::{Version, VersionReq};
+
+use crate::{
+    codec::{CLIENT_READY_FOR_LOGS, LISTENERS_WATCH_VERSION},
+    file::{
+        CANONICALIZE_VERSION, FIFO_VERSION, LOCK_VERSION, OPEN_IMAGE_FILE_VERSION,
+        READ_DIR_BATCH_VERSION, READ_STREAM_VERSION, WATCH_VERSION,
+    },
+    fs_usage::FS_USAGE_VERSION,
+    grep::GREP_VERSION,
+    scratch::SCRATCH_VERSION,
+    tcp::{
+        GRPC_FILTER_VERSION, HTTP_FILTERED_UPGRADE_VERSION, HTTP_FRAMED_VERSION,
+        HTTP_PASS_THROUGH_VERSION, HTTP_SHADOW_VERSION,
+    },
+    udp::UDP_INCOMING_VERSION,
+};
+
+/// Minimal mirrord-protocol version that allows
+/// [`ClientMessage::Capabilities`](crate::ClientMessage::Capabilities).
+pub static CAPABILITIES_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.21.0".parse().expect("Bad Identifier"));
+
+/// Minimal mirrord-protocol version of the agents that advertise the features that need Linux
+/// capabilities ([`Capability::agent_requirement`]). Older agents always have them.
+pub static AGENT_FEATURES_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.23.0".parse().expect("Bad Identifier"));
+
+/// Every version, for the agent features that all the agents had before
+/// [`AGENT_FEATURES_VERSION`].
+static ANY_VERSION: VersionReq = VersionReq::STAR;
+
+/// A feature of the protocol that the other side may not support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Capability {
+    ReadyForLogs,
+    HttpFramed,
+    HttpFilteredUpgrade,
+    OpenImageFile,
+    HttpShadow,
+    HttpPassThrough,
+    ListenersWatch,
+    UdpIncoming,
+    GrpcFilter,
+    Grep,
+    Watch,
+    Fifo,
+    Lock,
+    StreamedReads,
+    Scratch,
+    ReadDirBatch,
+    Canonicalize,
+    FsUsage,
+    IncomingMirror,
+    IncomingSteal,
+    RemoteEnv,
+    RemoteFs,
+}
+
+impl Capability {
+    /// Every capability known to this version of mirrord-protocol.
+    pub const ALL: &'static [Self] = &[
+        Self::ReadyForLogs,
+        Self::HttpFramed,
+        Self::HttpFilteredUpgrade,
+        Self::OpenImageFile,
+        Self::HttpShadow,
+        Self::HttpPassThrough,
+        Self::ListenersWatch,
+        Self::UdpIncoming,
+        Self::GrpcFilter,
+        Self::Grep,
+        Self::Watch,
+        Self::Fifo,
+        Self::Lock,
+        Self::StreamedReads,
+        Self::Scratch,
+        Self::ReadDirBatch,
+        Self::Canonicalize,
+        Self::FsUsage,
+        Self::IncomingMirror,
+        Self::IncomingSteal,
+        Self::RemoteEnv,
+        Self::RemoteFs,
+    ];
+
+    /// Name under which the capability is advertised.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ReadyForLogs => "ready_for_logs",
+            Self::HttpFramed => "http_framed",
+            Self::HttpFilteredUpgrade => "http_filtered_upgrade",
+            Self::OpenImageFile => "open_image_file",
+            Self::HttpShadow => "http_shadow",
+            Self::HttpPassThrough => "http_pass_through",
+            Self::ListenersWatch => "listeners_watch",
+            Self::UdpIncoming => "udp_incoming",
+            Self::GrpcFilter => "grpc_filter",
+            Self::Grep => "grep",
+            Self::Watch => "watch",
+            Self::Fifo => "fifo",
+            Self::Lock => "lock",
+            Self::StreamedReads => "streamed_reads",
+            Self::Scratch => "scratch",
+            Self::ReadDirBatch => "read_dir_batch",
+            Self::Canonicalize => "canonicalize",
+            Self::FsUsage => "fs_usage",
+            Self::IncomingMirror => "incoming_mirror",
+            Self::IncomingSteal => "incoming_steal",
+            Self::RemoteEnv => "remote_env",
+            Self::RemoteFs => "remote_fs",
+        }
+    }
+
+    /// Protocol versions that have the capability, used for the peers that predate
+    /// [`CAPABILITIES_VERSION`].
+    pub fn version_req(self) -> &'static VersionReq {
+        match self {
+            Self::ReadyForLogs => &CLIENT_READY_FOR_LOGS,
+            Self::HttpFramed => &HTTP_FRAMED_VERSION,
+            Self::HttpFilteredUpgrade => &HTTP_FILTERED_UPGRADE_VERSION,
+            Self::OpenImageFile => &OPEN_IMAGE_FILE_VERSION,
+            Self::HttpShadow => &HTTP_SHADOW_VERSION,
+            Self::HttpPassThrough => &HTTP_PASS_THROUGH_VERSION,
+            Self::ListenersWatch => &LISTENERS_WATCH_VERSION,
+            Self::UdpIncoming => &UDP_INCOMING_VERSION,
+            Self::GrpcFilter => &GRPC_FILTER_VERSION,
+            Self::Grep => &GREP_VERSION,
+            Self::Watch => &WATCH_VERSION,
+            Self::Fifo => &FIFO_VERSION,
+            Self::Lock => &LOCK_VERSION,
+            Self::StreamedReads => &READ_STREAM_VERSION,
+            Self::Scratch => &SCRATCH_VERSION,
+            Self::ReadDirBatch => &READ_DIR_BATCH_VERSION,
+            Self::Canonicalize => &CANONICALIZE_VERSION,
+            Self::FsUsage => &FS_USAGE_VERSION,
+            Self::IncomingMirror | Self::IncomingSteal | Self::RemoteEnv | Self::RemoteFs => {
+                &ANY_VERSION
+            }
+        }
+    }
+
+    /// The Linux capabilities of the agent container that the feature needs, for the features of
+    /// the agent that an unprivileged agent may not have.
+    ///
+    /// - Mirroring captures the traffic with a raw socket (`NET_RAW`).
+    /// - Stealing redirects the traffic with iptables (`NET_ADMIN`).
+    /// - The env and files of the target are read through `/proc/{pid}`, which needs `SYS_PTRACE`
+    ///   when the target runs as another user.
+    ///
+    /// An agent that is not in an ephemeral container also needs `SYS_ADMIN` to enter the
+    /// network namespace of the target.
+    pub fn agent_requirement(self) -> Option<&'static str> {
+        match self {
+            Self::IncomingMirror => Some("NET_RAW"),
+            Self::IncomingSteal => Some("NET_ADMIN"),
+            Self::RemoteEnv | Self::RemoteFs => Some("SYS_PTRACE"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Set of [`Capability`]s advertised by one side of the connection.
+///
+/// Capabilities are sent by name, so that a peer can advertise the ones that the other side
+/// doesn't know about (they're ignored).
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Default)]
+pub struct ProtocolCapabilities(BTreeSet<String>);
+
+impl ProtocolCapabilities {
+    /// Every capability that this version of mirrord-protocol supports.
+    pub fn all() -> Self {
+        Capability::ALL.iter().copied().collect()
+    }
+
+    /// Capabilities of a peer that negotiated the given protocol version, but doesn't support
+    /// the handshake ([`CAPABILITIES_VERSION`]).
+    pub fn from_version(version: &Version) -> Self {
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(|capability| capability.version_req().matches(version))
+            .collect()
+    }
+
+    /// Capabilities of an agent that negotiated the given protocol version and answered the
+    /// handshake with `answer`.
+    ///
+    /// Agents from before [`AGENT_FEATURES_VERSION`] don't advertise the features that need
+    /// Linux capabilities, but always have them.
+    pub fn from_agent_answer(mut answer: Self, version: &Version) -> Self {
+        if !AGENT_FEATURES_VERSION.matches(version) {
+            answer.0.extend(
+                Capability::ALL
+                    .iter()
+                    .filter(|capability| capability.agent_requirement().is_some())
+                    .map(|capability| capability.name().to_string()),
+            );
+        }
+
+        answer
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.0.contains(capability.name())
+    }
+
+    /// Capabilities supported by both sides.
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self(self.0.intersection(&other.0).cloned().collect())
+    }
+
+    /// Removes a capability, e.g. a feature that the agent can't provide.
+    pub fn remove(&mut self, capability: Capability) {
+        self.0.remove(capability.name());
+    }
+}
+
+impl FromIterator<Capability> for ProtocolCapabilities {
+    fn from_iter<T: IntoIterator<Item = Capability>>(iter: T) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|capability| capability.name().to_string())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_version_matches_version_reqs() {
+        let old = ProtocolCapabilities::from_version(&"1.9.0".parse().unwrap());
+        assert!(old.supports(Capability::HttpPassThrough));
+        assert!(!old.supports(Capability::UdpIncoming));
+        assert!(!old.supports(Capability::Canonicalize));
+
+        let current = ProtocolCapabilities::from_version(&crate::VERSION);
+        assert_eq!(current, ProtocolCapabilities::all());
+    }
+
+    #[test]
+    fn unknown_capabilities_are_ignored() {
+        let mut newer = ProtocolCapabilities::all();
+        newer.0.insert("teleportation".to_string());
+
+        let agreed = ProtocolCapabilities::all().intersection(&newer);
+        assert_eq!(agreed, ProtocolCapabilities::all());
+    }
+
+    #[test]
+    fn agent_features_of_older_agents() {
+        let mut answer = ProtocolCapabilities::all();
+        answer.remove(Capability::IncomingSteal);
+
+        let older =
+            ProtocolCapabilities::from_agent_answer(answer.clone(), &"1.22.0".parse().unwrap());
+        assert!(older.supports(Capability::IncomingSteal));
+
+        let unprivileged = ProtocolCapabilities::from_agent_answer(answer, &crate::VERSION);
+        assert!(!unprivileged.supports(Capability::IncomingSteal));
+        assert!(unprivileged.supports(Capability::IncomingMirror));
+    }
+}
@@ -10,24 +10,35 @@ use bincode::{error::DecodeError, Decode, Encode};
 use bytes::{Buf, BufMut, BytesMut};
 use mirrord_macros::protocol_break;
 use semver::VersionReq;
+use thiserror::Error;
 
 use crate::{
+    capabilities::ProtocolCapabilities,
     dns::{GetAddrInfoRequest, GetAddrInfoResponse},
     file::{
-        AccessFileRequest, AccessFileResponse, CloseDirRequest, CloseFileRequest, FdOpenDirRequest,
-        GetDEnts64Request, GetDEnts64Response, OpenDirResponse, OpenFileRequest, OpenFileResponse,
-        OpenRelativeFileRequest, ReadDirRequest, ReadDirResponse, ReadFileRequest,
-        ReadFileResponse, ReadLimitedFileRequest, ReadLinkFileRequest, ReadLinkFileResponse,
-        SeekFileRequest, SeekFileResponse, WriteFileRequest, WriteFileResponse,
-        WriteLimitedFileRequest, XstatFsRequest, XstatFsResponse, XstatRequest, XstatResponse,
+        AccessFileRequest, AccessFileResponse, AllowFifosRequest, CanonicalizeRequest,
+        CanonicalizeResponse, CloseDirRequest, CloseFileRequest, FdOpenDirRequest, FlockRequest,
+        GetDEnts64Request, GetDEnts64Response, GetLockRequest, GetLockResponse, OpenDirResponse,
+        OpenFileRequest, OpenFileResponse, OpenImageFileRequest, OpenRelativeFileRequest,
+        ReadDirBatchRequest, ReadDirBatchResponse, ReadDirRequest, ReadDirResponse,
+        ReadFileRequest, ReadFileResponse, ReadLimitedFileRequest, ReadLinkFileRequest,
+        ReadLinkFileResponse, ReadStreamAckRequest, ReadStreamChunk, ReadStreamRequest,
+        ReadStreamStopRequest, SeekFileRequest, SeekFileResponse, SetLockRequest, WatchAddRequest,
+        WatchAddResponse, WatchEventsRequest, WatchEventsResponse, WatchRemoveRequest,
+        WriteFileRequest, WriteFileResponse, WriteLimitedFileRequest, XstatFsRequest,
+        XstatFsResponse, XstatRequest, XstatResponse,
     },
+    fs_usage::{FsUsageReport, FsUsageRequest},
+    grep::{DaemonGrep, GrepRequest},
     outgoing::{
         tcp::{DaemonTcpOutgoing, LayerTcpOutgoing},
         udp::{DaemonUdpOutgoing, LayerUdpOutgoing},
     },
     pause::DaemonPauseTarget,
+    scratch::{DaemonScratch, LayerScratch},
     tcp::{DaemonTcp, LayerTcp, LayerTcpSteal},
-    ResponseError,
+    udp::{DaemonUdp, LayerUdp, LayerUdpSteal},
+    Port, ResponseError,
 };
 
 #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy)]
@@ -82,12 +93,42 @@ pub enum FileRequest {
     CloseDir(CloseDirRequest),
     GetDEnts64(GetDEnts64Request),
     ReadLink(ReadLinkFileRequest),
+    /// Requires [`OPEN_IMAGE_FILE_VERSION`](crate::file::OPEN_IMAGE_FILE_VERSION).
+    OpenImage(OpenImageFileRequest),
+    /// Requires [`WATCH_VERSION`](crate::file::WATCH_VERSION).
+    WatchAdd(WatchAddRequest),
+    /// Requires [`WATCH_VERSION`](crate::file::WATCH_VERSION).
+    WatchRemove(WatchRemoveRequest),
+    /// Requires [`WATCH_VERSION`](crate::file::WATCH_VERSION).
+    WatchEvents(WatchEventsRequest),
+    /// Requires [`FIFO_VERSION`](crate::file::FIFO_VERSION).
+    AllowFifos(AllowFifosRequest),
+    /// Requires [`LOCK_VERSION`](crate::file::LOCK_VERSION).
+    Flock(FlockRequest),
+    /// Requires [`LOCK_VERSION`](crate::file::LOCK_VERSION).
+    SetLock(SetLockRequest),
+    /// Requires [`LOCK_VERSION`](crate::file::LOCK_VERSION).
+    GetLock(GetLockRequest),
+    /// Requires [`READ_STREAM_VERSION`](crate::file::READ_STREAM_VERSION).
+    ReadStream(ReadStreamRequest),
+    /// Requires [`READ_STREAM_VERSION`](crate::file::READ_STREAM_VERSION).
+    ReadStreamAck(ReadStreamAckRequest),
+    /// Requires [`READ_STREAM_VERSION`](crate::file::READ_STREAM_VERSION).
+    ReadStreamStop(ReadStreamStopRequest),
+    /// Requires [`READ_DIR_BATCH_VERSION`](crate::file::READ_DIR_BATCH_VERSION).
+    ReadDirBatch(ReadDirBatchRequest),
+    /// Requires [`CANONICALIZE_VERSION`](crate::file::CANONICALIZE_VERSION).
+    Canonicalize(CanonicalizeRequest),
 }
 
 /// Minimal mirrord-protocol version that allows `ClientMessage::ReadyForLogs` message.
 pub static CLIENT_READY_FOR_LOGS: LazyLock<VersionReq> =
     LazyLock::new(|| ">=1.3.1".parse().expect("Bad Identifier"));
 
+/// Minimal mirrord-protocol version that allows `ClientMessage::WatchListeners` message.
+pub static LISTENERS_WATCH_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.10.0".parse().expect("Bad Identifier"));
+
 /// `-layer` --> `-agent` messages.
 #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
 pub enum ClientMessage {
@@ -104,6 +145,34 @@ pub enum ClientMessage {
     PauseTargetRequest(bool),
     SwitchProtocolVersion(#[bincode(with_serde)] semver::Version),
     ReadyForLogs,
+    /// Asks the agent to report the ports the target listens on, with
+    /// `DaemonMessage::Listeners`, now and whenever they change.
+    ///
+    /// Requires [`LISTENERS_WATCH_VERSION`].
+    WatchListeners,
+    /// Requires [`UDP_INCOMING_VERSION`](crate::udp::UDP_INCOMING_VERSION).
+    Udp(LayerUdp),
+    /// Requires [`UDP_INCOMING_VERSION`](crate::udp::UDP_INCOMING_VERSION).
+    UdpSteal(LayerUdpSteal),
+    /// Searches the files of the target, the results come in `DaemonMessage::Grep`.
+    ///
+    /// Requires [`GREP_VERSION`](crate::grep::GREP_VERSION).
+    Grep(GrepRequest),
+    /// Keeps a directory of the target in sync with a local one, the agent's side comes in
+    /// `DaemonMessage::Scratch`.
+    ///
+    /// Requires [`SCRATCH_VERSION`](crate::scratch::SCRATCH_VERSION).
+    Scratch(LayerScratch),
+    /// Advertises the capabilities of the client, sent after the protocol version is negotiated.
+    /// The agent answers with `DaemonMessage::Capabilities`.
+    ///
+    /// Requires [`CAPABILITIES_VERSION`](crate::capabilities::CAPABILITIES_VERSION).
+    Capabilities(ProtocolCapabilities),
+    /// Observes which files the target accesses for a while, the agent answers with
+    /// `DaemonMessage::FsUsage` when it's done.
+    ///
+    /// Requires [`FS_USAGE_VERSION`](crate::fs_usage::FS_USAGE_VERSION).
+    FsUsage(FsUsageRequest),
 }
 
 /// Type alias for `Result`s that should be returned from mirrord-agent to mirrord-layer.
@@ -124,6 +193,13 @@ pub enum FileResponse {
     OpenDir(RemoteResult<OpenDirResponse>),
     GetDEnts64(RemoteResult<GetDEnts64Response>),
     ReadLink(RemoteResult<ReadLinkFileResponse>),
+    WatchAdd(RemoteResult<WatchAddResponse>),
+    WatchEvents(RemoteResult<WatchEventsResponse>),
+    Flock(RemoteResult<()>),
+    SetLock(RemoteResult<()>),
+    GetLock(RemoteResult<GetLockResponse>),
+    ReadDirBatch(RemoteResult<ReadDirBatchResponse>),
+    Canonicalize(RemoteResult<CanonicalizeResponse>),
 }
 
 /// `-agent` --> `-layer` messages.
@@ -146,10 +222,83 @@ pub enum DaemonMessage {
     /// Pause is deprecated but we don't want to break protocol
     PauseTarget(DaemonPauseTarget),
     SwitchProtocolVersionResponse(#[bincode(with_serde)] semver::Version),
+    /// TCP ports the target currently listens on, sorted, sent after
+    /// `ClientMessage::WatchListeners`.
+    Listeners(Vec<Port>),
+    Udp(DaemonUdp),
+    UdpSteal(DaemonUdp),
+    Grep(DaemonGrep),
+    /// Contents of a file streamed after `FileRequest::ReadStream`.
+    ReadStream(ReadStreamChunk),
+    /// Agent's side of `ClientMessage::Scratch`.
+    Scratch(DaemonScratch),
+    /// Capabilities supported by both the agent and the client, answer to
+    /// `ClientMessage::Capabilities`.
+    Capabilities(ProtocolCapabilities),
+    /// Answer to `ClientMessage::FsUsage`.
+    FsUsage(RemoteResult<FsUsageReport>),
+}
+
+/// Bytes a single message can take with the `strict-decode` feature, see [`ProtocolCodec`].
+///
+/// Well above the biggest messages mirrord sends (e.g. scratch files of
+/// [`MAX_SCRATCH_FILE_SIZE`](crate::scratch::MAX_SCRATCH_FILE_SIZE)), but keeps a malformed length
+/// from making the decoder allocate (and panic on) exabytes.
+pub const STRICT_DECODE_LIMIT: usize = 256 * 1024 * 1024;
+
+#[cfg(feature = "strict-decode")]
+type CodecConfig = bincode::config::Configuration<
+    bincode::config::LittleEndian,
+    bincode::config::Varint,
+    bincode::config::Limit<STRICT_DECODE_LIMIT>,
+>;
+
+#[cfg(not(feature = "strict-decode"))]
+type CodecConfig = bincode::config::Configuration;
+
+/// Why a message could not be decoded with the `strict-decode` feature, the inner error of the
+/// [`io::Error`] returned by [`ProtocolCodec::decode`](Decoder::decode).
+#[derive(Error, Debug)]
+pub enum StrictDecodeError {
+    /// The message is bigger than [`STRICT_DECODE_LIMIT`], or one of its lengths says so.
+    #[error("message exceeds the limit of {STRICT_DECODE_LIMIT} bytes")]
+    LimitExceeded,
+
+    /// A variant this version doesn't know, e.g. of a message from a newer peer.
+    #[error("unexpected variant {found} of `{type_name}`")]
+    UnexpectedVariant { type_name: &'static str, found: u32 },
+
+    #[error("invalid UTF-8 in a string: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+
+    /// Any other malformed data, e.g. an integer or a `bool` out of range.
+    #[error("malformed message: {0}")]
+    Malformed(String),
 }
 
+impl From<DecodeError> for StrictDecodeError {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::LimitExceeded => Self::LimitExceeded,
+            DecodeError::UnexpectedVariant {
+                type_name, found, ..
+            } => Self::UnexpectedVariant { type_name, found },
+            DecodeError::Utf8 { inner } => Self::Utf8(inner),
+            other => Self::Malformed(other.to_string()),
+        }
+    }
+}
+
+/// Encodes and decodes the messages of the protocol with [`bincode`].
+///
+/// With the `strict-decode` feature, a message and the lengths in it can't exceed
+/// [`STRICT_DECODE_LIMIT`], and the errors are [`StrictDecodeError`]s. Otherwise a malformed
+/// length can make [`Decoder::decode`] panic allocating the memory for it, or wait for the peer
+/// to send it. The messages are not recursive, so their depth is bounded by their types.
+///
+/// `fuzz/` has a `cargo fuzz` target for each decoder.
 pub struct ProtocolCodec<I, O> {
-    config: bincode::config::Configuration,
+    config: CodecConfig,
     /// Phantom fields to make this struct generic over message types.
     _phantom_incoming_message: PhantomData<I>,
     _phantom_outgoing_message: PhantomData<O>,
@@ -165,13 +314,23 @@ pub type DaemonCodec = ProtocolCodec<ClientMessage, DaemonMessage>;
 impl<I, O> Default for ProtocolCodec<I, O> {
     fn default() -> Self {
         Self {
-            config: bincode::config::standard(),
+            config: codec_config(),
             _phantom_incoming_message: Default::default(),
             _phantom_outgoing_message: Default::default(),
         }
     }
 }
 
+#[cfg(feature = "strict-decode")]
+fn codec_config() -> CodecConfig {
+    bincode::config::standard().with_limit::<STRICT_DECODE_LIMIT>()
+}
+
+#[cfg(not(feature = "strict-decode"))]
+fn codec_config() -> CodecConfig {
+    bincode::config::standard()
+}
+
 impl<I: bincode::Decode, O> Decoder for ProtocolCodec<I, O> {
     type Item = I;
     type Error = io::Error;
@@ -182,7 +341,20 @@ impl<I: bincode::Decode, O> Decoder for ProtocolCodec<I, O> {
                 src.advance(read);
                 Ok(Some(message))
             }
+            // Don't buffer the rest of a message that can't fit in the limit.
+            Err(DecodeError::UnexpectedEnd { .. })
+                if cfg!(feature = "strict-decode") && src.len() > STRICT_DECODE_LIMIT =>
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    StrictDecodeError::LimitExceeded,
+                ))
+            }
             Err(DecodeError::UnexpectedEnd { .. }) => Ok(None),
+            Err(err) if cfg!(feature = "strict-decode") => Err(io::Error::new(
+                io::ErrorKind::Other,
+                StrictDecodeError::from(err),
+            )),
             Err(err) => Err(io::Error::new(io::ErrorKind::Other, err.to_string())),
         }
     }
@@ -281,4 +453,30 @@ mod tests {
             Err(err) => assert_eq!(err.kind(), io::ErrorKind::Other),
         }
     }
+
+    #[cfg(feature = "strict-decode")]
+    #[test]
+    fn decode_daemon_huge_length() {
+        let mut daemon_codec = DaemonCodec::default();
+        let mut client_codec = ClientCodec::default();
+        let mut buf = BytesMut::new();
+
+        let msg = DaemonMessage::Tcp(DaemonTcp::Data(TcpData {
+            connection_id: 1,
+            bytes: vec![],
+        }));
+        daemon_codec.encode(msg, &mut buf).unwrap();
+
+        // Replaces the empty length of `bytes` with a `u64` one of 1 TiB.
+        buf.truncate(buf.len() - 1);
+        buf.put_u8(253);
+        buf.put_u64_le(1 << 40);
+
+        let err = client_codec.decode(&mut buf).unwrap_err();
+        assert!(matches!(
+            err.get_ref()
+                .and_then(|inner| inner.downcast_ref::<StrictDecodeError>()),
+            Some(StrictDecodeError::LimitExceeded)
+        ));
+    }
 }
@@ -64,6 +64,18 @@ pub enum ResponseError {
 
     #[error("Failed stripping path with `{0}`!")]
     StripPrefix(String),
+
+    /// A request is bigger than a limit set in the config, returned by the internal proxy.
+    #[error(
+        "{what} of {size} bytes is over the limit of {limit} bytes, set `{config_key}` in the \
+         mirrord config to change it."
+    )]
+    SizeLimitExceeded {
+        what: String,
+        size: u64,
+        limit: u64,
+        config_key: String,
+    },
 }
 
 impl From<StripPrefixError> for ResponseError {
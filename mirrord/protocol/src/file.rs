@@ -5,11 +5,16 @@ use std::fs::DirEntry;
 use std::io;
 #[cfg(target_os = "linux")]
 use std::os::unix::fs::DirEntryExt;
-use std::{fs::Metadata, io::SeekFrom, os::unix::prelude::MetadataExt, path::PathBuf};
+use std::{
+    fs::Metadata, io::SeekFrom, os::unix::prelude::MetadataExt, path::PathBuf, sync::LazyLock,
+};
 
 use bincode::{Decode, Encode};
 #[cfg(target_os = "linux")]
 use nix::sys::statfs::Statfs;
+use semver::VersionReq;
+
+use crate::RemoteResult;
 
 /// Internal version of Metadata across operating system (macOS, Linux)
 /// Only mutual attributes
@@ -44,6 +49,12 @@ pub struct MetadataInternal {
     pub blocks: u64,
 }
 
+/// Nanoseconds since the epoch of a timestamp from [`MetadataExt`], which splits it into seconds
+/// and the nanoseconds within the second.
+fn timestamp_nanos(secs: i64, nanos: i64) -> i64 {
+    secs.saturating_mul(1_000_000_000).saturating_add(nanos)
+}
+
 impl From<Metadata> for MetadataInternal {
     fn from(metadata: Metadata) -> Self {
         Self {
@@ -55,9 +66,9 @@ impl From<Metadata> for MetadataInternal {
             group_id: metadata.gid(),
             rdevice_id: metadata.rdev(),
             size: metadata.size(),
-            access_time: metadata.atime_nsec(),
-            modification_time: metadata.mtime_nsec(),
-            creation_time: metadata.ctime_nsec(),
+            access_time: timestamp_nanos(metadata.atime(), metadata.atime_nsec()),
+            modification_time: timestamp_nanos(metadata.mtime(), metadata.mtime_nsec()),
+            creation_time: timestamp_nanos(metadata.ctime(), metadata.ctime_nsec()),
             block_size: metadata.blksize(),
             blocks: metadata.blocks(),
         }
@@ -268,6 +279,18 @@ pub struct ReadLinkFileRequest {
     pub path: PathBuf,
 }
 
+/// Opens `path` for reading from the target container's image filesystem, i.e. the root mount of
+/// the container without any volumes mounted on top of it.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct OpenImageFileRequest {
+    pub path: PathBuf,
+}
+
+/// Minimal mirrord-protocol version that allows
+/// [`FileRequest::OpenImage`](crate::FileRequest::OpenImage).
+pub static OPEN_IMAGE_FILE_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.7.0".parse().expect("Bad Identifier"));
+
 #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
 pub struct SeekFileRequest {
     pub fd: u64,
@@ -407,3 +430,246 @@ pub struct GetDEnts64Response {
     pub entries: Vec<DirEntryInternal>,
     pub result_size: u64,
 }
+
+/// Minimal mirrord-protocol version that allows [`FileRequest::WatchAdd`],
+/// [`FileRequest::WatchRemove`] and [`FileRequest::WatchEvents`].
+///
+/// [`FileRequest::WatchAdd`]: crate::FileRequest::WatchAdd
+/// [`FileRequest::WatchRemove`]: crate::FileRequest::WatchRemove
+/// [`FileRequest::WatchEvents`]: crate::FileRequest::WatchEvents
+pub static WATCH_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.14.0".parse().expect("Bad Identifier"));
+
+/// Starts watching `path` in the target for the `inotify` events in `mask` (`IN_*` flags).
+///
+/// Watching the same path again replaces its mask and returns the same `watch_id`, like
+/// `inotify_add_watch` does.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct WatchAddRequest {
+    pub path: PathBuf,
+    pub mask: u32,
+}
+
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct WatchAddResponse {
+    pub watch_id: u64,
+}
+
+/// Stops the watch started with [`WatchAddRequest`], there is no response.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct WatchRemoveRequest {
+    pub watch_id: u64,
+}
+
+/// Takes the events that happened on the watches of this client since the last request.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct WatchEventsRequest;
+
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct WatchEventsResponse {
+    pub events: Vec<WatchEvent>,
+}
+
+/// Mirrors `struct inotify_event`.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct WatchEvent {
+    pub watch_id: u64,
+    pub mask: u32,
+    pub cookie: u32,
+    /// Name of the file in the watched directory that the event is about.
+    pub name: Option<String>,
+}
+
+/// Minimal mirrord-protocol version that allows [`FileRequest::AllowFifos`].
+///
+/// [`FileRequest::AllowFifos`]: crate::FileRequest::AllowFifos
+pub static FIFO_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.15.0".parse().expect("Bad Identifier"));
+
+/// Lets this client open the named pipes (FIFOs) of the target, there is no response.
+///
+/// The agent keeps such pipes open for reading and writing, and never blocks on them: reads and
+/// writes that would block fail with `EAGAIN`. Without this request, opening a FIFO fails with
+/// `ENXIO`.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct AllowFifosRequest;
+
+/// Minimal mirrord-protocol version that allows [`FileRequest::Flock`],
+/// [`FileRequest::SetLock`] and [`FileRequest::GetLock`].
+///
+/// [`FileRequest::Flock`]: crate::FileRequest::Flock
+/// [`FileRequest::SetLock`]: crate::FileRequest::SetLock
+/// [`FileRequest::GetLock`]: crate::FileRequest::GetLock
+pub static LOCK_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.16.0".parse().expect("Bad Identifier"));
+
+/// Operation of a [`FlockRequest`], mirrors `LOCK_SH`, `LOCK_EX` and `LOCK_UN`.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FlockOperation {
+    Shared,
+    Exclusive,
+    Unlock,
+}
+
+/// `flock` on a remote file.
+///
+/// The agent never waits for the lock, as if `LOCK_NB` was always given: when someone else holds
+/// it, the request fails with `EWOULDBLOCK`.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct FlockRequest {
+    pub fd: u64,
+    pub operation: FlockOperation,
+}
+
+/// Type of a [`FileLock`], mirrors `F_RDLCK`, `F_WRLCK` and `F_UNLCK`.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FileLockType {
+    Read,
+    Write,
+    Unlock,
+}
+
+/// Mirrors `struct flock`, a lock on a range of a file.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct FileLock {
+    pub lock_type: FileLockType,
+    /// `SEEK_SET`, `SEEK_CUR` or `SEEK_END`, which `start` is relative to.
+    pub whence: i16,
+    pub start: i64,
+    /// `0` means until the end of the file, however large it grows.
+    pub len: i64,
+    /// Process that holds the lock, in [`GetLockResponse`]. `-1` when the lock belongs to an open
+    /// file description instead, e.g. of another mirrord client.
+    pub pid: i32,
+}
+
+/// `fcntl(F_SETLK)` on a remote file.
+///
+/// The locks are taken as open file description locks (`F_OFD_SETLK`), so that the clients of the
+/// same agent don't share them. The agent never waits for the lock, like with `F_SETLK`: when
+/// someone else holds it, the request fails with `EAGAIN`.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct SetLockRequest {
+    pub fd: u64,
+    pub lock: FileLock,
+}
+
+/// `fcntl(F_GETLK)` on a remote file.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct GetLockRequest {
+    pub fd: u64,
+    pub lock: FileLock,
+}
+
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct GetLockResponse {
+    /// A lock that would prevent taking the requested one, [`None`] if there's no such lock.
+    pub conflicting: Option<FileLock>,
+}
+
+/// Minimal mirrord-protocol version that allows [`FileRequest::ReadStream`],
+/// [`FileRequest::ReadStreamAck`] and [`FileRequest::ReadStreamStop`].
+///
+/// [`FileRequest::ReadStream`]: crate::FileRequest::ReadStream
+/// [`FileRequest::ReadStreamAck`]: crate::FileRequest::ReadStreamAck
+/// [`FileRequest::ReadStreamStop`]: crate::FileRequest::ReadStreamStop
+pub static READ_STREAM_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.17.0".parse().expect("Bad Identifier"));
+
+/// Starts streaming the file from its current position, there is no response.
+///
+/// The agent sends the contents in [`ReadStreamChunk`]s, without waiting for more requests, until
+/// the end of the file or the first error. At most `window` bytes are sent before the client
+/// acknowledges them with [`ReadStreamAckRequest`]s.
+///
+/// The stream owns the position of the file until [`ReadStreamStopRequest`] or the file is closed.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct ReadStreamRequest {
+    pub fd: u64,
+    /// Chosen by the client, unique among the streams of this client.
+    pub stream_id: u64,
+    pub window: u64,
+}
+
+/// The client consumed `amount` more bytes of the stream, the agent can send them again, there is
+/// no response.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct ReadStreamAckRequest {
+    pub stream_id: u64,
+    pub amount: u64,
+}
+
+/// Stops the stream and moves the position of the file right after the `consumed` bytes, as if
+/// only those were read, there is no response.
+///
+/// The client ignores the chunks that were already sent.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct ReadStreamStopRequest {
+    pub stream_id: u64,
+    pub consumed: u64,
+}
+
+/// Part of the file streamed with [`ReadStreamRequest`].
+#[derive(Encode, Decode, PartialEq, Eq, Clone)]
+pub struct ReadStreamChunk {
+    pub stream_id: u64,
+    /// Position of the chunk in the file.
+    pub offset: u64,
+    /// Empty at the end of the file. The stream ends with the first error, or the end of the file.
+    pub bytes: RemoteResult<Vec<u8>>,
+}
+
+impl fmt::Debug for ReadStreamChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadStreamChunk")
+            .field("stream_id", &self.stream_id)
+            .field("offset", &self.offset)
+            .field(
+                "bytes (length)",
+                &self.bytes.as_ref().map(|bytes| bytes.len()),
+            )
+            .finish()
+    }
+}
+
+/// Minimal mirrord-protocol version that allows [`FileRequest::ReadDirBatch`].
+///
+/// [`FileRequest::ReadDirBatch`]: crate::FileRequest::ReadDirBatch
+pub static READ_DIR_BATCH_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.19.0".parse().expect("Bad Identifier"));
+
+/// Reads up to `amount` next entries of the directory opened with [`FdOpenDirRequest`], along
+/// with their metadata, so that the client doesn't have to ask for each one separately.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct ReadDirBatchRequest {
+    pub remote_fd: u64,
+    pub amount: u64,
+}
+
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct ReadDirBatchResponse {
+    /// Empty when there are no more entries. Each entry comes with its metadata, symlinks are not
+    /// followed (like `lstat`).
+    pub entries: Vec<(DirEntryInternal, MetadataInternal)>,
+}
+
+/// Minimal mirrord-protocol version that allows [`FileRequest::Canonicalize`].
+///
+/// [`FileRequest::Canonicalize`]: crate::FileRequest::Canonicalize
+pub static CANONICALIZE_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.20.0".parse().expect("Bad Identifier"));
+
+/// Resolves all symlinks of the absolute `path` in the target's filesystem, like `realpath`.
+///
+/// Fails with `ELOOP` when there are too many symlinks to follow, and with `ENOENT` when a
+/// component doesn't exist.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct CanonicalizeRequest {
+    pub path: PathBuf,
+}
+
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct CanonicalizeResponse {
+    /// Absolute path in the target, without symlinks, `.` or `..` components.
+    pub path: PathBuf,
+}
@@ -0,0 +1,36 @@
+use std::{path::PathBuf, sync::LazyLock};
+
+use bincode::{Decode, Encode};
+use semver::VersionReq;
+
+/// Minimal mirrord-protocol version that allows
+/// [`ClientMessage::FsUsage`](crate::ClientMessage::FsUsage).
+pub static FS_USAGE_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.22.0".parse().expect("Bad Identifier"));
+
+/// Observes which files the target accesses for a while, see
+/// [`ClientMessage::FsUsage`](crate::ClientMessage::FsUsage).
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct FsUsageRequest {
+    /// How long to observe the target for.
+    pub duration_secs: u64,
+}
+
+/// A file of the target that was accessed while observing.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct FileUsage {
+    /// As seen by the target.
+    pub path: PathBuf,
+    pub read: bool,
+    pub written: bool,
+}
+
+/// Answer to [`FsUsageRequest`], sent when the observation is done.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct FsUsageReport {
+    /// Sorted by path.
+    pub files: Vec<FileUsage>,
+    /// Whether the kernel dropped some of the access events, then [`FsUsageReport::files`] may
+    /// be missing some.
+    pub overflowed: bool,
+}
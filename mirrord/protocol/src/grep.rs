@@ -0,0 +1,54 @@
+use std::{path::PathBuf, sync::LazyLock};
+
+use bincode::{Decode, Encode};
+use semver::VersionReq;
+
+use crate::RemoteResult;
+
+/// Minimal mirrord-protocol version that allows
+/// [`ClientMessage::Grep`](crate::ClientMessage::Grep).
+pub static GREP_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.13.0".parse().expect("Bad Identifier"));
+
+/// Searches the files of the target for lines matching a regex, see
+/// [`ClientMessage::Grep`](crate::ClientMessage::Grep).
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct GrepRequest {
+    /// Regex the lines are matched against, with the syntax of the
+    /// [`regex`](https://docs.rs/regex/latest/regex/) crate.
+    pub pattern: String,
+    /// Files and directories of the target to search, directories are searched recursively.
+    pub paths: Vec<PathBuf>,
+    pub ignore_case: bool,
+    /// The search stops after this many matches.
+    pub max_matches: u64,
+}
+
+/// A line of a target's file that matched the [`GrepRequest::pattern`].
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct GrepMatch {
+    pub path: PathBuf,
+    /// 1-based.
+    pub line_number: u64,
+    /// The line, without the line terminator, lossily converted to UTF-8.
+    pub line: String,
+}
+
+/// Sent when the search is done.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct GrepSummary {
+    pub files_searched: u64,
+    /// Files that could not be read (e.g. no permissions) or were skipped as binary.
+    pub files_skipped: u64,
+    pub matches: u64,
+    /// Whether the search stopped early because of [`GrepRequest::max_matches`].
+    pub truncated: bool,
+}
+
+/// Results of a [`GrepRequest`], from agent.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub enum DaemonGrep {
+    Match(GrepMatch),
+    /// Last message of the search.
+    Finished(RemoteResult<GrepSummary>),
+}
@@ -3,13 +3,18 @@
 #![feature(lazy_cell)]
 #![warn(clippy::indexing_slicing)]
 
+pub mod capabilities;
 pub mod codec;
 pub mod dns;
 pub mod error;
 pub mod file;
+pub mod fs_usage;
+pub mod grep;
 pub mod outgoing;
 pub mod pause;
+pub mod scratch;
 pub mod tcp;
+pub mod udp;
 
 use core::fmt;
 use std::{collections::HashSet, ops::Deref, str::FromStr, sync::LazyLock};
@@ -102,3 +107,59 @@ impl FromStr for MeshVendor {
 pub const AGENT_OPERATOR_CERT_ENV: &str = "MIRRORD_AGENT_OPERATOR_CERT";
 
 pub const AGENT_NETWORK_INTERFACE_ENV: &str = "MIRRORD_AGENT_INTERFACE";
+
+/// Capture backend of the agent's sniffer, `raw` or `ebpf` (`agent.sniffer`).
+pub const AGENT_SNIFFER_ENV: &str = "MIRRORD_AGENT_SNIFFER";
+
+/// Whether the agent runs without the Linux capabilities of its features, and provides only the
+/// ones it can (`agent.unprivileged`).
+pub const AGENT_UNPRIVILEGED_ENV: &str = "MIRRORD_AGENT_UNPRIVILEGED";
+
+/// Transport on which the agent accepts its clients, `tcp` or `quic` (`agent.transport`).
+pub const AGENT_TRANSPORT_ENV: &str = "MIRRORD_AGENT_TRANSPORT";
+
+/// PEM-encoded X509 certificate that the agent presents to the QUIC clients, generated by the
+/// client for the session (`agent.transport = "quic"`).
+pub const AGENT_QUIC_CERT_ENV: &str = "MIRRORD_AGENT_QUIC_CERT";
+
+/// PEM-encoded private key of the [`AGENT_QUIC_CERT_ENV`] certificate.
+pub const AGENT_QUIC_KEY_ENV: &str = "MIRRORD_AGENT_QUIC_KEY";
+
+/// ALPN protocol of the QUIC connections between the clients and the agent.
+pub const AGENT_QUIC_ALPN: &[u8] = b"mirrord";
+
+/// OTLP/HTTP endpoint to which the agent pushes its metrics (`agent.otlp_metrics.endpoint`).
+pub const AGENT_OTLP_METRICS_ENDPOINT_ENV: &str = "MIRRORD_AGENT_OTLP_METRICS_ENDPOINT";
+
+/// Headers the agent sends with its metrics, `key1=value1,key2=value2`
+/// (`agent.otlp_metrics.headers`).
+pub const AGENT_OTLP_METRICS_HEADERS_ENV: &str = "MIRRORD_AGENT_OTLP_METRICS_HEADERS";
+
+/// Seconds between the exports of the agent's metrics (`agent.otlp_metrics.interval`).
+pub const AGENT_OTLP_METRICS_INTERVAL_ENV: &str = "MIRRORD_AGENT_OTLP_METRICS_INTERVAL";
+
+/// Seconds after which the agent ends the sessions of its clients (`session.max_duration`).
+pub const AGENT_MAX_SESSION_DURATION_ENV: &str = "MIRRORD_AGENT_MAX_SESSION_DURATION";
+
+/// Milliseconds the agent waits for each attempt to make an outgoing connection
+/// (`feature.network.outgoing.connect_timeout`).
+pub const AGENT_OUTGOING_CONNECT_TIMEOUT_ENV: &str = "MIRRORD_AGENT_OUTGOING_CONNECT_TIMEOUT";
+
+/// How many times the agent retries a failed outgoing connection
+/// (`feature.network.outgoing.retries`).
+pub const AGENT_OUTGOING_CONNECT_RETRIES_ENV: &str = "MIRRORD_AGENT_OUTGOING_CONNECT_RETRIES";
+
+/// Bytes of mirrored traffic the agent keeps in memory for each client
+/// (`agent.mirror_buffer.memory`).
+pub const AGENT_MIRROR_BUFFER_MEMORY_ENV: &str = "MIRRORD_AGENT_MIRROR_BUFFER_MEMORY";
+
+/// Bytes of mirrored traffic the agent spills to disk for all its clients, when their memory is
+/// full (`agent.mirror_buffer.disk`).
+pub const AGENT_MIRROR_BUFFER_DISK_ENV: &str = "MIRRORD_AGENT_MIRROR_BUFFER_DISK";
+
+/// Directory to which the agent spills the mirrored traffic, the `emptyDir` volume of its pod.
+pub const AGENT_MIRROR_BUFFER_DIR_ENV: &str = "MIRRORD_AGENT_MIRROR_BUFFER_DIR";
+
+/// Which mirrored traffic the agent drops when the buffer of a client is full,
+/// `drop_oldest` or `drop_newest` (`agent.mirror_buffer.on_full`).
+pub const AGENT_MIRROR_BUFFER_ON_FULL_ENV: &str = "MIRRORD_AGENT_MIRROR_BUFFER_ON_FULL";
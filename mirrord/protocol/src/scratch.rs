@@ -0,0 +1,73 @@
+//! A directory of the target kept in sync with a local directory of the client, see
+//! [`ClientMessage::Scratch`](crate::ClientMessage::Scratch).
+
+use core::fmt;
+use std::{path::PathBuf, sync::LazyLock};
+
+use bincode::{Decode, Encode};
+use semver::VersionReq;
+
+use crate::ResponseError;
+
+/// Minimal mirrord-protocol version that allows
+/// [`ClientMessage::Scratch`](crate::ClientMessage::Scratch).
+pub static SCRATCH_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.18.0".parse().expect("Bad Identifier"));
+
+/// Bigger files are not synchronized by either side.
+pub const MAX_SCRATCH_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A directory or a file in the synchronized directory. Other kinds of files (e.g. symlinks) are
+/// not synchronized.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct ScratchEntry {
+    /// Relative to the synchronized directory.
+    pub path: PathBuf,
+    /// Permission bits.
+    pub mode: u32,
+    pub kind: ScratchEntryKind,
+}
+
+#[derive(Encode, Decode, PartialEq, Eq, Clone)]
+pub enum ScratchEntryKind {
+    Dir,
+    /// Whole contents of the file.
+    File(Vec<u8>),
+}
+
+impl fmt::Debug for ScratchEntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dir => f.write_str("Dir"),
+            Self::File(contents) => f
+                .debug_struct("File")
+                .field("contents (length)", &contents.len())
+                .finish(),
+        }
+    }
+}
+
+/// Client's side of the synchronization.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub enum LayerScratch {
+    /// Starts synchronizing the directory of the target with this absolute path, creating it if
+    /// needed. The agent sends [`DaemonScratch::Changed`] for everything that is already there.
+    ///
+    /// Replaces the previous synchronization, if any.
+    Start(PathBuf),
+    /// Created or changed in the client's directory, the agent writes it.
+    Changed(ScratchEntry),
+    /// Removed from the client's directory, along with everything under it.
+    Removed(PathBuf),
+}
+
+/// Agent's side of the synchronization, after [`LayerScratch::Start`].
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub enum DaemonScratch {
+    /// Created or changed in the target.
+    Changed(ScratchEntry),
+    /// Removed in the target, along with everything under it.
+    Removed(PathBuf),
+    /// The agent stopped synchronizing, e.g. it couldn't create or watch the directory.
+    Stopped(ResponseError),
+}
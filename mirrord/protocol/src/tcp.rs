@@ -13,7 +13,7 @@ use bincode::{Decode, Encode};
 use bytes::Bytes;
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::{
-    body::{Body, Frame, Incoming},
+    body::{Body, Frame},
     http,
     http::response::Parts,
     HeaderMap, Method, Request, Response, StatusCode, Uri, Version,
@@ -73,6 +73,11 @@ pub enum DaemonTcp {
     SubscribeResult(RemoteResult<Port>),
     HttpRequest(HttpRequest<Vec<u8>>),
     HttpRequestFramed(HttpRequest<InternalHttpBody>),
+    /// Response of the original destination to a request shadowed with
+    /// [`StealType::FilteredHttpShadow`].
+    ///
+    /// Requires [`HTTP_SHADOW_VERSION`].
+    HttpShadowResponse(HttpResponse<InternalHttpBody>),
 }
 
 /// Wraps the string that will become a [`fancy_regex::Regex`], providing a nice API in
@@ -113,6 +118,10 @@ pub enum HttpFilter {
     Header(Filter),
     /// Filter by path ("/api/v1")
     Path(Filter),
+    /// Filter gRPC requests by service and method.
+    ///
+    /// Requires [`GRPC_FILTER_VERSION`].
+    Grpc(GrpcFilter),
 }
 
 impl Display for HttpFilter {
@@ -120,6 +129,26 @@ impl Display for HttpFilter {
         match self {
             HttpFilter::Header(filter) => write!(f, "header={filter}"),
             HttpFilter::Path(filter) => write!(f, "path={filter}"),
+            HttpFilter::Grpc(filter) => write!(f, "grpc={filter}"),
+        }
+    }
+}
+
+/// Matches gRPC requests (`content-type: application/grpc...`) by the service and method names
+/// from their `/<service>/<method>` path.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub struct GrpcFilter {
+    /// Fully qualified name of the service, e.g. `my.package.Service`.
+    pub service: String,
+    /// Name of the method, e.g. `MyMethod`. [`None`] matches all methods of the service.
+    pub method: Option<String>,
+}
+
+impl Display for GrpcFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.method {
+            Some(method) => write!(f, "{}/{method}", self.service),
+            None => Display::fmt(&self.service, f),
         }
     }
 }
@@ -135,13 +164,20 @@ pub enum StealType {
     FilteredHttp(Port, Filter),
     /// Steal HTTP traffic matching a given filter - supporting more than once kind of filter
     FilteredHttpEx(Port, HttpFilter),
+    /// Shadow HTTP traffic matching a given filter: the client gets a copy of each matching
+    /// request, while the original destination still handles it and responds. The response of
+    /// the original destination is sent to the client in [`DaemonTcp::HttpShadowResponse`].
+    ///
+    /// Requires [`HTTP_SHADOW_VERSION`].
+    FilteredHttpShadow(Port, HttpFilter),
 }
 
 impl StealType {
     pub fn get_port(&self) -> Port {
         let (StealType::All(port)
         | StealType::FilteredHttpEx(port, ..)
-        | StealType::FilteredHttp(port, ..)) = self;
+        | StealType::FilteredHttp(port, ..)
+        | StealType::FilteredHttpShadow(port, ..)) = self;
         *port
     }
 }
@@ -155,6 +191,13 @@ pub enum LayerTcpSteal {
     Data(TcpData),
     HttpResponse(HttpResponse<Vec<u8>>),
     HttpResponseFramed(HttpResponse<InternalHttpBody>),
+    /// The client could not handle a stolen request (e.g. it responded with a server error), so
+    /// the request should be passed to its original destination, which responds to it instead.
+    ///
+    /// Carries the original request, since the agent does not keep it.
+    ///
+    /// Requires [`HTTP_PASS_THROUGH_VERSION`].
+    HttpRequestPassThrough(HttpRequest<InternalHttpBody>),
 }
 
 /// (De-)Serializable HTTP request.
@@ -265,6 +308,25 @@ impl HttpRequestFallback {
             HttpRequestFallback::Fallback(req) => req.internal_request.into(),
         }
     }
+
+    /// Converts this request into an [`HttpRequest`] with an [`InternalHttpBody`].
+    pub fn into_framed(self) -> HttpRequest<InternalHttpBody> {
+        match self {
+            HttpRequestFallback::Framed(req) => req,
+            HttpRequestFallback::Fallback(req) => HttpRequest {
+                internal_request: InternalHttpRequest {
+                    method: req.internal_request.method,
+                    uri: req.internal_request.uri,
+                    headers: req.internal_request.headers,
+                    version: req.internal_request.version,
+                    body: InternalHttpBody::from_bytes(&req.internal_request.body),
+                },
+                connection_id: req.connection_id,
+                request_id: req.request_id,
+                port: req.port,
+            },
+        }
+    }
 }
 
 /// Minimal mirrord-protocol version that allows [`DaemonTcp::HttpRequestFramed`] instead of
@@ -277,6 +339,19 @@ pub static HTTP_FRAMED_VERSION: LazyLock<VersionReq> =
 pub static HTTP_FILTERED_UPGRADE_VERSION: LazyLock<VersionReq> =
     LazyLock::new(|| ">=1.5.0".parse().expect("Bad Identifier"));
 
+/// Minimal mirrord-protocol version that allows [`StealType::FilteredHttpShadow`] and
+/// [`DaemonTcp::HttpShadowResponse`].
+pub static HTTP_SHADOW_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.8.0".parse().expect("Bad Identifier"));
+
+/// Minimal mirrord-protocol version that allows [`LayerTcpSteal::HttpRequestPassThrough`].
+pub static HTTP_PASS_THROUGH_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.9.0".parse().expect("Bad Identifier"));
+
+/// Minimal mirrord-protocol version that allows [`HttpFilter::Grpc`].
+pub static GRPC_FILTER_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.12.0".parse().expect("Bad Identifier"));
+
 /// Protocol break - on version 2, please add source port, dest/src IP to the message
 /// so we can avoid losing this information.
 #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
@@ -321,6 +396,22 @@ pub struct InternalHttpResponse<Body> {
 }
 
 impl<B> InternalHttpResponse<B> {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &B {
+        &self.body
+    }
+
     pub fn map_body<T, F>(self, cb: F) -> InternalHttpResponse<T>
     where
         F: FnOnce(B) -> T,
@@ -363,6 +454,29 @@ impl InternalHttpBody {
 
         Ok(InternalHttpBody(frames))
     }
+
+    /// Length of [`Self::data`], without copying it.
+    pub fn data_len(&self) -> usize {
+        self.0
+            .iter()
+            .map(|frame| match frame {
+                InternalHttpBodyFrame::Data(data) => data.len(),
+                InternalHttpBodyFrame::Trailers(..) => 0,
+            })
+            .sum()
+    }
+
+    /// Data of all frames in this body, without the trailers.
+    pub fn data(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .filter_map(|frame| match frame {
+                InternalHttpBodyFrame::Data(data) => Some(data.as_slice()),
+                InternalHttpBodyFrame::Trailers(..) => None,
+            })
+            .collect::<Vec<_>>()
+            .concat()
+    }
 }
 
 impl Body for InternalHttpBody {
@@ -432,6 +546,13 @@ pub enum HttpResponseFallback {
 }
 
 impl HttpResponseFallback {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HttpResponseFallback::Framed(res) => res.internal_response.status,
+            HttpResponseFallback::Fallback(res) => res.internal_response.status,
+        }
+    }
+
     pub fn connection_id(&self) -> ConnectionId {
         match self {
             HttpResponseFallback::Framed(req) => req.connection_id,
@@ -489,12 +610,15 @@ impl HttpResponse<InternalHttpBody> {
     /// and we also need some extra parameters.
     ///
     /// So this is our alternative implementation to `From<Response<Incoming>>`.
-    pub async fn from_hyper_response(
-        response: Response<Incoming>,
+    pub async fn from_hyper_response<B>(
+        response: Response<B>,
         port: Port,
         connection_id: ConnectionId,
         request_id: RequestId,
-    ) -> Result<HttpResponse<InternalHttpBody>, hyper::Error> {
+    ) -> Result<HttpResponse<InternalHttpBody>, B::Error>
+    where
+        B: Body<Data = Bytes> + Unpin,
+    {
         let (
             Parts {
                 status,
@@ -587,12 +711,15 @@ impl HttpResponse<Vec<u8>> {
     /// and we also need some extra parameters.
     ///
     /// So this is our alternative implementation to `From<Response<Incoming>>`.
-    pub async fn from_hyper_response(
-        response: Response<Incoming>,
+    pub async fn from_hyper_response<B>(
+        response: Response<B>,
         port: Port,
         connection_id: ConnectionId,
         request_id: RequestId,
-    ) -> Result<HttpResponse<Vec<u8>>, hyper::Error> {
+    ) -> Result<HttpResponse<Vec<u8>>, B::Error>
+    where
+        B: Body<Data = Bytes>,
+    {
         let (
             Parts {
                 status,
@@ -0,0 +1,82 @@
+use core::fmt;
+use std::{net::SocketAddr, sync::LazyLock};
+
+use bincode::{Decode, Encode};
+use semver::VersionReq;
+
+use crate::{Port, RemoteResult};
+
+/// Minimal mirrord-protocol version that allows [`ClientMessage::Udp`](crate::ClientMessage::Udp)
+/// and [`ClientMessage::UdpSteal`](crate::ClientMessage::UdpSteal).
+pub static UDP_INCOMING_VERSION: LazyLock<VersionReq> =
+    LazyLock::new(|| ">=1.11.0".parse().expect("Bad Identifier"));
+
+/// A datagram that arrived to a subscribed UDP port of the target.
+#[derive(Encode, Decode, PartialEq, Eq, Clone)]
+pub struct IncomingDatagram {
+    /// Port of the target that received the datagram.
+    pub port: Port,
+    /// Address of the peer that sent the datagram.
+    pub source: SocketAddr,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Debug for IncomingDatagram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncomingDatagram")
+            .field("port", &self.port)
+            .field("source", &self.source)
+            .field("bytes (length)", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// A datagram the application sent back to the peer of a stolen UDP port.
+#[derive(Encode, Decode, PartialEq, Eq, Clone)]
+pub struct OutgoingDatagram {
+    /// Stolen port of the target, the datagram is sent from it.
+    pub port: Port,
+    /// Address of the peer that should receive the datagram.
+    pub destination: SocketAddr,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Debug for OutgoingDatagram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutgoingDatagram")
+            .field("port", &self.port)
+            .field("destination", &self.destination)
+            .field("bytes (length)", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// Messages related to mirroring the incoming UDP traffic, from client.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub enum LayerUdp {
+    /// Start sending copies of the datagrams arriving to this port.
+    PortSubscribe(Port),
+    PortUnsubscribe(Port),
+}
+
+/// Messages related to stealing the incoming UDP traffic, from client.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub enum LayerUdpSteal {
+    /// Start redirecting the datagrams arriving to this port to the client.
+    PortSubscribe(Port),
+    PortUnsubscribe(Port),
+    /// The application's reply to a peer of a stolen port.
+    Reply(OutgoingDatagram),
+}
+
+/// Messages related to the incoming UDP traffic, from agent.
+///
+/// Used both for mirroring and stealing.
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+pub enum DaemonUdp {
+    SubscribeResult {
+        port: Port,
+        result: RemoteResult<()>,
+    },
+    Datagram(IncomingDatagram),
+}
@@ -1,17 +1,21 @@
 use std::{os::unix::process::ExitStatusExt, path::Path, process::Command};
 
-use crate::error::{Result, SipError};
+use crate::{
+    error::{Result, SipError},
+    reaper,
+};
 
 /// Sign the binary at the given path using the host's codesign binary.
 /// Consider using apple-codesign crate instead some day..
 pub(crate) fn sign<P: AsRef<Path>>(path: P) -> Result<()> {
-    let output = Command::new("codesign")
-        .arg("-s") // sign with identity
-        .arg("-") // adhoc identity
-        .arg("-f") // force (might have a signature already)
-        .arg(path.as_ref())
-        .env_remove("DYLD_INSERT_LIBRARIES") // don't load mirrord into the codesign binary
-        .output()?;
+    let output = reaper::output(
+        Command::new("codesign")
+            .arg("-s") // sign with identity
+            .arg("-") // adhoc identity
+            .arg("-f") // force (might have a signature already)
+            .arg(path.as_ref())
+            .env_remove("DYLD_INSERT_LIBRARIES"), // don't load mirrord into the codesign binary
+    )?;
     if output.status.success() {
         Ok(())
     } else {
@@ -4,6 +4,7 @@
 
 mod codesign;
 mod error;
+mod reaper;
 mod rpath;
 
 mod main {
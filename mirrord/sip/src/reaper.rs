@@ -0,0 +1,177 @@
+//! Runs the helper processes of the SIP patch (`codesign`, `install_name_tool`) under a dedicated
+//! reaper.
+//!
+//! The patch runs inside of the user's application, which may have its own `SIGCHLD` handler that
+//! reaps all of its children (`waitpid(-1, ..)`), e.g. app servers that supervise their workers.
+//! When it reaps a helper before we wait on it, its exit status is lost and
+//! [`Command::output`] fails with `ECHILD`.
+//!
+//! So the helper is not a child of the application, but a grandchild: the child in between waits
+//! for it and sends us its status through a pipe. The application can still reap the child in
+//! between, but we don't need anything from it.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    mem,
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    thread,
+};
+
+/// Runs `command` to completion and collects its output, like [`Command::output`].
+pub(crate) fn output(command: &mut Command) -> io::Result<Output> {
+    Helper::spawn(command)?.output()
+}
+
+/// A helper process, spawned under its own reaper.
+struct Helper {
+    /// The reaper, the helper is its only child.
+    reaper: Child,
+    /// Where the reaper writes the wait status of the helper.
+    status: File,
+}
+
+impl Helper {
+    fn spawn(command: &mut Command) -> io::Result<Self> {
+        let (status, status_writer) = cloexec_pipe()?;
+        let status_fd = status_writer.as_raw_fd();
+
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // SAFETY: only async-signal-safe functions are called between the fork and `execve`.
+        unsafe {
+            command.pre_exec(move || {
+                // The application's handler is inherited, and it could reap the helper from here.
+                libc::signal(libc::SIGCHLD, libc::SIG_DFL);
+
+                match libc::fork() {
+                    -1 => Err(io::Error::last_os_error()),
+                    // The helper, the pipe is closed on `execve`.
+                    0 => Ok(()),
+                    helper => {
+                        let mut wait_status = 0;
+                        while libc::waitpid(helper, &mut wait_status, 0) == -1 {
+                            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                                libc::_exit(1);
+                            }
+                        }
+
+                        libc::write(
+                            status_fd,
+                            (&wait_status as *const libc::c_int).cast(),
+                            mem::size_of::<libc::c_int>(),
+                        );
+                        libc::_exit(0);
+                    }
+                }
+            });
+        }
+
+        let reaper = command.spawn()?;
+        // Otherwise we'd never see the end of the pipe if the reaper dies without writing.
+        drop(status_writer);
+
+        Ok(Self { reaper, status })
+    }
+
+    fn output(mut self) -> io::Result<Output> {
+        let stderr = self.reaper.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut buffer = vec![];
+                stderr.read_to_end(&mut buffer).map(|_| buffer)
+            })
+        });
+
+        let mut stdout = vec![];
+        if let Some(mut reaper_stdout) = self.reaper.stdout.take() {
+            reaper_stdout.read_to_end(&mut stdout)?;
+        }
+
+        let stderr = match stderr {
+            Some(reader) => reader
+                .join()
+                .map_err(|_| io::Error::other("stderr reader panicked"))??,
+            None => vec![],
+        };
+
+        let mut wait_status = [0; mem::size_of::<libc::c_int>()];
+        let read_status = self.status.read_exact(&mut wait_status);
+
+        // The application may have reaped it already, we have what we need.
+        let _ = self.reaper.wait();
+
+        read_status.map_err(|_| io::Error::other("helper process exited without a status"))?;
+
+        Ok(Output {
+            status: ExitStatus::from_raw(libc::c_int::from_ne_bytes(wait_status)),
+            stdout,
+            stderr,
+        })
+    }
+}
+
+/// A pipe (read end, write end), with both ends closed on `execve`.
+fn cloexec_pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+
+    // SAFETY: `fds` has room for both ends.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: the fds were just created and are not owned by anything else.
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    for fd in [reader.as_raw_fd(), writer.as_raw_fd()] {
+        // SAFETY: `fd` is a valid fd.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok((reader, writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_and_status() {
+        let output =
+            output(Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"])).unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    /// The application's reaper gets to the reaper before we do.
+    #[test]
+    fn reaped_by_application() {
+        let helper =
+            Helper::spawn(Command::new("sh").args(["-c", "sleep 0.1; echo done"])).unwrap();
+
+        let mut wait_status = 0;
+        // SAFETY: waiting for our own child.
+        let reaped =
+            unsafe { libc::waitpid(helper.reaper.id() as libc::pid_t, &mut wait_status, 0) };
+        assert_eq!(reaped, helper.reaper.id() as libc::pid_t);
+
+        let output = helper.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"done\n");
+    }
+
+    #[test]
+    fn not_found() {
+        assert!(output(&mut Command::new("/nonexistent/mirrord-helper")).is_err());
+    }
+}
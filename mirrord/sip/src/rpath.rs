@@ -1,6 +1,9 @@
 use std::{iter, os::unix::process::ExitStatusExt, path::Path, process::Command};
 
-use crate::error::{Result, SipError};
+use crate::{
+    error::{Result, SipError},
+    reaper,
+};
 
 /// Run `install_name_tool` in a child process to add a loader command for each given rpath entry
 /// to the binary in `path`.
@@ -19,9 +22,10 @@ pub(crate) fn add_rpaths<P: AsRef<Path>>(path: P, rpath_entries: Vec<String>) ->
         )
         .chain(iter::once(path_str));
 
-    let output = Command::new("install_name_tool") // most forgettable tool name ever.
-        .args(args)
-        .output()?;
+    let output = reaper::output(
+        Command::new("install_name_tool") // most forgettable tool name ever.
+            .args(args),
+    )?;
 
     if output.status.success() {
         Ok(())